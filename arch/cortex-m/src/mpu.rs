@@ -257,6 +257,7 @@ impl<const NUM_REGIONS: usize> CortexMConfig<NUM_REGIONS> {
 #[derive(Copy, Clone)]
 pub struct CortexMRegion {
     location: Option<(*const u8, usize)>,
+    permissions: Option<mpu::Permissions>,
     base_address: FieldValue<u32, RegionBaseAddress::Register>,
     attributes: FieldValue<u32, RegionAttributes::Register>,
 }
@@ -336,6 +337,7 @@ impl CortexMRegion {
 
         Some(CortexMRegion {
             location: Some((logical_start, logical_size)),
+            permissions: Some(permissions),
             base_address,
             attributes,
         })
@@ -344,6 +346,7 @@ impl CortexMRegion {
     fn empty(region_num: usize) -> CortexMRegion {
         CortexMRegion {
             location: None,
+            permissions: None,
             base_address: RegionBaseAddress::VALID::UseRBAR
                 + RegionBaseAddress::REGION.val(region_num as u32),
             attributes: RegionAttributes::ENABLE::CLEAR,
@@ -405,6 +408,16 @@ unsafe impl<const NUM_REGIONS: usize, const MIN_REGION_SIZE: usize> mpu::MPU
         self.registers.mpu_type.read(Type::DREGION) as usize
     }
 
+    fn config_region(
+        &self,
+        config: &Self::MpuConfig,
+        region_number: usize,
+    ) -> Option<(mpu::Region, mpu::Permissions)> {
+        let region = config.regions.get(region_number)?;
+        let (start, size) = region.location()?;
+        Some((mpu::Region::new(start, size), region.permissions?))
+    }
+
     fn new_config(&self) -> Option<Self::MpuConfig> {
         let id = self.config_count.get();
         self.config_count.set(id.checked_add(1)?);
@@ -301,6 +301,7 @@ impl<const NUM_REGIONS: usize> CortexMConfig<NUM_REGIONS> {
 #[derive(Copy, Clone)]
 pub struct CortexMRegion {
     location: Option<(*const u8, *const u8)>,
+    permissions: Option<mpu::Permissions>,
     rbar_value: FieldValue<u32, MPU_RBAR::Register>,
     rlar_value: FieldValue<u32, MPU_RLAR::Register>,
     region_num: usize,
@@ -363,6 +364,7 @@ impl CortexMRegion {
 
         Some(CortexMRegion {
             location: Some((region_start, region_start.wrapping_add(region_size))),
+            permissions: Some(permissions),
             rbar_value,
             rlar_value,
             region_num,
@@ -372,6 +374,7 @@ impl CortexMRegion {
     fn empty(region_num: usize) -> CortexMRegion {
         CortexMRegion {
             location: None,
+            permissions: None,
             rbar_value: MPU_RBAR::BASE.val(0),
             rlar_value: MPU_RLAR::ENABLE::CLEAR,
             region_num,
@@ -419,6 +422,19 @@ unsafe impl<const NUM_REGIONS: usize> mpu::MPU for MPU<NUM_REGIONS> {
         self.registers.mpu_type.read(MPU_TYPE::DREGION) as usize
     }
 
+    fn config_region(
+        &self,
+        config: &Self::MpuConfig,
+        region_number: usize,
+    ) -> Option<(mpu::Region, mpu::Permissions)> {
+        let region = config.regions.get(region_number)?;
+        let (start, end) = region.location?;
+        Some((
+            mpu::Region::new(start, end as usize - start as usize),
+            region.permissions?,
+        ))
+    }
+
     fn new_config(&self) -> Option<Self::MpuConfig> {
         let id = self.config_count.get();
         self.config_count.set(id.checked_add(1)?);
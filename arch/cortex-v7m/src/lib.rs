@@ -435,6 +435,8 @@ unsafe extern "C" fn hard_fault_handler_arm_v7m_kernel(
         let thumb_bit = ((stacked_xpsr >> 24) & 0x1) == 1;
         let exception_number = (stacked_xpsr & 0x1ff) as usize;
 
+        kernel::panic_record::record_registers(stacked_pc as usize, stacked_lr as usize);
+
         panic!(
             "{} HardFault.\r\n\
          \tr0  0x{:x}\r\n\
@@ -911,6 +911,34 @@ unsafe impl<const MAX_REGIONS: usize, P: TORUserPMP<MAX_REGIONS> + 'static>
         self.pmp.available_regions()
     }
 
+    fn config_region(
+        &self,
+        config: &Self::MpuConfig,
+        region_number: usize,
+    ) -> Option<(mpu::Region, mpu::Permissions)> {
+        let (pmpcfg, start, end) = config.regions.get(region_number)?;
+        let pmpcfg = pmpcfg.get_reg();
+        if !pmpcfg.is_set(pmpcfg_octet::a) {
+            return None;
+        }
+        let permissions = match (
+            pmpcfg.is_set(pmpcfg_octet::r),
+            pmpcfg.is_set(pmpcfg_octet::w),
+            pmpcfg.is_set(pmpcfg_octet::x),
+        ) {
+            (true, true, true) => mpu::Permissions::ReadWriteExecute,
+            (true, true, false) => mpu::Permissions::ReadWriteOnly,
+            (true, false, true) => mpu::Permissions::ReadExecuteOnly,
+            (true, false, false) => mpu::Permissions::ReadOnly,
+            (false, false, true) => mpu::Permissions::ExecuteOnly,
+            _ => return None,
+        };
+        Some((
+            mpu::Region::new(*start, end.addr() - start.addr()),
+            permissions,
+        ))
+    }
+
     fn new_config(&self) -> Option<Self::MpuConfig> {
         let id = self.config_count.get();
         self.config_count.set(id.checked_add(1)?);
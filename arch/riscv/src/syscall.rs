@@ -772,4 +772,9 @@ impl kernel::syscall::UserspaceKernelBoundary for SysCall {
             Err(ErrorCode::SIZE)
         }
     }
+
+    fn load_context(&self, state: &mut Riscv32iStoredState, input: &[u8]) -> Result<(), ErrorCode> {
+        *state = Riscv32iStoredState::try_from(input)?;
+        Ok(())
+    }
 }
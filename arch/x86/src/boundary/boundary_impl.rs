@@ -251,4 +251,8 @@ impl UserspaceKernelBoundary for Boundary {
     ) -> Result<usize, ErrorCode> {
         unimplemented!()
     }
+
+    fn load_context(&self, _state: &mut Self::StoredState, _input: &[u8]) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
 }
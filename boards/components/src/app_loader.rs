@@ -25,9 +25,11 @@
 //!     capsules_extra::app_loader::DRIVER_NUM,
 //!     dynamic_binary_storage,
 //!     dynamic_binary_storage,
+//!     dynamic_binary_storage,
 //!     ).finalize(components::app_loader_component_static!(
 //!     DynamicBinaryStorage<'static>,
 //!     DynamicBinaryStorage<'static>,
+//!     DynamicBinaryStorage<'static>,
 //!     ));
 //! ```
 
@@ -41,8 +43,8 @@ use kernel::dynamic_binary_storage;
 // Setup static space for the objects.
 #[macro_export]
 macro_rules! app_loader_component_static {
-    ($S:ty, $L:ty $(,)?) => {{
-        let al = kernel::static_buf!(capsules_extra::app_loader::AppLoader<$S, $L>);
+    ($S:ty, $L:ty, $U:ty $(,)?) => {{
+        let al = kernel::static_buf!(capsules_extra::app_loader::AppLoader<$S, $L, $U>);
         let buffer = kernel::static_buf!([u8; capsules_extra::app_loader::BUF_LEN]);
 
         (al, buffer)
@@ -52,29 +54,34 @@ macro_rules! app_loader_component_static {
 pub struct AppLoaderComponent<
     S: dynamic_binary_storage::DynamicBinaryStore + 'static,
     L: dynamic_binary_storage::DynamicProcessLoad + 'static,
+    U: dynamic_binary_storage::DynamicProcessUpdate + 'static,
 > {
     board_kernel: &'static kernel::Kernel,
     driver_num: usize,
     storage_driver: &'static S,
     load_driver: &'static L,
+    update_driver: &'static U,
 }
 
 impl<
     S: dynamic_binary_storage::DynamicBinaryStore + 'static,
     L: dynamic_binary_storage::DynamicProcessLoad + 'static,
-> AppLoaderComponent<S, L>
+    U: dynamic_binary_storage::DynamicProcessUpdate + 'static,
+> AppLoaderComponent<S, L, U>
 {
     pub fn new(
         board_kernel: &'static kernel::Kernel,
         driver_num: usize,
         storage_driver: &'static S,
         load_driver: &'static L,
+        update_driver: &'static U,
     ) -> Self {
         Self {
             board_kernel,
             driver_num,
             storage_driver,
             load_driver,
+            update_driver,
         }
     }
 }
@@ -82,13 +89,14 @@ impl<
 impl<
     S: dynamic_binary_storage::DynamicBinaryStore + 'static,
     L: dynamic_binary_storage::DynamicProcessLoad + 'static,
-> Component for AppLoaderComponent<S, L>
+    U: dynamic_binary_storage::DynamicProcessUpdate + 'static,
+> Component for AppLoaderComponent<S, L, U>
 {
     type StaticInput = (
-        &'static mut MaybeUninit<AppLoader<S, L>>,
+        &'static mut MaybeUninit<AppLoader<S, L, U>>,
         &'static mut MaybeUninit<[u8; capsules_extra::app_loader::BUF_LEN]>,
     );
-    type Output = &'static AppLoader<S, L>;
+    type Output = &'static AppLoader<S, L, U>;
 
     fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
         let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
@@ -101,6 +109,7 @@ impl<
             self.board_kernel.create_grant(self.driver_num, &grant_cap),
             self.storage_driver,
             self.load_driver,
+            self.update_driver,
             buffer,
         ));
         dynamic_binary_storage::DynamicBinaryStore::set_storage_client(
@@ -111,6 +120,10 @@ impl<
             self.load_driver,
             dynamic_app_loader,
         );
+        dynamic_binary_storage::DynamicProcessUpdate::set_update_client(
+            self.update_driver,
+            dynamic_app_loader,
+        );
         dynamic_app_loader
     }
 }
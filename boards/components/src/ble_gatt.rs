@@ -0,0 +1,156 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Component for a BLE peripheral with a GATT server.
+//!
+//! This creates the link layer on a radio implementing
+//! `kernel::hil::ble_link::BleLinkRadio`, the attribute database, and the
+//! GATT server system call driver. The GAP service is registered with the
+//! name of the device.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let ble_gatt = components::ble_gatt::BleGattComponent::new(
+//!     board_kernel,
+//!     capsules_extra::ble::gatt_server::DRIVER_NUM,
+//!     &base_peripherals.ble_radio,
+//!     mux_alarm,
+//!     [0x13, 0x72, 0x4A, 0x0B, 0x3C, 0xD2], // Static random address
+//!     b"Tock",
+//! )
+//! .finalize(components::ble_gatt_component_static!(
+//!     nrf52840::ble_radio::Radio,
+//!     nrf52840::rtc::Rtc<'static>,
+//!     32,  // Number of attributes
+//!     512, // Bytes for the characteristic values
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::ble::att::{Attribute, AttributeDatabase};
+use capsules_extra::ble::gatt_server::GattServer;
+use capsules_extra::ble::link_layer::{ADDRESS_LEN, BleLinkLayer, LinkLayer};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::ble_link::{self, BleLinkRadio};
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! ble_gatt_component_static {
+    ($R:ty, $A:ty, $N:expr, $V:expr $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let adv_buffer = kernel::static_buf!([u8; kernel::hil::ble_link::MAX_PACKET_LEN]);
+        let link = kernel::static_buf!(
+            capsules_extra::ble::link_layer::LinkLayer<
+                'static,
+                $R,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let attributes = kernel::static_buf!([Option<capsules_extra::ble::att::Attribute>; $N]);
+        let values = kernel::static_buf!([u8; $V]);
+        let server = kernel::static_buf!(
+            capsules_extra::ble::gatt_server::GattServer<
+                'static,
+                capsules_extra::ble::link_layer::LinkLayer<
+                    'static,
+                    $R,
+                    capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                >,
+            >
+        );
+
+        (alarm, adv_buffer, link, attributes, values, server)
+    };};
+}
+
+pub type BleLinkLayerComponentType<R, A> = LinkLayer<'static, R, VirtualMuxAlarm<'static, A>>;
+pub type BleGattComponentType<R, A> = GattServer<'static, BleLinkLayerComponentType<R, A>>;
+
+pub struct BleGattComponent<
+    R: BleLinkRadio<'static> + 'static,
+    A: Alarm<'static> + 'static,
+    const N: usize,
+    const V: usize,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    radio: &'static R,
+    mux_alarm: &'static MuxAlarm<'static, A>,
+    address: [u8; ADDRESS_LEN],
+    device_name: &'static [u8],
+}
+
+impl<
+    R: BleLinkRadio<'static> + 'static,
+    A: Alarm<'static> + 'static,
+    const N: usize,
+    const V: usize,
+> BleGattComponent<R, A, N, V>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        radio: &'static R,
+        mux_alarm: &'static MuxAlarm<'static, A>,
+        address: [u8; ADDRESS_LEN],
+        device_name: &'static [u8],
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            radio,
+            mux_alarm,
+            address,
+            device_name,
+        }
+    }
+}
+
+impl<
+    R: BleLinkRadio<'static> + 'static,
+    A: Alarm<'static> + 'static,
+    const N: usize,
+    const V: usize,
+> Component for BleGattComponent<R, A, N, V>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<[u8; ble_link::MAX_PACKET_LEN]>,
+        &'static mut MaybeUninit<BleLinkLayerComponentType<R, A>>,
+        &'static mut MaybeUninit<[Option<Attribute>; N]>,
+        &'static mut MaybeUninit<[u8; V]>,
+        &'static mut MaybeUninit<BleGattComponentType<R, A>>,
+    );
+    type Output = &'static BleGattComponentType<R, A>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.mux_alarm));
+        alarm.setup();
+        let adv_buffer = s.1.write([0; ble_link::MAX_PACKET_LEN]);
+        let link =
+            s.2.write(LinkLayer::new(self.radio, alarm, self.address, adv_buffer));
+        self.radio.set_link_client(link);
+        alarm.set_alarm_client(link);
+
+        let database = AttributeDatabase::new(s.3.write([None; N]), s.4.write([0; V]));
+        let server = s.5.write(GattServer::new(
+            link,
+            database,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        link.set_client(server);
+        // Only fails if the database is too small for the GAP service.
+        let _ = server.init(self.device_name);
+
+        server
+    }
+}
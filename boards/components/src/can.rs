@@ -7,8 +7,10 @@
 
 //! Component for CAN syscall interface.
 //!
-//! This provides one Component, `CanComponent`, which implements a
-//! userspace syscall interface to the Can peripheral.
+//! This provides two Components, `CanComponent`, which implements a
+//! userspace syscall interface to a classic CAN peripheral, and
+//! `CanFdComponent`, which implements the same interface with 64-byte
+//! frames for a CAN FD peripheral.
 //!
 //! Usage
 //! -----
@@ -22,6 +24,19 @@
 //! ));
 //! ```
 //!
+//! For a CAN FD peripheral, where `CanFdPeripheral` stands for the type of
+//! the chip's peripheral that implements `kernel::hil::can::CanFd`:
+//!
+//! ```rust
+//! let can = components::can::CanFdComponent::new(
+//!     board_kernel,
+//!     capsules_extra::can::DRIVER_NUM,
+//!     &peripherals.can_fd
+//! ).finalize(components::can_fd_component_static!(
+//!     CanFdPeripheral<'static>
+//! ));
+//! ```
+//!
 
 use capsules_extra::can::CanCapsule;
 use core::mem::MaybeUninit;
@@ -44,6 +59,22 @@ macro_rules! can_component_static {
     };};
 }
 
+#[macro_export]
+macro_rules! can_fd_component_static {
+    ($C:ty $(,)?) => {{
+        use capsules_extra::can::CanCapsule;
+        use core::mem::MaybeUninit;
+        use kernel::hil::can;
+        use kernel::static_buf;
+
+        let CAN_TX_BUF = static_buf!([u8; can::FD_CAN_PACKET_SIZE]);
+        let CAN_RX_BUF = static_buf!([u8; can::FD_CAN_PACKET_SIZE]);
+        let can =
+            static_buf!(capsules_extra::can::CanCapsule<'static, $C, { can::FD_CAN_PACKET_SIZE }>);
+        (can, CAN_TX_BUF, CAN_RX_BUF)
+    };};
+}
+
 pub struct CanComponent<A: 'static + can::Can> {
     board_kernel: &'static kernel::Kernel,
     driver_num: usize,
@@ -89,3 +120,49 @@ impl<A: 'static + can::Can> Component for CanComponent<A> {
         can
     }
 }
+
+pub struct CanFdComponent<A: 'static + can::CanFd> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    can: &'static A,
+}
+
+impl<A: 'static + can::CanFd> CanFdComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        can: &'static A,
+    ) -> CanFdComponent<A> {
+        CanFdComponent {
+            board_kernel,
+            driver_num,
+            can,
+        }
+    }
+}
+
+impl<A: 'static + can::CanFd> Component for CanFdComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<CanCapsule<'static, A, { can::FD_CAN_PACKET_SIZE }>>,
+        &'static mut MaybeUninit<[u8; can::FD_CAN_PACKET_SIZE]>,
+        &'static mut MaybeUninit<[u8; can::FD_CAN_PACKET_SIZE]>,
+    );
+    type Output = &'static CanCapsule<'static, A, { can::FD_CAN_PACKET_SIZE }>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let grant_can = self.board_kernel.create_grant(self.driver_num, &grant_cap);
+
+        let can = static_buffer.0.write(CanCapsule::new(
+            self.can,
+            grant_can,
+            static_buffer.1.write([0; can::FD_CAN_PACKET_SIZE]),
+            static_buffer.2.write([0; can::FD_CAN_PACKET_SIZE]),
+        ));
+        can::Controller::set_client(self.can, Some(can));
+        can::Transmit::set_client(self.can, Some(can));
+        can::Receive::set_client(self.can, Some(can));
+
+        can
+    }
+}
@@ -0,0 +1,117 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Component for the CAN ISO-TP syscall interface.
+//!
+//! This provides one Component, `CanIsoTpComponent`, which implements an
+//! ISO 15765-2 transport for userspace on top of a CAN peripheral. The
+//! component takes over the transmit and receive clients of the peripheral,
+//! so it cannot be used together with `CanComponent` on the same peripheral.
+//! To share the peripheral, pass a `VirtualCanDevice` from a `MuxCan`
+//! instead.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let can_isotp = components::can_isotp::CanIsoTpComponent::new(
+//!     board_kernel,
+//!     capsules_extra::can_isotp::DRIVER_NUM,
+//!     &peripherals.can1,
+//!     mux_alarm,
+//! )
+//! .finalize(components::can_isotp_component_static!(
+//!     stm32f429zi::can::Can<'static>,
+//!     stm32f429zi::tim2::Tim2<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::can_isotp::CanIsoTp;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::can::{self, STANDARD_CAN_PACKET_SIZE};
+use kernel::hil::time::{self, Alarm};
+use kernel::{capabilities, create_capability};
+
+#[macro_export]
+macro_rules! can_isotp_component_static {
+    ($C:ty, $A:ty $(,)?) => {{
+        use capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm;
+        use capsules_extra::can_isotp::CanIsoTp;
+        use kernel::hil::can;
+        use kernel::static_buf;
+
+        let alarm = static_buf!(VirtualMuxAlarm<'static, $A>);
+        let can_tx_buffer = static_buf!([u8; can::STANDARD_CAN_PACKET_SIZE]);
+        let can_rx_buffer = static_buf!([u8; can::STANDARD_CAN_PACKET_SIZE]);
+        let isotp = static_buf!(CanIsoTp<'static, $C, VirtualMuxAlarm<'static, $A>>);
+        (alarm, can_tx_buffer, can_rx_buffer, isotp)
+    };};
+}
+
+pub type CanIsoTpComponentType<C, A> = CanIsoTp<'static, C, VirtualMuxAlarm<'static, A>>;
+
+pub struct CanIsoTpComponent<
+    C: 'static + can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>,
+    A: 'static + Alarm<'static>,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    can: &'static C,
+    mux_alarm: &'static MuxAlarm<'static, A>,
+}
+
+impl<
+    C: 'static + can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>,
+    A: 'static + Alarm<'static>,
+> CanIsoTpComponent<C, A>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        can: &'static C,
+        mux_alarm: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            can,
+            mux_alarm,
+        }
+    }
+}
+
+impl<
+    C: 'static + can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>,
+    A: 'static + Alarm<'static>,
+> Component for CanIsoTpComponent<C, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<[u8; STANDARD_CAN_PACKET_SIZE]>,
+        &'static mut MaybeUninit<[u8; STANDARD_CAN_PACKET_SIZE]>,
+        &'static mut MaybeUninit<CanIsoTp<'static, C, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static CanIsoTp<'static, C, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.mux_alarm));
+        alarm.setup();
+
+        let isotp = static_buffer.3.write(CanIsoTp::new(
+            self.can,
+            alarm,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            static_buffer.1.write([0; STANDARD_CAN_PACKET_SIZE]),
+            static_buffer.2.write([0; STANDARD_CAN_PACKET_SIZE]),
+        ));
+        can::Transmit::set_client(self.can, Some(isotp));
+        can::Receive::set_client(self.can, Some(isotp));
+        time::Alarm::set_alarm_client(alarm, isotp);
+
+        isotp
+    }
+}
@@ -0,0 +1,124 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Components for sharing a CAN peripheral.
+//!
+//! This provides two components.
+//!
+//! 1. `CanMuxComponent` provides a virtualization layer for a CAN
+//!    peripheral. It takes over the transmit and receive clients of the
+//!    peripheral, so it cannot be used together with `CanComponent`.
+//!
+//! 2. `VirtualCanDeviceComponent` provides a virtualized client of the CAN
+//!    peripheral, which can be passed to any capsule that uses the
+//!    `Transmit` and `Receive` traits, such as `CanIsoTp`.
+//!
+//! The board must configure and enable the peripheral itself.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let mux_can = components::can_mux::CanMuxComponent::new(
+//!     &peripherals.can1,
+//!     Some(&peripherals.can1),
+//! )
+//! .finalize(components::can_mux_component_static!(
+//!     stm32f429zi::can::Can<'static>
+//! ));
+//! let can_device = components::can_mux::VirtualCanDeviceComponent::new(mux_can)
+//!     .finalize(components::virtual_can_device_component_static!(
+//!         stm32f429zi::can::Can<'static>
+//!     ));
+//! ```
+
+use capsules_core::virtualizers::virtual_can::{MuxCan, VirtualCanDevice};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::can::{self, STANDARD_CAN_PACKET_SIZE};
+
+#[macro_export]
+macro_rules! can_mux_component_static {
+    ($C:ty $(,)?) => {{
+        use kernel::hil::can;
+        use kernel::static_buf;
+
+        let rx_buffer = static_buf!([u8; can::STANDARD_CAN_PACKET_SIZE]);
+        let mux = static_buf!(capsules_core::virtualizers::virtual_can::MuxCan<'static, $C>);
+        (mux, rx_buffer)
+    };};
+}
+
+#[macro_export]
+macro_rules! virtual_can_device_component_static {
+    ($C:ty $(,)?) => {{
+        kernel::static_buf!(capsules_core::virtualizers::virtual_can::VirtualCanDevice<'static, $C>)
+    };};
+}
+
+pub struct CanMuxComponent<
+    C: 'static + can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>,
+> {
+    can: &'static C,
+    filter: Option<&'static dyn can::Filter>,
+}
+
+impl<C: 'static + can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>>
+    CanMuxComponent<C>
+{
+    /// `filter` is the peripheral's filter interface, if it has one.
+    pub fn new(can: &'static C, filter: Option<&'static dyn can::Filter>) -> Self {
+        Self { can, filter }
+    }
+}
+
+impl<C: 'static + can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>>
+    Component for CanMuxComponent<C>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<MuxCan<'static, C>>,
+        &'static mut MaybeUninit<[u8; STANDARD_CAN_PACKET_SIZE]>,
+    );
+    type Output = &'static MuxCan<'static, C>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let rx_buffer = static_buffer.1.write([0; STANDARD_CAN_PACKET_SIZE]);
+        let mux_can = static_buffer
+            .0
+            .write(MuxCan::new(self.can, self.filter, rx_buffer));
+        kernel::deferred_call::DeferredCallClient::register(mux_can);
+
+        can::Transmit::set_client(self.can, Some(mux_can));
+        can::Receive::set_client(self.can, Some(mux_can));
+
+        mux_can
+    }
+}
+
+pub struct VirtualCanDeviceComponent<
+    C: 'static + can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>,
+> {
+    mux_can: &'static MuxCan<'static, C>,
+}
+
+impl<C: 'static + can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>>
+    VirtualCanDeviceComponent<C>
+{
+    pub fn new(mux_can: &'static MuxCan<'static, C>) -> Self {
+        Self { mux_can }
+    }
+}
+
+impl<C: 'static + can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>>
+    Component for VirtualCanDeviceComponent<C>
+{
+    type StaticInput = &'static mut MaybeUninit<VirtualCanDevice<'static, C>>;
+    type Output = &'static VirtualCanDevice<'static, C>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let device = static_buffer.write(VirtualCanDevice::new(self.mux_can));
+        device.setup();
+
+        device
+    }
+}
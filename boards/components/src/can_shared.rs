@@ -0,0 +1,101 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Component for the shared CAN syscall interface.
+//!
+//! This provides one Component, `SharedCanComponent`, which lets several
+//! processes send and receive CAN frames through a `MuxCan`, each with its
+//! own acceptance filters.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let shared_can = components::can_shared::SharedCanComponent::new(
+//!     board_kernel,
+//!     capsules_extra::can_shared::DRIVER_NUM,
+//!     mux_can,
+//! )
+//! .finalize(components::can_shared_component_static!(
+//!     stm32f429zi::can::Can<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_can::{MuxCan, VirtualCanDevice};
+use capsules_extra::can_shared::SharedCan;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::can::{self, STANDARD_CAN_PACKET_SIZE};
+use kernel::{capabilities, create_capability};
+
+#[macro_export]
+macro_rules! can_shared_component_static {
+    ($C:ty $(,)?) => {{
+        use capsules_core::virtualizers::virtual_can::VirtualCanDevice;
+        use capsules_extra::can_shared::SharedCan;
+        use kernel::hil::can;
+        use kernel::static_buf;
+
+        let device = static_buf!(VirtualCanDevice<'static, $C>);
+        let tx_buffer = static_buf!([u8; can::STANDARD_CAN_PACKET_SIZE]);
+        let rx_buffer = static_buf!([u8; can::STANDARD_CAN_PACKET_SIZE]);
+        let shared_can = static_buf!(SharedCan<'static, VirtualCanDevice<'static, $C>>);
+        (device, tx_buffer, rx_buffer, shared_can)
+    };};
+}
+
+pub type SharedCanComponentType<C> = SharedCan<'static, VirtualCanDevice<'static, C>>;
+
+pub struct SharedCanComponent<
+    C: 'static + can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    mux_can: &'static MuxCan<'static, C>,
+}
+
+impl<C: 'static + can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>>
+    SharedCanComponent<C>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        mux_can: &'static MuxCan<'static, C>,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            mux_can,
+        }
+    }
+}
+
+impl<C: 'static + can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>>
+    Component for SharedCanComponent<C>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualCanDevice<'static, C>>,
+        &'static mut MaybeUninit<[u8; STANDARD_CAN_PACKET_SIZE]>,
+        &'static mut MaybeUninit<[u8; STANDARD_CAN_PACKET_SIZE]>,
+        &'static mut MaybeUninit<SharedCan<'static, VirtualCanDevice<'static, C>>>,
+    );
+    type Output = &'static SharedCanComponentType<C>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let device = static_buffer.0.write(VirtualCanDevice::new(self.mux_can));
+        device.setup();
+
+        let shared_can = static_buffer.3.write(SharedCan::new(
+            device,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            static_buffer.1.write([0; STANDARD_CAN_PACKET_SIZE]),
+            static_buffer.2.write([0; STANDARD_CAN_PACKET_SIZE]),
+        ));
+        can::Transmit::set_client(device, Some(shared_can));
+        can::Receive::set_client(device, Some(shared_can));
+
+        shared_can
+    }
+}
@@ -45,7 +45,7 @@ macro_rules! ctap_component_static {
         let driver = kernel::static_buf!(
             capsules_extra::usb_hid_driver::UsbHidDriver<
                 'static,
-                capsules_extra::usb::usb_hid_driver::UsbHidDriver<'static, $U>,
+                capsules_extra::usb::ctap::CtapHid<'static, $U>,
             >
         );
         let send_buffer = kernel::static_buf!([u8; 64]);
@@ -20,6 +20,35 @@
 //!     kernel::process::ProcessStandardDebugFull,
 //! ));
 //! ```
+//!
+//! To support compacting the flash used for apps, the board gives the
+//! storage a flash region for the compaction journal, with each half of the
+//! region in a flash page of its own:
+//!
+//!```rust, ignore
+//! let journal = core::slice::from_raw_parts(
+//!     JOURNAL_START as *const u8,
+//!     2 * PAGE_SIZE,
+//! );
+//! dynamic_binary_storage.set_compaction_journal(journal);
+//! ```
+//!
+//! The kernel counts the faults of an upgraded app on trial, and rolls the
+//! upgrade back after too many, once the storage is the fault policy of the
+//! process loader. The board passes its own policy to the storage for all
+//! other processes. To keep the trial across a reset, the board also gives
+//! the storage a flash region for the trial record, laid out like the
+//! journal, before processes are loaded:
+//!
+//!```rust, ignore
+//! dynamic_binary_storage.set_fault_policy(&FAULT_RESPONSE);
+//! loader.set_fault_policy(dynamic_binary_storage);
+//! let record = core::slice::from_raw_parts(
+//!     TRIAL_RECORD_START as *const u8,
+//!     2 * PAGE_SIZE,
+//! );
+//! dynamic_binary_storage.set_trial_record(record);
+//! ```
 
 use capsules_extra::nonvolatile_to_pages::NonvolatileToPages;
 use core::mem::MaybeUninit;
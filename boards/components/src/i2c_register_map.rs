@@ -0,0 +1,149 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Components for emulating register-mapped I2C devices.
+//!
+//! This provides two components.
+//!
+//! 1. `I2CRegisterMapMuxComponent` listens as an I2C slave on behalf of
+//!    the emulated devices. It takes over the slave client of the
+//!    peripheral, so it cannot be used together with the I2C master/slave
+//!    syscall drivers.
+//!
+//! 2. `I2CRegisterMapComponent` provides the registers of one emulated
+//!    device, at its own address.
+//!
+//! After creating the devices, the board calls `start()` on the mux.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let mux = components::i2c_register_map::I2CRegisterMapMuxComponent::new(
+//!     &base_peripherals.twi1,
+//!     Some(&base_peripherals.twi1),
+//! )
+//! .finalize(components::i2c_register_map_mux_component_static!(
+//!     nrf52840::i2c::TWI<'static>,
+//!     32,
+//!     32
+//! ));
+//! let accelerometer = components::i2c_register_map::I2CRegisterMapComponent::new(
+//!     mux,
+//!     0x1D,
+//!     &ACCELEROMETER_ACCESS,
+//! )
+//! .finalize(components::i2c_register_map_component_static!(64));
+//! mux.start().unwrap();
+//! ```
+
+use capsules_extra::i2c_register_map::{I2CRegisterMap, MuxI2CRegisterMap, RegisterAccess};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::i2c::{I2CSlave, I2CSlaveMultiAddress};
+
+#[macro_export]
+macro_rules! i2c_register_map_mux_component_static {
+    ($S:ty, $WRITE_LEN:expr, $READ_LEN:expr $(,)?) => {{
+        let write_buffer = kernel::static_buf!([u8; $WRITE_LEN]);
+        let read_buffer = kernel::static_buf!([u8; $READ_LEN]);
+        let mux =
+            kernel::static_buf!(capsules_extra::i2c_register_map::MuxI2CRegisterMap<'static, $S>);
+        (mux, write_buffer, read_buffer)
+    };};
+}
+
+#[macro_export]
+macro_rules! i2c_register_map_component_static {
+    ($REGISTERS:expr $(,)?) => {{
+        let registers = kernel::static_buf!([u8; $REGISTERS]);
+        let map = kernel::static_buf!(capsules_extra::i2c_register_map::I2CRegisterMap<'static>);
+        (map, registers)
+    };};
+}
+
+pub struct I2CRegisterMapMuxComponent<
+    S: 'static + I2CSlave<'static>,
+    const WRITE_LEN: usize,
+    const READ_LEN: usize,
+> {
+    i2c: &'static S,
+    multi_address: Option<&'static dyn I2CSlaveMultiAddress>,
+}
+
+impl<S: 'static + I2CSlave<'static>, const WRITE_LEN: usize, const READ_LEN: usize>
+    I2CRegisterMapMuxComponent<S, WRITE_LEN, READ_LEN>
+{
+    /// `multi_address` is the peripheral's interface for more than one
+    /// address, if it has one.
+    pub fn new(i2c: &'static S, multi_address: Option<&'static dyn I2CSlaveMultiAddress>) -> Self {
+        Self { i2c, multi_address }
+    }
+}
+
+impl<S: 'static + I2CSlave<'static>, const WRITE_LEN: usize, const READ_LEN: usize> Component
+    for I2CRegisterMapMuxComponent<S, WRITE_LEN, READ_LEN>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<MuxI2CRegisterMap<'static, S>>,
+        &'static mut MaybeUninit<[u8; WRITE_LEN]>,
+        &'static mut MaybeUninit<[u8; READ_LEN]>,
+    );
+    type Output = &'static MuxI2CRegisterMap<'static, S>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let write_buffer = static_buffer.1.write([0; WRITE_LEN]);
+        let read_buffer = static_buffer.2.write([0; READ_LEN]);
+        let mux = static_buffer.0.write(MuxI2CRegisterMap::new(
+            self.i2c,
+            self.multi_address,
+            write_buffer,
+            read_buffer,
+        ));
+        self.i2c.set_slave_client(mux);
+
+        mux
+    }
+}
+
+pub struct I2CRegisterMapComponent<S: 'static + I2CSlave<'static>, const REGISTERS: usize> {
+    mux: &'static MuxI2CRegisterMap<'static, S>,
+    address: u8,
+    access: &'static [RegisterAccess],
+}
+
+impl<S: 'static + I2CSlave<'static>, const REGISTERS: usize> I2CRegisterMapComponent<S, REGISTERS> {
+    /// `access` gives the access of the registers starting from register 0.
+    /// Registers past the end of `access` are read-write.
+    pub fn new(
+        mux: &'static MuxI2CRegisterMap<'static, S>,
+        address: u8,
+        access: &'static [RegisterAccess],
+    ) -> Self {
+        Self {
+            mux,
+            address,
+            access,
+        }
+    }
+}
+
+impl<S: 'static + I2CSlave<'static>, const REGISTERS: usize> Component
+    for I2CRegisterMapComponent<S, REGISTERS>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CRegisterMap<'static>>,
+        &'static mut MaybeUninit<[u8; REGISTERS]>,
+    );
+    type Output = &'static I2CRegisterMap<'static>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let registers = static_buffer.1.write([0; REGISTERS]);
+        let map = static_buffer
+            .0
+            .write(I2CRegisterMap::new(self.address, registers, self.access));
+        self.mux.add_map(map);
+
+        map
+    }
+}
@@ -6,7 +6,8 @@
 
 use capsules_extra::kv_driver::KVStoreDriver;
 use capsules_extra::kv_store_permissions::KVStorePermissions;
-use capsules_extra::tickv::{KVSystem, KeyType};
+use capsules_extra::tickv::{KVSystem, KeyType, TicKVKeyType};
+use capsules_extra::tickv_encrypted::{EncryptedKVSystem, KeyCounter, MonotonicCounter};
 use capsules_extra::tickv_kv_store::TicKVKVStore;
 use capsules_extra::virtualizers::virtual_kv::{MuxKVPermissions, VirtualKVPermissions};
 use core::mem::MaybeUninit;
@@ -14,6 +15,7 @@ use kernel::capabilities;
 use kernel::component::Component;
 use kernel::create_capability;
 use kernel::hil;
+use kernel::hil::symmetric_encryption::{AES128, AES128_KEY_SIZE, AESGCM};
 
 ///////////////////////
 // KV Userspace Driver
@@ -251,3 +253,104 @@ impl<K: 'static + KVSystem<'static, K = T>, T: 'static + KeyType + Default> Comp
         kv_store
     }
 }
+
+/////////////////////
+// Encrypted KV System
+/////////////////////
+
+/// `$BUFFER_LEN` must be at least
+/// `capsules_extra::tickv_encrypted::buffer_len(max_value_len, $MAX_KEYS)`,
+/// where `$MAX_KEYS` is the number of keys that can be stored.
+#[macro_export]
+macro_rules! encrypted_kv_system_component_static {
+    ($K:ty, $A:ty, $C:ty, $BUFFER_LEN:expr, $MAX_KEYS:expr $(,)?) => {{
+        let buffer = kernel::static_buf!([u8; $BUFFER_LEN]);
+        let counter_key = kernel::static_buf!(capsules_extra::tickv::TicKVKeyType);
+        let key_counters =
+            kernel::static_buf!([capsules_extra::tickv_encrypted::KeyCounter; $MAX_KEYS]);
+        let kv_system = kernel::static_buf!(
+            capsules_extra::tickv_encrypted::EncryptedKVSystem<'static, $K, $A, $C>
+        );
+
+        (kv_system, buffer, counter_key, key_counters)
+    };};
+}
+
+pub type EncryptedKVSystemComponentType<K, A, C> =
+    capsules_extra::tickv_encrypted::EncryptedKVSystem<'static, K, A, C>;
+
+pub struct EncryptedKVSystemComponent<
+    K: 'static + KVSystem<'static, K = TicKVKeyType>,
+    A: 'static + AESGCM<'static, AES128>,
+    C: 'static + MonotonicCounter<'static>,
+    const BUFFER_LEN: usize,
+    const MAX_KEYS: usize,
+> {
+    kv_system: &'static K,
+    aes: &'static A,
+    counter: &'static C,
+    device_key: [u8; AES128_KEY_SIZE],
+}
+
+impl<
+    K: 'static + KVSystem<'static, K = TicKVKeyType>,
+    A: 'static + AESGCM<'static, AES128>,
+    C: 'static + MonotonicCounter<'static>,
+    const BUFFER_LEN: usize,
+    const MAX_KEYS: usize,
+> EncryptedKVSystemComponent<K, A, C, BUFFER_LEN, MAX_KEYS>
+{
+    pub fn new(
+        kv_system: &'static K,
+        aes: &'static A,
+        counter: &'static C,
+        device_key: [u8; AES128_KEY_SIZE],
+    ) -> Self {
+        Self {
+            kv_system,
+            aes,
+            counter,
+            device_key,
+        }
+    }
+}
+
+impl<
+    K: 'static + KVSystem<'static, K = TicKVKeyType>,
+    A: 'static + AESGCM<'static, AES128>,
+    C: 'static + MonotonicCounter<'static>,
+    const BUFFER_LEN: usize,
+    const MAX_KEYS: usize,
+> Component for EncryptedKVSystemComponent<K, A, C, BUFFER_LEN, MAX_KEYS>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<EncryptedKVSystem<'static, K, A, C>>,
+        &'static mut MaybeUninit<[u8; BUFFER_LEN]>,
+        &'static mut MaybeUninit<TicKVKeyType>,
+        &'static mut MaybeUninit<[KeyCounter; MAX_KEYS]>,
+    );
+    type Output = &'static EncryptedKVSystem<'static, K, A, C>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let buffer = static_buffer.1.write([0; BUFFER_LEN]);
+        let counter_key = static_buffer.2.write(TicKVKeyType::default());
+        let key_counters = static_buffer.3.write([KeyCounter::default(); MAX_KEYS]);
+
+        let kv_system = static_buffer.0.write(EncryptedKVSystem::new(
+            self.kv_system,
+            self.aes,
+            self.counter,
+            self.device_key,
+            buffer,
+            counter_key,
+            key_counters,
+        ));
+
+        self.kv_system.set_client(kv_system);
+        self.aes.set_client(kv_system);
+        self.counter.set_client(kv_system);
+        kv_system.initialise();
+
+        kv_system
+    }
+}
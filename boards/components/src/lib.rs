@@ -16,6 +16,7 @@ pub mod app_loader;
 pub mod appid;
 pub mod atecc508a;
 pub mod ble;
+pub mod ble_gatt;
 pub mod bme280;
 pub mod bmm150;
 pub mod bmp280;
@@ -24,6 +25,9 @@ pub mod button;
 pub mod button_keyboard;
 pub mod buzzer;
 pub mod can;
+pub mod can_isotp;
+pub mod can_mux;
+pub mod can_shared;
 pub mod ccs811;
 pub mod cdc;
 pub mod chirp_i2c_moisture;
@@ -48,6 +52,7 @@ pub mod hs3003;
 pub mod hts221;
 pub mod humidity;
 pub mod i2c;
+pub mod i2c_register_map;
 pub mod ieee802154;
 pub mod isl29035;
 pub mod isolated_nonvolatile_storage;
@@ -56,6 +61,7 @@ pub mod kv;
 pub mod l3gd20;
 pub mod led;
 pub mod led_matrix;
+pub mod littlefs;
 pub mod lldb;
 pub mod loader;
 pub mod lpm013m126;
@@ -69,14 +75,19 @@ pub mod mlx90614;
 pub mod moisture;
 pub mod mx25r6435f;
 pub mod ninedof;
+pub mod nonvolatile_counter;
 pub mod nonvolatile_storage;
 pub mod nrf51822;
 pub mod panic_button;
+pub mod panic_record;
 pub mod pressure;
 pub mod process_array;
+pub mod process_checkpoint;
 pub mod process_console;
+pub mod process_fault_dump;
 pub mod process_info_driver;
 pub mod process_printer;
+pub mod process_watchdog;
 pub mod proximity;
 pub mod pwm;
 pub mod rainfall;
@@ -101,6 +112,7 @@ pub mod spi;
 pub mod ssd1306;
 pub mod st77xx;
 pub mod storage_permissions;
+pub mod syscall_trace;
 pub mod temperature;
 pub mod temperature_rp2040;
 pub mod temperature_stm;
@@ -109,8 +121,12 @@ pub mod text_screen;
 pub mod thread_network;
 pub mod tickv;
 pub mod touch;
+pub mod udp_app_loader;
 pub mod udp_driver;
 pub mod udp_mux;
 pub mod usb;
+pub mod usb_composite;
+pub mod usb_dfu;
+pub mod usb_msc;
 pub mod virtual_scheduler_timer;
 pub mod wifi;
@@ -0,0 +1,170 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Component for the littlefs file system driver.
+//!
+//! This provides one component, LittleFsComponent, which provides a system
+//! call interface to a littlefs file system stored in a region of flash. The
+//! file system is mounted when the component is finalized. A region that
+//! doesn't hold a file system yet must be formatted once, by calling
+//! `format()` on the component when provisioning the board.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let mux_flash = components::flash::FlashMuxComponent::new(&base_peripherals.nvmc).finalize(
+//!     components::flash_mux_component_static!(nrf52840::nvmc::Nvmc),
+//! );
+//!
+//! let littlefs = components::littlefs::LittleFsComponent::new(
+//!     board_kernel,
+//!     capsules_extra::littlefs::DRIVER_NUM,
+//!     mux_flash,
+//!     0xc0000 / nrf52840::nvmc::PAGE_SIZE, // First page of the file system
+//!     64,                                  // Number of pages
+//!     16,                                  // Program size
+//! )
+//! .finalize(components::littlefs_component_static!(
+//!     nrf52840::nvmc::Nvmc,
+//!     nrf52840::nvmc::PAGE_SIZE,
+//!     64
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_flash::FlashUser;
+use capsules_core::virtualizers::virtual_flash::MuxFlash;
+use capsules_extra::littlefs::{Config, LittleFsDriver};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+use kernel::hil::flash::HasClient;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! littlefs_component_static {
+    ($F:ty, $PAGE_SIZE:expr, $PAGE_COUNT:expr $(,)?) => {{
+        let flash =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_flash::FlashUser<'static, $F>);
+        let page = kernel::static_buf!(<$F as kernel::hil::flash::Flash>::Page);
+        let cache = kernel::static_buf!([u8; capsules_extra::littlefs::CACHE_BLOCKS * $PAGE_SIZE]);
+        let bitmap = kernel::static_buf!([u8; ($PAGE_COUNT + 7) / 8]);
+        let buffer = kernel::static_buf!([u8; capsules_extra::littlefs::BUF_LEN]);
+        let driver = kernel::static_buf!(
+            capsules_extra::littlefs::LittleFsDriver<
+                'static,
+                capsules_core::virtualizers::virtual_flash::FlashUser<'static, $F>,
+            >
+        );
+
+        (flash, page, cache, bitmap, buffer, driver)
+    };};
+}
+
+pub type LittleFsComponentType<F> = LittleFsDriver<'static, FlashUser<'static, F>>;
+
+pub struct LittleFsComponent<
+    F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, MuxFlash<'static, F>>,
+    const CACHE_LEN: usize,
+    const BITMAP_LEN: usize,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    mux_flash: &'static MuxFlash<'static, F>,
+    region_offset: usize,
+    page_count: usize,
+    prog_size: usize,
+    format: bool,
+}
+
+impl<
+    F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, MuxFlash<'static, F>>,
+    const CACHE_LEN: usize,
+    const BITMAP_LEN: usize,
+> LittleFsComponent<F, CACHE_LEN, BITMAP_LEN>
+{
+    /// Use `page_count` pages of flash starting at page `region_offset`.
+    /// Metadata commits are padded to `prog_size` bytes, which must divide
+    /// the page size.
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        mux_flash: &'static MuxFlash<'static, F>,
+        region_offset: usize,
+        page_count: usize,
+        prog_size: usize,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            mux_flash,
+            region_offset,
+            page_count,
+            prog_size,
+            format: false,
+        }
+    }
+
+    /// Format the region instead of mounting it, erasing any files.
+    pub fn format(mut self) -> Self {
+        self.format = true;
+        self
+    }
+}
+
+impl<
+    F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, MuxFlash<'static, F>>,
+    const CACHE_LEN: usize,
+    const BITMAP_LEN: usize,
+> Component for LittleFsComponent<F, CACHE_LEN, BITMAP_LEN>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<FlashUser<'static, F>>,
+        &'static mut MaybeUninit<<F as hil::flash::Flash>::Page>,
+        &'static mut MaybeUninit<[u8; CACHE_LEN]>,
+        &'static mut MaybeUninit<[u8; BITMAP_LEN]>,
+        &'static mut MaybeUninit<[u8; capsules_extra::littlefs::BUF_LEN]>,
+        &'static mut MaybeUninit<LittleFsDriver<'static, FlashUser<'static, F>>>,
+    );
+    type Output = &'static LittleFsDriver<'static, FlashUser<'static, F>>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let virtual_flash = static_buffer.0.write(FlashUser::new(self.mux_flash));
+        let page = static_buffer
+            .1
+            .write(<F as hil::flash::Flash>::Page::default());
+        let cache = static_buffer.2.write([0; CACHE_LEN]);
+        let bitmap = static_buffer.3.write([0; BITMAP_LEN]);
+        let buffer = static_buffer
+            .4
+            .write([0; capsules_extra::littlefs::BUF_LEN]);
+
+        let config = Config {
+            block_size: CACHE_LEN / capsules_extra::littlefs::CACHE_BLOCKS,
+            block_count: self.page_count as u32,
+            prog_size: self.prog_size,
+        };
+
+        let driver = static_buffer.5.write(LittleFsDriver::new(
+            virtual_flash,
+            page,
+            self.region_offset,
+            config,
+            cache,
+            bitmap,
+            buffer,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        virtual_flash.set_client(driver);
+        if self.format {
+            let _ = driver.format();
+        } else {
+            driver.mount();
+        }
+        driver
+    }
+}
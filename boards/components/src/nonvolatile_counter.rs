@@ -0,0 +1,98 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Component for a monotonic counter stored in flash.
+//!
+//! The two slots of the counter are given as byte addresses in the flash and
+//! should be in different pages.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let counter = components::nonvolatile_counter::NonvolatileCounterComponent::new(
+//!     &base_peripherals.nvmc,
+//!     [0xFE000, 0xFF000],
+//! )
+//! .finalize(components::nonvolatile_counter_component_static!(
+//!     nrf52840::nvmc::Nvmc
+//! ));
+//! ```
+
+use capsules_extra::nonvolatile_counter::{NonvolatileCounter, SLOT_LEN};
+use capsules_extra::nonvolatile_to_pages::NonvolatileToPages;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! nonvolatile_counter_component_static {
+    ($F:ty $(,)?) => {{
+        let page = kernel::static_buf!(<$F as kernel::hil::flash::Flash>::Page);
+        let ntp = kernel::static_buf!(
+            capsules_extra::nonvolatile_to_pages::NonvolatileToPages<'static, $F>
+        );
+        let counter = kernel::static_buf!(
+            capsules_extra::nonvolatile_counter::NonvolatileCounter<
+                'static,
+                capsules_extra::nonvolatile_to_pages::NonvolatileToPages<'static, $F>,
+            >
+        );
+        let buffer = kernel::static_buf!([u8; capsules_extra::nonvolatile_counter::SLOT_LEN]);
+
+        (page, ntp, counter, buffer)
+    };};
+}
+
+pub type NonvolatileCounterComponentType<F> =
+    NonvolatileCounter<'static, NonvolatileToPages<'static, F>>;
+
+pub struct NonvolatileCounterComponent<
+    F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, NonvolatileToPages<'static, F>>,
+> {
+    flash: &'static F,
+    slots: [usize; 2],
+}
+
+impl<
+    F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, NonvolatileToPages<'static, F>>,
+> NonvolatileCounterComponent<F>
+{
+    pub fn new(flash: &'static F, slots: [usize; 2]) -> Self {
+        Self { flash, slots }
+    }
+}
+
+impl<
+    F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, NonvolatileToPages<'static, F>>,
+> Component for NonvolatileCounterComponent<F>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<<F as hil::flash::Flash>::Page>,
+        &'static mut MaybeUninit<NonvolatileToPages<'static, F>>,
+        &'static mut MaybeUninit<NonvolatileCounter<'static, NonvolatileToPages<'static, F>>>,
+        &'static mut MaybeUninit<[u8; SLOT_LEN]>,
+    );
+    type Output = &'static NonvolatileCounter<'static, NonvolatileToPages<'static, F>>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let buffer = static_buffer.3.write([0; SLOT_LEN]);
+
+        let flash_pagebuffer = static_buffer
+            .0
+            .write(<F as hil::flash::Flash>::Page::default());
+
+        let nv_to_page = static_buffer
+            .1
+            .write(NonvolatileToPages::new(self.flash, flash_pagebuffer));
+        hil::flash::HasClient::set_client(self.flash, nv_to_page);
+
+        let counter = static_buffer
+            .2
+            .write(NonvolatileCounter::new(nv_to_page, self.slots, buffer));
+        hil::nonvolatile_storage::NonvolatileStorage::set_client(nv_to_page, counter);
+
+        counter
+    }
+}
@@ -0,0 +1,121 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Component for the kernel panic record and its capsule.
+//!
+//! This sets up the kernel panic record in memory that survives a warm reset,
+//! prints the record of a panic before the reset, and makes it available to
+//! userspace. The component should be finalized as early as possible in the
+//! board's `main()`, as panics and debug output before that are not recorded.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let panic_record = components::panic_record::PanicRecordComponent::<
+//!     <ChipHw as kernel::platform::chip::Chip>::ThreadIdProvider,
+//! >::new(
+//!     board_kernel,
+//!     capsules_extra::panic_record::DRIVER_NUM,
+//!     Some(nonvolatile_storage),
+//!     0x2000, // Address of the record in the nonvolatile storage
+//! )
+//! .finalize(components::panic_record_component_static!(
+//!     <ChipHw as kernel::platform::chip::Chip>::ThreadIdProvider
+//! ));
+//! ```
+//!
+//! The component is only available on targets with atomics. Boards for other
+//! targets must call `kernel::panic_record::initialize_panic_record_unsafe()`
+//! and create the capsule themselves.
+
+#[cfg(target_has_atomic = "ptr")]
+use capsules_extra::panic_record::PanicRecordDriver;
+#[cfg(target_has_atomic = "ptr")]
+use core::marker::PhantomData;
+#[cfg(target_has_atomic = "ptr")]
+use core::mem::MaybeUninit;
+#[cfg(target_has_atomic = "ptr")]
+use kernel::capabilities;
+#[cfg(target_has_atomic = "ptr")]
+use kernel::component::Component;
+#[cfg(target_has_atomic = "ptr")]
+use kernel::create_capability;
+#[cfg(target_has_atomic = "ptr")]
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+#[cfg(target_has_atomic = "ptr")]
+use kernel::panic_record::{self, PanicRecord};
+#[cfg(target_has_atomic = "ptr")]
+use kernel::platform::chip::ThreadIdProvider;
+
+#[macro_export]
+macro_rules! panic_record_component_static {
+    ($P:ty $(,)?) => {{
+        // The record must not be initialized at boot, so it can't use
+        // `static_buf!()`.
+        #[unsafe(link_section = ".noinit")]
+        static mut RECORD: core::mem::MaybeUninit<kernel::panic_record::PanicRecord> =
+            core::mem::MaybeUninit::uninit();
+
+        let record = &mut *core::ptr::addr_of_mut!(RECORD);
+        let previous = kernel::static_buf!(kernel::panic_record::PanicRecord);
+        let driver = kernel::static_buf!(capsules_extra::panic_record::PanicRecordDriver<'static>);
+
+        (record, previous, driver)
+    };};
+}
+
+#[cfg(target_has_atomic = "ptr")]
+pub struct PanicRecordComponent<P: ThreadIdProvider> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    storage: Option<&'static dyn NonvolatileStorage<'static>>,
+    storage_address: usize,
+    _thread_id_provider: PhantomData<P>,
+}
+
+#[cfg(target_has_atomic = "ptr")]
+impl<P: ThreadIdProvider> PanicRecordComponent<P> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        storage: Option<&'static dyn NonvolatileStorage<'static>>,
+        storage_address: usize,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            storage,
+            storage_address,
+            _thread_id_provider: PhantomData,
+        }
+    }
+}
+
+#[cfg(target_has_atomic = "ptr")]
+impl<P: ThreadIdProvider> Component for PanicRecordComponent<P> {
+    type StaticInput = (
+        &'static mut MaybeUninit<PanicRecord>,
+        &'static mut MaybeUninit<PanicRecord>,
+        &'static mut MaybeUninit<PanicRecordDriver<'static>>,
+    );
+    type Output = &'static PanicRecordDriver<'static>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let previous = panic_record::initialize_panic_record::<P>(static_buffer.0, static_buffer.1);
+
+        let driver = static_buffer.2.write(PanicRecordDriver::new(
+            previous,
+            self.storage,
+            self.storage_address,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        if let Some(storage) = self.storage {
+            storage.set_client(driver);
+        }
+        driver.init();
+        driver
+    }
+}
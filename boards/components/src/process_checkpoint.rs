@@ -0,0 +1,172 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Component for checkpointing processes to flash.
+//!
+//! This provides two components:
+//!
+//! - ProcessCheckpointComponent creates a SequentialProcessCheckpoint for a
+//!   flash region and has the process loader restore processes from it. The
+//!   component must be finalized before processes are loaded, i.e. before the
+//!   kernel loop starts.
+//! - ProcessCheckpointDriverComponent creates the syscall driver that lets
+//!   processes checkpoint themselves.
+//!
+//! The board can also checkpoint processes itself, for example when a supply
+//! voltage monitor reports that power is about to be lost. The checkpoint
+//! region needs two slots for each process that is checkpointed.
+//!
+//!```rust, ignore
+//! # use kernel::static_init;
+//!
+//! let region = core::slice::from_raw_parts(
+//!     CHECKPOINT_START as *const u8,
+//!     CHECKPOINT_LENGTH,
+//! );
+//! let checkpoint = components::process_checkpoint::ProcessCheckpointComponent::new(
+//!     board_kernel,
+//!     nv_to_page,
+//!     loader,
+//!     region,
+//!     0x4000,
+//! )
+//! .finalize(components::process_checkpoint_component_static!(
+//!     capsules_extra::nonvolatile_to_pages::NonvolatileToPages<'static, nrf52840::nvmc::Nvmc>,
+//! ));
+//!
+//! let checkpoint_driver = components::process_checkpoint::ProcessCheckpointDriverComponent::new(
+//!     board_kernel,
+//!     checkpoint,
+//!     create_capability!(capabilities::ProcessManagementCapability),
+//! )
+//! .finalize(components::process_checkpoint_driver_component_static!(
+//!     ProcessMgmtCap
+//! ));
+//! ```
+
+use capsules_extra::process_checkpoint::ProcessCheckpointDriver;
+use core::mem::MaybeUninit;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::component::Component;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+use kernel::platform::chip::Chip;
+use kernel::process::ProcessStandardDebug;
+use kernel::process::SequentialProcessLoaderMachine;
+use kernel::process_checkpoint::{ProcessCheckpoint, SequentialProcessCheckpoint};
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! process_checkpoint_component_static {
+    ($F:ty $(,)?) => {{
+        let checkpoint = kernel::static_buf!(
+            kernel::process_checkpoint::SequentialProcessCheckpoint<'static, $F>
+        );
+        let buffer = kernel::static_buf!([u8; kernel::process_checkpoint::BUF_LEN]);
+
+        (checkpoint, buffer)
+    };};
+}
+
+#[macro_export]
+macro_rules! process_checkpoint_driver_component_static {
+    ($C:ty $(,)?) => {{
+        kernel::static_buf!(
+            capsules_extra::process_checkpoint::ProcessCheckpointDriver<'static, $C>
+        )
+    };};
+}
+
+pub struct ProcessCheckpointComponent<
+    F: NonvolatileStorage<'static> + 'static,
+    C: Chip + 'static,
+    D: ProcessStandardDebug + 'static,
+> {
+    kernel: &'static kernel::Kernel,
+    storage: &'static F,
+    loader_driver: &'static SequentialProcessLoaderMachine<'static, C, D>,
+    region: &'static [u8],
+    slot_length: usize,
+}
+
+impl<F: NonvolatileStorage<'static> + 'static, C: 'static + Chip, D: 'static + ProcessStandardDebug>
+    ProcessCheckpointComponent<F, C, D>
+{
+    pub fn new(
+        kernel: &'static kernel::Kernel,
+        storage: &'static F,
+        loader_driver: &'static SequentialProcessLoaderMachine<'static, C, D>,
+        region: &'static [u8],
+        slot_length: usize,
+    ) -> Self {
+        Self {
+            kernel,
+            storage,
+            loader_driver,
+            region,
+            slot_length,
+        }
+    }
+}
+
+impl<F: NonvolatileStorage<'static> + 'static, C: 'static + Chip, D: 'static + ProcessStandardDebug>
+    Component for ProcessCheckpointComponent<F, C, D>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<SequentialProcessCheckpoint<'static, F>>,
+        &'static mut MaybeUninit<[u8; kernel::process_checkpoint::BUF_LEN]>,
+    );
+    type Output = &'static SequentialProcessCheckpoint<'static, F>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let buffer = static_buffer
+            .1
+            .write([0; kernel::process_checkpoint::BUF_LEN]);
+
+        let checkpoint = static_buffer.0.write(SequentialProcessCheckpoint::new(
+            self.kernel,
+            self.storage,
+            self.region,
+            self.slot_length,
+            buffer,
+        ));
+        self.storage.set_client(checkpoint);
+        self.loader_driver.set_restore(checkpoint);
+        checkpoint
+    }
+}
+
+pub struct ProcessCheckpointDriverComponent<C: ProcessManagementCapability + 'static> {
+    kernel: &'static kernel::Kernel,
+    checkpoint: &'static dyn ProcessCheckpoint<'static>,
+    capability: C,
+}
+
+impl<C: ProcessManagementCapability> ProcessCheckpointDriverComponent<C> {
+    pub fn new(
+        kernel: &'static kernel::Kernel,
+        checkpoint: &'static dyn ProcessCheckpoint<'static>,
+        capability: C,
+    ) -> Self {
+        Self {
+            kernel,
+            checkpoint,
+            capability,
+        }
+    }
+}
+
+impl<C: ProcessManagementCapability> Component for ProcessCheckpointDriverComponent<C> {
+    type StaticInput = &'static mut MaybeUninit<ProcessCheckpointDriver<'static, C>>;
+    type Output = &'static ProcessCheckpointDriver<'static, C>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let driver = static_buffer.write(ProcessCheckpointDriver::new(
+            self.checkpoint,
+            self.kernel,
+            self.capability,
+        ));
+        self.checkpoint.set_checkpoint_client(driver);
+        driver
+    }
+}
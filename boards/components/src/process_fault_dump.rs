@@ -0,0 +1,119 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Component for the process fault dump capsule.
+//!
+//! The returned capsule wraps the board's fault policy, and should be passed
+//! to the process loader as its fault policy. The record of a fault that
+//! causes a panic is kept in memory that is not initialized at boot, so the
+//! board's linker script must have a `.noinit` section.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let fault_policy = static_init!(
+//!     capsules_system::process_policies::RestartFaultPolicy,
+//!     capsules_system::process_policies::RestartFaultPolicy {}
+//! );
+//! let fault_dump = components::process_fault_dump::ProcessFaultDumpComponent::new(
+//!     board_kernel,
+//!     capsules_extra::process_fault_dump::DRIVER_NUM,
+//!     nonvolatile_storage,
+//!     0x1000, // Address of the records in the nonvolatile storage
+//!     0x1000, // Length of the records area
+//!     fault_policy,
+//! )
+//! .finalize(components::process_fault_dump_component_static!());
+//! ```
+
+use capsules_extra::process_fault_dump::{self, ProcessFaultDump};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+use kernel::process::ProcessFaultPolicy;
+
+#[macro_export]
+macro_rules! process_fault_dump_component_static {
+    () => {{
+        // The panic buffer must not be initialized at boot, so it can't use
+        // `static_buf!()`.
+        #[unsafe(link_section = ".noinit")]
+        static mut PANIC_BUFFER: core::mem::MaybeUninit<
+            [u8; capsules_extra::process_fault_dump::RECORD_LEN],
+        > = core::mem::MaybeUninit::uninit();
+
+        let panic_buffer = &mut *core::ptr::addr_of_mut!(PANIC_BUFFER);
+        let buffer = kernel::static_buf!([u8; capsules_extra::process_fault_dump::RECORD_LEN]);
+        let fault_dump =
+            kernel::static_buf!(capsules_extra::process_fault_dump::ProcessFaultDump<'static>);
+
+        (buffer, panic_buffer, fault_dump)
+    };};
+}
+
+pub struct ProcessFaultDumpComponent {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    storage: &'static dyn NonvolatileStorage<'static>,
+    region_address: usize,
+    region_length: usize,
+    policy: &'static dyn ProcessFaultPolicy,
+}
+
+impl ProcessFaultDumpComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        storage: &'static dyn NonvolatileStorage<'static>,
+        region_address: usize,
+        region_length: usize,
+        policy: &'static dyn ProcessFaultPolicy,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            storage,
+            region_address,
+            region_length,
+            policy,
+        }
+    }
+}
+
+impl Component for ProcessFaultDumpComponent {
+    type StaticInput = (
+        &'static mut MaybeUninit<[u8; process_fault_dump::RECORD_LEN]>,
+        &'static mut MaybeUninit<[u8; process_fault_dump::RECORD_LEN]>,
+        &'static mut MaybeUninit<ProcessFaultDump<'static>>,
+    );
+    type Output = &'static ProcessFaultDump<'static>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let buffer = static_buffer.0.write([0; process_fault_dump::RECORD_LEN]);
+
+        // # Safety
+        //
+        // `panic_buffer` is RAM that has not been initialized since the reset,
+        // and so holds whatever it held before, which is a valid byte array as
+        // any bytes are.
+        let panic_buffer = unsafe { static_buffer.1.assume_init_mut() };
+
+        let fault_dump = static_buffer.2.write(ProcessFaultDump::new(
+            self.storage,
+            self.region_address,
+            self.region_length,
+            buffer,
+            panic_buffer,
+            self.policy,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        self.storage.set_client(fault_dump);
+        fault_dump.init();
+        fault_dump
+    }
+}
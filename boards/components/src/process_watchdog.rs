@@ -0,0 +1,145 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Component for the process watchdog capsule.
+//!
+//! The returned capsule wraps the chip's watchdog, and should be used as the
+//! `WatchDog` of the board's `KernelResources`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let watchdog_apps = static_init!(
+//!     [kernel::process::ShortId; 1],
+//!     [kernel::process::ShortId::Fixed(
+//!         core::num::NonZeroU32::new(0x1234).unwrap()
+//!     )]
+//! );
+//! let process_watchdog = components::process_watchdog::ProcessWatchdogComponent::new(
+//!     board_kernel,
+//!     mux_alarm,
+//!     &peripherals.wdt,
+//!     nonvolatile_storage,
+//!     0x0, // Address of the record in the nonvolatile storage
+//!     create_capability!(capabilities::ProcessManagementCapability),
+//!     capsules_extra::process_watchdog::MissAction::FaultProcess,
+//!     watchdog_apps,
+//! )
+//! .finalize(components::process_watchdog_component_static!(
+//!     sam4l::ast::Ast,
+//!     sam4l::wdt::Wdt,
+//!     ProcessMgmtCap
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::process_watchdog::{self, MissAction, ProcessWatchdog};
+use core::mem::MaybeUninit;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::component::Component;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+use kernel::hil::time::{self, Alarm};
+use kernel::platform::watchdog::WatchDog;
+use kernel::process::ShortId;
+
+#[macro_export]
+macro_rules! process_watchdog_component_static {
+    ($A:ty, $W:ty, $C:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let buffer = kernel::static_buf!([u8; capsules_extra::process_watchdog::RECORD_LEN]);
+        let process_watchdog = kernel::static_buf!(
+            capsules_extra::process_watchdog::ProcessWatchdog<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                $W,
+                $C,
+            >
+        );
+
+        (alarm, buffer, process_watchdog)
+    };};
+}
+
+pub type ProcessWatchdogComponentType<A, W, C> =
+    ProcessWatchdog<'static, VirtualMuxAlarm<'static, A>, W, C>;
+
+pub struct ProcessWatchdogComponent<
+    A: 'static + time::Alarm<'static>,
+    W: 'static + WatchDog,
+    C: ProcessManagementCapability,
+> {
+    board_kernel: &'static kernel::Kernel,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    watchdog: &'static W,
+    storage: &'static dyn NonvolatileStorage<'static>,
+    record_address: usize,
+    capability: C,
+    action: MissAction,
+    allowed_apps: &'static [ShortId],
+}
+
+impl<A: 'static + time::Alarm<'static>, W: 'static + WatchDog, C: ProcessManagementCapability>
+    ProcessWatchdogComponent<A, W, C>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        watchdog: &'static W,
+        storage: &'static dyn NonvolatileStorage<'static>,
+        record_address: usize,
+        capability: C,
+        action: MissAction,
+        allowed_apps: &'static [ShortId],
+    ) -> Self {
+        Self {
+            board_kernel,
+            alarm_mux,
+            watchdog,
+            storage,
+            record_address,
+            capability,
+            action,
+            allowed_apps,
+        }
+    }
+}
+
+impl<
+    A: 'static + time::Alarm<'static>,
+    W: 'static + WatchDog,
+    C: ProcessManagementCapability + 'static,
+> Component for ProcessWatchdogComponent<A, W, C>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<[u8; process_watchdog::RECORD_LEN]>,
+        &'static mut MaybeUninit<ProcessWatchdog<'static, VirtualMuxAlarm<'static, A>, W, C>>,
+    );
+    type Output = &'static ProcessWatchdog<'static, VirtualMuxAlarm<'static, A>, W, C>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let buffer = static_buffer.1.write([0; process_watchdog::RECORD_LEN]);
+
+        let process_watchdog = static_buffer.2.write(ProcessWatchdog::new(
+            alarm,
+            self.watchdog,
+            self.storage,
+            self.record_address,
+            buffer,
+            self.board_kernel,
+            self.capability,
+            self.action,
+            self.allowed_apps,
+        ));
+        alarm.set_alarm_client(process_watchdog);
+        self.storage.set_client(process_watchdog);
+        process_watchdog.load_record();
+        process_watchdog
+    }
+}
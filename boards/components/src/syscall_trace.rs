@@ -0,0 +1,149 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Component for the binary syscall trace.
+//!
+//! This creates a `SyscallTraceBuffer` that records the system calls of all
+//! processes, and sends them over a dedicated UART device or RTT channel.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! // A UART that is not shared with the console.
+//! let trace_uart = static_init!(
+//!     capsules_core::virtualizers::virtual_uart::UartDevice<'static>,
+//!     capsules_core::virtualizers::virtual_uart::UartDevice::new(trace_uart_mux, false)
+//! );
+//! trace_uart.setup();
+//! let syscall_trace = components::syscall_trace::SyscallTraceComponent::new(
+//!     board_kernel,
+//!     &base_peripherals.rtc,
+//!     trace_uart,
+//!     create_capability!(capabilities::ProcessManagementCapability),
+//! )
+//! .finalize(components::syscall_trace_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     capsules_core::virtualizers::virtual_uart::UartDevice<'static>,
+//!     256, // Number of events in the ring buffer
+//! ));
+//! ```
+//!
+//! Or, over a Segger RTT channel that the console doesn't use:
+//!
+//! ```rust
+//! let rtt_memory = components::segger_rtt::SeggerRttMemoryComponent::new()
+//!     .finalize(components::segger_rtt_memory_component_static!());
+//! let rtt = components::segger_rtt::SeggerRttComponent::new(mux_alarm, rtt_memory)
+//!     .finalize(components::segger_rtt_component_static!(nrf52840::rtc::Rtc<'static>));
+//! components::syscall_trace::SyscallTraceComponent::new(
+//!     board_kernel,
+//!     &base_peripherals.rtc,
+//!     rtt,
+//!     create_capability!(capabilities::ProcessManagementCapability),
+//! )
+//! .finalize(components::syscall_trace_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     segger::rtt::SeggerRtt<
+//!         'static,
+//!         capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<
+//!             'static,
+//!             nrf52840::rtc::Rtc<'static>,
+//!         >,
+//!     >,
+//!     256,
+//! ));
+//! ```
+
+use capsules_extra::syscall_trace::{self, SyscallTraceBuffer, TraceEvent};
+use core::mem::MaybeUninit;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::component::Component;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil::time;
+use kernel::hil::uart;
+
+/// Number of events sent in one transmission.
+pub const TX_EVENTS: usize = 8;
+
+#[macro_export]
+macro_rules! syscall_trace_component_static {
+    ($T:ty, $U:ty, $N:expr $(,)?) => {{
+        let events = kernel::static_buf!(
+            [core::mem::MaybeUninit<capsules_extra::syscall_trace::TraceEvent>; $N]
+        );
+        let tx_buffer = kernel::static_buf!(
+            [u8; capsules_extra::syscall_trace::EVENT_LEN * $crate::syscall_trace::TX_EVENTS]
+        );
+        let trace =
+            kernel::static_buf!(capsules_extra::syscall_trace::SyscallTraceBuffer<'static, $T, $U>);
+
+        (events, tx_buffer, trace)
+    };};
+}
+
+pub struct SyscallTraceComponent<
+    const N: usize,
+    T: 'static + time::Time,
+    U: 'static + uart::Transmit<'static>,
+    C: ProcessManagementCapability,
+> {
+    board_kernel: &'static kernel::Kernel,
+    time: &'static T,
+    uart: &'static U,
+    capability: C,
+    marker: core::marker::PhantomData<[TraceEvent; N]>,
+}
+
+impl<
+    const N: usize,
+    T: 'static + time::Time,
+    U: 'static + uart::Transmit<'static>,
+    C: ProcessManagementCapability,
+> SyscallTraceComponent<N, T, U, C>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        time: &'static T,
+        uart: &'static U,
+        capability: C,
+    ) -> Self {
+        Self {
+            board_kernel,
+            time,
+            uart,
+            capability,
+            marker: core::marker::PhantomData,
+        }
+    }
+}
+
+impl<
+    const N: usize,
+    T: 'static + time::Time,
+    U: 'static + uart::Transmit<'static>,
+    C: ProcessManagementCapability,
+> Component for SyscallTraceComponent<N, T, U, C>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<[MaybeUninit<TraceEvent>; N]>,
+        &'static mut MaybeUninit<[u8; syscall_trace::EVENT_LEN * TX_EVENTS]>,
+        &'static mut MaybeUninit<SyscallTraceBuffer<'static, T, U>>,
+    );
+    type Output = &'static SyscallTraceBuffer<'static, T, U>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let events = static_buffer.0.write([MaybeUninit::uninit(); N]);
+        let tx_buffer = static_buffer
+            .1
+            .write([0; syscall_trace::EVENT_LEN * TX_EVENTS]);
+
+        let trace = static_buffer.2.write(SyscallTraceBuffer::new(
+            self.time, self.uart, events, tx_buffer,
+        ));
+        trace.register();
+        self.uart.set_transmit_client(trace);
+        self.board_kernel.set_syscall_trace(trace, &self.capability);
+        trace
+    }
+}
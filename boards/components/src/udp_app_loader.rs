@@ -0,0 +1,183 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Component for loading apps over UDP.
+//!
+//! This provides one component, UdpAppLoaderComponent, which binds the UDP
+//! app loader capsule to a port and connects it to the dynamic binary
+//! storage.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let udp_app_loader = components::udp_app_loader::UdpAppLoaderComponent::new(
+//!     udp_send_mux,
+//!     udp_recv_mux,
+//!     udp_port_table,
+//!     5683,
+//!     dynamic_binary_storage,
+//!     dynamic_binary_storage,
+//! )
+//! .finalize(components::udp_app_loader_component_static!(
+//!     nrf52840::rtc::Rtc,
+//!     DynamicBinaryStorage<'static>,
+//!     DynamicBinaryStorage<'static>,
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm;
+use capsules_extra::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules_extra::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules_extra::net::udp::udp_port_table::UdpPortManager;
+use capsules_extra::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver};
+use capsules_extra::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use capsules_extra::udp_app_loader::{self, UdpAppLoader};
+use core::mem::MaybeUninit;
+use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::dynamic_binary_storage::{DynamicBinaryStore, DynamicProcessLoad};
+use kernel::hil::time::Alarm;
+use kernel::utilities::leasable_buffer::SubSliceMut;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! udp_app_loader_component_static {
+    ($A:ty, $S:ty, $L:ty $(,)?) => {{
+        let udp_send = kernel::static_buf!(
+            capsules_extra::net::udp::udp_send::UDPSendStruct<
+                'static,
+                capsules_extra::net::ipv6::ipv6_send::IP6SendStruct<
+                    'static,
+                    capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                >,
+            >
+        );
+        let udp_recv =
+            kernel::static_buf!(capsules_extra::net::udp::udp_recv::UDPReceiver<'static>);
+        let udp_vis_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::UdpVisibilityCapability);
+        let net_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::NetworkCapability);
+        let buffer = kernel::static_buf!([u8; capsules_extra::udp_app_loader::BUF_LEN]);
+        let send_buffer = kernel::static_buf!([u8; capsules_extra::udp_app_loader::ACK_LEN]);
+        let loader =
+            kernel::static_buf!(capsules_extra::udp_app_loader::UdpAppLoader<'static, $S, $L>);
+
+        (
+            udp_send,
+            udp_recv,
+            udp_vis_cap,
+            net_cap,
+            buffer,
+            send_buffer,
+            loader,
+        )
+    };};
+}
+
+pub type UdpAppLoaderComponentType<S, L> = UdpAppLoader<'static, S, L>;
+
+pub struct UdpAppLoaderComponent<
+    A: Alarm<'static> + 'static,
+    S: DynamicBinaryStore + 'static,
+    L: DynamicProcessLoad + 'static,
+> {
+    udp_send_mux:
+        &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+    udp_recv_mux: &'static MuxUdpReceiver<'static>,
+    port_table: &'static UdpPortManager,
+    port: u16,
+    storage_driver: &'static S,
+    load_driver: &'static L,
+}
+
+impl<A: Alarm<'static> + 'static, S: DynamicBinaryStore + 'static, L: DynamicProcessLoad + 'static>
+    UdpAppLoaderComponent<A, S, L>
+{
+    pub fn new(
+        udp_send_mux: &'static MuxUdpSender<
+            'static,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+        >,
+        udp_recv_mux: &'static MuxUdpReceiver<'static>,
+        port_table: &'static UdpPortManager,
+        port: u16,
+        storage_driver: &'static S,
+        load_driver: &'static L,
+    ) -> Self {
+        Self {
+            udp_send_mux,
+            udp_recv_mux,
+            port_table,
+            port,
+            storage_driver,
+            load_driver,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static, S: DynamicBinaryStore + 'static, L: DynamicProcessLoad + 'static>
+    Component for UdpAppLoaderComponent<A, S, L>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<
+            UDPSendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        >,
+        &'static mut MaybeUninit<UDPReceiver<'static>>,
+        &'static mut MaybeUninit<UdpVisibilityCapability>,
+        &'static mut MaybeUninit<NetworkCapability>,
+        &'static mut MaybeUninit<[u8; udp_app_loader::BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; udp_app_loader::ACK_LEN]>,
+        &'static mut MaybeUninit<UdpAppLoader<'static, S, L>>,
+    );
+    type Output = &'static UdpAppLoader<'static, S, L>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(NetworkCapabilityCreationCapability);
+        let udp_vis = s.2.write(UdpVisibilityCapability::new(&create_cap));
+        let net_cap = s.3.write(NetworkCapability::new(
+            AddrRange::Any,
+            PortRange::Any,
+            PortRange::Any,
+            &create_cap,
+        ));
+
+        let udp_send = s.0.write(UDPSendStruct::new(self.udp_send_mux, udp_vis));
+        let udp_recv = s.1.write(UDPReceiver::new());
+
+        let buffer = s.4.write([0; udp_app_loader::BUF_LEN]);
+        let send_buffer = s.5.write([0; udp_app_loader::ACK_LEN]);
+
+        let loader = s.6.write(UdpAppLoader::new(
+            self.storage_driver,
+            self.load_driver,
+            udp_send,
+            net_cap,
+            buffer,
+            SubSliceMut::new(send_buffer),
+        ));
+        udp_send.set_client(loader);
+        udp_recv.set_client(loader);
+        self.storage_driver.set_storage_client(loader);
+        self.load_driver.set_load_client(loader);
+
+        // The loader is useless without its port, so fail loudly if the port
+        // is taken or there are no free sockets.
+        let socket = self
+            .port_table
+            .create_socket()
+            .expect("udp_app_loader: no free socket");
+        let Ok((tx_bind, rx_bind)) = self.port_table.bind(socket, self.port, net_cap) else {
+            panic!("udp_app_loader: could not bind port");
+        };
+        udp_send.set_binding(tx_bind);
+        udp_recv.set_binding(rx_bind);
+        self.udp_recv_mux.add_client(udp_recv);
+
+        loader
+    }
+}
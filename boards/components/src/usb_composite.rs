@@ -0,0 +1,149 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Components for composite USB devices.
+//!
+//! `UsbCompositeComponent` creates the device on a USB controller, and
+//! `UsbFunctionComponent` creates a function of the device, which is passed
+//! to the component of a USB class driver in place of the controller.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! // A CTAP security key with a CDC-ACM debug console.
+//! let usb = components::usb_composite::UsbCompositeComponent::new(
+//!     &nrf52840_peripherals.usbd,
+//!     capsules_extra::usb::cdc::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x1915,
+//!     0x503a,
+//!     strings,
+//! )
+//! .finalize(components::usb_composite_component_static!(
+//!     nrf52840::usbd::Usbd
+//! ));
+//!
+//! let ctap_function = components::usb_composite::UsbFunctionComponent::new(usb)
+//!     .finalize(components::usb_function_component_static!(nrf52840::usbd::Usbd));
+//! let (_ctap, _ctap_driver) = components::ctap::CtapComponent::new(
+//!     board_kernel,
+//!     capsules_core::driver::NUM::CtapHid as usize,
+//!     ctap_function,
+//!     0x1915,
+//!     0x503a,
+//!     strings,
+//! )
+//! .finalize(components::ctap_component_static!(
+//!     components::usb_composite::UsbFunctionComponentType<nrf52840::usbd::Usbd>
+//! ));
+//!
+//! let cdc_function = components::usb_composite::UsbFunctionComponent::new(usb)
+//!     .finalize(components::usb_function_component_static!(nrf52840::usbd::Usbd));
+//! let cdc = components::cdc::CdcAcmComponent::new(
+//!     cdc_function,
+//!     capsules_extra::usb::cdc::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x1915,
+//!     0x503a,
+//!     strings,
+//!     mux_alarm,
+//!     None,
+//! )
+//! .finalize(components::cdc_acm_component_static!(
+//!     components::usb_composite::UsbFunctionComponentType<nrf52840::usbd::Usbd>,
+//!     nrf52840::rtc::Rtc
+//! ));
+//!
+//! // Enables and attaches all functions.
+//! usb.enable();
+//! usb.attach();
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules_extra::usb::composite::{UsbComposite, UsbFunction};
+use kernel::component::Component;
+use kernel::hil;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! usb_composite_component_static {
+    ($U:ty $(,)?) => {{
+        kernel::static_buf!(capsules_extra::usb::composite::UsbComposite<'static, $U>)
+    };};
+}
+
+#[macro_export]
+macro_rules! usb_function_component_static {
+    ($U:ty $(,)?) => {{
+        kernel::static_buf!(capsules_extra::usb::composite::UsbFunction<'static, $U>)
+    };};
+}
+
+pub type UsbCompositeComponentType<U> = UsbComposite<'static, U>;
+pub type UsbFunctionComponentType<U> = UsbFunction<'static, U>;
+
+pub struct UsbCompositeComponent<U: 'static + hil::usb::UsbController<'static>> {
+    usb: &'static U,
+    max_ctrl_packet_size: u8,
+    vendor_id: u16,
+    product_id: u16,
+    strings: &'static [&'static str; 3],
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> UsbCompositeComponent<U> {
+    pub fn new(
+        usb: &'static U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+    ) -> Self {
+        Self {
+            usb,
+            max_ctrl_packet_size,
+            vendor_id,
+            product_id,
+            strings,
+        }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> Component for UsbCompositeComponent<U> {
+    type StaticInput = &'static mut MaybeUninit<UsbComposite<'static, U>>;
+    type Output = &'static UsbComposite<'static, U>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let composite = s.write(UsbComposite::new(
+            self.usb,
+            self.max_ctrl_packet_size,
+            self.vendor_id,
+            self.product_id,
+            self.strings,
+        ));
+        self.usb.set_client(composite);
+
+        composite
+    }
+}
+
+pub struct UsbFunctionComponent<U: 'static + hil::usb::UsbController<'static>> {
+    composite: &'static UsbComposite<'static, U>,
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> UsbFunctionComponent<U> {
+    pub fn new(composite: &'static UsbComposite<'static, U>) -> Self {
+        Self { composite }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> Component for UsbFunctionComponent<U> {
+    type StaticInput = &'static mut MaybeUninit<UsbFunction<'static, U>>;
+    type Output = &'static UsbFunction<'static, U>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let function = s.write(UsbFunction::new(self.composite));
+        function.setup();
+
+        function
+    }
+}
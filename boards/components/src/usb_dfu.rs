@@ -0,0 +1,138 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Component for the USB Device Firmware Upgrade class.
+//!
+//! The board gets a DFU device apps are installed with by `dfu-util`, and
+//! optionally a kernel staging slot.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! struct KernelUpdateCap;
+//! unsafe impl capabilities::KernelUpdateCapability for KernelUpdateCap {}
+//!
+//! let kernel_slot = capsules_extra::usb::dfu::KernelSlot::new(
+//!     staging_storage,
+//!     0x40000,
+//!     &KernelUpdateCap,
+//! );
+//! let dfu = components::usb_dfu::UsbDfuComponent::new(
+//!     &nrf52840_peripherals.usbd,
+//!     capsules_extra::usb::cdc::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x1915,
+//!     0x503a,
+//!     strings,
+//!     dynamic_binary_storage,
+//!     dynamic_binary_storage,
+//!     Some(kernel_slot),
+//! )
+//! .finalize(components::usb_dfu_component_static!(
+//!     nrf52840::usbd::Usbd,
+//!     DynamicBinaryStorage<'static>,
+//!     DynamicBinaryStorage<'static>,
+//! ));
+//! dfu.enable();
+//! dfu.attach();
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules_extra::usb::dfu::{self, KernelSlot, UsbDfu};
+use kernel::component::Component;
+use kernel::dynamic_binary_storage::{DynamicBinaryStore, DynamicProcessLoad};
+use kernel::hil;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! usb_dfu_component_static {
+    ($U:ty, $S:ty, $L:ty $(,)?) => {{
+        let buffer = kernel::static_buf!([u8; capsules_extra::usb::dfu::BUF_LEN]);
+        let dfu = kernel::static_buf!(capsules_extra::usb::dfu::UsbDfu<'static, $U, $S, $L>);
+
+        (buffer, dfu)
+    };};
+}
+
+pub type UsbDfuComponentType<U, S, L> = UsbDfu<'static, U, S, L>;
+
+pub struct UsbDfuComponent<
+    U: 'static + hil::usb::UsbController<'static>,
+    S: DynamicBinaryStore + 'static,
+    L: DynamicProcessLoad + 'static,
+> {
+    usb: &'static U,
+    max_ctrl_packet_size: u8,
+    vendor_id: u16,
+    product_id: u16,
+    strings: &'static [&'static str; 3],
+    storage_driver: &'static S,
+    load_driver: &'static L,
+    kernel_slot: Option<KernelSlot<'static>>,
+}
+
+impl<
+    U: 'static + hil::usb::UsbController<'static>,
+    S: DynamicBinaryStore + 'static,
+    L: DynamicProcessLoad + 'static,
+> UsbDfuComponent<U, S, L>
+{
+    pub fn new(
+        usb: &'static U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+        storage_driver: &'static S,
+        load_driver: &'static L,
+        kernel_slot: Option<KernelSlot<'static>>,
+    ) -> Self {
+        Self {
+            usb,
+            max_ctrl_packet_size,
+            vendor_id,
+            product_id,
+            strings,
+            storage_driver,
+            load_driver,
+            kernel_slot,
+        }
+    }
+}
+
+impl<
+    U: 'static + hil::usb::UsbController<'static>,
+    S: DynamicBinaryStore + 'static,
+    L: DynamicProcessLoad + 'static,
+> Component for UsbDfuComponent<U, S, L>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<[u8; dfu::BUF_LEN]>,
+        &'static mut MaybeUninit<UsbDfu<'static, U, S, L>>,
+    );
+    type Output = &'static UsbDfu<'static, U, S, L>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let buffer = s.0.write([0; dfu::BUF_LEN]);
+        let dfu = s.1.write(UsbDfu::new(
+            self.usb,
+            self.max_ctrl_packet_size,
+            self.vendor_id,
+            self.product_id,
+            self.strings,
+            self.storage_driver,
+            self.load_driver,
+            self.kernel_slot,
+            buffer,
+        ));
+        self.usb.set_client(dfu);
+        self.storage_driver.set_storage_client(dfu);
+        self.load_driver.set_load_client(dfu);
+        if let Some(slot) = self.kernel_slot {
+            slot.storage().set_client(dfu);
+        }
+
+        dfu
+    }
+}
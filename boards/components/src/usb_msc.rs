@@ -0,0 +1,187 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Components for the USB Mass Storage Class.
+//!
+//! This provides two components. `UsbMscComponent` exposes a region of a
+//! nonvolatile storage as a USB drive, and `UsbMscAppDropComponent` creates
+//! a virtual drive apps are installed with by copying them to it.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! static STRINGS: &'static [&str; 3] = &[
+//!     "Tock",           // Manufacturer
+//!     "App Drop",       // Product
+//!     "Serial No. 5",   // Serial number
+//! ];
+//! let app_drop = components::usb_msc::UsbMscAppDropComponent::new(
+//!     dynamic_binary_storage,
+//!     dynamic_binary_storage,
+//! )
+//! .finalize(components::usb_msc_app_drop_component_static!(
+//!     DynamicBinaryStorage<'static>,
+//!     DynamicBinaryStorage<'static>,
+//! ));
+//! let msc = components::usb_msc::UsbMscComponent::new(
+//!     &nrf52::usbd::USBD,
+//!     capsules_extra::usb::usbc_client::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x1915,
+//!     0x503a,
+//!     STRINGS,
+//!     app_drop,
+//!     0,
+//!     capsules_extra::usb::msc_app_drop::NUM_BLOCKS,
+//! )
+//! .finalize(components::usb_msc_component_static!(
+//!     nrf52::usbd::Usbd,
+//!     components::usb_msc::UsbMscAppDropComponentType<
+//!         DynamicBinaryStorage<'static>,
+//!         DynamicBinaryStorage<'static>,
+//!     >,
+//! ));
+//! msc.enable();
+//! msc.attach();
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules_extra::usb::msc::{self, MassStorage};
+use capsules_extra::usb::msc_app_drop::{self, AppDrop};
+use kernel::component::Component;
+use kernel::dynamic_binary_storage::{DynamicBinaryStore, DynamicProcessLoad};
+use kernel::hil;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! usb_msc_component_static {
+    ($U:ty, $S:ty $(,)?) => {{
+        let block = kernel::static_buf!([u8; capsules_extra::usb::msc::BLOCK_SIZE]);
+        let msc = kernel::static_buf!(capsules_extra::usb::msc::MassStorage<'static, $U, $S>);
+
+        (block, msc)
+    };};
+}
+
+#[macro_export]
+macro_rules! usb_msc_app_drop_component_static {
+    ($S:ty, $L:ty $(,)?) => {{
+        let buffer = kernel::static_buf!([u8; capsules_extra::usb::msc_app_drop::BUF_LEN]);
+        let app_drop =
+            kernel::static_buf!(capsules_extra::usb::msc_app_drop::AppDrop<$S, $L>);
+
+        (buffer, app_drop)
+    };};
+}
+
+pub type UsbMscComponentType<U, S> = MassStorage<'static, U, S>;
+pub type UsbMscAppDropComponentType<S, L> = AppDrop<S, L>;
+
+pub struct UsbMscComponent<
+    U: 'static + hil::usb::UsbController<'static>,
+    S: 'static + NonvolatileStorage<'static>,
+> {
+    usb: &'static U,
+    max_ctrl_packet_size: u8,
+    vendor_id: u16,
+    product_id: u16,
+    strings: &'static [&'static str; 3],
+    storage: &'static S,
+    start_address: usize,
+    num_blocks: u32,
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>, S: 'static + NonvolatileStorage<'static>>
+    UsbMscComponent<U, S>
+{
+    pub fn new(
+        usb: &'static U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+        storage: &'static S,
+        start_address: usize,
+        num_blocks: u32,
+    ) -> Self {
+        Self {
+            usb,
+            max_ctrl_packet_size,
+            vendor_id,
+            product_id,
+            strings,
+            storage,
+            start_address,
+            num_blocks,
+        }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>, S: 'static + NonvolatileStorage<'static>>
+    Component for UsbMscComponent<U, S>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<[u8; msc::BLOCK_SIZE]>,
+        &'static mut MaybeUninit<MassStorage<'static, U, S>>,
+    );
+    type Output = &'static MassStorage<'static, U, S>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let block = s.0.write([0; msc::BLOCK_SIZE]);
+        let msc = s.1.write(MassStorage::new(
+            self.usb,
+            self.max_ctrl_packet_size,
+            self.vendor_id,
+            self.product_id,
+            self.strings,
+            self.storage,
+            self.start_address,
+            self.num_blocks,
+            block,
+        ));
+        self.usb.set_client(msc);
+        self.storage.set_client(msc);
+
+        msc
+    }
+}
+
+pub struct UsbMscAppDropComponent<S: DynamicBinaryStore + 'static, L: DynamicProcessLoad + 'static>
+{
+    storage_driver: &'static S,
+    load_driver: &'static L,
+}
+
+impl<S: DynamicBinaryStore + 'static, L: DynamicProcessLoad + 'static>
+    UsbMscAppDropComponent<S, L>
+{
+    pub fn new(storage_driver: &'static S, load_driver: &'static L) -> Self {
+        Self {
+            storage_driver,
+            load_driver,
+        }
+    }
+}
+
+impl<S: DynamicBinaryStore + 'static, L: DynamicProcessLoad + 'static> Component
+    for UsbMscAppDropComponent<S, L>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<[u8; msc_app_drop::BUF_LEN]>,
+        &'static mut MaybeUninit<AppDrop<S, L>>,
+    );
+    type Output = &'static AppDrop<S, L>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let buffer = s.0.write([0; msc_app_drop::BUF_LEN]);
+        let app_drop =
+            s.1.write(AppDrop::new(self.storage_driver, self.load_driver, buffer));
+        kernel::deferred_call::DeferredCallClient::register(app_drop);
+        self.storage_driver.set_storage_client(app_drop);
+        self.load_driver.set_load_client(app_drop);
+
+        app_drop
+    }
+}
@@ -60,13 +60,22 @@ static PANIC_RESOURCES: SingleThreadValue<PanicResources<ChipHw, ProcessPrinter>
 
 kernel::stack_size! {0x2000}
 
+// Length of a process checkpoint slot, which holds the RAM of one process.
+const CHECKPOINT_SLOT_LENGTH: usize = 0x4000;
+
+/// Capability for the syscall driver that checkpoints processes.
+pub struct ProcessMgmtCap;
+unsafe impl capabilities::ProcessManagementCapability for ProcessMgmtCap {}
+
 //------------------------------------------------------------------------------
 // SYSCALL DRIVER TYPE DEFINITIONS
 //------------------------------------------------------------------------------
 
 type AlarmDriver = components::alarm::AlarmDriverComponentType<nrf52840::rtc::Rtc<'static>>;
 
-type NonVolatilePages = components::dynamic_binary_storage::NVPages<nrf52840::nvmc::Nvmc>;
+type VirtualFlash =
+    capsules_core::virtualizers::virtual_flash::FlashUser<'static, nrf52840::nvmc::Nvmc>;
+type NonVolatilePages = components::dynamic_binary_storage::NVPages<VirtualFlash>;
 type DynamicBinaryStorage<'a> = kernel::dynamic_binary_storage::SequentialDynamicBinaryStorage<
     'static,
     'static,
@@ -94,6 +103,11 @@ pub struct Platform {
     dynamic_app_loader: &'static capsules_extra::app_loader::AppLoader<
         DynamicBinaryStorage<'static>,
         DynamicBinaryStorage<'static>,
+        DynamicBinaryStorage<'static>,
+    >,
+    process_checkpoint: &'static capsules_extra::process_checkpoint::ProcessCheckpointDriver<
+        'static,
+        ProcessMgmtCap,
     >,
 }
 
@@ -109,6 +123,7 @@ impl SyscallDriverLookup for Platform {
             capsules_core::button::DRIVER_NUM => f(Some(self.button)),
             capsules_core::adc::DRIVER_NUM => f(Some(self.adc)),
             capsules_extra::app_loader::DRIVER_NUM => f(Some(self.dynamic_app_loader)),
+            capsules_extra::process_checkpoint::DRIVER_NUM => f(Some(self.process_checkpoint)),
             _ => f(None),
         }
     }
@@ -467,6 +482,66 @@ pub unsafe fn main() {
         NUM_PROCS
     ));
 
+    //--------------------------------------------------------------------------
+    // VIRTUAL FLASH
+    //--------------------------------------------------------------------------
+
+    let mux_flash = components::flash::FlashMuxComponent::new(&base_peripherals.nvmc).finalize(
+        components::flash_mux_component_static!(nrf52840::nvmc::Nvmc),
+    );
+
+    // Create a virtual flash user for dynamic binary storage
+    let virtual_flash_dbs = components::flash::FlashUserComponent::new(mux_flash).finalize(
+        components::flash_user_component_static!(nrf52840::nvmc::Nvmc),
+    );
+
+    // Create a virtual flash user for process checkpoints
+    let virtual_flash_checkpoint = components::flash::FlashUserComponent::new(mux_flash).finalize(
+        components::flash_user_component_static!(nrf52840::nvmc::Nvmc),
+    );
+
+    //--------------------------------------------------------------------------
+    // PROCESS CHECKPOINTS
+    //--------------------------------------------------------------------------
+
+    // 64kB of flash for checkpoints, two slots for each of two processes.
+    kernel::storage_volume!(CHECKPOINTS, 64);
+
+    let checkpoint_page = static_init!(nrf52840::nvmc::NrfPage, nrf52840::nvmc::NrfPage::default());
+    let checkpoint_storage = static_init!(
+        NonVolatilePages,
+        capsules_extra::nonvolatile_to_pages::NonvolatileToPages::new(
+            virtual_flash_checkpoint,
+            checkpoint_page
+        )
+    );
+    kernel::hil::flash::HasClient::set_client(virtual_flash_checkpoint, checkpoint_storage);
+
+    // Processes are loaded once the kernel loop starts, so they are restored
+    // from their checkpoints.
+    let checkpoint = components::process_checkpoint::ProcessCheckpointComponent::new(
+        board_kernel,
+        checkpoint_storage,
+        loader,
+        core::slice::from_raw_parts(
+            core::ptr::addr_of!(CHECKPOINTS).cast::<u8>(),
+            CHECKPOINTS.len(),
+        ),
+        CHECKPOINT_SLOT_LENGTH,
+    )
+    .finalize(components::process_checkpoint_component_static!(
+        NonVolatilePages
+    ));
+
+    let process_checkpoint = components::process_checkpoint::ProcessCheckpointDriverComponent::new(
+        board_kernel,
+        checkpoint,
+        ProcessMgmtCap,
+    )
+    .finalize(components::process_checkpoint_driver_component_static!(
+        ProcessMgmtCap
+    ));
+
     //--------------------------------------------------------------------------
     // Dynamic App Loading
     //--------------------------------------------------------------------------
@@ -474,25 +549,32 @@ pub unsafe fn main() {
     // Create the dynamic binary flasher.
     let dynamic_binary_storage =
         components::dynamic_binary_storage::SequentialBinaryStorageComponent::new(
-            &base_peripherals.nvmc,
+            virtual_flash_dbs,
             loader,
         )
         .finalize(components::sequential_binary_storage_component_static!(
-            nrf52840::nvmc::Nvmc,
+            VirtualFlash,
             nrf52840::chip::NRF52<Nrf52840DefaultPeripherals>,
             kernel::process::ProcessStandardDebugFull,
         ));
 
+    // The kernel restarts upgraded apps on trial after a fault, and rolls the
+    // upgrade back after three faults.
+    dynamic_binary_storage.set_fault_policy(&FAULT_RESPONSE);
+    loader.set_fault_policy(dynamic_binary_storage);
+
     // Create the dynamic app loader capsule.
     let dynamic_app_loader = components::app_loader::AppLoaderComponent::new(
         board_kernel,
         capsules_extra::app_loader::DRIVER_NUM,
         dynamic_binary_storage,
         dynamic_binary_storage,
+        dynamic_binary_storage,
     )
     .finalize(components::app_loader_component_static!(
         DynamicBinaryStorage<'static>,
         DynamicBinaryStorage<'static>,
+        DynamicBinaryStorage<'static>,
     ));
 
     //--------------------------------------------------------------------------
@@ -514,6 +596,7 @@ pub unsafe fn main() {
             systick: cortexm4::systick::SysTick::new_with_calibration(64000000),
             processes,
             dynamic_app_loader,
+            process_checkpoint,
         }
     );
     loader.set_client(platform);
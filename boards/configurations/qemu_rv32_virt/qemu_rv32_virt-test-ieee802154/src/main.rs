@@ -0,0 +1,182 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Board file for a qemu-system-riscv32 "virt" machine with a simulated
+//! IEEE 802.15.4 radio.
+//!
+//! The VirtIO NetworkCard is used to tunnel 802.15.4 frames to a radio
+//! medium simulator on the host
+//! (`tools/debugging-and-development/ieee802154_medium.py`), instead of
+//! exposing it as an Ethernet interface. This allows running several nodes of
+//! an 802.15.4 network, each in its own QEMU instance.
+
+#![no_std]
+#![no_main]
+
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil::radio::RadioConfig;
+use kernel::platform::KernelResources;
+use kernel::platform::SyscallDriverLookup;
+use kernel::{create_capability, debug};
+
+// How should the kernel respond when a process faults.
+const FAULT_RESPONSE: capsules_system::process_policies::PanicFaultPolicy =
+    capsules_system::process_policies::PanicFaultPolicy {};
+
+type Ieee802154Driver =
+    components::ieee802154::Ieee802154RawComponentType<qemu_rv32_virt_lib::Ieee802154MediumRadio>;
+
+struct Platform {
+    base: qemu_rv32_virt_lib::QemuRv32VirtPlatform,
+    ieee802154: Option<&'static Ieee802154Driver>,
+    eui64: Option<&'static capsules_extra::eui64::Eui64>,
+}
+
+impl SyscallDriverLookup for Platform {
+    fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
+    where
+        F: FnOnce(Option<&dyn kernel::syscall::SyscallDriver>) -> R,
+    {
+        match driver_num {
+            capsules_extra::ieee802154::DRIVER_NUM => match self.ieee802154 {
+                Some(driver) => f(Some(driver)),
+                None => f(None),
+            },
+            capsules_extra::eui64::DRIVER_NUM => match self.eui64 {
+                Some(driver) => f(Some(driver)),
+                None => f(None),
+            },
+
+            _ => self.base.with_driver(driver_num, f),
+        }
+    }
+}
+
+impl KernelResources<qemu_rv32_virt_lib::ChipHw> for Platform {
+    type SyscallDriverLookup = Self;
+    type SyscallFilter = <qemu_rv32_virt_lib::QemuRv32VirtPlatform as KernelResources<
+        qemu_rv32_virt_lib::ChipHw,
+    >>::SyscallFilter;
+    type ProcessFault = <qemu_rv32_virt_lib::QemuRv32VirtPlatform as KernelResources<
+        qemu_rv32_virt_lib::ChipHw,
+    >>::ProcessFault;
+    type Scheduler = <qemu_rv32_virt_lib::QemuRv32VirtPlatform as KernelResources<
+        qemu_rv32_virt_lib::ChipHw,
+    >>::Scheduler;
+    type SchedulerTimer = <qemu_rv32_virt_lib::QemuRv32VirtPlatform as KernelResources<
+        qemu_rv32_virt_lib::ChipHw,
+    >>::SchedulerTimer;
+    type WatchDog = <qemu_rv32_virt_lib::QemuRv32VirtPlatform as KernelResources<
+        qemu_rv32_virt_lib::ChipHw,
+    >>::WatchDog;
+    type ContextSwitchCallback = <qemu_rv32_virt_lib::QemuRv32VirtPlatform as KernelResources<
+        qemu_rv32_virt_lib::ChipHw,
+    >>::ContextSwitchCallback;
+
+    fn syscall_driver_lookup(&self) -> &Self::SyscallDriverLookup {
+        self
+    }
+    fn syscall_filter(&self) -> &Self::SyscallFilter {
+        self.base.syscall_filter()
+    }
+    fn process_fault(&self) -> &Self::ProcessFault {
+        self.base.process_fault()
+    }
+    fn scheduler(&self) -> &Self::Scheduler {
+        self.base.scheduler()
+    }
+    fn scheduler_timer(&self) -> &Self::SchedulerTimer {
+        self.base.scheduler_timer()
+    }
+    fn watchdog(&self) -> &Self::WatchDog {
+        self.base.watchdog()
+    }
+    fn context_switch_callback(&self) -> &Self::ContextSwitchCallback {
+        self.base.context_switch_callback()
+    }
+}
+
+/// Main function called after RAM initialized.
+#[no_mangle]
+pub unsafe fn main() {
+    let main_loop_capability = create_capability!(capabilities::MainLoopCapability);
+
+    let (board_kernel, base_platform, chip) =
+        qemu_rv32_virt_lib::start_with_network(qemu_rv32_virt_lib::NetworkMode::Ieee802154Medium);
+
+    //--------------------------------------------------------------------------
+    // IEEE 802.15.4
+    //--------------------------------------------------------------------------
+
+    // The radio is only present if QEMU was started with a VirtIO
+    // NetworkCard, see the Makefile.
+    let ieee802154 = base_platform.virtio_ieee802154_radio.map(|radio| {
+        components::ieee802154::Ieee802154RawComponent::new(
+            board_kernel,
+            capsules_extra::ieee802154::DRIVER_NUM,
+            radio,
+        )
+        .finalize(components::ieee802154_raw_component_static!(
+            qemu_rv32_virt_lib::Ieee802154MediumRadio,
+        ))
+    });
+
+    // The EUI-64 is derived from the MAC address of the NetworkCard, so it is
+    // unique per node.
+    let eui64 = base_platform.virtio_ieee802154_radio.map(|radio| {
+        components::eui64::Eui64Component::new(u64::from_be_bytes(radio.get_address_long()))
+            .finalize(components::eui64_component_static!())
+    });
+
+    let platform = Platform {
+        base: base_platform,
+        ieee802154,
+        eui64,
+    };
+
+    // Start the process console:
+    let _ = platform.base.pconsole.start();
+
+    // These symbols are defined in the linker script.
+    extern "C" {
+        /// Beginning of the ROM region containing app images.
+        static _sapps: u8;
+        /// End of the ROM region containing app images.
+        static _eapps: u8;
+        /// Beginning of the RAM region for app memory.
+        static mut _sappmem: u8;
+        /// End of the RAM region for app memory.
+        static _eappmem: u8;
+    }
+    let process_mgmt_cap = create_capability!(capabilities::ProcessManagementCapability);
+
+    kernel::process::load_processes(
+        board_kernel,
+        chip,
+        core::slice::from_raw_parts(
+            core::ptr::addr_of!(_sapps),
+            core::ptr::addr_of!(_eapps) as usize - core::ptr::addr_of!(_sapps) as usize,
+        ),
+        core::slice::from_raw_parts_mut(
+            core::ptr::addr_of_mut!(_sappmem),
+            core::ptr::addr_of!(_eappmem) as usize - core::ptr::addr_of!(_sappmem) as usize,
+        ),
+        &FAULT_RESPONSE,
+        &process_mgmt_cap,
+    )
+    .unwrap_or_else(|err| {
+        debug!("Error loading processes!");
+        debug!("{:?}", err);
+    });
+
+    debug!("Entering main loop.");
+
+    board_kernel.kernel_loop(
+        &platform,
+        chip,
+        Some(&platform.base.ipc),
+        &main_loop_capability,
+    );
+}
@@ -70,7 +70,7 @@
 #![no_std]
 #![deny(missing_docs)]
 
-use capsules_core::virtualizers::virtual_alarm::MuxAlarm;
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use capsules_extra::net::ieee802154::MacAddress;
 use capsules_extra::net::ipv6::ip_utils::IPAddr;
 use kernel::component::Component;
@@ -135,6 +135,17 @@ pub mod io;
 /// - Set to true to use Segger RTT over USB.
 pub const USB_DEBUGGING: bool = false;
 
+/// Whether to send a binary trace of the system calls of processes over Segger
+/// RTT, see `capsules_extra::syscall_trace`.
+///
+/// The board has one RTT channel, so this requires `USB_DEBUGGING` to be false.
+pub const SYSCALL_TRACE: bool = false;
+
+const _: () = assert!(
+    !(USB_DEBUGGING && SYSCALL_TRACE),
+    "The console and the syscall trace can't share the RTT channel"
+);
+
 /// This platform's chip type:
 pub type ChipHw = nrf52840::chip::NRF52<'static, Nrf52840DefaultPeripherals<'static>>;
 /// Type for the process details printer.
@@ -215,6 +226,15 @@ pub type Eui64Driver = components::eui64::Eui64ComponentType;
 /// Userspace UDP driver.
 pub type UdpDriver = components::udp_driver::UDPDriverComponentType;
 
+// USB
+type UsbHw = nrf52840::usbd::Usbd<'static>;
+type UsbFunction = components::usb_composite::UsbFunctionComponentType<UsbHw>;
+/// Userspace CTAP driver on a function of the composite USB device.
+pub type CtapDriver = capsules_extra::usb_hid_driver::UsbHidDriver<
+    'static,
+    capsules_extra::usb::ctap::CtapHid<'static, UsbFunction>,
+>;
+
 type SchedulerInUse = components::sched::round_robin::RoundRobinComponentType;
 
 /// Supported drivers by the platform
@@ -383,6 +403,78 @@ pub unsafe fn ieee802154_udp(
     (eui64_driver, ieee802154_driver, udp_driver)
 }
 
+/// Create a composite USB device with a CTAP security key and a CDC-ACM
+/// serial port, which runs a second process console for debugging.
+pub unsafe fn usb_ctap_cdc(
+    board_kernel: &'static kernel::Kernel,
+    nrf52840_peripherals: &'static Nrf52840DefaultPeripherals<'static>,
+    mux_alarm: &'static MuxAlarm<AlarmHw>,
+) -> &'static CtapDriver {
+    // Create the strings we include in the USB descriptor.
+    let strings = static_init!(
+        [&str; 3],
+        [
+            "Nordic Semiconductor", // Manufacturer
+            "nRF52840dk - TockOS",  // Product
+            "serial0001",           // Serial number
+        ]
+    );
+
+    let usb = components::usb_composite::UsbCompositeComponent::new(
+        &nrf52840_peripherals.usbd,
+        capsules_extra::usb::cdc::MAX_CTRL_PACKET_SIZE_NRF52840,
+        0x1915, // Nordic Semiconductor
+        0x503a,
+        strings,
+    )
+    .finalize(components::usb_composite_component_static!(UsbHw));
+
+    let ctap_function = components::usb_composite::UsbFunctionComponent::new(usb)
+        .finalize(components::usb_function_component_static!(UsbHw));
+    let (_ctap, ctap_driver) = components::ctap::CtapComponent::new(
+        board_kernel,
+        capsules_core::driver::NUM::CtapHid as usize,
+        ctap_function,
+        0x1915,
+        0x503a,
+        strings,
+    )
+    .finalize(components::ctap_component_static!(UsbFunction));
+
+    let cdc_function = components::usb_composite::UsbFunctionComponent::new(usb)
+        .finalize(components::usb_function_component_static!(UsbHw));
+    let cdc = components::cdc::CdcAcmComponent::new(
+        cdc_function,
+        capsules_extra::usb::cdc::MAX_CTRL_PACKET_SIZE_NRF52840,
+        0x1915,
+        0x503a,
+        strings,
+        mux_alarm,
+        None,
+    )
+    .finalize(components::cdc_acm_component_static!(UsbFunction, AlarmHw));
+
+    let cdc_mux = components::console::UartMuxComponent::new(cdc, 115200)
+        .finalize(components::uart_mux_component_static!());
+    let process_printer = components::process_printer::ProcessPrinterTextComponent::new()
+        .finalize(components::process_printer_text_component_static!());
+    let cdc_pconsole = components::process_console::ProcessConsoleComponent::new(
+        board_kernel,
+        cdc_mux,
+        mux_alarm,
+        process_printer,
+        Some(cortexm4::support::reset),
+    )
+    .finalize(components::process_console_component_static!(AlarmHw));
+    let _ = cdc_pconsole.start();
+
+    // Enables and attaches all functions.
+    usb.enable();
+    usb.attach();
+
+    ctap_driver
+}
+
 /// This is in a separate, inline(never) function so that its stack frame is
 /// removed when this function returns. Otherwise, the stack space used for
 /// these static_inits is wasted.
@@ -449,20 +541,26 @@ pub unsafe fn start_no_pconsole() -> (
 
     // Choose the channel for serial output. This board can be configured to use
     // either the Segger RTT channel or via UART with traditional TX/RX GPIO
-    // pins.
-    let uart_channel = if USB_DEBUGGING {
-        // Initialize early so any panic beyond this point can use the RTT
-        // memory object.
-        let rtt_memory_refs = components::segger_rtt::SeggerRttMemoryComponent::new()
-            .finalize(components::segger_rtt_memory_component_static!());
-
-        RTT_BUFFER.get().map(|rtt_buffer_cell| {
-            rtt_buffer_cell.replace(*core::ptr::addr_of!(rtt_memory_refs.rtt_memory))
-        });
-
-        UartChannel::Rtt(rtt_memory_refs)
-    } else {
-        UartChannel::Pins(UartPins::new(UART_RTS, UART_TXD, UART_CTS, UART_RXD))
+    // pins. If the console uses the UART, the RTT channel can carry the syscall
+    // trace instead.
+    let rtt_memory_refs = (USB_DEBUGGING || SYSCALL_TRACE).then(|| {
+        components::segger_rtt::SeggerRttMemoryComponent::new()
+            .finalize(components::segger_rtt_memory_component_static!())
+    });
+    let (uart_channel, trace_rtt_memory_refs) = match rtt_memory_refs {
+        Some(rtt_memory_refs) if USB_DEBUGGING => {
+            // Initialize early so any panic beyond this point can use the RTT
+            // memory object.
+            RTT_BUFFER.get().map(|rtt_buffer_cell| {
+                rtt_buffer_cell.replace(*core::ptr::addr_of!(rtt_memory_refs.rtt_memory))
+            });
+
+            (UartChannel::Rtt(rtt_memory_refs), None)
+        }
+        trace_rtt_memory_refs => (
+            UartChannel::Pins(UartPins::new(UART_RTS, UART_TXD, UART_CTS, UART_RXD)),
+            trace_rtt_memory_refs,
+        ),
     };
 
     // Create an array to hold process references.
@@ -597,6 +695,26 @@ pub unsafe fn start_no_pconsole() -> (
     )
     .finalize(components::alarm_component_static!(AlarmHw));
 
+    //--------------------------------------------------------------------------
+    // SYSCALL TRACE
+    //--------------------------------------------------------------------------
+
+    if let Some(rtt_memory_refs) = trace_rtt_memory_refs {
+        let rtt = components::segger_rtt::SeggerRttComponent::new(mux_alarm, rtt_memory_refs)
+            .finalize(components::segger_rtt_component_static!(AlarmHw));
+        components::syscall_trace::SyscallTraceComponent::new(
+            board_kernel,
+            rtc,
+            rtt,
+            create_capability!(capabilities::ProcessManagementCapability),
+        )
+        .finalize(components::syscall_trace_component_static!(
+            AlarmHw,
+            segger::rtt::SeggerRtt<'static, VirtualMuxAlarm<'static, AlarmHw>>,
+            256, // Number of events in the ring buffer
+        ));
+    }
+
     //--------------------------------------------------------------------------
     // UART & CONSOLE & DEBUG
     //--------------------------------------------------------------------------
@@ -771,6 +889,14 @@ pub unsafe fn start_no_pconsole() -> (
         TICKV_PAGE_SIZE,
     ));
 
+    // Let garbage collection move objects of up to 256 bytes out of worn
+    // regions.
+    tickv.set_relocation_buffer(static_init!([u8; 256], [0; 256]));
+
+    // Let the process console print TicKV usage and wear statistics.
+    kernel::hil::kv::KVStatistics::set_statistics_client(tickv, pconsole);
+    pconsole.set_kv_statistics(tickv);
+
     // KVSystem interface to KV (built on TicKV).
     let tickv_kv_store = components::kv::TicKVKVStoreComponent::new(tickv).finalize(
         components::tickv_kv_store_component_static!(
@@ -893,6 +1019,8 @@ pub unsafe fn start_no_pconsole() -> (
     // keyboard_hid.enable();
     // keyboard_hid.attach();
 
+    // See `usb_ctap_cdc()` for a composite device with CTAP and CDC-ACM.
+
     //--------------------------------------------------------------------------
     // PLATFORM SETUP, SCHEDULER, AND START KERNEL LOOP
     //--------------------------------------------------------------------------
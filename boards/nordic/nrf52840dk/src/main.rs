@@ -8,6 +8,7 @@
 #![no_main]
 #![deny(missing_docs)]
 
+use kernel::component::Component;
 use kernel::debug;
 use kernel::platform::{KernelResources, SyscallDriverLookup};
 use kernel::{capabilities, create_capability};
@@ -17,11 +18,15 @@ use kernel::{capabilities, create_capability};
 const FAULT_RESPONSE: capsules_system::process_policies::PanicFaultPolicy =
     capsules_system::process_policies::PanicFaultPolicy {};
 
+const CTAP_DRIVER_NUM: usize = capsules_core::driver::NUM::CtapHid as usize;
+
 struct Platform {
     base: nrf52840dk_lib::Platform,
     eui64_driver: &'static nrf52840dk_lib::Eui64Driver,
     ieee802154_driver: &'static nrf52840dk_lib::Ieee802154Driver,
     udp_driver: &'static nrf52840dk_lib::UdpDriver,
+    panic_record: &'static capsules_extra::panic_record::PanicRecordDriver<'static>,
+    ctap_driver: &'static nrf52840dk_lib::CtapDriver,
 }
 
 impl SyscallDriverLookup for Platform {
@@ -33,6 +38,8 @@ impl SyscallDriverLookup for Platform {
             capsules_extra::eui64::DRIVER_NUM => f(Some(self.eui64_driver)),
             capsules_extra::net::udp::DRIVER_NUM => f(Some(self.udp_driver)),
             capsules_extra::ieee802154::DRIVER_NUM => f(Some(self.ieee802154_driver)),
+            capsules_extra::panic_record::DRIVER_NUM => f(Some(self.panic_record)),
+            CTAP_DRIVER_NUM => f(Some(self.ctap_driver)),
             _ => self.base.with_driver(driver_num, f),
         }
     }
@@ -79,6 +86,24 @@ pub unsafe fn main() {
     let (board_kernel, base_platform, chip, default_peripherals, mux_alarm) =
         nrf52840dk_lib::start();
 
+    //--------------------------------------------------------------------------
+    // PANIC RECORD
+    //--------------------------------------------------------------------------
+
+    // Set up the panic record first, so that as much debug output as possible
+    // is recorded.
+    let panic_record = components::panic_record::PanicRecordComponent::<
+        <ChipHw as kernel::platform::chip::Chip>::ThreadIdProvider,
+    >::new(
+        board_kernel,
+        capsules_extra::panic_record::DRIVER_NUM,
+        None,
+        0,
+    )
+    .finalize(components::panic_record_component_static!(
+        <ChipHw as kernel::platform::chip::Chip>::ThreadIdProvider
+    ));
+
     //--------------------------------------------------------------------------
     // IEEE 802.15.4 and UDP
     //--------------------------------------------------------------------------
@@ -86,11 +111,20 @@ pub unsafe fn main() {
     let (eui64_driver, ieee802154_driver, udp_driver) =
         nrf52840dk_lib::ieee802154_udp(board_kernel, default_peripherals, mux_alarm);
 
+    //--------------------------------------------------------------------------
+    // USB
+    //--------------------------------------------------------------------------
+
+    // A CTAP security key with a process console on a CDC-ACM serial port.
+    let ctap_driver = nrf52840dk_lib::usb_ctap_cdc(board_kernel, default_peripherals, mux_alarm);
+
     let platform = Platform {
         base: base_platform,
         eui64_driver,
         ieee802154_driver,
         udp_driver,
+        panic_record,
+        ctap_driver,
     };
 
     // These symbols are defined in the linker script.
@@ -42,10 +42,28 @@ pub type ScreenHw = qemu_rv32_virt_chip::virtio::devices::virtio_gpu::VirtIOGPU<
 >;
 
 type AlarmHw = qemu_rv32_virt_chip::chip::QemuRv32VirtClint<'static>;
+type NetHw =
+    qemu_rv32_virt_chip::virtio::devices::virtio_net::VirtIONet<'static, RiscvCoherentDmaFence>;
+pub type Ieee802154MediumRadio = capsules_extra::ieee802154_medium::MediumRadio<
+    'static,
+    NetHw,
+    VirtualMuxAlarm<'static, AlarmHw>,
+>;
 type SchedulerTimerHw =
     components::virtual_scheduler_timer::VirtualSchedulerTimerComponentType<AlarmHw>;
 type SchedulerInUse = components::sched::cooperative::CooperativeComponentType;
 
+/// What a VirtIO NetworkCard, if present, is used for.
+#[derive(Clone, Copy, PartialEq)]
+pub enum NetworkMode {
+    /// Forward raw Ethernet frames from and to userspace.
+    EthernetTap,
+    /// Simulate an IEEE 802.15.4 radio, by tunnelling its frames to a medium
+    /// simulator on the host (see
+    /// `tools/debugging-and-development/ieee802154_medium.py`).
+    Ieee802154Medium,
+}
+
 /// Resources for when a board panics used by io.rs.
 static PANIC_RESOURCES: SingleThreadValue<PanicResources<ChipHw, ProcessPrinter>> =
     SingleThreadValue::new();
@@ -86,6 +104,8 @@ pub struct QemuRv32VirtPlatform {
             >,
         >,
     >,
+    /// Simulated 802.15.4 radio, with `NetworkMode::Ieee802154Medium`.
+    pub virtio_ieee802154_radio: Option<&'static Ieee802154MediumRadio>,
     pub virtio_gpu_screen: Option<
         &'static capsules_extra::screen::screen_adapters::ScreenARGB8888ToMono8BitPage<
             'static,
@@ -170,6 +190,21 @@ impl
     }
 }
 
+/// Initialize the board, using a VirtIO NetworkCard for the Ethernet Tap
+/// driver.
+pub unsafe fn start() -> (
+    &'static kernel::Kernel,
+    QemuRv32VirtPlatform,
+    &'static qemu_rv32_virt_chip::chip::QemuRv32VirtChip<
+        'static,
+        QemuRv32VirtDefaultPeripherals<'static>,
+    >,
+) {
+    start_with_network(NetworkMode::EthernetTap)
+}
+
+/// Initialize the board, choosing what a VirtIO NetworkCard is used for.
+///
 /// This is in a separate, inline(never) function so that its stack frame is
 /// removed when this function returns. Otherwise, the stack space used for
 /// these static_inits is wasted.
@@ -178,7 +213,9 @@ impl
 // into an ARGB_8888 format. This can consume a large amount of stack
 // space, as we allocate this buffer with `static_init!()`:
 #[allow(clippy::large_stack_frames, clippy::large_stack_arrays)]
-pub unsafe fn start() -> (
+pub unsafe fn start_with_network(
+    network_mode: NetworkMode,
+) -> (
     &'static kernel::Kernel,
     QemuRv32VirtPlatform,
     &'static qemu_rv32_virt_chip::chip::QemuRv32VirtChip<
@@ -483,18 +520,9 @@ pub unsafe fn start() -> (
 
     // If there is a VirtIO NetworkCard present, use the appropriate VirtIONet
     // driver, and expose this device through the Ethernet Tap driver
-    // (forwarding raw Ethernet frames from and to userspace).
-    let virtio_ethernet_tap: Option<
-        &'static capsules_extra::ethernet_tap::EthernetTapDriver<
-            'static,
-            qemu_rv32_virt_chip::virtio::devices::virtio_net::VirtIONet<
-                'static,
-                RiscvCoherentDmaFence,
-            >,
-        >,
-    > = if let Some(net_idx) = virtio_net_idx {
-        use capsules_extra::ethernet_tap::EthernetTapDriver;
-        use kernel::hil::ethernet::EthernetAdapterDatapath;
+    // (forwarding raw Ethernet frames from and to userspace), or as a
+    // simulated 802.15.4 radio, depending on `network_mode`.
+    let virtio_net: Option<(&'static NetHw, [u8; 6])> = if let Some(net_idx) = virtio_net_idx {
         use qemu_rv32_virt_chip::virtio::devices::virtio_net::VirtIONet;
         use qemu_rv32_virt_chip::virtio::queues::Virtqueue;
         use qemu_rv32_virt_chip::virtio::queues::split_queue::{
@@ -555,36 +583,82 @@ pub unsafe fn start() -> (
             .initialize(virtio_net, mmio_queues)
             .unwrap();
 
-        // Instantiate the userspace tap network driver over this device:
-        let virtio_ethernet_tap_tx_buffer = static_init!(
-            [u8; capsules_extra::ethernet_tap::MAX_MTU],
-            [0; capsules_extra::ethernet_tap::MAX_MTU],
-        );
-        let virtio_ethernet_tap = static_init!(
-            EthernetTapDriver<'static, VirtIONet<'static, RiscvCoherentDmaFence>>,
-            EthernetTapDriver::new(
-                virtio_net,
-                board_kernel.create_grant(
-                    capsules_extra::ethernet_tap::DRIVER_NUM,
-                    &memory_allocation_cap
-                ),
-                virtio_ethernet_tap_tx_buffer,
-            ),
-        );
-        virtio_net.set_client(virtio_ethernet_tap);
+        // The MAC address is at the start of the device configuration space.
+        let mut mac = [0; 6];
+        peripherals.virtio_mmio[net_idx].read_device_config(0, &mut mac);
 
-        // This enables reception on the underlying device:
-        virtio_ethernet_tap.initialize();
-
-        Some(
-            virtio_ethernet_tap
-                as &'static EthernetTapDriver<'static, VirtIONet<'static, RiscvCoherentDmaFence>>,
-        )
+        Some((virtio_net, mac))
     } else {
         // No VirtIO NetworkCard discovered
         None
     };
 
+    let virtio_ethernet_tap: Option<
+        &'static capsules_extra::ethernet_tap::EthernetTapDriver<'static, NetHw>,
+    > = match (virtio_net, network_mode) {
+        (Some((virtio_net, _)), NetworkMode::EthernetTap) => {
+            use capsules_extra::ethernet_tap::EthernetTapDriver;
+            use kernel::hil::ethernet::EthernetAdapterDatapath;
+
+            // Instantiate the userspace tap network driver over this device:
+            let virtio_ethernet_tap_tx_buffer = static_init!(
+                [u8; capsules_extra::ethernet_tap::MAX_MTU],
+                [0; capsules_extra::ethernet_tap::MAX_MTU],
+            );
+            let virtio_ethernet_tap = static_init!(
+                EthernetTapDriver<'static, NetHw>,
+                EthernetTapDriver::new(
+                    virtio_net,
+                    board_kernel.create_grant(
+                        capsules_extra::ethernet_tap::DRIVER_NUM,
+                        &memory_allocation_cap
+                    ),
+                    virtio_ethernet_tap_tx_buffer,
+                ),
+            );
+            virtio_net.set_client(virtio_ethernet_tap);
+
+            // This enables reception on the underlying device:
+            virtio_ethernet_tap.initialize();
+
+            Some(virtio_ethernet_tap as &'static EthernetTapDriver<'static, NetHw>)
+        }
+        _ => None,
+    };
+
+    let virtio_ieee802154_radio: Option<&'static Ieee802154MediumRadio> =
+        match (virtio_net, network_mode) {
+            (Some((virtio_net, mac)), NetworkMode::Ieee802154Medium) => {
+                use capsules_extra::ieee802154_medium::{FRAME_BUF_LEN, MediumRadio};
+                use kernel::deferred_call::DeferredCallClient;
+                use kernel::hil::ethernet::EthernetAdapterDatapath;
+                use kernel::hil::radio::RadioConfig;
+
+                let radio_alarm = static_init!(
+                    VirtualMuxAlarm<'static, AlarmHw>,
+                    VirtualMuxAlarm::new(mux_alarm)
+                );
+                radio_alarm.setup();
+                let frame_buf = static_init!([u8; FRAME_BUF_LEN], [0; FRAME_BUF_LEN]);
+                let radio = static_init!(
+                    Ieee802154MediumRadio,
+                    MediumRadio::new(virtio_net, radio_alarm, mac, frame_buf)
+                );
+                virtio_net.set_client(radio);
+                hil::time::Alarm::set_alarm_client(radio_alarm, radio);
+                radio.register();
+
+                // Each QEMU instance is given its own MAC address, from which the
+                // EUI-64 and short address of the node are derived.
+                radio
+                    .set_address_long([mac[0], mac[1], mac[2], 0xFF, 0xFE, mac[3], mac[4], mac[5]]);
+                radio.set_address(u16::from_be_bytes([mac[4], mac[5]]));
+
+                Some(radio as &'static Ieee802154MediumRadio)
+            }
+            _ => None,
+        };
+
     let virtio_input_keyboard: Option<
         &'static qemu_rv32_virt_chip::virtio::devices::virtio_input::VirtIOInput<
             RiscvCoherentDmaFence,
@@ -743,6 +817,7 @@ pub unsafe fn start() -> (
         scheduler_timer,
         rng: rng_driver,
         virtio_ethernet_tap,
+        virtio_ieee802154_radio,
         virtio_gpu_screen,
         virtio_input_keyboard,
         ipc: kernel::ipc::IPC::new(
@@ -767,10 +842,14 @@ pub unsafe fn start() -> (
     } else {
         debug!("- VirtIO EntropySource device not found, disabling RngDriver");
     }
-    if virtio_ethernet_tap.is_some() {
-        debug!("- Found VirtIO NetworkCard device, enabling EthernetTapDriver");
-    } else {
-        debug!("- VirtIO NetworkCard device not found, disabling EthernetTapDriver");
+    match (virtio_net, network_mode) {
+        (Some(_), NetworkMode::EthernetTap) => {
+            debug!("- Found VirtIO NetworkCard device, enabling EthernetTapDriver")
+        }
+        (Some(_), NetworkMode::Ieee802154Medium) => {
+            debug!("- Found VirtIO NetworkCard device, enabling simulated 802.15.4 radio")
+        }
+        (None, _) => debug!("- VirtIO NetworkCard device not found, disabling networking"),
     }
     if virtio_input_keyboard.is_some() {
         debug!("- Found VirtIO Input device, enabling Input");
@@ -82,6 +82,7 @@ type DynamicBinaryStorage<'a> = kernel::dynamic_binary_storage::SequentialDynami
 type AppLoaderDriver = capsules_extra::app_loader::AppLoader<
     DynamicBinaryStorage<'static>,
     DynamicBinaryStorage<'static>,
+    DynamicBinaryStorage<'static>,
 >;
 
 type Verifier = ecdsa_sw::p256_verifier::EcdsaP256SignatureVerifier<'static>;
@@ -515,16 +516,23 @@ pub unsafe fn main() {
             kernel::process::ProcessStandardDebugFull,
         ));
 
+    // The kernel restarts upgraded apps on trial after a fault, and rolls the
+    // upgrade back after three faults.
+    dynamic_binary_storage.set_fault_policy(&FAULT_RESPONSE);
+    loader.set_fault_policy(dynamic_binary_storage);
+
     // Create the dynamic app loader capsule.
     let dynamic_app_loader = components::app_loader::AppLoaderComponent::new(
         board_kernel,
         capsules_extra::app_loader::DRIVER_NUM,
         dynamic_binary_storage,
         dynamic_binary_storage,
+        dynamic_binary_storage,
     )
     .finalize(components::app_loader_component_static!(
         DynamicBinaryStorage<'static>,
         DynamicBinaryStorage<'static>,
+        DynamicBinaryStorage<'static>,
     ));
 
     //--------------------------------------------------------------------------
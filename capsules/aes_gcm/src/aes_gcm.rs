@@ -46,6 +46,7 @@ pub struct Aes128Gcm<'a, A: AES<'a, AES128> + AESCtr + AESCBC + AESECB + AESCCM<
     buf: TakeCell<'static, [u8]>,
 
     pos: Cell<(usize, usize, usize)>,
+    tag_len: Cell<usize>,
     key: Cell<[u8; AES128_KEY_SIZE]>,
     iv: Cell<[u8; AES128_KEY_SIZE]>,
 }
@@ -68,6 +69,7 @@ impl<'a, A: AES<'a, AES128> + AESCtr + AESCBC + AESECB + AESCCM<'a, AES128>> Aes
 
             buf: TakeCell::empty(),
             pos: Cell::new((0, 0, 0)),
+            tag_len: Cell::new(0),
             key: Cell::new(Default::default()),
             iv: Cell::new(Default::default()),
         }
@@ -85,15 +87,11 @@ impl<'a, A: AES<'a, AES128> + AESCtr + AESCBC + AESECB + AESCCM<'a, AES128>> Aes
 
         self.aes.start_message();
         let crypt_buf = self.crypt_buf.take().unwrap();
-        let (_aad_offset, message_offset, message_len) = self.pos.get();
+        let (_aad_offset, _message_offset, message_len) = self.pos.get();
 
-        match AES::crypt(
-            self.aes,
-            None,
-            crypt_buf,
-            message_offset,
-            message_offset + message_len + AES_BLOCK_SIZE,
-        ) {
+        // The first block generates the mask for the tag, followed by the
+        // keystream for the message.
+        match AES::crypt(self.aes, None, crypt_buf, 0, Self::crypt_len(message_len)) {
             None => {
                 self.state.set(GCMState::CtrEncrypt);
                 Ok(())
@@ -105,6 +103,36 @@ impl<'a, A: AES<'a, AES128> + AESCtr + AESCBC + AESECB + AESCCM<'a, AES128>> Aes
         }
     }
 
+    /// The number of bytes of `crypt_buf` used to encrypt or decrypt a
+    /// message of `message_len` bytes.
+    fn crypt_len(message_len: usize) -> usize {
+        AES_BLOCK_SIZE + message_len.next_multiple_of(AES_BLOCK_SIZE)
+    }
+
+    /// Add the lengths block to the GHASH calculation and return the tag.
+    fn finalize_tag(
+        mut mac: GHash,
+        aad_len: usize,
+        message_len: usize,
+        tag_mask: &[u8],
+    ) -> ghash::Block {
+        let associated_data_bits = (aad_len as u64) * 8;
+        let buffer_bits = (message_len as u64) * 8;
+
+        let mut block = ghash::Block::default();
+        block[..8].copy_from_slice(&associated_data_bits.to_be_bytes());
+        block[8..].copy_from_slice(&buffer_bits.to_be_bytes());
+        mac.update(&block);
+
+        let mut tag = mac.finalize().into_bytes();
+
+        for (t, m) in tag.iter_mut().zip(tag_mask.iter()) {
+            *t ^= m;
+        }
+
+        tag
+    }
+
     fn crypt_r(
         &self,
         buf: &'static mut [u8],
@@ -197,10 +225,21 @@ impl<'a, A: AES<'a, AES128> + AESCtr + AESCBC + AESECB + AESCCM<'a, AES128>>
             return Err((ErrorCode::BUSY, buf));
         }
 
-        if aad_offset > message_offset || message_offset + message_len + tag_len > buf.len() {
+        if aad_offset > message_offset
+            || message_offset + message_len + tag_len > buf.len()
+            || tag_len > AES_BLOCK_SIZE
+        {
             return Err((ErrorCode::INVAL, buf));
         }
 
+        if self.crypt_buf.map_or(true, |crypt_buf| {
+            Self::crypt_len(message_len) > crypt_buf.len()
+        }) {
+            return Err((ErrorCode::SIZE, buf));
+        }
+
+        self.tag_len.set(tag_len);
+
         let _ = self
             .crypt_r(buf, aad_offset, message_offset, message_len, encrypting)
             .map_err(|(ecode, _)| {
@@ -331,37 +370,21 @@ impl<'a, A: AES<'a, AES128> + AESCtr + AESCBC + AESECB + AESCCM<'a, AES128>>
                 let mut mac = GHash::new(Key::from_slice(&crypt_buf[0..AES_BLOCK_SIZE]));
                 let buf = self.buf.take().unwrap();
 
-                if self.encrypting.get() {
-                    mac.update_padded(&buf[aad_offset..message_offset]);
-
-                    crypt_buf[AES_BLOCK_SIZE..(AES_BLOCK_SIZE + message_len)]
-                        .copy_from_slice(&buf[message_offset..(message_offset + message_len)]);
-                    for i in 0..AES_BLOCK_SIZE {
-                        crypt_buf[i] = 0;
-                    }
-
-                    self.mac.replace(mac);
-                } else {
-                    let copy_offset = (message_offset / AES_BLOCK_SIZE) * AES_BLOCK_SIZE;
-                    mac.update_padded(&buf[aad_offset..message_offset]);
+                mac.update_padded(&buf[aad_offset..message_offset]);
+                if !self.encrypting.get() {
+                    // When decrypting the tag covers the input ciphertext
                     mac.update_padded(&buf[message_offset..(message_offset + message_len)]);
+                }
+                self.mac.replace(mac);
 
-                    let associated_data_bits = ((message_offset - aad_offset) as u64) * 8;
-                    let buffer_bits = (message_len as u64) * 8;
-
-                    let mut block = ghash::Block::default();
-                    block[..8].copy_from_slice(&associated_data_bits.to_be_bytes());
-                    block[8..].copy_from_slice(&buffer_bits.to_be_bytes());
-                    mac.update(&block);
-
-                    let mut tag = mac.finalize().into_bytes();
+                // The first block is encrypted to generate the tag mask, the
+                // message (padded to a full block) follows it.
+                let crypt_len = Self::crypt_len(message_len);
+                crypt_buf[0..AES_BLOCK_SIZE].fill(0);
+                crypt_buf[AES_BLOCK_SIZE..(AES_BLOCK_SIZE + message_len)]
+                    .copy_from_slice(&buf[message_offset..(message_offset + message_len)]);
+                crypt_buf[(AES_BLOCK_SIZE + message_len)..crypt_len].fill(0);
 
-                    for i in 0..AES_BLOCK_SIZE {
-                        tag[i] ^= crypt_buf[copy_offset + i];
-                    }
-
-                    buf[0..AES_BLOCK_SIZE].copy_from_slice(&tag);
-                }
                 self.crypt_buf.replace(crypt_buf);
                 self.buf.replace(buf);
 
@@ -370,47 +393,47 @@ impl<'a, A: AES<'a, AES128> + AESCtr + AESCBC + AESECB + AESCCM<'a, AES128>>
             GCMState::CtrEncrypt => {
                 let buf = self.buf.take().unwrap();
                 let (aad_offset, message_offset, message_len) = self.pos.get();
-                let tag_offset = (message_offset / AES_BLOCK_SIZE) * AES_BLOCK_SIZE;
-                let copy_offset = (message_offset / AES_BLOCK_SIZE).max(1) * AES_BLOCK_SIZE;
+                let tag_len = self.tag_len.get();
+                let output = &crypt_buf[AES_BLOCK_SIZE..(AES_BLOCK_SIZE + message_len)];
+                let tag_range =
+                    (message_offset + message_len)..(message_offset + message_len + tag_len);
 
+                let mut mac = self.mac.take().unwrap();
                 if self.encrypting.get() {
-                    // Check the mac
-                    let mut mac = self.mac.take().unwrap();
-                    mac.update_padded(
-                        &crypt_buf[(message_offset + AES_BLOCK_SIZE)
-                            ..(message_offset + message_len + AES_BLOCK_SIZE)],
-                    );
-
-                    buf[0..message_len]
-                        .copy_from_slice(&crypt_buf[copy_offset..(copy_offset + message_len)]);
-
-                    let associated_data_bits = ((message_offset - aad_offset) as u64) * 8;
-                    let buffer_bits = (message_len as u64) * 8;
-
-                    let mut block = ghash::Block::default();
-                    block[..8].copy_from_slice(&associated_data_bits.to_be_bytes());
-                    block[8..].copy_from_slice(&buffer_bits.to_be_bytes());
-                    mac.update(&block);
-
-                    let mut tag = mac.finalize().into_bytes();
-
-                    for i in 0..AES_BLOCK_SIZE {
-                        tag[i] ^= crypt_buf[tag_offset + i];
-                    }
-
-                    buf[(message_offset + message_len)
-                        ..(message_offset + message_len + AES_BLOCK_SIZE)]
-                        .copy_from_slice(&tag);
-                } else {
-                    buf[0..message_len]
-                        .copy_from_slice(&crypt_buf[copy_offset..(copy_offset + message_len)]);
+                    // When encrypting the tag covers the output ciphertext
+                    mac.update_padded(output);
                 }
+                let tag = Self::finalize_tag(
+                    mac,
+                    message_offset - aad_offset,
+                    message_len,
+                    &crypt_buf[0..AES_BLOCK_SIZE],
+                );
+
+                buf[message_offset..(message_offset + message_len)].copy_from_slice(output);
+
+                let tag_is_valid = if self.encrypting.get() {
+                    buf[tag_range].copy_from_slice(&tag[..tag_len]);
+                    true
+                } else {
+                    // Compare the whole tag to avoid leaking where it differs
+                    let difference = buf[tag_range]
+                        .iter()
+                        .zip(tag.iter())
+                        .fold(0, |acc, (a, b)| acc | (a ^ b));
+
+                    if difference != 0 {
+                        // Don't release unauthenticated plaintext
+                        buf[message_offset..(message_offset + message_len)].fill(0);
+                    }
+                    difference == 0
+                };
 
                 self.aes.disable();
                 self.crypt_buf.replace(crypt_buf);
                 self.state.set(GCMState::Idle);
                 self.gcm_client.map(move |client| {
-                    client.crypt_done(buf, Ok(()), true);
+                    client.crypt_done(buf, Ok(()), tag_is_valid);
                 });
             }
         }
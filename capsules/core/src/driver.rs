@@ -28,6 +28,10 @@ pub enum NUM {
     Ipc                   = 0x10000,
     AppLoader             = 0x10001,
     ProcessInfo           = 0x10002,
+    ProcessWatchdog       = 0x10003,
+    ProcessFaultDump      = 0x10004,
+    PanicRecord           = 0x10005,
+    ProcessCheckpoint     = 0x10006,
 
     // HW Buses
     Spi                   = 0x20001,
@@ -36,6 +40,8 @@ pub enum NUM {
     UsbUser               = 0x20005,
     I2cMasterSlave        = 0x20006,
     Can                   = 0x20007,
+    CanIsoTp              = 0x20008,
+    CanShared             = 0x20009,
 
     // Networking
     BleAdvertising        = 0x30000,
@@ -47,6 +53,7 @@ pub enum NUM {
     Eui64                 = 0x30006,
     EthernetTap           = 0x30007,
     Wifi                  = 0x30008,
+    BleGatt               = 0x30009,
 
     // Cryptography
     Rng                   = 0x40001,
@@ -62,6 +69,7 @@ pub enum NUM {
     SdCard                = 0x50002,
     Kv                    = 0x50003,
     IsolatedNvmStorage    = 0x50004,
+    FileSystem            = 0x50005,
 
     // Sensors
     Temperature           = 0x60000,
@@ -16,11 +16,13 @@ use kernel::capabilities::ProcessManagementCapability;
 use kernel::capabilities::ProcessStartCapability;
 use kernel::hil::time::ConvertTicks;
 use kernel::utilities::cells::MapCell;
+use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::cells::TakeCell;
 
 use kernel::ErrorCode;
 use kernel::Kernel;
 use kernel::debug;
+use kernel::hil::kv;
 use kernel::hil::time::{Alarm, AlarmClient};
 use kernel::hil::uart;
 use kernel::introspection::KernelInfo;
@@ -44,7 +46,7 @@ pub const DEFAULT_COMMAND_HISTORY_LEN: usize = 10;
 /// List of valid commands for printing help. Consolidated as these are
 /// displayed in a few different cases.
 const VALID_COMMANDS_STR: &[u8] =
-    b"help status list stop start fault boot terminate process kernel reset panic console-start console-stop\r\n";
+    b"help status list stop start fault boot terminate process kernel kvstats reset panic console-start console-stop\r\n";
 
 /// Escape character for ANSI escape sequences.
 const ESC: u8 = b'\x1B';
@@ -271,6 +273,13 @@ pub struct ProcessConsole<
     /// Function used to reset the device in bootloader mode
     reset_function: Option<fn() -> !>,
 
+    /// Optional KV store to print usage and wear statistics for.
+    kv_statistics: OptionalCell<&'a dyn kv::KVStatistics<'a>>,
+
+    /// Internal flag that the KV statistics are being collected, the prompt
+    /// is displayed once they have been printed.
+    kv_statistics_pending: Cell<bool>,
+
     /// This capsule needs to use potentially dangerous APIs related to
     /// processes, and requires a capability to access those APIs.
     capability: C,
@@ -477,10 +486,17 @@ impl<
             kernel,
             kernel_addresses,
             reset_function,
+            kv_statistics: OptionalCell::empty(),
+            kv_statistics_pending: Cell::new(false),
             capability,
         }
     }
 
+    /// Set the KV store used by the `kvstats` command.
+    pub fn set_kv_statistics(&self, kv_statistics: &'a dyn kv::KVStatistics<'a>) {
+        self.kv_statistics.set(kv_statistics);
+    }
+
     /// Start the process console listening for user commands.
     pub fn start(&self) -> Result<(), ErrorCode> {
         if self.mode.get() == ProcessConsoleState::Off {
@@ -994,6 +1010,25 @@ impl<
                             // Prints kernel memory by moving the writer to the
                             // start state.
                             self.writer_state.replace(WriterState::KernelStart);
+                        } else if clean_str.starts_with("kvstats") {
+                            self.kv_statistics.map_or_else(
+                                || {
+                                    let _ = self.write_bytes(b"No KV store available\r\n");
+                                },
+                                |kv_statistics| match kv_statistics.get_statistics() {
+                                    Ok(()) => self.kv_statistics_pending.set(true),
+                                    Err(e) => {
+                                        let mut console_writer = ConsoleWriter::new();
+                                        let _ = write(
+                                            &mut console_writer,
+                                            format_args!("KV statistics failed: {:?}\r\n", e),
+                                        );
+                                        let _ = self.write_bytes(
+                                            &(console_writer.buf)[..console_writer.size],
+                                        );
+                                    }
+                                },
+                            );
                         } else if clean_str.starts_with("reset") {
                             self.reset_function.map_or_else(
                                 || {
@@ -1025,7 +1060,7 @@ impl<
             command[0] = 0;
         });
         self.command_index.set(0);
-        if self.writer_state.get() == WriterState::Empty {
+        if self.writer_state.get() == WriterState::Empty && !self.kv_statistics_pending.get() {
             self.prompt();
         }
     }
@@ -1128,6 +1163,42 @@ impl<
     }
 }
 
+impl<
+    'a,
+    const COMMAND_HISTORY_LEN: usize,
+    A: Alarm<'a>,
+    C: ProcessManagementCapability + ProcessStartCapability,
+> kv::StatisticsClient for ProcessConsole<'a, COMMAND_HISTORY_LEN, A, C>
+{
+    fn statistics_complete(&self, result: Result<kv::Statistics, ErrorCode>) {
+        self.kv_statistics_pending.set(false);
+
+        let mut console_writer = ConsoleWriter::new();
+        match result {
+            Ok(stats) => {
+                let _ = write(
+                    &mut console_writer,
+                    format_args!(
+                        "KV free: {} bytes, invalidated: {} bytes, erase count min: {} max: {}\r\n",
+                        stats.free_bytes,
+                        stats.invalidated_bytes,
+                        stats.min_erase_count,
+                        stats.max_erase_count,
+                    ),
+                );
+            }
+            Err(e) => {
+                let _ = write(
+                    &mut console_writer,
+                    format_args!("KV statistics failed: {:?}\r\n", e),
+                );
+            }
+        }
+        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+        self.prompt();
+    }
+}
+
 impl<
     'a,
     const COMMAND_HISTORY_LEN: usize,
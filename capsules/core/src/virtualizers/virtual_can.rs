@@ -0,0 +1,541 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Virtualize a CAN controller.
+//!
+//! `MuxCan` provides shared access to a single CAN controller for multiple
+//! users. `VirtualCanDevice` gives each user its own transmit slot, receive
+//! buffer and acceptance filters.
+//!
+//! Transmissions are queued and sent one at a time, taking turns between
+//! devices. Every received message is offered to each receiving device and
+//! copied into that device's buffer if it passes one of the device's filters.
+//! A device with no filters enabled receives every message.
+//!
+//! Received messages are not queued. The receive buffer of a device holds a
+//! single message, which is passed to its client from the controller's
+//! `message_received` callback, so a client that needs to keep messages must
+//! copy them out before returning.
+//!
+//! If the controller provides hardware filters, the mux programs the filters
+//! of all receiving devices into the controller's filter banks so that
+//! unwanted messages are dropped by the hardware. When a receiving device has
+//! no filters, or the devices have more filters than the controller has
+//! banks, the mux enables a single bank that accepts every message and only
+//! filters in software.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! # use kernel::static_init;
+//!
+//! let mux_can = static_init!(
+//!     MuxCan<'static, stm32f429zi::can::Can<'static>>,
+//!     MuxCan::new(&peripherals.can1, Some(&peripherals.can1), rx_buffer)
+//! );
+//! kernel::deferred_call::DeferredCallClient::register(mux_can);
+//! can::Transmit::set_client(&peripherals.can1, Some(mux_can));
+//! can::Receive::set_client(&peripherals.can1, Some(mux_can));
+//!
+//! let device = static_init!(
+//!     VirtualCanDevice<'static, stm32f429zi::can::Can<'static>>,
+//!     VirtualCanDevice::new(mux_can)
+//! );
+//! device.setup();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use core::ptr;
+
+use kernel::ErrorCode;
+use kernel::collections::list::{List, ListLink, ListNode};
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::can::{self, STANDARD_CAN_PACKET_SIZE};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+/// Number of acceptance filters each device can enable.
+pub const DEVICE_FILTER_COUNT: usize = 4;
+
+/// Hardware filter that accepts every message.
+const ACCEPT_ALL: can::FilterParameters = can::FilterParameters {
+    number: 0,
+    scale_bits: can::ScaleBits::Bits32,
+    identifier_mode: can::IdentifierMode::Mask,
+    fifo_number: 0,
+    id: can::Id::Standard(0),
+    mask: 0,
+};
+
+#[derive(Copy, Clone, PartialEq)]
+enum RxState {
+    Idle,
+    Receiving,
+    /// Waiting for the controller to return the receive buffer.
+    Stopping,
+}
+
+pub struct MuxCan<
+    'a,
+    C: can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>,
+> {
+    can: &'a C,
+    filter: Option<&'a dyn can::Filter>,
+    devices: List<'a, VirtualCanDevice<'a, C>>,
+    inflight: OptionalCell<&'a VirtualCanDevice<'a, C>>,
+    last_sent: OptionalCell<&'a VirtualCanDevice<'a, C>>,
+    rx_buffer: TakeCell<'static, [u8; STANDARD_CAN_PACKET_SIZE]>,
+    rx_state: Cell<RxState>,
+    // Filter banks that may be enabled, starting from bank 0
+    hardware_filters: Cell<usize>,
+    deferred_call: DeferredCall,
+}
+
+impl<'a, C: can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>>
+    MuxCan<'a, C>
+{
+    /// `filter` is the controller's filter interface, if it has one.
+    /// `rx_buffer` is used to receive messages from the controller.
+    pub fn new(
+        can: &'a C,
+        filter: Option<&'a dyn can::Filter>,
+        rx_buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE],
+    ) -> Self {
+        Self {
+            can,
+            filter,
+            devices: List::new(),
+            inflight: OptionalCell::empty(),
+            last_sent: OptionalCell::empty(),
+            rx_buffer: TakeCell::new(rx_buffer),
+            rx_state: Cell::new(RxState::Idle),
+            hardware_filters: Cell::new(0),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    /// Sends the next queued message, starting with the device after the one
+    /// that sent last so that a busy device cannot starve the others.
+    fn do_next_send(&self) {
+        if self.inflight.is_some() {
+            return;
+        }
+
+        let last_sent = self.last_sent.get();
+        let next = self
+            .devices
+            .iter()
+            .skip_while(|device| last_sent.is_none_or(|last| !ptr::eq(*device, last)))
+            .skip(1)
+            .chain(self.devices.iter())
+            .find(|device| matches!(device.tx_state.get(), TxState::Queued(..)));
+
+        if let Some(device) = next {
+            if let TxState::Queued(id, len) = device.tx_state.get() {
+                device
+                    .tx_buffer
+                    .take()
+                    .map(|buffer| match self.can.send(id, buffer, len) {
+                        Ok(()) => {
+                            device.tx_state.set(TxState::Sending);
+                            self.inflight.set(device);
+                        }
+                        Err((_, buffer)) => {
+                            device.tx_buffer.replace(buffer);
+                            device.tx_state.set(TxState::Failed);
+                            self.deferred_call.set();
+                        }
+                    });
+            }
+        }
+    }
+
+    fn start_receive(&self) -> Result<(), ErrorCode> {
+        match self.rx_state.get() {
+            RxState::Receiving => self.configure_filters(),
+            // Receiving restarts once the controller has stopped
+            RxState::Stopping => Ok(()),
+            RxState::Idle => {
+                let buffer = self.rx_buffer.take().ok_or(ErrorCode::FAIL)?;
+                match self.can.start_receive_process(buffer) {
+                    Ok(()) => {
+                        self.rx_state.set(RxState::Receiving);
+                        // The controller may enable filters of its own when
+                        // receiving starts, so clear every bank once.
+                        self.hardware_filters
+                            .set(self.filter.map_or(0, |filter| filter.filter_count()));
+                        self.configure_filters()
+                    }
+                    Err((error, buffer)) => {
+                        self.rx_buffer.replace(buffer);
+                        Err(error)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Stops the controller once no device is receiving anymore.
+    fn stop_receive(&self) {
+        if self.rx_state.get() != RxState::Receiving {
+            return;
+        }
+        if self.devices.iter().any(|device| device.receiving.get()) {
+            let _ = self.configure_filters();
+            return;
+        }
+
+        self.disable_hardware_filters(0);
+        if self.can.stop_receive().is_ok() {
+            self.rx_state.set(RxState::Stopping);
+        }
+    }
+
+    /// Programs the hardware filters for the devices that are receiving.
+    ///
+    /// If the controller rejects one of the filters, the mux accepts every
+    /// message instead. If it rejects that as well, no bank is left enabled
+    /// and the error is returned.
+    fn configure_filters(&self) -> Result<(), ErrorCode> {
+        let Some(filter) = self.filter else {
+            return Ok(());
+        };
+        if self.rx_state.get() != RxState::Receiving {
+            return Ok(());
+        }
+
+        let receiving = || self.devices.iter().filter(|device| device.receiving.get());
+        let count: usize = receiving()
+            .map(|device| device.enabled_filters().count())
+            .sum();
+        let accept_all = count == 0
+            || count > filter.filter_count()
+            || receiving().any(|device| device.enabled_filters().next().is_none());
+
+        let programmed = if accept_all {
+            None
+        } else {
+            receiving()
+                .flat_map(|device| device.enabled_filters())
+                .enumerate()
+                .try_fold(0, |_, (number, device_filter)| {
+                    self.enable_hardware_filter(
+                        filter,
+                        can::FilterParameters {
+                            number: number as u32,
+                            fifo_number: 0,
+                            ..device_filter
+                        },
+                    )
+                    .ok()
+                    .map(|()| number + 1)
+                })
+        };
+        let used = match programmed {
+            Some(used) => used,
+            None => {
+                if let Err(error) = self.enable_hardware_filter(filter, ACCEPT_ALL) {
+                    self.disable_hardware_filters(0);
+                    return Err(error);
+                }
+                1
+            }
+        };
+        // This also disables the banks of filters enabled before one failed.
+        self.disable_hardware_filters(used);
+        Ok(())
+    }
+
+    /// Enables a filter bank, and notes that it may be enabled so that it is
+    /// disabled again when it is not needed, even if enabling it failed.
+    fn enable_hardware_filter(
+        &self,
+        filter: &dyn can::Filter,
+        parameters: can::FilterParameters,
+    ) -> Result<(), ErrorCode> {
+        self.hardware_filters.set(cmp::max(
+            self.hardware_filters.get(),
+            parameters.number as usize + 1,
+        ));
+        filter.enable_filter(parameters)
+    }
+
+    /// Disables the filter banks from `first` on that may be enabled.
+    fn disable_hardware_filters(&self, first: usize) {
+        if let Some(filter) = self.filter {
+            for number in first..self.hardware_filters.get() {
+                let _ = filter.disable_filter(number as u32);
+            }
+            self.hardware_filters.set(first);
+        }
+    }
+}
+
+impl<C: can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>>
+    can::TransmitClient<STANDARD_CAN_PACKET_SIZE> for MuxCan<'_, C>
+{
+    fn transmit_complete(
+        &self,
+        status: Result<(), can::Error>,
+        buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE],
+    ) {
+        if let Some(device) = self.inflight.take() {
+            self.last_sent.set(device);
+            device.tx_state.set(TxState::Idle);
+            device
+                .tx_client
+                .map(move |client| client.transmit_complete(status, buffer));
+        }
+        self.do_next_send();
+    }
+}
+
+impl<C: can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>>
+    can::ReceiveClient<STANDARD_CAN_PACKET_SIZE> for MuxCan<'_, C>
+{
+    fn message_received(
+        &self,
+        id: can::Id,
+        buffer: &mut [u8; STANDARD_CAN_PACKET_SIZE],
+        len: usize,
+        status: Result<(), can::Error>,
+    ) {
+        let len = cmp::min(len, STANDARD_CAN_PACKET_SIZE);
+        // Errors are reported to every receiving device
+        for device in self
+            .devices
+            .iter()
+            .filter(|device| device.receiving.get() && (status.is_err() || device.accepts(id)))
+        {
+            device.rx_buffer.map(|rx_buffer| {
+                rx_buffer[..len].copy_from_slice(&buffer[..len]);
+                device
+                    .rx_client
+                    .map(|client| client.message_received(id, rx_buffer, len, status));
+            });
+        }
+    }
+
+    fn stopped(&self, buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE]) {
+        self.rx_buffer.replace(buffer);
+        self.rx_state.set(RxState::Idle);
+        // A device may have started receiving while the controller stopped
+        if self.devices.iter().any(|device| device.receiving.get()) {
+            let _ = self.start_receive();
+        }
+    }
+}
+
+impl<C: can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>>
+    DeferredCallClient for MuxCan<'_, C>
+{
+    fn handle_deferred_call(&self) {
+        for device in self.devices.iter() {
+            if device.tx_state.get() == TxState::Failed {
+                device.tx_state.set(TxState::Idle);
+                device.tx_buffer.take().map(|buffer| {
+                    device.tx_client.map(move |client| {
+                        client.transmit_complete(Err(can::Error::Transmission), buffer)
+                    })
+                });
+            }
+            if device.stopping.take() {
+                device
+                    .rx_buffer
+                    .take()
+                    .map(|buffer| device.rx_client.map(move |client| client.stopped(buffer)));
+            }
+        }
+        self.do_next_send();
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum TxState {
+    Idle,
+    Queued(can::Id, usize),
+    Sending,
+    /// The controller refused the message; completes from a deferred call.
+    Failed,
+}
+
+pub struct VirtualCanDevice<
+    'a,
+    C: can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>,
+> {
+    mux: &'a MuxCan<'a, C>,
+    tx_buffer: TakeCell<'static, [u8; STANDARD_CAN_PACKET_SIZE]>,
+    tx_state: Cell<TxState>,
+    rx_buffer: TakeCell<'static, [u8; STANDARD_CAN_PACKET_SIZE]>,
+    receiving: Cell<bool>,
+    stopping: Cell<bool>,
+    filters: [OptionalCell<can::FilterParameters>; DEVICE_FILTER_COUNT],
+    next: ListLink<'a, VirtualCanDevice<'a, C>>,
+    tx_client: OptionalCell<&'static dyn can::TransmitClient<STANDARD_CAN_PACKET_SIZE>>,
+    rx_client: OptionalCell<&'static dyn can::ReceiveClient<STANDARD_CAN_PACKET_SIZE>>,
+}
+
+impl<'a, C: can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>>
+    VirtualCanDevice<'a, C>
+{
+    pub fn new(mux: &'a MuxCan<'a, C>) -> Self {
+        Self {
+            mux,
+            tx_buffer: TakeCell::empty(),
+            tx_state: Cell::new(TxState::Idle),
+            rx_buffer: TakeCell::empty(),
+            receiving: Cell::new(false),
+            stopping: Cell::new(false),
+            filters: [const { OptionalCell::empty() }; DEVICE_FILTER_COUNT],
+            next: ListLink::empty(),
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+        }
+    }
+
+    /// Must be called right after `static_init!()`.
+    pub fn setup(&'a self) {
+        self.mux.devices.push_head(self);
+    }
+
+    fn enabled_filters(&self) -> impl Iterator<Item = can::FilterParameters> + '_ {
+        self.filters.iter().filter_map(|filter| filter.get())
+    }
+
+    /// Whether a message with the identifier `id` passes the device's
+    /// filters.
+    fn accepts(&self, id: can::Id) -> bool {
+        let mut filters = self.enabled_filters().peekable();
+        filters.peek().is_none() || filters.any(|filter| filter.matches(id))
+    }
+}
+
+impl<'a, C: can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>>
+    ListNode<'a, VirtualCanDevice<'a, C>> for VirtualCanDevice<'a, C>
+{
+    fn next(&'a self) -> &'a ListLink<'a, VirtualCanDevice<'a, C>> {
+        &self.next
+    }
+}
+
+impl<C: can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>>
+    can::Transmit<STANDARD_CAN_PACKET_SIZE> for VirtualCanDevice<'_, C>
+{
+    fn set_client(
+        &self,
+        client: Option<&'static dyn can::TransmitClient<STANDARD_CAN_PACKET_SIZE>>,
+    ) {
+        self.tx_client.insert(client);
+    }
+
+    fn send(
+        &self,
+        id: can::Id,
+        buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8; STANDARD_CAN_PACKET_SIZE])> {
+        if self.tx_state.get() != TxState::Idle {
+            return Err((ErrorCode::BUSY, buffer));
+        }
+        if len > STANDARD_CAN_PACKET_SIZE {
+            return Err((ErrorCode::SIZE, buffer));
+        }
+        self.tx_buffer.replace(buffer);
+        self.tx_state.set(TxState::Queued(id, len));
+        self.mux.do_next_send();
+        Ok(())
+    }
+}
+
+impl<C: can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>>
+    can::Receive<STANDARD_CAN_PACKET_SIZE> for VirtualCanDevice<'_, C>
+{
+    fn set_client(
+        &self,
+        client: Option<&'static dyn can::ReceiveClient<STANDARD_CAN_PACKET_SIZE>>,
+    ) {
+        self.rx_client.insert(client);
+    }
+
+    /// Unlike on a controller, filters may be enabled before receiving
+    /// starts.
+    fn start_receive_process(
+        &self,
+        buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; STANDARD_CAN_PACKET_SIZE])> {
+        if self.receiving.get() {
+            return Err((ErrorCode::ALREADY, buffer));
+        }
+        if self.stopping.get() {
+            return Err((ErrorCode::BUSY, buffer));
+        }
+        self.receiving.set(true);
+        if let Err(error) = self.mux.start_receive() {
+            self.receiving.set(false);
+            // Stops the controller if it started for this device.
+            self.mux.stop_receive();
+            return Err((error, buffer));
+        }
+        self.rx_buffer.replace(buffer);
+        Ok(())
+    }
+
+    fn stop_receive(&self) -> Result<(), ErrorCode> {
+        if !self.receiving.get() {
+            return Err(ErrorCode::OFF);
+        }
+        self.receiving.set(false);
+        self.stopping.set(true);
+        self.mux.stop_receive();
+        self.mux.deferred_call.set();
+        Ok(())
+    }
+}
+
+/// Filters are matched in software against the identifier and mask, and are
+/// combined into the controller's filter banks by the mux. The bank width and
+/// FIFO are chosen by the mux; `number` selects one of the device's
+/// [`DEVICE_FILTER_COUNT`] filters.
+impl<C: can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>>
+    can::Filter for VirtualCanDevice<'_, C>
+{
+    fn enable_filter(&self, filter: can::FilterParameters) -> Result<(), ErrorCode> {
+        let slot = self
+            .filters
+            .get(filter.number as usize)
+            .ok_or(ErrorCode::INVAL)?;
+        let previous = slot.get();
+        slot.set(can::FilterParameters {
+            scale_bits: can::ScaleBits::Bits32,
+            ..filter
+        });
+        if self.receiving.get()
+            && let Err(error) = self.mux.configure_filters()
+        {
+            // Go back to the filters the controller accepted.
+            slot.insert(previous);
+            let _ = self.mux.configure_filters();
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    fn disable_filter(&self, number: u32) -> Result<(), ErrorCode> {
+        let slot = self.filters.get(number as usize).ok_or(ErrorCode::INVAL)?;
+        slot.clear();
+        if self.receiving.get() {
+            self.mux.configure_filters()?;
+        }
+        Ok(())
+    }
+
+    fn filter_count(&self) -> usize {
+        DEVICE_FILTER_COUNT
+    }
+}
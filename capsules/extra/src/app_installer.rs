@@ -0,0 +1,340 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Storing and loading an app image received from a host.
+//!
+//! Capsules that install apps sent by a host, such as `udp_app_loader`,
+//! `usb::msc_app_drop` and `usb::dfu`, all go through the same steps: set up
+//! the storage for an image of a known length, write the image in order,
+//! finalize it, and load it. [`AppInstaller`] tracks these steps for them.
+//!
+//! The capsule stays the client of the storage and load drivers, and passes
+//! their callbacks on to the `AppInstaller`. The `AppInstaller` returns
+//! `None` for callbacks which are not for the current step, and otherwise
+//! what the capsule has to report to its host.
+
+use core::cell::Cell;
+
+use kernel::ErrorCode;
+use kernel::dynamic_binary_storage::{DynamicBinaryStore, DynamicProcessLoad};
+use kernel::process::ProcessLoadError;
+use kernel::utilities::leasable_buffer::SubSliceMut;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum State {
+    /// No image is being stored.
+    Idle,
+    /// Waiting for the storage to be set up for the image.
+    Setup,
+    /// Waiting for the next chunk of the image.
+    Receiving,
+    /// Writing a chunk.
+    Writing,
+    Finalizing,
+    Loading,
+    Aborting,
+}
+
+pub struct AppInstaller<'a, S: DynamicBinaryStore, L: DynamicProcessLoad> {
+    storage_driver: &'a S,
+    load_driver: &'a L,
+    state: Cell<State>,
+    /// Length of the image.
+    length: Cell<usize>,
+    /// Number of bytes stored.
+    offset: Cell<usize>,
+}
+
+impl<'a, S: DynamicBinaryStore, L: DynamicProcessLoad> AppInstaller<'a, S, L> {
+    pub fn new(storage_driver: &'a S, load_driver: &'a L) -> Self {
+        Self {
+            storage_driver,
+            load_driver,
+            state: Cell::new(State::Idle),
+            length: Cell::new(0),
+            offset: Cell::new(0),
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state.get()
+    }
+
+    /// Length of the image being stored.
+    pub fn length(&self) -> usize {
+        self.length.get()
+    }
+
+    /// Number of bytes of the image stored.
+    pub fn offset(&self) -> usize {
+        self.offset.get()
+    }
+
+    /// Set up the storage for an image of `length` bytes.
+    pub fn setup(&self, length: usize) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.storage_driver.setup(length)?;
+        self.length.set(length);
+        self.offset.set(0);
+        self.state.set(State::Setup);
+        Ok(())
+    }
+
+    /// Write the chunk in `buffer` at `offset`, which must be the number of
+    /// bytes stored so far.
+    pub fn write(
+        &self,
+        buffer: SubSliceMut<'static, u8>,
+        offset: usize,
+    ) -> Result<(), (ErrorCode, Option<&'static mut [u8]>)> {
+        if self.state.get() != State::Receiving {
+            return Err((ErrorCode::BUSY, Some(buffer.take())));
+        }
+        let end = offset.checked_add(buffer.len());
+        if offset != self.offset.get()
+            || buffer.len() == 0
+            || end.is_none_or(|end| end > self.length.get())
+        {
+            return Err((ErrorCode::INVAL, Some(buffer.take())));
+        }
+        self.storage_driver.write(buffer, offset)?;
+        self.state.set(State::Writing);
+        Ok(())
+    }
+
+    /// Finalize the image once all of it is stored, and load it.
+    pub fn finish(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Receiving || self.offset.get() != self.length.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        match self.storage_driver.finalize() {
+            Ok(()) => {
+                self.state.set(State::Finalizing);
+                Ok(())
+            }
+            Err(e) => {
+                self.state.set(State::Idle);
+                Err(e)
+            }
+        }
+    }
+
+    /// Remove the image being stored.
+    ///
+    /// Fails with `BUSY` while an operation of the storage is in progress,
+    /// and with `ALREADY` if there is no image.
+    pub fn abort(&self) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Setup | State::Receiving => match self.storage_driver.abort() {
+                Ok(()) => {
+                    self.state.set(State::Aborting);
+                    Ok(())
+                }
+                // There is no way to release the storage, so drop the image.
+                Err(e) => {
+                    self.state.set(State::Idle);
+                    Err(e)
+                }
+            },
+            State::Idle => Err(ErrorCode::ALREADY),
+            _ => Err(ErrorCode::BUSY),
+        }
+    }
+
+    /// Handle `DynamicBinaryStoreClient::setup_done`.
+    pub fn setup_done(&self, result: Result<(), ErrorCode>) -> Option<Result<(), ErrorCode>> {
+        if self.state.get() != State::Setup {
+            return None;
+        }
+        self.state.set(match result {
+            Ok(()) => State::Receiving,
+            Err(_) => State::Idle,
+        });
+        Some(result)
+    }
+
+    /// Handle `DynamicBinaryStoreClient::write_done`.
+    pub fn write_done(
+        &self,
+        result: Result<(), ErrorCode>,
+        length: usize,
+    ) -> Option<Result<(), ErrorCode>> {
+        if self.state.get() != State::Writing {
+            return None;
+        }
+        self.state.set(State::Receiving);
+        if result.is_ok() {
+            self.offset.set(self.offset.get() + length);
+        }
+        Some(result)
+    }
+
+    /// Handle `DynamicBinaryStoreClient::finalize_done`.
+    ///
+    /// Returns the result of the installation if it failed. Otherwise the
+    /// app is being loaded, and the result is returned by `load_done()`.
+    pub fn finalize_done(&self, result: Result<(), ErrorCode>) -> Option<Result<(), ErrorCode>> {
+        if self.state.get() != State::Finalizing {
+            return None;
+        }
+        match result.and_then(|()| self.load_driver.load()) {
+            Ok(()) => {
+                self.state.set(State::Loading);
+                None
+            }
+            Err(e) => {
+                self.state.set(State::Idle);
+                Some(Err(e))
+            }
+        }
+    }
+
+    /// Handle `DynamicBinaryStoreClient::abort_done`.
+    pub fn abort_done(&self, result: Result<(), ErrorCode>) -> Option<Result<(), ErrorCode>> {
+        if self.state.get() != State::Aborting {
+            return None;
+        }
+        self.state.set(State::Idle);
+        self.offset.set(0);
+        Some(result)
+    }
+
+    /// Handle `DynamicProcessLoadClient::load_done`, and return the result
+    /// of the installation.
+    ///
+    /// Fails with `INVAL` if the credentials of the app were rejected and
+    /// with `NOMEM` if there are no resources to run it.
+    pub fn load_done(&self, result: Result<(), ProcessLoadError>) -> Option<Result<(), ErrorCode>> {
+        // The load driver signals every loaded process and the end of
+        // loading, only the first is for this image.
+        if self.state.get() != State::Loading {
+            return None;
+        }
+        self.state.set(State::Idle);
+        Some(result.map_err(|e| match e {
+            ProcessLoadError::CheckError(_) => ErrorCode::INVAL,
+            ProcessLoadError::NotEnoughMemory | ProcessLoadError::NoProcessSlot => ErrorCode::NOMEM,
+            _ => ErrorCode::FAIL,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use kernel::dynamic_binary_storage::{DynamicBinaryStoreClient, DynamicProcessLoadClient};
+    use std::boxed::Box;
+
+    /// Accepts every operation, which completes when the test calls the
+    /// installer.
+    struct MockStore;
+
+    impl DynamicBinaryStore for MockStore {
+        fn setup(&self, app_length: usize) -> Result<usize, ErrorCode> {
+            Ok(app_length)
+        }
+
+        fn write(
+            &self,
+            _buffer: SubSliceMut<'static, u8>,
+            _offset: usize,
+        ) -> Result<(), (ErrorCode, Option<&'static mut [u8]>)> {
+            Ok(())
+        }
+
+        fn finalize(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn abort(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn set_storage_client(&self, _client: &'static dyn DynamicBinaryStoreClient) {}
+    }
+
+    impl DynamicProcessLoad for MockStore {
+        fn load(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn set_load_client(&self, _client: &'static dyn DynamicProcessLoadClient) {}
+    }
+
+    fn chunk(length: usize) -> SubSliceMut<'static, u8> {
+        let mut chunk = SubSliceMut::new(Box::leak(Box::new([0u8; 16])) as &mut [u8]);
+        chunk.slice(..length);
+        chunk
+    }
+
+    #[test]
+    fn installs_an_image_written_in_order() {
+        let installer = AppInstaller::new(&MockStore, &MockStore);
+        assert_eq!(installer.setup(24), Ok(()));
+        assert_eq!(installer.setup(24), Err(ErrorCode::BUSY));
+        assert_eq!(installer.setup_done(Ok(())), Some(Ok(())));
+
+        assert!(installer.write(chunk(16), 0).is_ok());
+        assert_eq!(installer.write_done(Ok(()), 16), Some(Ok(())));
+        // Chunks at another offset or past the end are rejected.
+        assert!(matches!(
+            installer.write(chunk(8), 0),
+            Err((ErrorCode::INVAL, Some(_)))
+        ));
+        assert!(matches!(
+            installer.write(chunk(16), 16),
+            Err((ErrorCode::INVAL, Some(_)))
+        ));
+        assert_eq!(installer.finish(), Err(ErrorCode::BUSY));
+
+        assert!(installer.write(chunk(8), 16).is_ok());
+        assert_eq!(installer.write_done(Ok(()), 8), Some(Ok(())));
+        assert_eq!(installer.offset(), 24);
+        assert_eq!(installer.finish(), Ok(()));
+        assert_eq!(installer.finalize_done(Ok(())), None);
+        assert_eq!(installer.state(), State::Loading);
+        assert_eq!(installer.load_done(Ok(())), Some(Ok(())));
+        // Later processes of the same load are not for this image.
+        assert_eq!(installer.load_done(Ok(())), None);
+        assert_eq!(installer.state(), State::Idle);
+    }
+
+    #[test]
+    fn reports_why_an_image_was_not_loaded() {
+        let installer = AppInstaller::new(&MockStore, &MockStore);
+        for (error, code) in [
+            (ProcessLoadError::NoProcessSlot, ErrorCode::NOMEM),
+            (ProcessLoadError::InternalError, ErrorCode::FAIL),
+        ] {
+            assert_eq!(installer.setup(8), Ok(()));
+            installer.setup_done(Ok(()));
+            assert!(installer.write(chunk(8), 0).is_ok());
+            installer.write_done(Ok(()), 8);
+            assert_eq!(installer.finish(), Ok(()));
+            installer.finalize_done(Ok(()));
+            assert_eq!(installer.load_done(Err(error)), Some(Err(code)));
+        }
+    }
+
+    #[test]
+    fn aborts_only_between_operations() {
+        let installer = AppInstaller::new(&MockStore, &MockStore);
+        assert_eq!(installer.abort(), Err(ErrorCode::ALREADY));
+        assert_eq!(installer.setup(8), Ok(()));
+        installer.setup_done(Ok(()));
+        assert!(installer.write(chunk(4), 0).is_ok());
+        assert_eq!(installer.abort(), Err(ErrorCode::BUSY));
+        installer.write_done(Ok(()), 4);
+
+        assert_eq!(installer.abort(), Ok(()));
+        assert_eq!(installer.write_done(Ok(()), 4), None);
+        assert_eq!(installer.abort_done(Ok(())), Some(Ok(())));
+        assert_eq!(installer.state(), State::Idle);
+        assert_eq!(installer.offset(), 0);
+    }
+}
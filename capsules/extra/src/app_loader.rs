@@ -10,6 +10,20 @@
 //! written. Then the app is actually written to flash. Finally, the
 //! the userspace app sends a request for the app to be loaded.
 //!
+//! Apps can also be uninstalled, or upgraded: instead of loading the new
+//! binary as a new app, it replaces a running app. The new version then runs
+//! on trial while the old version is kept in flash. The userspace app checks
+//! how often the new version faulted, and either commits the upgrade or rolls
+//! it back. The kernel rolls the upgrade back by itself once the new version
+//! faulted as many times as the rollback threshold of the board. The kernel
+//! keeps the trial and its count of faults across a reset if the board gives
+//! it a trial record (see `kernel::dynamic_binary_storage`).
+//!
+//! The userspace app can also ask the kernel to compact the flash used for
+//! apps, which moves app binaries to merge the free space between them. The
+//! kernel also starts compacting when there is no room for a new app. Apps
+//! whose binary is moved are restarted from the new copy.
+//!
 //!
 //! Here is a diagram of the expected stack with this capsule:
 //! Boxes are components and between the boxes are the traits that are the
@@ -29,6 +43,7 @@
 //! +-----------------------------------------------------------------+
 //!         kernel::dynamic_binary_storage::DynamicBinaryStore
 //!         kernel::dynamic_binary_storage::DynamicProcessLoad
+//!         kernel::dynamic_binary_storage::DynamicProcessUpdate
 //! +-----------------------------------------------------------------+
 //! |                                     |                           |
 //! |  Physical Nonvolatile Storage       |           Kernel          |
@@ -55,14 +70,15 @@
 //!     capsules_extra::app_loader::DRIVER_NUM,
 //!     dynamic_binary_storage,
 //!     dynamic_binary_storage,
+//!     dynamic_binary_storage,
 //!     ).finalize(components::app_loader_component_static!(
 //!     DynamicBinaryStorage<'static>,
 //!     DynamicBinaryStorage<'static>,
+//!     DynamicBinaryStorage<'static>,
 //!     ));
 //!
 //! NOTE:
 //! 1. This capsule is not virtualized, and can only serve one app at a time.
-//! 2. This implementation currently only loads new apps. It does not update apps.
 //! ```
 
 use core::cell::Cell;
@@ -94,8 +110,18 @@ mod upcall {
     pub const LOAD_DONE: usize = 3;
     /// Abort done callback.
     pub const ABORT_DONE: usize = 4;
+    /// Uninstall done callback.
+    pub const UNINSTALL_DONE: usize = 5;
+    /// Upgrade done callback.
+    pub const UPGRADE_DONE: usize = 6;
+    /// Commit done callback.
+    pub const COMMIT_DONE: usize = 7;
+    /// Rollback done callback.
+    pub const ROLLBACK_DONE: usize = 8;
+    /// Compact done callback.
+    pub const COMPACT_DONE: usize = 9;
     /// Number of upcalls.
-    pub const COUNT: u8 = 5;
+    pub const COUNT: u8 = 10;
 }
 
 // Ids for read-only allow buffers
@@ -118,10 +144,12 @@ pub struct App {
 pub struct AppLoader<
     S: dynamic_binary_storage::DynamicBinaryStore + 'static,
     L: dynamic_binary_storage::DynamicProcessLoad + 'static,
+    U: dynamic_binary_storage::DynamicProcessUpdate + 'static,
 > {
     // The underlying driver for the process flashing and loading.
     storage_driver: &'static S,
     load_driver: &'static L,
+    update_driver: &'static U,
     // Per-app state.
     apps: Grant<
         App,
@@ -140,7 +168,8 @@ pub struct AppLoader<
 impl<
     S: dynamic_binary_storage::DynamicBinaryStore + 'static,
     L: dynamic_binary_storage::DynamicProcessLoad + 'static,
-> AppLoader<S, L>
+    U: dynamic_binary_storage::DynamicProcessUpdate + 'static,
+> AppLoader<S, L, U>
 {
     pub fn new(
         grant: Grant<
@@ -151,12 +180,14 @@ impl<
         >,
         storage_driver: &'static S,
         load_driver: &'static L,
+        update_driver: &'static U,
         buffer: &'static mut [u8],
-    ) -> AppLoader<S, L> {
+    ) -> AppLoader<S, L, U> {
         AppLoader {
             apps: grant,
             storage_driver,
             load_driver,
+            update_driver,
             buffer: TakeCell::new(buffer),
             current_process: OptionalCell::empty(),
             new_app_length: Cell::new(0),
@@ -227,7 +258,12 @@ impl<
                         let res = self.storage_driver.write(write_buffer, offset);
                         match res {
                             Ok(()) => Ok(()),
-                            Err(e) => Err(e),
+                            Err((e, buffer)) => {
+                                if let Some(buffer) = buffer {
+                                    self.buffer.replace(buffer);
+                                }
+                                Err(e)
+                            }
                         }
                     })
             })
@@ -238,7 +274,8 @@ impl<
 impl<
     S: dynamic_binary_storage::DynamicBinaryStore + 'static,
     L: dynamic_binary_storage::DynamicProcessLoad + 'static,
-> dynamic_binary_storage::DynamicBinaryStoreClient for AppLoader<S, L>
+    U: dynamic_binary_storage::DynamicProcessUpdate + 'static,
+> dynamic_binary_storage::DynamicBinaryStoreClient for AppLoader<S, L, U>
 {
     /// Let the requesting app know we are done setting up for the new app
     fn setup_done(&self, result: Result<(), ErrorCode>) {
@@ -301,7 +338,8 @@ impl<
 impl<
     S: dynamic_binary_storage::DynamicBinaryStore + 'static,
     L: dynamic_binary_storage::DynamicProcessLoad + 'static,
-> dynamic_binary_storage::DynamicProcessLoadClient for AppLoader<S, L>
+    U: dynamic_binary_storage::DynamicProcessUpdate + 'static,
+> dynamic_binary_storage::DynamicProcessLoadClient for AppLoader<S, L, U>
 {
     /// Let the requesting app know we are done loading the new process
     ///
@@ -344,11 +382,71 @@ impl<
     }
 }
 
+impl<
+    S: dynamic_binary_storage::DynamicBinaryStore + 'static,
+    L: dynamic_binary_storage::DynamicProcessLoad + 'static,
+    U: dynamic_binary_storage::DynamicProcessUpdate + 'static,
+> AppLoader<S, L, U>
+{
+    /// Signal `upcall_num` with `result` to the current process and release
+    /// the driver.
+    fn update_done(&self, upcall_num: usize, result: Result<(), ErrorCode>) {
+        self.current_process.take().map(|processid| {
+            let _ = self.apps.enter(processid, move |app, kernel_data| {
+                app.pending_command = false;
+                let _ = kernel_data.schedule_upcall(upcall_num, (into_statuscode(result), 0, 0));
+            });
+        });
+    }
+}
+
+impl<
+    S: dynamic_binary_storage::DynamicBinaryStore + 'static,
+    L: dynamic_binary_storage::DynamicProcessLoad + 'static,
+    U: dynamic_binary_storage::DynamicProcessUpdate + 'static,
+> dynamic_binary_storage::DynamicProcessUpdateClient for AppLoader<S, L, U>
+{
+    /// Let the requesting app know the app was uninstalled
+    fn uninstall_done(&self, result: Result<(), ErrorCode>) {
+        self.update_done(upcall::UNINSTALL_DONE, result);
+    }
+
+    /// Let the requesting app know the new version is running on trial
+    fn upgrade_done(&self, result: Result<(), ErrorCode>) {
+        self.update_done(upcall::UPGRADE_DONE, result);
+    }
+
+    /// Let the requesting app know the old version was removed
+    fn commit_done(&self, result: Result<(), ErrorCode>) {
+        self.update_done(upcall::COMMIT_DONE, result);
+    }
+
+    /// Let the requesting app know the old version is running again
+    fn rollback_done(&self, result: Result<(), ErrorCode>) {
+        self.update_done(upcall::ROLLBACK_DONE, result);
+    }
+
+    /// Let the requesting app know how many binaries were moved
+    fn compact_done(&self, result: Result<usize, ErrorCode>) {
+        self.current_process.take().map(|processid| {
+            let _ = self.apps.enter(processid, move |app, kernel_data| {
+                app.pending_command = false;
+                let moved = result.unwrap_or(0);
+                let _ = kernel_data.schedule_upcall(
+                    upcall::COMPACT_DONE,
+                    (into_statuscode(result.map(|_| ())), moved, 0),
+                );
+            });
+        });
+    }
+}
+
 /// Provide an interface for userland.
 impl<
     S: dynamic_binary_storage::DynamicBinaryStore + 'static,
     L: dynamic_binary_storage::DynamicProcessLoad + 'static,
-> SyscallDriver for AppLoader<S, L>
+    U: dynamic_binary_storage::DynamicProcessUpdate + 'static,
+> SyscallDriver for AppLoader<S, L, U>
 {
     /// Command interface.
     ///
@@ -366,6 +464,8 @@ impl<
     /// - `0`: Return Ok(()) if this driver is included on the platform.
     /// - `1`: Request kernel to setup for loading app.
     ///  - Returns appsize if the kernel has available space
+    ///  - Returns ErrorCode::BUSY if there is no room for the new app, but
+    ///    the kernel started compacting the flash for apps. Try again later.
     ///  - Returns ErrorCode::FAIL if the kernel is unable to allocate space for
     ///    the new app
     /// - `2`: Request kernel to write app data to the nonvolatile_storage
@@ -387,6 +487,33 @@ impl<
     ///  - Returns ErrorCode::BUSY when the abort fails(due to padding app being
     ///    unable to be written, so try again)
     ///  - Returns ErrorCode::FAIL if the driver is not dedicated to this process
+    /// - `6`: Request kernel to uninstall the app with process identifier
+    ///   `arg1`.
+    ///  - Returns Ok(()) when the app was terminated and its binary is being
+    ///    removed
+    ///  - Returns ErrorCode::INVAL if there is no such app, or it is the
+    ///    calling app
+    /// - `7`: Request kernel to replace the app with process identifier `arg1`
+    ///   with the new app. This is called instead of `4` after `3`.
+    ///  - Returns Ok(()) when the old version was terminated. The upcall
+    ///    reports ErrorCode::CANCEL if the new version could not be loaded
+    ///    and the old version is running again.
+    ///  - Returns ErrorCode::INVAL if there is no such app, or it is the
+    ///    calling app
+    /// - `8`: Check on the upgraded app running on trial.
+    ///  - Returns the number of times it faulted, including before resets
+    ///  - Returns ErrorCode::INVAL if no upgraded app is on trial, for example
+    ///    because the kernel rolled the upgrade back
+    /// - `9`: Commit the upgrade and remove the old version from flash.
+    ///  - Returns ErrorCode::INVAL if no upgraded app is on trial
+    /// - `10`: Roll back the upgrade and run the old version again.
+    ///  - Returns ErrorCode::INVAL if no upgraded app is on trial
+    /// - `11`: Request kernel to compact the flash used for apps.
+    ///  - Returns Ok(()) when compaction started. The upcall reports how
+    ///    many app binaries were moved. If the binary of the calling app is
+    ///    moved, the app is restarted and gets no upcall.
+    ///  - Returns ErrorCode::NOSUPPORT if the board has no compaction journal
+    ///  - Returns ErrorCode::INVAL if another operation is in progress
     ///
     /// The driver returns ErrorCode::INVAL if any operation is called before
     /// the preceding operation was invoked. For example, `write()` cannot be
@@ -510,6 +637,62 @@ impl<
                     }
                 }
             }
+
+            6 | 7 => {
+                // An app cannot replace or remove itself.
+                let res = if arg1 == processid.id() {
+                    Err(ErrorCode::INVAL)
+                } else if command_num == 6 {
+                    self.update_driver.uninstall(arg1)
+                } else {
+                    self.update_driver.upgrade(arg1)
+                };
+                self.new_app_length.set(0);
+                match res {
+                    Ok(()) => CommandReturn::success(),
+                    Err(e) => {
+                        self.current_process.take();
+                        CommandReturn::failure(e)
+                    }
+                }
+            }
+
+            8 => {
+                // Check how the upgraded app is doing.
+                self.current_process.take();
+                match self.update_driver.trial_faults() {
+                    Ok(faults) => CommandReturn::success_u32(faults as u32),
+                    Err(e) => CommandReturn::failure(e),
+                }
+            }
+
+            9 | 10 => {
+                // Finish the upgrade.
+                let res = if command_num == 9 {
+                    self.update_driver.commit()
+                } else {
+                    self.update_driver.rollback()
+                };
+                match res {
+                    Ok(()) => CommandReturn::success(),
+                    Err(e) => {
+                        self.current_process.take();
+                        CommandReturn::failure(e)
+                    }
+                }
+            }
+
+            11 => {
+                // Request kernel to compact the flash used for apps.
+                match self.update_driver.compact() {
+                    Ok(()) => CommandReturn::success(),
+                    Err(e) => {
+                        self.current_process.take();
+                        CommandReturn::failure(e)
+                    }
+                }
+            }
+
             // Unsupported command numbers.
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
@@ -0,0 +1,823 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Attribute protocol (ATT) server and the attribute database of the GATT
+//! server.
+//!
+//! The database holds the services and characteristics registered by the
+//! kernel and by processes, laid out as GATT attributes (Bluetooth Core
+//! Specification Vol. 3, Part G, section 3): a service declaration, followed
+//! by a declaration, a value and, for characteristics supporting
+//! notifications, a client characteristic configuration descriptor for each
+//! of its characteristics. Attribute handles are assigned in registration
+//! order, starting at 1.
+//!
+//! Characteristic values are stored in the kernel, in a pool of memory
+//! provided by the board, so that the server answers reads without waiting
+//! for the owning process.
+//!
+//! The server uses the default ATT_MTU of 23 bytes, so each ATT PDU fits in a
+//! single link layer packet.
+
+use core::cell::Cell;
+
+use kernel::ErrorCode;
+use kernel::ProcessId;
+use kernel::utilities::cells::TakeCell;
+
+/// The ATT_MTU, which is never changed from its default on LE.
+pub const ATT_MTU: usize = 23;
+
+/// Characteristic properties, Vol. 3, Part G, section 3.3.1.1.
+pub mod properties {
+    pub const BROADCAST: u8 = 0x01;
+    pub const READ: u8 = 0x02;
+    pub const WRITE_WITHOUT_RESPONSE: u8 = 0x04;
+    pub const WRITE: u8 = 0x08;
+    pub const NOTIFY: u8 = 0x10;
+}
+
+/// ATT opcodes, Vol. 3, Part F, section 3.4.
+mod opcode {
+    pub const ERROR_RSP: u8 = 0x01;
+    pub const EXCHANGE_MTU_REQ: u8 = 0x02;
+    pub const EXCHANGE_MTU_RSP: u8 = 0x03;
+    pub const FIND_INFORMATION_REQ: u8 = 0x04;
+    pub const FIND_INFORMATION_RSP: u8 = 0x05;
+    pub const FIND_BY_TYPE_VALUE_REQ: u8 = 0x06;
+    pub const FIND_BY_TYPE_VALUE_RSP: u8 = 0x07;
+    pub const READ_BY_TYPE_REQ: u8 = 0x08;
+    pub const READ_BY_TYPE_RSP: u8 = 0x09;
+    pub const READ_REQ: u8 = 0x0A;
+    pub const READ_RSP: u8 = 0x0B;
+    pub const READ_BLOB_REQ: u8 = 0x0C;
+    pub const READ_BLOB_RSP: u8 = 0x0D;
+    pub const READ_BY_GROUP_TYPE_REQ: u8 = 0x10;
+    pub const READ_BY_GROUP_TYPE_RSP: u8 = 0x11;
+    pub const WRITE_REQ: u8 = 0x12;
+    pub const WRITE_RSP: u8 = 0x13;
+    pub const HANDLE_VALUE_NTF: u8 = 0x1B;
+    pub const WRITE_CMD: u8 = 0x52;
+    /// Set in the opcodes of commands, which have no response.
+    pub const COMMAND_FLAG: u8 = 0x40;
+}
+
+/// ATT error codes, Vol. 3, Part F, section 3.4.1.1.
+mod error {
+    pub const INVALID_HANDLE: u8 = 0x01;
+    pub const READ_NOT_PERMITTED: u8 = 0x02;
+    pub const WRITE_NOT_PERMITTED: u8 = 0x03;
+    pub const INVALID_PDU: u8 = 0x04;
+    pub const REQUEST_NOT_SUPPORTED: u8 = 0x06;
+    pub const INVALID_OFFSET: u8 = 0x07;
+    pub const ATTRIBUTE_NOT_FOUND: u8 = 0x0A;
+    pub const INVALID_ATTRIBUTE_VALUE_LENGTH: u8 = 0x0D;
+    pub const UNSUPPORTED_GROUP_TYPE: u8 = 0x10;
+}
+
+/// Attribute types defined by GATT.
+const PRIMARY_SERVICE: Uuid = Uuid::Uuid16(0x2800);
+const CHARACTERISTIC: Uuid = Uuid::Uuid16(0x2803);
+const CLIENT_CONFIGURATION: Uuid = Uuid::Uuid16(0x2902);
+
+/// Longest attribute value that is not stored in the value pool: a
+/// characteristic declaration with a 128-bit UUID.
+const DECLARATION_LEN: usize = 19;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Uuid {
+    Uuid16(u16),
+    /// A 128-bit UUID, in little endian byte order like on the air.
+    Uuid128([u8; 16]),
+}
+
+impl Uuid {
+    /// Bluetooth base UUID, of which 16-bit UUIDs are aliases.
+    const BASE: [u8; 16] = [
+        0xFB, 0x34, 0x9B, 0x5F, 0x80, 0x00, 0x00, 0x80, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00,
+    ];
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Uuid> {
+        match bytes.len() {
+            2 => Some(Uuid::Uuid16(u16::from_le_bytes([bytes[0], bytes[1]]))),
+            16 => {
+                let mut uuid = [0; 16];
+                uuid.copy_from_slice(bytes);
+                Some(Uuid::Uuid128(uuid))
+            }
+            _ => None,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Uuid::Uuid16(_) => 2,
+            Uuid::Uuid128(_) => 16,
+        }
+    }
+
+    /// Write the UUID to the start of `out`, and return its length.
+    fn encode(&self, out: &mut [u8]) -> usize {
+        match self {
+            Uuid::Uuid16(uuid) => out[..2].copy_from_slice(&uuid.to_le_bytes()),
+            Uuid::Uuid128(uuid) => out[..16].copy_from_slice(uuid),
+        }
+        self.len()
+    }
+
+    fn to_uuid128(self) -> [u8; 16] {
+        match self {
+            Uuid::Uuid16(uuid) => {
+                let mut full = Self::BASE;
+                full[12..14].copy_from_slice(&uuid.to_le_bytes());
+                full
+            }
+            Uuid::Uuid128(uuid) => uuid,
+        }
+    }
+
+    /// Whether both are the same UUID, even if one is a 16-bit alias.
+    fn matches(&self, other: &Uuid) -> bool {
+        self.to_uuid128() == other.to_uuid128()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum AttributeKind {
+    PrimaryService(Uuid),
+    /// Characteristic declaration. The value follows it.
+    Characteristic {
+        properties: u8,
+        uuid: Uuid,
+    },
+    /// Characteristic value, stored at `offset` in the value pool.
+    Value {
+        uuid: Uuid,
+        properties: u8,
+        offset: usize,
+        len: usize,
+        max_len: usize,
+    },
+    /// Client characteristic configuration descriptor.
+    ClientConfiguration {
+        notify: bool,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Attribute {
+    kind: AttributeKind,
+    /// The process which registered the attribute, or `None` for the kernel.
+    owner: Option<ProcessId>,
+}
+
+impl Attribute {
+    fn attribute_type(&self) -> Uuid {
+        match self.kind {
+            AttributeKind::PrimaryService(_) => PRIMARY_SERVICE,
+            AttributeKind::Characteristic { .. } => CHARACTERISTIC,
+            AttributeKind::Value { uuid, .. } => uuid,
+            AttributeKind::ClientConfiguration { .. } => CLIENT_CONFIGURATION,
+        }
+    }
+
+    fn readable(&self) -> bool {
+        match self.kind {
+            AttributeKind::Value { properties, .. } => properties & properties::READ != 0,
+            _ => true,
+        }
+    }
+
+    /// The value of the attribute, either from the value pool or built in
+    /// `scratch`.
+    fn value<'b>(
+        &self,
+        handle: u16,
+        values: &'b [u8],
+        scratch: &'b mut [u8; DECLARATION_LEN],
+    ) -> &'b [u8] {
+        match self.kind {
+            AttributeKind::PrimaryService(uuid) => {
+                let len = uuid.encode(scratch);
+                &scratch[..len]
+            }
+            AttributeKind::Characteristic { properties, uuid } => {
+                scratch[0] = properties;
+                scratch[1..3].copy_from_slice(&(handle + 1).to_le_bytes());
+                let len = uuid.encode(&mut scratch[3..]);
+                &scratch[..3 + len]
+            }
+            AttributeKind::Value { offset, len, .. } => &values[offset..offset + len],
+            AttributeKind::ClientConfiguration { notify } => {
+                scratch[0] = notify as u8;
+                scratch[1] = 0;
+                &scratch[..2]
+            }
+        }
+    }
+}
+
+/// A write of a characteristic value by the client.
+#[derive(Clone, Copy, Debug)]
+pub struct Written {
+    pub handle: u16,
+    pub owner: Option<ProcessId>,
+    pub len: usize,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+/// The registered attributes with handles between `start` and `end`.
+fn attributes_in_range(
+    attributes: &[Option<Attribute>],
+    start: u16,
+    end: u16,
+) -> impl Iterator<Item = (u16, &Attribute)> {
+    attributes
+        .iter()
+        .map_while(Option::as_ref)
+        .enumerate()
+        .map(|(i, attribute)| ((i + 1) as u16, attribute))
+        .skip_while(move |(handle, _)| *handle < start)
+        .take_while(move |(handle, _)| *handle <= end)
+}
+
+/// The last handle of the service declared at `handle`.
+fn group_end(attributes: &[Option<Attribute>], handle: u16) -> u16 {
+    attributes_in_range(attributes, handle + 1, u16::MAX)
+        .find(|(_, attribute)| matches!(attribute.kind, AttributeKind::PrimaryService(_)))
+        .map_or_else(
+            || attributes.iter().map_while(Option::as_ref).count() as u16,
+            |(next, _)| next - 1,
+        )
+}
+
+/// Check the handle range of a request, Vol. 3, Part F, section 3.4.3.1.
+fn check_range(start: u16, end: u16) -> Result<(), (u16, u8)> {
+    if start == 0 || start > end {
+        Err((start, error::INVALID_HANDLE))
+    } else {
+        Ok(())
+    }
+}
+
+pub struct AttributeDatabase {
+    attributes: TakeCell<'static, [Option<Attribute>]>,
+    /// Pool holding the characteristic values.
+    values: TakeCell<'static, [u8]>,
+    values_used: Cell<usize>,
+}
+
+impl AttributeDatabase {
+    pub fn new(attributes: &'static mut [Option<Attribute>], values: &'static mut [u8]) -> Self {
+        Self {
+            attributes: TakeCell::new(attributes),
+            values: TakeCell::new(values),
+            values_used: Cell::new(0),
+        }
+    }
+
+    /// Append `new` to the attributes, and return the handle of the first
+    /// one.
+    fn append(&self, new: &[Attribute]) -> Result<u16, ErrorCode> {
+        self.attributes.map_or(Err(ErrorCode::FAIL), |attributes| {
+            let count = attributes.iter().map_while(Option::as_ref).count();
+            if count + new.len() > attributes.len() || count + new.len() > u16::MAX as usize {
+                return Err(ErrorCode::NOMEM);
+            }
+            for (slot, attribute) in attributes[count..].iter_mut().zip(new) {
+                *slot = Some(*attribute);
+            }
+            Ok((count + 1) as u16)
+        })
+    }
+
+    /// Register a primary service, and return the handle of its
+    /// declaration.
+    pub fn add_service(&self, owner: Option<ProcessId>, uuid: Uuid) -> Result<u16, ErrorCode> {
+        self.append(&[Attribute {
+            kind: AttributeKind::PrimaryService(uuid),
+            owner,
+        }])
+    }
+
+    /// Register a characteristic with values of up to `max_len` bytes in the
+    /// last registered service, and return the handle of its value.
+    ///
+    /// The last registered service must belong to `owner`, and there must not
+    /// be attributes of another owner after it. A client configuration
+    /// descriptor is added if the characteristic supports notifications.
+    pub fn add_characteristic(
+        &self,
+        owner: Option<ProcessId>,
+        uuid: Uuid,
+        properties: u8,
+        max_len: usize,
+    ) -> Result<u16, ErrorCode> {
+        let last_owner = self.attributes.map_or(Err(ErrorCode::FAIL), |attributes| {
+            attributes
+                .iter()
+                .map_while(Option::as_ref)
+                .last()
+                .map(|attribute| attribute.owner)
+                .ok_or(ErrorCode::INVAL)
+        })?;
+        if last_owner != owner {
+            return Err(ErrorCode::INVAL);
+        }
+        if max_len > 512 {
+            return Err(ErrorCode::SIZE);
+        }
+        let offset = self.values_used.get();
+        if offset + max_len > self.values.map_or(0, |values| values.len()) {
+            return Err(ErrorCode::NOMEM);
+        }
+
+        let declaration = Attribute {
+            kind: AttributeKind::Characteristic { properties, uuid },
+            owner,
+        };
+        let value = Attribute {
+            kind: AttributeKind::Value {
+                uuid,
+                properties,
+                offset,
+                len: 0,
+                max_len,
+            },
+            owner,
+        };
+        let configuration = Attribute {
+            kind: AttributeKind::ClientConfiguration { notify: false },
+            owner,
+        };
+        let handle = if properties & properties::NOTIFY != 0 {
+            self.append(&[declaration, value, configuration])?
+        } else {
+            self.append(&[declaration, value])?
+        };
+        self.values_used.set(offset + max_len);
+        Ok(handle + 1)
+    }
+
+    /// Get the characteristic value at `handle`, which must belong to
+    /// `owner`, and its maximum length.
+    fn owned_value(
+        attributes: &[Option<Attribute>],
+        owner: Option<ProcessId>,
+        handle: u16,
+    ) -> Result<&Attribute, ErrorCode> {
+        attributes_in_range(attributes, handle, handle)
+            .next()
+            .map(|(_, attribute)| attribute)
+            .filter(|attribute| {
+                attribute.owner == owner && matches!(attribute.kind, AttributeKind::Value { .. })
+            })
+            .ok_or(ErrorCode::INVAL)
+    }
+
+    /// Set the characteristic value at `handle`, which must belong to
+    /// `owner`. `fill` is passed the space for the value, and returns its
+    /// length.
+    pub fn set_value(
+        &self,
+        owner: Option<ProcessId>,
+        handle: u16,
+        fill: impl FnOnce(&mut [u8]) -> usize,
+    ) -> Result<(), ErrorCode> {
+        self.attributes.map_or(Err(ErrorCode::FAIL), |attributes| {
+            let attribute = Self::owned_value(attributes, owner, handle)?;
+            let AttributeKind::Value {
+                uuid,
+                properties,
+                offset,
+                max_len,
+                ..
+            } = attribute.kind
+            else {
+                return Err(ErrorCode::INVAL);
+            };
+            let len = self.values.map_or(Err(ErrorCode::FAIL), |values| {
+                Ok(fill(&mut values[offset..offset + max_len]).min(max_len))
+            })?;
+            attributes[handle as usize - 1] = Some(Attribute {
+                kind: AttributeKind::Value {
+                    uuid,
+                    properties,
+                    offset,
+                    len,
+                    max_len,
+                },
+                owner,
+            });
+            Ok(())
+        })
+    }
+
+    /// Pass the characteristic value at `handle`, which must belong to
+    /// `owner`, to `f`.
+    pub fn value<R>(
+        &self,
+        owner: Option<ProcessId>,
+        handle: u16,
+        f: impl FnOnce(&[u8]) -> R,
+    ) -> Result<R, ErrorCode> {
+        self.attributes.map_or(Err(ErrorCode::FAIL), |attributes| {
+            let attribute = Self::owned_value(attributes, owner, handle)?;
+            let AttributeKind::Value { offset, len, .. } = attribute.kind else {
+                return Err(ErrorCode::INVAL);
+            };
+            self.values.map_or(Err(ErrorCode::FAIL), |values| {
+                Ok(f(&values[offset..offset + len]))
+            })
+        })
+    }
+
+    /// Build a notification of the characteristic value at `handle`, which
+    /// must belong to `owner`, in `out`, and return its length.
+    ///
+    /// Fails with `NOSUPPORT` if the characteristic doesn't support
+    /// notifications, and `OFF` if the client didn't enable them.
+    pub fn notification(
+        &self,
+        owner: Option<ProcessId>,
+        handle: u16,
+        out: &mut [u8],
+    ) -> Result<usize, ErrorCode> {
+        self.attributes.map_or(Err(ErrorCode::FAIL), |attributes| {
+            let attribute = Self::owned_value(attributes, owner, handle)?;
+            let AttributeKind::Value {
+                properties,
+                offset,
+                len,
+                ..
+            } = attribute.kind
+            else {
+                return Err(ErrorCode::INVAL);
+            };
+            if properties & properties::NOTIFY == 0 {
+                return Err(ErrorCode::NOSUPPORT);
+            }
+            let enabled = matches!(
+                attributes_in_range(attributes, handle + 1, handle + 1).next(),
+                Some((
+                    _,
+                    Attribute {
+                        kind: AttributeKind::ClientConfiguration { notify: true },
+                        ..
+                    }
+                ))
+            );
+            if !enabled {
+                return Err(ErrorCode::OFF);
+            }
+            let len = len.min(ATT_MTU - 3);
+            out[0] = opcode::HANDLE_VALUE_NTF;
+            out[1..3].copy_from_slice(&handle.to_le_bytes());
+            self.values.map_or(Err(ErrorCode::FAIL), |values| {
+                out[3..3 + len].copy_from_slice(&values[offset..offset + len]);
+                Ok(3 + len)
+            })
+        })
+    }
+
+    /// Disable notifications, which are only configured for the duration
+    /// of a connection.
+    pub fn reset_client_configuration(&self) {
+        self.attributes.map(|attributes| {
+            for attribute in attributes.iter_mut().map_while(Option::as_mut) {
+                if let AttributeKind::ClientConfiguration { notify } = &mut attribute.kind {
+                    *notify = false;
+                }
+            }
+        });
+    }
+
+    /// Handle an ATT PDU from the client, writing the response in `response`
+    /// (which is at least `ATT_MTU` bytes). Returns the length of the
+    /// response, or 0 for none, and the characteristic value written, if
+    /// any.
+    pub fn handle_request(&self, request: &[u8], response: &mut [u8]) -> (usize, Option<Written>) {
+        let Some(&request_opcode) = request.first() else {
+            return (0, None);
+        };
+        let response = &mut response[..ATT_MTU];
+        let mut written = None;
+        let res = self
+            .attributes
+            .map_or(Err((0, error::INVALID_HANDLE)), |attributes| {
+                self.values
+                    .map_or(Err((0, error::INVALID_HANDLE)), |values| {
+                        let params = &request[1..];
+                        match request_opcode {
+                            opcode::EXCHANGE_MTU_REQ if params.len() == 2 => {
+                                response[0] = opcode::EXCHANGE_MTU_RSP;
+                                response[1..3].copy_from_slice(&(ATT_MTU as u16).to_le_bytes());
+                                Ok(3)
+                            }
+                            opcode::FIND_INFORMATION_REQ if params.len() == 4 => {
+                                Self::find_information(attributes, params, response)
+                            }
+                            opcode::FIND_BY_TYPE_VALUE_REQ if params.len() >= 6 => {
+                                Self::find_by_type_value(attributes, params, response)
+                            }
+                            opcode::READ_BY_TYPE_REQ if params.len() == 6 || params.len() == 20 => {
+                                Self::read_by_type(attributes, values, params, response)
+                            }
+                            opcode::READ_REQ if params.len() == 2 => Self::read(
+                                attributes,
+                                values,
+                                u16_at(params, 0),
+                                0,
+                                opcode::READ_RSP,
+                                response,
+                            ),
+                            opcode::READ_BLOB_REQ if params.len() == 4 => Self::read(
+                                attributes,
+                                values,
+                                u16_at(params, 0),
+                                u16_at(params, 2) as usize,
+                                opcode::READ_BLOB_RSP,
+                                response,
+                            ),
+                            opcode::READ_BY_GROUP_TYPE_REQ
+                                if params.len() == 6 || params.len() == 20 =>
+                            {
+                                Self::read_by_group_type(attributes, params, response)
+                            }
+                            opcode::WRITE_REQ | opcode::WRITE_CMD if params.len() >= 2 => {
+                                Self::write(attributes, values, request_opcode, params).map(|w| {
+                                    written = w;
+                                    response[0] = opcode::WRITE_RSP;
+                                    1
+                                })
+                            }
+                            opcode::EXCHANGE_MTU_REQ
+                            | opcode::FIND_INFORMATION_REQ
+                            | opcode::FIND_BY_TYPE_VALUE_REQ
+                            | opcode::READ_BY_TYPE_REQ
+                            | opcode::READ_REQ
+                            | opcode::READ_BLOB_REQ
+                            | opcode::READ_BY_GROUP_TYPE_REQ
+                            | opcode::WRITE_REQ => Err((0, error::INVALID_PDU)),
+                            _ => Err((0, error::REQUEST_NOT_SUPPORTED)),
+                        }
+                    })
+            });
+
+        // Commands never have a response.
+        if request_opcode & opcode::COMMAND_FLAG != 0 {
+            return (0, written);
+        }
+        match res {
+            Ok(len) => (len, written),
+            Err((handle, code)) => {
+                response[0] = opcode::ERROR_RSP;
+                response[1] = request_opcode;
+                response[2..4].copy_from_slice(&handle.to_le_bytes());
+                response[4] = code;
+                (5, None)
+            }
+        }
+    }
+
+    fn find_information(
+        attributes: &[Option<Attribute>],
+        params: &[u8],
+        response: &mut [u8],
+    ) -> Result<usize, (u16, u8)> {
+        let (start, end) = (u16_at(params, 0), u16_at(params, 2));
+        check_range(start, end)?;
+        response[0] = opcode::FIND_INFORMATION_RSP;
+        let mut len = 2;
+        for (handle, attribute) in attributes_in_range(attributes, start, end) {
+            let attribute_type = attribute.attribute_type();
+            // All entries must use the same format, set by the first one.
+            let format = if attribute_type.len() == 2 { 1 } else { 2 };
+            if len == 2 {
+                response[1] = format;
+            } else if response[1] != format {
+                break;
+            }
+            if len + 2 + attribute_type.len() > ATT_MTU {
+                break;
+            }
+            response[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+            len += 2 + attribute_type.encode(&mut response[len + 2..]);
+        }
+        if len == 2 {
+            return Err((start, error::ATTRIBUTE_NOT_FOUND));
+        }
+        Ok(len)
+    }
+
+    /// Only supports finding primary services by UUID, which is the only use
+    /// of this request by GATT.
+    fn find_by_type_value(
+        attributes: &[Option<Attribute>],
+        params: &[u8],
+        response: &mut [u8],
+    ) -> Result<usize, (u16, u8)> {
+        let (start, end) = (u16_at(params, 0), u16_at(params, 2));
+        check_range(start, end)?;
+        let attribute_type = Uuid::Uuid16(u16_at(params, 4));
+        let value = Uuid::from_bytes(&params[6..]);
+        response[0] = opcode::FIND_BY_TYPE_VALUE_RSP;
+        let mut len = 1;
+        if let (true, Some(value)) = (attribute_type.matches(&PRIMARY_SERVICE), value) {
+            for (handle, attribute) in attributes_in_range(attributes, start, end) {
+                if len + 4 > ATT_MTU {
+                    break;
+                }
+                if let AttributeKind::PrimaryService(uuid) = attribute.kind {
+                    if uuid.matches(&value) {
+                        response[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+                        response[len + 2..len + 4]
+                            .copy_from_slice(&group_end(attributes, handle).to_le_bytes());
+                        len += 4;
+                    }
+                }
+            }
+        }
+        if len == 1 {
+            return Err((start, error::ATTRIBUTE_NOT_FOUND));
+        }
+        Ok(len)
+    }
+
+    fn read_by_type(
+        attributes: &[Option<Attribute>],
+        values: &[u8],
+        params: &[u8],
+        response: &mut [u8],
+    ) -> Result<usize, (u16, u8)> {
+        let (start, end) = (u16_at(params, 0), u16_at(params, 2));
+        check_range(start, end)?;
+        let attribute_type = Uuid::from_bytes(&params[4..]).ok_or((0, error::INVALID_PDU))?;
+        response[0] = opcode::READ_BY_TYPE_RSP;
+        let mut len = 2;
+        for (handle, attribute) in attributes_in_range(attributes, start, end) {
+            if !attribute.attribute_type().matches(&attribute_type) {
+                continue;
+            }
+            if !attribute.readable() {
+                if len == 2 {
+                    return Err((handle, error::READ_NOT_PERMITTED));
+                }
+                break;
+            }
+            let mut scratch = [0; DECLARATION_LEN];
+            let value = attribute.value(handle, values, &mut scratch);
+            // All entries must have the same length, set by the first one.
+            let entry_len = 2 + value.len().min(ATT_MTU - 4);
+            if len == 2 {
+                response[1] = entry_len as u8;
+            } else if response[1] as usize != entry_len {
+                break;
+            }
+            if len + entry_len > ATT_MTU {
+                break;
+            }
+            response[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+            response[len + 2..len + entry_len].copy_from_slice(&value[..entry_len - 2]);
+            len += entry_len;
+        }
+        if len == 2 {
+            return Err((start, error::ATTRIBUTE_NOT_FOUND));
+        }
+        Ok(len)
+    }
+
+    /// Answer with `response_opcode` and the value at `handle` from
+    /// `offset`.
+    fn read(
+        attributes: &[Option<Attribute>],
+        values: &[u8],
+        handle: u16,
+        offset: usize,
+        response_opcode: u8,
+        response: &mut [u8],
+    ) -> Result<usize, (u16, u8)> {
+        let (_, attribute) = attributes_in_range(attributes, handle, handle)
+            .next()
+            .filter(|_| handle != 0)
+            .ok_or((handle, error::INVALID_HANDLE))?;
+        if !attribute.readable() {
+            return Err((handle, error::READ_NOT_PERMITTED));
+        }
+        let mut scratch = [0; DECLARATION_LEN];
+        let value = attribute.value(handle, values, &mut scratch);
+        if offset > value.len() {
+            return Err((handle, error::INVALID_OFFSET));
+        }
+        let len = (value.len() - offset).min(ATT_MTU - 1);
+        response[0] = response_opcode;
+        response[1..1 + len].copy_from_slice(&value[offset..offset + len]);
+        Ok(1 + len)
+    }
+
+    fn read_by_group_type(
+        attributes: &[Option<Attribute>],
+        params: &[u8],
+        response: &mut [u8],
+    ) -> Result<usize, (u16, u8)> {
+        let (start, end) = (u16_at(params, 0), u16_at(params, 2));
+        check_range(start, end)?;
+        let group_type = Uuid::from_bytes(&params[4..]).ok_or((0, error::INVALID_PDU))?;
+        if !group_type.matches(&PRIMARY_SERVICE) {
+            return Err((start, error::UNSUPPORTED_GROUP_TYPE));
+        }
+        response[0] = opcode::READ_BY_GROUP_TYPE_RSP;
+        let mut len = 2;
+        for (handle, attribute) in attributes_in_range(attributes, start, end) {
+            let AttributeKind::PrimaryService(uuid) = attribute.kind else {
+                continue;
+            };
+            // All entries must have the same length, set by the first one.
+            let entry_len = 4 + uuid.len();
+            if len == 2 {
+                response[1] = entry_len as u8;
+            } else if response[1] as usize != entry_len {
+                break;
+            }
+            if len + entry_len > ATT_MTU {
+                break;
+            }
+            response[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+            response[len + 2..len + 4]
+                .copy_from_slice(&group_end(attributes, handle).to_le_bytes());
+            uuid.encode(&mut response[len + 4..]);
+            len += entry_len;
+        }
+        if len == 2 {
+            return Err((start, error::ATTRIBUTE_NOT_FOUND));
+        }
+        Ok(len)
+    }
+
+    fn write(
+        attributes: &mut [Option<Attribute>],
+        values: &mut [u8],
+        request_opcode: u8,
+        params: &[u8],
+    ) -> Result<Option<Written>, (u16, u8)> {
+        let handle = u16_at(params, 0);
+        let value = &params[2..];
+        let (_, attribute) = attributes_in_range(attributes, handle, handle)
+            .next()
+            .filter(|_| handle != 0)
+            .ok_or((handle, error::INVALID_HANDLE))?;
+        let owner = attribute.owner;
+        match attribute.kind {
+            AttributeKind::Value {
+                properties,
+                offset,
+                max_len,
+                ..
+            } => {
+                let required = if request_opcode == opcode::WRITE_CMD {
+                    properties::WRITE_WITHOUT_RESPONSE
+                } else {
+                    properties::WRITE
+                };
+                if properties & required == 0 {
+                    return Err((handle, error::WRITE_NOT_PERMITTED));
+                }
+                if value.len() > max_len {
+                    return Err((handle, error::INVALID_ATTRIBUTE_VALUE_LENGTH));
+                }
+                values[offset..offset + value.len()].copy_from_slice(value);
+                if let Some(Attribute {
+                    kind: AttributeKind::Value { len, .. },
+                    ..
+                }) = &mut attributes[handle as usize - 1]
+                {
+                    *len = value.len();
+                }
+                Ok(Some(Written {
+                    handle,
+                    owner,
+                    len: value.len(),
+                }))
+            }
+            AttributeKind::ClientConfiguration { .. } => {
+                if value.len() != 2 {
+                    return Err((handle, error::INVALID_ATTRIBUTE_VALUE_LENGTH));
+                }
+                if let Some(Attribute {
+                    kind: AttributeKind::ClientConfiguration { notify },
+                    ..
+                }) = &mut attributes[handle as usize - 1]
+                {
+                    *notify = value[0] & 1 != 0;
+                }
+                Ok(None)
+            }
+            _ => Err((handle, error::WRITE_NOT_PERMITTED)),
+        }
+    }
+}
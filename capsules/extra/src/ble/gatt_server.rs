@@ -0,0 +1,441 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Bluetooth Low Energy GATT server for processes.
+//!
+//! The server sits on the [link layer](super::link_layer) and implements the
+//! minimum of L2CAP needed by a peripheral: it dispatches the attribute
+//! protocol channel to the [attribute database](super::att), rejects
+//! signaling requests and declines pairing. Processes register services and
+//! characteristics in the database, set and read their values, are notified
+//! when the client writes them, and can send notifications.
+//!
+//! The kernel registers the GAP service with the device name. Attributes are
+//! not removed when a process exits, so the database must be sized for all
+//! the registrations of processes over the uptime of the board.
+//!
+//! ### Allow system calls
+//!
+//! Read-only buffers:
+//!
+//! * 0: Advertising data (AD structures), up to 31 bytes.
+//! * 1: Scan response data, up to 31 bytes.
+//! * 2: A 128-bit UUID, in little endian byte order, for commands 3 and 4.
+//! * 3: Characteristic value, for command 5.
+//!
+//! Read-write buffer 0 receives a characteristic value for command 6.
+//!
+//! ### Subscribe system call
+//!
+//! * 0: Connection state changed. The arguments are 1 for connected or 0 for
+//!   disconnected, and the reason of the disconnection.
+//! * 1: The client wrote a characteristic of the process. The arguments are
+//!   the handle of the value and its length.
+//!
+//! ### Command system call
+//!
+//! * 0: Check driver exists.
+//! * 1: Start advertising every `data1` milliseconds. Advertising restarts
+//!   after a disconnection, until it is stopped.
+//! * 2: Stop advertising.
+//! * 3: Register a primary service with the 16-bit UUID `data1`, or the
+//!   128-bit UUID in read-only buffer 2 if `data1` is 0. Returns the handle of
+//!   the service.
+//! * 4: Register a characteristic in the last service registered by the
+//!   process, with the 16-bit UUID `data1` (or a 128-bit UUID as for command
+//!   3). The low byte of `data2` holds the characteristic properties and the
+//!   upper bits the maximum length of the value. Returns the handle of the
+//!   value.
+//! * 5: Set the value of characteristic `data1` from read-only buffer 3.
+//! * 6: Copy the value of characteristic `data1` into read-write buffer 0.
+//!   Returns the length of the value.
+//! * 7: Notify the client of the value of characteristic `data1`. Fails with
+//!   `OFF` if the client didn't enable notifications.
+//! * 8: Disconnect.
+//!
+//! Usage
+//! -----
+//!
+//! See `components::ble_gatt::BleGattComponent`.
+
+use core::cell::Cell;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+use super::att::{self, AttributeDatabase, Uuid};
+use super::link_layer::{
+    ADDRESS_LEN, ADV_DATA_LEN, BleLinkLayer, BleLinkLayerClient, DATA_PAYLOAD_LEN,
+};
+
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::BleGatt as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const ADV_DATA: usize = 0;
+    pub const SCAN_RESPONSE: usize = 1;
+    pub const UUID: usize = 2;
+    pub const VALUE: usize = 3;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 4;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const VALUE: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+mod upcall {
+    pub const CONNECTION: usize = 0;
+    pub const WRITTEN: usize = 1;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+/// L2CAP channel identifiers, Vol. 3, Part A, section 2.1.
+mod cid {
+    pub const ATT: u16 = 0x0004;
+    pub const SIGNALING: u16 = 0x0005;
+    pub const SECURITY_MANAGER: u16 = 0x0006;
+}
+
+const L2CAP_HEADER_LEN: usize = 4;
+
+// LE signaling, Vol. 3, Part A, section 4.
+const COMMAND_REJECT: u8 = 0x01;
+const CONNECTION_PARAMETER_UPDATE_RSP: u8 = 0x13;
+
+// Security manager, Vol. 3, Part H, section 3.5.
+const PAIRING_REQUEST: u8 = 0x01;
+const PAIRING_FAILED: u8 = 0x05;
+const PAIRING_NOT_SUPPORTED: u8 = 0x05;
+
+/// GAP service and characteristics, from the Bluetooth assigned numbers.
+const GAP_SERVICE: u16 = 0x1800;
+const DEVICE_NAME: u16 = 0x2A00;
+const APPEARANCE: u16 = 0x2A01;
+
+type L2capPacket = ([u8; DATA_PAYLOAD_LEN], usize);
+
+pub struct GattServer<'a, L: BleLinkLayer<'a>> {
+    link: &'a L,
+    database: AttributeDatabase,
+    apps: Grant<
+        (),
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    /// The process advertising, and its interval.
+    advertising: OptionalCell<(ProcessId, u32)>,
+    /// Response waiting for space in the transmit queue of the link layer.
+    pending: Cell<Option<L2capPacket>>,
+}
+
+impl<'a, L: BleLinkLayer<'a>> GattServer<'a, L> {
+    pub fn new(
+        link: &'a L,
+        database: AttributeDatabase,
+        grant: Grant<
+            (),
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> Self {
+        Self {
+            link,
+            database,
+            apps: grant,
+            advertising: OptionalCell::empty(),
+            pending: Cell::new(None),
+        }
+    }
+
+    /// Register the GAP service, with the name of the device.
+    pub fn init(&self, device_name: &[u8]) -> Result<(), ErrorCode> {
+        self.database.add_service(None, Uuid::Uuid16(GAP_SERVICE))?;
+        let name = self.database.add_characteristic(
+            None,
+            Uuid::Uuid16(DEVICE_NAME),
+            att::properties::READ,
+            device_name.len(),
+        )?;
+        self.database.set_value(None, name, |value| {
+            value.copy_from_slice(device_name);
+            device_name.len()
+        })?;
+        let appearance = self.database.add_characteristic(
+            None,
+            Uuid::Uuid16(APPEARANCE),
+            att::properties::READ,
+            2,
+        )?;
+        // Unknown appearance.
+        self.database.set_value(None, appearance, |value| {
+            value.fill(0);
+            2
+        })
+    }
+
+    /// Send `payload` on the L2CAP channel `channel`.
+    fn send(&self, channel: u16, payload: &[u8]) -> Result<(), ErrorCode> {
+        let mut packet = [0; DATA_PAYLOAD_LEN];
+        let len = L2CAP_HEADER_LEN + payload.len();
+        if len > DATA_PAYLOAD_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        packet[0..2].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+        packet[2..4].copy_from_slice(&channel.to_le_bytes());
+        packet[L2CAP_HEADER_LEN..len].copy_from_slice(payload);
+        self.link.send(true, &packet[..len])
+    }
+
+    /// Send an answer to the client, or keep it until there is space in the
+    /// transmit queue. The client only has one request outstanding at a
+    /// time, so there is at most one pending answer.
+    fn answer(&self, channel: u16, payload: &[u8]) {
+        if self.send(channel, payload) == Err(ErrorCode::BUSY) {
+            let mut packet = [0; DATA_PAYLOAD_LEN];
+            packet[..2].copy_from_slice(&channel.to_le_bytes());
+            packet[2..2 + payload.len()].copy_from_slice(payload);
+            self.pending.set(Some((packet, payload.len())));
+        }
+    }
+
+    fn uuid(&self, processid: ProcessId, uuid16: usize) -> Result<Uuid, ErrorCode> {
+        if uuid16 != 0 {
+            return u16::try_from(uuid16)
+                .map(Uuid::Uuid16)
+                .map_err(|_| ErrorCode::INVAL);
+        }
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::UUID)
+                    .and_then(|uuid| {
+                        uuid.enter(|uuid| {
+                            let mut bytes = [0; 16];
+                            uuid.get(..16)
+                                .ok_or(ErrorCode::SIZE)
+                                .map(|uuid| uuid.copy_to_slice(&mut bytes))?;
+                            Ok(Uuid::Uuid128(bytes))
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::FAIL))
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    fn start_advertising(&self, processid: ProcessId, interval_ms: u32) -> Result<(), ErrorCode> {
+        if self
+            .advertising
+            .get()
+            .is_some_and(|(advertiser, _)| advertiser != processid)
+        {
+            return Err(ErrorCode::RESERVE);
+        }
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                let mut adv_data = [0; ADV_DATA_LEN];
+                let mut scan_response = [0; ADV_DATA_LEN];
+                let copy = |index, out: &mut [u8; ADV_DATA_LEN]| {
+                    kernel_data
+                        .get_readonly_processbuffer(index)
+                        .and_then(|data| {
+                            data.enter(|data| {
+                                let len = data.len().min(ADV_DATA_LEN);
+                                data[..len].copy_to_slice(&mut out[..len]);
+                                len
+                            })
+                        })
+                        .unwrap_or(0)
+                };
+                let adv_data_len = copy(ro_allow::ADV_DATA, &mut adv_data);
+                let scan_response_len = copy(ro_allow::SCAN_RESPONSE, &mut scan_response);
+                self.link.start_advertising(
+                    &adv_data[..adv_data_len],
+                    &scan_response[..scan_response_len],
+                    interval_ms,
+                )
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+        self.advertising.set((processid, interval_ms));
+        Ok(())
+    }
+
+    fn l2cap_received(&self, channel: u16, payload: &[u8]) {
+        match channel {
+            cid::ATT => {
+                let mut response = [0; att::ATT_MTU];
+                let (len, written) = self.database.handle_request(payload, &mut response);
+                if len > 0 {
+                    self.answer(cid::ATT, &response[..len]);
+                }
+                if let Some(written) = written {
+                    if let Some(owner) = written.owner {
+                        let _ = self.apps.enter(owner, |_, kernel_data| {
+                            let _ = kernel_data.schedule_upcall(
+                                upcall::WRITTEN,
+                                (written.handle as usize, written.len, 0),
+                            );
+                        });
+                    }
+                }
+            }
+            cid::SIGNALING if payload.len() >= 4 => {
+                // No signaling procedure is supported.
+                if !matches!(payload[0], COMMAND_REJECT | CONNECTION_PARAMETER_UPDATE_RSP) {
+                    self.answer(cid::SIGNALING, &[COMMAND_REJECT, payload[1], 2, 0, 0, 0]);
+                }
+            }
+            cid::SECURITY_MANAGER if payload.first() == Some(&PAIRING_REQUEST) => {
+                self.answer(
+                    cid::SECURITY_MANAGER,
+                    &[PAIRING_FAILED, PAIRING_NOT_SUPPORTED],
+                );
+            }
+            _ => {}
+        }
+    }
+
+    fn connection_upcall(&self, connected: bool, reason: u8) {
+        self.apps.each(|_, _, kernel_data| {
+            let _ = kernel_data
+                .schedule_upcall(upcall::CONNECTION, (connected as usize, reason as usize, 0));
+        });
+    }
+}
+
+impl<'a, L: BleLinkLayer<'a>> BleLinkLayerClient for GattServer<'a, L> {
+    fn connected(&self, _peer: [u8; ADDRESS_LEN]) {
+        self.connection_upcall(true, 0);
+    }
+
+    fn disconnected(&self, reason: u8) {
+        self.pending.set(None);
+        self.database.reset_client_configuration();
+        if let Some((processid, interval_ms)) = self.advertising.take() {
+            if self.start_advertising(processid, interval_ms).is_err() {
+                self.advertising.clear();
+            }
+        }
+        self.connection_upcall(false, reason);
+    }
+
+    fn received(&self, start: bool, data: &[u8]) {
+        // With the default ATT_MTU, no message is fragmented, so a
+        // continuation is not expected.
+        if !start || data.len() < L2CAP_HEADER_LEN {
+            return;
+        }
+        let len = u16::from_le_bytes([data[0], data[1]]) as usize;
+        let channel = u16::from_le_bytes([data[2], data[3]]);
+        if let Some(payload) = data.get(L2CAP_HEADER_LEN..L2CAP_HEADER_LEN + len) {
+            self.l2cap_received(channel, payload);
+        }
+    }
+
+    fn send_done(&self) {
+        if let Some((packet, len)) = self.pending.take() {
+            let channel = u16::from_le_bytes([packet[0], packet[1]]);
+            self.answer(channel, &packet[2..2 + len]);
+        }
+    }
+}
+
+impl<'a, L: BleLinkLayer<'a>> SyscallDriver for GattServer<'a, L> {
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        let handle = data1 as u16;
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => self.start_advertising(processid, data1 as u32).into(),
+            2 => match self.advertising.get() {
+                Some((advertiser, _)) if advertiser == processid => {
+                    self.advertising.clear();
+                    self.link.stop_advertising().into()
+                }
+                _ => CommandReturn::failure(ErrorCode::ALREADY),
+            },
+            3 => self
+                .uuid(processid, data1)
+                .and_then(|uuid| self.database.add_service(Some(processid), uuid))
+                .map_or_else(CommandReturn::failure, |handle| {
+                    CommandReturn::success_u32(handle as u32)
+                }),
+            4 => self
+                .uuid(processid, data1)
+                .and_then(|uuid| {
+                    self.database
+                        .add_characteristic(Some(processid), uuid, data2 as u8, data2 >> 8)
+                })
+                .map_or_else(CommandReturn::failure, |handle| {
+                    CommandReturn::success_u32(handle as u32)
+                }),
+            5 => self
+                .apps
+                .enter(processid, |_, kernel_data| {
+                    kernel_data
+                        .get_readonly_processbuffer(ro_allow::VALUE)
+                        .and_then(|src| {
+                            src.enter(|src| {
+                                self.database.set_value(Some(processid), handle, |value| {
+                                    let len = src.len().min(value.len());
+                                    src[..len].copy_to_slice(&mut value[..len]);
+                                    len
+                                })
+                            })
+                        })
+                        .unwrap_or(Err(ErrorCode::FAIL))
+                })
+                .unwrap_or_else(|err| Err(err.into()))
+                .into(),
+            6 => self
+                .apps
+                .enter(processid, |_, kernel_data| {
+                    kernel_data
+                        .get_readwrite_processbuffer(rw_allow::VALUE)
+                        .and_then(|dest| {
+                            dest.mut_enter(|dest| {
+                                self.database.value(Some(processid), handle, |value| {
+                                    let len = value.len().min(dest.len());
+                                    dest[..len].copy_from_slice(&value[..len]);
+                                    value.len()
+                                })
+                            })
+                        })
+                        .unwrap_or(Err(ErrorCode::FAIL))
+                })
+                .unwrap_or_else(|err| Err(err.into()))
+                .map_or_else(CommandReturn::failure, |len| {
+                    CommandReturn::success_u32(len as u32)
+                }),
+            7 => {
+                let mut notification = [0; att::ATT_MTU];
+                self.database
+                    .notification(Some(processid), handle, &mut notification)
+                    .and_then(|len| self.send(cid::ATT, &notification[..len]))
+                    .into()
+            }
+            8 => self.link.disconnect().into(),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
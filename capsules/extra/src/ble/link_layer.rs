@@ -0,0 +1,990 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Bluetooth Low Energy link layer in the peripheral role.
+//!
+//! The link layer advertises as connectable (`ADV_IND`) with a static random
+//! address, answers scan requests, and accepts a connection from a central.
+//! In a connection it follows the connection events of the central: it hops
+//! over the data channels with channel selection algorithm #1, listens in a
+//! window widened by the sleep clock accuracy of both devices, acknowledges
+//! and retransmits data channel PDUs, applies connection parameter and
+//! channel map updates at their instant, and drops the connection when the
+//! supervision timeout expires.
+//!
+//! It is radio agnostic: the radio only implements
+//! [`BleLinkRadio`](kernel::hil::ble_link::BleLinkRadio), and the timing of
+//! advertising and connection events is done with an alarm.
+//!
+//! Above it, the host (L2CAP and up) uses the [`BleLinkLayer`] trait to send
+//! and receive data channel payloads.
+//!
+//! Limitations:
+//!
+//! - Only the LE 1M PHY, without the data length extension, so payloads are
+//!   at most [`DATA_PAYLOAD_LEN`] bytes.
+//! - No encryption.
+//! - A single packet is exchanged in each connection event, and the
+//!   peripheral latency is not used to skip events.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! # use kernel::static_init;
+//!
+//! let ble_link = static_init!(
+//!     capsules_extra::ble::link_layer::LinkLayer<'static, Radio, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules_extra::ble::link_layer::LinkLayer::new(radio, alarm, address, adv_buffer)
+//! );
+//! radio.set_link_client(ble_link);
+//! alarm.set_alarm_client(ble_link);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::ErrorCode;
+use kernel::hil::ble_advertising::RadioChannel;
+use kernel::hil::ble_link::{self, BleLinkRadio, BleLinkRadioClient};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+
+/// Length of a device address.
+pub const ADDRESS_LEN: usize = 6;
+
+/// Maximum length of the advertising data and the scan response data.
+pub const ADV_DATA_LEN: usize = 31;
+
+/// Maximum payload of a data channel PDU.
+pub const DATA_PAYLOAD_LEN: usize = 27;
+
+const DATA_PACKET_LEN: usize = 2 + DATA_PAYLOAD_LEN;
+
+/// Number of data channel PDUs waiting to be sent.
+const TX_QUEUE_LEN: usize = 4;
+
+// Advertising channel PDU types, Bluetooth Core Specification Vol. 6, Part B,
+// section 2.3.
+const ADV_IND: u8 = 0b0000;
+const SCAN_REQ: u8 = 0b0011;
+const SCAN_RSP: u8 = 0b0100;
+const CONNECT_IND: u8 = 0b0101;
+const ADV_HEADER_TXADD: u8 = 1 << 6;
+const ADV_HEADER_RXADD: u8 = 1 << 7;
+
+/// Length of the `CONNECT_IND` payload.
+const CONNECT_IND_LEN: usize = 34;
+
+/// Logical link identifiers of data channel PDUs, section 2.4.
+mod llid {
+    /// Continuation of an L2CAP message, or an empty PDU.
+    pub const CONTINUATION: u8 = 0b01;
+    /// Start of an L2CAP message, or a complete one.
+    pub const START: u8 = 0b10;
+    /// LL control PDU.
+    pub const CONTROL: u8 = 0b11;
+    pub const MASK: u8 = 0b11;
+}
+
+const DATA_HEADER_NESN: u8 = 1 << 2;
+const DATA_HEADER_SN: u8 = 1 << 3;
+
+/// LL control PDU opcodes, section 2.4.2.
+mod control {
+    pub const CONNECTION_UPDATE_IND: u8 = 0x00;
+    pub const CHANNEL_MAP_IND: u8 = 0x01;
+    pub const TERMINATE_IND: u8 = 0x02;
+    pub const UNKNOWN_RSP: u8 = 0x07;
+    pub const FEATURE_REQ: u8 = 0x08;
+    pub const FEATURE_RSP: u8 = 0x09;
+    pub const VERSION_IND: u8 = 0x0C;
+    pub const PERIPHERAL_FEATURE_REQ: u8 = 0x0E;
+    pub const PING_REQ: u8 = 0x12;
+    pub const PING_RSP: u8 = 0x13;
+    pub const LENGTH_REQ: u8 = 0x14;
+    pub const LENGTH_RSP: u8 = 0x15;
+}
+
+/// Reasons for a disconnection, from the error codes of Vol. 1, Part F.
+pub mod reason {
+    pub const CONNECTION_TIMEOUT: u8 = 0x08;
+    pub const REMOTE_USER_TERMINATED: u8 = 0x13;
+    pub const LOCAL_HOST_TERMINATED: u8 = 0x16;
+    pub const INSTANT_PASSED: u8 = 0x28;
+    pub const FAILED_TO_BE_ESTABLISHED: u8 = 0x3E;
+}
+
+/// Link layer version 4.0.
+const VERSION: u8 = 6;
+/// Company identifier sent in `LL_VERSION_IND`, for none.
+const COMPANY_ID: u16 = 0xFFFF;
+/// Implementation specific subversion sent in `LL_VERSION_IND`.
+const SUBVERSION: u16 = 0;
+
+/// Unit of the connection timing parameters.
+const UNIT_US: u32 = 1250;
+/// Delay between the end of `CONNECT_IND` and the first transmit window.
+const TRANSMIT_WINDOW_DELAY_US: u32 = 1250;
+/// How long before a packet is expected the radio starts listening, to cover
+/// its ramp up.
+const RX_LEAD_US: u32 = 150;
+/// Accuracy of the sleep clock of this device.
+const LOCAL_SCA_PPM: u32 = 50;
+/// Sleep clock accuracy of the central, indexed by the `SCA` field of
+/// `CONNECT_IND`.
+const SCA_PPM: [u32; 8] = [500, 250, 150, 100, 75, 50, 30, 20];
+
+/// Number of data channels.
+const DATA_CHANNELS: u8 = 37;
+
+/// Interface of the link layer to the host.
+pub trait BleLinkLayer<'a> {
+    fn set_client(&self, client: &'a dyn BleLinkLayerClient);
+
+    /// Start connectable advertising with `adv_data` as the advertising data
+    /// and `scan_response` as the scan response data, every `interval_ms`.
+    ///
+    /// If advertising is already started, this updates the data and the
+    /// interval. Returns `BUSY` while connected.
+    fn start_advertising(
+        &self,
+        adv_data: &[u8],
+        scan_response: &[u8],
+        interval_ms: u32,
+    ) -> Result<(), ErrorCode>;
+
+    fn stop_advertising(&self) -> Result<(), ErrorCode>;
+
+    fn is_connected(&self) -> bool;
+
+    /// Queue `data` to be sent in the connection. `start` is whether it is
+    /// the start of an L2CAP message.
+    ///
+    /// Returns `BUSY` if the queue is full, in which case
+    /// [`BleLinkLayerClient::send_done`] is called once there is space.
+    fn send(&self, start: bool, data: &[u8]) -> Result<(), ErrorCode>;
+
+    /// Terminate the connection.
+    fn disconnect(&self) -> Result<(), ErrorCode>;
+}
+
+pub trait BleLinkLayerClient {
+    /// A central connected. Advertising is stopped.
+    fn connected(&self, peer: [u8; ADDRESS_LEN]);
+
+    /// The connection ended, for `reason`.
+    fn disconnected(&self, reason: u8);
+
+    /// Data was received. `start` is whether it is the start of an L2CAP
+    /// message.
+    fn received(&self, start: bool, data: &[u8]);
+
+    /// A queued packet was sent, so there is space in the queue.
+    fn send_done(&self);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Idle,
+    /// Waiting for the next advertising event.
+    AdvertisingWait,
+    /// Advertising on the channel with this index.
+    Advertising(u8),
+    /// Waiting for the next connection event.
+    ConnectionWait,
+    ConnectionEvent,
+    /// Waiting for the radio to stop.
+    Stopping,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Update {
+    Connection {
+        instant: u16,
+        window_size_us: u32,
+        window_offset_us: u32,
+        interval_us: u32,
+        timeout_us: u32,
+    },
+    ChannelMap {
+        instant: u16,
+        channel_map: [u8; 5],
+    },
+}
+
+impl Update {
+    fn instant(&self) -> u16 {
+        match *self {
+            Update::Connection { instant, .. } | Update::ChannelMap { instant, .. } => instant,
+        }
+    }
+}
+
+struct Connection<T: Ticks> {
+    peer: [u8; ADDRESS_LEN],
+    access_address: u32,
+    crc_init: u32,
+    interval_us: u32,
+    timeout_us: u32,
+    /// Sleep clock accuracy of the central.
+    sca_ppm: u32,
+    channel_map: [u8; 5],
+    hop: u8,
+    unmapped_channel: u8,
+    /// Counter of the last connection event.
+    event_counter: u16,
+    /// Anchor point of the last connection event, or when it was expected if
+    /// nothing was received.
+    anchor: T,
+    /// Time between the last received packet and the next anchor point.
+    since_rx_us: u32,
+    /// Listening window of the next connection event.
+    listen_window_us: u32,
+    /// Transmit window of the next connection event, as an offset from the
+    /// last anchor point and a length, after the connection is created or
+    /// updated.
+    transmit_window: Option<(u32, u32)>,
+    /// Whether a packet was received in the connection.
+    established: bool,
+    sn: bool,
+    nesn: bool,
+    /// Whether the last packet sent is the head of the transmit queue.
+    sent_data: bool,
+    /// Whether a packet from the transmit queue was acknowledged in this
+    /// connection event.
+    acknowledged: bool,
+    version_sent: bool,
+    update: Option<Update>,
+    /// The connection ends after this connection event, for this reason.
+    closing: Option<u8>,
+}
+
+impl<T: Ticks> Connection<T> {
+    fn channel_used(&self, channel: u8) -> bool {
+        self.channel_map[channel as usize / 8] & (1 << (channel % 8)) != 0
+    }
+
+    fn used_channels(channel_map: &[u8; 5]) -> u32 {
+        // Only 37 channels exist.
+        channel_map[..4].iter().map(|b| b.count_ones()).sum::<u32>()
+            + (channel_map[4] & 0x1F).count_ones()
+    }
+
+    /// Channel selection algorithm #1, section 4.5.8.2.
+    fn next_channel(&mut self) -> u8 {
+        self.unmapped_channel = (self.unmapped_channel + self.hop) % DATA_CHANNELS;
+        if self.channel_used(self.unmapped_channel) {
+            return self.unmapped_channel;
+        }
+        let remapping_index = self.unmapped_channel as u32 % Self::used_channels(&self.channel_map);
+        (0..DATA_CHANNELS)
+            .filter(|channel| self.channel_used(*channel))
+            .nth(remapping_index as usize)
+            .unwrap_or(0)
+    }
+}
+
+struct AdvertisingData {
+    adv_data: [u8; ADV_DATA_LEN],
+    adv_data_len: usize,
+    scan_response: [u8; ADV_DATA_LEN],
+    scan_response_len: usize,
+}
+
+struct TxQueue {
+    packets: [[u8; DATA_PACKET_LEN]; TX_QUEUE_LEN],
+    head: usize,
+    len: usize,
+}
+
+impl TxQueue {
+    fn push(&mut self, llid: u8, payload: &[u8]) -> Result<(), ErrorCode> {
+        if payload.len() > DATA_PAYLOAD_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        if self.is_full() {
+            return Err(ErrorCode::BUSY);
+        }
+        let packet = &mut self.packets[(self.head + self.len) % TX_QUEUE_LEN];
+        packet[0] = llid;
+        packet[1] = payload.len() as u8;
+        packet[2..2 + payload.len()].copy_from_slice(payload);
+        self.len += 1;
+        Ok(())
+    }
+
+    fn head(&self) -> Option<&[u8]> {
+        (self.len > 0).then(|| {
+            let packet = &self.packets[self.head];
+            &packet[..2 + packet[1] as usize]
+        })
+    }
+
+    fn pop(&mut self) -> Option<[u8; DATA_PACKET_LEN]> {
+        (self.len > 0).then(|| {
+            let packet = self.packets[self.head];
+            self.head = (self.head + 1) % TX_QUEUE_LEN;
+            self.len -= 1;
+            packet
+        })
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == TX_QUEUE_LEN
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+/// Time to transmit a packet of `len` bytes (header and payload) on the LE
+/// 1M PHY, including the preamble, access address and CRC.
+fn airtime_us(len: usize) -> u32 {
+    ((1 + 4 + len + 3) * 8) as u32
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+pub struct LinkLayer<'a, R: BleLinkRadio<'a>, A: Alarm<'a>> {
+    radio: &'a R,
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn BleLinkLayerClient>,
+    /// Static random device address.
+    address: [u8; ADDRESS_LEN],
+    state: Cell<State>,
+    /// Buffer for advertising channel packets.
+    adv_buffer: TakeCell<'static, [u8]>,
+    advertising: MapCell<AdvertisingData>,
+    adv_interval_ms: Cell<u32>,
+    /// State of the pseudo random `advDelay`.
+    random: Cell<u32>,
+    connection: MapCell<Connection<A::Ticks>>,
+    tx_queue: MapCell<TxQueue>,
+    /// Packet received in the current connection event, handled after it.
+    rx_packet: Cell<Option<[u8; DATA_PACKET_LEN]>>,
+    /// Whether the client is waiting for space in the transmit queue.
+    send_blocked: Cell<bool>,
+}
+
+impl<'a, R: BleLinkRadio<'a>, A: Alarm<'a>> LinkLayer<'a, R, A> {
+    /// `address` is the static random address of the device, with the two
+    /// most significant bits of its last byte set. `adv_buffer` must be at
+    /// least `ble_link::MAX_PACKET_LEN` bytes long.
+    pub fn new(
+        radio: &'a R,
+        alarm: &'a A,
+        address: [u8; ADDRESS_LEN],
+        adv_buffer: &'static mut [u8],
+    ) -> Self {
+        Self {
+            radio,
+            alarm,
+            client: OptionalCell::empty(),
+            address,
+            state: Cell::new(State::Idle),
+            adv_buffer: TakeCell::new(adv_buffer),
+            advertising: MapCell::new(AdvertisingData {
+                adv_data: [0; ADV_DATA_LEN],
+                adv_data_len: 0,
+                scan_response: [0; ADV_DATA_LEN],
+                scan_response_len: 0,
+            }),
+            adv_interval_ms: Cell::new(100),
+            random: Cell::new(
+                u32::from_le_bytes([address[0], address[1], address[2], address[3]]) | 1,
+            ),
+            connection: MapCell::empty(),
+            tx_queue: MapCell::new(TxQueue {
+                packets: [[0; DATA_PACKET_LEN]; TX_QUEUE_LEN],
+                head: 0,
+                len: 0,
+            }),
+            rx_packet: Cell::new(None),
+            send_blocked: Cell::new(false),
+        }
+    }
+
+    /// Returns a new pseudo random number, with the Xorshift algorithm.
+    fn random(&self) -> u32 {
+        let mut x = self.random.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random.set(x);
+        x
+    }
+
+    fn schedule_advertising(&self) {
+        // The pseudo random advDelay of 0 to 10 ms avoids repeated collisions
+        // with other advertisers.
+        let delay_ms = self.adv_interval_ms.get() + self.random() % 11;
+        self.state.set(State::AdvertisingWait);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(delay_ms));
+    }
+
+    /// Send `ADV_IND` on the advertising channel with index `channel`.
+    fn advertise(&self, channel: u8) {
+        let (Some(buffer), Some(radio_channel)) = (
+            self.adv_buffer.take(),
+            RadioChannel::from_channel_index(channel),
+        ) else {
+            return;
+        };
+        let len = self.advertising.map_or(0, |advertising| {
+            let payload_len = ADDRESS_LEN + advertising.adv_data_len;
+            buffer[0] = ADV_IND | ADV_HEADER_TXADD;
+            buffer[1] = payload_len as u8;
+            buffer[2..2 + ADDRESS_LEN].copy_from_slice(&self.address);
+            buffer[2 + ADDRESS_LEN..2 + payload_len]
+                .copy_from_slice(&advertising.adv_data[..advertising.adv_data_len]);
+            2 + payload_len
+        });
+        self.radio.set_access_address(
+            ble_link::ADVERTISING_ACCESS_ADDRESS,
+            ble_link::ADVERTISING_CRC_INIT,
+        );
+        self.state.set(State::Advertising(channel));
+        if let Err((_, buffer)) = self.radio.transmit_then_receive(radio_channel, buffer, len) {
+            self.adv_buffer.replace(buffer);
+            self.schedule_advertising();
+        }
+    }
+
+    /// Handle a packet received right after `ADV_IND`.
+    fn advertising_packet_received(&self, packet: &[u8], response: &mut [u8]) -> usize {
+        if packet.len() < 2 {
+            return 0;
+        }
+        let pdu_type = packet[0] & 0x0F;
+        let payload = &packet[2..(2 + packet[1] as usize).min(packet.len())];
+        // Both scan and connection requests are addressed to a random
+        // address with AdvA following the address of the scanner or
+        // initiator.
+        let for_us = packet[0] & ADV_HEADER_RXADD != 0
+            && payload.len() >= 2 * ADDRESS_LEN
+            && payload[ADDRESS_LEN..2 * ADDRESS_LEN] == self.address;
+        if !for_us {
+            return 0;
+        }
+        match pdu_type {
+            SCAN_REQ if payload.len() == 2 * ADDRESS_LEN => {
+                self.advertising.map_or(0, |advertising| {
+                    let payload_len = ADDRESS_LEN + advertising.scan_response_len;
+                    response[0] = SCAN_RSP | ADV_HEADER_TXADD;
+                    response[1] = payload_len as u8;
+                    response[2..2 + ADDRESS_LEN].copy_from_slice(&self.address);
+                    response[2 + ADDRESS_LEN..2 + payload_len].copy_from_slice(
+                        &advertising.scan_response[..advertising.scan_response_len],
+                    );
+                    2 + payload_len
+                })
+            }
+            CONNECT_IND if payload.len() == CONNECT_IND_LEN => {
+                self.connect_ind_received(payload);
+                0
+            }
+            _ => 0,
+        }
+    }
+
+    /// Prepare a connection from the payload of `CONNECT_IND`, which just
+    /// ended. The connection starts once the radio is done.
+    fn connect_ind_received(&self, payload: &[u8]) {
+        let mut peer = [0; ADDRESS_LEN];
+        peer.copy_from_slice(&payload[..ADDRESS_LEN]);
+        let ll_data = &payload[2 * ADDRESS_LEN..];
+        let window_size = ll_data[7] as u32;
+        let window_offset = u16_at(ll_data, 8) as u32;
+        let interval = u16_at(ll_data, 10) as u32;
+        let timeout = u16_at(ll_data, 14) as u32;
+        let mut channel_map = [0; 5];
+        channel_map.copy_from_slice(&ll_data[16..21]);
+        let hop = ll_data[21] & 0x1F;
+        let sca = ll_data[21] >> 5;
+
+        // Ignore requests with invalid parameters, Vol. 6, Part B, section
+        // 2.3.3.1.
+        let valid = (6..=3200).contains(&interval)
+            && (10..=3200).contains(&timeout)
+            && (1..=8).contains(&window_size)
+            && window_offset <= interval
+            && (5..=16).contains(&hop)
+            && Connection::<A::Ticks>::used_channels(&channel_map) >= 2;
+        if !valid {
+            return;
+        }
+        self.connection.put(Connection {
+            peer,
+            access_address: u32::from_le_bytes([ll_data[0], ll_data[1], ll_data[2], ll_data[3]]),
+            crc_init: u32::from_le_bytes([ll_data[4], ll_data[5], ll_data[6], 0]),
+            interval_us: interval * UNIT_US,
+            timeout_us: timeout * 10_000,
+            sca_ppm: SCA_PPM[sca as usize],
+            channel_map,
+            hop,
+            unmapped_channel: 0,
+            event_counter: u16::MAX,
+            // The transmit window is relative to the end of `CONNECT_IND`.
+            anchor: self.alarm.now(),
+            since_rx_us: 0,
+            listen_window_us: 0,
+            transmit_window: Some((
+                TRANSMIT_WINDOW_DELAY_US + window_offset * UNIT_US,
+                window_size * UNIT_US,
+            )),
+            established: false,
+            sn: false,
+            nesn: false,
+            sent_data: false,
+            acknowledged: false,
+            version_sent: false,
+            update: None,
+            closing: None,
+        });
+    }
+
+    fn start_connection(&self) {
+        let Some((peer, access_address, crc_init)) = self
+            .connection
+            .map(|conn| (conn.peer, conn.access_address, conn.crc_init))
+        else {
+            return;
+        };
+        self.radio.set_access_address(access_address, crc_init);
+        self.client.map(|client| client.connected(peer));
+        self.schedule_connection_event();
+    }
+
+    /// Set the alarm for the next connection event, or end the connection if
+    /// the supervision timeout expires before it.
+    fn schedule_connection_event(&self) {
+        let next = self.connection.map(|conn| {
+            let counter = conn.event_counter.wrapping_add(1);
+            if let Some(update) = conn.update {
+                if update.instant() == counter {
+                    conn.update = None;
+                    match update {
+                        Update::Connection {
+                            window_size_us,
+                            window_offset_us,
+                            interval_us,
+                            timeout_us,
+                            ..
+                        } => {
+                            // The transmit window starts after the old
+                            // interval, then the new parameters apply.
+                            conn.transmit_window =
+                                Some((conn.interval_us + window_offset_us, window_size_us));
+                            conn.interval_us = interval_us;
+                            conn.timeout_us = timeout_us;
+                        }
+                        Update::ChannelMap { channel_map, .. } => conn.channel_map = channel_map,
+                    }
+                }
+            }
+
+            let (delay_us, window_us) = conn.transmit_window.unwrap_or((conn.interval_us, 0));
+            // Until a packet is received in it, the transmit window repeats
+            // one interval later.
+            conn.transmit_window = conn
+                .transmit_window
+                .map(|(_, size_us)| (conn.interval_us, size_us));
+            conn.since_rx_us = conn.since_rx_us.saturating_add(delay_us);
+            let timeout_us = if conn.established {
+                conn.timeout_us
+            } else {
+                6 * conn.interval_us
+            };
+            if conn.since_rx_us > timeout_us {
+                return Err(if conn.established {
+                    reason::CONNECTION_TIMEOUT
+                } else {
+                    reason::FAILED_TO_BE_ESTABLISHED
+                });
+            }
+
+            // Listen from before the anchor point to cover the drift of both
+            // sleep clocks since the last synchronization.
+            let widening_us = ((conn.sca_ppm + LOCAL_SCA_PPM) as u64 * conn.since_rx_us as u64
+                / 1_000_000) as u32;
+            let widening_us = widening_us.min(conn.interval_us / 2);
+            conn.listen_window_us = window_us + 2 * widening_us + 2 * RX_LEAD_US;
+
+            let reference = conn.anchor;
+            conn.anchor = reference.wrapping_add(self.alarm.ticks_from_us(delay_us));
+            Ok((reference, delay_us.saturating_sub(widening_us + RX_LEAD_US)))
+        });
+        match next {
+            Some(Ok((reference, dt_us))) => {
+                self.state.set(State::ConnectionWait);
+                self.alarm
+                    .set_alarm(reference, self.alarm.ticks_from_us(dt_us));
+            }
+            Some(Err(reason)) => self.close(reason),
+            None => {}
+        }
+    }
+
+    fn connection_event(&self) {
+        let Some((channel, window_us)) = self.connection.map(|conn| {
+            conn.event_counter = conn.event_counter.wrapping_add(1);
+            conn.acknowledged = false;
+            (conn.next_channel(), conn.listen_window_us)
+        }) else {
+            return;
+        };
+        self.state.set(State::ConnectionEvent);
+        let res = RadioChannel::from_channel_index(channel)
+            .ok_or(ErrorCode::FAIL)
+            .and_then(|channel| self.radio.receive_then_transmit(channel, window_us));
+        if res.is_err() {
+            // Handle it like an event without any packet from the central.
+            self.connection_event_done();
+        }
+    }
+
+    /// Handle a data channel packet, and build the answer.
+    fn connection_packet_received(&self, packet: &[u8], response: &mut [u8]) -> usize {
+        if packet.len() < 2 {
+            return 0;
+        }
+        let now = self.alarm.now();
+        let len = packet[1] as usize;
+        if len > DATA_PAYLOAD_LEN || 2 + len > packet.len() {
+            return 0;
+        }
+        self.connection.map_or(0, |conn| {
+            // The anchor point is the start of the packet from the central.
+            conn.anchor = now.wrapping_sub(self.alarm.ticks_from_us(airtime_us(2 + len)));
+            conn.since_rx_us = 0;
+            conn.established = true;
+            conn.transmit_window = None;
+
+            let header = packet[0];
+            if (header & DATA_HEADER_NESN != 0) != conn.sn {
+                // The central acknowledged the last packet.
+                conn.sn = !conn.sn;
+                if conn.sent_data {
+                    conn.sent_data = false;
+                    conn.acknowledged = true;
+                    let sent = self.tx_queue.map(|queue| queue.pop()).flatten();
+                    if let Some(sent) = sent {
+                        if sent[0] & llid::MASK == llid::CONTROL
+                            && sent[2] == control::TERMINATE_IND
+                        {
+                            conn.closing = Some(reason::LOCAL_HOST_TERMINATED);
+                        }
+                    }
+                }
+            }
+            if (header & DATA_HEADER_SN != 0) == conn.nesn {
+                // A new packet. Only acknowledge it if it can be handled
+                // after the event, including queuing an answer to a control
+                // PDU, otherwise the central sends it again.
+                let queue_full = self.tx_queue.map_or(true, |queue| queue.is_full());
+                if len == 0 {
+                    conn.nesn = !conn.nesn;
+                } else if self.rx_packet.get().is_none() && !queue_full {
+                    let mut rx_packet = [0; DATA_PACKET_LEN];
+                    rx_packet[..2 + len].copy_from_slice(&packet[..2 + len]);
+                    self.rx_packet.set(Some(rx_packet));
+                    conn.nesn = !conn.nesn;
+                }
+            }
+
+            // Answer with the head of the transmit queue, or an empty PDU.
+            // Without an acknowledgement, this sends the same packet again.
+            response[0] = llid::CONTINUATION;
+            response[1] = 0;
+            let mut response_len = 2;
+            if conn.closing.is_none() {
+                self.tx_queue.map(|queue| {
+                    if let Some(head) = queue.head() {
+                        response[..head.len()].copy_from_slice(head);
+                        response_len = head.len();
+                        conn.sent_data = true;
+                    }
+                });
+            }
+            if conn.nesn {
+                response[0] |= DATA_HEADER_NESN;
+            }
+            if conn.sn {
+                response[0] |= DATA_HEADER_SN;
+            }
+            response_len
+        })
+    }
+
+    fn connection_event_done(&self) {
+        if let Some(packet) = self.rx_packet.take() {
+            let len = packet[1] as usize;
+            let payload = &packet[2..2 + len];
+            match packet[0] & llid::MASK {
+                llid::CONTROL => self.control_received(payload),
+                llid::START => {
+                    self.client.map(|client| client.received(true, payload));
+                }
+                llid::CONTINUATION => {
+                    self.client.map(|client| client.received(false, payload));
+                }
+                _ => {}
+            }
+        }
+
+        let (acknowledged, closing) = self
+            .connection
+            .map_or((false, None), |conn| (conn.acknowledged, conn.closing));
+        if let Some(reason) = closing {
+            self.close(reason);
+            return;
+        }
+        self.schedule_connection_event();
+        if acknowledged && self.send_blocked.take() {
+            self.client.map(|client| client.send_done());
+        }
+    }
+
+    /// Handle an LL control PDU.
+    fn control_received(&self, payload: &[u8]) {
+        let Some(&opcode) = payload.first() else {
+            return;
+        };
+        let mut answer = [0; 9];
+        let answer_len = match opcode {
+            control::CONNECTION_UPDATE_IND if payload.len() == 12 => {
+                self.update_received(Update::Connection {
+                    window_size_us: payload[1] as u32 * UNIT_US,
+                    window_offset_us: u16_at(payload, 2) as u32 * UNIT_US,
+                    interval_us: u16_at(payload, 4) as u32 * UNIT_US,
+                    timeout_us: u16_at(payload, 8) as u32 * 10_000,
+                    instant: u16_at(payload, 10),
+                });
+                0
+            }
+            control::CHANNEL_MAP_IND if payload.len() == 8 => {
+                let mut channel_map = [0; 5];
+                channel_map.copy_from_slice(&payload[1..6]);
+                self.update_received(Update::ChannelMap {
+                    channel_map,
+                    instant: u16_at(payload, 6),
+                });
+                0
+            }
+            control::TERMINATE_IND if payload.len() == 2 => {
+                self.connection.map(|conn| conn.closing = Some(payload[1]));
+                0
+            }
+            control::FEATURE_REQ | control::PERIPHERAL_FEATURE_REQ => {
+                // No optional features are supported.
+                answer[0] = control::FEATURE_RSP;
+                9
+            }
+            control::VERSION_IND => {
+                // The version is only sent once in a connection.
+                if self.connection.map_or(true, |conn| conn.version_sent) {
+                    0
+                } else {
+                    self.connection.map(|conn| conn.version_sent = true);
+                    answer[0] = control::VERSION_IND;
+                    answer[1] = VERSION;
+                    answer[2..4].copy_from_slice(&COMPANY_ID.to_le_bytes());
+                    answer[4..6].copy_from_slice(&SUBVERSION.to_le_bytes());
+                    6
+                }
+            }
+            control::PING_REQ => {
+                answer[0] = control::PING_RSP;
+                1
+            }
+            control::LENGTH_REQ => {
+                // Without the data length extension, the lengths are the
+                // minimum ones.
+                answer[0] = control::LENGTH_RSP;
+                for i in 0..2 {
+                    answer[1 + 4 * i] = DATA_PAYLOAD_LEN as u8;
+                    answer[3 + 4 * i..5 + 4 * i].copy_from_slice(&328u16.to_le_bytes());
+                }
+                9
+            }
+            // Answers to procedures this device never starts.
+            control::UNKNOWN_RSP
+            | control::FEATURE_RSP
+            | control::PING_RSP
+            | control::LENGTH_RSP => 0,
+            _ => {
+                answer[0] = control::UNKNOWN_RSP;
+                answer[1] = opcode;
+                2
+            }
+        };
+        if answer_len > 0 {
+            // There is space for the answer, the packet was only accepted
+            // with space in the queue.
+            let _ = self
+                .tx_queue
+                .map(|queue| queue.push(llid::CONTROL, &answer[..answer_len]));
+        }
+    }
+
+    fn update_received(&self, update: Update) {
+        self.connection.map(|conn| {
+            // The instant must be in the future, section 5.5.2.
+            let events_to_instant = update.instant().wrapping_sub(conn.event_counter);
+            if events_to_instant == 0 || events_to_instant >= 0x8000 {
+                conn.closing = Some(reason::INSTANT_PASSED);
+            } else {
+                conn.update = Some(update);
+            }
+        });
+    }
+
+    fn close(&self, reason: u8) {
+        let _ = self.alarm.disarm();
+        self.connection.take();
+        self.rx_packet.set(None);
+        self.tx_queue.map(|queue| queue.clear());
+        self.send_blocked.set(false);
+        self.state.set(State::Idle);
+        self.client.map(|client| client.disconnected(reason));
+    }
+}
+
+impl<'a, R: BleLinkRadio<'a>, A: Alarm<'a>> BleLinkLayer<'a> for LinkLayer<'a, R, A> {
+    fn set_client(&self, client: &'a dyn BleLinkLayerClient) {
+        self.client.set(client);
+    }
+
+    fn start_advertising(
+        &self,
+        adv_data: &[u8],
+        scan_response: &[u8],
+        interval_ms: u32,
+    ) -> Result<(), ErrorCode> {
+        if adv_data.len() > ADV_DATA_LEN || scan_response.len() > ADV_DATA_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        if !matches!(
+            self.state.get(),
+            State::Idle | State::AdvertisingWait | State::Advertising(_)
+        ) {
+            return Err(ErrorCode::BUSY);
+        }
+        self.advertising.map(|advertising| {
+            advertising.adv_data[..adv_data.len()].copy_from_slice(adv_data);
+            advertising.adv_data_len = adv_data.len();
+            advertising.scan_response[..scan_response.len()].copy_from_slice(scan_response);
+            advertising.scan_response_len = scan_response.len();
+        });
+        // Section 4.4.2.2.1.
+        self.adv_interval_ms.set(interval_ms.clamp(20, 10_240));
+        if self.state.get() == State::Idle {
+            self.advertise(37);
+        }
+        Ok(())
+    }
+
+    fn stop_advertising(&self) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::AdvertisingWait => {
+                let _ = self.alarm.disarm();
+                self.state.set(State::Idle);
+                Ok(())
+            }
+            State::Advertising(_) => {
+                self.state.set(State::Stopping);
+                self.radio.stop();
+                Ok(())
+            }
+            State::Idle | State::Stopping => Err(ErrorCode::ALREADY),
+            State::ConnectionWait | State::ConnectionEvent => Err(ErrorCode::BUSY),
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        matches!(
+            self.state.get(),
+            State::ConnectionWait | State::ConnectionEvent
+        )
+    }
+
+    fn send(&self, start: bool, data: &[u8]) -> Result<(), ErrorCode> {
+        if !self.is_connected() {
+            return Err(ErrorCode::OFF);
+        }
+        let llid = if start {
+            llid::START
+        } else {
+            llid::CONTINUATION
+        };
+        let res = self
+            .tx_queue
+            .map_or(Err(ErrorCode::FAIL), |queue| queue.push(llid, data));
+        if res == Err(ErrorCode::BUSY) {
+            self.send_blocked.set(true);
+        }
+        res
+    }
+
+    fn disconnect(&self) -> Result<(), ErrorCode> {
+        if !self.is_connected() {
+            return Err(ErrorCode::OFF);
+        }
+        // The connection ends once the central acknowledges the
+        // `LL_TERMINATE_IND`.
+        self.tx_queue.map_or(Err(ErrorCode::FAIL), |queue| {
+            queue.push(
+                llid::CONTROL,
+                &[control::TERMINATE_IND, reason::REMOTE_USER_TERMINATED],
+            )
+        })
+    }
+}
+
+impl<'a, R: BleLinkRadio<'a>, A: Alarm<'a>> BleLinkRadioClient for LinkLayer<'a, R, A> {
+    fn packet_received(&self, packet: &[u8], response: &mut [u8]) -> usize {
+        match self.state.get() {
+            State::Advertising(_) => self.advertising_packet_received(packet, response),
+            State::ConnectionEvent => self.connection_packet_received(packet, response),
+            _ => 0,
+        }
+    }
+
+    fn transmit_done(&self, packet: &'static mut [u8], _result: Result<(), ErrorCode>) {
+        self.adv_buffer.replace(packet);
+        match self.state.get() {
+            State::Advertising(_) if self.connection.is_some() => self.start_connection(),
+            State::Advertising(channel) if channel < 39 => self.advertise(channel + 1),
+            State::Advertising(_) => self.schedule_advertising(),
+            State::Stopping => {
+                // A connection requested just before stopping is ignored.
+                self.connection.take();
+                self.state.set(State::Idle);
+            }
+            _ => {}
+        }
+    }
+
+    fn receive_done(&self, _result: Result<(), ErrorCode>) {
+        match self.state.get() {
+            State::ConnectionEvent => self.connection_event_done(),
+            State::Stopping => self.state.set(State::Idle),
+            _ => {}
+        }
+    }
+}
+
+impl<'a, R: BleLinkRadio<'a>, A: Alarm<'a>> AlarmClient for LinkLayer<'a, R, A> {
+    fn alarm(&self) {
+        match self.state.get() {
+            State::AdvertisingWait => self.advertise(37),
+            State::ConnectionWait => self.connection_event(),
+            _ => {}
+        }
+    }
+}
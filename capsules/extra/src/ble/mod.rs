@@ -0,0 +1,18 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Bluetooth Low Energy peripheral with a GATT server.
+//!
+//! - [`link_layer`]: advertising and connections, on a radio implementing
+//!   `kernel::hil::ble_link::BleLinkRadio`.
+//! - [`att`]: the attribute database and the attribute protocol.
+//! - [`gatt_server`]: L2CAP, and the system call driver for processes to
+//!   register services and characteristics.
+
+pub mod att;
+pub mod gatt_server;
+pub mod link_layer;
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,877 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Host tests of the link layer and the attribute server.
+//!
+//! The link layer runs on a simulated radio and alarm. The tests play the
+//! role of the central: they complete the radio operations the link layer
+//! starts with the packets the central sends, at the time it sends them, and
+//! check the answers and the timing and channels of the radio operations.
+
+extern crate std;
+
+use core::cell::{Cell, RefCell};
+use std::boxed::Box;
+use std::vec;
+use std::vec::Vec;
+
+use kernel::ErrorCode;
+use kernel::hil::ble_link::{self, BleLinkRadio, BleLinkRadioClient};
+use kernel::hil::time::{Alarm, AlarmClient, Freq1MHz, Ticks, Ticks32, Time};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+use super::att::{AttributeDatabase, Uuid, properties};
+use super::link_layer::{ADDRESS_LEN, BleLinkLayer, BleLinkLayerClient, LinkLayer, reason};
+
+const ADDRESS: [u8; ADDRESS_LEN] = [0x11, 0x22, 0x33, 0x44, 0x55, 0xC6];
+const CENTRAL_ADDRESS: [u8; ADDRESS_LEN] = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06];
+
+/// An alarm with a time in microseconds only advanced by the tests.
+struct SimAlarm {
+    now: Cell<u32>,
+    expiration: Cell<Option<u32>>,
+    client: OptionalCell<&'static dyn AlarmClient>,
+}
+
+impl SimAlarm {
+    /// Advance the time to the alarm, unless it is in the past, and fire it.
+    /// Returns when the alarm was set to fire.
+    fn fire(&self) -> u32 {
+        let expiration = self.expiration.take().expect("the alarm is not armed");
+        if expiration.wrapping_sub(self.now.get()) < 0x8000_0000 {
+            self.now.set(expiration);
+        }
+        self.client.map(|client| client.alarm());
+        expiration
+    }
+}
+
+impl Time for SimAlarm {
+    type Frequency = Freq1MHz;
+    type Ticks = Ticks32;
+
+    fn now(&self) -> Ticks32 {
+        self.now.get().into()
+    }
+}
+
+impl Alarm<'static> for SimAlarm {
+    fn set_alarm_client(&self, client: &'static dyn AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn set_alarm(&self, reference: Ticks32, dt: Ticks32) {
+        self.expiration
+            .set(Some(reference.wrapping_add(dt).into_u32()));
+    }
+
+    fn get_alarm(&self) -> Ticks32 {
+        self.expiration.get().unwrap_or(0).into()
+    }
+
+    fn disarm(&self) -> Result<(), ErrorCode> {
+        self.expiration.set(None);
+        Ok(())
+    }
+
+    fn is_armed(&self) -> bool {
+        self.expiration.get().is_some()
+    }
+
+    fn minimum_dt(&self) -> Ticks32 {
+        1.into()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Operation {
+    TransmitThenReceive { channel: u32, packet: Vec<u8> },
+    ReceiveThenTransmit { channel: u32, window_us: u32 },
+}
+
+/// A radio which records the operation the link layer starts, for the test
+/// to complete.
+struct SimRadio {
+    client: OptionalCell<&'static dyn BleLinkRadioClient>,
+    operation: RefCell<Option<Operation>>,
+    buffer: TakeCell<'static, [u8]>,
+    access_address: Cell<u32>,
+}
+
+impl SimRadio {
+    fn take_operation(&self) -> Operation {
+        self.operation
+            .borrow_mut()
+            .take()
+            .expect("no radio operation")
+    }
+
+    fn answer(&self, received: Option<&[u8]>) -> Option<Vec<u8>> {
+        received.and_then(|packet| {
+            let mut response = [0; ble_link::MAX_PACKET_LEN];
+            let len = self
+                .client
+                .map_or(0, |client| client.packet_received(packet, &mut response));
+            (len > 0).then(|| response[..len].to_vec())
+        })
+    }
+
+    /// Complete a `transmit_then_receive` operation, with the packet
+    /// received after the transmission if any. Returns the answer.
+    fn finish_transmit(&self, received: Option<&[u8]>) -> Option<Vec<u8>> {
+        let answer = self.answer(received);
+        let buffer = self.buffer.take().expect("no transmission");
+        self.client
+            .map(|client| client.transmit_done(buffer, Ok(())));
+        answer
+    }
+
+    /// Complete a `receive_then_transmit` operation, with the packet
+    /// received if any. Returns the answer.
+    fn finish_receive(&self, received: Option<&[u8]>) -> Option<Vec<u8>> {
+        let answer = self.answer(received);
+        let result = match received {
+            Some(_) => Ok(()),
+            None => Err(ErrorCode::NOACK),
+        };
+        self.client.map(|client| client.receive_done(result));
+        answer
+    }
+}
+
+impl BleLinkRadio<'static> for SimRadio {
+    fn set_link_client(&self, client: &'static dyn BleLinkRadioClient) {
+        self.client.set(client);
+    }
+
+    fn set_access_address(&self, access_address: u32, _crc_init: u32) {
+        self.access_address.set(access_address);
+    }
+
+    fn transmit_then_receive(
+        &self,
+        channel: kernel::hil::ble_advertising::RadioChannel,
+        packet: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        self.operation.replace(Some(Operation::TransmitThenReceive {
+            channel: channel.get_channel_index(),
+            packet: packet[..len].to_vec(),
+        }));
+        self.buffer.replace(packet);
+        Ok(())
+    }
+
+    fn receive_then_transmit(
+        &self,
+        channel: kernel::hil::ble_advertising::RadioChannel,
+        window_us: u32,
+    ) -> Result<(), ErrorCode> {
+        self.operation.replace(Some(Operation::ReceiveThenTransmit {
+            channel: channel.get_channel_index(),
+            window_us,
+        }));
+        Ok(())
+    }
+
+    fn stop(&self) {
+        match self.operation.take() {
+            Some(Operation::TransmitThenReceive { .. }) => {
+                let buffer = self.buffer.take().unwrap();
+                self.client
+                    .map(|client| client.transmit_done(buffer, Err(ErrorCode::CANCEL)));
+            }
+            Some(Operation::ReceiveThenTransmit { .. }) => {
+                self.client
+                    .map(|client| client.receive_done(Err(ErrorCode::CANCEL)));
+            }
+            None => {}
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum LinkEvent {
+    Connected([u8; ADDRESS_LEN]),
+    Disconnected(u8),
+    Received(bool, Vec<u8>),
+    SendDone,
+}
+
+struct TestClient {
+    events: RefCell<Vec<LinkEvent>>,
+}
+
+impl TestClient {
+    fn take_events(&self) -> Vec<LinkEvent> {
+        self.events.take()
+    }
+}
+
+impl BleLinkLayerClient for TestClient {
+    fn connected(&self, peer: [u8; ADDRESS_LEN]) {
+        self.events.borrow_mut().push(LinkEvent::Connected(peer));
+    }
+
+    fn disconnected(&self, reason: u8) {
+        self.events
+            .borrow_mut()
+            .push(LinkEvent::Disconnected(reason));
+    }
+
+    fn received(&self, start: bool, data: &[u8]) {
+        self.events
+            .borrow_mut()
+            .push(LinkEvent::Received(start, data.to_vec()));
+    }
+
+    fn send_done(&self) {
+        self.events.borrow_mut().push(LinkEvent::SendDone);
+    }
+}
+
+fn airtime_us(len: usize) -> u32 {
+    ((1 + 4 + len + 3) * 8) as u32
+}
+
+/// The central side of a connection.
+struct Central {
+    access_address: u32,
+    interval_us: u32,
+    hop: u8,
+    channel_map: [u8; 5],
+    unmapped_channel: u8,
+    event_counter: u16,
+    /// When the central sends its packet in the next connection event.
+    next_anchor: u32,
+    sn: bool,
+    nesn: bool,
+}
+
+impl Central {
+    const INTERVAL: u16 = 24;
+    const TIMEOUT: u16 = 100;
+    const HOP: u8 = 7;
+
+    fn new() -> Self {
+        Self {
+            access_address: 0x5065_A3C1,
+            interval_us: Self::INTERVAL as u32 * 1250,
+            hop: Self::HOP,
+            channel_map: [0xFF, 0xFF, 0xFF, 0xFF, 0x1F],
+            unmapped_channel: 0,
+            event_counter: 0,
+            next_anchor: 0,
+            sn: false,
+            nesn: false,
+        }
+    }
+
+    fn connect_ind(&self) -> Vec<u8> {
+        let mut packet = vec![0x05 | 0x80, 34];
+        packet.extend_from_slice(&CENTRAL_ADDRESS);
+        packet.extend_from_slice(&ADDRESS);
+        packet.extend_from_slice(&self.access_address.to_le_bytes());
+        packet.extend_from_slice(&[0x12, 0x34, 0x56]);
+        // Transmit window size and offset.
+        packet.push(2);
+        packet.extend_from_slice(&0u16.to_le_bytes());
+        packet.extend_from_slice(&Self::INTERVAL.to_le_bytes());
+        // Latency.
+        packet.extend_from_slice(&0u16.to_le_bytes());
+        packet.extend_from_slice(&Self::TIMEOUT.to_le_bytes());
+        packet.extend_from_slice(&self.channel_map);
+        // Hop and a sleep clock accuracy of 50 ppm.
+        packet.push(self.hop | (5 << 5));
+        packet
+    }
+
+    fn next_channel(&mut self) -> u32 {
+        self.unmapped_channel = (self.unmapped_channel + self.hop) % 37;
+        let used: Vec<u8> = (0..37)
+            .filter(|channel| self.channel_map[*channel as usize / 8] & (1 << (channel % 8)) != 0)
+            .collect();
+        if used.contains(&self.unmapped_channel) {
+            self.unmapped_channel as u32
+        } else {
+            used[self.unmapped_channel as usize % used.len()] as u32
+        }
+    }
+
+    fn packet(&self, llid: u8, payload: &[u8]) -> Vec<u8> {
+        let mut header = llid;
+        if self.nesn {
+            header |= 1 << 2;
+        }
+        if self.sn {
+            header |= 1 << 3;
+        }
+        let mut packet = vec![header, payload.len() as u8];
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn empty(&self) -> Vec<u8> {
+        self.packet(0b01, &[])
+    }
+
+    /// Handle the answer of the peripheral. Returns its LLID and payload if
+    /// it is new data. A packet which isn't `accepted` isn't acknowledged.
+    fn process_answer(&mut self, answer: &[u8], accepted: bool) -> Option<(u8, Vec<u8>)> {
+        let nesn = answer[0] & (1 << 2) != 0;
+        let sn = answer[0] & (1 << 3) != 0;
+        if nesn != self.sn {
+            self.sn = !self.sn;
+        }
+        if sn == self.nesn && accepted {
+            self.nesn = !self.nesn;
+            if answer[1] > 0 {
+                return Some((answer[0] & 0b11, answer[2..].to_vec()));
+            }
+        }
+        None
+    }
+}
+
+struct Harness {
+    alarm: &'static SimAlarm,
+    radio: &'static SimRadio,
+    link: &'static LinkLayer<'static, SimRadio, SimAlarm>,
+    client: &'static TestClient,
+}
+
+impl Harness {
+    fn new() -> Self {
+        let alarm: &'static SimAlarm = Box::leak(Box::new(SimAlarm {
+            now: Cell::new(1_000_000),
+            expiration: Cell::new(None),
+            client: OptionalCell::empty(),
+        }));
+        let radio: &'static SimRadio = Box::leak(Box::new(SimRadio {
+            client: OptionalCell::empty(),
+            operation: RefCell::new(None),
+            buffer: TakeCell::empty(),
+            access_address: Cell::new(0),
+        }));
+        let buffer = Box::leak(Box::new([0; ble_link::MAX_PACKET_LEN]));
+        let link: &'static LinkLayer<'static, SimRadio, SimAlarm> =
+            Box::leak(Box::new(LinkLayer::new(radio, alarm, ADDRESS, buffer)));
+        let client: &'static TestClient = Box::leak(Box::new(TestClient {
+            events: RefCell::new(Vec::new()),
+        }));
+        radio.set_link_client(link);
+        alarm.set_alarm_client(link);
+        link.set_client(client);
+        Self {
+            alarm,
+            radio,
+            link,
+            client,
+        }
+    }
+
+    /// Advertise and accept a connection from the central.
+    fn connect(&self, central: &mut Central) {
+        self.link.start_advertising(&[2, 1, 6], &[], 100).unwrap();
+        assert!(matches!(
+            self.radio.take_operation(),
+            Operation::TransmitThenReceive { channel: 37, .. }
+        ));
+        let connect_ind_end = self.alarm.now.get() + 400;
+        self.alarm.now.set(connect_ind_end);
+        assert_eq!(
+            self.radio.finish_transmit(Some(&central.connect_ind())),
+            None
+        );
+        assert_eq!(
+            self.client.take_events(),
+            [LinkEvent::Connected(CENTRAL_ADDRESS)]
+        );
+        assert_eq!(self.radio.access_address.get(), central.access_address);
+        // The central sends in the middle of the transmit window.
+        central.next_anchor = connect_ind_end + 1250 + 1000;
+    }
+
+    /// Run a connection event in which the central sends an empty PDU.
+    fn event_empty(&self, central: &mut Central) -> Option<Vec<u8>> {
+        let packet = central.empty();
+        self.event(central, Some(&packet))
+    }
+
+    /// Run a connection event in which the central sends `payload`.
+    fn event_packet(&self, central: &mut Central, llid: u8, payload: &[u8]) -> Option<Vec<u8>> {
+        let packet = central.packet(llid, payload);
+        self.event(central, Some(&packet))
+    }
+
+    /// Run a connection event in which the central sends `packet`, if any.
+    /// Returns the answer of the peripheral.
+    fn event(&self, central: &mut Central, packet: Option<&[u8]>) -> Option<Vec<u8>> {
+        let start = self.alarm.fire();
+        let Operation::ReceiveThenTransmit { channel, window_us } = self.radio.take_operation()
+        else {
+            panic!("not a connection event");
+        };
+        let anchor = central.next_anchor;
+        central.next_anchor = anchor.wrapping_add(central.interval_us);
+        central.event_counter = central.event_counter.wrapping_add(1);
+        assert_eq!(channel, central.next_channel());
+        // The peripheral listens when the central sends.
+        assert!(start <= anchor, "listening starts after the anchor point");
+        assert!(
+            start + window_us >= anchor,
+            "listening ends before the anchor point"
+        );
+        match packet {
+            Some(packet) => {
+                self.alarm.now.set(anchor + airtime_us(packet.len()));
+                self.radio.finish_receive(Some(packet))
+            }
+            None => {
+                self.alarm.now.set(start + window_us);
+                self.radio.finish_receive(None)
+            }
+        }
+    }
+}
+
+#[test]
+fn advertises_on_all_channels() {
+    let h = Harness::new();
+    h.link.start_advertising(&[2, 1, 6], &[], 100).unwrap();
+    for channel in [37, 38, 39] {
+        let mut expected = vec![0x40, 9];
+        expected.extend_from_slice(&ADDRESS);
+        expected.extend_from_slice(&[2, 1, 6]);
+        assert_eq!(
+            h.radio.take_operation(),
+            Operation::TransmitThenReceive {
+                channel,
+                packet: expected,
+            }
+        );
+        assert_eq!(
+            h.radio.access_address.get(),
+            ble_link::ADVERTISING_ACCESS_ADDRESS
+        );
+        assert_eq!(h.radio.finish_transmit(None), None);
+    }
+
+    // The next advertising event is after the interval and a random delay of
+    // up to 10 ms.
+    let now = h.alarm.now.get();
+    let next = h.alarm.fire();
+    assert!((100_000..=110_000).contains(&next.wrapping_sub(now)));
+    assert!(matches!(
+        h.radio.take_operation(),
+        Operation::TransmitThenReceive { channel: 37, .. }
+    ));
+
+    h.link.stop_advertising().unwrap();
+    assert!(!h.alarm.is_armed());
+    assert_eq!(h.link.stop_advertising(), Err(ErrorCode::ALREADY));
+}
+
+#[test]
+fn answers_scan_requests_for_its_address() {
+    let h = Harness::new();
+    h.link
+        .start_advertising(&[2, 1, 6], &[3, 9, b'h', b'i'], 100)
+        .unwrap();
+    h.radio.take_operation();
+
+    let mut scan_request = vec![0x03 | 0x80, 12];
+    scan_request.extend_from_slice(&CENTRAL_ADDRESS);
+    scan_request.extend_from_slice(&ADDRESS);
+    let mut expected = vec![0x44, 10];
+    expected.extend_from_slice(&ADDRESS);
+    expected.extend_from_slice(&[3, 9, b'h', b'i']);
+    assert_eq!(h.radio.finish_transmit(Some(&scan_request)), Some(expected));
+
+    // A request for another advertiser is ignored.
+    h.radio.take_operation();
+    scan_request[8] ^= 0xFF;
+    assert_eq!(h.radio.finish_transmit(Some(&scan_request)), None);
+}
+
+#[test]
+fn connection_follows_the_central() {
+    let h = Harness::new();
+    let mut central = Central::new();
+    h.connect(&mut central);
+    assert!(h.link.is_connected());
+
+    // Empty packets are exchanged and acknowledged, on the channels and at
+    // the times the central uses.
+    for _ in 0..50 {
+        let answer = h.event_empty(&mut central).unwrap();
+        assert_eq!(answer[1], 0);
+        assert_eq!(central.process_answer(&answer, true), None);
+    }
+    assert!(h.client.take_events().is_empty());
+    assert_eq!(
+        h.link.start_advertising(&[], &[], 100),
+        Err(ErrorCode::BUSY)
+    );
+}
+
+#[test]
+fn data_is_acknowledged_and_retransmitted() {
+    let h = Harness::new();
+    let mut central = Central::new();
+    h.connect(&mut central);
+
+    h.link.send(true, b"hello").unwrap();
+    let answer = h.event_empty(&mut central).unwrap();
+    assert_eq!(&answer[2..], b"hello");
+    assert_eq!(answer[0] & 0b11, 0b10);
+    // The central doesn't acknowledge it, so it is sent again.
+    central.process_answer(&answer, false);
+    let again = h.event_empty(&mut central).unwrap();
+    // Same sequence number and payload, only the acknowledgement changes.
+    assert_eq!(again[0] & 0b1011, answer[0] & 0b1011);
+    assert_eq!(again[1..], answer[1..]);
+    assert_eq!(
+        central.process_answer(&again, true),
+        Some((0b10, b"hello".to_vec()))
+    );
+    // Once acknowledged, the queue is empty.
+    let answer = h.event_empty(&mut central).unwrap();
+    assert_eq!(answer[1], 0);
+    central.process_answer(&answer, true);
+
+    // Data from the central is acknowledged and passed up after the event.
+    let answer = h.event_packet(&mut central, 0b10, b"world").unwrap();
+    assert_eq!(
+        h.client.take_events(),
+        [LinkEvent::Received(true, b"world".to_vec())]
+    );
+    let sn_before = central.sn;
+    central.process_answer(&answer, true);
+    assert_ne!(central.sn, sn_before);
+}
+
+#[test]
+fn full_queue_reports_send_done() {
+    let h = Harness::new();
+    let mut central = Central::new();
+    h.connect(&mut central);
+
+    for i in 0..4 {
+        h.link.send(true, &[i]).unwrap();
+    }
+    assert_eq!(h.link.send(true, &[4]), Err(ErrorCode::BUSY));
+    let answer = h.event_empty(&mut central).unwrap();
+    central.process_answer(&answer, true);
+    assert!(h.client.take_events().is_empty());
+    // The acknowledgement frees space in the queue.
+    let answer = h.event_empty(&mut central).unwrap();
+    assert_eq!(&answer[2..], &[1]);
+    assert_eq!(h.client.take_events(), [LinkEvent::SendDone]);
+    h.link.send(true, &[4]).unwrap();
+}
+
+#[test]
+fn supervision_timeout_ends_the_connection() {
+    let h = Harness::new();
+    let mut central = Central::new();
+    h.connect(&mut central);
+    let answer = h.event_empty(&mut central).unwrap();
+    central.process_answer(&answer, true);
+
+    // The central disappears, and the peripheral keeps listening with wider
+    // windows until the supervision timeout of 1 s.
+    let timeout_events = (Central::TIMEOUT as u32 * 10_000) / central.interval_us;
+    let mut window = 0;
+    for _ in 0..timeout_events {
+        let start = h.alarm.fire();
+        let Operation::ReceiveThenTransmit { channel, window_us } = h.radio.take_operation() else {
+            panic!("not a connection event");
+        };
+        assert_eq!(channel, central.next_channel());
+        assert!(window_us >= window);
+        window = window_us;
+        h.alarm.now.set(start + window_us);
+        h.radio.finish_receive(None);
+    }
+    assert_eq!(
+        h.client.take_events(),
+        [LinkEvent::Disconnected(reason::CONNECTION_TIMEOUT)]
+    );
+    assert!(!h.link.is_connected());
+    assert!(!h.alarm.is_armed());
+}
+
+#[test]
+fn connection_not_established_after_six_intervals() {
+    let h = Harness::new();
+    let mut central = Central::new();
+    h.connect(&mut central);
+    for _ in 0..6 {
+        h.event(&mut central, None);
+    }
+    assert_eq!(
+        h.client.take_events(),
+        [LinkEvent::Disconnected(reason::FAILED_TO_BE_ESTABLISHED)]
+    );
+}
+
+#[test]
+fn channel_map_update_applies_at_instant() {
+    let h = Harness::new();
+    let mut central = Central::new();
+    h.connect(&mut central);
+
+    // Only use channels 0 to 7 from 3 events later.
+    let instant = central.event_counter.wrapping_add(3);
+    let mut payload = vec![0x01, 0xFF, 0, 0, 0, 0];
+    payload.extend_from_slice(&instant.to_le_bytes());
+    let answer = h.event_packet(&mut central, 0b11, &payload).unwrap();
+    central.process_answer(&answer, true);
+    for _ in 0..20 {
+        if central.event_counter == instant {
+            central.channel_map = [0xFF, 0, 0, 0, 0];
+        }
+        // `event` checks the channel.
+        let answer = h.event_empty(&mut central).unwrap();
+        central.process_answer(&answer, true);
+    }
+    assert!(h.link.is_connected());
+}
+
+#[test]
+fn connection_update_applies_at_instant() {
+    let h = Harness::new();
+    let mut central = Central::new();
+    h.connect(&mut central);
+
+    // Double the interval 4 events later, with the new anchor 2.5 ms into
+    // the transmit window.
+    let instant = central.event_counter.wrapping_add(4);
+    let mut payload = vec![0x00, 4];
+    payload.extend_from_slice(&1u16.to_le_bytes());
+    payload.extend_from_slice(&(2 * Central::INTERVAL).to_le_bytes());
+    payload.extend_from_slice(&0u16.to_le_bytes());
+    payload.extend_from_slice(&Central::TIMEOUT.to_le_bytes());
+    payload.extend_from_slice(&instant.to_le_bytes());
+    let answer = h.event_packet(&mut central, 0b11, &payload).unwrap();
+    central.process_answer(&answer, true);
+    for _ in 0..10 {
+        if central.event_counter == instant {
+            central.next_anchor += 1250 + 2500;
+            central.interval_us *= 2;
+        }
+        let answer = h.event_empty(&mut central).unwrap();
+        central.process_answer(&answer, true);
+    }
+    assert!(h.link.is_connected());
+}
+
+#[test]
+fn control_procedures() {
+    let h = Harness::new();
+    let mut central = Central::new();
+    h.connect(&mut central);
+
+    let mut exchange = |payload: &[u8]| {
+        let answer = h.event_packet(&mut central, 0b11, payload).unwrap();
+        central.process_answer(&answer, true);
+        // The answer is sent in the next event.
+        let answer = h.event_empty(&mut central).unwrap();
+        central.process_answer(&answer, true)
+    };
+    assert_eq!(
+        exchange(&[0x0C, 9, 0x59, 0x00, 0x01, 0x00]),
+        Some((0b11, vec![0x0C, 6, 0xFF, 0xFF, 0, 0]))
+    );
+    assert_eq!(
+        exchange(&[0x08, 0, 0, 0, 0, 0, 0, 0, 0]),
+        Some((0b11, vec![0x09, 0, 0, 0, 0, 0, 0, 0, 0]))
+    );
+    assert_eq!(exchange(&[0x12]), Some((0b11, vec![0x13])));
+    assert_eq!(exchange(&[0x03, 0]), Some((0b11, vec![0x07, 0x03])));
+    assert!(h.client.take_events().is_empty());
+
+    // The central ends the connection.
+    let answer = h.event_packet(&mut central, 0b11, &[0x02, 0x13]).unwrap();
+    central.process_answer(&answer, true);
+    assert_eq!(
+        h.client.take_events(),
+        [LinkEvent::Disconnected(reason::REMOTE_USER_TERMINATED)]
+    );
+}
+
+#[test]
+fn local_disconnection() {
+    let h = Harness::new();
+    let mut central = Central::new();
+    h.connect(&mut central);
+
+    h.link.disconnect().unwrap();
+    let answer = h.event_empty(&mut central).unwrap();
+    assert_eq!(
+        central.process_answer(&answer, true),
+        Some((0b11, vec![0x02, reason::REMOTE_USER_TERMINATED]))
+    );
+    assert!(h.client.take_events().is_empty());
+    // Once the central acknowledges it, the connection ends.
+    h.event_empty(&mut central).unwrap();
+    assert_eq!(
+        h.client.take_events(),
+        [LinkEvent::Disconnected(reason::LOCAL_HOST_TERMINATED)]
+    );
+}
+
+fn database() -> AttributeDatabase {
+    let database = AttributeDatabase::new(
+        Box::leak(Box::new([None; 16])),
+        Box::leak(Box::new([0; 64])),
+    );
+    assert_eq!(database.add_service(None, Uuid::Uuid16(0x1800)), Ok(1));
+    let name = database
+        .add_characteristic(None, Uuid::Uuid16(0x2A00), properties::READ, 4)
+        .unwrap();
+    assert_eq!(name, 3);
+    database
+        .set_value(None, name, |value| {
+            value.copy_from_slice(b"tock");
+            4
+        })
+        .unwrap();
+    let uuid = Uuid::Uuid128(core::array::from_fn(|i| i as u8));
+    assert_eq!(database.add_service(None, uuid), Ok(4));
+    let value = database
+        .add_characteristic(
+            None,
+            Uuid::Uuid16(0x2A19),
+            properties::READ | properties::WRITE | properties::NOTIFY,
+            4,
+        )
+        .unwrap();
+    assert_eq!(value, 6);
+    database
+}
+
+fn request(database: &AttributeDatabase, request: &[u8]) -> Vec<u8> {
+    let mut response = [0; 23];
+    let (len, _) = database.handle_request(request, &mut response);
+    response[..len].to_vec()
+}
+
+#[test]
+fn att_service_discovery() {
+    let database = database();
+
+    // Services are grouped by UUID length.
+    assert_eq!(
+        request(&database, &[0x10, 1, 0, 0xFF, 0xFF, 0x00, 0x28]),
+        [0x11, 6, 1, 0, 3, 0, 0x00, 0x18]
+    );
+    let mut expected = vec![0x11, 20, 4, 0, 7, 0];
+    expected.extend(0..16);
+    assert_eq!(
+        request(&database, &[0x10, 4, 0, 0xFF, 0xFF, 0x00, 0x28]),
+        expected
+    );
+    assert_eq!(
+        request(&database, &[0x10, 8, 0, 0xFF, 0xFF, 0x00, 0x28]),
+        [0x01, 0x10, 8, 0, 0x0A]
+    );
+    assert_eq!(
+        request(&database, &[0x06, 1, 0, 0xFF, 0xFF, 0x00, 0x28, 0x00, 0x18]),
+        [0x07, 1, 0, 3, 0]
+    );
+
+    // Characteristics and descriptors.
+    assert_eq!(
+        request(&database, &[0x08, 4, 0, 7, 0, 0x03, 0x28]),
+        [0x09, 7, 5, 0, 0x1A, 6, 0, 0x19, 0x2A]
+    );
+    assert_eq!(
+        request(&database, &[0x04, 6, 0, 7, 0]),
+        [0x05, 1, 6, 0, 0x19, 0x2A, 7, 0, 0x02, 0x29]
+    );
+    // Group type other than primary services.
+    assert_eq!(
+        request(&database, &[0x10, 1, 0, 0xFF, 0xFF, 0x01, 0x28]),
+        [0x01, 0x10, 1, 0, 0x10]
+    );
+}
+
+#[test]
+fn att_read_and_write() {
+    let database = database();
+
+    assert_eq!(request(&database, &[0x0A, 3, 0]), b"\x0Btock");
+    assert_eq!(request(&database, &[0x0C, 3, 0, 2, 0]), b"\x0Dck");
+    assert_eq!(
+        request(&database, &[0x0C, 3, 0, 5, 0]),
+        [0x01, 0x0C, 3, 0, 0x07]
+    );
+    // Reading by the UUID of the value.
+    assert_eq!(
+        request(&database, &[0x08, 1, 0, 0xFF, 0xFF, 0x00, 0x2A]),
+        b"\x09\x06\x03\x00tock"
+    );
+    assert_eq!(request(&database, &[0x0A, 0, 0]), [0x01, 0x0A, 0, 0, 0x01]);
+    assert_eq!(
+        request(&database, &[0x0A, 99, 0]),
+        [0x01, 0x0A, 99, 0, 0x01]
+    );
+
+    let mut response = [0; 23];
+    let (len, written) = database.handle_request(&[0x12, 6, 0, 1, 2], &mut response);
+    assert_eq!(&response[..len], [0x13]);
+    let written = written.unwrap();
+    assert_eq!((written.handle, written.len), (6, 2));
+    assert_eq!(
+        database.value(None, 6, |value| value.to_vec()),
+        Ok(vec![1, 2])
+    );
+    assert_eq!(request(&database, &[0x0A, 6, 0]), [0x0B, 1, 2]);
+
+    // Too long, not writable, and a command without response.
+    assert_eq!(
+        request(&database, &[0x12, 6, 0, 1, 2, 3, 4, 5]),
+        [0x01, 0x12, 6, 0, 0x0D]
+    );
+    assert_eq!(
+        request(&database, &[0x12, 3, 0, 1]),
+        [0x01, 0x12, 3, 0, 0x03]
+    );
+    assert_eq!(request(&database, &[0x52, 6, 0, 1]), []);
+    assert_eq!(request(&database, &[0x02, 0xF7, 0]), [0x03, 23, 0]);
+    assert_eq!(request(&database, &[0x20]), [0x01, 0x20, 0, 0, 0x06]);
+}
+
+#[test]
+fn att_notifications() {
+    let database = database();
+    let mut notification = [0; 23];
+    assert_eq!(
+        database.notification(None, 3, &mut notification),
+        Err(ErrorCode::NOSUPPORT)
+    );
+    assert_eq!(
+        database.notification(None, 6, &mut notification),
+        Err(ErrorCode::OFF)
+    );
+
+    assert_eq!(request(&database, &[0x12, 7, 0, 1, 0]), [0x13]);
+    assert_eq!(request(&database, &[0x0A, 7, 0]), [0x0B, 1, 0]);
+    database
+        .set_value(None, 6, |value| {
+            value[..3].copy_from_slice(&[7, 8, 9]);
+            3
+        })
+        .unwrap();
+    assert_eq!(database.notification(None, 6, &mut notification), Ok(6));
+    assert_eq!(notification[..6], [0x1B, 6, 0, 7, 8, 9]);
+
+    database.reset_client_configuration();
+    assert_eq!(
+        database.notification(None, 6, &mut notification),
+        Err(ErrorCode::OFF)
+    );
+}
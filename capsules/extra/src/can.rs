@@ -10,7 +10,10 @@
 //! This module has a CAN syscall driver capsule implementation.
 //!
 //! This capsule sends commands from the userspace to a driver that
-//! implements the Can trait.
+//! implements the Can trait, or the CanFd trait for peripherals that
+//! support CAN FD. With a CAN FD peripheral, the capsule sends and
+//! receives frames of up to 64 bytes, and userspace can configure the
+//! payload bit timing and bit rate switching.
 //!
 //! The capsule shares 2 buffers with the userspace: one RO that is used
 //! for transmitting messages and one RW that is used for receiving
@@ -48,6 +51,10 @@
 //! kernel::hil::can::Receive::set_client(can_peripheral, Some(can));
 //! ```
 //!
+//! For a CAN FD peripheral, the capsule is instantiated as
+//! `CanCapsule<'static, Can, { can::FD_CAN_PACKET_SIZE }>` and uses
+//! 64-byte buffers.
+//!
 
 use core::mem::size_of;
 
@@ -67,6 +74,46 @@ pub const BYTE3_MASK: usize = 0xff0000;
 pub const BYTE2_MASK: usize = 0xff00;
 pub const BYTE1_MASK: usize = 0xff;
 
+/// A CAN peripheral that the capsule can use.
+///
+/// This is implemented for classic CAN peripherals with 8-byte frames and
+/// for CAN FD peripherals with 64-byte frames. Only the latter support the
+/// payload bit timing and bit rate switching commands.
+pub trait CanPeripheral<const PACKET_SIZE: usize>:
+    can::Transmit<PACKET_SIZE> + can::Receive<PACKET_SIZE> + can::Configure + can::Controller
+{
+    /// Sets the bit timing used for the payload of CAN FD frames.
+    fn set_payload_bit_timing(&self, _payload_bit_timing: can::BitTiming) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    /// Sets whether CAN FD frames are sent with bit rate switching.
+    fn set_bit_rate_switching(&self, _enabled: bool) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    /// Returns the maximum number of bytes in a frame.
+    fn frame_size(&self) -> usize {
+        PACKET_SIZE
+    }
+}
+
+impl<Can: can::Can> CanPeripheral<{ can::STANDARD_CAN_PACKET_SIZE }> for Can {}
+
+impl<Can: can::CanFd> CanPeripheral<{ can::FD_CAN_PACKET_SIZE }> for Can {
+    fn set_payload_bit_timing(&self, payload_bit_timing: can::BitTiming) -> Result<(), ErrorCode> {
+        can::ConfigureFd::set_payload_bit_timing(self, payload_bit_timing)
+    }
+
+    fn set_bit_rate_switching(&self, enabled: bool) -> Result<(), ErrorCode> {
+        can::ConfigureFd::set_bit_rate_switching(self, enabled)
+    }
+
+    fn frame_size(&self) -> usize {
+        <Can as can::ConfigureFd>::get_frame_size()
+    }
+}
+
 mod error_upcalls {
     pub const ERROR_TX: usize = 100;
     pub const ERROR_RX: usize = 101;
@@ -92,13 +139,17 @@ mod rw_allow {
     pub const COUNT: u8 = 1;
 }
 
-pub struct CanCapsule<'a, Can: can::Can> {
+pub struct CanCapsule<
+    'a,
+    Can: CanPeripheral<PACKET_SIZE>,
+    const PACKET_SIZE: usize = { can::STANDARD_CAN_PACKET_SIZE },
+> {
     // CAN driver
     can: &'a Can,
 
     // CAN buffers
-    can_tx: TakeCell<'static, [u8; can::STANDARD_CAN_PACKET_SIZE]>,
-    can_rx: TakeCell<'static, [u8; can::STANDARD_CAN_PACKET_SIZE]>,
+    can_tx: TakeCell<'static, [u8; PACKET_SIZE]>,
+    can_rx: TakeCell<'static, [u8; PACKET_SIZE]>,
 
     // Process
     processes: Grant<
@@ -117,9 +168,14 @@ pub struct CanCapsule<'a, Can: can::Can> {
 #[derive(Default)]
 pub struct App {
     lost_messages: u32,
+    // Whether the upcalls for received messages and receive errors carry
+    // the message length and the CAN error.
+    extended_upcalls: bool,
 }
 
-impl<'a, Can: can::Can> CanCapsule<'a, Can> {
+impl<'a, Can: CanPeripheral<PACKET_SIZE>, const PACKET_SIZE: usize>
+    CanCapsule<'a, Can, PACKET_SIZE>
+{
     pub fn new(
         can: &'a Can,
         grant: Grant<
@@ -128,9 +184,9 @@ impl<'a, Can: can::Can> CanCapsule<'a, Can> {
             AllowRoCount<{ ro_allow::COUNT }>,
             AllowRwCount<{ rw_allow::COUNT }>,
         >,
-        can_tx: &'static mut [u8; can::STANDARD_CAN_PACKET_SIZE],
-        can_rx: &'static mut [u8; can::STANDARD_CAN_PACKET_SIZE],
-    ) -> CanCapsule<'a, Can> {
+        can_tx: &'static mut [u8; PACKET_SIZE],
+        can_rx: &'static mut [u8; PACKET_SIZE],
+    ) -> CanCapsule<'a, Can, PACKET_SIZE> {
         CanCapsule {
             can,
             can_tx: TakeCell::new(can_tx),
@@ -166,6 +222,9 @@ impl<'a, Can: can::Can> CanCapsule<'a, Can> {
                         |buffer_ref| {
                             buffer_ref
                                 .enter(|buffer| {
+                                    if length > self.can.frame_size() || length > buffer.len() {
+                                        return Err(ErrorCode::SIZE);
+                                    }
                                     self.can_tx.take().map_or(
                                         Err(ErrorCode::NOMEM),
                                         |dest_buffer| {
@@ -189,6 +248,16 @@ impl<'a, Can: can::Can> CanCapsule<'a, Can> {
             .unwrap_or_else(|err| err.into())
     }
 
+    /// Whether the process using the capsule asked for the extended
+    /// upcalls with command 13.
+    fn extended_upcalls(&self) -> bool {
+        self.processid.map_or(false, |processid| {
+            self.processes
+                .enter(processid, |app, _| app.extended_upcalls)
+                .unwrap_or(false)
+        })
+    }
+
     pub fn is_valid_process(&self, processid: ProcessId) -> bool {
         self.processid.map_or(true, |owning_process| {
             self.processes
@@ -198,7 +267,9 @@ impl<'a, Can: can::Can> CanCapsule<'a, Can> {
     }
 }
 
-impl<Can: can::Can> SyscallDriver for CanCapsule<'_, Can> {
+impl<Can: CanPeripheral<PACKET_SIZE>, const PACKET_SIZE: usize> SyscallDriver
+    for CanCapsule<'_, Can, PACKET_SIZE>
+{
     fn command(
         &self,
         command_num: usize,
@@ -291,10 +362,9 @@ impl<Can: can::Can> SyscallDriver for CanCapsule<'_, Can> {
                                         buffer_ref
                                             .enter(|buffer| {
                                                 // make sure that the receiving buffer can have at least
-                                                // 2 messages of 8 bytes each and 4 another bytes for the counter
+                                                // 2 messages and 4 another bytes for the counter
                                                 if buffer.len()
-                                                    >= 2 * can::STANDARD_CAN_PACKET_SIZE
-                                                        + size_of::<u32>()
+                                                    >= 2 * PACKET_SIZE + size_of::<u32>()
                                                 {
                                                     Ok(())
                                                 } else {
@@ -335,6 +405,39 @@ impl<Can: can::Can> SyscallDriver for CanCapsule<'_, Can> {
                 }
             }
 
+            // Set the timing parameters for the payload of CAN FD frames
+            10 => {
+                match self.can.set_payload_bit_timing(can::BitTiming {
+                    segment1: ((arg1 & BYTE4_MASK) >> 24) as u8,
+                    segment2: ((arg1 & BYTE3_MASK) >> 16) as u8,
+                    propagation: arg2 as u8,
+                    sync_jump_width: ((arg1 & BYTE2_MASK) >> 8) as u32,
+                    baud_rate_prescaler: (arg1 & BYTE1_MASK) as u32,
+                }) {
+                    Ok(()) => CommandReturn::success(),
+                    Err(err) => CommandReturn::failure(err),
+                }
+            }
+
+            // Enable or disable bit rate switching for CAN FD frames
+            11 => match self.can.set_bit_rate_switching(arg1 != 0) {
+                Ok(()) => CommandReturn::success(),
+                Err(err) => CommandReturn::failure(err),
+            },
+
+            // Get the maximum number of bytes in a frame
+            12 => CommandReturn::success_u32(self.can.frame_size() as u32),
+
+            // Enable or disable the extended upcalls, that also report the
+            // length of received messages and the CAN error of receive errors
+            13 => self
+                .processes
+                .enter(processid, |app, _| {
+                    app.extended_upcalls = arg1 != 0;
+                    CommandReturn::success()
+                })
+                .unwrap_or_else(|err| CommandReturn::failure(err.into())),
+
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
     }
@@ -344,7 +447,9 @@ impl<Can: can::Can> SyscallDriver for CanCapsule<'_, Can> {
     }
 }
 
-impl<Can: can::Can> can::ControllerClient for CanCapsule<'_, Can> {
+impl<Can: CanPeripheral<PACKET_SIZE>, const PACKET_SIZE: usize> can::ControllerClient
+    for CanCapsule<'_, Can, PACKET_SIZE>
+{
     // This callback must be called after an `enable` or `disable` command was sent.
     // It stores the new state of the peripheral.
     fn state_changed(&self, state: can::State) {
@@ -409,13 +514,15 @@ impl<Can: can::Can> can::ControllerClient for CanCapsule<'_, Can> {
     }
 }
 
-impl<Can: can::Can> can::TransmitClient<{ can::STANDARD_CAN_PACKET_SIZE }> for CanCapsule<'_, Can> {
+impl<Can: CanPeripheral<PACKET_SIZE>, const PACKET_SIZE: usize> can::TransmitClient<PACKET_SIZE>
+    for CanCapsule<'_, Can, PACKET_SIZE>
+{
     // This callback is called when the hardware acknowledges that a message
     // was sent. This callback also makes an upcall to the userspace.
     fn transmit_complete(
         &self,
         status: Result<(), can::Error>,
-        buffer: &'static mut [u8; can::STANDARD_CAN_PACKET_SIZE],
+        buffer: &'static mut [u8; PACKET_SIZE],
     ) {
         self.can_tx.replace(buffer);
         match status {
@@ -430,18 +537,25 @@ impl<Can: can::Can> can::TransmitClient<{ can::STANDARD_CAN_PACKET_SIZE }> for C
     }
 }
 
-impl<Can: can::Can> can::ReceiveClient<{ can::STANDARD_CAN_PACKET_SIZE }> for CanCapsule<'_, Can> {
+impl<Can: CanPeripheral<PACKET_SIZE>, const PACKET_SIZE: usize> can::ReceiveClient<PACKET_SIZE>
+    for CanCapsule<'_, Can, PACKET_SIZE>
+{
     // This callback is called when a new message is received on any receiving
     // fifo.
     fn message_received(
         &self,
         id: can::Id,
-        buffer: &mut [u8; can::STANDARD_CAN_PACKET_SIZE],
-        _len: usize,
+        buffer: &mut [u8; PACKET_SIZE],
+        len: usize,
         status: Result<(), can::Error>,
     ) {
         match status {
             Ok(()) => {
+                // Messages are stored with a fixed size, so clear the bytes
+                // past the end of a shorter one.
+                if let Some(unused) = buffer.get_mut(len..) {
+                    unused.fill(0);
+                }
                 let res: Result<(bool, u32), ErrorCode> =
                     self.processid.map_or(Err(ErrorCode::NOMEM), |processid| {
                         self.processes
@@ -474,7 +588,7 @@ impl<Can: can::Can> can::ReceiveClient<{ can::STANDARD_CAN_PACKET_SIZE }> for Ca
                     Ok((_first_chunk, new_offset)) => self.schedule_callback(
                         up_calls::UPCALL_MESSAGE_RECEIVED,
                         (
-                            0,
+                            if self.extended_upcalls() { len } else { 0 },
                             new_offset as usize,
                             match id {
                                 can::Id::Standard(u16) => u16 as usize,
@@ -488,13 +602,21 @@ impl<Can: can::Can> can::ReceiveClient<{ can::STANDARD_CAN_PACKET_SIZE }> for Ca
                 let kernel_err: ErrorCode = err.into();
                 self.schedule_callback(
                     up_calls::UPCALL_TRANSMISSION_ERROR,
-                    (error_upcalls::ERROR_RX, kernel_err.into(), 0),
+                    (
+                        error_upcalls::ERROR_RX,
+                        kernel_err.into(),
+                        if self.extended_upcalls() {
+                            err as usize
+                        } else {
+                            0
+                        },
+                    ),
                 )
             }
         }
     }
 
-    fn stopped(&self, buffer: &'static mut [u8; can::STANDARD_CAN_PACKET_SIZE]) {
+    fn stopped(&self, buffer: &'static mut [u8; PACKET_SIZE]) {
         self.can_rx.replace(buffer);
         self.schedule_callback(up_calls::UPCALL_RECEIVED_STOPPED, (0, 0, 0));
     }
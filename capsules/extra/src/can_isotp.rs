@@ -0,0 +1,867 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! ISO-TP (ISO 15765-2) transport over CAN.
+//!
+//! ISO-TP carries messages of up to 4095 bytes over classic CAN frames, and is
+//! the transport used by UDS diagnostics. This capsule segments outgoing
+//! messages into a first frame and consecutive frames, reassembles incoming
+//! messages, and exchanges the flow control frames in between:
+//!
+//! - When sending, it waits for a flow control frame from the receiver after
+//!   the first frame and after every block of consecutive frames, and waits
+//!   at least the separation time (STmin) the receiver asked for between
+//!   consecutive frames.
+//! - When receiving, it answers a first frame with a flow control frame that
+//!   carries the block size and STmin configured by the app, and sends a new
+//!   one after every block.
+//!
+//! The capsule uses normal addressing: it sends all frames with one CAN
+//! identifier, and accepts frames with another. Frames are always padded to
+//! 8 bytes with `0xCC`. A message can be sent while another one is being
+//! received. If the peer stops sending flow control frames or consecutive
+//! frames for one second (N_Bs and N_Cr), the transfer fails with `NOACK`.
+//!
+//! Like the raw CAN driver, one process owns the capsule at a time.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let isotp = static_init!(
+//!     capsules_extra::can_isotp::CanIsoTp<'static, Can, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules_extra::can_isotp::CanIsoTp::new(
+//!         can, alarm, grant, can_tx_buffer, can_rx_buffer,
+//!     )
+//! );
+//! kernel::hil::can::Transmit::set_client(can, Some(isotp));
+//! kernel::hil::can::Receive::set_client(can, Some(isotp));
+//! alarm.set_alarm_client(isotp);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::ErrorCode;
+use kernel::ProcessId;
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::can::{self, STANDARD_CAN_PACKET_SIZE};
+use kernel::hil::time::{self, Alarm, ConvertTicks, Ticks};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::CanIsoTp as usize;
+
+/// Longest message a first frame can announce.
+pub const MAX_MESSAGE_LEN: usize = 0xFFF;
+
+/// Set in an identifier passed from userspace to mark it as an extended
+/// (29-bit) identifier.
+pub const EXTENDED_ID_FLAG: usize = 1 << 31;
+
+/// Value of the unused bytes of a frame.
+const PADDING: u8 = 0xCC;
+
+/// How long to wait for a flow control frame (N_Bs) or for the next
+/// consecutive frame (N_Cr).
+const TIMEOUT_MS: u32 = 1000;
+
+/// How many flow control frames in a row may ask the sender to wait before
+/// it gives up (N_WFTmax).
+const MAX_WAIT_FRAMES: u8 = 10;
+
+/// Longest payload of a single frame.
+const SINGLE_FRAME_DATA_LEN: usize = 7;
+/// Payload of a first frame.
+const FIRST_FRAME_DATA_LEN: usize = 6;
+/// Longest payload of a consecutive frame.
+const CONSECUTIVE_FRAME_DATA_LEN: usize = 7;
+
+/// IDs for subscribed upcalls.
+mod upcall {
+    /// A message was sent, or sending it failed.
+    ///
+    /// Arguments: status code, message length.
+    pub const SENT: usize = 0;
+    /// A message was received, or receiving it failed.
+    ///
+    /// Arguments: status code, message length.
+    pub const RECEIVED: usize = 1;
+    /// Number of upcalls.
+    pub const COUNT: u8 = 2;
+}
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// The message to send.
+    pub const MESSAGE: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// Where received messages are stored, starting at offset 0.
+    pub const MESSAGE: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// The protocol control information in the high nibble of the first byte of
+/// every frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FrameType {
+    Single = 0,
+    First = 1,
+    Consecutive = 2,
+    FlowControl = 3,
+}
+
+/// The flow status of a flow control frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FlowStatus {
+    ContinueToSend = 0,
+    Wait = 1,
+    Overflow = 2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TxState {
+    Idle,
+    /// The next frame of the message waits for the CAN transmit buffer.
+    Ready,
+    /// A frame of the message is being transmitted.
+    Sending,
+    /// Waiting for a flow control frame from the receiver.
+    WaitFlowControl,
+    /// Waiting for the separation time before the next consecutive frame.
+    Separation,
+}
+
+/// A point in time an alarm is set for.
+#[derive(Clone, Copy)]
+struct Deadline<T: Ticks> {
+    reference: T,
+    dt: T,
+}
+
+impl<T: Ticks> Deadline<T> {
+    /// Returns the time left until the deadline, or `None` if it passed.
+    fn remaining(&self, now: T) -> Option<T> {
+        let end = self.reference.wrapping_add(self.dt);
+        if now.within_range(self.reference, end) {
+            Some(end.wrapping_sub(now))
+        } else {
+            None
+        }
+    }
+}
+
+pub struct CanIsoTp<
+    'a,
+    C: can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>,
+    A: Alarm<'a>,
+> {
+    can: &'a C,
+    alarm: &'a A,
+
+    // CAN buffers
+    can_tx: TakeCell<'static, [u8; STANDARD_CAN_PACKET_SIZE]>,
+    can_rx: TakeCell<'static, [u8; STANDARD_CAN_PACKET_SIZE]>,
+    /// The kind of frame `can_tx` holds while it is being transmitted.
+    in_flight: Cell<Option<FrameType>>,
+    receiving: Cell<bool>,
+
+    // Process
+    apps: Grant<
+        (),
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    processid: OptionalCell<ProcessId>,
+
+    // Addressing
+    tx_id: OptionalCell<can::Id>,
+    rx_id: OptionalCell<can::Id>,
+
+    // Sending
+    tx_state: Cell<TxState>,
+    tx_len: Cell<usize>,
+    tx_offset: Cell<usize>,
+    tx_sequence: Cell<u8>,
+    /// Block size the receiver asked for, 0 if unlimited.
+    tx_block_size: Cell<u8>,
+    /// Consecutive frames left in the current block.
+    tx_block_left: Cell<u8>,
+    /// STmin the receiver asked for.
+    tx_separation_time: Cell<u8>,
+    tx_wait_frames: Cell<u8>,
+    tx_deadline: OptionalCell<Deadline<A::Ticks>>,
+
+    // Receiving
+    rx_active: Cell<bool>,
+    rx_len: Cell<usize>,
+    rx_offset: Cell<usize>,
+    rx_sequence: Cell<u8>,
+    /// Block size to ask senders for, 0 if unlimited.
+    rx_block_size: Cell<u8>,
+    /// Consecutive frames received in the current block.
+    rx_block_count: Cell<u8>,
+    /// STmin to ask senders for.
+    rx_separation_time: Cell<u8>,
+    /// Flow control frame waiting for the CAN transmit buffer.
+    rx_flow_control: Cell<Option<FlowStatus>>,
+    rx_deadline: OptionalCell<Deadline<A::Ticks>>,
+}
+
+impl<
+    'a,
+    C: can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>,
+    A: Alarm<'a>,
+> CanIsoTp<'a, C, A>
+{
+    pub fn new(
+        can: &'a C,
+        alarm: &'a A,
+        grant: Grant<
+            (),
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        can_tx: &'static mut [u8; STANDARD_CAN_PACKET_SIZE],
+        can_rx: &'static mut [u8; STANDARD_CAN_PACKET_SIZE],
+    ) -> Self {
+        Self {
+            can,
+            alarm,
+            can_tx: TakeCell::new(can_tx),
+            can_rx: TakeCell::new(can_rx),
+            in_flight: Cell::new(None),
+            receiving: Cell::new(false),
+            apps: grant,
+            processid: OptionalCell::empty(),
+            tx_id: OptionalCell::empty(),
+            rx_id: OptionalCell::empty(),
+            tx_state: Cell::new(TxState::Idle),
+            tx_len: Cell::new(0),
+            tx_offset: Cell::new(0),
+            tx_sequence: Cell::new(0),
+            tx_block_size: Cell::new(0),
+            tx_block_left: Cell::new(0),
+            tx_separation_time: Cell::new(0),
+            tx_wait_frames: Cell::new(0),
+            tx_deadline: OptionalCell::empty(),
+            rx_active: Cell::new(false),
+            rx_len: Cell::new(0),
+            rx_offset: Cell::new(0),
+            rx_sequence: Cell::new(0),
+            rx_block_size: Cell::new(0),
+            rx_block_count: Cell::new(0),
+            rx_separation_time: Cell::new(0),
+            rx_flow_control: Cell::new(None),
+            rx_deadline: OptionalCell::empty(),
+        }
+    }
+
+    fn is_valid_process(&self, processid: ProcessId) -> bool {
+        self.processid.map_or(true, |owning_process| {
+            self.apps
+                .enter(owning_process, |_, _| owning_process == processid)
+                .unwrap_or(true)
+        })
+    }
+
+    fn schedule_upcall(&self, upcall_number: usize, result: Result<(), ErrorCode>, len: usize) {
+        self.processid.map(|processid| {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                let _ =
+                    kernel_data.schedule_upcall(upcall_number, (into_statuscode(result), len, 0));
+            });
+        });
+    }
+
+    fn id_from_user(id: usize) -> can::Id {
+        if id & EXTENDED_ID_FLAG != 0 {
+            can::Id::Extended((id & !EXTENDED_ID_FLAG) as u32)
+        } else {
+            can::Id::Standard(id as u16)
+        }
+    }
+
+    /// Converts an STmin value into ticks. Reserved values mean the longest
+    /// separation time, 127 ms.
+    fn separation_ticks(&self, separation_time: u8) -> A::Ticks {
+        match separation_time {
+            0x00..=0x7F => self.alarm.ticks_from_ms(separation_time as u32),
+            0xF1..=0xF9 => self
+                .alarm
+                .ticks_from_us((separation_time - 0xF0) as u32 * 100),
+            _ => self.alarm.ticks_from_ms(0x7F),
+        }
+    }
+
+    fn is_valid_separation_time(separation_time: usize) -> bool {
+        matches!(separation_time, 0x00..=0x7F | 0xF1..=0xF9)
+    }
+
+    // Timers
+
+    fn set_deadline(&self, deadline: &OptionalCell<Deadline<A::Ticks>>, dt: A::Ticks) {
+        deadline.set(Deadline {
+            reference: self.alarm.now(),
+            dt,
+        });
+        self.rearm();
+    }
+
+    fn clear_deadline(&self, deadline: &OptionalCell<Deadline<A::Ticks>>) {
+        deadline.clear();
+        self.rearm();
+    }
+
+    /// Sets the alarm for the earliest of the send and receive deadlines.
+    fn rearm(&self) {
+        let now = self.alarm.now();
+        let remaining = |deadline: Deadline<A::Ticks>| deadline.remaining(now).unwrap_or(0.into());
+        let next = match (
+            self.tx_deadline.get().map(remaining),
+            self.rx_deadline.get().map(remaining),
+        ) {
+            (Some(tx), Some(rx)) => Some(cmp::min(tx, rx)),
+            (tx, rx) => tx.or(rx),
+        };
+        match next {
+            Some(dt) => self.alarm.set_alarm(now, dt),
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+
+    // Frames
+
+    /// Sends the pending flow control frame, or else the next frame of the
+    /// message being sent, if the CAN transmit buffer is free.
+    fn service_tx(&self) {
+        let Some(frame) = self.can_tx.take() else {
+            return;
+        };
+        frame.fill(PADDING);
+
+        let frame_type = if let Some(status) = self.rx_flow_control.take() {
+            frame[0] = ((FrameType::FlowControl as u8) << 4) | status as u8;
+            frame[1] = self.rx_block_size.get();
+            frame[2] = self.rx_separation_time.get();
+            FrameType::FlowControl
+        } else if self.tx_state.get() == TxState::Ready {
+            match self.fill_data_frame(frame) {
+                Ok(frame_type) => frame_type,
+                Err(err) => {
+                    self.can_tx.replace(frame);
+                    self.finish_tx(Err(err));
+                    return;
+                }
+            }
+        } else {
+            self.can_tx.replace(frame);
+            return;
+        };
+
+        let Some(id) = self.tx_id.get() else {
+            self.can_tx.replace(frame);
+            return;
+        };
+        match self.can.send(id, frame, STANDARD_CAN_PACKET_SIZE) {
+            Ok(()) => {
+                self.in_flight.set(Some(frame_type));
+                if frame_type != FrameType::FlowControl {
+                    self.tx_state.set(TxState::Sending);
+                }
+            }
+            Err((err, frame)) => {
+                self.can_tx.replace(frame);
+                if frame_type == FrameType::FlowControl {
+                    if self.rx_active.get() {
+                        self.finish_rx(Err(err));
+                    }
+                } else {
+                    self.finish_tx(Err(err));
+                }
+            }
+        }
+    }
+
+    /// Copies the next part of the message being sent into `frame`.
+    fn fill_data_frame(
+        &self,
+        frame: &mut [u8; STANDARD_CAN_PACKET_SIZE],
+    ) -> Result<FrameType, ErrorCode> {
+        let len = self.tx_len.get();
+        let offset = self.tx_offset.get();
+        let (frame_type, header_len, data_len) = if offset == 0 && len <= SINGLE_FRAME_DATA_LEN {
+            frame[0] = len as u8;
+            (FrameType::Single, 1, len)
+        } else if offset == 0 {
+            frame[0] = ((FrameType::First as u8) << 4) | (len >> 8) as u8;
+            frame[1] = len as u8;
+            (FrameType::First, 2, FIRST_FRAME_DATA_LEN)
+        } else {
+            frame[0] = ((FrameType::Consecutive as u8) << 4) | self.tx_sequence.get();
+            let data_len = cmp::min(CONSECUTIVE_FRAME_DATA_LEN, len - offset);
+            (FrameType::Consecutive, 1, data_len)
+        };
+
+        self.processid
+            .map_or(Err(ErrorCode::RESERVE), |processid| {
+                self.apps
+                    .enter(processid, |_, kernel_data| {
+                        kernel_data
+                            .get_readonly_processbuffer(ro_allow::MESSAGE)
+                            .and_then(|buffer| {
+                                buffer.enter(|message| {
+                                    message.get(offset..offset + data_len).map_or(
+                                        Err(ErrorCode::SIZE),
+                                        |data| {
+                                            data.copy_to_slice(
+                                                &mut frame[header_len..header_len + data_len],
+                                            );
+                                            Ok(())
+                                        },
+                                    )
+                                })
+                            })
+                            .unwrap_or_else(|err| Err(err.into()))
+                    })
+                    .unwrap_or_else(|err| Err(err.into()))
+            })?;
+
+        self.tx_offset.set(offset + data_len);
+        if frame_type == FrameType::Consecutive {
+            self.tx_sequence.set((self.tx_sequence.get() + 1) & 0x0F);
+        }
+        Ok(frame_type)
+    }
+
+    // Sending
+
+    fn start_tx(&self, len: usize) -> Result<(), ErrorCode> {
+        if self.tx_id.is_none() {
+            return Err(ErrorCode::OFF);
+        }
+        if self.tx_state.get() != TxState::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if len == 0 || len > MAX_MESSAGE_LEN {
+            return Err(ErrorCode::INVAL);
+        }
+
+        self.tx_len.set(len);
+        self.tx_offset.set(0);
+        self.tx_sequence.set(1);
+        self.tx_wait_frames.set(0);
+        self.tx_state.set(TxState::Ready);
+        self.service_tx();
+        Ok(())
+    }
+
+    fn finish_tx(&self, result: Result<(), ErrorCode>) {
+        self.tx_state.set(TxState::Idle);
+        self.clear_deadline(&self.tx_deadline);
+        self.schedule_upcall(upcall::SENT, result, self.tx_len.get());
+    }
+
+    /// Continues with the message being sent after one of its frames was
+    /// transmitted.
+    fn data_frame_sent(&self, frame_type: FrameType) {
+        if self.tx_offset.get() == self.tx_len.get() {
+            self.finish_tx(Ok(()));
+            return;
+        }
+
+        if frame_type == FrameType::Consecutive && self.tx_block_size.get() != 0 {
+            self.tx_block_left.set(self.tx_block_left.get() - 1);
+        }
+        if frame_type == FrameType::First
+            || (self.tx_block_size.get() != 0 && self.tx_block_left.get() == 0)
+        {
+            self.tx_state.set(TxState::WaitFlowControl);
+            self.set_deadline(&self.tx_deadline, self.alarm.ticks_from_ms(TIMEOUT_MS));
+            return;
+        }
+
+        let separation = self.separation_ticks(self.tx_separation_time.get());
+        if separation.into_u32() == 0 {
+            self.tx_state.set(TxState::Ready);
+        } else {
+            self.tx_state.set(TxState::Separation);
+            self.set_deadline(&self.tx_deadline, separation);
+        }
+    }
+
+    fn flow_control_received(&self, frame: &[u8]) {
+        if self.tx_state.get() != TxState::WaitFlowControl || frame.len() < 3 {
+            return;
+        }
+
+        match frame[0] & 0x0F {
+            status if status == FlowStatus::ContinueToSend as u8 => {
+                self.tx_block_size.set(frame[1]);
+                self.tx_block_left.set(frame[1]);
+                self.tx_separation_time.set(frame[2]);
+                self.tx_wait_frames.set(0);
+                self.tx_state.set(TxState::Ready);
+                self.clear_deadline(&self.tx_deadline);
+                self.service_tx();
+            }
+            status if status == FlowStatus::Wait as u8 => {
+                self.tx_wait_frames.set(self.tx_wait_frames.get() + 1);
+                if self.tx_wait_frames.get() > MAX_WAIT_FRAMES {
+                    self.finish_tx(Err(ErrorCode::BUSY));
+                } else {
+                    self.set_deadline(&self.tx_deadline, self.alarm.ticks_from_ms(TIMEOUT_MS));
+                }
+            }
+            status if status == FlowStatus::Overflow as u8 => {
+                self.finish_tx(Err(ErrorCode::SIZE));
+            }
+            _ => self.finish_tx(Err(ErrorCode::FAIL)),
+        }
+    }
+
+    // Receiving
+
+    fn receive_capacity(&self) -> usize {
+        self.processid.map_or(0, |processid| {
+            self.apps
+                .enter(processid, |_, kernel_data| {
+                    kernel_data
+                        .get_readwrite_processbuffer(rw_allow::MESSAGE)
+                        .map_or(0, |buffer| buffer.len())
+                })
+                .unwrap_or(0)
+        })
+    }
+
+    fn copy_to_app(&self, offset: usize, data: &[u8]) -> Result<(), ErrorCode> {
+        self.processid.map_or(Err(ErrorCode::RESERVE), |processid| {
+            self.apps
+                .enter(processid, |_, kernel_data| {
+                    kernel_data
+                        .get_readwrite_processbuffer(rw_allow::MESSAGE)
+                        .and_then(|buffer| {
+                            buffer.mut_enter(|message| {
+                                message.get(offset..offset + data.len()).map_or(
+                                    Err(ErrorCode::SIZE),
+                                    |dest| {
+                                        dest.copy_from_slice(data);
+                                        Ok(())
+                                    },
+                                )
+                            })
+                        })
+                        .unwrap_or_else(|err| Err(err.into()))
+                })
+                .unwrap_or_else(|err| Err(err.into()))
+        })
+    }
+
+    fn send_flow_control(&self, status: FlowStatus) {
+        self.rx_flow_control.set(Some(status));
+        self.service_tx();
+    }
+
+    fn finish_rx(&self, result: Result<(), ErrorCode>) {
+        self.rx_active.set(false);
+        self.clear_deadline(&self.rx_deadline);
+        self.schedule_upcall(upcall::RECEIVED, result, self.rx_len.get());
+    }
+
+    fn single_frame_received(&self, frame: &[u8]) {
+        let len = (frame[0] & 0x0F) as usize;
+        if len == 0 || len >= frame.len() {
+            return;
+        }
+        if self.rx_active.get() {
+            self.finish_rx(Err(ErrorCode::CANCEL));
+        }
+
+        self.rx_len.set(len);
+        let result = self.copy_to_app(0, &frame[1..=len]);
+        self.schedule_upcall(upcall::RECEIVED, result, len);
+    }
+
+    fn first_frame_received(&self, frame: &[u8]) {
+        let len = (((frame[0] & 0x0F) as usize) << 8) | frame[1] as usize;
+        if frame.len() < STANDARD_CAN_PACKET_SIZE || len <= SINGLE_FRAME_DATA_LEN {
+            return;
+        }
+        if self.rx_active.get() {
+            self.finish_rx(Err(ErrorCode::CANCEL));
+        }
+
+        self.rx_len.set(len);
+        if len > self.receive_capacity() {
+            self.send_flow_control(FlowStatus::Overflow);
+            self.schedule_upcall(upcall::RECEIVED, Err(ErrorCode::SIZE), len);
+            return;
+        }
+        if let Err(err) = self.copy_to_app(0, &frame[2..]) {
+            self.schedule_upcall(upcall::RECEIVED, Err(err), len);
+            return;
+        }
+
+        self.rx_active.set(true);
+        self.rx_offset.set(FIRST_FRAME_DATA_LEN);
+        self.rx_sequence.set(1);
+        self.rx_block_count.set(0);
+        self.set_deadline(&self.rx_deadline, self.alarm.ticks_from_ms(TIMEOUT_MS));
+        self.send_flow_control(FlowStatus::ContinueToSend);
+    }
+
+    fn consecutive_frame_received(&self, frame: &[u8]) {
+        if !self.rx_active.get() {
+            return;
+        }
+        if frame[0] & 0x0F != self.rx_sequence.get() {
+            self.finish_rx(Err(ErrorCode::FAIL));
+            return;
+        }
+
+        let offset = self.rx_offset.get();
+        let data_len = cmp::min(
+            cmp::min(CONSECUTIVE_FRAME_DATA_LEN, self.rx_len.get() - offset),
+            frame.len() - 1,
+        );
+        if let Err(err) = self.copy_to_app(offset, &frame[1..=data_len]) {
+            self.finish_rx(Err(err));
+            return;
+        }
+        self.rx_offset.set(offset + data_len);
+        self.rx_sequence.set((self.rx_sequence.get() + 1) & 0x0F);
+
+        if self.rx_offset.get() == self.rx_len.get() {
+            self.finish_rx(Ok(()));
+            return;
+        }
+
+        self.set_deadline(&self.rx_deadline, self.alarm.ticks_from_ms(TIMEOUT_MS));
+        if self.rx_block_size.get() != 0 {
+            self.rx_block_count.set(self.rx_block_count.get() + 1);
+            if self.rx_block_count.get() == self.rx_block_size.get() {
+                self.rx_block_count.set(0);
+                self.send_flow_control(FlowStatus::ContinueToSend);
+            }
+        }
+    }
+
+    /// Stops all transfers and releases the capsule.
+    fn close(&self) -> Result<(), ErrorCode> {
+        if self.receiving.get() {
+            self.can.stop_receive()?;
+        }
+        self.tx_state.set(TxState::Idle);
+        self.rx_active.set(false);
+        self.rx_flow_control.set(None);
+        self.tx_deadline.clear();
+        self.clear_deadline(&self.rx_deadline);
+        self.tx_id.clear();
+        self.rx_id.clear();
+        self.processid.clear();
+        Ok(())
+    }
+}
+
+impl<
+    'a,
+    C: can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>,
+    A: Alarm<'a>,
+> SyscallDriver for CanIsoTp<'a, C, A>
+{
+    /// Send and receive ISO-TP messages.
+    ///
+    /// Identifiers are standard 11-bit identifiers, unless
+    /// [`EXTENDED_ID_FLAG`] is set.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Start receiving, with `data1` as the identifier of frames to
+    ///   send and `data2` as the identifier of frames to accept. Returns
+    ///   `BUSY` while a message is being sent or received.
+    /// - `2`: Set the flow control parameters for receiving: the block size
+    ///   (0 for unlimited) in `data1` and STmin in `data2`, both encoded as in
+    ///   a flow control frame.
+    /// - `3`: Send the first `data1` bytes of the read-only buffer as a
+    ///   message.
+    /// - `4`: Stop receiving, abort all transfers and release the capsule
+    ///   for other processes.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        if command_num == 0 {
+            return CommandReturn::success();
+        }
+
+        // Only one application can use the capsule at a time.
+        if !self.is_valid_process(processid) {
+            return CommandReturn::failure(ErrorCode::RESERVE);
+        } else {
+            self.processid.set(processid);
+        }
+
+        match command_num {
+            1 => {
+                if self.tx_state.get() != TxState::Idle || self.rx_active.get() {
+                    return CommandReturn::failure(ErrorCode::BUSY);
+                }
+                self.tx_id.set(Self::id_from_user(data1));
+                self.rx_id.set(Self::id_from_user(data2));
+                if self.receiving.get() {
+                    return CommandReturn::success();
+                }
+                self.can_rx
+                    .take()
+                    .map_or(CommandReturn::failure(ErrorCode::BUSY), |buffer| match self
+                        .can
+                        .start_receive_process(buffer)
+                    {
+                        Ok(()) => {
+                            self.receiving.set(true);
+                            CommandReturn::success()
+                        }
+                        Err((err, buffer)) => {
+                            self.can_rx.replace(buffer);
+                            CommandReturn::failure(err)
+                        }
+                    })
+            }
+
+            2 => {
+                if data1 > u8::MAX as usize || !Self::is_valid_separation_time(data2) {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+                self.rx_block_size.set(data1 as u8);
+                self.rx_separation_time.set(data2 as u8);
+                CommandReturn::success()
+            }
+
+            3 => self.start_tx(data1).into(),
+
+            4 => self.close().into(),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+impl<
+    'a,
+    C: can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>,
+    A: Alarm<'a>,
+> can::TransmitClient<STANDARD_CAN_PACKET_SIZE> for CanIsoTp<'a, C, A>
+{
+    fn transmit_complete(
+        &self,
+        status: Result<(), can::Error>,
+        buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE],
+    ) {
+        self.can_tx.replace(buffer);
+        match self.in_flight.take() {
+            Some(FrameType::FlowControl) => {
+                if let Err(err) = status {
+                    if self.rx_active.get() {
+                        self.finish_rx(Err(err.into()));
+                    }
+                }
+            }
+            Some(frame_type) if self.tx_state.get() == TxState::Sending => match status {
+                Ok(()) => self.data_frame_sent(frame_type),
+                Err(err) => self.finish_tx(Err(err.into())),
+            },
+            _ => {}
+        }
+        self.service_tx();
+    }
+}
+
+impl<
+    'a,
+    C: can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>,
+    A: Alarm<'a>,
+> can::ReceiveClient<STANDARD_CAN_PACKET_SIZE> for CanIsoTp<'a, C, A>
+{
+    fn message_received(
+        &self,
+        id: can::Id,
+        buffer: &mut [u8; STANDARD_CAN_PACKET_SIZE],
+        len: usize,
+        status: Result<(), can::Error>,
+    ) {
+        if status.is_err() || self.rx_id.get() != Some(id) || len == 0 {
+            return;
+        }
+        let frame = &buffer[..cmp::min(len, STANDARD_CAN_PACKET_SIZE)];
+
+        match frame[0] >> 4 {
+            pci if pci == FrameType::Single as u8 => self.single_frame_received(frame),
+            pci if pci == FrameType::First as u8 => self.first_frame_received(frame),
+            pci if pci == FrameType::Consecutive as u8 => self.consecutive_frame_received(frame),
+            pci if pci == FrameType::FlowControl as u8 => self.flow_control_received(frame),
+            _ => {}
+        }
+    }
+
+    fn stopped(&self, buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE]) {
+        self.can_rx.replace(buffer);
+        self.receiving.set(false);
+    }
+}
+
+impl<
+    'a,
+    C: can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE>,
+    A: Alarm<'a>,
+> time::AlarmClient for CanIsoTp<'a, C, A>
+{
+    fn alarm(&self) {
+        let now = self.alarm.now();
+        let expired = |deadline: &OptionalCell<Deadline<A::Ticks>>| {
+            deadline
+                .get()
+                .is_some_and(|deadline| deadline.remaining(now).is_none())
+        };
+
+        if expired(&self.tx_deadline) {
+            self.tx_deadline.clear();
+            match self.tx_state.get() {
+                TxState::WaitFlowControl => self.finish_tx(Err(ErrorCode::NOACK)),
+                TxState::Separation => {
+                    self.tx_state.set(TxState::Ready);
+                    self.service_tx();
+                }
+                _ => {}
+            }
+        }
+        if expired(&self.rx_deadline) {
+            self.rx_deadline.clear();
+            if self.rx_active.get() {
+                self.finish_rx(Err(ErrorCode::NOACK));
+            }
+        }
+        self.rearm();
+    }
+}
@@ -0,0 +1,458 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Syscall driver capsule for sharing a CAN bus between processes.
+//!
+//! Unlike the raw CAN driver, which gives one process control of the
+//! controller, this capsule lets any number of processes send and receive
+//! classic CAN frames at the same time. The board configures and enables the
+//! controller; processes cannot change the bit rate or operation mode.
+//!
+//! Each process registers up to [`PROCESS_FILTER_COUNT`] acceptance filters,
+//! made of an identifier and a mask, and only receives the frames that pass
+//! one of them. A process without filters receives every frame. The filters
+//! of all receiving processes are passed down to the CAN device, normally a
+//! [`VirtualCanDevice`](capsules_core::virtualizers::virtual_can::VirtualCanDevice),
+//! so that the controller can drop frames no process wants; each process'
+//! filters are also applied in software.
+//!
+//! Every process receives into its own streaming buffer, so a slow process
+//! loses frames without affecting the others. Sends are queued, one per
+//! process, and go out one at a time.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let shared_can = static_init!(
+//!     capsules_extra::can_shared::SharedCan<'static, VirtualCanDevice<'static, Can>>,
+//!     capsules_extra::can_shared::SharedCan::new(device, grant, tx_buffer, rx_buffer)
+//! );
+//! kernel::hil::can::Transmit::set_client(device, Some(shared_can));
+//! kernel::hil::can::Receive::set_client(device, Some(shared_can));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::ErrorCode;
+use kernel::ProcessId;
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::can::{self, STANDARD_CAN_PACKET_SIZE};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::streaming_process_slice::StreamingProcessSlice;
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::CanShared as usize;
+
+/// Identifiers from userspace use the same encoding as the ISO-TP driver.
+pub use crate::can_isotp::EXTENDED_ID_FLAG;
+
+/// Number of acceptance filters each process can register.
+pub const PROCESS_FILTER_COUNT: usize = 4;
+
+/// Size of a received frame in the receive buffer.
+///
+/// A frame is stored as the identifier (a `u32` in little endian, with
+/// [`EXTENDED_ID_FLAG`] set for extended identifiers), the data length,
+/// three reserved bytes and eight data bytes.
+pub const FRAME_RECORD_LEN: usize = 16;
+
+/// Ids for subscribed upcalls.
+mod upcall {
+    /// A frame was sent or failed to send.
+    pub const SENT: usize = 0;
+    /// Frames were appended to the receive buffer, or receiving failed.
+    pub const RECEIVED: usize = 1;
+    /// Number of upcalls.
+    pub const COUNT: u8 = 2;
+}
+
+/// Ids for read-only allow buffers.
+mod ro_allow {
+    /// Data of the frame to send.
+    pub const TX: usize = 0;
+    /// Number of read-only allow buffers.
+    pub const COUNT: u8 = 1;
+}
+
+/// Ids for read-write allow buffers.
+mod rw_allow {
+    /// Streaming buffer for received frames.
+    pub const RX: usize = 0;
+    /// Number of read-write allow buffers.
+    pub const COUNT: u8 = 1;
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum RxState {
+    Idle,
+    Receiving,
+    /// Waiting for the device to return the receive buffer.
+    Stopping,
+}
+
+#[derive(Default)]
+pub struct App {
+    filters: [Option<can::FilterParameters>; PROCESS_FILTER_COUNT],
+    receiving: bool,
+    pending_tx: Option<(can::Id, usize)>,
+    lost_messages: u32,
+}
+
+impl App {
+    fn filter_count(&self) -> usize {
+        self.filters.iter().flatten().count()
+    }
+
+    fn accepts(&self, id: can::Id) -> bool {
+        self.filter_count() == 0 || self.filters.iter().flatten().any(|f| f.matches(id))
+    }
+}
+
+pub struct SharedCan<
+    'a,
+    D: can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE> + can::Filter,
+> {
+    device: &'a D,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    tx_buffer: TakeCell<'static, [u8; STANDARD_CAN_PACKET_SIZE]>,
+    rx_buffer: TakeCell<'static, [u8; STANDARD_CAN_PACKET_SIZE]>,
+    sending: OptionalCell<ProcessId>,
+    rx_state: Cell<RxState>,
+}
+
+impl<
+    'a,
+    D: can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE> + can::Filter,
+> SharedCan<'a, D>
+{
+    pub fn new(
+        device: &'a D,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        tx_buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE],
+        rx_buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE],
+    ) -> Self {
+        Self {
+            device,
+            apps: grant,
+            tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            sending: OptionalCell::empty(),
+            rx_state: Cell::new(RxState::Idle),
+        }
+    }
+
+    fn id_from_user(id: usize) -> can::Id {
+        if id & EXTENDED_ID_FLAG != 0 {
+            can::Id::Extended((id & !EXTENDED_ID_FLAG) as u32)
+        } else {
+            can::Id::Standard(id as u16)
+        }
+    }
+
+    fn id_to_user(id: can::Id) -> u32 {
+        match id {
+            can::Id::Standard(id) => id as u32,
+            can::Id::Extended(id) => id | EXTENDED_ID_FLAG as u32,
+        }
+    }
+
+    /// Starts sending the next queued frame, if no frame is being sent.
+    fn do_next_send(&self) {
+        if self.sending.is_some() {
+            return;
+        }
+        for app in self.apps.iter() {
+            let processid = app.processid();
+            let started = app.enter(|app, kernel_data| {
+                let Some((id, len)) = app.pending_tx.take() else {
+                    return false;
+                };
+                let Some(buffer) = self.tx_buffer.take() else {
+                    app.pending_tx = Some((id, len));
+                    return false;
+                };
+                let copied = kernel_data
+                    .get_readonly_processbuffer(ro_allow::TX)
+                    .and_then(|tx| {
+                        tx.enter(|data| {
+                            if data.len() < len {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            data[..len].copy_to_slice(&mut buffer[..len]);
+                            Ok(())
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE));
+                let result = match copied {
+                    Ok(()) => self.device.send(id, buffer, len).map_err(|(err, buffer)| {
+                        self.tx_buffer.replace(buffer);
+                        err
+                    }),
+                    Err(err) => {
+                        self.tx_buffer.replace(buffer);
+                        Err(err)
+                    }
+                };
+                if let Err(err) = result {
+                    let _ = kernel_data
+                        .schedule_upcall(upcall::SENT, (into_statuscode(Err(err)), 0, 0));
+                }
+                result.is_ok()
+            });
+            if started {
+                self.sending.set(processid);
+                break;
+            }
+        }
+    }
+
+    /// Starts or stops the device depending on whether any process is
+    /// receiving, and passes the filters of the receiving processes to it.
+    fn update_receive(&self) -> Result<(), ErrorCode> {
+        let mut receiving = false;
+        let mut accept_all = false;
+        let mut count = 0;
+        self.apps.each(|_, app, _| {
+            if app.receiving {
+                receiving = true;
+                accept_all |= app.filter_count() == 0;
+                count += app.filter_count();
+            }
+        });
+
+        // The device accepts every frame when it has no filters enabled
+        let mut number = 0;
+        if !accept_all && count <= self.device.filter_count() {
+            self.apps.each(|_, app, _| {
+                for filter in app.filters.iter().flatten().filter(|_| app.receiving) {
+                    let enabled = self
+                        .device
+                        .enable_filter(can::FilterParameters { number, ..*filter });
+                    if enabled.is_ok() {
+                        number += 1;
+                    }
+                }
+            });
+        }
+        for number in number..self.device.filter_count() as u32 {
+            let _ = self.device.disable_filter(number);
+        }
+
+        match (receiving, self.rx_state.get()) {
+            (true, RxState::Idle) => {
+                let buffer = self.rx_buffer.take().ok_or(ErrorCode::FAIL)?;
+                self.device
+                    .start_receive_process(buffer)
+                    .map_err(|(err, buffer)| {
+                        self.rx_buffer.replace(buffer);
+                        err
+                    })?;
+                self.rx_state.set(RxState::Receiving);
+            }
+            (false, RxState::Receiving) => {
+                self.device.stop_receive()?;
+                self.rx_state.set(RxState::Stopping);
+            }
+            // Receiving restarts once the device has stopped
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Appends a frame to the receive buffer of every process that accepts
+    /// it.
+    fn deliver(&self, id: can::Id, data: &[u8]) {
+        let mut record = [0; FRAME_RECORD_LEN];
+        record[0..4].copy_from_slice(&Self::id_to_user(id).to_le_bytes());
+        record[4] = data.len() as u8;
+        record[8..8 + data.len()].copy_from_slice(data);
+
+        self.apps.each(|_, app, kernel_data| {
+            if !app.receiving || !app.accepts(id) {
+                return;
+            }
+            let appended = kernel_data
+                .get_readwrite_processbuffer(rw_allow::RX)
+                .and_then(|rx| {
+                    rx.mut_enter(|slice| StreamingProcessSlice::new(slice).append_chunk(&record))
+                })
+                .unwrap_or(Err(ErrorCode::RESERVE));
+            match appended {
+                Ok((_first_chunk, offset)) => {
+                    let _ = kernel_data.schedule_upcall(
+                        upcall::RECEIVED,
+                        (0, offset as usize, app.lost_messages as usize),
+                    );
+                }
+                Err(_) => app.lost_messages += 1,
+            }
+        });
+    }
+}
+
+impl<
+    D: can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE> + can::Filter,
+> SyscallDriver for SharedCan<'_, D>
+{
+    /// Command interface.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Return Ok(()) if this driver is included on the platform.
+    /// - `1`: Add an acceptance filter. `data1` is the identifier, with
+    ///   [`EXTENDED_ID_FLAG`] set for an extended identifier, and `data2` is
+    ///   the mask of identifier bits that must match. Returns `NOMEM` if
+    ///   the process already has [`PROCESS_FILTER_COUNT`] filters.
+    /// - `2`: Remove all of the process' filters.
+    /// - `3`: Start receiving frames into the read-write buffer.
+    /// - `4`: Stop receiving frames.
+    /// - `5`: Send `data2` bytes from the read-only buffer with the
+    ///   identifier `data1`. Returns `BUSY` if the process' previous frame has
+    ///   not been sent yet.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        let result = match command_num {
+            0 => return CommandReturn::success(),
+            1 => self
+                .apps
+                .enter(processid, |app, _| {
+                    let receiving = app.receiving;
+                    let slot = app.filters.iter_mut().find(|filter| filter.is_none());
+                    slot.map_or(Err(ErrorCode::NOMEM), |slot| {
+                        *slot = Some(can::FilterParameters {
+                            number: 0,
+                            scale_bits: can::ScaleBits::Bits32,
+                            identifier_mode: can::IdentifierMode::Mask,
+                            fifo_number: 0,
+                            id: Self::id_from_user(data1),
+                            mask: data2 as u32,
+                        });
+                        Ok(receiving)
+                    })
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            2 => self
+                .apps
+                .enter(processid, |app, _| {
+                    app.filters = Default::default();
+                    Ok(app.receiving)
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            3 | 4 => self
+                .apps
+                .enter(processid, |app, _| {
+                    app.receiving = command_num == 3;
+                    Ok(true)
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            5 => {
+                if data2 > STANDARD_CAN_PACKET_SIZE {
+                    return CommandReturn::failure(ErrorCode::SIZE);
+                }
+                let queued = self
+                    .apps
+                    .enter(processid, |app, _| {
+                        if app.pending_tx.is_some() || self.sending.contains(&processid) {
+                            return Err(ErrorCode::BUSY);
+                        }
+                        app.pending_tx = Some((Self::id_from_user(data1), data2));
+                        Ok(())
+                    })
+                    .unwrap_or_else(|err| Err(err.into()));
+                if queued.is_ok() {
+                    self.do_next_send();
+                }
+                return queued.into();
+            }
+            _ => return CommandReturn::failure(ErrorCode::NOSUPPORT),
+        };
+
+        // Changes to a receiving process' filters update the device
+        match result {
+            Ok(true) => {
+                let updated = self.update_receive();
+                if updated.is_err() && command_num == 3 {
+                    let _ = self.apps.enter(processid, |app, _| app.receiving = false);
+                }
+                updated.into()
+            }
+            Ok(false) => CommandReturn::success(),
+            Err(err) => CommandReturn::failure(err),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+impl<
+    D: can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE> + can::Filter,
+> can::TransmitClient<STANDARD_CAN_PACKET_SIZE> for SharedCan<'_, D>
+{
+    fn transmit_complete(
+        &self,
+        status: Result<(), can::Error>,
+        buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE],
+    ) {
+        self.tx_buffer.replace(buffer);
+        if let Some(processid) = self.sending.take() {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                let status = status.map_err(ErrorCode::from);
+                let _ = kernel_data.schedule_upcall(upcall::SENT, (into_statuscode(status), 0, 0));
+            });
+        }
+        self.do_next_send();
+    }
+}
+
+impl<
+    D: can::Transmit<STANDARD_CAN_PACKET_SIZE> + can::Receive<STANDARD_CAN_PACKET_SIZE> + can::Filter,
+> can::ReceiveClient<STANDARD_CAN_PACKET_SIZE> for SharedCan<'_, D>
+{
+    fn message_received(
+        &self,
+        id: can::Id,
+        buffer: &mut [u8; STANDARD_CAN_PACKET_SIZE],
+        len: usize,
+        status: Result<(), can::Error>,
+    ) {
+        match status {
+            Ok(()) => self.deliver(id, &buffer[..len.min(STANDARD_CAN_PACKET_SIZE)]),
+            Err(err) => self.apps.each(|_, app, kernel_data| {
+                if app.receiving {
+                    let status = into_statuscode(Err(err.into()));
+                    let _ = kernel_data.schedule_upcall(upcall::RECEIVED, (status, 0, 0));
+                }
+            }),
+        }
+    }
+
+    fn stopped(&self, buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE]) {
+        self.rx_buffer.replace(buffer);
+        self.rx_state.set(RxState::Idle);
+        // A process may have started receiving while the device stopped
+        let _ = self.update_receive();
+    }
+}
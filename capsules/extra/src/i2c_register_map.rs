@@ -0,0 +1,333 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Emulate register-mapped I2C devices with the I2C slave hardware.
+//!
+//! `MuxI2CRegisterMap` listens as an I2C slave and answers the master from
+//! one or more `I2CRegisterMap`s, each at its own address, so the board can
+//! present itself as a standard register-mapped device (e.g. a sensor) to a
+//! host processor. Transfers are handled in the kernel, without waiting on a
+//! process.
+//!
+//! Each map follows the usual register-pointer protocol:
+//!
+//! - A write from the master starts with the register address, which sets
+//!   the map's register pointer. The following bytes are written to
+//!   consecutive registers.
+//! - A read from the master returns consecutive registers, starting at the
+//!   register pointer.
+//!
+//! The pointer auto-increments after each byte. Writes to read-only
+//! registers are ignored and write-only registers read as 0. Reads past the
+//! last register return 0xFF and writes past it are ignored. After the
+//! master writes registers, the map's client is notified so it can act on
+//! the new values.
+//!
+//! The read buffer is filled when the hardware reports that the master
+//! started a read, while it stretches the clock, so the master always reads
+//! the current register values.
+//!
+//! The mux can serve more maps than the single address of `I2CSlave` if the
+//! hardware implements `I2CSlaveMultiAddress`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! # use kernel::static_init;
+//!
+//! let mux = static_init!(
+//!     MuxI2CRegisterMap<'static, nrf52840::i2c::TWI<'static>>,
+//!     MuxI2CRegisterMap::new(&base_peripherals.twi1, Some(&base_peripherals.twi1),
+//!         write_buffer, read_buffer)
+//! );
+//! I2CSlave::set_slave_client(&base_peripherals.twi1, mux);
+//!
+//! let map = static_init!(
+//!     I2CRegisterMap<'static>,
+//!     I2CRegisterMap::new(0x1D, registers, &ACCESS)
+//! );
+//! mux.add_map(map);
+//! mux.start().unwrap();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::ErrorCode;
+use kernel::collections::list::{List, ListLink, ListNode};
+use kernel::hil::i2c::{self, I2CHwSlaveClient, I2CSlave, I2CSlaveMultiAddress};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+/// Value read from addresses past the last register.
+pub const UNMAPPED_VALUE: u8 = 0xFF;
+
+/// How the master can access a register.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RegisterAccess {
+    ReadWrite,
+    ReadOnly,
+    WriteOnly,
+}
+
+/// Client notified when the master writes the registers of a map.
+pub trait RegisterMapClient {
+    /// The master wrote `count` registers, starting at `first`. Writes to
+    /// read-only registers in the range were ignored.
+    fn registers_written(&self, first: u8, count: usize);
+}
+
+/// The registers of one emulated device.
+pub struct I2CRegisterMap<'a> {
+    address: u8,
+    registers: TakeCell<'static, [u8]>,
+    access: &'a [RegisterAccess],
+    pointer: Cell<u8>,
+    // Index of the hardware address assigned to this map
+    index: OptionalCell<usize>,
+    client: OptionalCell<&'a dyn RegisterMapClient>,
+    next: ListLink<'a, I2CRegisterMap<'a>>,
+}
+
+impl<'a> I2CRegisterMap<'a> {
+    /// `access` gives the access of the registers starting from register 0.
+    /// Registers past the end of `access` are read-write.
+    pub fn new(address: u8, registers: &'static mut [u8], access: &'a [RegisterAccess]) -> Self {
+        Self {
+            address,
+            registers: TakeCell::new(registers),
+            access,
+            pointer: Cell::new(0),
+            index: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+            next: ListLink::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn RegisterMapClient) {
+        self.client.set(client);
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Sets registers from the kernel, regardless of their access.
+    pub fn set_registers(&self, first: u8, data: &[u8]) -> Result<(), ErrorCode> {
+        self.registers.map_or(Err(ErrorCode::FAIL), |registers| {
+            let first = first as usize;
+            registers
+                .get_mut(first..first + data.len())
+                .ok_or(ErrorCode::INVAL)?
+                .copy_from_slice(data);
+            Ok(())
+        })
+    }
+
+    /// Reads registers from the kernel, regardless of their access.
+    pub fn get_registers(&self, first: u8, data: &mut [u8]) -> Result<(), ErrorCode> {
+        self.registers.map_or(Err(ErrorCode::FAIL), |registers| {
+            let first = first as usize;
+            data.copy_from_slice(
+                registers
+                    .get(first..first + data.len())
+                    .ok_or(ErrorCode::INVAL)?,
+            );
+            Ok(())
+        })
+    }
+
+    fn access(&self, register: usize) -> RegisterAccess {
+        self.access
+            .get(register)
+            .copied()
+            .unwrap_or(RegisterAccess::ReadWrite)
+    }
+
+    /// Handles a write from the master: a register address followed by
+    /// the values of consecutive registers.
+    fn master_write(&self, data: &[u8]) {
+        let Some((&first, values)) = data.split_first() else {
+            return;
+        };
+        self.pointer.set(first.wrapping_add(values.len() as u8));
+        if values.is_empty() {
+            return;
+        }
+
+        let written = self.registers.map_or(0, |registers| {
+            let start = first as usize;
+            let count = cmp::min(values.len(), registers.len().saturating_sub(start));
+            for (offset, value) in values[..count].iter().enumerate() {
+                if self.access(start + offset) != RegisterAccess::ReadOnly {
+                    registers[start + offset] = *value;
+                }
+            }
+            count
+        });
+        if written > 0 {
+            self.client
+                .map(|client| client.registers_written(first, written));
+        }
+    }
+
+    /// Fills `buffer` with the registers the master reads next.
+    fn fill_read(&self, buffer: &mut [u8]) {
+        let first = self.pointer.get();
+        self.registers.map(|registers| {
+            for (offset, byte) in buffer.iter_mut().enumerate() {
+                let register = first.wrapping_add(offset as u8) as usize;
+                *byte = match registers.get(register) {
+                    None => UNMAPPED_VALUE,
+                    Some(_) if self.access(register) == RegisterAccess::WriteOnly => 0,
+                    Some(value) => *value,
+                };
+            }
+        });
+    }
+
+    /// Moves the register pointer past the `length` bytes the master read.
+    fn master_read(&self, length: usize) {
+        self.pointer
+            .set(self.pointer.get().wrapping_add(length as u8));
+    }
+}
+
+impl<'a> ListNode<'a, I2CRegisterMap<'a>> for I2CRegisterMap<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, I2CRegisterMap<'a>> {
+        &self.next
+    }
+}
+
+pub struct MuxI2CRegisterMap<'a, S: I2CSlave<'a>> {
+    i2c: &'a S,
+    multi_address: Option<&'a dyn I2CSlaveMultiAddress>,
+    maps: List<'a, I2CRegisterMap<'a>>,
+    write_buffer: TakeCell<'static, [u8]>,
+    read_buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a, S: I2CSlave<'a>> MuxI2CRegisterMap<'a, S> {
+    /// `multi_address` is the hardware's interface for more than one
+    /// address, if it has one. `write_buffer` bounds the number of bytes
+    /// the master can write in one transfer and `read_buffer` the number of
+    /// bytes it can read.
+    pub fn new(
+        i2c: &'a S,
+        multi_address: Option<&'a dyn I2CSlaveMultiAddress>,
+        write_buffer: &'static mut [u8],
+        read_buffer: &'static mut [u8],
+    ) -> Self {
+        Self {
+            i2c,
+            multi_address,
+            maps: List::new(),
+            write_buffer: TakeCell::new(write_buffer),
+            read_buffer: TakeCell::new(read_buffer),
+        }
+    }
+
+    /// Adds a map. Maps must be added before calling `start`.
+    pub fn add_map(&self, map: &'a I2CRegisterMap<'a>) {
+        self.maps.push_head(map);
+    }
+
+    /// Sets the addresses of the maps and starts listening.
+    ///
+    /// Returns `NOSUPPORT` if there are more maps than the hardware has
+    /// addresses, or `INVAL` if there are no maps.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        let address_count = self
+            .multi_address
+            .map_or(1, |multi_address| multi_address.address_count());
+        let map_count = self.maps.iter().count();
+        if map_count == 0 {
+            return Err(ErrorCode::INVAL);
+        }
+        if map_count > address_count {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+
+        for (index, map) in self.maps.iter().enumerate() {
+            match self.multi_address {
+                Some(multi_address) => multi_address.set_address_at(index, map.address)?,
+                None => self.i2c.set_address(map.address)?,
+            }
+            map.index.set(index);
+        }
+
+        self.receive_write();
+        self.i2c.enable();
+        self.i2c.listen();
+        Ok(())
+    }
+
+    /// Stops listening.
+    pub fn stop(&self) {
+        self.i2c.disable();
+    }
+
+    /// The map the master addressed in the current transfer.
+    fn current_map(&self) -> Option<&'a I2CRegisterMap<'a>> {
+        let index = self
+            .multi_address
+            .map_or(0, |multi_address| multi_address.matched_address());
+        self.maps.iter().find(|map| map.index.contains(&index))
+    }
+
+    /// Gives the write buffer to the hardware for the next write from the
+    /// master.
+    fn receive_write(&self) {
+        self.write_buffer.take().map(|buffer| {
+            let len = buffer.len();
+            if let Err((_, buffer)) = self.i2c.write_receive(buffer, len) {
+                self.write_buffer.replace(buffer);
+            }
+        });
+    }
+}
+
+impl<'a, S: I2CSlave<'a>> I2CHwSlaveClient for MuxI2CRegisterMap<'a, S> {
+    fn command_complete(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+        transmission_type: i2c::SlaveTransmissionType,
+    ) {
+        let map = self.current_map();
+        match transmission_type {
+            i2c::SlaveTransmissionType::Write => {
+                let length = cmp::min(length, buffer.len());
+                if let Some(map) = map {
+                    map.master_write(&buffer[..length]);
+                }
+                self.write_buffer.replace(buffer);
+                self.receive_write();
+            }
+            i2c::SlaveTransmissionType::Read => {
+                if let Some(map) = map {
+                    map.master_read(length);
+                }
+                self.read_buffer.replace(buffer);
+            }
+        }
+    }
+
+    fn read_expected(&self) {
+        self.read_buffer.take().map(|buffer| {
+            match self.current_map() {
+                Some(map) => map.fill_read(buffer),
+                None => buffer.fill(UNMAPPED_VALUE),
+            }
+            let len = buffer.len();
+            if let Err((_, buffer)) = self.i2c.read_send(buffer, len) {
+                self.read_buffer.replace(buffer);
+            }
+        });
+    }
+
+    fn write_expected(&self) {
+        self.receive_write();
+    }
+}
@@ -0,0 +1,480 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Simulated IEEE 802.15.4 radio, tunnelled over Ethernet to a host-side
+//! radio medium.
+//!
+//! This implements the 802.15.4 radio HIL on top of an Ethernet adapter, so
+//! that the 802.15.4, 6LoWPAN and Thread stacks can run on emulated boards
+//! (for example `qemu_rv32_virt` with a VirtIO network card). Every 802.15.4
+//! frame is sent to the medium as one broadcast Ethernet frame, and the medium
+//! simulator (`tools/debugging-and-development/ieee802154_medium.py`)
+//! forwards it to the nodes in range, possibly dropping it, according to its
+//! topology and loss configuration.
+//!
+//! Frame format
+//! ------------
+//!
+//! ```text
+//! +-----------------+-----------+---------+---------+-----+-----+------------+
+//! | Dst MAC (bcast) | Src MAC   | 0x88B5  | Version | Ch. | LQI | Len | PSDU |
+//! +-----------------+-----------+---------+---------+-----+-----+------------+
+//!   6 bytes           6 bytes     2 bytes   1 byte   1 byte 1 byte 1 byte
+//! ```
+//!
+//! The PSDU excludes the frame check sequence, as the medium delivers frames
+//! either intact or not at all. The sender sets the LQI to 255 and the medium
+//! may lower it according to the link quality.
+//!
+//! Like a transceiver, the radio only receives frames sent on the channel it
+//! is tuned to, acknowledges frames addressed to it which request an
+//! acknowledgement, and waits for the acknowledgement of its own frames.
+//! Address filtering of received frames is left to the upper layers.
+
+use core::cell::Cell;
+
+use kernel::ErrorCode;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::ethernet::{EthernetAdapterDatapath, EthernetAdapterDatapathClient};
+use kernel::hil::radio::{self, RadioChannel, RadioConfig};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+/// EtherType of the tunnelled frames (IEEE 802 local experimental).
+pub const ETHERTYPE: u16 = 0x88B5;
+/// Version of the medium frame format.
+pub const VERSION: u8 = 1;
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const MEDIUM_HEADER_LEN: usize = 4;
+const HEADER_LEN: usize = ETHERNET_HEADER_LEN + MEDIUM_HEADER_LEN;
+
+/// Length of the Ethernet frame buffer passed to [`MediumRadio::new`].
+pub const FRAME_BUF_LEN: usize = HEADER_LEN + radio::MAX_FRAME_SIZE;
+
+/// How long to wait for an acknowledgement. Much longer than on air, as the
+/// frames go through the host.
+const ACK_WAIT_MS: u32 = 20;
+
+/// Frame control bits, in the first byte of the PSDU.
+const FRAME_TYPE_MASK: u8 = 0b111;
+const FRAME_TYPE_ACK: u8 = 0b010;
+const ACK_REQUEST: u8 = 1 << 5;
+/// Destination addressing mode, in the second byte of the PSDU.
+const DST_MODE_SHIFT: u8 = 2;
+const ADDRESS_MODE_SHORT: u8 = 0b10;
+const ADDRESS_MODE_LONG: u8 = 0b11;
+/// Length of an acknowledgement: frame control and sequence number.
+const ACK_LEN: usize = 3;
+
+/// `transmission_identifier`s of the Ethernet frames.
+const TX_DATA: usize = 0;
+const TX_ACK: usize = 1;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum TxState {
+    Idle,
+    /// Waiting for the Ethernet adapter to finish sending an acknowledgement.
+    Queued,
+    Sending,
+    /// Waiting for the acknowledgement with this sequence number.
+    WaitingAck(u8),
+}
+
+pub struct MediumRadio<'a, E: EthernetAdapterDatapath<'a>, A: Alarm<'a>> {
+    ethernet: &'a E,
+    alarm: &'a A,
+    mac: [u8; 6],
+    /// Ethernet frame buffer, empty while the adapter sends it.
+    frame: TakeCell<'static, [u8]>,
+    tx_buf: TakeCell<'static, [u8]>,
+    tx_state: Cell<TxState>,
+    /// Sequence number of a received frame to acknowledge once the frame
+    /// buffer is back.
+    pending_ack: Cell<Option<u8>>,
+    rx_buf: TakeCell<'static, [u8]>,
+    on: Cell<bool>,
+
+    address: Cell<u16>,
+    address_long: Cell<[u8; 8]>,
+    pan: Cell<u16>,
+    tx_power: Cell<i8>,
+    channel: Cell<RadioChannel>,
+    /// The channel in use, set from `channel` on `config_commit`.
+    tuned_channel: Cell<RadioChannel>,
+
+    tx_client: OptionalCell<&'a dyn radio::TxClient>,
+    rx_client: OptionalCell<&'a dyn radio::RxClient>,
+    config_client: OptionalCell<&'a dyn radio::ConfigClient>,
+    power_client: OptionalCell<&'a dyn radio::PowerClient>,
+    deferred_call: DeferredCall,
+    config_done_pending: Cell<bool>,
+    power_changed_pending: Cell<bool>,
+}
+
+impl<'a, E: EthernetAdapterDatapath<'a>, A: Alarm<'a>> MediumRadio<'a, E, A> {
+    /// `mac` is the source address of the Ethernet frames, and `frame` must be
+    /// at least [`FRAME_BUF_LEN`] bytes long.
+    pub fn new(ethernet: &'a E, alarm: &'a A, mac: [u8; 6], frame: &'static mut [u8]) -> Self {
+        Self {
+            ethernet,
+            alarm,
+            mac,
+            frame: TakeCell::new(frame),
+            tx_buf: TakeCell::empty(),
+            tx_state: Cell::new(TxState::Idle),
+            pending_ack: Cell::new(None),
+            rx_buf: TakeCell::empty(),
+            on: Cell::new(false),
+            address: Cell::new(0),
+            address_long: Cell::new([0; 8]),
+            pan: Cell::new(0),
+            tx_power: Cell::new(0),
+            channel: Cell::new(RadioChannel::Channel26),
+            tuned_channel: Cell::new(RadioChannel::Channel26),
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            config_client: OptionalCell::empty(),
+            power_client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+            config_done_pending: Cell::new(false),
+            power_changed_pending: Cell::new(false),
+        }
+    }
+
+    /// Send `psdu` to the medium in the frame buffer.
+    fn send_to_medium(
+        &self,
+        frame: &'static mut [u8],
+        psdu: &[u8],
+        id: usize,
+    ) -> Result<(), &'static mut [u8]> {
+        frame[0..6].fill(0xFF);
+        frame[6..12].copy_from_slice(&self.mac);
+        frame[12..14].copy_from_slice(&ETHERTYPE.to_be_bytes());
+        frame[14] = VERSION;
+        frame[15] = self.tuned_channel.get().get_channel_number();
+        frame[16] = 0xFF;
+        frame[17] = psdu.len() as u8;
+        frame[HEADER_LEN..HEADER_LEN + psdu.len()].copy_from_slice(psdu);
+        self.ethernet
+            .transmit_frame(frame, (HEADER_LEN + psdu.len()) as u16, id)
+            .map_err(|(_, frame)| frame)
+    }
+
+    /// Send the frame of the client, once the frame buffer is available.
+    fn send_data(&self) -> Result<(), ErrorCode> {
+        let Some(frame) = self.frame.take() else {
+            self.tx_state.set(TxState::Queued);
+            return Ok(());
+        };
+        let Some(buf) = self.tx_buf.take() else {
+            self.frame.replace(frame);
+            return Err(ErrorCode::FAIL);
+        };
+        let len = buf[radio::PHR_OFFSET] as usize - radio::MFR_SIZE;
+        let res = self.send_to_medium(
+            frame,
+            &buf[radio::PSDU_OFFSET..radio::PSDU_OFFSET + len],
+            TX_DATA,
+        );
+        self.tx_buf.replace(buf);
+        match res {
+            Ok(()) => {
+                self.tx_state.set(TxState::Sending);
+                Ok(())
+            }
+            Err(frame) => {
+                self.frame.replace(frame);
+                self.tx_state.set(TxState::Idle);
+                Err(ErrorCode::FAIL)
+            }
+        }
+    }
+
+    fn send_ack(&self, sequence_number: u8) {
+        match self.frame.take() {
+            Some(frame) => {
+                let ack = [FRAME_TYPE_ACK, 0, sequence_number];
+                if let Err(frame) = self.send_to_medium(frame, &ack, TX_ACK) {
+                    self.frame.replace(frame);
+                }
+            }
+            None => self.pending_ack.set(Some(sequence_number)),
+        }
+    }
+
+    fn transmit_done(&self, acked: bool, result: Result<(), ErrorCode>) {
+        self.tx_state.set(TxState::Idle);
+        if let Some(buf) = self.tx_buf.take() {
+            self.tx_client
+                .map(|client| client.send_done(buf, acked, result));
+        }
+    }
+
+    /// Whether a received frame is addressed to this radio, by its short or
+    /// long address.
+    fn is_for_us(&self, psdu: &[u8]) -> bool {
+        // Frame control, sequence number and destination PAN, then the
+        // destination address.
+        const DST_OFFSET: usize = 5;
+        match (psdu[1] >> DST_MODE_SHIFT) & 0b11 {
+            ADDRESS_MODE_SHORT if psdu.len() >= DST_OFFSET + 2 => {
+                u16::from_le_bytes([psdu[DST_OFFSET], psdu[DST_OFFSET + 1]]) == self.address.get()
+            }
+            ADDRESS_MODE_LONG if psdu.len() >= DST_OFFSET + 8 => psdu[DST_OFFSET..DST_OFFSET + 8]
+                .iter()
+                .eq(self.address_long.get().iter().rev()),
+            _ => false,
+        }
+    }
+
+    fn schedule_deferred(&self) {
+        if !self.deferred_call.is_pending() {
+            self.deferred_call.set();
+        }
+    }
+}
+
+impl<'a, E: EthernetAdapterDatapath<'a>, A: Alarm<'a>> radio::RadioConfig<'a>
+    for MediumRadio<'a, E, A>
+{
+    fn initialize(&self) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+
+    fn start(&self) -> Result<(), ErrorCode> {
+        self.on.set(true);
+        self.ethernet.enable_receive();
+        self.power_changed_pending.set(true);
+        self.schedule_deferred();
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ErrorCode> {
+        self.on.set(false);
+        self.ethernet.disable_receive();
+        self.power_changed_pending.set(true);
+        self.schedule_deferred();
+        Ok(())
+    }
+
+    fn is_on(&self) -> bool {
+        self.on.get()
+    }
+
+    fn busy(&self) -> bool {
+        self.tx_state.get() != TxState::Idle
+    }
+
+    fn set_power_client(&self, client: &'a dyn radio::PowerClient) {
+        self.power_client.set(client);
+    }
+
+    fn config_commit(&self) {
+        self.tuned_channel.set(self.channel.get());
+        self.config_done_pending.set(true);
+        self.schedule_deferred();
+    }
+
+    fn set_config_client(&self, client: &'a dyn radio::ConfigClient) {
+        self.config_client.set(client);
+    }
+
+    fn get_address(&self) -> u16 {
+        self.address.get()
+    }
+
+    fn get_address_long(&self) -> [u8; 8] {
+        self.address_long.get()
+    }
+
+    fn get_pan(&self) -> u16 {
+        self.pan.get()
+    }
+
+    fn get_tx_power(&self) -> i8 {
+        self.tx_power.get()
+    }
+
+    fn get_channel(&self) -> u8 {
+        self.channel.get().get_channel_number()
+    }
+
+    fn set_address(&self, addr: u16) {
+        self.address.set(addr);
+    }
+
+    fn set_address_long(&self, addr: [u8; 8]) {
+        self.address_long.set(addr);
+    }
+
+    fn set_pan(&self, id: u16) {
+        self.pan.set(id);
+    }
+
+    /// The power is recorded but the medium ignores it.
+    fn set_tx_power(&self, power: i8) -> Result<(), ErrorCode> {
+        self.tx_power.set(power);
+        Ok(())
+    }
+
+    fn set_channel(&self, chan: RadioChannel) {
+        self.channel.set(chan);
+    }
+}
+
+impl<'a, E: EthernetAdapterDatapath<'a>, A: Alarm<'a>> radio::RadioData<'a>
+    for MediumRadio<'a, E, A>
+{
+    fn set_transmit_client(&self, client: &'a dyn radio::TxClient) {
+        self.tx_client.set(client);
+    }
+
+    fn set_receive_client(&self, client: &'a dyn radio::RxClient) {
+        self.rx_client.set(client);
+    }
+
+    fn set_receive_buffer(&self, receive_buffer: &'static mut [u8]) {
+        self.rx_buf.replace(receive_buffer);
+    }
+
+    fn transmit(
+        &self,
+        buf: &'static mut [u8],
+        frame_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if !self.on.get() {
+            return Err((ErrorCode::OFF, buf));
+        } else if self.busy() {
+            return Err((ErrorCode::BUSY, buf));
+        } else if frame_len > radio::MAX_FRAME_SIZE - radio::MFR_SIZE
+            || buf.len() < radio::PSDU_OFFSET + frame_len + radio::MFR_SIZE
+        {
+            return Err((ErrorCode::SIZE, buf));
+        }
+
+        buf[radio::PHR_OFFSET] = (frame_len + radio::MFR_SIZE) as u8;
+        self.tx_buf.replace(buf);
+        self.send_data()
+            .or_else(|err| self.tx_buf.take().map_or(Ok(()), |buf| Err((err, buf))))
+    }
+}
+
+impl<'a, E: EthernetAdapterDatapath<'a>, A: Alarm<'a>> EthernetAdapterDatapathClient
+    for MediumRadio<'a, E, A>
+{
+    fn transmit_frame_done(
+        &self,
+        err: Result<(), ErrorCode>,
+        frame_buffer: &'static mut [u8],
+        _len: u16,
+        transmission_identifier: usize,
+        _timestamp: Option<u64>,
+    ) {
+        self.frame.replace(frame_buffer);
+
+        if transmission_identifier == TX_DATA {
+            // Wait for the acknowledgement if the frame requested one.
+            let ack_request = self.tx_buf.map_or(None, |buf| {
+                (buf[radio::PSDU_OFFSET] & ACK_REQUEST != 0)
+                    .then_some(buf[radio::PSDU_OFFSET + radio::MHR_FC_SIZE])
+            });
+            match (err, ack_request) {
+                (Ok(()), Some(sequence_number)) => {
+                    self.tx_state.set(TxState::WaitingAck(sequence_number));
+                    self.alarm
+                        .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ACK_WAIT_MS));
+                }
+                (res, _) => self.transmit_done(false, res),
+            }
+        }
+
+        if let Some(sequence_number) = self.pending_ack.take() {
+            self.send_ack(sequence_number);
+        }
+        if self.tx_state.get() == TxState::Queued {
+            if let Err(err) = self.send_data() {
+                self.transmit_done(false, Err(err));
+            }
+        }
+    }
+
+    fn received_frame(&self, frame: &[u8], _timestamp: Option<u64>) {
+        if !self.on.get()
+            || frame.len() < HEADER_LEN
+            || frame[12..14] != ETHERTYPE.to_be_bytes()
+            || frame[14] != VERSION
+            || frame[15] != self.tuned_channel.get().get_channel_number()
+        {
+            return;
+        }
+        let lqi = frame[16];
+        let len = frame[17] as usize;
+        if len < ACK_LEN
+            || len > radio::MAX_FRAME_SIZE - radio::MFR_SIZE
+            || frame.len() < HEADER_LEN + len
+        {
+            return;
+        }
+        let psdu = &frame[HEADER_LEN..HEADER_LEN + len];
+
+        if psdu[0] & FRAME_TYPE_MASK == FRAME_TYPE_ACK {
+            if self.tx_state.get() == TxState::WaitingAck(psdu[radio::MHR_FC_SIZE]) {
+                let _ = self.alarm.disarm();
+                self.transmit_done(true, Ok(()));
+            }
+            return;
+        }
+
+        if psdu[0] & ACK_REQUEST != 0 && self.is_for_us(psdu) {
+            self.send_ack(psdu[radio::MHR_FC_SIZE]);
+        }
+
+        if let Some(buf) = self.rx_buf.take() {
+            if buf.len() < radio::PSDU_OFFSET + len + radio::MFR_SIZE {
+                self.rx_buf.replace(buf);
+                return;
+            }
+            buf[radio::PHR_OFFSET] = (len + radio::MFR_SIZE) as u8;
+            buf[radio::PSDU_OFFSET..radio::PSDU_OFFSET + len].copy_from_slice(psdu);
+            match self.rx_client.get() {
+                Some(client) => client.receive(buf, len, lqi, true, Ok(())),
+                None => {
+                    self.rx_buf.replace(buf);
+                }
+            }
+        }
+    }
+}
+
+impl<'a, E: EthernetAdapterDatapath<'a>, A: Alarm<'a>> AlarmClient for MediumRadio<'a, E, A> {
+    fn alarm(&self) {
+        if let TxState::WaitingAck(_) = self.tx_state.get() {
+            self.transmit_done(false, Ok(()));
+        }
+    }
+}
+
+impl<'a, E: EthernetAdapterDatapath<'a>, A: Alarm<'a>> DeferredCallClient
+    for MediumRadio<'a, E, A>
+{
+    fn handle_deferred_call(&self) {
+        if self.config_done_pending.replace(false) {
+            self.config_client.map(|client| client.config_done(Ok(())));
+        }
+        if self.power_changed_pending.replace(false) {
+            self.power_client
+                .map(|client| client.changed(self.on.get()));
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
@@ -18,9 +18,11 @@ pub mod analog_comparator;
 pub mod analog_sensor;
 pub mod apds9960;
 pub mod app_flash_driver;
+pub mod app_installer;
 pub mod app_loader;
 pub mod at24c_eeprom;
 pub mod atecc508a;
+pub mod ble;
 pub mod ble_advertising_driver;
 pub mod bme280;
 pub mod bmm150;
@@ -30,6 +32,8 @@ pub mod button_keyboard;
 pub mod buzzer_driver;
 pub mod buzzer_pwm;
 pub mod can;
+pub mod can_isotp;
+pub mod can_shared;
 pub mod ccs811;
 pub mod chirp_i2c_moisture;
 pub mod crc;
@@ -53,12 +57,15 @@ pub mod hmac_sha256;
 pub mod hs3003;
 pub mod hts221;
 pub mod humidity;
+pub mod i2c_register_map;
 pub mod ieee802154;
+pub mod ieee802154_medium;
 pub mod isl29035;
 pub mod isolated_nonvolatile_storage_driver;
 pub mod kv_driver;
 pub mod kv_store_permissions;
 pub mod l3gd20;
+pub mod littlefs;
 pub mod led_matrix;
 pub mod log;
 pub mod lpm013m126;
@@ -75,13 +82,18 @@ pub mod mlx90614;
 pub mod moisture;
 pub mod mx25r6435f;
 pub mod ninedof;
+pub mod nonvolatile_counter;
 pub mod nonvolatile_storage_driver;
 pub mod nonvolatile_to_pages;
 pub mod nrf51822_serialization;
 pub mod panic_button;
+pub mod panic_record;
 pub mod pca9544a;
 pub mod pressure;
+pub mod process_checkpoint;
+pub mod process_fault_dump;
 pub mod process_info_driver;
+pub mod process_watchdog;
 pub mod proximity;
 pub mod public_key_crypto;
 pub mod pwm;
@@ -107,14 +119,17 @@ pub mod sound_pressure;
 pub mod ssd1306;
 pub mod st77xx;
 pub mod symmetric_encryption;
+pub mod syscall_trace;
 pub mod temperature;
 pub mod temperature_rp2040;
 pub mod temperature_stm;
 pub mod text_screen;
 pub mod tickv;
+pub mod tickv_encrypted;
 pub mod tickv_kv_store;
 pub mod touch;
 pub mod tsl2561;
+pub mod udp_app_loader;
 pub mod usb;
 pub mod usb_hid_driver;
 pub mod virtualizers;
@@ -0,0 +1,805 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! File system access for userspace.
+//!
+//! This capsule stores files in a region of flash formatted as a
+//! [littlefs](https://github.com/littlefs-project/littlefs) file system,
+//! using the implementation in `libraries/littlefs`. Images can be created or
+//! inspected on a host with the upstream littlefs tools.
+//!
+//! ```text
+//! +-----------------------+
+//! |       userspace       |
+//! +-----------------------+
+//!
+//!    kernel::SyscallDriver
+//!
+//! +-----------------------+
+//! |  LittleFs (this file) |
+//! +-----------------------+
+//!       |             |
+//!   hil::flash        |
+//!               +--------------------+
+//!               | libraries/littlefs |
+//!               +--------------------+
+//! ```
+//!
+//! ## Mounting
+//!
+//! The board mounts the file system at boot. If the region doesn't hold a
+//! littlefs image, or the image is damaged, the mount fails and every
+//! command returns `OFF`; the flash is left untouched so the data can still
+//! be recovered. A new region must be formatted explicitly, either by
+//! writing an image created on a host or with [`LittleFsDriver::format`].
+//!
+//! ## Limitations
+//!
+//! The file system implements a subset of littlefs, see
+//! `libraries/littlefs`. These restrictions are part of the design rather
+//! than missing features:
+//!
+//!  * Entries can't be renamed.
+//!  * File data is never stored inline in the metadata, so every non-empty
+//!    file takes at least one flash page.
+//!
+//! ## Per-app directories
+//!
+//! Each app's files live in a top-level directory named after the app's
+//! `write_id`, as eight lowercase hex digits (for example `/0000002a`).
+//! Paths passed by an app are relative to its own directory, and the
+//! directory is created the first time the app modifies it. An app without
+//! a `write_id` can't use relative paths.
+//!
+//! An app can use absolute paths that start with another directory, such as
+//! `/0000002a/calibration`, to access the files of other apps. Reading files
+//! or listing directories requires read permission for the id of the
+//! directory, and modifying them requires modify permission, as granted by
+//! the app's `StoragePermissions`. Absolute paths must name one of these
+//! directories, so there is no way to access the root directory.
+//!
+//! ## Syscall interface
+//!
+//! Only one operation per app can be outstanding. Operations complete with
+//! upcall 0, whose first argument is a status code. Paths are passed in
+//! read-only allow 0, and end at the first NUL byte or at the end of the
+//! buffer. File data is written from read-only allow 1, and read into
+//! read-write allow 0.
+//!
+//! The file system errors are reported as:
+//!
+//! | Error                       | ErrorCode   |
+//! |-----------------------------|-------------|
+//! | Entry not found             | `NODEVICE`  |
+//! | Entry already exists        | `ALREADY`   |
+//! | Directory not empty         | `RESERVE`   |
+//! | File system full            | `NOMEM`     |
+//! | Name too long               | `SIZE`      |
+//! | Not a file or directory     | `INVAL`     |
+//! | Unsupported operation       | `NOSUPPORT` |
+//! | Not mounted or read-only    | `OFF`       |
+//! | Flash or image error        | `FAIL`      |
+
+use core::cmp;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
+use kernel::hil::flash::{self, Flash};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+use littlefs::{EntryKind, FileHandle, LittleFs, MAX_OPEN_FILES, OpenFlags, Output, Request};
+
+use capsules_core::driver;
+
+pub use littlefs::{CACHE_BLOCKS, Config};
+
+pub const DRIVER_NUM: usize = driver::NUM::FileSystem as usize;
+
+/// Recommended size for the buffer used to pass paths and file data.
+pub const BUF_LEN: usize = 512;
+
+/// How many files a single app can have open at the same time.
+pub const MAX_FILES_PER_APP: usize = 4;
+
+/// IDs for subscribed upcalls.
+mod upcall {
+    /// An operation completed.
+    pub const DONE: usize = 0;
+    /// Number of upcalls.
+    pub const COUNT: u8 = 1;
+}
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// The path used by open, unlink, mkdir, readdir and stat.
+    pub const PATH: usize = 0;
+    /// The data to write to a file.
+    pub const WRITE: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// Data read from a file, or the name of a directory entry.
+    pub const READ: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// Length of the directory of an app, `/xxxxxxxx`.
+const APP_DIR_LEN: usize = 9;
+
+/// The operation the process requested.
+#[derive(Clone, Copy, Debug)]
+enum FsCommand {
+    Open { flags: OpenFlags },
+    Close { fd: usize },
+    Sync { fd: usize },
+    Read { fd: usize, len: usize },
+    Write { fd: usize, len: usize },
+    Unlink,
+    Mkdir,
+    ReadDir { index: u32 },
+    Stat,
+}
+
+impl FsCommand {
+    fn needs_read(&self) -> bool {
+        match self {
+            FsCommand::Open { flags } => flags.contains(OpenFlags::READ),
+            FsCommand::ReadDir { .. } | FsCommand::Stat => true,
+            _ => false,
+        }
+    }
+
+    fn needs_modify(&self) -> bool {
+        match self {
+            FsCommand::Open { flags } => {
+                flags.contains(OpenFlags::WRITE)
+                    || flags.contains(OpenFlags::CREATE)
+                    || flags.contains(OpenFlags::TRUNC)
+            }
+            FsCommand::Unlink | FsCommand::Mkdir => true,
+            _ => false,
+        }
+    }
+}
+
+/// What is currently using the file system.
+#[derive(Clone, Copy, Debug)]
+enum User {
+    Mount,
+    /// Formatting the file system, which is then mounted.
+    Format,
+    /// Creating the directory of an app before running its command.
+    CreateDir {
+        processid: ProcessId,
+    },
+    App {
+        processid: ProcessId,
+        command: FsCommand,
+    },
+}
+
+/// State stored in the grant region on behalf of each app.
+#[derive(Default)]
+pub struct App {
+    /// Operation waiting for the file system.
+    pending_command: Option<FsCommand>,
+    /// The app's directory is known to exist.
+    dir_created: bool,
+}
+
+fn into_error_code(error: littlefs::Error) -> ErrorCode {
+    match error {
+        littlefs::Error::NotFound => ErrorCode::NODEVICE,
+        littlefs::Error::Exists => ErrorCode::ALREADY,
+        littlefs::Error::NotEmpty => ErrorCode::RESERVE,
+        littlefs::Error::NoSpace | littlefs::Error::NoMemory => ErrorCode::NOMEM,
+        littlefs::Error::NameTooLong => ErrorCode::SIZE,
+        littlefs::Error::NotDir
+        | littlefs::Error::IsDir
+        | littlefs::Error::Invalid
+        | littlefs::Error::BadHandle => ErrorCode::INVAL,
+        littlefs::Error::Unsupported | littlefs::Error::UnsupportedVersion => ErrorCode::NOSUPPORT,
+        littlefs::Error::ReadOnly | littlefs::Error::NotMounted => ErrorCode::OFF,
+        littlefs::Error::Busy => ErrorCode::BUSY,
+        littlefs::Error::Io | littlefs::Error::Corrupt => ErrorCode::FAIL,
+    }
+}
+
+/// Write the directory of the app with `id` to the start of `buffer`.
+fn write_app_dir(buffer: &mut [u8], id: u32) {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    buffer[0] = b'/';
+    for (i, c) in buffer[1..APP_DIR_LEN].iter_mut().enumerate() {
+        *c = HEX[((id >> (28 - 4 * i)) & 0xf) as usize];
+    }
+}
+
+/// Parse the id of an app directory. Only lowercase hex digits are
+/// accepted, so that each id has exactly one directory.
+fn parse_app_dir(name: &[u8]) -> Option<u32> {
+    name.iter().try_fold(0u32, |id, c| {
+        let digit = match c {
+            b'0'..=b'9' => c - b'0',
+            b'a'..=b'f' => c - b'a' + 10,
+            _ => return None,
+        };
+        Some((id << 4) | digit as u32)
+    })
+}
+
+pub struct LittleFsDriver<'a, F: Flash + 'static> {
+    flash: &'a F,
+    /// Buffer for flash operations.
+    page: TakeCell<'static, F::Page>,
+    /// The first page of the file system.
+    region_offset: usize,
+    fs: MapCell<LittleFs<'static>>,
+    /// Per-app state.
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    /// What issued the operation in progress.
+    current_user: OptionalCell<User>,
+    /// The open files. Apps refer to files by their index in this table.
+    files: [OptionalCell<(ProcessId, FileHandle)>; MAX_OPEN_FILES],
+}
+
+impl<'a, F: Flash> LittleFsDriver<'a, F> {
+    /// Create the driver for a file system starting at page `region_offset`
+    /// of `flash`. `config.block_size` must be the page size. `cache` must
+    /// hold `CACHE_BLOCKS` pages and `bitmap` a bit per block, `buffer` is
+    /// used to pass paths and file data.
+    pub fn new(
+        flash: &'a F,
+        page: &'static mut F::Page,
+        region_offset: usize,
+        config: Config,
+        cache: &'static mut [u8],
+        bitmap: &'static mut [u8],
+        buffer: &'static mut [u8],
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> Self {
+        assert_eq!(page.as_mut().len(), config.block_size);
+
+        Self {
+            flash,
+            page: TakeCell::new(page),
+            region_offset,
+            fs: MapCell::new(LittleFs::new(config, cache, bitmap, buffer)),
+            apps: grant,
+            current_user: OptionalCell::empty(),
+            files: [const { OptionalCell::empty() }; MAX_OPEN_FILES],
+        }
+    }
+
+    /// Mount the file system. If the flash doesn't contain a valid littlefs
+    /// image the file system stays unmounted.
+    pub fn mount(&self) {
+        if self.current_user.is_none() && self.fs.map_or(false, |fs| fs.mount().is_ok()) {
+            self.current_user.set(User::Mount);
+            self.run();
+        }
+    }
+
+    /// Erase the file system and mount the new, empty one. All files are
+    /// lost, so this is meant for provisioning a device.
+    ///
+    /// Returns `BUSY` if an operation is in progress or a file is open.
+    pub fn format(&self) -> Result<(), ErrorCode> {
+        if self.current_user.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.fs
+            .map_or(Err(littlefs::Error::Busy), |fs| fs.format())
+            .map_err(into_error_code)?;
+        self.current_user.set(User::Format);
+        self.run();
+        Ok(())
+    }
+
+    /// Run the current operation until it waits for the flash or completes.
+    fn run(&self) {
+        loop {
+            let Some(request) = self.fs.map(|fs| fs.poll()) else {
+                return;
+            };
+            let started = match request {
+                Request::Idle => return,
+                Request::Read(block) => self.page.take().is_some_and(|page| {
+                    self.flash
+                        .read_page(self.region_offset + block as usize, page)
+                        .map_err(|(_, page)| self.page.replace(page))
+                        .is_ok()
+                }),
+                Request::Erase(block) => self
+                    .flash
+                    .erase_page(self.region_offset + block as usize)
+                    .is_ok(),
+                Request::Write(block) => self.page.take().is_some_and(|page| {
+                    self.fs.map(|fs| {
+                        let data = page.as_mut();
+                        let len = cmp::min(data.len(), fs.io_buffer().len());
+                        data[..len].copy_from_slice(&fs.io_buffer()[..len]);
+                    });
+                    self.flash
+                        .write_page(self.region_offset + block as usize, page)
+                        .map_err(|(_, page)| self.page.replace(page))
+                        .is_ok()
+                }),
+                Request::Done(result) => {
+                    self.operation_done(result);
+                    return;
+                }
+            };
+            if started {
+                return;
+            }
+            // The operation fails, poll again to collect the result.
+            self.fs.map(|fs| fs.io_failed());
+        }
+    }
+
+    fn io_done(&self, result: Result<(), flash::Error>) {
+        self.fs.map(|fs| match result {
+            Ok(()) => fs.io_complete(),
+            Err(_) => fs.io_failed(),
+        });
+        self.run();
+    }
+
+    fn operation_done(&self, result: Result<Output, littlefs::Error>) {
+        match self.current_user.take() {
+            Some(User::Mount) => {}
+            Some(User::Format) => {
+                if result.is_ok() {
+                    self.mount();
+                }
+            }
+            Some(User::CreateDir { processid }) => {
+                let _ = self.apps.enter(processid, |app, kernel_data| match result {
+                    Ok(_) | Err(littlefs::Error::Exists) => app.dir_created = true,
+                    Err(e) => {
+                        app.pending_command = None;
+                        let _ = kernel_data.schedule_upcall(
+                            upcall::DONE,
+                            (into_statuscode(Err(into_error_code(e))), 0, 0),
+                        );
+                    }
+                });
+            }
+            Some(User::App { processid, command }) => {
+                self.command_done(processid, command, result);
+            }
+            None => {}
+        }
+        self.check_queue();
+    }
+
+    fn command_done(
+        &self,
+        processid: ProcessId,
+        command: FsCommand,
+        result: Result<Output, littlefs::Error>,
+    ) {
+        // Track open files first, the process may have exited in the
+        // meantime.
+        let result = match (command, result) {
+            (FsCommand::Open { .. }, Ok(Output::File(handle))) => {
+                match self.files.iter().position(|file| file.is_none()) {
+                    Some(fd) => {
+                        self.files[fd].set((processid, handle));
+                        Ok(Output::File(handle))
+                    }
+                    None => {
+                        self.fs.map(|fs| {
+                            let _ = fs.release(handle);
+                        });
+                        Err(littlefs::Error::NoMemory)
+                    }
+                }
+            }
+            (FsCommand::Close { fd }, result) => {
+                // The file stays open if it couldn't be committed.
+                if let Some((_, handle)) = self.files[fd].get() {
+                    if self.fs.map_or(true, |fs| fs.size(handle).is_err()) {
+                        self.files[fd].clear();
+                    }
+                }
+                result
+            }
+            (_, result) => result,
+        };
+
+        let _ = self.apps.enter(processid, |app, kernel_data| {
+            app.pending_command = None;
+            let result = self.fs.map_or(Err(ErrorCode::FAIL), |fs| match result {
+                Ok(Output::File(handle)) => {
+                    let fd = self
+                        .files
+                        .iter()
+                        .position(|file| file.get() == Some((processid, handle)))
+                        .unwrap_or(0);
+                    Ok((fd, fs.size(handle).unwrap_or(0) as usize))
+                }
+                Ok(Output::Length(len)) => match command {
+                    FsCommand::Read { .. } => {
+                        Ok((Self::copy_to_app(kernel_data, &fs.buffer()[..len]), 0))
+                    }
+                    _ => Ok((len, 0)),
+                },
+                Ok(Output::Entry(entry)) => {
+                    let kind = match entry.kind {
+                        EntryKind::File => 0,
+                        EntryKind::Dir => 1,
+                    };
+                    match command {
+                        FsCommand::ReadDir { .. } => {
+                            Self::copy_to_app(kernel_data, &fs.buffer()[..entry.name_len]);
+                            Ok((kind, entry.name_len))
+                        }
+                        _ => Ok((kind, entry.size as usize)),
+                    }
+                }
+                Ok(Output::None) => Ok((0, 0)),
+                Err(e) => Err(into_error_code(e)),
+            });
+            let (r1, r2) = result.unwrap_or((0, 0));
+            let _ = kernel_data
+                .schedule_upcall(upcall::DONE, (into_statuscode(result.map(|_| ())), r1, r2));
+        });
+    }
+
+    /// Copy `data` into the read buffer of the app. Returns the number of
+    /// bytes copied.
+    fn copy_to_app(kernel_data: &GrantKernelData, data: &[u8]) -> usize {
+        kernel_data
+            .get_readwrite_processbuffer(rw_allow::READ)
+            .and_then(|read| {
+                read.mut_enter(|app_buffer| {
+                    let len = cmp::min(app_buffer.len(), data.len());
+                    app_buffer[..len].copy_from_slice(&data[..len]);
+                    len
+                })
+            })
+            .unwrap_or(0)
+    }
+
+    /// Copy the path allowed by the app into the file system buffer,
+    /// resolved to the app directory it is in. Returns the length of the
+    /// path and the id of the directory.
+    fn load_path(
+        fs: &mut LittleFs,
+        processid: ProcessId,
+        kernel_data: &GrantKernelData,
+    ) -> Result<(usize, u32), ErrorCode> {
+        let write_id = processid
+            .get_storage_permissions()
+            .and_then(|perms| perms.get_write_id());
+
+        kernel_data
+            .get_readonly_processbuffer(ro_allow::PATH)
+            .and_then(|path| {
+                path.enter(|path| {
+                    let len = path.iter().position(|c| c.get() == 0).unwrap_or(path.len());
+                    let buffer = fs.buffer_mut();
+
+                    if path.iter().next().map(|c| c.get()) == Some(b'/') {
+                        if len > buffer.len() {
+                            return Err(ErrorCode::SIZE);
+                        }
+                        path[..len].copy_to_slice(&mut buffer[..len]);
+                        if len < APP_DIR_LEN || (len > APP_DIR_LEN && buffer[APP_DIR_LEN] != b'/') {
+                            return Err(ErrorCode::INVAL);
+                        }
+                        let owner =
+                            parse_app_dir(&buffer[1..APP_DIR_LEN]).ok_or(ErrorCode::INVAL)?;
+                        Ok((len, owner))
+                    } else {
+                        let owner = write_id.ok_or(ErrorCode::NOSUPPORT)?;
+                        let total = APP_DIR_LEN + 1 + len;
+                        if total > buffer.len() {
+                            return Err(ErrorCode::SIZE);
+                        }
+                        write_app_dir(buffer, owner);
+                        buffer[APP_DIR_LEN] = b'/';
+                        path[..len].copy_to_slice(&mut buffer[APP_DIR_LEN + 1..total]);
+                        Ok((total, owner))
+                    }
+                })
+            })
+            .unwrap_or(Err(ErrorCode::RESERVE))
+    }
+
+    fn check_permissions(
+        processid: ProcessId,
+        owner: u32,
+        command: FsCommand,
+    ) -> Result<(), ErrorCode> {
+        let perms = processid
+            .get_storage_permissions()
+            .ok_or(ErrorCode::NOSUPPORT)?;
+        if command.needs_read() && !perms.check_read_permission(owner) {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        if command.needs_modify() && !perms.check_modify_permission(owner) {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        Ok(())
+    }
+
+    /// The file the app refers to as `fd`.
+    fn file(&self, processid: ProcessId, fd: usize) -> Result<FileHandle, ErrorCode> {
+        self.files
+            .get(fd)
+            .and_then(|file| file.get())
+            .filter(|(owner, _)| *owner == processid)
+            .map(|(_, handle)| handle)
+            .ok_or(ErrorCode::INVAL)
+    }
+
+    /// Close the files of processes that exited without closing them. Data
+    /// they didn't commit is dropped.
+    fn release_stale_files(&self, fs: &mut LittleFs, processid: ProcessId) {
+        for file in self.files.iter() {
+            if let Some((owner, handle)) = file.get() {
+                if owner != processid && self.apps.enter(owner, |_, _| ()).is_err() {
+                    let _ = fs.release(handle);
+                    file.clear();
+                }
+            }
+        }
+    }
+
+    /// Start the command of the app. Returns who is using the file system,
+    /// which is the app's command or the creation of its directory.
+    fn start_command(
+        &self,
+        processid: ProcessId,
+        command: FsCommand,
+        app: &App,
+        kernel_data: &GrantKernelData,
+    ) -> Result<User, ErrorCode> {
+        self.fs.map_or(Err(ErrorCode::FAIL), |fs| {
+            if !fs.is_mounted() {
+                return Err(ErrorCode::OFF);
+            }
+
+            let started = match command {
+                FsCommand::Close { fd } => fs.close(self.file(processid, fd)?),
+                FsCommand::Sync { fd } => fs.sync(self.file(processid, fd)?),
+                FsCommand::Read { fd, len } => {
+                    let handle = self.file(processid, fd)?;
+                    let allow_len = kernel_data
+                        .get_readwrite_processbuffer(rw_allow::READ)
+                        .map_or(0, |read| read.len());
+                    if allow_len == 0 {
+                        return Err(ErrorCode::RESERVE);
+                    }
+                    fs.read(handle, cmp::min(len, allow_len))
+                }
+                FsCommand::Write { fd, len } => {
+                    let handle = self.file(processid, fd)?;
+                    let len = kernel_data
+                        .get_readonly_processbuffer(ro_allow::WRITE)
+                        .and_then(|write| {
+                            write.enter(|app_buffer| {
+                                let buffer = fs.buffer_mut();
+                                let len = cmp::min(len, cmp::min(app_buffer.len(), buffer.len()));
+                                app_buffer[..len].copy_to_slice(&mut buffer[..len]);
+                                len
+                            })
+                        })
+                        .unwrap_or(0);
+                    if len == 0 {
+                        return Err(ErrorCode::RESERVE);
+                    }
+                    fs.write(handle, len)
+                }
+                FsCommand::Open { .. }
+                | FsCommand::Unlink
+                | FsCommand::Mkdir
+                | FsCommand::ReadDir { .. }
+                | FsCommand::Stat => {
+                    let (path_len, owner) = Self::load_path(fs, processid, kernel_data)?;
+                    Self::check_permissions(processid, owner, command)?;
+
+                    let own_dir = processid
+                        .get_storage_permissions()
+                        .and_then(|perms| perms.get_write_id())
+                        == Some(owner);
+                    if own_dir && command.needs_modify() && !app.dir_created {
+                        // The path starts with the app directory.
+                        fs.mkdir(APP_DIR_LEN).map_err(into_error_code)?;
+                        return Ok(User::CreateDir { processid });
+                    }
+
+                    // Path of the entry within the app directory, which
+                    // can't be removed or created by the app.
+                    let in_dir = fs.buffer()[APP_DIR_LEN..path_len]
+                        .iter()
+                        .any(|c| *c != b'/');
+
+                    match command {
+                        FsCommand::Open { flags } => {
+                            self.release_stale_files(fs, processid);
+                            let open = self
+                                .files
+                                .iter()
+                                .filter(|file| file.get().is_some_and(|(p, _)| p == processid))
+                                .count();
+                            if open >= MAX_FILES_PER_APP {
+                                return Err(ErrorCode::NOMEM);
+                            }
+                            fs.open(path_len, flags)
+                        }
+                        FsCommand::Unlink if in_dir => fs.remove(path_len),
+                        FsCommand::Mkdir if in_dir => fs.mkdir(path_len),
+                        FsCommand::ReadDir { index } => fs.read_dir(path_len, index),
+                        FsCommand::Stat => fs.stat(path_len),
+                        _ => return Err(ErrorCode::INVAL),
+                    }
+                }
+            };
+            started.map_err(into_error_code)?;
+            Ok(User::App { processid, command })
+        })
+    }
+
+    fn check_queue(&self) {
+        if self.current_user.is_some() {
+            // The file system is busy, the queue is checked again once the
+            // operation completes.
+            return;
+        }
+
+        let started = self.apps.iter().any(|app| {
+            let processid = app.processid();
+            app.enter(|app, kernel_data| {
+                let Some(command) = app.pending_command else {
+                    return false;
+                };
+                match self.start_command(processid, command, app, kernel_data) {
+                    Ok(user) => {
+                        self.current_user.set(user);
+                        true
+                    }
+                    Err(e) => {
+                        app.pending_command = None;
+                        let _ = kernel_data
+                            .schedule_upcall(upcall::DONE, (into_statuscode(Err(e)), 0, 0));
+                        false
+                    }
+                }
+            })
+        });
+        if started {
+            self.run();
+        }
+    }
+
+    fn enqueue_command(&self, command: FsCommand, processid: ProcessId) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(processid, |app, _kernel_data| {
+                if app.pending_command.is_some() {
+                    return Err(ErrorCode::BUSY);
+                }
+                app.pending_command = Some(command);
+                Ok(())
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+
+        self.check_queue();
+        Ok(())
+    }
+}
+
+impl<F: Flash> flash::Client<F> for LittleFsDriver<'_, F> {
+    fn read_complete(&self, pagebuffer: &'static mut F::Page, result: Result<(), flash::Error>) {
+        self.fs.map(|fs| {
+            let buffer = fs.io_buffer();
+            let len = cmp::min(buffer.len(), pagebuffer.as_mut().len());
+            buffer[..len].copy_from_slice(&pagebuffer.as_mut()[..len]);
+        });
+        self.page.replace(pagebuffer);
+        self.io_done(result);
+    }
+
+    fn write_complete(&self, pagebuffer: &'static mut F::Page, result: Result<(), flash::Error>) {
+        self.page.replace(pagebuffer);
+        self.io_done(result);
+    }
+
+    fn erase_complete(&self, result: Result<(), flash::Error>) {
+        self.io_done(result);
+    }
+}
+
+/// Provide an interface for userland.
+impl<F: Flash> SyscallDriver for LittleFsDriver<'_, F> {
+    /// Command interface.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Return Ok(()) if this driver is included on the platform.
+    /// - `1`: Open the file at the path with the littlefs open flags in
+    ///   `arg1`. The upcall returns the file descriptor and the file size.
+    /// - `2`: Commit and close file `arg1`.
+    /// - `3`: Read up to `arg2` bytes from file `arg1`. The upcall returns
+    ///   the number of bytes read.
+    /// - `4`: Write up to `arg2` bytes to file `arg1` at its position, or at
+    ///   its end if it was opened to append. The upcall returns the number of
+    ///   bytes written.
+    /// - `5`: Move the position of file `arg1` to `arg2`, which can't be past
+    ///   the end of the file. Completes immediately.
+    /// - `6`: Remove the file or empty directory at the path.
+    /// - `7`: Create a directory at the path.
+    /// - `8`: Read entry `arg1` of the directory at the path. The upcall
+    ///   returns the kind of entry (0 for a file, 1 for a directory) and the
+    ///   length of its name, which is copied to the read buffer. Fails with
+    ///   `NODEVICE` past the last entry.
+    /// - `9`: Look up the path. The upcall returns the kind of entry and the
+    ///   file size.
+    /// - `10`: Commit the data written to file `arg1`.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        let command = match command_num {
+            0 => return CommandReturn::success(),
+            1 => {
+                let flags = OpenFlags(arg1 as u32);
+                if !flags.is_valid() {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+                FsCommand::Open { flags }
+            }
+            2 => FsCommand::Close { fd: arg1 },
+            3 => FsCommand::Read {
+                fd: arg1,
+                len: arg2,
+            },
+            4 => FsCommand::Write {
+                fd: arg1,
+                len: arg2,
+            },
+            5 => {
+                let res = self.file(processid, arg1).and_then(|handle| {
+                    self.fs.map_or(Err(ErrorCode::FAIL), |fs| {
+                        fs.seek(handle, arg2 as u32).map_err(into_error_code)
+                    })
+                });
+                return res.into();
+            }
+            6 => FsCommand::Unlink,
+            7 => FsCommand::Mkdir,
+            8 => FsCommand::ReadDir { index: arg1 as u32 },
+            9 => FsCommand::Stat,
+            10 => FsCommand::Sync { fd: arg1 },
+            _ => return CommandReturn::failure(ErrorCode::NOSUPPORT),
+        };
+
+        self.enqueue_command(command, processid).into()
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
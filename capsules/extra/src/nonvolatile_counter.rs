@@ -0,0 +1,218 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Monotonic counter stored in nonvolatile storage.
+//!
+//! This implements `MonotonicCounter` for `EncryptedKVSystem` on top of a
+//! `NonvolatileStorage`, for example internal flash through
+//! `NonvolatileToPages`. The storage must not be accessible to anyone who can
+//! roll back the K-V store.
+//!
+//! The counter is kept in two slots, written alternately:
+//!
+//! ```text
+//! +---------------+----------------+
+//! | value (BE)    | !value (BE)    |
+//! | 8 bytes       | 8 bytes        |
+//! +---------------+----------------+
+//! ```
+//!
+//! A slot is only valid if the second half is the complement of the first,
+//! so erased or partially written slots are ignored. The counter is the
+//! largest valid value of the two slots, or zero if neither is valid. Each
+//! slot should be in its own flash page, so that a power loss while one is
+//! written can't corrupt the other.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let counter = static_init!(
+//!     NonvolatileCounter<'static, NonvolatileToPages<'static, Flash>>,
+//!     NonvolatileCounter::new(nv_to_pages, [0x1000, 0x2000], buffer)
+//! );
+//! hil::nonvolatile_storage::NonvolatileStorage::set_client(nv_to_pages, counter);
+//! ```
+
+use crate::tickv_encrypted::{MonotonicCounter, MonotonicCounterClient};
+use core::cell::Cell;
+use kernel::ErrorCode;
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+/// The length of a slot, and of the buffer passed to
+/// `NonvolatileCounter::new()`.
+pub const SLOT_LEN: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    /// Reading a slot, then incrementing if `increment` is set.
+    Read {
+        slot: usize,
+        increment: bool,
+    },
+    /// Writing the new value.
+    Write(u64),
+}
+
+pub struct NonvolatileCounter<'a, S: NonvolatileStorage<'a>> {
+    storage: &'a S,
+    /// The addresses of the two slots.
+    slots: [usize; 2],
+    buffer: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    /// The largest valid value read so far.
+    value: OptionalCell<u64>,
+    client: OptionalCell<&'a dyn MonotonicCounterClient>,
+}
+
+impl<'a, S: NonvolatileStorage<'a>> NonvolatileCounter<'a, S> {
+    /// Create a new `NonvolatileCounter` with its slots at the addresses in
+    /// `slots`. `buffer` must be at least `SLOT_LEN` bytes long.
+    pub fn new(
+        storage: &'a S,
+        slots: [usize; 2],
+        buffer: &'static mut [u8],
+    ) -> NonvolatileCounter<'a, S> {
+        Self {
+            storage,
+            slots,
+            buffer: TakeCell::new(buffer),
+            state: Cell::new(State::Idle),
+            value: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    fn read_slot(&self, slot: usize, increment: bool) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        self.state.set(State::Read { slot, increment });
+        self.storage
+            .read(buffer, self.slots[slot], SLOT_LEN)
+            .inspect_err(|_| {
+                self.state.set(State::Idle);
+            })
+    }
+
+    /// Write `value + 1` to the slot not holding `value`.
+    fn write_next(&self, value: u64) -> Result<(), ErrorCode> {
+        let new_value = value.checked_add(1).ok_or(ErrorCode::FAIL)?;
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        buffer[0..8].copy_from_slice(&new_value.to_be_bytes());
+        buffer[8..SLOT_LEN].copy_from_slice(&(!new_value).to_be_bytes());
+
+        self.state.set(State::Write(new_value));
+        self.storage
+            .write(buffer, self.slots[(new_value % 2) as usize], SLOT_LEN)
+            .inspect_err(|_| {
+                self.state.set(State::Idle);
+            })
+    }
+
+    /// Returns the value in `slot` if it is valid.
+    fn parse_slot(slot: &[u8]) -> Option<u64> {
+        let mut value = [0; 8];
+        let mut complement = [0; 8];
+        value.copy_from_slice(&slot[0..8]);
+        complement.copy_from_slice(&slot[8..SLOT_LEN]);
+
+        let value = u64::from_be_bytes(value);
+        if !value == u64::from_be_bytes(complement) {
+            Some(value)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a, S: NonvolatileStorage<'a>> MonotonicCounter<'a> for NonvolatileCounter<'a, S> {
+    fn set_client(&self, client: &'a dyn MonotonicCounterClient) {
+        self.client.set(client);
+    }
+
+    fn read(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.value.clear();
+        self.read_slot(0, false)
+    }
+
+    fn increment(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+
+        match self.value.get() {
+            Some(value) => self.write_next(value),
+            None => self.read_slot(0, true),
+        }
+    }
+}
+
+impl<'a, S: NonvolatileStorage<'a>> NonvolatileStorageClient for NonvolatileCounter<'a, S> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        let State::Read { slot, increment } = self.state.get() else {
+            self.buffer.replace(buffer);
+            return;
+        };
+
+        if length == SLOT_LEN {
+            if let Some(value) = Self::parse_slot(buffer) {
+                if self.value.map_or(true, |newest| value > newest) {
+                    self.value.set(value);
+                }
+            }
+        }
+        self.buffer.replace(buffer);
+
+        if slot + 1 < self.slots.len() {
+            if let Err(e) = self.read_slot(slot + 1, increment) {
+                self.value.clear();
+                self.client.map(|client| {
+                    if increment {
+                        client.increment_complete(Err(e));
+                    } else {
+                        client.read_complete(Err(e));
+                    }
+                });
+            }
+            return;
+        }
+
+        // Neither slot has been written yet.
+        let value = self.value.get().unwrap_or(0);
+        self.value.set(value);
+
+        self.state.set(State::Idle);
+        if increment {
+            if let Err(e) = self.write_next(value) {
+                self.client.map(|client| client.increment_complete(Err(e)));
+            }
+        } else {
+            self.client.map(|client| client.read_complete(Ok(value)));
+        }
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.buffer.replace(buffer);
+        let State::Write(new_value) = self.state.get() else {
+            return;
+        };
+        self.state.set(State::Idle);
+
+        let result = if length == SLOT_LEN {
+            self.value.set(new_value);
+            Ok(new_value)
+        } else {
+            // The slot may be partially written, read it back before the
+            // next increment.
+            self.value.clear();
+            Err(ErrorCode::FAIL)
+        };
+        self.client.map(|client| client.increment_complete(result));
+    }
+}
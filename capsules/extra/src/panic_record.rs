@@ -0,0 +1,236 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Userspace access to the panic record of the previous boot.
+//!
+//! The kernel keeps a panic record in RAM that survives a warm reset (see
+//! `kernel::panic_record`). At boot the board retrieves the record left by a
+//! panic before the reset and passes it to this capsule, which prints it and
+//! lets a process read it, for example to upload it, and then clear it.
+//!
+//! Optionally, the capsule copies the record to nonvolatile storage, so that
+//! it also survives a later power loss. On a boot without a record in RAM, the
+//! record is instead read back from the storage.
+//!
+//! ## Commands
+//!
+//! - `0`: Check driver exists.
+//! - `1`: Check whether there is a panic record. Returns the PC and LR of the
+//!   fault that caused the panic, or 0 if they were not recorded. Fails with
+//!   `NODEVICE` if there is no record, or `BUSY` while the record is read from
+//!   storage.
+//! - `2`: Copy the panic message into read-write allow buffer 0. Returns the
+//!   number of bytes copied.
+//! - `3`: Copy the last debug output before the panic into read-write allow
+//!   buffer 0. Returns the number of bytes copied.
+//! - `4`: Clear the record, including in the storage.
+
+use core::cell::Cell;
+
+use kernel::debug;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::panic_record::PanicRecord;
+use kernel::processbuffer::WriteableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::TakeCell;
+use kernel::{ErrorCode, ProcessId};
+
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::PanicRecord as usize;
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const DATA: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum StorageState {
+    /// Reading the record from the storage.
+    Loading,
+    /// Writing the record, or clearing it, in the storage.
+    Saving,
+    Idle,
+}
+
+pub struct PanicRecordDriver<'a> {
+    /// The record of the previous panic, if it is valid.
+    record: TakeCell<'static, PanicRecord>,
+    storage: Option<&'a dyn NonvolatileStorage<'a>>,
+    /// Address of the record in `storage`.
+    storage_address: usize,
+    storage_state: Cell<StorageState>,
+    /// The record must be cleared in the storage once it is free.
+    clear_pending: Cell<bool>,
+    apps: Grant<(), UpcallCount<0>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+}
+
+impl<'a> PanicRecordDriver<'a> {
+    pub fn new(
+        record: &'static mut PanicRecord,
+        storage: Option<&'a dyn NonvolatileStorage<'a>>,
+        storage_address: usize,
+        grant: Grant<(), UpcallCount<0>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+    ) -> Self {
+        Self {
+            record: TakeCell::new(record),
+            storage,
+            storage_address,
+            storage_state: Cell::new(StorageState::Idle),
+            clear_pending: Cell::new(false),
+            apps: grant,
+        }
+    }
+
+    /// Print a record left by a panic before the reset and save it to the
+    /// storage, or read a saved record from the storage if there is none.
+    pub fn init(&self) {
+        let Some(record) = self.record.take() else {
+            return;
+        };
+        let valid = record.is_valid();
+        if valid {
+            debug!("{}", record);
+        }
+        let Some(storage) = self.storage else {
+            self.record.replace(record);
+            return;
+        };
+
+        let buffer = record.into_bytes();
+        let length = buffer.len();
+        let res = if valid {
+            storage.write(buffer, self.storage_address, length)
+        } else {
+            storage.read(buffer, self.storage_address, length)
+        };
+        match res {
+            Ok(()) if valid => self.storage_state.set(StorageState::Saving),
+            Ok(()) => self.storage_state.set(StorageState::Loading),
+            // The storage didn't take the buffer, and there is no way to get
+            // it back, so the record is lost.
+            Err(_) => {}
+        }
+    }
+
+    fn storage_done(&self, buffer: &'static mut [u8]) {
+        self.storage_state.set(StorageState::Idle);
+        if let Ok(record) = PanicRecord::from_bytes(buffer) {
+            self.record.replace(record);
+        }
+        if self.clear_pending.take() {
+            let _ = self.clear();
+        }
+    }
+
+    fn clear(&self) -> Result<(), ErrorCode> {
+        if self.storage_state.get() != StorageState::Idle {
+            // The record is cleared in the storage once it is free.
+            self.clear_pending.set(true);
+            return Ok(());
+        }
+        let record = self.record.take().ok_or(ErrorCode::FAIL)?;
+        record.clear();
+        match self.storage {
+            Some(storage) => {
+                // Only the magic at the start of the record has to be
+                // cleared.
+                let res = storage.write(record.into_bytes(), self.storage_address, 4);
+                if res.is_ok() {
+                    self.storage_state.set(StorageState::Saving);
+                }
+                res
+            }
+            None => {
+                self.record.replace(record);
+                Ok(())
+            }
+        }
+    }
+
+    fn copy_to_process(
+        &self,
+        processid: ProcessId,
+        data: impl Fn(&PanicRecord) -> (&[u8], &[u8]),
+    ) -> Result<usize, ErrorCode> {
+        self.record
+            .map(|record| {
+                if !record.is_valid() {
+                    return Err(ErrorCode::NODEVICE);
+                }
+                let (first, second) = data(record);
+                self.apps
+                    .enter(processid, |_, kernel_data| {
+                        kernel_data
+                            .get_readwrite_processbuffer(rw_allow::DATA)
+                            .and_then(|dest| {
+                                dest.mut_enter(|dest| {
+                                    let mut copied = 0;
+                                    for src in [first, second] {
+                                        let len = src.len().min(dest.len() - copied);
+                                        dest[copied..copied + len].copy_from_slice(&src[..len]);
+                                        copied += len;
+                                    }
+                                    copied
+                                })
+                            })
+                            .map_err(ErrorCode::from)
+                    })
+                    .unwrap_or_else(|err| Err(err.into()))
+            })
+            .unwrap_or(Err(ErrorCode::BUSY))
+    }
+}
+
+impl NonvolatileStorageClient for PanicRecordDriver<'_> {
+    fn read_done(&self, buffer: &'static mut [u8], _length: usize) {
+        self.storage_done(buffer);
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], _length: usize) {
+        self.storage_done(buffer);
+    }
+}
+
+impl SyscallDriver for PanicRecordDriver<'_> {
+    fn command(
+        &self,
+        command_num: usize,
+        _data1: usize,
+        _data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => self
+                .record
+                .map_or(CommandReturn::failure(ErrorCode::BUSY), |record| {
+                    if record.is_valid() {
+                        let (pc, lr) = record.registers().unwrap_or((0, 0));
+                        CommandReturn::success_u32_u32(pc, lr)
+                    } else {
+                        CommandReturn::failure(ErrorCode::NODEVICE)
+                    }
+                }),
+            2 => self
+                .copy_to_process(processid, |record| (record.message(), &[]))
+                .map_or_else(CommandReturn::failure, |len| {
+                    CommandReturn::success_u32(len as u32)
+                }),
+            3 => self
+                .copy_to_process(processid, |record| record.log())
+                .map_or_else(CommandReturn::failure, |len| {
+                    CommandReturn::success_u32(len as u32)
+                }),
+            4 => self.clear().into(),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
@@ -0,0 +1,114 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Lets a process checkpoint itself to flash.
+//!
+//! The kernel restores a process from its last checkpoint when it is loaded
+//! after a reboot (see `kernel::process_checkpoint`). This capsule is the
+//! trigger for processes: a process that opted in to checkpoints with the
+//! Checkpoint TLV in its TBF header calls command 1 at a point it wants to
+//! continue from, for example before a long computation or once it has
+//! initialized its state.
+//!
+//! The process is stopped until the checkpoint is written, so the command
+//! only returns once the checkpoint is in flash or has failed. A process
+//! restored from the checkpoint continues from the same command, which
+//! returns `Ok(())` again. Grants are not part of the checkpoint, so after a
+//! restore the process must allow buffers and subscribe to upcalls again.
+//!
+//! Only one process can be checkpointed at a time.
+//!
+//! ## Commands
+//!
+//! - `0`: Check driver exists.
+//! - `1`: Checkpoint the calling process. Fails with `NOSUPPORT` if the
+//!   process did not opt in to checkpoints, `BUSY` if another checkpoint is
+//!   being written, `SIZE` if the memory of the process does not fit in a
+//!   slot, `NOMEM` if there is no free slot, or with the error of the flash.
+
+use kernel::Kernel;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::process_checkpoint::{ProcessCheckpoint, ProcessCheckpointClient};
+use kernel::syscall::{CommandReturn, SyscallDriver, SyscallReturn};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::ProcessCheckpoint as usize;
+
+pub struct ProcessCheckpointDriver<'a, C: ProcessManagementCapability> {
+    checkpoint: &'a dyn ProcessCheckpoint<'a>,
+    kernel: &'static Kernel,
+    capability: C,
+    /// The process whose checkpoint it requested is being written.
+    requester: OptionalCell<ProcessId>,
+}
+
+impl<'a, C: ProcessManagementCapability> ProcessCheckpointDriver<'a, C> {
+    pub fn new(
+        checkpoint: &'a dyn ProcessCheckpoint<'a>,
+        kernel: &'static Kernel,
+        capability: C,
+    ) -> Self {
+        Self {
+            checkpoint,
+            kernel,
+            capability,
+            requester: OptionalCell::empty(),
+        }
+    }
+}
+
+impl<C: ProcessManagementCapability> ProcessCheckpointClient for ProcessCheckpointDriver<'_, C> {
+    fn checkpoint_done(&self, processid: ProcessId, result: Result<(), ErrorCode>) {
+        if self.requester.get() != Some(processid) {
+            // The board checkpointed a process.
+            return;
+        }
+        self.requester.clear();
+        if let Err(e) = result {
+            // The process has not run since its command returned `Ok(())`,
+            // so the failure replaces that return value.
+            self.kernel.process_map_or_external(
+                (),
+                processid,
+                |process| process.set_syscall_return_value(SyscallReturn::Failure(e)),
+                &self.capability,
+            );
+        }
+    }
+}
+
+impl<C: ProcessManagementCapability> SyscallDriver for ProcessCheckpointDriver<'_, C> {
+    fn command(
+        &self,
+        command_num: usize,
+        _data1: usize,
+        _data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => {
+                if self.requester.is_some() {
+                    return CommandReturn::failure(ErrorCode::BUSY);
+                }
+                // This stops the process, which stays stopped while the
+                // kernel sets the return value of this command.
+                match self.checkpoint.checkpoint(processid) {
+                    Ok(()) => {
+                        self.requester.set(processid);
+                        CommandReturn::success()
+                    }
+                    Err(e) => CommandReturn::failure(e),
+                }
+            }
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, _processid: ProcessId) -> Result<(), kernel::process::Error> {
+        Ok(())
+    }
+}
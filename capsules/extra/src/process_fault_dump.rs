@@ -0,0 +1,590 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Binary process fault dumps persisted to nonvolatile storage.
+//!
+//! The kernel prints a fault report to the debug writer when a process
+//! faults, which is lost unless someone is watching the console. This capsule
+//! instead writes a binary record of each fault to a reserved area of
+//! nonvolatile storage, so that faults in deployed devices can be retrieved
+//! and analysed later, for example by `tools/debugging-and-development/
+//! decode_fault_dump.py`.
+//!
+//! The capsule implements `ProcessFaultPolicy` by wrapping the board's fault
+//! policy, and is passed to the process loader as the fault policy. The
+//! wrapped policy still decides what happens to the process. The record is
+//! taken before the kernel acts on the fault, so it describes the process as
+//! it faulted.
+//!
+//! If the action is `FaultAction::Panic`, the kernel panics before the record
+//! could be written to the storage. The record is then kept in the panic
+//! buffer, which must be memory that is not initialized at boot, and is
+//! written to the storage once the board has reset and the capsule has found
+//! the records of previous boots.
+//!
+//! ## Records
+//!
+//! The storage area is split into slots of `RECORD_LEN` bytes which are used
+//! as a ring, so the oldest record is overwritten once all slots are used. A
+//! record holds, as little-endian words unless noted:
+//!
+//! | Offset | Field                                                      |
+//! |--------|------------------------------------------------------------|
+//! | 0      | Magic                                                      |
+//! | 4      | Record format version                                      |
+//! | 8      | Sequence number                                            |
+//! | 12     | Length of the record, including the checksum               |
+//! | 16     | Kernel major and minor version (u16 each)                  |
+//! | 20     | Fixed ShortId of the process, or 0                         |
+//! | 24     | Restart count                                              |
+//! | 28     | Number of syscalls the process made                        |
+//! | 32     | Process memory: flash start and end, RAM start, app break, |
+//! |        | grant start, and RAM end                                   |
+//! | 56     | Process name (32 bytes, NUL padded)                        |
+//! | 88     | The last 8 syscalls, newest first: class (`0xFFFFFFFF` if  |
+//! |        | none), driver number, subdriver number, and two arguments  |
+//! | 248    | The first 16 MPU regions of the process: start, size, and  |
+//! |        | permissions (0 if the region is unused or unknown, then    |
+//! |        | `ReadWriteExecute`, `ReadWriteOnly`, `ReadExecuteOnly`,    |
+//! |        | `ReadOnly`, and `ExecuteOnly` from 1)                      |
+//! | 440    | Length of the stored state                                 |
+//! | 444    | Stored state, as returned by `Process::get_stored_state()` |
+//! | 604    | Stack pointer                                              |
+//! | 608    | Length of the stack slice                                  |
+//! | 612    | Stack slice, from the stack pointer upwards                |
+//! | len-4  | FNV-1a checksum of the preceding bytes                     |
+//!
+//! The stored state holds the process registers, in the architecture's
+//! format. The MPU regions are only known on architectures whose MPU reports
+//! them. Dumps of faults that don't panic are only taken while the capsule
+//! isn't using the storage; otherwise they are dropped and counted in
+//! `dropped_count()`.
+//!
+//! ## Commands
+//!
+//! - `0`: Check driver exists.
+//! - `1`: Get the number of records.
+//! - `2`: Read record `data1`, where 0 is the newest record, into the
+//!   read-write allow buffer 0. Upcall 0 is called with the length of the
+//!   record and its index once it has been read.
+//! - `3`: Erase all records. Upcall 1 is called once they are erased.
+
+use core::cell::Cell;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::platform::mpu;
+use kernel::process::{self, Process, ProcessFaultPolicy, ShortId};
+use kernel::processbuffer::WriteableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::ProcessFaultDump as usize;
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const RECORD: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// Ids for upcalls
+mod upcall {
+    pub const READ_DONE: usize = 0;
+    pub const ERASE_DONE: usize = 1;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+/// Size of a record slot in nonvolatile storage, and of the buffers provided
+/// to this capsule.
+pub const RECORD_LEN: usize = 1024;
+
+const RECORD_MAGIC: u32 = 0x4654_4450;
+const RECORD_VERSION: u32 = 2;
+
+const ADDRESSES_OFFSET: usize = 32;
+const NAME_OFFSET: usize = 56;
+const NAME_LEN: usize = 32;
+const SYSCALLS_OFFSET: usize = 88;
+const SYSCALLS: usize = 8;
+const SYSCALL_LEN: usize = 20;
+const MPU_REGIONS_OFFSET: usize = 248;
+const MPU_REGIONS: usize = 16;
+const MPU_REGION_LEN: usize = 12;
+const STORED_STATE_OFFSET: usize = 444;
+const STORED_STATE_LEN: usize = 160;
+const STACK_OFFSET: usize = 612;
+const CHECKSUM_LEN: usize = 4;
+
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash: u32, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Returns the sequence number and length of a valid record.
+fn parse_record(bytes: &[u8]) -> Option<(u32, usize)> {
+    if bytes.len() < RECORD_LEN || read_u32(bytes, 0) != RECORD_MAGIC {
+        return None;
+    }
+    let length = read_u32(bytes, 12) as usize;
+    if !(STACK_OFFSET + CHECKSUM_LEN..=RECORD_LEN).contains(&length) {
+        return None;
+    }
+    let end = length - CHECKSUM_LEN;
+    if read_u32(bytes, end) != checksum(&bytes[..end]) {
+        return None;
+    }
+    Some((read_u32(bytes, 8), length))
+}
+
+/// The syscall class and arguments recorded for the last syscall.
+fn encode_syscall(syscall: Option<kernel::syscall::Syscall>) -> [u32; 5] {
+    match syscall {
+        None => [u32::MAX, 0, 0, 0, 0],
+        Some(syscall) => {
+            let (class, [driver, subdriver, arg0, arg1]) = syscall.class_and_arguments();
+            [
+                class as u32,
+                driver as u32,
+                subdriver as u32,
+                arg0 as u32,
+                arg1 as u32,
+            ]
+        }
+    }
+}
+
+/// The encoding of the permissions of an MPU region.
+fn encode_permissions(permissions: mpu::Permissions) -> u32 {
+    match permissions {
+        mpu::Permissions::ReadWriteExecute => 1,
+        mpu::Permissions::ReadWriteOnly => 2,
+        mpu::Permissions::ReadExecuteOnly => 3,
+        mpu::Permissions::ReadOnly => 4,
+        mpu::Permissions::ExecuteOnly => 5,
+    }
+}
+
+/// Set the sequence number, length and checksum of the record in `bytes`.
+fn seal_record(bytes: &mut [u8], sequence: u32, length: usize) {
+    write_u32(bytes, 8, sequence);
+    write_u32(bytes, 12, length as u32);
+    let end = length - CHECKSUM_LEN;
+    let sum = checksum(&bytes[..end]);
+    write_u32(bytes, end, sum);
+}
+
+#[derive(Default)]
+pub struct App;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    /// Reading slot `n` to find the records left by previous boots.
+    Scanning(usize),
+    /// Writing a fault record.
+    Saving,
+    /// Writing the record of a fault that caused a panic before the reset.
+    SavingPanicRecord,
+    /// Reading a record for a process.
+    Reading(usize),
+    /// Erasing slot `n`.
+    Erasing(usize),
+    Idle,
+}
+
+pub struct ProcessFaultDump<'a> {
+    storage: &'a dyn NonvolatileStorage<'a>,
+    /// Address of the first slot in `storage`.
+    region_address: usize,
+    /// Number of record slots.
+    slots: usize,
+    buffer: TakeCell<'static, [u8]>,
+    /// Holds the record of a fault that causes a panic until it is written
+    /// after the reset.
+    panic_buffer: TakeCell<'static, [u8]>,
+    /// The fault policy deciding what happens to faulted processes.
+    policy: &'a dyn ProcessFaultPolicy,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<0>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    state: Cell<State>,
+    /// Number of valid records.
+    count: Cell<usize>,
+    /// Sequence number of the next record.
+    next_sequence: Cell<u32>,
+    /// Number of faults that couldn't be recorded.
+    dropped: Cell<usize>,
+    /// The process that requested the current read or erase.
+    requester: OptionalCell<ProcessId>,
+}
+
+impl<'a> ProcessFaultDump<'a> {
+    pub fn new(
+        storage: &'a dyn NonvolatileStorage<'a>,
+        region_address: usize,
+        region_length: usize,
+        buffer: &'static mut [u8],
+        panic_buffer: &'static mut [u8],
+        policy: &'a dyn ProcessFaultPolicy,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<0>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> Self {
+        Self {
+            storage,
+            region_address,
+            slots: region_length / RECORD_LEN,
+            buffer: TakeCell::new(buffer),
+            panic_buffer: TakeCell::new(panic_buffer),
+            policy,
+            apps: grant,
+            state: Cell::new(State::Idle),
+            count: Cell::new(0),
+            next_sequence: Cell::new(0),
+            dropped: Cell::new(0),
+            requester: OptionalCell::empty(),
+        }
+    }
+
+    /// Find the records left by previous boots, and then write the record of
+    /// a fault that caused a panic before the reset. Faults that don't panic
+    /// are not recorded until this has completed.
+    pub fn init(&self) {
+        self.count.set(0);
+        self.next_sequence.set(0);
+        if self.slots > 0 && self.read_slot(0).is_ok() {
+            self.state.set(State::Scanning(0));
+        } else {
+            self.save_panic_record();
+        }
+    }
+
+    /// The number of records in the storage.
+    pub fn record_count(&self) -> usize {
+        self.count.get()
+    }
+
+    /// The number of faults since boot that were not recorded because the
+    /// storage was busy.
+    pub fn dropped_count(&self) -> usize {
+        self.dropped.get()
+    }
+
+    fn slot_address(&self, slot: usize) -> usize {
+        self.region_address + slot * RECORD_LEN
+    }
+
+    fn read_slot(&self, slot: usize) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buffer| {
+            self.storage
+                .read(buffer, self.slot_address(slot), RECORD_LEN)
+        })
+    }
+
+    fn erase_slot(&self, slot: usize) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buffer| {
+            // Clearing the magic is enough to invalidate the record.
+            buffer[..4].fill(0);
+            self.storage.write(buffer, self.slot_address(slot), 4)
+        })
+    }
+
+    /// Fill `buffer` with the record of `process`, returning its length. The
+    /// record still has to be sealed with `seal_record()`.
+    fn build_record(&self, process: &dyn Process, buffer: &mut [u8]) -> usize {
+        buffer.fill(0);
+        write_u32(buffer, 0, RECORD_MAGIC);
+        write_u32(buffer, 4, RECORD_VERSION);
+        write_u32(
+            buffer,
+            16,
+            kernel::KERNEL_MAJOR_VERSION as u32 | (kernel::KERNEL_MINOR_VERSION as u32) << 16,
+        );
+        let app_id = match process.short_app_id() {
+            ShortId::Fixed(id) => id.get(),
+            ShortId::LocallyUnique => 0,
+        };
+        write_u32(buffer, 20, app_id);
+        write_u32(buffer, 24, process.get_restart_count() as u32);
+        write_u32(buffer, 28, process.debug_syscall_count() as u32);
+
+        let addresses = process.get_addresses();
+        for (i, address) in [
+            addresses.flash_start,
+            addresses.flash_end,
+            addresses.sram_start,
+            addresses.sram_app_brk,
+            addresses.sram_grant_start,
+            addresses.sram_end,
+        ]
+        .iter()
+        .enumerate()
+        {
+            write_u32(buffer, ADDRESSES_OFFSET + 4 * i, *address as u32);
+        }
+
+        let name = process.get_process_name().as_bytes();
+        let name_len = name.len().min(NAME_LEN);
+        buffer[NAME_OFFSET..NAME_OFFSET + name_len].copy_from_slice(&name[..name_len]);
+
+        for index in 0..SYSCALLS {
+            let syscall = encode_syscall(process.debug_syscall_history(index));
+            for (i, word) in syscall.iter().enumerate() {
+                write_u32(buffer, SYSCALLS_OFFSET + SYSCALL_LEN * index + 4 * i, *word);
+            }
+        }
+
+        for region_number in 0..MPU_REGIONS {
+            let offset = MPU_REGIONS_OFFSET + MPU_REGION_LEN * region_number;
+            if let Some((region, permissions)) = process.debug_mpu_region(region_number) {
+                write_u32(buffer, offset, region.start_address() as u32);
+                write_u32(buffer, offset + 4, region.size() as u32);
+                write_u32(buffer, offset + 8, encode_permissions(permissions));
+            }
+        }
+
+        let stored_state = process
+            .get_stored_state(
+                &mut buffer[STORED_STATE_OFFSET..STORED_STATE_OFFSET + STORED_STATE_LEN],
+            )
+            .unwrap_or(0);
+        write_u32(buffer, STORED_STATE_OFFSET - 4, stored_state as u32);
+
+        let stack_end = RECORD_LEN - CHECKSUM_LEN;
+        let (stack_pointer, stack_len) = process
+            .debug_stack_slice(&mut buffer[STACK_OFFSET..stack_end])
+            .unwrap_or((0, 0));
+        write_u32(buffer, STACK_OFFSET - 8, stack_pointer as u32);
+        write_u32(buffer, STACK_OFFSET - 4, stack_len as u32);
+
+        STACK_OFFSET + stack_len + CHECKSUM_LEN
+    }
+
+    /// Seal the record in `buffer` with the next sequence number and write it
+    /// to the slot after the newest record.
+    fn write_record(&self, buffer: &'static mut [u8], length: usize) -> Result<(), ErrorCode> {
+        let sequence = self.next_sequence.get();
+        seal_record(buffer, sequence, length);
+        let slot = sequence as usize % self.slots;
+        self.storage
+            .write(buffer, self.slot_address(slot), length)
+            .inspect(|()| {
+                self.next_sequence.set(sequence.wrapping_add(1));
+                self.count.set((self.count.get() + 1).min(self.slots));
+            })
+    }
+
+    fn save(&self, process: &dyn Process) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle || self.slots == 0 {
+            return Err(ErrorCode::BUSY);
+        }
+        self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buffer| {
+            let length = self.build_record(process, buffer);
+            self.write_record(buffer, length)
+        })?;
+        self.state.set(State::Saving);
+        Ok(())
+    }
+
+    /// Keep the record of `process` in the panic buffer, as the kernel is
+    /// about to panic.
+    fn save_for_panic(&self, process: &dyn Process) {
+        self.panic_buffer.map(|panic_buffer| {
+            let length = self.build_record(process, panic_buffer);
+            // The sequence number is set when the record is written.
+            seal_record(panic_buffer, 0, length);
+        });
+    }
+
+    /// Write the record of a fault that caused a panic before the reset, if
+    /// there is one.
+    fn save_panic_record(&self) {
+        let length = self
+            .panic_buffer
+            .map_or(None, |panic_buffer| parse_record(panic_buffer))
+            .map(|(_, length)| length);
+        let Some(length) = length else {
+            return;
+        };
+        if self.slots == 0 {
+            return;
+        }
+        let Some(buffer) = self.buffer.take() else {
+            return;
+        };
+        self.panic_buffer
+            .map(|panic_buffer| buffer[..length].copy_from_slice(&panic_buffer[..length]));
+        if self.write_record(buffer, length).is_ok() {
+            self.state.set(State::SavingPanicRecord);
+        }
+    }
+
+    fn read_record(&self, processid: ProcessId, index: usize) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if index >= self.count.get() {
+            return Err(ErrorCode::INVAL);
+        }
+        let sequence = self
+            .next_sequence
+            .get()
+            .wrapping_sub(1)
+            .wrapping_sub(index as u32);
+        self.read_slot(sequence as usize % self.slots)?;
+        self.state.set(State::Reading(index));
+        self.requester.set(processid);
+        Ok(())
+    }
+
+    fn erase(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if self.slots == 0 {
+            return Err(ErrorCode::NODEVICE);
+        }
+        self.erase_slot(0)?;
+        self.state.set(State::Erasing(0));
+        self.requester.set(processid);
+        Ok(())
+    }
+
+    fn read_done_for(&self, index: usize, record: &[u8]) {
+        let length = parse_record(record).map_or(0, |(_, length)| length);
+        self.requester.take().map(|processid| {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                let copied = kernel_data
+                    .get_readwrite_processbuffer(rw_allow::RECORD)
+                    .and_then(|dest| {
+                        dest.mut_enter(|dest| {
+                            let len = dest.len().min(length);
+                            dest[..len].copy_from_slice(&record[..len]);
+                            len
+                        })
+                    })
+                    .unwrap_or(0);
+                let _ = kernel_data.schedule_upcall(upcall::READ_DONE, (copied, index, 0));
+            });
+        });
+    }
+}
+
+impl ProcessFaultPolicy for ProcessFaultDump<'_> {
+    fn action(&self, process: &dyn Process) -> process::FaultAction {
+        let action = self.policy.action(process);
+        if matches!(action, process::FaultAction::Panic) {
+            self.save_for_panic(process);
+        } else if self.save(process).is_err() {
+            self.dropped.set(self.dropped.get() + 1);
+        }
+        action
+    }
+}
+
+impl NonvolatileStorageClient for ProcessFaultDump<'_> {
+    fn read_done(&self, buffer: &'static mut [u8], _length: usize) {
+        match self.state.get() {
+            State::Scanning(slot) => {
+                if let Some((sequence, _)) = parse_record(buffer) {
+                    let count = self.count.get();
+                    // The next sequence number follows the newest record.
+                    if count == 0 || sequence.wrapping_sub(self.next_sequence.get()) < u32::MAX / 2
+                    {
+                        self.next_sequence.set(sequence.wrapping_add(1));
+                    }
+                    self.count.set(count + 1);
+                }
+                self.buffer.replace(buffer);
+                if slot + 1 < self.slots && self.read_slot(slot + 1).is_ok() {
+                    self.state.set(State::Scanning(slot + 1));
+                } else {
+                    self.state.set(State::Idle);
+                    self.save_panic_record();
+                }
+            }
+            State::Reading(index) => {
+                self.read_done_for(index, buffer);
+                self.buffer.replace(buffer);
+                self.state.set(State::Idle);
+            }
+            _ => {
+                self.buffer.replace(buffer);
+                self.state.set(State::Idle);
+            }
+        }
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], _length: usize) {
+        self.buffer.replace(buffer);
+        match self.state.get() {
+            State::Erasing(slot) => {
+                if slot + 1 < self.slots && self.erase_slot(slot + 1).is_ok() {
+                    self.state.set(State::Erasing(slot + 1));
+                    return;
+                }
+                self.count.set(0);
+                self.next_sequence.set(0);
+                self.state.set(State::Idle);
+                self.requester.take().map(|processid| {
+                    let _ = self.apps.enter(processid, |_, kernel_data| {
+                        let _ = kernel_data.schedule_upcall(upcall::ERASE_DONE, (0, 0, 0));
+                    });
+                });
+            }
+            State::SavingPanicRecord => {
+                // Clearing the magic is enough to invalidate the record.
+                self.panic_buffer
+                    .map(|panic_buffer| panic_buffer[..4].fill(0));
+                self.state.set(State::Idle);
+            }
+            _ => self.state.set(State::Idle),
+        }
+    }
+}
+
+impl SyscallDriver for ProcessFaultDump<'_> {
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        _data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => CommandReturn::success_u32(self.count.get() as u32),
+            2 => self.read_record(processid, data1).into(),
+            3 => self.erase(processid).into(),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
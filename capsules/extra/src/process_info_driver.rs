@@ -34,6 +34,11 @@
 //!     stopped=3, faulted=4, terminated=5).
 //! - 6: Change the process state. `data1` is the process ID, and `data2` is the
 //!   new state.(1=start, 2=stop, 3=fault, 4=terminate, 5=boot).
+//! - 7: Fill the allow RW buffer with the memory high-water marks of the
+//!   process specified by the process ID in `data1`, in bytes, or
+//!   0xFFFFFFFF if unknown.
+//!   - The most stack the process has used.
+//!   - The most heap the process has used.
 
 use kernel::Kernel;
 use kernel::capabilities::{ProcessManagementCapability, ProcessStartCapability};
@@ -280,6 +285,44 @@ impl<C: ProcessManagementCapability + ProcessStartCapability> SyscallDriver for
                 }
             }
 
+            7 => self
+                .apps
+                .enter(process_id, |_app, kernel_data| {
+                    kernel_data
+                        .get_readwrite_processbuffer(rw_allow::INFO)
+                        .and_then(|shared| {
+                            shared.mut_enter(|s| {
+                                let mut matched = false;
+                                let mut chunks = s.chunks(size_of::<u32>());
+                                self.kernel
+                                    .process_each_capability(&self.capability, |process| {
+                                        if process.processid().id() == data1 {
+                                            matched = true;
+                                            let high_water_marks = process.debug_high_water_marks();
+                                            for mark in
+                                                [high_water_marks.stack, high_water_marks.heap]
+                                            {
+                                                if let Some(chunk) = chunks.next() {
+                                                    let _ = chunk.copy_from_slice_or_err(
+                                                        &mark
+                                                            .map_or(u32::MAX, |bytes| bytes as u32)
+                                                            .to_le_bytes(),
+                                                    );
+                                                }
+                                            }
+                                        }
+                                    });
+                                if matched {
+                                    CommandReturn::success()
+                                } else {
+                                    CommandReturn::failure(ErrorCode::INVAL)
+                                }
+                            })
+                        })
+                        .unwrap_or_else(|err| CommandReturn::failure(err.into()))
+                })
+                .unwrap_or_else(|err| CommandReturn::failure(err.into())),
+
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
     }
@@ -0,0 +1,503 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Software watchdog that checks critical processes are alive.
+//!
+//! The kernel tickles the hardware watchdog on every iteration of the kernel
+//! loop, so a process stuck in a loop or waiting for an event that never
+//! arrives doesn't trip it. With this capsule, processes register with a
+//! timeout and must check in before it expires. If a process misses its
+//! deadline, the capsule either faults the process, which is then handled
+//! by the board's fault policy (for example restarting it), or stops
+//! tickling the hardware watchdog so that it resets the chip.
+//!
+//! A registered process that can no longer check in, because it was stopped,
+//! faulted, terminated or restarted, is treated as if its deadline had
+//! expired. Registrations are kept by the capsule rather than in the process's
+//! grant, which is freed when the process faults, so they outlive the
+//! process. At most [`MAX_PROCESSES`] processes can be registered at once.
+//!
+//! A process that registers can reset the chip, or fault itself over and
+//! over, so only the processes the board lists can register. The board
+//! passes the fixed ShortIds of these processes to `new()`. Processes with a
+//! locally unique ShortId can never register.
+//!
+//! The capsule implements `WatchDog` by wrapping the chip's watchdog, and is
+//! used as the watchdog in the board's `KernelResources`. The hardware
+//! watchdog is only tickled while no registered process has missed its
+//! deadline.
+//!
+//! ## Reset reason
+//!
+//! Each miss is recorded in nonvolatile storage, before the hardware
+//! watchdog is allowed to reset the chip. The record of the last miss is read
+//! (and then erased) at boot, and is available to userspace and to the
+//! board through `last_record()`. Boards should make sure the hardware
+//! watchdog period is long enough for the record to be written.
+//!
+//! ## Commands
+//!
+//! - `0`: Check driver exists.
+//! - `1`: Register the process, or change its timeout, with a timeout of
+//!   `data1` milliseconds. This also counts as a check-in. Fails with
+//!   `NOSUPPORT` if the board doesn't allow the process to register, or with
+//!   `NOMEM` if [`MAX_PROCESSES`] processes are already registered.
+//! - `2`: Check in. Fails with `RESERVE` if the process isn't registered.
+//! - `3`: Unregister the process.
+//! - `4`: Get the record of the last miss before this boot. Returns the
+//!   action taken (1 for a process fault, 2 for a hardware reset) and the
+//!   fixed ShortId of the process that missed (0 if it didn't have one).
+//!   Fails with `NODEVICE` if there is no record, or `BUSY` if the record
+//!   hasn't been read yet.
+
+use core::cell::Cell;
+
+use kernel::Kernel;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::hil::time::{self, Alarm, ConvertTicks, Ticks};
+use kernel::platform::watchdog::WatchDog;
+use kernel::process::{ShortId, State};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::ProcessWatchdog as usize;
+
+/// Size of the record in nonvolatile storage, and of the buffer provided to
+/// this capsule.
+pub const RECORD_LEN: usize = 12;
+
+/// Number of processes that can be registered at the same time.
+pub const MAX_PROCESSES: usize = 4;
+
+const RECORD_MAGIC: u32 = 0x5744_5252;
+
+/// What to do when a process misses its deadline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MissAction {
+    /// Fault the process. What happens next depends on the board's process
+    /// fault policy.
+    FaultProcess = 1,
+    /// Stop tickling the hardware watchdog so that it resets the chip.
+    HardwareReset = 2,
+}
+
+/// The record of a deadline miss.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MissRecord {
+    pub action: MissAction,
+    /// The fixed ShortId of the process, or 0.
+    pub app_id: u32,
+}
+
+impl MissRecord {
+    fn to_bytes(self) -> [u8; RECORD_LEN] {
+        let action = self.action as u32;
+        let mut bytes = [0; RECORD_LEN];
+        bytes[0..4].copy_from_slice(&(RECORD_MAGIC ^ action).to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.app_id.to_le_bytes());
+        bytes[8..12].copy_from_slice(&(RECORD_MAGIC ^ action ^ self.app_id).to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let word =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        let action = word(0) ^ RECORD_MAGIC;
+        let app_id = word(4);
+        if word(8) != word(0) ^ app_id {
+            return None;
+        }
+        let action = match action {
+            1 => MissAction::FaultProcess,
+            2 => MissAction::HardwareReset,
+            _ => return None,
+        };
+        Some(MissRecord { action, app_id })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Expiration<T: Ticks> {
+    reference: T,
+    dt: T,
+}
+
+impl<T: Ticks> Expiration<T> {
+    fn remaining(&self, now: T) -> Option<T> {
+        let deadline = self.reference.wrapping_add(self.dt);
+        now.within_range(self.reference, deadline)
+            .then(|| deadline.wrapping_sub(now))
+    }
+}
+
+/// A registered process.
+#[derive(Clone, Copy, Debug)]
+struct Registration<T: Ticks> {
+    processid: ProcessId,
+    /// The deadline of the process.
+    expiration: Expiration<T>,
+    /// The timeout the process registered with.
+    timeout: T,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum StorageState {
+    /// Reading the record left by the previous boot.
+    Loading,
+    /// Erasing the record left by the previous boot.
+    Clearing,
+    Saving,
+    Idle,
+}
+
+pub struct ProcessWatchdog<'a, A: Alarm<'a>, W: WatchDog, C: ProcessManagementCapability> {
+    alarm: &'a A,
+    /// The hardware watchdog.
+    watchdog: &'a W,
+    storage: &'a dyn NonvolatileStorage<'a>,
+    /// Address of the record in `storage`.
+    record_address: usize,
+    buffer: TakeCell<'static, [u8]>,
+    kernel: &'static Kernel,
+    capability: C,
+    action: MissAction,
+    /// The processes that are allowed to register.
+    allowed_apps: &'a [ShortId],
+    registrations: [Cell<Option<Registration<A::Ticks>>>; MAX_PROCESSES],
+    storage_state: Cell<StorageState>,
+    /// The record read at boot.
+    last_record: OptionalCell<MissRecord>,
+    /// A record waiting for the storage.
+    pending_record: OptionalCell<MissRecord>,
+    /// A process missed its deadline and the chip must be reset.
+    reset_requested: Cell<bool>,
+}
+
+impl<'a, A: Alarm<'a>, W: WatchDog, C: ProcessManagementCapability> ProcessWatchdog<'a, A, W, C> {
+    pub fn new(
+        alarm: &'a A,
+        watchdog: &'a W,
+        storage: &'a dyn NonvolatileStorage<'a>,
+        record_address: usize,
+        buffer: &'static mut [u8],
+        kernel: &'static Kernel,
+        capability: C,
+        action: MissAction,
+        allowed_apps: &'a [ShortId],
+    ) -> Self {
+        Self {
+            alarm,
+            watchdog,
+            storage,
+            record_address,
+            buffer: TakeCell::new(buffer),
+            kernel,
+            capability,
+            action,
+            allowed_apps,
+            registrations: [const { Cell::new(None) }; MAX_PROCESSES],
+            storage_state: Cell::new(StorageState::Idle),
+            last_record: OptionalCell::empty(),
+            pending_record: OptionalCell::empty(),
+            reset_requested: Cell::new(false),
+        }
+    }
+
+    /// Read the record left by the previous boot.
+    pub fn load_record(&self) {
+        let res = self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buffer| {
+            self.storage.read(buffer, self.record_address, RECORD_LEN)
+        });
+        if res.is_ok() {
+            self.storage_state.set(StorageState::Loading);
+        }
+    }
+
+    /// The record of the last deadline miss before this boot.
+    pub fn last_record(&self) -> Option<MissRecord> {
+        self.last_record.get()
+    }
+
+    /// Returns true while the hardware watchdog should be tickled. After a
+    /// miss that resets the chip, this stays true until the record has been
+    /// written.
+    fn hardware_live(&self) -> bool {
+        !self.reset_requested.get()
+            || self.storage_state.get() != StorageState::Idle
+            || self.pending_record.is_some()
+    }
+
+    fn write(&self, bytes: &[u8]) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buffer| {
+            buffer[..RECORD_LEN].copy_from_slice(bytes);
+            self.storage.write(buffer, self.record_address, RECORD_LEN)
+        })
+    }
+
+    fn save_record(&self, record: MissRecord) {
+        if self.storage_state.get() != StorageState::Idle {
+            // The record is written once the storage is free.
+            self.pending_record.set(record);
+            return;
+        }
+        if self.write(&record.to_bytes()).is_ok() {
+            self.storage_state.set(StorageState::Saving);
+        }
+    }
+
+    fn storage_done(&self) {
+        self.storage_state.set(StorageState::Idle);
+        if let Some(record) = self.pending_record.take() {
+            self.save_record(record);
+        }
+    }
+
+    fn missed(&self, processid: ProcessId) {
+        let app_id = match processid.short_app_id() {
+            ShortId::Fixed(id) => id.get(),
+            ShortId::LocallyUnique => 0,
+        };
+
+        self.save_record(MissRecord {
+            action: self.action,
+            app_id,
+        });
+        match self.action {
+            MissAction::FaultProcess => {
+                self.kernel.process_map_or_external(
+                    (),
+                    processid,
+                    |process| {
+                        // A process that already faulted or was terminated
+                        // has been handled by the kernel.
+                        if !matches!(process.get_state(), State::Faulted | State::Terminated) {
+                            process.set_fault_state();
+                        }
+                    },
+                    &self.capability,
+                );
+            }
+            MissAction::HardwareReset => self.reset_requested.set(true),
+        }
+    }
+
+    /// Returns true if the process can still run and check in. A process that
+    /// was restarted has a new `ProcessId`, so the old one no longer matches.
+    fn can_check_in(&self, processid: ProcessId) -> bool {
+        self.kernel.process_map_or_external(
+            false,
+            processid,
+            |process| {
+                matches!(
+                    process.get_state(),
+                    State::Running | State::Yielded | State::YieldedFor(_)
+                )
+            },
+            &self.capability,
+        )
+    }
+
+    /// Handle the registered processes for which `missed` returns true.
+    /// Returns whether there were any.
+    fn handle_misses(&self, missed: impl Fn(&Registration<A::Ticks>) -> bool) -> bool {
+        let mut any = false;
+        for registration in &self.registrations {
+            if let Some(r) = registration.get().filter(|r| missed(r)) {
+                registration.set(None);
+                self.missed(r.processid);
+                any = true;
+            }
+        }
+        any
+    }
+
+    /// Handle the processes that missed their deadline or can no longer check
+    /// in, and set the alarm for the next deadline.
+    fn check_processes(&self) {
+        self.handle_misses(|r| {
+            r.expiration.remaining(self.alarm.now()).is_none() || !self.can_check_in(r.processid)
+        });
+        self.schedule();
+    }
+
+    fn schedule(&self) {
+        let now = self.alarm.now();
+        let next = self
+            .registrations
+            .iter()
+            .filter_map(|registration| registration.get()?.expiration.remaining(now))
+            .min();
+        match next {
+            Some(dt) => self.alarm.set_alarm(now, dt),
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+
+    fn find(&self, processid: ProcessId) -> Option<&Cell<Option<Registration<A::Ticks>>>> {
+        self.registrations
+            .iter()
+            .find(|registration| registration.get().is_some_and(|r| r.processid == processid))
+    }
+
+    fn register(&self, processid: ProcessId, timeout_ms: usize) -> Result<(), ErrorCode> {
+        // A locally unique ShortId is not equal to any other ShortId, so
+        // these processes are never allowed.
+        if !self.allowed_apps.contains(&processid.short_app_id()) {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        let timeout = self
+            .alarm
+            .ticks_from_ms(u32::try_from(timeout_ms).map_err(|_| ErrorCode::INVAL)?);
+        if timeout_ms == 0 || timeout >= A::Ticks::half_max_value() {
+            return Err(ErrorCode::INVAL);
+        }
+        let registration = self
+            .find(processid)
+            .or_else(|| {
+                self.registrations
+                    .iter()
+                    .find(|registration| registration.get().is_none())
+            })
+            .ok_or(ErrorCode::NOMEM)?;
+        registration.set(Some(Registration {
+            processid,
+            expiration: Expiration {
+                reference: self.alarm.now(),
+                dt: timeout,
+            },
+            timeout,
+        }));
+        Ok(())
+    }
+
+    fn check_in(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        let registration = self.find(processid).ok_or(ErrorCode::RESERVE)?;
+        registration.set(registration.get().map(|r| Registration {
+            expiration: Expiration {
+                reference: self.alarm.now(),
+                dt: r.timeout,
+            },
+            ..r
+        }));
+        Ok(())
+    }
+
+    fn unregister(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        if let Some(registration) = self.find(processid) {
+            registration.set(None);
+        }
+        Ok(())
+    }
+}
+
+impl<'a, A: Alarm<'a>, W: WatchDog, C: ProcessManagementCapability> WatchDog
+    for ProcessWatchdog<'a, A, W, C>
+{
+    fn setup(&self) {
+        self.watchdog.setup();
+    }
+
+    fn tickle(&self) {
+        // Called on every iteration of the kernel loop, so a process that
+        // stops or faults is handled right away rather than at its deadline.
+        if self.handle_misses(|r| !self.can_check_in(r.processid)) {
+            self.schedule();
+        }
+        if self.hardware_live() {
+            self.watchdog.tickle();
+        }
+    }
+
+    fn suspend(&self) {
+        // Once a reset is due the hardware watchdog must keep running.
+        if self.hardware_live() {
+            self.watchdog.suspend();
+        }
+    }
+
+    fn resume(&self) {
+        if self.hardware_live() {
+            self.watchdog.resume();
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, W: WatchDog, C: ProcessManagementCapability> time::AlarmClient
+    for ProcessWatchdog<'a, A, W, C>
+{
+    fn alarm(&self) {
+        self.check_processes();
+    }
+}
+
+impl<'a, A: Alarm<'a>, W: WatchDog, C: ProcessManagementCapability> NonvolatileStorageClient
+    for ProcessWatchdog<'a, A, W, C>
+{
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        let record = if length >= RECORD_LEN {
+            MissRecord::from_bytes(&buffer[..RECORD_LEN])
+        } else {
+            None
+        };
+        self.buffer.replace(buffer);
+
+        if let Some(record) = record {
+            self.last_record.set(record);
+            // Erase the record so it is only reported after the boot that
+            // follows it.
+            if self.write(&[0; RECORD_LEN]).is_ok() {
+                self.storage_state.set(StorageState::Clearing);
+                return;
+            }
+        }
+        self.storage_done();
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], _length: usize) {
+        self.buffer.replace(buffer);
+        self.storage_done();
+    }
+}
+
+impl<'a, A: Alarm<'a>, W: WatchDog, C: ProcessManagementCapability> SyscallDriver
+    for ProcessWatchdog<'a, A, W, C>
+{
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        _data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        let res = match command_num {
+            0 => Ok(()),
+            1 => self.register(processid, data1),
+            2 => self.check_in(processid),
+            3 => self.unregister(processid),
+            4 => {
+                return match (self.last_record.get(), self.storage_state.get()) {
+                    (Some(record), _) => {
+                        CommandReturn::success_u32_u32(record.action as u32, record.app_id)
+                    }
+                    (None, StorageState::Loading) => CommandReturn::failure(ErrorCode::BUSY),
+                    (None, _) => CommandReturn::failure(ErrorCode::NODEVICE),
+                };
+            }
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+        if res.is_ok() && command_num != 0 {
+            self.schedule();
+        }
+        res.into()
+    }
+
+    fn allocate_grant(&self, _processid: ProcessId) -> Result<(), kernel::process::Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Binary trace of the system calls processes make.
+//!
+//! The `trace_syscalls` kernel configuration option prints every system call
+//! with `debug!()`, which changes the timing of the system and floods the
+//! console. This capsule instead records each system call and its return
+//! value as a compact, timestamped binary event in a ring buffer, and drains
+//! the buffer over a dedicated UART (or any other `uart::Transmit`, such as a
+//! Segger RTT channel) in the background. If the transport can't keep up, the
+//! oldest events are overwritten and the number of lost events is reported.
+//!
+//! `tools/debugging-and-development/decode_syscall_trace.py` turns the
+//! stream into a timeline. The nrf52840dk board sends the stream over RTT if
+//! its `SYSCALL_TRACE` option is set.
+//!
+//! ## Stream format
+//!
+//! The stream is a sequence of `EVENT_LEN` byte events, each holding:
+//!
+//! | Offset | Field                                                 |
+//! |--------|-------------------------------------------------------|
+//! | 0      | `0xA5` synchronization byte                           |
+//! | 1      | Kind                                                  |
+//! | 2      | Process identifier (u16)                              |
+//! | 4      | Timestamp in ticks of the trace's clock (u32)         |
+//! | 8      | Four words (u32)                                      |
+//!
+//! The kinds are:
+//!
+//! - `0`-`7`: A system call, of the class with that identifier. The words are
+//!   as returned by `Syscall::class_and_arguments()`.
+//! - `0x80`: The return value of a system call, encoded in four registers as
+//!   specified in TRD104.
+//! - `0xFE`: Events were lost. The first word is how many.
+//! - `0xFF`: Stream information, sent first. The words are the frequency of
+//!   the clock in Hz, and the number of bits of the timestamps.
+
+use core::cell::Cell;
+
+use kernel::ErrorCode;
+use kernel::ProcessId;
+use kernel::collections::queue::Queue;
+use kernel::collections::ring_buffer::RingBuffer;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::time::{Frequency, Ticks, Time};
+use kernel::hil::uart;
+use kernel::syscall::{Syscall, SyscallReturn, SyscallTrace};
+use kernel::utilities::arch_helpers::{TRD104SyscallReturn, encode_syscall_return_trd104};
+use kernel::utilities::cells::{MapCell, TakeCell};
+
+/// Length of an event in the stream.
+pub const EVENT_LEN: usize = 24;
+
+const SYNC: u8 = 0xA5;
+
+mod kind {
+    pub const RETURN: u8 = 0x80;
+    pub const DROPPED: u8 = 0xFE;
+    pub const INFO: u8 = 0xFF;
+}
+
+/// A trace event waiting to be sent.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TraceEvent {
+    kind: u8,
+    process: u16,
+    timestamp: u32,
+    words: [u32; 4],
+}
+
+impl TraceEvent {
+    fn encode(&self, out: &mut [u8]) {
+        out[0] = SYNC;
+        out[1] = self.kind;
+        out[2..4].copy_from_slice(&self.process.to_le_bytes());
+        out[4..8].copy_from_slice(&self.timestamp.to_le_bytes());
+        for (i, word) in self.words.iter().enumerate() {
+            out[8 + 4 * i..12 + 4 * i].copy_from_slice(&word.to_le_bytes());
+        }
+    }
+}
+
+pub struct SyscallTraceBuffer<'a, T: Time, U: uart::Transmit<'a>> {
+    time: &'a T,
+    uart: &'a U,
+    events: MapCell<RingBuffer<'static, TraceEvent>>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    /// Number of events overwritten before they were sent.
+    dropped: Cell<u32>,
+    /// Whether the stream information was sent.
+    info_sent: Cell<bool>,
+    deferred_call: DeferredCall,
+}
+
+impl<'a, T: Time, U: uart::Transmit<'a>> SyscallTraceBuffer<'a, T, U> {
+    pub fn new(
+        time: &'a T,
+        uart: &'a U,
+        events: &'static mut [core::mem::MaybeUninit<TraceEvent>],
+        tx_buffer: &'static mut [u8],
+    ) -> Self {
+        Self {
+            time,
+            uart,
+            events: MapCell::new(RingBuffer::new(events)),
+            tx_buffer: TakeCell::new(tx_buffer),
+            dropped: Cell::new(0),
+            info_sent: Cell::new(false),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    fn record(&self, processid: ProcessId, kind: u8, words: [u32; 4]) {
+        let event = TraceEvent {
+            kind,
+            process: processid.id() as u16,
+            timestamp: self.time.now().into_u32(),
+            words,
+        };
+        self.events.map(|events| {
+            if events.push(event).is_some() {
+                self.dropped.set(self.dropped.get().saturating_add(1));
+            }
+        });
+        // Send the events once the kernel is done with the system call,
+        // rather than while it handles it.
+        if self.tx_buffer.is_some() && !self.deferred_call.is_pending() {
+            self.deferred_call.set();
+        }
+    }
+
+    /// Send as many events as fit in the transmit buffer.
+    fn send(&self) {
+        let Some(tx_buffer) = self.tx_buffer.take() else {
+            return;
+        };
+        let mut chunks = tx_buffer.chunks_exact_mut(EVENT_LEN);
+        let mut len = 0;
+
+        if !self.info_sent.get() {
+            if let Some(chunk) = chunks.next() {
+                self.info_sent.set(true);
+                let info = TraceEvent {
+                    kind: kind::INFO,
+                    process: 0,
+                    timestamp: 0,
+                    words: [T::Frequency::frequency(), T::Ticks::width(), 0, 0],
+                };
+                info.encode(chunk);
+                len += EVENT_LEN;
+            }
+        }
+        self.events.map(|events| {
+            let dropped = self.dropped.get();
+            if dropped > 0 {
+                if let Some(chunk) = chunks.next() {
+                    self.dropped.set(0);
+                    let lost = TraceEvent {
+                        kind: kind::DROPPED,
+                        process: 0,
+                        timestamp: self.time.now().into_u32(),
+                        words: [dropped, 0, 0, 0],
+                    };
+                    lost.encode(chunk);
+                    len += EVENT_LEN;
+                }
+            }
+            for chunk in chunks {
+                match events.dequeue() {
+                    Some(event) => event.encode(chunk),
+                    None => break,
+                }
+                len += EVENT_LEN;
+            }
+        });
+
+        if len == 0 {
+            self.tx_buffer.replace(tx_buffer);
+            return;
+        }
+        if let Err((_, tx_buffer)) = self.uart.transmit_buffer(tx_buffer, len) {
+            self.tx_buffer.replace(tx_buffer);
+        }
+    }
+}
+
+impl<'a, T: Time, U: uart::Transmit<'a>> SyscallTrace for SyscallTraceBuffer<'a, T, U> {
+    fn syscall(&self, processid: ProcessId, syscall: &Syscall) {
+        let (class, words) = syscall.class_and_arguments();
+        self.record(processid, class as u8, words.map(|word| word as u32));
+    }
+
+    fn syscall_return(&self, processid: ProcessId, return_value: &SyscallReturn) {
+        let mut words = [0; 4];
+        let [a0, a1, a2, a3] = &mut words;
+        encode_syscall_return_trd104(
+            &TRD104SyscallReturn::from_syscall_return(*return_value),
+            a0,
+            a1,
+            a2,
+            a3,
+        );
+        self.record(processid, kind::RETURN, words);
+    }
+}
+
+impl<'a, T: Time, U: uart::Transmit<'a>> uart::TransmitClient for SyscallTraceBuffer<'a, T, U> {
+    fn transmitted_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        _tx_len: usize,
+        _rval: Result<(), ErrorCode>,
+    ) {
+        self.tx_buffer.replace(tx_buffer);
+        self.send();
+    }
+}
+
+impl<'a, T: Time, U: uart::Transmit<'a>> DeferredCallClient for SyscallTraceBuffer<'a, T, U> {
+    fn handle_deferred_call(&self) {
+        self.send();
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
@@ -3,6 +3,10 @@
 // Copyright Tock Contributors 2022.
 
 //! Test the AES GCM implementation on top of AES hardware.
+//!
+//! Runs NIST CAVP (gcmEncryptExtIV128) vectors and test case 4 of the GCM
+//! specification in both directions, then checks that decrypting the last
+//! vector with a modified tag fails authentication.
 
 use core::cell::Cell;
 use kernel::ErrorCode;
@@ -16,6 +20,8 @@ pub struct Test<'a, A: AESGCM<'a, AES128>> {
     buf: TakeCell<'static, [u8]>,
     current_test: Cell<usize>,
     encrypting: Cell<bool>,
+    /// Decrypting the last vector with a modified tag.
+    tampered: Cell<bool>,
 
     // (key, iv, pt, aad, ct, tag)
     tests: [(
@@ -25,7 +31,7 @@ pub struct Test<'a, A: AESGCM<'a, AES128>> {
         &'static [u8],
         &'static [u8],
         &'static [u8],
-    ); 2],
+    ); 3],
 }
 
 impl<'a, A: AESGCM<'a, AES128>> Test<'a, A> {
@@ -35,6 +41,7 @@ impl<'a, A: AESGCM<'a, AES128>> Test<'a, A> {
             buf: TakeCell::new(buf),
             current_test: Cell::new(0),
             encrypting: Cell::new(true),
+            tampered: Cell::new(false),
             tests: [
                 (
                     &KEY_128_TWELVE,
@@ -52,6 +59,14 @@ impl<'a, A: AESGCM<'a, AES128>> Test<'a, A> {
                     &CT_128_THIRTEEN,
                     &TAG_128_THIRTEEN,
                 ),
+                (
+                    &KEY_GCM_CASE_4,
+                    &IV_GCM_CASE_4,
+                    &PT_GCM_CASE_4,
+                    &AAD_GCM_CASE_4,
+                    &CT_GCM_CASE_4,
+                    &TAG_GCM_CASE_4,
+                ),
             ],
         }
     }
@@ -62,14 +77,16 @@ impl<'a, A: AESGCM<'a, AES128>> Test<'a, A> {
     }
 
     fn next_test(&self) -> bool {
+        if self.tampered.get() {
+            return false;
+        }
         if self.encrypting.get() {
             self.encrypting.set(false);
-        } else {
+        } else if self.current_test.get() + 1 < self.tests.len() {
             self.encrypting.set(true);
             self.current_test.set(self.current_test.get() + 1);
-            if self.current_test.get() >= self.tests.len() {
-                return false;
-            }
+        } else {
+            self.tampered.set(true);
         }
         true
     }
@@ -91,6 +108,9 @@ impl<'a, A: AESGCM<'a, AES128>> Test<'a, A> {
             buf[aad_off..pt_off].copy_from_slice(aad);
             buf[pt_off..pt_off + pt_len].copy_from_slice(ct);
             buf[pt_off + pt_len..(pt_off + pt_len + tag.len())].copy_from_slice(tag);
+            if self.tampered.get() {
+                buf[pt_off + pt_len] ^= 0x01;
+            }
         }
 
         if self.aes_gcm.set_key(key) != Ok(()) {
@@ -120,7 +140,12 @@ impl<'a, A: AESGCM<'a, AES128>> Test<'a, A> {
             Some(buf) => buf,
         };
 
-        if encrypting {
+        if self.tampered.get() {
+            if tag_is_valid {
+                panic!("aes_gcm_test failed: a modified tag was accepted");
+            }
+            debug!("aes_gcm_test passed: modified tag rejected");
+        } else if encrypting {
             let ct_matches = buf[pt_off..(pt_off + pt_len)]
                 .iter()
                 .zip(ct.iter())
@@ -236,3 +261,34 @@ static CT_128_THIRTEEN: [u8; 32] = [
 static TAG_128_THIRTEEN: [u8; 16] = [
     0x42, 0x26, 0x93, 0x16, 0xce, 0xce, 0x7d, 0x88, 0x2c, 0xc6, 0x8c, 0x3e, 0xd9, 0xd2, 0xf0, 0xae,
 ];
+
+static KEY_GCM_CASE_4: [u8; AES128_KEY_SIZE] = [
+    0xfe, 0xff, 0xe9, 0x92, 0x86, 0x65, 0x73, 0x1c, 0x6d, 0x6a, 0x8f, 0x94, 0x67, 0x30, 0x83, 0x08,
+];
+
+static IV_GCM_CASE_4: [u8; 12] = [
+    0xca, 0xfe, 0xba, 0xbe, 0xfa, 0xce, 0xdb, 0xad, 0xde, 0xca, 0xf8, 0x88,
+];
+
+static AAD_GCM_CASE_4: [u8; 20] = [
+    0xfe, 0xed, 0xfa, 0xce, 0xde, 0xad, 0xbe, 0xef, 0xfe, 0xed, 0xfa, 0xce, 0xde, 0xad, 0xbe, 0xef,
+    0xab, 0xad, 0xda, 0xd2,
+];
+
+static PT_GCM_CASE_4: [u8; 60] = [
+    0xd9, 0x31, 0x32, 0x25, 0xf8, 0x84, 0x06, 0xe5, 0xa5, 0x59, 0x09, 0xc5, 0xaf, 0xf5, 0x26, 0x9a,
+    0x86, 0xa7, 0xa9, 0x53, 0x15, 0x34, 0xf7, 0xda, 0x2e, 0x4c, 0x30, 0x3d, 0x8a, 0x31, 0x8a, 0x72,
+    0x1c, 0x3c, 0x0c, 0x95, 0x95, 0x68, 0x09, 0x53, 0x2f, 0xcf, 0x0e, 0x24, 0x49, 0xa6, 0xb5, 0x25,
+    0xb1, 0x6a, 0xed, 0xf5, 0xaa, 0x0d, 0xe6, 0x57, 0xba, 0x63, 0x7b, 0x39,
+];
+
+static CT_GCM_CASE_4: [u8; 60] = [
+    0x42, 0x83, 0x1e, 0xc2, 0x21, 0x77, 0x74, 0x24, 0x4b, 0x72, 0x21, 0xb7, 0x84, 0xd0, 0xd4, 0x9c,
+    0xe3, 0xaa, 0x21, 0x2f, 0x2c, 0x02, 0xa4, 0xe0, 0x35, 0xc1, 0x7e, 0x23, 0x29, 0xac, 0xa1, 0x2e,
+    0x21, 0xd5, 0x14, 0xb2, 0x54, 0x66, 0x93, 0x1c, 0x7d, 0x8f, 0x6a, 0x5a, 0xac, 0x84, 0xaa, 0x05,
+    0x1b, 0xa3, 0x0b, 0x39, 0x6a, 0x0a, 0xac, 0x97, 0x3d, 0x58, 0xe0, 0x91,
+];
+
+static TAG_GCM_CASE_4: [u8; 16] = [
+    0x5b, 0xc9, 0x4f, 0xbc, 0x32, 0x21, 0xa5, 0xdb, 0x94, 0xfa, 0xe9, 0x5a, 0xe7, 0x12, 0x1a, 0x47,
+];
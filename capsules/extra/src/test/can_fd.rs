@@ -0,0 +1,221 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Test CAN FD transmission and reception in loopback mode.
+//!
+//! The test puts the peripheral in `OperationMode::Loopback` with bit rate
+//! switching enabled and sends a 64-byte frame with an extended identifier.
+//! It passes if the peripheral receives the same frame back. The peripheral
+//! is disabled when the test finishes.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let test = static_init!(
+//!     capsules_extra::test::can_fd::TestCanFd<'static, Fdcan<'static>>,
+//!     capsules_extra::test::can_fd::TestCanFd::new(
+//!         &peripherals.fdcan1,
+//!         500_000,
+//!         payload_bit_timing,
+//!         static_init!([u8; can::FD_CAN_PACKET_SIZE], [0; can::FD_CAN_PACKET_SIZE]),
+//!         static_init!([u8; can::FD_CAN_PACKET_SIZE], [0; can::FD_CAN_PACKET_SIZE]),
+//!     )
+//! );
+//! can::Controller::set_client(&peripherals.fdcan1, Some(test));
+//! can::Transmit::set_client(&peripherals.fdcan1, Some(test));
+//! can::Receive::set_client(&peripherals.fdcan1, Some(test));
+//! test.run();
+//! ```
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::ErrorCode;
+use kernel::debug;
+use kernel::hil::can::{self, FD_CAN_PACKET_SIZE};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+
+/// Identifier of the test frame.
+pub const TEST_ID: can::Id = can::Id::Extended(0x18DA_F110);
+
+pub struct TestCanFd<'a, C: can::CanFd> {
+    can: &'a C,
+    bitrate: u32,
+    payload_bit_timing: can::BitTiming,
+    tx_buffer: TakeCell<'static, [u8; FD_CAN_PACKET_SIZE]>,
+    rx_buffer: TakeCell<'static, [u8; FD_CAN_PACKET_SIZE]>,
+    result: MapCell<Result<(), CapsuleTestError>>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl<'a, C: can::CanFd> TestCanFd<'a, C> {
+    pub fn new(
+        can: &'a C,
+        bitrate: u32,
+        payload_bit_timing: can::BitTiming,
+        tx_buffer: &'static mut [u8; FD_CAN_PACKET_SIZE],
+        rx_buffer: &'static mut [u8; FD_CAN_PACKET_SIZE],
+    ) -> Self {
+        TestCanFd {
+            can,
+            bitrate,
+            payload_bit_timing,
+            tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            result: MapCell::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        let res = self
+            .can
+            .set_operation_mode(can::OperationMode::Loopback)
+            .and_then(|()| self.can.set_bitrate(self.bitrate))
+            .and_then(|()| self.can.set_payload_bit_timing(self.payload_bit_timing))
+            .and_then(|()| self.can.set_bit_rate_switching(true))
+            .and_then(|()| self.can.enable());
+        if let Err(error) = res {
+            debug!(
+                "CanFdTest ERROR: failed to configure the peripheral: {:?}",
+                error
+            );
+            self.done(Err(CapsuleTestError::ErrorCode(error)));
+        }
+    }
+
+    /// The byte at `index` of the test frame.
+    fn expected(index: usize) -> u8 {
+        (index as u8).wrapping_mul(37).wrapping_add(1)
+    }
+
+    fn send(&self) -> Result<(), ErrorCode> {
+        let buffer = self.tx_buffer.take().ok_or(ErrorCode::NOMEM)?;
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = Self::expected(i);
+        }
+        self.can
+            .send(TEST_ID, buffer, FD_CAN_PACKET_SIZE)
+            .map_err(|(error, buffer)| {
+                self.tx_buffer.replace(buffer);
+                error
+            })
+    }
+
+    /// Stops receiving and disables the peripheral, then reports `result`.
+    fn finish(&self, result: Result<(), CapsuleTestError>) {
+        self.result.replace(result);
+        let res = if self.rx_buffer.is_none() {
+            self.can.stop_receive()
+        } else {
+            self.can.disable()
+        };
+        if let Err(error) = res {
+            debug!(
+                "CanFdTest ERROR: failed to stop the peripheral: {:?}",
+                error
+            );
+            self.report();
+        }
+    }
+
+    fn report(&self) {
+        if let Some(result) = self.result.take() {
+            self.done(result);
+        }
+    }
+
+    fn done(&self, result: Result<(), CapsuleTestError>) {
+        if result.is_ok() {
+            debug!("CanFdTest: loopback frame received");
+        }
+        self.client.map(|client| client.done(result));
+    }
+}
+
+impl<C: can::CanFd> can::ControllerClient for TestCanFd<'_, C> {
+    fn state_changed(&self, _state: can::State) {}
+
+    fn enabled(&self, status: Result<(), ErrorCode>) {
+        let res = status.and_then(|()| {
+            let buffer = self.rx_buffer.take().ok_or(ErrorCode::NOMEM)?;
+            self.can
+                .start_receive_process(buffer)
+                .map_err(|(error, buffer)| {
+                    self.rx_buffer.replace(buffer);
+                    error
+                })
+        });
+        if let Err(error) = res.and_then(|()| self.send()) {
+            debug!("CanFdTest ERROR: failed to start the test: {:?}", error);
+            self.finish(Err(CapsuleTestError::ErrorCode(error)));
+        }
+    }
+
+    fn disabled(&self, _status: Result<(), ErrorCode>) {
+        self.report();
+    }
+}
+
+impl<C: can::CanFd> can::TransmitClient<FD_CAN_PACKET_SIZE> for TestCanFd<'_, C> {
+    fn transmit_complete(
+        &self,
+        status: Result<(), can::Error>,
+        buffer: &'static mut [u8; FD_CAN_PACKET_SIZE],
+    ) {
+        self.tx_buffer.replace(buffer);
+        if let Err(error) = status {
+            debug!("CanFdTest ERROR: failed to send the frame: {:?}", error);
+            self.finish(Err(CapsuleTestError::ErrorCode(error.into())));
+        }
+    }
+}
+
+impl<C: can::CanFd> can::ReceiveClient<FD_CAN_PACKET_SIZE> for TestCanFd<'_, C> {
+    fn message_received(
+        &self,
+        id: can::Id,
+        buffer: &mut [u8; FD_CAN_PACKET_SIZE],
+        len: usize,
+        status: Result<(), can::Error>,
+    ) {
+        let result = match status {
+            Err(error) => {
+                debug!("CanFdTest ERROR: failed to receive the frame: {:?}", error);
+                Err(CapsuleTestError::ErrorCode(error.into()))
+            }
+            Ok(()) => {
+                let matches = id == TEST_ID
+                    && len == FD_CAN_PACKET_SIZE
+                    && buffer
+                        .iter()
+                        .enumerate()
+                        .all(|(i, byte)| *byte == Self::expected(i));
+                if matches {
+                    Ok(())
+                } else {
+                    debug!("CanFdTest ERROR: received {:?} ({} bytes)", id, len);
+                    Err(CapsuleTestError::IncorrectResult)
+                }
+            }
+        };
+        self.finish(result);
+    }
+
+    fn stopped(&self, buffer: &'static mut [u8; FD_CAN_PACKET_SIZE]) {
+        self.rx_buffer.replace(buffer);
+        if let Err(error) = self.can.disable() {
+            debug!(
+                "CanFdTest ERROR: failed to disable the peripheral: {:?}",
+                error
+            );
+            self.report();
+        }
+    }
+}
+
+impl<C: can::CanFd> CapsuleTest for TestCanFd<'_, C> {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
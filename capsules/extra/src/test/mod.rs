@@ -5,6 +5,7 @@
 pub mod aes;
 pub mod aes_ccm;
 pub mod aes_gcm;
+pub mod can_fd;
 pub mod crc;
 pub mod hmac_sha256;
 pub mod kv_system;
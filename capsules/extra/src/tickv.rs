@@ -37,6 +37,7 @@ use core::cell::Cell;
 use kernel::ErrorCode;
 use kernel::hil::flash::{self, Flash};
 use kernel::hil::hasher::{self, Hasher};
+use kernel::hil::kv;
 use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
 use kernel::utilities::leasable_buffer::{SubSlice, SubSliceMut};
 use tickv::AsyncTicKV;
@@ -203,6 +204,7 @@ enum Operation {
     AppendKey,
     InvalidateKey,
     GarbageCollect,
+    Stats,
 }
 
 /// Wrapper object that provides the flash interface TicKV expects using the
@@ -276,6 +278,10 @@ impl<F: Flash, const PAGE_SIZE: usize> tickv::flash_controller::FlashController<
     }
 
     fn erase_region(&self, region_number: usize) -> Result<(), tickv::error_codes::ErrorCode> {
+        // `write()` updates the last page that was read, so make sure a
+        // region header written after the erase doesn't bring back old data.
+        self.flash_read_buffer.map(|buf| buf.as_mut().fill(0xFF));
+
         let _ = self.flash.erase_page(self.region_offset + region_number);
 
         Err(tickv::error_codes::ErrorCode::EraseNotReady(region_number))
@@ -304,6 +310,8 @@ pub struct TicKVSystem<'a, F: Flash + 'static, H: Hasher<'a, 8>, const PAGE_SIZE
     value_buffer: MapCell<SubSliceMut<'static, u8>>,
     /// Callback client when the `KVSystem` operation completes.
     client: OptionalCell<&'a dyn KVSystemClient<TicKVKeyType>>,
+    /// Callback client when collecting statistics completes.
+    statistics_client: OptionalCell<&'a dyn kv::StatisticsClient>,
 }
 
 impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> TicKVSystem<'a, F, H, PAGE_SIZE> {
@@ -330,9 +338,23 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> TicKVSystem<'a, F,
             key_buffer: TakeCell::empty(),
             value_buffer: MapCell::empty(),
             client: OptionalCell::empty(),
+            statistics_client: OptionalCell::empty(),
         }
     }
 
+    /// Set the number of erases a region may be ahead of the least worn
+    /// region before new objects are stored elsewhere. `None` disables wear
+    /// leveling.
+    pub fn set_wear_leveling_threshold(&self, threshold: Option<u32>) {
+        self.tickv.tickv.set_wear_leveling_threshold(threshold);
+    }
+
+    /// Set the buffer used by garbage collection to move objects out of
+    /// worn regions. Objects larger than the buffer are not moved.
+    pub fn set_relocation_buffer(&self, buffer: &'a mut [u8]) {
+        self.tickv.tickv.set_relocation_buffer(buffer);
+    }
+
     pub fn initialise(&self) {
         let _ret = self.tickv.initialise(0x7bc9f7ff4f76f244);
         self.operation.set(Operation::Init);
@@ -376,9 +398,54 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> TicKVSystem<'a, F,
                     });
                 }
             }
+            Operation::Stats => {
+                if let Err(error) = kv::KVStatistics::get_statistics(self) {
+                    self.statistics_client.map(move |cb| {
+                        cb.statistics_complete(Err(error));
+                    });
+                }
+            }
         }
         self.next_operation.set(Operation::None);
     }
+
+    /// Continue an initialisation or garbage collection after an erase or a
+    /// region header write has completed.
+    fn continue_region_operation(&self) {
+        let (ret, tickv_buf, tickv_buf_len) = self.tickv.continue_operation();
+
+        // If we got the buffer back from TicKV then store it.
+        tickv_buf.map(|buf| {
+            let mut val_buf = SubSliceMut::new(buf);
+            if tickv_buf_len > 0 {
+                // Length of zero means nothing was inserted into the buffer so
+                // no need to slice it.
+                val_buf.slice(0..tickv_buf_len);
+            }
+            self.value_buffer.replace(val_buf);
+        });
+
+        match self.operation.get() {
+            Operation::Init => match ret {
+                Ok(tickv::success_codes::SuccessCode::Complete)
+                | Ok(tickv::success_codes::SuccessCode::Written) => {
+                    self.complete_init();
+                }
+                _ => {}
+            },
+            Operation::GarbageCollect => match ret {
+                Ok(tickv::success_codes::SuccessCode::Complete)
+                | Ok(tickv::success_codes::SuccessCode::Written) => {
+                    self.operation.set(Operation::None);
+                    self.client.map(|cb| {
+                        cb.garbage_collect_complete(Ok(()));
+                    });
+                }
+                _ => {}
+            },
+            _ => unreachable!(),
+        }
+    }
 }
 
 impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> hasher::Client<8>
@@ -554,6 +621,27 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> flash::Client<F>
                 }
                 _ => {}
             },
+            Operation::Stats => match ret {
+                Ok(tickv::success_codes::SuccessCode::Complete) => {
+                    self.operation.set(Operation::None);
+                    let stats = self.tickv.take_stats().ok_or(ErrorCode::FAIL);
+                    self.statistics_client.map(|cb| {
+                        cb.statistics_complete(stats.map(|stats| kv::Statistics {
+                            free_bytes: stats.free_bytes,
+                            invalidated_bytes: stats.invalidated_bytes,
+                            min_erase_count: stats.min_erase_count,
+                            max_erase_count: stats.max_erase_count,
+                        }));
+                    });
+                }
+                Err(tickv::error_codes::ErrorCode::ReadNotReady(_)) => {}
+                _ => {
+                    self.operation.set(Operation::None);
+                    self.statistics_client.map(|cb| {
+                        cb.statistics_complete(Err(ErrorCode::FAIL));
+                    });
+                }
+            },
             _ => unreachable!(),
         }
     }
@@ -567,7 +655,16 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> flash::Client<F>
 
         match self.operation.get() {
             Operation::Init => {
-                self.complete_init();
+                if self.tickv.operation_pending() {
+                    // A region header was written while formatting the flash
+                    self.continue_region_operation();
+                } else {
+                    self.complete_init();
+                }
+            }
+            Operation::GarbageCollect => {
+                // A region header was written after reclaiming a region
+                self.continue_region_operation();
             }
             Operation::AppendKey => {
                 self.operation.set(Operation::None);
@@ -590,39 +687,7 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> flash::Client<F>
     }
 
     fn erase_complete(&self, _result: Result<(), flash::Error>) {
-        let (ret, tickv_buf, tickv_buf_len) = self.tickv.continue_operation();
-
-        // If we got the buffer back from TicKV then store it.
-        tickv_buf.map(|buf| {
-            let mut val_buf = SubSliceMut::new(buf);
-            if tickv_buf_len > 0 {
-                // Length of zero means nothing was inserted into the buffer so
-                // no need to slice it.
-                val_buf.slice(0..tickv_buf_len);
-            }
-            self.value_buffer.replace(val_buf);
-        });
-
-        match self.operation.get() {
-            Operation::Init => match ret {
-                Ok(tickv::success_codes::SuccessCode::Complete)
-                | Ok(tickv::success_codes::SuccessCode::Written) => {
-                    self.complete_init();
-                }
-                _ => {}
-            },
-            Operation::GarbageCollect => match ret {
-                Ok(tickv::success_codes::SuccessCode::Complete)
-                | Ok(tickv::success_codes::SuccessCode::Written) => {
-                    self.operation.set(Operation::None);
-                    self.client.map(|cb| {
-                        cb.garbage_collect_complete(Ok(()));
-                    });
-                }
-                _ => {}
-            },
-            _ => unreachable!(),
-        }
+        self.continue_region_operation();
     }
 }
 
@@ -778,3 +843,33 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> KVSystem<'a>
         }
     }
 }
+
+impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> kv::KVStatistics<'a>
+    for TicKVSystem<'a, F, H, PAGE_SIZE>
+{
+    fn set_statistics_client(&self, client: &'a dyn kv::StatisticsClient) {
+        self.statistics_client.set(client);
+    }
+
+    fn get_statistics(&self) -> Result<(), ErrorCode> {
+        match self.operation.get() {
+            Operation::None => {
+                self.operation.set(Operation::Stats);
+                self.tickv.stats().and(Ok(())).map_err(|_| {
+                    self.operation.set(Operation::None);
+                    ErrorCode::FAIL
+                })
+            }
+            Operation::Init => {
+                // The init process is still occurring.
+                // We can save this request and start it after init.
+                self.next_operation.set(Operation::Stats);
+                Ok(())
+            }
+            _ => {
+                // An operation is already in process.
+                Err(ErrorCode::BUSY)
+            }
+        }
+    }
+}
@@ -0,0 +1,1058 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Encrypted and authenticated layer for a `KVSystem`.
+//!
+//! This capsule sits between a K-V store (such as `TicKVKVStore`) and the
+//! `KVSystem` storing the data (such as `TicKVSystem`). Values are encrypted
+//! with AES-128-GCM under a device key before they are written to flash, so a
+//! device with external flash doesn't expose its stored values to anyone who
+//! can read the flash chip.
+//!
+//! The device key must be a secret of the device, for example derived by a
+//! key manager from a per-device secret. A key compiled into the kernel is
+//! known to anyone who has the kernel image, and doesn't protect the values.
+//!
+//! ```text
+//! +-----------------------+
+//! |  TickVKVStore         |
+//! +-----------------------+
+//!
+//!    capsules::tickv::KVSystem
+//!
+//! +-----------------------+        +-------------------+
+//! |  EncryptedKVSystem    | -----> | MonotonicCounter  |
+//! |  (this file)          |        +-------------------+
+//! +-----------------------+
+//!       |             |
+//!       |        hil::symmetric_encryption::AESGCM
+//!       |
+//!    capsules::tickv::KVSystem
+//!
+//! +-----------------------+
+//! |  TicKVSystem          |
+//! +-----------------------+
+//! ```
+//!
+//! Record Format
+//! -------------
+//!
+//! Each value is stored as:
+//!
+//! ```text
+//! +---------+--------------+----------------+----------+
+//! | version | counter (BE) | ciphertext     | tag      |
+//! | 1 byte  | 8 bytes      | value length   | 16 bytes |
+//! +---------+--------------+----------------+----------+
+//! ```
+//!
+//! The associated data is the hashed key followed by the version and the
+//! counter. Binding the hashed key means a record can't be moved to a
+//! different key without the tag check failing. The nonce is the counter, so
+//! a nonce is never reused under the device key.
+//!
+//! Rollback Protection
+//! -------------------
+//!
+//! Encryption alone doesn't stop an attacker from restoring an older copy of
+//! the flash, or of a single record, bringing back a deleted or overwritten
+//! value. To detect this the capsule uses a `MonotonicCounter` which must be
+//! stored somewhere the attacker can't roll back (for example OTP or internal
+//! flash).
+//!
+//! Every write or invalidation increments the counter and then stores a
+//! counter record, authenticated the same way as values. The counter record
+//! holds the counter value, a rollback limit and a table with the counter of
+//! the newest record of every key in the store:
+//!
+//! ```text
+//! +----------------+-----------------+-----------------+-----
+//! | rollback limit | hashed key 0    | counter 0 (BE)  | ...
+//! | 8 bytes (BE)   | 8 bytes         | 8 bytes         |
+//! +----------------+-----------------+-----------------+-----
+//! ```
+//!
+//! A value is only returned if its counter matches the counter in the table
+//! for its key, so restoring an older record of one key, or a record of a
+//! key that has since been invalidated, is rejected with `FAIL`. The table
+//! holds a fixed number of keys, set by the buffer passed to `new()`. Writing
+//! a new key when the table is full fails with `NOMEM`. Two counter records
+//! are used alternately, so a power loss while one is updated leaves the
+//! other intact.
+//!
+//! When the capsule is initialised the newest counter record is compared to
+//! the monotonic counter. If the store is behind the counter it has been
+//! rolled back as a whole, and every record written before that point is
+//! rejected with `FAIL` when read. The counter records also store this
+//! rollback limit so it persists across reboots.
+//!
+//! As a power loss can occur between incrementing the counter and writing the
+//! counter record, a store one write behind the counter is accepted. This
+//! means the last write before a reset can be rolled back. A key whose value
+//! was written but whose counter record wasn't returns `FAIL` until it is
+//! written again.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let encrypted_kv = static_init!(
+//!     EncryptedKVSystem<'static, TicKVSystemType, Aes128GcmType, CounterType>,
+//!     EncryptedKVSystem::new(
+//!         tickv,
+//!         aes_gcm,
+//!         counter,
+//!         device_key,
+//!         buffer,
+//!         counter_key,
+//!         key_counters
+//!     )
+//! );
+//! tickv.set_client(encrypted_kv);
+//! aes_gcm.set_client(encrypted_kv);
+//! counter.set_client(encrypted_kv);
+//! encrypted_kv.initialise();
+//! ```
+
+use crate::tickv::{KVSystem, KVSystemClient, TicKVKeyType};
+use core::cell::Cell;
+use kernel::ErrorCode;
+use kernel::hil::symmetric_encryption::{AES128, AES128_KEY_SIZE, AESGCM, GCMClient};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+
+/// A counter that can only be incremented.
+///
+/// The value must survive resets and must not be restorable to an earlier
+/// value by anyone with access to the flash storing the K-V data.
+pub trait MonotonicCounter<'a> {
+    /// Set the client.
+    fn set_client(&self, client: &'a dyn MonotonicCounterClient);
+
+    /// Read the current value of the counter.
+    ///
+    /// On success `read_complete()` will be called.
+    fn read(&self) -> Result<(), ErrorCode>;
+
+    /// Increment the counter by one.
+    ///
+    /// On success `increment_complete()` will be called.
+    fn increment(&self) -> Result<(), ErrorCode>;
+}
+
+/// Implement this trait and use `set_client()` in order to receive callbacks.
+pub trait MonotonicCounterClient {
+    /// This callback is called when the read operation completes.
+    ///
+    /// - `result`: The counter value on success, 'ErrorCode' on error
+    fn read_complete(&self, result: Result<u64, ErrorCode>);
+
+    /// This callback is called when the increment operation completes.
+    ///
+    /// - `result`: The new counter value on success, 'ErrorCode' on error
+    fn increment_complete(&self, result: Result<u64, ErrorCode>);
+}
+
+/// The version of the record format.
+const VERSION: u8 = 0;
+/// The length of the hashed key in the associated data.
+const KEY_LEN: usize = 8;
+/// The length of the version and counter stored in front of the ciphertext.
+const HEADER_LEN: usize = 9;
+/// The length of the GCM tag.
+const TAG_LEN: usize = 16;
+/// The offset of the message in the buffer while it is encrypted or
+/// decrypted, after the associated data.
+const MESSAGE_OFFSET: usize = KEY_LEN + HEADER_LEN;
+/// The length of the rollback limit at the start of a counter record.
+const ROLLBACK_LIMIT_LEN: usize = 8;
+/// The length of each entry in the key table of a counter record.
+const KEY_COUNTER_LEN: usize = KEY_LEN + 8;
+
+/// The number of bytes the buffer passed to `EncryptedKVSystem::new()` needs
+/// in addition to the largest value that will be stored.
+pub const BUFFER_OVERHEAD: usize = MESSAGE_OFFSET + TAG_LEN;
+
+/// The length of the buffer needed to store values of up to `max_value_len`
+/// bytes, with a key table of `max_keys` entries.
+pub const fn buffer_len(max_value_len: usize, max_keys: usize) -> usize {
+    let counter_record_len = ROLLBACK_LIMIT_LEN + max_keys * KEY_COUNTER_LEN;
+    if max_value_len > counter_record_len {
+        max_value_len + BUFFER_OVERHEAD
+    } else {
+        counter_record_len + BUFFER_OVERHEAD
+    }
+}
+
+/// The counter of the newest record of a key.
+#[derive(Clone, Copy, Default)]
+pub struct KeyCounter {
+    key: TicKVKeyType,
+    counter: u64,
+}
+
+/// The hashed keys of the two counter records.
+const COUNTER_KEYS: [TicKVKeyType; 2] = [*b"tkvctr\x00\x00", *b"tkvctr\x00\x01"];
+
+/// Last byte of the nonce for values.
+const VALUE_NONCE: u8 = 0;
+/// Last byte of the nonce for counter records, which share counter values
+/// with the value written just before them.
+const COUNTER_RECORD_NONCE: u8 = 1;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Status {
+    /// `initialise()` hasn't completed yet.
+    Uninitialised,
+    /// The store has been checked and can be used.
+    Ready,
+    /// The monotonic counter couldn't be read or is behind the store, which
+    /// would lead to nonce reuse.
+    Failed,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Operation {
+    None,
+    ReadCounter,
+    ReadCounterRecord(usize),
+    DecryptCounterRecord(usize),
+    GetValue,
+    DecryptValue,
+    IncrementForAppend,
+    EncryptValue,
+    AppendValue,
+    InvalidateValue,
+    IncrementForInvalidate,
+    InvalidateCounterRecord,
+    EncryptCounterRecord,
+    AppendCounterRecord,
+    GarbageCollect,
+}
+
+/// `EncryptedKVSystem` implements `KVSystem` by encrypting values and storing
+/// them in another `KVSystem`.
+pub struct EncryptedKVSystem<
+    'a,
+    K: KVSystem<'a, K = TicKVKeyType>,
+    A: AESGCM<'a, AES128>,
+    C: MonotonicCounter<'a>,
+> {
+    kv: &'a K,
+    aes: &'a A,
+    counter: &'a C,
+    device_key: [u8; AES128_KEY_SIZE],
+
+    status: Cell<Status>,
+    operation: Cell<Operation>,
+    /// The last value of the monotonic counter.
+    counter_value: Cell<u64>,
+    /// Records with a counter lower than this were rolled back.
+    rollback_limit: Cell<u64>,
+    /// The counter of the newest counter record found while initialising.
+    newest_counter_record: OptionalCell<u64>,
+    /// The counter of the newest record of each key, the first `key_count`
+    /// entries are used.
+    key_counters: TakeCell<'static, [KeyCounter]>,
+    key_count: Cell<usize>,
+    /// The number of entries of `key_counters` that fit in a counter record.
+    max_keys: usize,
+    /// The counter of the record being decrypted.
+    record_counter: Cell<u64>,
+    /// The length of the value being decrypted.
+    message_len: Cell<usize>,
+    /// The result of an append or invalidate, reported after the counter
+    /// record is updated.
+    result: Cell<Result<(), ErrorCode>>,
+
+    /// Work buffer used to encrypt and decrypt records.
+    buffer: TakeCell<'static, [u8]>,
+    /// Buffer holding the hashed key of a counter record.
+    counter_key: TakeCell<'static, TicKVKeyType>,
+    /// The caller's hashed key.
+    key: TakeCell<'static, TicKVKeyType>,
+    /// The caller's value or return buffer.
+    value: MapCell<SubSliceMut<'static, u8>>,
+
+    client: OptionalCell<&'a dyn KVSystemClient<TicKVKeyType>>,
+}
+
+impl<'a, K: KVSystem<'a, K = TicKVKeyType>, A: AESGCM<'a, AES128>, C: MonotonicCounter<'a>>
+    EncryptedKVSystem<'a, K, A, C>
+{
+    /// Create a new `EncryptedKVSystem`.
+    ///
+    /// - `buffer`: Work buffer, values up to `buffer.len() - BUFFER_OVERHEAD`
+    ///   bytes long can be stored. The AES-GCM implementation may impose a
+    ///   lower limit. Use `buffer_len()` to size it.
+    /// - `counter_key`: Buffer used for the hashed key of counter records.
+    /// - `key_counters`: Table of the keys in the store, limiting the number
+    ///   of keys that can be stored. Entries that don't fit in a counter
+    ///   record in `buffer` are not used.
+    pub fn new(
+        kv: &'a K,
+        aes: &'a A,
+        counter: &'a C,
+        device_key: [u8; AES128_KEY_SIZE],
+        buffer: &'static mut [u8],
+        counter_key: &'static mut TicKVKeyType,
+        key_counters: &'static mut [KeyCounter],
+    ) -> EncryptedKVSystem<'a, K, A, C> {
+        let max_keys = (buffer
+            .len()
+            .saturating_sub(BUFFER_OVERHEAD + ROLLBACK_LIMIT_LEN)
+            / KEY_COUNTER_LEN)
+            .min(key_counters.len());
+
+        Self {
+            kv,
+            aes,
+            counter,
+            device_key,
+            status: Cell::new(Status::Uninitialised),
+            operation: Cell::new(Operation::None),
+            counter_value: Cell::new(0),
+            rollback_limit: Cell::new(0),
+            newest_counter_record: OptionalCell::empty(),
+            key_counters: TakeCell::new(key_counters),
+            key_count: Cell::new(0),
+            max_keys,
+            record_counter: Cell::new(0),
+            message_len: Cell::new(0),
+            result: Cell::new(Ok(())),
+            buffer: TakeCell::new(buffer),
+            counter_key: TakeCell::new(counter_key),
+            key: TakeCell::empty(),
+            value: MapCell::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Check the store for rollback. This must be called after the underlying
+    /// `KVSystem` has been initialised. Until this completes all operations
+    /// return `BUSY`.
+    pub fn initialise(&self) {
+        if self.status.get() != Status::Uninitialised || self.operation.get() != Operation::None {
+            return;
+        }
+
+        self.operation.set(Operation::ReadCounter);
+        if self.counter.read().is_err() {
+            self.operation.set(Operation::None);
+            self.status.set(Status::Failed);
+        }
+    }
+
+    /// Returns the error for a request that can't be started now, if any.
+    fn check_ready(&self) -> Result<(), ErrorCode> {
+        match self.status.get() {
+            Status::Uninitialised => Err(ErrorCode::BUSY),
+            Status::Failed => Err(ErrorCode::FAIL),
+            Status::Ready if self.operation.get() != Operation::None => Err(ErrorCode::BUSY),
+            Status::Ready => Ok(()),
+        }
+    }
+
+    /// Start encrypting or decrypting the `message_len` bytes at
+    /// `MESSAGE_OFFSET` in `buffer`, authenticating `key` and the record
+    /// header.
+    fn start_crypt(
+        &self,
+        buffer: &'static mut [u8],
+        key: &TicKVKeyType,
+        counter: u64,
+        nonce: u8,
+        message_len: usize,
+        encrypting: bool,
+    ) -> Result<(), ErrorCode> {
+        buffer[0..KEY_LEN].copy_from_slice(key);
+        buffer[KEY_LEN] = VERSION;
+        buffer[(KEY_LEN + 1)..MESSAGE_OFFSET].copy_from_slice(&counter.to_be_bytes());
+
+        let mut iv = [0; 12];
+        iv[0..8].copy_from_slice(&counter.to_be_bytes());
+        iv[11] = nonce;
+
+        let res = self
+            .aes
+            .set_key(&self.device_key)
+            .and_then(|()| self.aes.set_iv(&iv));
+        if let Err(e) = res {
+            self.buffer.replace(buffer);
+            return Err(e);
+        }
+
+        self.aes
+            .crypt(buffer, 0, MESSAGE_OFFSET, message_len, TAG_LEN, encrypting)
+            .map_err(|(e, buffer)| {
+                self.buffer.replace(buffer);
+                e
+            })
+    }
+
+    /// Check the header of the record of `length` bytes at the start of
+    /// `buffer` and move it after the associated data. Returns the counter
+    /// and the message length.
+    fn unpack_record(buffer: &mut [u8], length: usize) -> Result<(u64, usize), ErrorCode> {
+        if length < HEADER_LEN + TAG_LEN || length + KEY_LEN > buffer.len() {
+            return Err(ErrorCode::FAIL);
+        }
+        if buffer[0] != VERSION {
+            return Err(ErrorCode::FAIL);
+        }
+
+        let mut counter = [0; 8];
+        counter.copy_from_slice(&buffer[1..HEADER_LEN]);
+
+        buffer.copy_within(0..length, KEY_LEN);
+        Ok((u64::from_be_bytes(counter), length - HEADER_LEN - TAG_LEN))
+    }
+
+    /// Move the encrypted record to the start of `buffer`, returning it as a
+    /// value to pass to the underlying `KVSystem`.
+    fn pack_record(buffer: &'static mut [u8], message_len: usize) -> SubSliceMut<'static, u8> {
+        let length = HEADER_LEN + message_len + TAG_LEN;
+        buffer.copy_within(KEY_LEN..(KEY_LEN + length), 0);
+
+        let mut record = SubSliceMut::new(buffer);
+        record.slice(0..length);
+        record
+    }
+
+    /// Returns the counter of the newest record of `key`.
+    fn key_counter(&self, key: &TicKVKeyType) -> Option<u64> {
+        self.key_counters.map_or(None, |key_counters| {
+            key_counters[..self.key_count.get()]
+                .iter()
+                .find(|entry| entry.key == *key)
+                .map(|entry| entry.counter)
+        })
+    }
+
+    /// Returns whether a record of `key` can be written without the key
+    /// table overflowing.
+    fn has_room_for(&self, key: &TicKVKeyType) -> bool {
+        self.key_count.get() < self.max_keys || self.key_counter(key).is_some()
+    }
+
+    /// Record `counter` as the counter of the newest record of `key`.
+    fn set_key_counter(&self, key: &TicKVKeyType, counter: u64) {
+        let count = self.key_count.get();
+        self.key_counters.map(|key_counters| {
+            if let Some(entry) = key_counters[..count]
+                .iter_mut()
+                .find(|entry| entry.key == *key)
+            {
+                entry.counter = counter;
+            } else if count < self.max_keys {
+                key_counters[count] = KeyCounter { key: *key, counter };
+                self.key_count.set(count + 1);
+            }
+        });
+    }
+
+    /// Remove `key` from the key table.
+    fn remove_key_counter(&self, key: &TicKVKeyType) {
+        let count = self.key_count.get();
+        self.key_counters.map(|key_counters| {
+            if let Some(index) = key_counters[..count]
+                .iter()
+                .position(|entry| entry.key == *key)
+            {
+                key_counters[index] = key_counters[count - 1];
+                self.key_count.set(count - 1);
+            }
+        });
+    }
+
+    /// Returns whether `message_len` is a valid length for a counter record
+    /// with a key table that fits in memory.
+    fn is_counter_record_len(&self, message_len: usize) -> bool {
+        message_len >= ROLLBACK_LIMIT_LEN
+            && (message_len - ROLLBACK_LIMIT_LEN).is_multiple_of(KEY_COUNTER_LEN)
+            && (message_len - ROLLBACK_LIMIT_LEN) / KEY_COUNTER_LEN <= self.max_keys
+    }
+
+    /// Load the rollback limit and key table from the decrypted counter
+    /// record `message`.
+    fn load_counter_record(&self, message: &[u8]) {
+        let mut rollback_limit = [0; ROLLBACK_LIMIT_LEN];
+        rollback_limit.copy_from_slice(&message[..ROLLBACK_LIMIT_LEN]);
+        self.rollback_limit.set(u64::from_be_bytes(rollback_limit));
+
+        let entries = message[ROLLBACK_LIMIT_LEN..].chunks_exact(KEY_COUNTER_LEN);
+        self.key_count.set(entries.len());
+        self.key_counters.map(|key_counters| {
+            for (entry, bytes) in key_counters.iter_mut().zip(entries) {
+                let mut counter = [0; 8];
+                entry.key.copy_from_slice(&bytes[..KEY_LEN]);
+                counter.copy_from_slice(&bytes[KEY_LEN..]);
+                entry.counter = u64::from_be_bytes(counter);
+            }
+        });
+    }
+
+    fn read_counter_record(&self, slot: usize) {
+        let (Some(key), Some(buffer)) = (self.counter_key.take(), self.buffer.take()) else {
+            self.finish_init();
+            return;
+        };
+        *key = COUNTER_KEYS[slot];
+
+        self.operation.set(Operation::ReadCounterRecord(slot));
+        if let Err((key, buffer, _e)) = self.kv.get_value(key, SubSliceMut::new(buffer)) {
+            self.counter_key.replace(key);
+            self.buffer.replace(buffer.take());
+            self.next_counter_record(slot);
+        }
+    }
+
+    fn next_counter_record(&self, slot: usize) {
+        if slot + 1 < COUNTER_KEYS.len() {
+            self.read_counter_record(slot + 1);
+        } else {
+            self.finish_init();
+        }
+    }
+
+    /// Compare the newest counter record to the monotonic counter.
+    fn finish_init(&self) {
+        let counter = self.counter_value.get();
+
+        self.operation.set(Operation::None);
+        match self.newest_counter_record.take() {
+            Some(record_counter) if record_counter > counter => {
+                // The counter has gone backwards, so using it again would
+                // reuse nonces.
+                self.status.set(Status::Failed);
+                return;
+            }
+            Some(record_counter) if record_counter + 1 >= counter => {
+                // The rollback limit and key table were loaded with the
+                // record.
+            }
+            None if counter == 0 => {}
+            _ => {
+                // The store has been rolled back (or the counter records
+                // removed), don't trust anything written before now.
+                self.rollback_limit.set(counter + 1);
+                self.key_count.set(0);
+            }
+        }
+        self.status.set(Status::Ready);
+    }
+
+    /// Record the counter value in the counter record after an append or
+    /// invalidate.
+    fn commit(&self) {
+        let Some(key) = self.counter_key.take() else {
+            self.finish(Err(ErrorCode::FAIL));
+            return;
+        };
+        *key = COUNTER_KEYS[(self.counter_value.get() % 2) as usize];
+
+        self.operation.set(Operation::InvalidateCounterRecord);
+        if let Err((key, _e)) = self.kv.invalidate_key(key) {
+            self.counter_key.replace(key);
+            self.encrypt_counter_record();
+        }
+    }
+
+    fn encrypt_counter_record(&self) {
+        let (Some(buffer), Some(key)) = (self.buffer.take(), self.counter_key.map(|key| *key))
+        else {
+            self.finish(Err(ErrorCode::FAIL));
+            return;
+        };
+        let count = self.key_count.get();
+        let message_len = ROLLBACK_LIMIT_LEN + count * KEY_COUNTER_LEN;
+        let message = &mut buffer[MESSAGE_OFFSET..(MESSAGE_OFFSET + message_len)];
+        message[..ROLLBACK_LIMIT_LEN].copy_from_slice(&self.rollback_limit.get().to_be_bytes());
+        self.key_counters.map(|key_counters| {
+            for (bytes, entry) in message[ROLLBACK_LIMIT_LEN..]
+                .chunks_exact_mut(KEY_COUNTER_LEN)
+                .zip(key_counters[..count].iter())
+            {
+                bytes[..KEY_LEN].copy_from_slice(&entry.key);
+                bytes[KEY_LEN..].copy_from_slice(&entry.counter.to_be_bytes());
+            }
+        });
+
+        self.operation.set(Operation::EncryptCounterRecord);
+        if let Err(e) = self.start_crypt(
+            buffer,
+            &key,
+            self.counter_value.get(),
+            COUNTER_RECORD_NONCE,
+            message_len,
+            true,
+        ) {
+            self.finish(Err(e));
+        }
+    }
+
+    /// Report the result of an append or invalidate.
+    fn finish(&self, result: Result<(), ErrorCode>) {
+        let result = self.result.get().and(result);
+        self.operation.set(Operation::None);
+
+        let Some(key) = self.key.take() else {
+            return;
+        };
+        match self.value.take() {
+            Some(value) => self.client.map(move |cb| {
+                cb.append_key_complete(result, key, value);
+            }),
+            None => self.client.map(move |cb| {
+                cb.invalidate_key_complete(result, key);
+            }),
+        };
+    }
+
+    fn finish_get(&self, result: Result<(), ErrorCode>) {
+        self.operation.set(Operation::None);
+
+        if let (Some(key), Some(value)) = (self.key.take(), self.value.take()) {
+            self.client.map(move |cb| {
+                cb.get_value_complete(result, key, value);
+            });
+        }
+    }
+}
+
+impl<'a, K: KVSystem<'a, K = TicKVKeyType>, A: AESGCM<'a, AES128>, C: MonotonicCounter<'a>>
+    KVSystem<'a> for EncryptedKVSystem<'a, K, A, C>
+{
+    type K = TicKVKeyType;
+
+    fn set_client(&self, client: &'a dyn KVSystemClient<Self::K>) {
+        self.client.set(client);
+    }
+
+    fn generate_key(
+        &self,
+        unhashed_key: SubSliceMut<'static, u8>,
+        key_buf: &'static mut Self::K,
+    ) -> Result<(), (SubSliceMut<'static, u8>, &'static mut Self::K, ErrorCode)> {
+        self.kv.generate_key(unhashed_key, key_buf)
+    }
+
+    fn append_key(
+        &self,
+        key: &'static mut Self::K,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<(), (&'static mut Self::K, SubSliceMut<'static, u8>, ErrorCode)> {
+        if let Err(e) = self.check_ready() {
+            return Err((key, value, e));
+        }
+        if self
+            .buffer
+            .map_or(true, |buffer| value.len() + BUFFER_OVERHEAD > buffer.len())
+        {
+            return Err((key, value, ErrorCode::SIZE));
+        }
+        if !self.has_room_for(key) {
+            return Err((key, value, ErrorCode::NOMEM));
+        }
+
+        // The counter is incremented before the value is encrypted, so a
+        // power loss can't cause the nonce to be used again.
+        self.operation.set(Operation::IncrementForAppend);
+        if let Err(e) = self.counter.increment() {
+            self.operation.set(Operation::None);
+            return Err((key, value, e));
+        }
+
+        self.result.set(Ok(()));
+        self.key.replace(key);
+        self.value.replace(value);
+        Ok(())
+    }
+
+    fn get_value(
+        &self,
+        key: &'static mut Self::K,
+        ret_buf: SubSliceMut<'static, u8>,
+    ) -> Result<(), (&'static mut Self::K, SubSliceMut<'static, u8>, ErrorCode)> {
+        if ret_buf.is_sliced() {
+            return Err((key, ret_buf, ErrorCode::SIZE));
+        }
+        if let Err(e) = self.check_ready() {
+            return Err((key, ret_buf, e));
+        }
+        let Some(buffer) = self.buffer.take() else {
+            return Err((key, ret_buf, ErrorCode::FAIL));
+        };
+
+        self.operation.set(Operation::GetValue);
+        match self.kv.get_value(key, SubSliceMut::new(buffer)) {
+            Ok(()) => {
+                self.value.replace(ret_buf);
+                Ok(())
+            }
+            Err((key, buffer, e)) => {
+                self.operation.set(Operation::None);
+                self.buffer.replace(buffer.take());
+                Err((key, ret_buf, e))
+            }
+        }
+    }
+
+    fn invalidate_key(
+        &self,
+        key: &'static mut Self::K,
+    ) -> Result<(), (&'static mut Self::K, ErrorCode)> {
+        if let Err(e) = self.check_ready() {
+            return Err((key, e));
+        }
+
+        self.operation.set(Operation::InvalidateValue);
+        self.kv.invalidate_key(key).inspect_err(|_| {
+            self.operation.set(Operation::None);
+        })
+    }
+
+    fn garbage_collect(&self) -> Result<(), ErrorCode> {
+        self.check_ready()?;
+
+        self.operation.set(Operation::GarbageCollect);
+        self.kv.garbage_collect().inspect_err(|_| {
+            self.operation.set(Operation::None);
+        })
+    }
+}
+
+impl<'a, K: KVSystem<'a, K = TicKVKeyType>, A: AESGCM<'a, AES128>, C: MonotonicCounter<'a>>
+    KVSystemClient<TicKVKeyType> for EncryptedKVSystem<'a, K, A, C>
+{
+    fn generate_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        unhashed_key: SubSliceMut<'static, u8>,
+        key_buf: &'static mut TicKVKeyType,
+    ) {
+        self.client.map(move |cb| {
+            cb.generate_key_complete(result, unhashed_key, key_buf);
+        });
+    }
+
+    fn append_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut TicKVKeyType,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        self.buffer.replace(value.take());
+
+        match self.operation.get() {
+            Operation::AppendValue => {
+                if result.is_ok() {
+                    self.set_key_counter(key, self.counter_value.get());
+                }
+
+                // Update the counter record even if the append failed, as
+                // the counter has already been incremented.
+                self.key.replace(key);
+                self.result.set(result);
+                self.commit();
+            }
+            Operation::AppendCounterRecord => {
+                self.counter_key.replace(key);
+                self.finish(result);
+            }
+            _ => {}
+        }
+    }
+
+    fn get_value_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut TicKVKeyType,
+        ret_buf: SubSliceMut<'static, u8>,
+    ) {
+        let length = ret_buf.len();
+        let buffer = ret_buf.take();
+
+        match self.operation.get() {
+            Operation::ReadCounterRecord(slot) => {
+                self.counter_key.replace(key);
+
+                let record = result
+                    .and_then(|()| Self::unpack_record(buffer, length))
+                    .and_then(|(counter, message_len)| {
+                        if self.is_counter_record_len(message_len) {
+                            Ok((counter, message_len))
+                        } else {
+                            Err(ErrorCode::FAIL)
+                        }
+                    });
+                let Ok((counter, message_len)) = record else {
+                    self.buffer.replace(buffer);
+                    self.next_counter_record(slot);
+                    return;
+                };
+
+                self.record_counter.set(counter);
+                self.message_len.set(message_len);
+                self.operation.set(Operation::DecryptCounterRecord(slot));
+                if self
+                    .start_crypt(
+                        buffer,
+                        &COUNTER_KEYS[slot],
+                        counter,
+                        COUNTER_RECORD_NONCE,
+                        message_len,
+                        false,
+                    )
+                    .is_err()
+                {
+                    self.next_counter_record(slot);
+                }
+            }
+            Operation::GetValue => {
+                let hashed_key = *key;
+                self.key.replace(key);
+
+                let record = result.and_then(|()| Self::unpack_record(buffer, length));
+                let (counter, message_len) = match record {
+                    Ok((counter, _))
+                        if counter < self.rollback_limit.get()
+                            || self.key_counter(&hashed_key) != Some(counter) =>
+                    {
+                        // Written before the store was rolled back, or not
+                        // the newest record of this key.
+                        self.buffer.replace(buffer);
+                        self.finish_get(Err(ErrorCode::FAIL));
+                        return;
+                    }
+                    Ok(record) => record,
+                    Err(e) => {
+                        self.buffer.replace(buffer);
+                        self.finish_get(Err(e));
+                        return;
+                    }
+                };
+
+                self.message_len.set(message_len);
+                self.operation.set(Operation::DecryptValue);
+                if let Err(e) = self.start_crypt(
+                    buffer,
+                    &hashed_key,
+                    counter,
+                    VALUE_NONCE,
+                    message_len,
+                    false,
+                ) {
+                    self.finish_get(Err(e));
+                }
+            }
+            _ => {
+                self.buffer.replace(buffer);
+            }
+        }
+    }
+
+    fn invalidate_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut TicKVKeyType,
+    ) {
+        match self.operation.get() {
+            Operation::InvalidateValue => {
+                if let Err(e) = result {
+                    self.operation.set(Operation::None);
+                    self.client.map(move |cb| {
+                        cb.invalidate_key_complete(Err(e), key);
+                    });
+                    return;
+                }
+
+                self.remove_key_counter(key);
+                self.key.replace(key);
+                self.result.set(Ok(()));
+                self.operation.set(Operation::IncrementForInvalidate);
+                if let Err(e) = self.counter.increment() {
+                    self.finish(Err(e));
+                }
+            }
+            Operation::InvalidateCounterRecord => {
+                // The counter record won't exist for the first write.
+                self.counter_key.replace(key);
+                self.encrypt_counter_record();
+            }
+            _ => {}
+        }
+    }
+
+    fn garbage_collect_complete(&self, result: Result<(), ErrorCode>) {
+        self.operation.set(Operation::None);
+        self.client.map(move |cb| {
+            cb.garbage_collect_complete(result);
+        });
+    }
+}
+
+impl<'a, K: KVSystem<'a, K = TicKVKeyType>, A: AESGCM<'a, AES128>, C: MonotonicCounter<'a>>
+    GCMClient for EncryptedKVSystem<'a, K, A, C>
+{
+    fn crypt_done(&self, buf: &'static mut [u8], res: Result<(), ErrorCode>, tag_is_valid: bool) {
+        let res = res.and(if tag_is_valid {
+            Ok(())
+        } else {
+            Err(ErrorCode::FAIL)
+        });
+
+        match self.operation.get() {
+            Operation::DecryptCounterRecord(slot) => {
+                let record_counter = self.record_counter.get();
+                if res.is_ok()
+                    && self
+                        .newest_counter_record
+                        .map_or(true, |newest| record_counter > newest)
+                {
+                    self.newest_counter_record.set(record_counter);
+                    self.load_counter_record(
+                        &buf[MESSAGE_OFFSET..(MESSAGE_OFFSET + self.message_len.get())],
+                    );
+                }
+
+                self.buffer.replace(buf);
+                self.next_counter_record(slot);
+            }
+            Operation::DecryptValue => {
+                let length = self.message_len.get();
+                let result = res.and_then(|()| {
+                    self.value.map_or(Err(ErrorCode::FAIL), |ret_buf| {
+                        // Copy as much as fits, like `TicKVSystem` does.
+                        let copy_len = length.min(ret_buf.len());
+                        ret_buf[0..copy_len]
+                            .copy_from_slice(&buf[MESSAGE_OFFSET..(MESSAGE_OFFSET + copy_len)]);
+                        ret_buf.slice(0..copy_len);
+
+                        if copy_len < length {
+                            Err(ErrorCode::SIZE)
+                        } else {
+                            Ok(())
+                        }
+                    })
+                });
+
+                // Don't leave the plaintext in the work buffer.
+                buf.fill(0);
+                self.buffer.replace(buf);
+                self.finish_get(result);
+            }
+            Operation::EncryptValue => {
+                let message_len = self.value.map_or(0, |value| value.len());
+                let record = Self::pack_record(buf, message_len);
+
+                let Some(key) = self.key.take() else {
+                    self.buffer.replace(record.take());
+                    self.finish(Err(ErrorCode::FAIL));
+                    return;
+                };
+                if let Err(e) = res {
+                    self.key.replace(key);
+                    self.buffer.replace(record.take());
+                    self.result.set(Err(e));
+                    self.commit();
+                    return;
+                }
+
+                self.operation.set(Operation::AppendValue);
+                if let Err((key, record, e)) = self.kv.append_key(key, record) {
+                    self.key.replace(key);
+                    self.buffer.replace(record.take());
+                    self.result.set(Err(e));
+                    self.commit();
+                }
+            }
+            Operation::EncryptCounterRecord => {
+                let message_len = ROLLBACK_LIMIT_LEN + self.key_count.get() * KEY_COUNTER_LEN;
+                let record = Self::pack_record(buf, message_len);
+
+                let Some(key) = self.counter_key.take() else {
+                    self.buffer.replace(record.take());
+                    self.finish(Err(ErrorCode::FAIL));
+                    return;
+                };
+                if let Err(e) = res {
+                    self.counter_key.replace(key);
+                    self.buffer.replace(record.take());
+                    self.finish(Err(e));
+                    return;
+                }
+
+                self.operation.set(Operation::AppendCounterRecord);
+                if let Err((key, record, e)) = self.kv.append_key(key, record) {
+                    self.counter_key.replace(key);
+                    self.buffer.replace(record.take());
+                    self.finish(Err(e));
+                }
+            }
+            _ => {
+                self.buffer.replace(buf);
+            }
+        }
+    }
+}
+
+impl<'a, K: KVSystem<'a, K = TicKVKeyType>, A: AESGCM<'a, AES128>, C: MonotonicCounter<'a>>
+    MonotonicCounterClient for EncryptedKVSystem<'a, K, A, C>
+{
+    fn read_complete(&self, result: Result<u64, ErrorCode>) {
+        match result {
+            Ok(counter) => {
+                self.counter_value.set(counter);
+                self.read_counter_record(0);
+            }
+            Err(_) => {
+                self.operation.set(Operation::None);
+                self.status.set(Status::Failed);
+            }
+        }
+    }
+
+    fn increment_complete(&self, result: Result<u64, ErrorCode>) {
+        let counter = match result {
+            Ok(counter) => counter,
+            Err(e) => {
+                self.finish(Err(e));
+                return;
+            }
+        };
+        self.counter_value.set(counter);
+
+        match self.operation.get() {
+            Operation::IncrementForAppend => {
+                let Some(buffer) = self.buffer.take() else {
+                    self.finish(Err(ErrorCode::FAIL));
+                    return;
+                };
+                let Some(key) = self.key.map(|key| *key) else {
+                    self.buffer.replace(buffer);
+                    self.finish(Err(ErrorCode::FAIL));
+                    return;
+                };
+                let message_len = self.value.map_or(0, |value| {
+                    buffer[MESSAGE_OFFSET..(MESSAGE_OFFSET + value.len())]
+                        .copy_from_slice(value.as_slice());
+                    value.len()
+                });
+
+                self.operation.set(Operation::EncryptValue);
+                if let Err(e) =
+                    self.start_crypt(buffer, &key, counter, VALUE_NONCE, message_len, true)
+                {
+                    self.result.set(Err(e));
+                    self.commit();
+                }
+            }
+            Operation::IncrementForInvalidate => {
+                self.commit();
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,350 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Over-the-air app loading over UDP.
+//!
+//! This capsule receives TBF images from a host on the network and loads them
+//! as new apps, without a loader app running on the node. Images are stored
+//! with [`DynamicBinaryStore`] and loaded with [`DynamicProcessLoad`], so the
+//! process checker verifies their credentials with the board's
+//! `AppCredentialsPolicy` before they run. Boards that want to accept only
+//! signed images must use a policy that requires credentials.
+//!
+//! The same storage driver can only have one client, so a board uses either
+//! this capsule or the `app_loader` capsule with it.
+//!
+//! Protocol
+//! --------
+//!
+//! The host sends requests to the port the capsule is bound to, and the
+//! capsule replies to the port the request came from. All integers are big
+//! endian. Every request starts with a type and a 16 bit session number
+//! chosen by the host:
+//!
+//! ```text
+//! START  | 0x01 | session | length (u32) |
+//! DATA   | 0x02 | session | offset (u32) | data ... |
+//! FINISH | 0x03 | session |
+//! ABORT  | 0x04 | session |
+//! STATUS | 0x05 | session |
+//! ```
+//!
+//! Every request is answered with an acknowledgment which contains the
+//! number of bytes of the image the node has stored, and the result of the
+//! request as a status code (0 for success, or an `ErrorCode`):
+//!
+//! ```text
+//! ACK    | 0x80 | session | offset (u32) | status (u8) |
+//! ```
+//!
+//! The transfer is stop-and-wait: the host sends the chunk at `offset` and
+//! waits for its acknowledgment before sending the next one. Requests for
+//! which no acknowledgment arrives can be repeated; a chunk that doesn't
+//! start at the acknowledged offset is not stored, and the acknowledgment
+//! tells the host where to continue. To resume a transfer, for example after
+//! the host restarted, it sends STATUS or START again with the same session
+//! and length, from the same address.
+//!
+//! Once all bytes are acknowledged the host sends FINISH. The acknowledgment
+//! for FINISH is sent once the app was loaded, with `ErrorCode::INVAL` if
+//! its credentials were rejected and `ErrorCode::NOMEM` if there are no
+//! resources to run it.
+//!
+//! Only one image is transferred at a time. During a transfer, only requests
+//! of its session from the address the transfer came from are accepted, all
+//! others are answered with `ErrorCode::BUSY`. The host may send from another
+//! port, acknowledgments go to the port of its latest request. A START of a
+//! new session from the address the transfer came from cancels a transfer
+//! that is still receiving data, so a host can recover from a transfer it
+//! abandoned. Other hosts can't cancel it.
+//!
+//! Usage
+//! -----
+//! See `components::udp_app_loader`.
+
+use core::cell::Cell;
+
+use kernel::ErrorCode;
+use kernel::dynamic_binary_storage::{
+    DynamicBinaryStore, DynamicBinaryStoreClient, DynamicProcessLoad, DynamicProcessLoadClient,
+};
+use kernel::errorcode::into_statuscode;
+use kernel::process::ProcessLoadError;
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+
+use crate::app_installer::{AppInstaller, State};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::udp::udp_recv::UDPRecvClient;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+
+/// Message types.
+mod message {
+    pub const START: u8 = 0x01;
+    pub const DATA: u8 = 0x02;
+    pub const FINISH: u8 = 0x03;
+    pub const ABORT: u8 = 0x04;
+    pub const STATUS: u8 = 0x05;
+    pub const ACK: u8 = 0x80;
+}
+
+/// Length of the type and session fields.
+const HEADER_LEN: usize = 3;
+
+/// Length of an acknowledgment.
+pub const ACK_LEN: usize = HEADER_LEN + 5;
+
+/// Length of the buffer chunks are written to flash from. Chunks can't be
+/// longer than this.
+pub const BUF_LEN: usize = 256;
+
+pub struct UdpAppLoader<'a, S: DynamicBinaryStore + 'static, L: DynamicProcessLoad + 'static> {
+    installer: AppInstaller<'static, S, L>,
+    udp_sender: &'a dyn UDPSender<'a>,
+    net_cap: &'static NetworkCapability,
+
+    /// Buffer chunks are copied into to write them to flash.
+    buffer: TakeCell<'static, [u8]>,
+    /// Buffer for acknowledgments.
+    send_buffer: MapCell<SubSliceMut<'static, u8>>,
+
+    session: Cell<u16>,
+    /// Address and port of the host of the current session.
+    peer: OptionalCell<(IPAddr, u16)>,
+}
+
+impl<'a, S: DynamicBinaryStore + 'static, L: DynamicProcessLoad + 'static> UdpAppLoader<'a, S, L> {
+    pub fn new(
+        storage_driver: &'static S,
+        load_driver: &'static L,
+        udp_sender: &'a dyn UDPSender<'a>,
+        net_cap: &'static NetworkCapability,
+        buffer: &'static mut [u8],
+        send_buffer: SubSliceMut<'static, u8>,
+    ) -> UdpAppLoader<'a, S, L> {
+        UdpAppLoader {
+            installer: AppInstaller::new(storage_driver, load_driver),
+            udp_sender,
+            net_cap,
+            buffer: TakeCell::new(buffer),
+            send_buffer: MapCell::new(send_buffer),
+            session: Cell::new(0),
+            peer: OptionalCell::empty(),
+        }
+    }
+
+    /// Send an acknowledgment to `peer`.
+    ///
+    /// Acknowledgments are dropped if the previous one is still being sent,
+    /// the host repeats its request in that case.
+    fn send_ack(
+        &self,
+        peer: (IPAddr, u16),
+        session: u16,
+        offset: usize,
+        result: Result<(), ErrorCode>,
+    ) {
+        self.send_buffer.take().map(|mut dgram| {
+            dgram.reset();
+            dgram[0] = message::ACK;
+            dgram[1..3].copy_from_slice(&session.to_be_bytes());
+            dgram[3..7].copy_from_slice(&(offset as u32).to_be_bytes());
+            dgram[7] = into_statuscode(result) as u8;
+            dgram.slice(0..ACK_LEN);
+
+            if let Err(mut dgram) = self.udp_sender.send_to(peer.0, peer.1, dgram, self.net_cap) {
+                dgram.reset();
+                self.send_buffer.replace(dgram);
+            }
+        });
+    }
+
+    /// Whether `peer` has the address of the host of the current session.
+    fn is_session_host(&self, peer: (IPAddr, u16)) -> bool {
+        self.peer
+            .get()
+            .is_some_and(|current| current.0.0 == peer.0.0)
+    }
+
+    /// Acknowledge a request of the current session from a callback.
+    fn ack_session(&self, result: Result<(), ErrorCode>) {
+        self.peer.map(|peer| {
+            self.send_ack(peer, self.session.get(), self.installer.offset(), result);
+        });
+    }
+
+    fn start(&self, peer: (IPAddr, u16), session: u16, length: usize) {
+        match self.installer.state() {
+            State::Idle => {
+                self.session.set(session);
+                self.peer.set(peer);
+                if let Err(e) = self.installer.setup(length) {
+                    self.send_ack(peer, session, 0, Err(e));
+                }
+            }
+            // Resume the current transfer.
+            State::Receiving
+                if session == self.session.get()
+                    && length == self.installer.length()
+                    && self.is_session_host(peer) =>
+            {
+                self.peer.set(peer);
+                self.send_ack(peer, session, self.installer.offset(), Ok(()));
+            }
+            // Cancel a transfer the host may have abandoned. The host retries
+            // once the abort is done.
+            State::Receiving if session != self.session.get() && self.is_session_host(peer) => {
+                let _ = self.installer.abort();
+                self.send_ack(peer, session, 0, Err(ErrorCode::BUSY));
+            }
+            _ => self.send_ack(peer, session, 0, Err(ErrorCode::BUSY)),
+        }
+    }
+
+    fn data(&self, peer: (IPAddr, u16), session: u16, offset: usize, data: &[u8]) {
+        if self.installer.state() != State::Receiving {
+            self.send_ack(peer, session, self.installer.offset(), Err(ErrorCode::BUSY));
+            return;
+        }
+        if offset != self.installer.offset() {
+            // Tell the host where to continue.
+            self.send_ack(peer, session, self.installer.offset(), Ok(()));
+            return;
+        }
+        if data.len() > BUF_LEN {
+            self.send_ack(peer, session, offset, Err(ErrorCode::INVAL));
+            return;
+        }
+
+        let Some(buffer) = self.buffer.take() else {
+            self.send_ack(peer, session, offset, Err(ErrorCode::NOMEM));
+            return;
+        };
+        self.peer.set(peer);
+        buffer[..data.len()].copy_from_slice(data);
+        let mut chunk = SubSliceMut::new(buffer);
+        chunk.slice(..data.len());
+        match self.installer.write(chunk, offset) {
+            Ok(()) => {}
+            // The chunk was rejected, the host can send it again.
+            Err((e, Some(buffer))) => {
+                self.buffer.replace(buffer);
+                self.send_ack(peer, session, offset, Err(e));
+            }
+            // The flash driver kept the buffer, so no more images can be
+            // received. Remove what was stored of this one.
+            Err((e, None)) => {
+                let _ = self.installer.abort();
+                self.send_ack(peer, session, offset, Err(e));
+            }
+        }
+    }
+
+    fn finish(&self, peer: (IPAddr, u16), session: u16) {
+        self.peer.set(peer);
+        if let Err(e) = self.installer.finish() {
+            self.send_ack(peer, session, self.installer.offset(), Err(e));
+        }
+    }
+
+    fn abort(&self, peer: (IPAddr, u16), session: u16) {
+        self.peer.set(peer);
+        match self.installer.abort() {
+            Ok(()) => {}
+            // There is nothing to abort.
+            Err(ErrorCode::ALREADY) => self.send_ack(peer, session, 0, Ok(())),
+            Err(e) => self.send_ack(peer, session, self.installer.offset(), Err(e)),
+        }
+    }
+}
+
+impl<S: DynamicBinaryStore + 'static, L: DynamicProcessLoad + 'static> UDPRecvClient
+    for UdpAppLoader<'_, S, L>
+{
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        _dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        let (header, body) = match payload.split_at_checked(HEADER_LEN) {
+            Some(split) => split,
+            None => return,
+        };
+        let peer = (src_addr, src_port);
+        let session = u16::from_be_bytes([header[1], header[2]]);
+        let field = body
+            .first_chunk::<4>()
+            .map(|field| u32::from_be_bytes(*field) as usize);
+
+        // Requests of other sessions or hosts are only accepted while idle,
+        // or to start a new session.
+        let idle = self.installer.state() == State::Idle;
+        let current = idle || (session == self.session.get() && self.is_session_host(peer));
+
+        match (header[0], field) {
+            (message::START, Some(length)) => self.start(peer, session, length),
+            (_, _) if !current => self.send_ack(peer, session, 0, Err(ErrorCode::BUSY)),
+            (message::DATA, Some(offset)) => self.data(peer, session, offset, &body[4..]),
+            (message::FINISH, _) => self.finish(peer, session),
+            (message::ABORT, _) => self.abort(peer, session),
+            (message::STATUS, _) => {
+                let offset = if idle { 0 } else { self.installer.offset() };
+                self.send_ack(peer, session, offset, Ok(()));
+            }
+            _ => self.send_ack(peer, session, 0, Err(ErrorCode::NOSUPPORT)),
+        }
+    }
+}
+
+impl<S: DynamicBinaryStore + 'static, L: DynamicProcessLoad + 'static> UDPSendClient
+    for UdpAppLoader<'_, S, L>
+{
+    fn send_done(&self, _result: Result<(), ErrorCode>, mut dgram: SubSliceMut<'static, u8>) {
+        dgram.reset();
+        self.send_buffer.replace(dgram);
+    }
+}
+
+impl<S: DynamicBinaryStore + 'static, L: DynamicProcessLoad + 'static> DynamicBinaryStoreClient
+    for UdpAppLoader<'_, S, L>
+{
+    fn setup_done(&self, result: Result<(), ErrorCode>) {
+        if let Some(result) = self.installer.setup_done(result) {
+            self.ack_session(result);
+        }
+    }
+
+    fn write_done(&self, result: Result<(), ErrorCode>, buffer: &'static mut [u8], length: usize) {
+        self.buffer.replace(buffer);
+        if let Some(result) = self.installer.write_done(result, length) {
+            self.ack_session(result);
+        }
+    }
+
+    fn finalize_done(&self, result: Result<(), ErrorCode>) {
+        if let Some(result) = self.installer.finalize_done(result) {
+            self.ack_session(result);
+        }
+    }
+
+    fn abort_done(&self, result: Result<(), ErrorCode>) {
+        if self.installer.abort_done(result).is_some() {
+            self.ack_session(Err(ErrorCode::CANCEL));
+        }
+    }
+}
+
+impl<S: DynamicBinaryStore + 'static, L: DynamicProcessLoad + 'static> DynamicProcessLoadClient
+    for UdpAppLoader<'_, S, L>
+{
+    fn load_done(&self, result: Result<(), ProcessLoadError>) {
+        if let Some(result) = self.installer.load_done(result) {
+            self.ack_session(result);
+        }
+    }
+}
@@ -277,6 +277,7 @@ impl<'a, U: hil::usb::UsbController<'a>, A: 'a + Alarm<'a>> CdcAcm<'a, U, A> {
                 endpoints,
                 None, // No HID descriptor
                 Some(cdc_descriptors),
+                None, // No DFU descriptor
             );
 
         Self {
@@ -0,0 +1,1117 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Composite USB device
+//!
+//! This virtualizes a USB controller so that several class drivers, for
+//! example a CTAP security key and a CDC-ACM console, are exposed as the
+//! functions of one device. Each class driver uses a `UsbFunction` as its
+//! `hil::usb::UsbController`, exactly as it would use the hardware:
+//!
+//! ```text
+//!        CdcAcm         CtapHid
+//!          |               |
+//!     UsbFunction     UsbFunction
+//!           \             /
+//!            UsbComposite
+//!                 |
+//!           UsbController
+//! ```
+//!
+//! `UsbComposite` answers the requests for the device:
+//!
+//! - The configuration descriptor is the concatenation of the configuration
+//!   descriptors of the functions, which are read from each function with a
+//!   GET_DESCRIPTOR request when the device is enabled. The interfaces of
+//!   each function are renumbered to follow the ones of the previous
+//!   functions, and the interfaces of functions with several interfaces are
+//!   grouped with an Interface Association Descriptor (IAD), so that the host
+//!   binds them to one driver.
+//! - The device descriptor and the strings are the ones of the composite.
+//!   String indices in the descriptors of the functions are cleared.
+//! - Requests addressed to an interface or an endpoint are forwarded to the
+//!   function owning it, with the interface or endpoint number of the
+//!   function. Other requests to the device that are not standard requests go
+//!   to the first function.
+//!
+//! The endpoints used by the functions are allocated from the endpoints of
+//! the controller in the order the functions configure them, so functions
+//! using the same endpoint numbers can be combined. The endpoint buffers of
+//! the functions are given to the controller as is.
+//!
+//! Usage
+//! -----
+//! See `components::usb_composite`.
+
+use core::cell::Cell;
+use core::cmp::min;
+
+use super::descriptors;
+use super::descriptors::Buffer64;
+use super::descriptors::CdcInterfaceDescriptorSubType;
+use super::descriptors::Descriptor;
+use super::descriptors::DescriptorType;
+use super::descriptors::InterfaceAssociationDescriptor;
+use super::descriptors::LanguagesDescriptor;
+use super::descriptors::Recipient;
+use super::descriptors::SetupData;
+use super::descriptors::StandardRequest;
+use super::descriptors::StringDescriptor;
+
+use kernel::collections::list::{List, ListLink, ListNode};
+use kernel::hil;
+use kernel::hil::usb::TransferType;
+use kernel::utilities::cells::{OptionalCell, VolatileCell};
+
+/// Number of endpoints of the controller, including the control endpoint.
+pub const MAX_ENDPOINTS: usize = 8;
+
+/// Number of endpoint numbers of a function.
+const FUNCTION_ENDPOINTS: usize = 16;
+
+/// Maximum length of the configuration descriptor of a function, as sent by
+/// `ClientCtrl`.
+const FUNCTION_DESCRIPTOR_BUFLEN: usize = 128;
+
+/// Maximum length of the configuration descriptor of the device.
+const CONFIGURATION_BUFLEN: usize = 256;
+
+static LANGUAGES: &[u16; 1] = &[
+    0x0409, // English (United States)
+];
+
+/// States of the control endpoint.
+#[derive(Copy, Clone)]
+enum CtrlState {
+    Idle,
+    /// Sending the descriptor in `descriptor_storage`, with the given extent
+    /// remaining to send.
+    Descriptor(usize, usize),
+    /// Sending the configuration descriptor, with the given extent remaining
+    /// to send.
+    Configuration(usize, usize),
+    SetAddress,
+    /// The transfer is handled by `ctrl_function`.
+    Function,
+}
+
+/// A USB device made of several functions.
+pub struct UsbComposite<'a, U: 'a> {
+    /// The USB hardware controller.
+    controller: &'a U,
+
+    functions: List<'a, UsbFunction<'a, U>>,
+    num_functions: Cell<usize>,
+
+    /// The identifier of the function using each endpoint of the controller.
+    endpoints: [OptionalCell<usize>; MAX_ENDPOINTS],
+
+    /// A 64-byte buffer for the control endpoint.
+    ctrl_buffer: Buffer64,
+    ctrl_state: Cell<CtrlState>,
+    /// The function handling the current control transfer.
+    ctrl_function: OptionalCell<&'a UsbFunction<'a, U>>,
+
+    /// Storage for composing descriptors, and for the configuration
+    /// descriptors of the functions.
+    descriptor_storage: [Cell<u8>; FUNCTION_DESCRIPTOR_BUFLEN],
+
+    /// The configuration descriptor of the device.
+    configuration: [Cell<u8>; CONFIGURATION_BUFLEN],
+    configuration_len: Cell<usize>,
+
+    max_ctrl_packet_size: u8,
+    vendor_id: u16,
+    product_id: u16,
+    strings: &'static [&'static str; 3],
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> UsbComposite<'a, U> {
+    pub fn new(
+        controller: &'a U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+    ) -> Self {
+        Self {
+            controller,
+            functions: List::new(),
+            num_functions: Cell::new(0),
+            endpoints: Default::default(),
+            ctrl_buffer: Buffer64::default(),
+            ctrl_state: Cell::new(CtrlState::Idle),
+            ctrl_function: OptionalCell::empty(),
+            descriptor_storage: [(); FUNCTION_DESCRIPTOR_BUFLEN].map(|()| Cell::default()),
+            configuration: [(); CONFIGURATION_BUFLEN].map(|()| Cell::default()),
+            configuration_len: Cell::new(0),
+            max_ctrl_packet_size,
+            vendor_id,
+            product_id,
+            strings,
+        }
+    }
+
+    fn add_function(&self, function: &'a UsbFunction<'a, U>) {
+        function.id.set(self.num_functions.get());
+        self.num_functions.set(self.num_functions.get() + 1);
+        self.functions.push_tail(function);
+    }
+
+    fn function(&self, id: usize) -> Option<&'a UsbFunction<'a, U>> {
+        self.functions
+            .iter()
+            .find(|function| function.id.get() == id)
+    }
+
+    /// Returns the function using an endpoint of the controller, and the
+    /// endpoint number of the function.
+    fn endpoint_function(&self, endpoint: usize) -> Option<(&'a UsbFunction<'a, U>, usize)> {
+        if endpoint == 0 || endpoint >= MAX_ENDPOINTS {
+            return None;
+        }
+        self.endpoints[endpoint]
+            .get()
+            .and_then(|id| self.function(id))
+            .and_then(|function| {
+                function
+                    .function_endpoint(endpoint)
+                    .map(|function_endpoint| (function, function_endpoint))
+            })
+    }
+
+    fn interface_function(&self, interface: u8) -> Option<&'a UsbFunction<'a, U>> {
+        self.functions.iter().find(|function| {
+            let first = function.first_interface.get();
+            interface >= first && interface - first < function.num_interfaces.get()
+        })
+    }
+
+    /// Allocates an endpoint of the controller to a function.
+    fn allocate_endpoint(&self, id: usize) -> usize {
+        let endpoint = (1..MAX_ENDPOINTS)
+            .find(|&endpoint| self.endpoints[endpoint].is_none())
+            .expect("usb composite: no free endpoint");
+        self.endpoints[endpoint].set(id);
+        endpoint
+    }
+
+    /// Returns the received setup packet, with the index replaced.
+    fn setup_packet(&self, index: u16) -> [u8; 8] {
+        let mut packet = [0; 8];
+        for (byte, cell) in packet.iter_mut().zip(self.ctrl_buffer.buf.iter()) {
+            *byte = cell.get();
+        }
+        packet[4..6].copy_from_slice(&index.to_le_bytes());
+        packet
+    }
+
+    /// Forwards the current setup packet to a function, with the index
+    /// replaced.
+    fn forward_setup(
+        &self,
+        function: &'a UsbFunction<'a, U>,
+        index: u16,
+    ) -> hil::usb::CtrlSetupResult {
+        let result = function.ctrl_setup(self.setup_packet(index));
+        if let hil::usb::CtrlSetupResult::Ok = result {
+            self.ctrl_function.set(function);
+            self.ctrl_state.set(CtrlState::Function);
+        }
+        result
+    }
+
+    /// Reads the configuration descriptor of a function into
+    /// `descriptor_storage`, returning its length.
+    fn read_function_configuration(&self, function: &UsbFunction<'a, U>) -> usize {
+        let mut request = [0; 8];
+        request[0] = 0x80; // Standard request from the device
+        request[1] = 6; // GET_DESCRIPTOR
+        request[3] = DescriptorType::Configuration as u8;
+        request[6..8].copy_from_slice(&(FUNCTION_DESCRIPTOR_BUFLEN as u16).to_le_bytes());
+        if !matches!(function.ctrl_setup(request), hil::usb::CtrlSetupResult::Ok) {
+            return 0;
+        }
+
+        let packet = function.ctrl_packet();
+        let mut len = 0;
+        while let hil::usb::CtrlInResult::Packet(packet_bytes, transfer_complete) =
+            function.ctrl_in()
+        {
+            let packet_bytes = min(
+                min(packet_bytes, packet.len()),
+                FUNCTION_DESCRIPTOR_BUFLEN - len,
+            );
+            for (dst, src) in self.descriptor_storage[len..]
+                .iter()
+                .zip(&packet[..packet_bytes])
+            {
+                dst.set(src.get());
+            }
+            len += packet_bytes;
+            if transfer_complete || packet_bytes == 0 {
+                break;
+            }
+        }
+        function.ctrl_status_complete();
+        len
+    }
+
+    /// Appends the descriptors of a function, read into
+    /// `descriptor_storage`, to the configuration descriptor at `start`.
+    /// Returns the end of the descriptors, or `None` if they do not fit.
+    fn append_function_configuration(
+        &self,
+        function: &UsbFunction<'a, U>,
+        len: usize,
+        start: usize,
+    ) -> Option<usize> {
+        let src = &self.descriptor_storage[..len];
+        let dst = &self.configuration;
+        let first_interface = function.first_interface.get();
+        let num_interfaces = function.num_interfaces.get();
+
+        // Skip the configuration descriptor of the function.
+        let mut i = src[0].get() as usize;
+        let mut end = start;
+        while i + 2 <= len {
+            let desc_len = src[i].get() as usize;
+            if desc_len < 2 || i + desc_len > len {
+                break;
+            }
+            let desc = &src[i..i + desc_len];
+            let desc_type = desc[1].get();
+
+            // Group the interfaces of the function before its first one.
+            if desc_type == DescriptorType::Interface as u8
+                && desc_len >= 9
+                && desc[2].get() == 0
+                && desc[3].get() == 0
+                && num_interfaces > 1
+            {
+                let iad = InterfaceAssociationDescriptor {
+                    first_interface,
+                    interface_count: num_interfaces,
+                    function_class: desc[5].get(),
+                    function_subclass: desc[6].get(),
+                    function_protocol: desc[7].get(),
+                    string_index: 0,
+                };
+                if end + iad.size() > dst.len() {
+                    return None;
+                }
+                end += iad.write_to(&dst[end..]);
+            }
+
+            if end + desc_len > dst.len() {
+                return None;
+            }
+            let out = &dst[end..end + desc_len];
+            for (dst, src) in out.iter().zip(desc) {
+                dst.set(src.get());
+            }
+            if desc_type == DescriptorType::Interface as u8 && desc_len >= 9 {
+                out[2].set(first_interface + desc[2].get());
+                out[8].set(0); // String index
+            } else if desc_type == DescriptorType::Endpoint as u8 && desc_len >= 7 {
+                let address = desc[2].get();
+                let endpoint = function.endpoint((address & 0xf) as usize);
+                out[2].set((address & 0x80) | endpoint as u8);
+            } else if desc_type == DescriptorType::CdcInterface as u8 && desc_len >= 4 {
+                // Functional descriptors referring to interfaces.
+                let subtype = desc[2].get();
+                if subtype == CdcInterfaceDescriptorSubType::CallManagement as u8 && desc_len >= 5 {
+                    out[4].set(first_interface + desc[4].get());
+                } else if subtype == CdcInterfaceDescriptorSubType::Union as u8 {
+                    for interface in &out[3..] {
+                        interface.set(first_interface + interface.get());
+                    }
+                }
+            }
+
+            end += desc_len;
+            i += desc_len;
+        }
+        Some(end)
+    }
+
+    /// Builds the configuration descriptor of the device from the ones of
+    /// the functions.
+    fn build_configuration(&self) {
+        let header_len = descriptors::ConfigurationDescriptor::default().size();
+        let mut len = header_len;
+        let mut num_interfaces = 0;
+        for function in self.functions.iter() {
+            let function_len = self.read_function_configuration(function);
+            if function_len < header_len
+                || self.descriptor_storage[1].get() != DescriptorType::Configuration as u8
+            {
+                continue;
+            }
+            function.first_interface.set(num_interfaces);
+            function
+                .num_interfaces
+                .set(self.descriptor_storage[4].get());
+
+            // A function that does not fit is left out.
+            if let Some(end) = self.append_function_configuration(function, function_len, len) {
+                len = end;
+                num_interfaces += function.num_interfaces.get();
+            } else {
+                function.num_interfaces.set(0);
+            }
+        }
+
+        descriptors::ConfigurationDescriptor {
+            num_interfaces,
+            related_descriptor_length: len - header_len,
+            ..descriptors::ConfigurationDescriptor::default()
+        }
+        .write_to(&self.configuration);
+        self.configuration_len.set(len);
+    }
+
+    fn handle_standard_device_request(
+        &'a self,
+        request: StandardRequest,
+    ) -> hil::usb::CtrlSetupResult {
+        match request {
+            StandardRequest::GetDescriptor {
+                descriptor_type,
+                descriptor_index,
+                lang_id,
+                requested_length,
+            } => {
+                let requested_length = requested_length as usize;
+                match descriptor_type {
+                    DescriptorType::Device => match descriptor_index {
+                        0 => {
+                            let len = descriptors::DeviceDescriptor {
+                                vendor_id: self.vendor_id,
+                                product_id: self.product_id,
+                                manufacturer_string: 1,
+                                product_string: 2,
+                                serial_number_string: 3,
+                                // Miscellaneous device using IADs
+                                class: 0xef,
+                                subclass: 0x02,
+                                protocol: 0x01,
+                                max_packet_size_ep0: self.max_ctrl_packet_size,
+                                ..descriptors::DeviceDescriptor::default()
+                            }
+                            .write_to(&self.descriptor_storage);
+                            self.ctrl_state
+                                .set(CtrlState::Descriptor(0, min(len, requested_length)));
+                            hil::usb::CtrlSetupResult::Ok
+                        }
+                        _ => hil::usb::CtrlSetupResult::ErrInvalidDeviceIndex,
+                    },
+                    DescriptorType::Configuration => match descriptor_index {
+                        0 => {
+                            let len = self.configuration_len.get();
+                            self.ctrl_state
+                                .set(CtrlState::Configuration(0, min(len, requested_length)));
+                            hil::usb::CtrlSetupResult::Ok
+                        }
+                        _ => hil::usb::CtrlSetupResult::ErrInvalidConfigurationIndex,
+                    },
+                    DescriptorType::String => {
+                        let len = match descriptor_index {
+                            0 => LanguagesDescriptor { langs: LANGUAGES }
+                                .write_to(&self.descriptor_storage),
+                            i if (i as usize) <= self.strings.len() && lang_id == LANGUAGES[0] => {
+                                StringDescriptor {
+                                    string: self.strings[i as usize - 1],
+                                }
+                                .write_to(&self.descriptor_storage)
+                            }
+                            _ => return hil::usb::CtrlSetupResult::ErrInvalidStringIndex,
+                        };
+                        self.ctrl_state
+                            .set(CtrlState::Descriptor(0, min(len, requested_length)));
+                        hil::usb::CtrlSetupResult::Ok
+                    }
+                    // We are full-speed only, so we must respond with a
+                    // request error.
+                    DescriptorType::DeviceQualifier => {
+                        hil::usb::CtrlSetupResult::ErrNoDeviceQualifier
+                    }
+                    _ => hil::usb::CtrlSetupResult::ErrUnrecognizedDescriptorType,
+                }
+            }
+            StandardRequest::SetAddress { device_address } => {
+                // The address is enabled in the status stage.
+                self.controller.set_address(device_address);
+                self.ctrl_state.set(CtrlState::SetAddress);
+                hil::usb::CtrlSetupResult::OkSetAddress
+            }
+            StandardRequest::SetConfiguration { .. } => hil::usb::CtrlSetupResult::Ok,
+            _ => hil::usb::CtrlSetupResult::ErrUnrecognizedRequestType,
+        }
+    }
+
+    /// Copies the next packet of a descriptor to the control buffer. Returns
+    /// the result and the new start.
+    fn descriptor_packet(
+        &self,
+        storage: &[Cell<u8>],
+        start: usize,
+        end: usize,
+    ) -> (hil::usb::CtrlInResult, usize) {
+        let packet_bytes = min(self.ctrl_buffer.buf.len(), end.saturating_sub(start));
+        for (dst, src) in self
+            .ctrl_buffer
+            .buf
+            .iter()
+            .zip(&storage[start..start + packet_bytes])
+        {
+            dst.set(src.get());
+        }
+        let start = start + packet_bytes;
+        (
+            hil::usb::CtrlInResult::Packet(packet_bytes, start >= end),
+            start,
+        )
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> hil::usb::Client<'a> for UsbComposite<'a, U> {
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.controller
+            .endpoint_set_ctrl_buffer(&self.ctrl_buffer.buf);
+        self.controller
+            .enable_as_device(hil::usb::DeviceSpeed::Full); // must be Full for Bulk transfers
+        self.controller
+            .endpoint_out_enable(TransferType::Control, 0);
+
+        // The functions configure their endpoints, which allocates them.
+        for function in self.functions.iter() {
+            function.client.map(|client| client.enable());
+        }
+        self.build_configuration();
+    }
+
+    fn attach(&'a self) {
+        for function in self.functions.iter() {
+            function.client.map(|client| client.attach());
+        }
+        self.controller.attach();
+    }
+
+    fn bus_reset(&'a self) {
+        self.ctrl_state.set(CtrlState::Idle);
+        self.ctrl_function.clear();
+        for function in self.functions.iter() {
+            function.client.map(|client| client.bus_reset());
+        }
+    }
+
+    /// Handle a Control Setup transaction.
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        if endpoint != 0 {
+            // For now we only support the default Control endpoint
+            return hil::usb::CtrlSetupResult::ErrInvalidDeviceIndex;
+        }
+        self.ctrl_state.set(CtrlState::Idle);
+        self.ctrl_function.clear();
+
+        let Some(setup_data) = SetupData::get(&self.ctrl_buffer.buf) else {
+            return hil::usb::CtrlSetupResult::ErrNoParse;
+        };
+        match setup_data.request_type.recipient() {
+            Recipient::Interface => {
+                let interface = setup_data.index as u8;
+                self.interface_function(interface).map_or(
+                    hil::usb::CtrlSetupResult::ErrInvalidInterfaceIndex,
+                    |function| {
+                        let interface = interface - function.first_interface.get();
+                        self.forward_setup(function, (setup_data.index & 0xff00) | interface as u16)
+                    },
+                )
+            }
+            Recipient::Endpoint => {
+                let address = setup_data.index as u8;
+                self.endpoint_function((address & 0xf) as usize).map_or(
+                    hil::usb::CtrlSetupResult::ErrGeneric,
+                    |(function, endpoint)| {
+                        let address = (address & 0x80) | endpoint as u8;
+                        self.forward_setup(function, (setup_data.index & 0xff00) | address as u16)
+                    },
+                )
+            }
+            _ => match setup_data.get_standard_request() {
+                Some(request) => self.handle_standard_device_request(request),
+                // Other requests to the device go to the first function, as
+                // if it was the only one.
+                None => self.functions.head().map_or(
+                    hil::usb::CtrlSetupResult::ErrNonstandardRequest,
+                    |function| self.forward_setup(function, setup_data.index),
+                ),
+            },
+        }
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, _endpoint: usize) -> hil::usb::CtrlInResult {
+        match self.ctrl_state.get() {
+            CtrlState::Descriptor(start, end) => {
+                let (result, start) = self.descriptor_packet(&self.descriptor_storage, start, end);
+                self.ctrl_state.set(CtrlState::Descriptor(start, end));
+                result
+            }
+            CtrlState::Configuration(start, end) => {
+                let (result, start) = self.descriptor_packet(&self.configuration, start, end);
+                self.ctrl_state.set(CtrlState::Configuration(start, end));
+                result
+            }
+            CtrlState::Function => {
+                self.ctrl_function
+                    .map_or(hil::usb::CtrlInResult::Error, |function| {
+                        let result = function.ctrl_in();
+                        if let hil::usb::CtrlInResult::Packet(packet_bytes, _) = result {
+                            let packet = function.ctrl_packet();
+                            for (dst, src) in self
+                                .ctrl_buffer
+                                .buf
+                                .iter()
+                                .zip(&packet[..min(packet_bytes, packet.len())])
+                            {
+                                dst.set(src.get());
+                            }
+                        }
+                        result
+                    })
+            }
+            CtrlState::Idle | CtrlState::SetAddress => hil::usb::CtrlInResult::Error,
+        }
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&'a self, _endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        match self.ctrl_state.get() {
+            CtrlState::Function => {
+                self.ctrl_function
+                    .map_or(hil::usb::CtrlOutResult::Halted, |function| {
+                        for (dst, src) in function
+                            .ctrl_packet()
+                            .iter()
+                            .zip(self.ctrl_buffer.buf.iter().take(packet_bytes as usize))
+                        {
+                            dst.set(src.get());
+                        }
+                        function.ctrl_out(packet_bytes)
+                    })
+            }
+            _ => hil::usb::CtrlOutResult::Halted,
+        }
+    }
+
+    fn ctrl_status(&'a self, _endpoint: usize) {
+        if let CtrlState::Function = self.ctrl_state.get() {
+            self.ctrl_function.map(|function| function.ctrl_status());
+        }
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, _endpoint: usize) {
+        match self.ctrl_state.get() {
+            CtrlState::SetAddress => self.controller.enable_address(),
+            CtrlState::Function => {
+                self.ctrl_function
+                    .take()
+                    .map(|function| function.ctrl_status_complete());
+            }
+            _ => {}
+        }
+        self.ctrl_state.set(CtrlState::Idle);
+    }
+
+    fn packet_in(&'a self, transfer_type: TransferType, endpoint: usize) -> hil::usb::InResult {
+        self.endpoint_function(endpoint)
+            .and_then(|(function, endpoint)| {
+                function
+                    .client
+                    .map(|client| client.packet_in(transfer_type, endpoint))
+            })
+            .unwrap_or(hil::usb::InResult::Error)
+    }
+
+    fn packet_out(
+        &'a self,
+        transfer_type: TransferType,
+        endpoint: usize,
+        packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        self.endpoint_function(endpoint)
+            .and_then(|(function, endpoint)| {
+                function
+                    .client
+                    .map(|client| client.packet_out(transfer_type, endpoint, packet_bytes))
+            })
+            .unwrap_or(hil::usb::OutResult::Error)
+    }
+
+    fn packet_transmitted(&'a self, endpoint: usize) {
+        if let Some((function, endpoint)) = self.endpoint_function(endpoint) {
+            function
+                .client
+                .map(|client| client.packet_transmitted(endpoint));
+        }
+    }
+}
+
+/// One function of a composite device, used by a class driver as its USB
+/// controller.
+pub struct UsbFunction<'a, U: 'a> {
+    composite: &'a UsbComposite<'a, U>,
+    id: Cell<usize>,
+    client: OptionalCell<&'a dyn hil::usb::Client<'a>>,
+
+    /// The control endpoint buffer of the class driver.
+    ctrl_buffer: OptionalCell<&'a [VolatileCell<u8>]>,
+
+    /// The endpoint of the controller allocated to each endpoint number of
+    /// the function, or 0 if none is.
+    endpoints: [Cell<u8>; FUNCTION_ENDPOINTS],
+
+    /// The interfaces of the device the interfaces of the function are
+    /// numbered as.
+    first_interface: Cell<u8>,
+    num_interfaces: Cell<u8>,
+
+    next: ListLink<'a, UsbFunction<'a, U>>,
+}
+
+impl<'a, U> ListNode<'a, UsbFunction<'a, U>> for UsbFunction<'a, U> {
+    fn next(&'a self) -> &'a ListLink<'a, UsbFunction<'a, U>> {
+        &self.next
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> UsbFunction<'a, U> {
+    pub fn new(composite: &'a UsbComposite<'a, U>) -> Self {
+        Self {
+            composite,
+            id: Cell::new(0),
+            client: OptionalCell::empty(),
+            ctrl_buffer: OptionalCell::empty(),
+            endpoints: Default::default(),
+            first_interface: Cell::new(0),
+            num_interfaces: Cell::new(0),
+            next: ListLink::empty(),
+        }
+    }
+
+    /// Adds the function to the device. Functions must be added before the
+    /// device is enabled, and are placed in the order they are added.
+    pub fn setup(&'a self) {
+        self.composite.add_function(self);
+    }
+
+    /// Returns the endpoint of the controller for an endpoint number of the
+    /// function, allocating one if needed.
+    fn endpoint(&self, endpoint: usize) -> usize {
+        let cell = &self.endpoints[endpoint & 0xf];
+        if cell.get() == 0 {
+            cell.set(self.composite.allocate_endpoint(self.id.get()) as u8);
+        }
+        cell.get() as usize
+    }
+
+    /// Returns the endpoint of the controller for an endpoint number of the
+    /// function, if one is allocated.
+    fn allocated_endpoint(&self, endpoint: usize) -> Option<usize> {
+        match self.endpoints[endpoint & 0xf].get() {
+            0 => None,
+            endpoint => Some(endpoint as usize),
+        }
+    }
+
+    /// Returns the endpoint number of the function for an endpoint of the
+    /// controller.
+    fn function_endpoint(&self, endpoint: usize) -> Option<usize> {
+        self.endpoints
+            .iter()
+            .position(|cell| cell.get() as usize == endpoint)
+    }
+
+    fn ctrl_packet(&self) -> &'a [VolatileCell<u8>] {
+        self.ctrl_buffer.get().unwrap_or(&[])
+    }
+
+    fn ctrl_setup(&self, packet: [u8; 8]) -> hil::usb::CtrlSetupResult {
+        let buf = self.ctrl_packet();
+        if buf.len() < packet.len() {
+            return hil::usb::CtrlSetupResult::ErrGeneric;
+        }
+        for (dst, src) in buf.iter().zip(packet) {
+            dst.set(src);
+        }
+        self.client
+            .map_or(hil::usb::CtrlSetupResult::ErrGeneric, |client| {
+                client.ctrl_setup(0)
+            })
+    }
+
+    fn ctrl_in(&self) -> hil::usb::CtrlInResult {
+        self.client
+            .map_or(hil::usb::CtrlInResult::Error, |client| client.ctrl_in(0))
+    }
+
+    fn ctrl_out(&self, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        self.client
+            .map_or(hil::usb::CtrlOutResult::Halted, |client| {
+                client.ctrl_out(0, packet_bytes)
+            })
+    }
+
+    fn ctrl_status(&self) {
+        self.client.map(|client| client.ctrl_status(0));
+    }
+
+    fn ctrl_status_complete(&self) {
+        self.client.map(|client| client.ctrl_status_complete(0));
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> hil::usb::UsbController<'a> for UsbFunction<'a, U> {
+    fn set_client(&self, client: &'a dyn hil::usb::Client<'a>) {
+        self.client.set(client);
+    }
+
+    fn endpoint_set_ctrl_buffer(&self, buf: &'a [VolatileCell<u8>]) {
+        // The control endpoint is shared, its transfers are copied to and
+        // from this buffer.
+        self.ctrl_buffer.set(buf);
+    }
+
+    fn endpoint_set_in_buffer(&self, endpoint: usize, buf: &'a [VolatileCell<u8>]) {
+        self.composite
+            .controller
+            .endpoint_set_in_buffer(self.endpoint(endpoint), buf);
+    }
+
+    fn endpoint_set_out_buffer(&self, endpoint: usize, buf: &'a [VolatileCell<u8>]) {
+        self.composite
+            .controller
+            .endpoint_set_out_buffer(self.endpoint(endpoint), buf);
+    }
+
+    fn enable_as_device(&self, _speed: hil::usb::DeviceSpeed) {
+        // The composite device enables the controller.
+    }
+
+    fn attach(&self) {
+        // The composite device attaches once all functions are enabled.
+    }
+
+    fn detach(&self) {}
+
+    fn set_address(&self, _addr: u16) {
+        // The composite device handles the address.
+    }
+
+    fn enable_address(&self) {}
+
+    fn endpoint_in_enable(&self, transfer_type: TransferType, endpoint: usize) {
+        self.composite
+            .controller
+            .endpoint_in_enable(transfer_type, self.endpoint(endpoint));
+    }
+
+    fn endpoint_out_enable(&self, transfer_type: TransferType, endpoint: usize) {
+        // The control endpoint is enabled by the composite device.
+        if endpoint != 0 {
+            self.composite
+                .controller
+                .endpoint_out_enable(transfer_type, self.endpoint(endpoint));
+        }
+    }
+
+    fn endpoint_in_out_enable(&self, transfer_type: TransferType, endpoint: usize) {
+        self.composite
+            .controller
+            .endpoint_in_out_enable(transfer_type, self.endpoint(endpoint));
+    }
+
+    fn endpoint_resume_in(&self, endpoint: usize) {
+        if let Some(endpoint) = self.allocated_endpoint(endpoint) {
+            self.composite.controller.endpoint_resume_in(endpoint);
+        }
+    }
+
+    fn endpoint_resume_out(&self, endpoint: usize) {
+        if let Some(endpoint) = self.allocated_endpoint(endpoint) {
+            self.composite.controller.endpoint_resume_out(endpoint);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::usb::ctap::CtapHid;
+    use crate::usb::descriptors::{
+        CdcInterfaceDescriptor, EndpointAddress, EndpointDescriptor, InterfaceDescriptor,
+        TransferDirection,
+    };
+    use crate::usb::usbc_client_ctrl::ClientCtrl;
+    use hil::usb::{Client, UsbController};
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    static STRINGS: &[&str; 3] = &["Tock", "Composite", "0"];
+
+    /// Records the buffers and endpoints configured by the composite.
+    #[derive(Default)]
+    struct MockController {
+        ctrl_buffer: OptionalCell<&'static [VolatileCell<u8>]>,
+        in_buffers: [Cell<bool>; MAX_ENDPOINTS],
+        out_buffers: [Cell<bool>; MAX_ENDPOINTS],
+    }
+
+    impl UsbController<'static> for MockController {
+        fn set_client(&self, _client: &'static dyn Client<'static>) {}
+        fn endpoint_set_ctrl_buffer(&self, buf: &'static [VolatileCell<u8>]) {
+            self.ctrl_buffer.set(buf);
+        }
+        fn endpoint_set_in_buffer(&self, endpoint: usize, _buf: &'static [VolatileCell<u8>]) {
+            self.in_buffers[endpoint].set(true);
+        }
+        fn endpoint_set_out_buffer(&self, endpoint: usize, _buf: &'static [VolatileCell<u8>]) {
+            self.out_buffers[endpoint].set(true);
+        }
+        fn enable_as_device(&self, _speed: hil::usb::DeviceSpeed) {}
+        fn attach(&self) {}
+        fn detach(&self) {}
+        fn set_address(&self, _addr: u16) {}
+        fn enable_address(&self) {}
+        fn endpoint_in_enable(&self, _transfer_type: TransferType, _endpoint: usize) {}
+        fn endpoint_out_enable(&self, _transfer_type: TransferType, _endpoint: usize) {}
+        fn endpoint_in_out_enable(&self, _transfer_type: TransferType, _endpoint: usize) {}
+        fn endpoint_resume_in(&self, _endpoint: usize) {}
+        fn endpoint_resume_out(&self, _endpoint: usize) {}
+    }
+
+    /// A function with a control and a data interface, described like
+    /// CDC-ACM, using the same endpoint number as CTAP.
+    struct TwoInterfaces {
+        client_ctrl: ClientCtrl<'static, 'static, UsbFunction<'static, MockController>>,
+        buffer: Buffer64,
+        last_index: Cell<u16>,
+        last_out_endpoint: Cell<usize>,
+    }
+
+    impl TwoInterfaces {
+        fn new(controller: &'static UsbFunction<'static, MockController>) -> Self {
+            let interfaces: &mut [InterfaceDescriptor] = &mut [
+                InterfaceDescriptor {
+                    interface_number: 0,
+                    interface_class: 0x02,
+                    interface_subclass: 0x02,
+                    interface_protocol: 0x01,
+                    ..InterfaceDescriptor::default()
+                },
+                InterfaceDescriptor {
+                    interface_number: 1,
+                    interface_class: 0x0a,
+                    ..InterfaceDescriptor::default()
+                },
+            ];
+            let cdc_descriptors: &[CdcInterfaceDescriptor] = &[
+                CdcInterfaceDescriptor {
+                    subtype: CdcInterfaceDescriptorSubType::CallManagement,
+                    field1: 0x00,
+                    field2: 0x01,
+                },
+                CdcInterfaceDescriptor {
+                    subtype: CdcInterfaceDescriptorSubType::Union,
+                    field1: 0x00,
+                    field2: 0x01,
+                },
+            ];
+            let endpoints: &[&[EndpointDescriptor]] = &[
+                &[EndpointDescriptor {
+                    endpoint_address: EndpointAddress::new_const(
+                        2,
+                        TransferDirection::DeviceToHost,
+                    ),
+                    transfer_type: TransferType::Interrupt,
+                    max_packet_size: 8,
+                    interval: 16,
+                }],
+                &[EndpointDescriptor {
+                    endpoint_address: EndpointAddress::new_const(
+                        1,
+                        TransferDirection::HostToDevice,
+                    ),
+                    transfer_type: TransferType::Bulk,
+                    max_packet_size: 64,
+                    interval: 0,
+                }],
+            ];
+            let (device, other) = descriptors::create_descriptor_buffers(
+                descriptors::DeviceDescriptor::default(),
+                descriptors::ConfigurationDescriptor::default(),
+                interfaces,
+                endpoints,
+                None,
+                Some(cdc_descriptors),
+                None,
+            );
+            Self {
+                client_ctrl: ClientCtrl::new(controller, device, other, None, None, LANGUAGES, &[]),
+                buffer: Buffer64::default(),
+                last_index: Cell::new(0),
+                last_out_endpoint: Cell::new(0),
+            }
+        }
+    }
+
+    impl Client<'static> for TwoInterfaces {
+        fn enable(&'static self) {
+            self.client_ctrl.enable();
+            self.client_ctrl
+                .controller()
+                .endpoint_set_out_buffer(1, &self.buffer.buf);
+        }
+        fn attach(&'static self) {}
+        fn bus_reset(&'static self) {}
+        fn ctrl_setup(&'static self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+            if let Some(setup_data) = SetupData::get(&self.client_ctrl.ctrl_buffer.buf) {
+                self.last_index.set(setup_data.index);
+            }
+            self.client_ctrl.ctrl_setup(endpoint)
+        }
+        fn ctrl_in(&'static self, endpoint: usize) -> hil::usb::CtrlInResult {
+            self.client_ctrl.ctrl_in(endpoint)
+        }
+        fn ctrl_out(&'static self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+            self.client_ctrl.ctrl_out(endpoint, packet_bytes)
+        }
+        fn ctrl_status(&'static self, endpoint: usize) {
+            self.client_ctrl.ctrl_status(endpoint)
+        }
+        fn ctrl_status_complete(&'static self, endpoint: usize) {
+            self.client_ctrl.ctrl_status_complete(endpoint)
+        }
+        fn packet_in(&'static self, _: TransferType, _: usize) -> hil::usb::InResult {
+            hil::usb::InResult::Delay
+        }
+        fn packet_out(
+            &'static self,
+            _: TransferType,
+            endpoint: usize,
+            _: u32,
+        ) -> hil::usb::OutResult {
+            self.last_out_endpoint.set(endpoint);
+            hil::usb::OutResult::Ok
+        }
+        fn packet_transmitted(&'static self, _endpoint: usize) {}
+    }
+
+    fn leak<T>(value: T) -> &'static T {
+        Box::leak(Box::new(value))
+    }
+
+    /// Runs a control read on the composite, returning the data.
+    fn control_read(
+        composite: &'static UsbComposite<'static, MockController>,
+        controller: &MockController,
+        setup: [u8; 8],
+    ) -> Vec<u8> {
+        let buf = controller.ctrl_buffer.get().unwrap();
+        for (cell, byte) in buf.iter().zip(setup) {
+            cell.set(byte);
+        }
+        assert!(matches!(
+            composite.ctrl_setup(0),
+            hil::usb::CtrlSetupResult::Ok
+        ));
+        let mut data = Vec::new();
+        while let hil::usb::CtrlInResult::Packet(len, last) = composite.ctrl_in(0) {
+            data.extend(buf[..len].iter().map(|cell| cell.get()));
+            if last {
+                break;
+            }
+        }
+        composite.ctrl_status_complete(0);
+        data
+    }
+
+    #[test]
+    fn merges_functions() {
+        let controller = leak(MockController::default());
+        let composite = leak(UsbComposite::new(controller, 64, 0x1234, 0x5678, STRINGS));
+
+        let ctap_function = leak(UsbFunction::new(composite));
+        ctap_function.setup();
+        let ctap = leak(CtapHid::new(ctap_function, 0, 0, STRINGS));
+        ctap_function.set_client(ctap);
+
+        let cdc_function = leak(UsbFunction::new(composite));
+        cdc_function.setup();
+        let cdc = leak(TwoInterfaces::new(cdc_function));
+        cdc_function.set_client(cdc);
+
+        composite.enable();
+
+        // CTAP uses endpoint 1 of the controller, the second function gets
+        // the next ones for its endpoints 1 and 2.
+        assert!(controller.in_buffers[1].get() && controller.out_buffers[1].get());
+        assert!(controller.out_buffers[2].get());
+
+        let device = control_read(composite, controller, [0x80, 6, 0, 1, 0, 0, 18, 0]);
+        assert_eq!(&device[4..7], &[0xef, 0x02, 0x01]);
+
+        let config = control_read(composite, controller, [0x80, 6, 0, 2, 0, 0, 255, 0]);
+        assert_eq!(
+            u16::from_le_bytes([config[2], config[3]]) as usize,
+            config.len()
+        );
+        assert_eq!(config[4], 3);
+
+        // Walk the descriptors, collecting (type, interesting bytes).
+        let mut i = 9;
+        let mut interfaces = Vec::new();
+        let mut endpoints = Vec::new();
+        let mut iads = Vec::new();
+        let mut union = None;
+        while i < config.len() {
+            let desc = &config[i..i + config[i] as usize];
+            match desc[1] {
+                0x04 => interfaces.push(desc[2]),
+                0x05 => endpoints.push(desc[2]),
+                0x0b => iads.push((desc[2], desc[3], desc[4])),
+                0x24 if desc[2] == 0x06 => union = Some((desc[3], desc[4])),
+                _ => {}
+            }
+            i += desc.len();
+        }
+        assert_eq!(interfaces, [0, 1, 2]);
+        assert_eq!(iads, [(1, 2, 0x02)]);
+        assert_eq!(union, Some((1, 2)));
+        assert_eq!(endpoints, [0x81, 0x01, 0x83, 0x02]);
+
+        // A class request to the data interface reaches the second function
+        // with its own interface number.
+        let buf = controller.ctrl_buffer.get().unwrap();
+        for (cell, byte) in buf.iter().zip([0x21, 0x22, 0, 0, 2, 0, 0, 0]) {
+            cell.set(byte);
+        }
+        assert!(matches!(
+            composite.ctrl_setup(0),
+            hil::usb::CtrlSetupResult::Ok
+        ));
+        composite.ctrl_status_complete(0);
+        assert_eq!(cdc.last_index.get(), 1);
+
+        // So does data on its endpoint.
+        composite.packet_out(TransferType::Bulk, 2, 64);
+        assert_eq!(cdc.last_out_endpoint.get(), 1);
+    }
+}
@@ -151,6 +151,7 @@ impl<'a, U: hil::usb::UsbController<'a>> CtapHid<'a, U> {
                 endpoints,
                 Some(&HID_DESCRIPTOR),
                 None,
+                None,
             );
 
         CtapHid {
@@ -160,6 +160,7 @@ pub enum DescriptorType {
     DeviceQualifier,
     OtherSpeedConfiguration,
     InterfacePower,
+    InterfaceAssociation = 0x0B,
     HID = 0x21,
     Report = 0x22,
     CdcInterface = 0x24,
@@ -175,6 +176,7 @@ fn get_descriptor_type(byte: u8) -> Option<DescriptorType> {
         6 => Some(DescriptorType::DeviceQualifier),
         7 => Some(DescriptorType::OtherSpeedConfiguration),
         8 => Some(DescriptorType::InterfacePower),
+        0x0B => Some(DescriptorType::InterfaceAssociation),
         0x21 => Some(DescriptorType::HID),
         0x22 => Some(DescriptorType::Report),
         0x24 => Some(DescriptorType::CdcInterface),
@@ -428,6 +430,7 @@ pub fn create_descriptor_buffers(
     endpoint_descriptors: &[&[EndpointDescriptor]],
     hid_descriptor: Option<&HIDDescriptor>,
     cdc_descriptor: Option<&[CdcInterfaceDescriptor]>,
+    dfu_descriptor: Option<&DfuFunctionalDescriptor>,
 ) -> (DeviceBuffer, DescriptorBuffer) {
     // Create device descriptor buffer and fill.
     // Cell doesn't implement Copy, so here we are.
@@ -499,7 +502,10 @@ pub fn create_descriptor_buffers(
 
     // Configuration Descriptor. We assume there is only one configuration
     // descriptor, since this is very common for most USB devices.
-    configuration_descriptor.num_interfaces = interface_descriptor.len() as u8;
+    configuration_descriptor.num_interfaces = interface_descriptor
+        .iter()
+        .filter(|d| d.alternate_setting == 0)
+        .count() as u8;
 
     // Calculate the length of all dependent descriptors.
     // TODO should we be erroring here if len > 128? Otherwise we'll probably
@@ -511,7 +517,8 @@ pub fn create_descriptor_buffers(
                 .map(|descs| descs.iter().map(|d| d.size()).sum::<usize>())
                 .sum::<usize>()
             + hid_descriptor.map_or(0, |d| d.size())
-            + cdc_descriptor.map_or(0, |ds| ds.iter().map(|d| d.size()).sum::<usize>());
+            + cdc_descriptor.map_or(0, |ds| ds.iter().map(|d| d.size()).sum::<usize>())
+            + dfu_descriptor.map_or(0, |d| d.size());
 
     // Set the number of endpoints for each interface descriptor.
     for (i, d) in interface_descriptor.iter_mut().enumerate() {
@@ -552,6 +559,11 @@ pub fn create_descriptor_buffers(
             len += de.write_to(&other_buf.buf[len..]);
         }
     }
+
+    // The DFU functional descriptor follows all (alternate) interfaces.
+    if let Some(dd) = dfu_descriptor {
+        len += dd.write_to(&other_buf.buf[len..]);
+    }
     other_buf.len = min(len, other_buf.buf.len());
 
     // return the two buffers
@@ -660,6 +672,34 @@ impl Descriptor for InterfaceDescriptor {
     }
 }
 
+/// Groups the interfaces of one function of a composite device.
+pub struct InterfaceAssociationDescriptor {
+    pub first_interface: u8,
+    pub interface_count: u8,
+    pub function_class: u8,
+    pub function_subclass: u8,
+    pub function_protocol: u8,
+    pub string_index: u8,
+}
+
+impl Descriptor for InterfaceAssociationDescriptor {
+    fn size(&self) -> usize {
+        8
+    }
+
+    fn write_to_unchecked(&self, buf: &[Cell<u8>]) -> usize {
+        buf[0].set(8); // Size of descriptor
+        buf[1].set(DescriptorType::InterfaceAssociation as u8);
+        buf[2].set(self.first_interface);
+        buf[3].set(self.interface_count);
+        buf[4].set(self.function_class);
+        buf[5].set(self.function_subclass);
+        buf[6].set(self.function_protocol);
+        buf[7].set(self.string_index);
+        8
+    }
+}
+
 pub struct EndpointAddress(u8);
 
 impl EndpointAddress {
@@ -779,6 +819,39 @@ impl Descriptor for HIDDescriptor<'_> {
     }
 }
 
+/// Capabilities of a Device Firmware Upgrade (DFU 1.1) interface.
+pub struct DfuFunctionalDescriptor {
+    pub can_download: bool,
+    pub can_upload: bool,
+    pub manifestation_tolerant: bool,
+    pub will_detach: bool,
+    /// Time in ms the device waits for a USB reset after a DFU_DETACH.
+    pub detach_timeout: u16,
+    /// Maximum number of bytes per control write or read.
+    pub transfer_size: u16,
+}
+
+impl Descriptor for DfuFunctionalDescriptor {
+    fn size(&self) -> usize {
+        9
+    }
+
+    fn write_to_unchecked(&self, buf: &[Cell<u8>]) -> usize {
+        buf[0].set(9); // Size of descriptor
+        buf[1].set(0x21); // DFU FUNCTIONAL, same value as HID
+        buf[2].set(
+            self.can_download as u8
+                | (self.can_upload as u8) << 1
+                | (self.manifestation_tolerant as u8) << 2
+                | (self.will_detach as u8) << 3,
+        );
+        put_u16(&buf[3..5], self.detach_timeout);
+        put_u16(&buf[5..7], self.transfer_size);
+        put_u16(&buf[7..9], 0x0110); // DFU 1.1
+        9
+    }
+}
+
 pub struct ReportDescriptor<'a> {
     pub desc: &'a [u8],
 }
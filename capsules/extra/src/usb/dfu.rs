@@ -0,0 +1,976 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Device Firmware Upgrade (DFU 1.1) class for USB
+//!
+//! This capsule is a USB device in DFU mode, so standard tools like
+//! `dfu-util` can install apps on the board, and optionally update its
+//! kernel:
+//!
+//! ```text
+//! $ dfu-util -a 0 -D app.tbf      # install an app
+//! $ dfu-util -a 1 -D kernel.bin   # write a kernel to the staging slot
+//! ```
+//!
+//! The interface has one alternate setting per target:
+//!
+//! - Alternate setting 0 receives a TBF, which is stored with
+//!   [`DynamicBinaryStore`] and loaded with [`DynamicProcessLoad`] during
+//!   manifestation, so the process checker verifies its credentials before it
+//!   runs. The length of the app is taken from its TBF header, data past it
+//!   is ignored.
+//! - Alternate setting 1 exists only if the board passes a [`KernelSlot`],
+//!   and writes the image to the slot unchanged. Installing the image from
+//!   the slot is left to the bootloader of the board. Creating a
+//!   `KernelSlot` requires the `KernelUpdateCapability`.
+//!
+//! Each DFU_DNLOAD request carries one block of data in its data stage, of
+//! at most the size of a control packet, since not all USB controllers can
+//! receive longer control writes. The host is told to poll again with
+//! DFU_GETSTATUS while a block is being written. The first block of a
+//! download can have any block number, and each following block must have
+//! the next number. A block with the number of the previous block is a
+//! retransmission by a host that missed the status of the previous request,
+//! and is acknowledged without being written again; other numbers enter
+//! dfuERROR. The device is manifestation tolerant: it returns to dfuIDLE once an app is loaded,
+//! so several images can be downloaded without a reset. Uploads are not
+//! supported.
+//!
+//! Usage
+//! -----
+//! See `components::usb_dfu`.
+
+use core::cell::Cell;
+use core::cmp;
+
+use super::descriptors;
+use super::descriptors::DfuFunctionalDescriptor;
+use super::descriptors::EndpointDescriptor;
+use super::descriptors::InterfaceDescriptor;
+use super::descriptors::Recipient;
+use super::descriptors::RequestType;
+use super::usbc_client_ctrl::ClientCtrl;
+
+use kernel::ErrorCode;
+use kernel::capabilities::KernelUpdateCapability;
+use kernel::dynamic_binary_storage::{
+    DynamicBinaryStore, DynamicBinaryStoreClient, DynamicProcessLoad, DynamicProcessLoadClient,
+};
+use kernel::hil;
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::process::ProcessLoadError;
+use kernel::utilities::cells::TakeCell;
+use kernel::utilities::leasable_buffer::SubSliceMut;
+
+use crate::app_installer::{self, AppInstaller};
+
+static LANGUAGES: &[u16; 1] = &[
+    0x0409, // English (United States)
+];
+
+/// Length of the buffer passed to [`UsbDfu::new`]. Blocks are at most this
+/// long.
+pub const BUF_LEN: usize = 64;
+
+/// Time in ms the host waits before polling again while the device is busy.
+const POLL_TIMEOUT_MS: u32 = 10;
+
+/// Alternate settings of the DFU interface.
+const ALT_APP: u8 = 0;
+const ALT_KERNEL: u8 = 1;
+
+/// DFU class requests.
+mod request {
+    pub const DNLOAD: u8 = 1;
+    pub const GETSTATUS: u8 = 3;
+    pub const CLRSTATUS: u8 = 4;
+    pub const GETSTATE: u8 = 5;
+    pub const ABORT: u8 = 6;
+}
+
+/// Standard interface requests, which `ClientCtrl` does not handle.
+const REQUEST_GET_INTERFACE: u8 = 10;
+const REQUEST_SET_INTERFACE: u8 = 11;
+
+/// States of the DFU mode state machine which a download goes through.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+enum State {
+    Idle = 2,
+    DnloadSync = 3,
+    DnBusy = 4,
+    DnloadIdle = 5,
+    ManifestSync = 6,
+    Manifest = 7,
+    Error = 10,
+}
+
+/// Status codes reported by DFU_GETSTATUS.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+enum Status {
+    Ok = 0x00,
+    ErrFile = 0x02,
+    ErrWrite = 0x03,
+    ErrVerify = 0x07,
+    ErrAddress = 0x08,
+    ErrStalledPkt = 0x0F,
+}
+
+/// The class request whose data stage is pending.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Pending {
+    None,
+    /// Receiving a block.
+    Dnload {
+        length: usize,
+        block_num: u16,
+    },
+    GetStatus,
+    GetState,
+    GetInterface,
+}
+
+/// A nonvolatile storage a new kernel image is written to.
+///
+/// The image is written from address 0 of `storage`, so the storage usually
+/// covers only the staging region of the flash.
+#[derive(Clone, Copy)]
+pub struct KernelSlot<'a> {
+    storage: &'a dyn NonvolatileStorage<'a>,
+    length: usize,
+}
+
+impl<'a> KernelSlot<'a> {
+    /// Creates a slot for images of up to `length` bytes.
+    pub fn new<C: KernelUpdateCapability>(
+        storage: &'a dyn NonvolatileStorage<'a>,
+        length: usize,
+        _capability: &C,
+    ) -> Self {
+        Self { storage, length }
+    }
+
+    pub fn storage(&self) -> &'a dyn NonvolatileStorage<'a> {
+        self.storage
+    }
+}
+
+/// Implementation of the USB DFU class in DFU mode.
+pub struct UsbDfu<'a, U: 'a, S: DynamicBinaryStore + 'static, L: DynamicProcessLoad + 'static> {
+    /// Helper USB client library for handling many USB operations.
+    client_ctrl: ClientCtrl<'a, 'static, U>,
+
+    installer: AppInstaller<'a, S, L>,
+    kernel_slot: Option<KernelSlot<'a>>,
+
+    /// Selected alternate setting.
+    alternate_setting: Cell<u8>,
+    state: Cell<State>,
+    status: Cell<Status>,
+    pending: Cell<Pending>,
+    /// Whether a block is being written to the kernel slot.
+    kernel_writing: Cell<bool>,
+
+    /// Maximum length of a block.
+    transfer_size: usize,
+    /// Buffer the block being received or written is in.
+    buffer: TakeCell<'static, [u8]>,
+    /// Bytes of the block received or being written.
+    block_len: Cell<usize>,
+    /// Offset in the image of the current block.
+    offset: Cell<usize>,
+    /// Number of the next block of the download.
+    next_block: Cell<u16>,
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, S: DynamicBinaryStore, L: DynamicProcessLoad>
+    UsbDfu<'a, U, S, L>
+{
+    pub fn new(
+        controller: &'a U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+        storage_driver: &'a S,
+        load_driver: &'a L,
+        kernel_slot: Option<KernelSlot<'a>>,
+        buffer: &'static mut [u8],
+    ) -> Self {
+        let transfer_size = cmp::min(max_ctrl_packet_size as usize, buffer.len());
+        let interfaces: &mut [InterfaceDescriptor] = &mut [
+            InterfaceDescriptor {
+                interface_number: 0,
+                alternate_setting: ALT_APP,
+                interface_class: 0xFE,    // Application specific
+                interface_subclass: 0x01, // Device Firmware Upgrade
+                interface_protocol: 0x02, // DFU mode
+                ..InterfaceDescriptor::default()
+            },
+            InterfaceDescriptor {
+                interface_number: 0,
+                alternate_setting: ALT_KERNEL,
+                interface_class: 0xFE,
+                interface_subclass: 0x01,
+                interface_protocol: 0x02,
+                ..InterfaceDescriptor::default()
+            },
+        ];
+        let num_settings = if kernel_slot.is_some() { 2 } else { 1 };
+        let endpoints: &[&[EndpointDescriptor]] = &[&[], &[]];
+
+        let (device_descriptor_buffer, other_descriptor_buffer) =
+            descriptors::create_descriptor_buffers(
+                descriptors::DeviceDescriptor {
+                    vendor_id,
+                    product_id,
+                    manufacturer_string: 1,
+                    product_string: 2,
+                    serial_number_string: 3,
+                    max_packet_size_ep0: max_ctrl_packet_size,
+                    ..descriptors::DeviceDescriptor::default()
+                },
+                descriptors::ConfigurationDescriptor::default(),
+                &mut interfaces[..num_settings],
+                &endpoints[..num_settings],
+                None, // No HID descriptor
+                None, // No CDC descriptor
+                Some(&DfuFunctionalDescriptor {
+                    can_download: true,
+                    can_upload: false,
+                    manifestation_tolerant: true,
+                    will_detach: false,
+                    detach_timeout: 0,
+                    transfer_size: transfer_size as u16,
+                }),
+            );
+
+        Self {
+            client_ctrl: ClientCtrl::new(
+                controller,
+                device_descriptor_buffer,
+                other_descriptor_buffer,
+                None, // No HID descriptor
+                None, // No report descriptor
+                LANGUAGES,
+                strings,
+            ),
+            installer: AppInstaller::new(storage_driver, load_driver),
+            kernel_slot,
+            alternate_setting: Cell::new(ALT_APP),
+            state: Cell::new(State::Idle),
+            status: Cell::new(Status::Ok),
+            pending: Cell::new(Pending::None),
+            kernel_writing: Cell::new(false),
+            transfer_size,
+            buffer: TakeCell::new(buffer),
+            block_len: Cell::new(0),
+            offset: Cell::new(0),
+            next_block: Cell::new(0),
+        }
+    }
+
+    /// Whether the storage is busy with a block or the app.
+    fn busy(&self) -> bool {
+        self.kernel_writing.get()
+            || !matches!(
+                self.installer.state(),
+                app_installer::State::Idle | app_installer::State::Receiving
+            )
+    }
+
+    /// Whether the block which was received is being written.
+    fn downloading(&self) -> bool {
+        matches!(self.state.get(), State::DnloadSync | State::DnBusy)
+    }
+
+    /// Enters dfuERROR with `status`, dropping the app being downloaded.
+    fn fail(&self, status: Status) {
+        self.state.set(State::Error);
+        self.status.set(status);
+        self.abort_app();
+    }
+
+    /// Releases the storage set up for an app, if any. While the storage is
+    /// busy, this is done once its operation completes.
+    fn abort_app(&self) {
+        let _ = self.installer.abort();
+    }
+
+    /// Returns to dfuIDLE, discarding a download in progress.
+    fn reset(&self) {
+        self.abort_app();
+        self.state.set(State::Idle);
+        self.status.set(Status::Ok);
+        self.offset.set(0);
+    }
+
+    /// Handles a class request of the DFU interface.
+    fn class_request(&self, setup_data: descriptors::SetupData) -> hil::usb::CtrlSetupResult {
+        let state = self.state.get();
+        let idle = !self.busy();
+        match setup_data.request_code {
+            request::DNLOAD => {
+                let length = setup_data.length as usize;
+                let block_num = setup_data.value;
+                let next_block = self.next_block.get();
+                match state {
+                    State::Idle | State::DnloadIdle
+                        if idle
+                            && length > 0
+                            && length <= self.transfer_size
+                            && (state == State::Idle
+                                || block_num == next_block
+                                || block_num == next_block.wrapping_sub(1)) =>
+                    {
+                        if state == State::Idle {
+                            self.offset.set(0);
+                        }
+                        self.block_len.set(0);
+                        self.pending.set(Pending::Dnload { length, block_num });
+                        hil::usb::CtrlSetupResult::Ok
+                    }
+                    State::DnloadIdle if idle && length == 0 => {
+                        self.manifest();
+                        hil::usb::CtrlSetupResult::Ok
+                    }
+                    _ => self.stall(),
+                }
+            }
+            request::GETSTATUS => {
+                let busy = !idle && self.installer.state() != app_installer::State::Aborting;
+                match state {
+                    State::DnloadSync | State::DnBusy if busy => self.state.set(State::DnBusy),
+                    State::DnloadSync | State::DnBusy => self.state.set(State::DnloadIdle),
+                    State::ManifestSync | State::Manifest if busy => {
+                        self.state.set(State::Manifest)
+                    }
+                    State::ManifestSync | State::Manifest => {
+                        self.offset.set(0);
+                        self.state.set(State::Idle);
+                    }
+                    _ => {}
+                }
+                self.pending.set(Pending::GetStatus);
+                hil::usb::CtrlSetupResult::Ok
+            }
+            request::CLRSTATUS if state == State::Error => {
+                self.reset();
+                hil::usb::CtrlSetupResult::Ok
+            }
+            request::GETSTATE => {
+                self.pending.set(Pending::GetState);
+                hil::usb::CtrlSetupResult::Ok
+            }
+            request::ABORT if idle && matches!(state, State::Idle | State::DnloadIdle) => {
+                self.reset();
+                hil::usb::CtrlSetupResult::Ok
+            }
+            // DFU_DETACH, DFU_UPLOAD and requests in the wrong state.
+            _ => self.stall(),
+        }
+    }
+
+    /// Rejects a request, which enters dfuERROR.
+    fn stall(&self) -> hil::usb::CtrlSetupResult {
+        self.fail(Status::ErrStalledPkt);
+        hil::usb::CtrlSetupResult::ErrGeneric
+    }
+
+    /// Starts writing the block which was received.
+    fn download(&self) {
+        self.state.set(State::DnloadSync);
+        let result = if self.alternate_setting.get() == ALT_KERNEL {
+            self.write_kernel()
+        } else if self.offset.get() == 0 {
+            self.setup_app()
+        } else {
+            self.write_app()
+        };
+        if let Err(status) = result {
+            self.fail(status);
+        }
+    }
+
+    /// Sets up storage for the app whose first block was received.
+    fn setup_app(&self) -> Result<(), Status> {
+        let length = self
+            .buffer
+            .map(|buffer| {
+                if self.block_len.get() < 8 {
+                    return 0;
+                }
+                u32::from_le_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]) as usize
+            })
+            .unwrap_or(0);
+        if length < 8 {
+            return Err(Status::ErrFile);
+        }
+        self.installer.setup(length).map_err(|_| Status::ErrAddress)
+    }
+
+    fn write_app(&self) -> Result<(), Status> {
+        let offset = self.offset.get();
+        let length = cmp::min(
+            self.block_len.get(),
+            self.installer.length().saturating_sub(offset),
+        );
+        self.block_len.set(length);
+        if length == 0 {
+            // Padding after the app.
+            return Ok(());
+        }
+
+        let buffer = self.buffer.take().ok_or(Status::ErrWrite)?;
+        let mut block = SubSliceMut::new(buffer);
+        block.slice(..length);
+        match self.installer.write(block, offset) {
+            Ok(()) => Ok(()),
+            Err((_, buffer)) => {
+                if let Some(buffer) = buffer {
+                    self.buffer.replace(buffer);
+                }
+                Err(Status::ErrWrite)
+            }
+        }
+    }
+
+    fn write_kernel(&self) -> Result<(), Status> {
+        let slot = self.kernel_slot.ok_or(Status::ErrAddress)?;
+        let offset = self.offset.get();
+        let length = self.block_len.get();
+        if offset + length > slot.length {
+            return Err(Status::ErrAddress);
+        }
+
+        let buffer = self.buffer.take().ok_or(Status::ErrWrite)?;
+        match slot.storage.write(buffer, offset, length) {
+            Ok(()) => {
+                self.kernel_writing.set(true);
+                Ok(())
+            }
+            // `NonvolatileStorage` doesn't return the buffer on errors.
+            Err(_) => Err(Status::ErrWrite),
+        }
+    }
+
+    /// Completes the download after its last block.
+    fn manifest(&self) {
+        self.state.set(State::ManifestSync);
+        if self.alternate_setting.get() == ALT_KERNEL {
+            // The image is complete in the slot.
+            return;
+        }
+        if self.installer.finish().is_err() {
+            self.fail(Status::ErrVerify);
+        }
+    }
+
+    /// Whether the app is being manifested.
+    fn manifesting(&self) -> bool {
+        matches!(self.state.get(), State::ManifestSync | State::Manifest)
+    }
+
+    /// Handles the completion of the write of a block.
+    fn block_written(&self, result: Result<(), ErrorCode>) {
+        if !self.downloading() {
+            // The download was aborted while the block was written.
+            self.abort_app();
+            return;
+        }
+        match result {
+            Ok(()) => self.offset.set(self.offset.get() + self.block_len.get()),
+            Err(_) => self.fail(Status::ErrWrite),
+        }
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, S: DynamicBinaryStore, L: DynamicProcessLoad>
+    hil::usb::Client<'a> for UsbDfu<'a, U, S, L>
+{
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.client_ctrl.enable();
+    }
+
+    fn attach(&'a self) {
+        self.client_ctrl.attach();
+    }
+
+    fn bus_reset(&'a self) {
+        self.pending.set(Pending::None);
+        if !self.busy() {
+            self.reset();
+        }
+    }
+
+    /// Handle a Control Setup transaction.
+    ///
+    /// The DFU class requests and the selection of the alternate setting are
+    /// handled here, everything else by `ClientCtrl`.
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        if let Some(setup_data) = descriptors::SetupData::get(&self.client_ctrl.ctrl_buffer.buf) {
+            if matches!(setup_data.request_type.recipient(), Recipient::Interface) {
+                match setup_data.request_type.request_type() {
+                    RequestType::Class => return self.class_request(setup_data),
+                    RequestType::Standard => match setup_data.request_code {
+                        REQUEST_GET_INTERFACE => {
+                            self.pending.set(Pending::GetInterface);
+                            return hil::usb::CtrlSetupResult::Ok;
+                        }
+                        REQUEST_SET_INTERFACE => {
+                            let setting = setup_data.value;
+                            let valid = setting == ALT_APP as u16
+                                || (setting == ALT_KERNEL as u16 && self.kernel_slot.is_some());
+                            if !valid || self.busy() {
+                                return hil::usb::CtrlSetupResult::ErrGeneric;
+                            }
+                            self.reset();
+                            self.alternate_setting.set(setting as u8);
+                            return hil::usb::CtrlSetupResult::Ok;
+                        }
+                        _ => {}
+                    },
+                    _ => {}
+                }
+            }
+        }
+
+        self.client_ctrl.ctrl_setup(endpoint)
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
+        let buf = &self.client_ctrl.ctrl_buffer.buf;
+        match self.pending.get() {
+            Pending::GetStatus => {
+                let state = self.state.get();
+                let poll_timeout = match state {
+                    State::DnBusy | State::Manifest => POLL_TIMEOUT_MS,
+                    _ => 0,
+                };
+                buf[0].set(self.status.get() as u8);
+                for (cell, byte) in buf[1..4].iter().zip(poll_timeout.to_le_bytes()) {
+                    cell.set(byte);
+                }
+                buf[4].set(state as u8);
+                buf[5].set(0); // No status description string
+                hil::usb::CtrlInResult::Packet(6, true)
+            }
+            Pending::GetState => {
+                buf[0].set(self.state.get() as u8);
+                hil::usb::CtrlInResult::Packet(1, true)
+            }
+            Pending::GetInterface => {
+                buf[0].set(self.alternate_setting.get());
+                hil::usb::CtrlInResult::Packet(1, true)
+            }
+            _ => self.client_ctrl.ctrl_in(endpoint),
+        }
+    }
+
+    /// Handle a Control Out transaction
+    ///
+    /// This receives the block of a DFU_DNLOAD request, which is written once
+    /// complete.
+    fn ctrl_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        let Pending::Dnload { length, block_num } = self.pending.get() else {
+            return self.client_ctrl.ctrl_out(endpoint, packet_bytes);
+        };
+        let received = self.block_len.get();
+        let packet_bytes = cmp::min(packet_bytes as usize, length - received);
+        let copied = self.buffer.map(|buffer| {
+            let packet = &self.client_ctrl.ctrl_buffer.buf[..packet_bytes];
+            for (byte, cell) in buffer[received..].iter_mut().zip(packet) {
+                *byte = cell.get();
+            }
+        });
+        if copied.is_none() {
+            return hil::usb::CtrlOutResult::Halted;
+        }
+
+        self.block_len.set(received + packet_bytes);
+        if received + packet_bytes == length {
+            self.pending.set(Pending::None);
+            if self.state.get() == State::DnloadIdle && block_num != self.next_block.get() {
+                // The previous block again, which was written already.
+                self.state.set(State::DnloadSync);
+            } else {
+                self.next_block.set(block_num.wrapping_add(1));
+                self.download();
+            }
+        }
+        hil::usb::CtrlOutResult::Ok
+    }
+
+    fn ctrl_status(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status(endpoint)
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, endpoint: usize) {
+        self.pending.set(Pending::None);
+        self.client_ctrl.ctrl_status_complete(endpoint)
+    }
+
+    // DFU mode uses the control endpoint only.
+
+    fn packet_in(
+        &'a self,
+        _transfer_type: hil::usb::TransferType,
+        _endpoint: usize,
+    ) -> hil::usb::InResult {
+        hil::usb::InResult::Error
+    }
+
+    fn packet_out(
+        &'a self,
+        _transfer_type: hil::usb::TransferType,
+        _endpoint: usize,
+        _packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        hil::usb::OutResult::Error
+    }
+
+    fn packet_transmitted(&'a self, _endpoint: usize) {}
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, S: DynamicBinaryStore, L: DynamicProcessLoad>
+    DynamicBinaryStoreClient for UsbDfu<'a, U, S, L>
+{
+    fn setup_done(&self, result: Result<(), ErrorCode>) {
+        match self.installer.setup_done(result) {
+            Some(_) if !self.downloading() => self.abort_app(),
+            Some(Ok(())) => {
+                if let Err(status) = self.write_app() {
+                    self.fail(status);
+                }
+            }
+            Some(Err(_)) => self.fail(Status::ErrAddress),
+            None => {}
+        }
+    }
+
+    fn write_done(&self, result: Result<(), ErrorCode>, buffer: &'static mut [u8], length: usize) {
+        self.buffer.replace(buffer);
+        if let Some(result) = self.installer.write_done(result, length) {
+            self.block_written(result);
+        }
+    }
+
+    fn finalize_done(&self, result: Result<(), ErrorCode>) {
+        if let Some(Err(_)) = self.installer.finalize_done(result)
+            && self.manifesting()
+        {
+            self.fail(Status::ErrVerify);
+        }
+    }
+
+    fn abort_done(&self, result: Result<(), ErrorCode>) {
+        self.installer.abort_done(result);
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, S: DynamicBinaryStore, L: DynamicProcessLoad>
+    DynamicProcessLoadClient for UsbDfu<'a, U, S, L>
+{
+    fn load_done(&self, result: Result<(), ProcessLoadError>) {
+        if let Some(Err(_)) = self.installer.load_done(result)
+            && self.manifesting()
+        {
+            self.fail(Status::ErrVerify);
+        }
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, S: DynamicBinaryStore, L: DynamicProcessLoad>
+    NonvolatileStorageClient for UsbDfu<'a, U, S, L>
+{
+    fn read_done(&self, buffer: &'static mut [u8], _length: usize) {
+        self.buffer.replace(buffer);
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.buffer.replace(buffer);
+        if !self.kernel_writing.take() {
+            return;
+        }
+        let result = if length == self.block_len.get() {
+            Ok(())
+        } else {
+            Err(ErrorCode::FAIL)
+        };
+        self.block_written(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use hil::usb::{Client, CtrlInResult, CtrlOutResult, CtrlSetupResult, UsbController};
+    use kernel::utilities::cells::VolatileCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    static STRINGS: &[&str; 3] = &["Tock", "DFU", "0"];
+
+    struct MockController;
+
+    impl UsbController<'static> for MockController {
+        fn set_client(&self, _client: &'static dyn Client<'static>) {}
+        fn endpoint_set_ctrl_buffer(&self, _buf: &'static [VolatileCell<u8>]) {}
+        fn endpoint_set_in_buffer(&self, _endpoint: usize, _buf: &'static [VolatileCell<u8>]) {}
+        fn endpoint_set_out_buffer(&self, _endpoint: usize, _buf: &'static [VolatileCell<u8>]) {}
+        fn enable_as_device(&self, _speed: hil::usb::DeviceSpeed) {}
+        fn attach(&self) {}
+        fn detach(&self) {}
+        fn set_address(&self, _addr: u16) {}
+        fn enable_address(&self) {}
+        fn endpoint_in_enable(&self, _transfer_type: hil::usb::TransferType, _endpoint: usize) {}
+        fn endpoint_out_enable(&self, _transfer_type: hil::usb::TransferType, _endpoint: usize) {}
+        fn endpoint_in_out_enable(&self, _transfer_type: hil::usb::TransferType, _endpoint: usize) {
+        }
+        fn endpoint_resume_in(&self, _endpoint: usize) {}
+        fn endpoint_resume_out(&self, _endpoint: usize) {}
+    }
+
+    /// Stores the app in memory. Operations complete when the test calls
+    /// the client.
+    #[derive(Default)]
+    struct MockStore {
+        app: core::cell::RefCell<Vec<u8>>,
+        pending_write: core::cell::RefCell<Option<(&'static mut [u8], usize)>>,
+        finalized: Cell<bool>,
+        loaded: Cell<bool>,
+        aborted: Cell<bool>,
+    }
+
+    impl DynamicBinaryStore for MockStore {
+        fn setup(&self, app_length: usize) -> Result<usize, ErrorCode> {
+            self.app.replace(std::vec![0; app_length]);
+            Ok(app_length)
+        }
+
+        fn write(
+            &self,
+            buffer: SubSliceMut<'static, u8>,
+            offset: usize,
+        ) -> Result<(), (ErrorCode, Option<&'static mut [u8]>)> {
+            let data = buffer.as_slice();
+            self.app.borrow_mut()[offset..offset + data.len()].copy_from_slice(data);
+            let length = data.len();
+            self.pending_write.replace(Some((buffer.take(), length)));
+            Ok(())
+        }
+
+        fn finalize(&self) -> Result<(), ErrorCode> {
+            self.finalized.set(true);
+            Ok(())
+        }
+
+        fn abort(&self) -> Result<(), ErrorCode> {
+            self.aborted.set(true);
+            Ok(())
+        }
+
+        fn set_storage_client(&self, _client: &'static dyn DynamicBinaryStoreClient) {}
+    }
+
+    impl DynamicProcessLoad for MockStore {
+        fn load(&self) -> Result<(), ErrorCode> {
+            self.loaded.set(true);
+            Ok(())
+        }
+
+        fn set_load_client(&self, _client: &'static dyn DynamicProcessLoadClient) {}
+    }
+
+    type Dfu = UsbDfu<'static, MockController, MockStore, MockStore>;
+
+    fn new_dfu() -> (&'static Dfu, &'static MockStore) {
+        let store = Box::leak(Box::new(MockStore::default()));
+        let buffer = Box::leak(Box::new([0; BUF_LEN]));
+        let dfu = Box::leak(Box::new(UsbDfu::new(
+            Box::leak(Box::new(MockController)),
+            64,
+            0x6667,
+            0xabcd,
+            STRINGS,
+            store,
+            store,
+            None,
+            buffer,
+        )));
+        (dfu, store)
+    }
+
+    fn setup(dfu: &'static Dfu, request_type: u8, request: u8, value: u16, length: u16) {
+        let packet = [
+            request_type,
+            request,
+            value as u8,
+            (value >> 8) as u8,
+            0,
+            0,
+            length as u8,
+            (length >> 8) as u8,
+        ];
+        for (cell, byte) in dfu.client_ctrl.ctrl_buffer.buf.iter().zip(packet) {
+            cell.set(byte);
+        }
+    }
+
+    fn download(dfu: &'static Dfu, block_num: u16, data: &[u8]) -> CtrlSetupResult {
+        setup(dfu, 0x21, request::DNLOAD, block_num, data.len() as u16);
+        let result = dfu.ctrl_setup(0);
+        if matches!(result, CtrlSetupResult::Ok) && !data.is_empty() {
+            for (cell, byte) in dfu.client_ctrl.ctrl_buffer.buf.iter().zip(data) {
+                cell.set(*byte);
+            }
+            assert!(matches!(
+                dfu.ctrl_out(0, data.len() as u32),
+                CtrlOutResult::Ok
+            ));
+        }
+        dfu.ctrl_status_complete(0);
+        result
+    }
+
+    /// Returns the status and state of DFU_GETSTATUS.
+    fn get_status(dfu: &'static Dfu) -> (u8, u8) {
+        setup(dfu, 0xA1, request::GETSTATUS, 0, 6);
+        assert!(matches!(dfu.ctrl_setup(0), CtrlSetupResult::Ok));
+        assert!(matches!(dfu.ctrl_in(0), CtrlInResult::Packet(6, true)));
+        dfu.ctrl_status_complete(0);
+        let buf = &dfu.client_ctrl.ctrl_buffer.buf;
+        (buf[0].get(), buf[4].get())
+    }
+
+    fn complete_write(dfu: &'static Dfu, store: &MockStore) {
+        let (buffer, length) = store.pending_write.take().unwrap();
+        DynamicBinaryStoreClient::write_done(dfu, Ok(()), buffer, length);
+    }
+
+    #[test]
+    fn download_app() {
+        let (dfu, store) = new_dfu();
+        dfu.enable();
+
+        // A 100 byte app, followed by padding.
+        let mut app: Vec<u8> = (0..128).map(|i| i as u8).collect();
+        app[4..8].copy_from_slice(&100u32.to_le_bytes());
+
+        assert!(matches!(download(dfu, 0, &app[..64]), CtrlSetupResult::Ok));
+        dfu.setup_done(Ok(()));
+        assert_eq!(get_status(dfu), (0, State::DnBusy as u8));
+        complete_write(dfu, store);
+        assert_eq!(get_status(dfu), (0, State::DnloadIdle as u8));
+
+        assert!(matches!(download(dfu, 1, &app[64..]), CtrlSetupResult::Ok));
+        complete_write(dfu, store);
+        assert_eq!(get_status(dfu), (0, State::DnloadIdle as u8));
+        assert_eq!(*store.app.borrow(), app[..100]);
+
+        // Manifestation.
+        assert!(matches!(download(dfu, 2, &[]), CtrlSetupResult::Ok));
+        assert!(store.finalized.get());
+        dfu.finalize_done(Ok(()));
+        assert!(store.loaded.get());
+        assert_eq!(get_status(dfu), (0, State::Manifest as u8));
+        dfu.load_done(Ok(()));
+        assert_eq!(get_status(dfu), (0, State::Idle as u8));
+        assert!(!store.aborted.get());
+    }
+
+    #[test]
+    fn errors() {
+        let (dfu, store) = new_dfu();
+        dfu.enable();
+
+        // There is no kernel slot.
+        setup(dfu, 0x01, REQUEST_SET_INTERFACE, ALT_KERNEL as u16, 0);
+        assert!(matches!(dfu.ctrl_setup(0), CtrlSetupResult::ErrGeneric));
+
+        // Manifestation without a download.
+        assert!(matches!(download(dfu, 0, &[]), CtrlSetupResult::ErrGeneric));
+        assert_eq!(
+            get_status(dfu),
+            (Status::ErrStalledPkt as u8, State::Error as u8)
+        );
+        setup(dfu, 0x21, request::CLRSTATUS, 0, 0);
+        assert!(matches!(dfu.ctrl_setup(0), CtrlSetupResult::Ok));
+        assert_eq!(get_status(dfu), (0, State::Idle as u8));
+
+        // A block too short for a TBF header.
+        assert!(matches!(download(dfu, 0, &[0; 4]), CtrlSetupResult::Ok));
+        assert_eq!(get_status(dfu), (Status::ErrFile as u8, State::Error as u8));
+        assert!(!store.aborted.get());
+
+        // A failed write drops the app.
+        setup(dfu, 0x21, request::CLRSTATUS, 0, 0);
+        assert!(matches!(dfu.ctrl_setup(0), CtrlSetupResult::Ok));
+        let mut app = [0; 16];
+        app[4..8].copy_from_slice(&16u32.to_le_bytes());
+        assert!(matches!(download(dfu, 0, &app), CtrlSetupResult::Ok));
+        dfu.setup_done(Ok(()));
+        let (buffer, _) = store.pending_write.take().unwrap();
+        DynamicBinaryStoreClient::write_done(dfu, Err(ErrorCode::FAIL), buffer, 0);
+        assert_eq!(
+            get_status(dfu),
+            (Status::ErrWrite as u8, State::Error as u8)
+        );
+        assert!(store.aborted.get());
+    }
+
+    #[test]
+    fn repeated_blocks_are_not_written_again() {
+        let (dfu, store) = new_dfu();
+        dfu.enable();
+
+        let mut app: Vec<u8> = (0..128).map(|i| i as u8).collect();
+        app[4..8].copy_from_slice(&128u32.to_le_bytes());
+
+        // The first block can have any number.
+        assert!(matches!(download(dfu, 7, &app[..64]), CtrlSetupResult::Ok));
+        dfu.setup_done(Ok(()));
+        complete_write(dfu, store);
+        assert_eq!(get_status(dfu), (0, State::DnloadIdle as u8));
+
+        // The host missed the status and sends the block again.
+        assert!(matches!(download(dfu, 7, &app[..64]), CtrlSetupResult::Ok));
+        assert!(store.pending_write.borrow().is_none());
+        assert_eq!(get_status(dfu), (0, State::DnloadIdle as u8));
+
+        assert!(matches!(download(dfu, 8, &app[64..]), CtrlSetupResult::Ok));
+        complete_write(dfu, store);
+        assert_eq!(get_status(dfu), (0, State::DnloadIdle as u8));
+        assert_eq!(*store.app.borrow(), app);
+    }
+
+    #[test]
+    fn skipped_blocks_are_rejected() {
+        let (dfu, store) = new_dfu();
+        dfu.enable();
+
+        let mut app = [0; 128];
+        app[4..8].copy_from_slice(&128u32.to_le_bytes());
+        assert!(matches!(download(dfu, 0, &app[..64]), CtrlSetupResult::Ok));
+        dfu.setup_done(Ok(()));
+        complete_write(dfu, store);
+        assert_eq!(get_status(dfu), (0, State::DnloadIdle as u8));
+
+        assert!(matches!(
+            download(dfu, 2, &app[64..]),
+            CtrlSetupResult::ErrGeneric
+        ));
+        assert_eq!(
+            get_status(dfu),
+            (Status::ErrStalledPkt as u8, State::Error as u8)
+        );
+        assert!(store.aborted.get());
+    }
+}
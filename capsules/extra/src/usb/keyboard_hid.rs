@@ -151,6 +151,7 @@ impl<'a, U: hil::usb::UsbController<'a>> KeyboardHid<'a, U> {
                 endpoints,
                 Some(&HID_DESCRIPTOR),
                 None,
+                None,
             );
 
         KeyboardHid {
@@ -3,9 +3,13 @@
 // Copyright Tock Contributors 2022.
 
 pub mod cdc;
+pub mod composite;
 pub mod ctap;
 pub mod descriptors;
+pub mod dfu;
 pub mod keyboard_hid;
+pub mod msc;
+pub mod msc_app_drop;
 pub mod usb_user;
 pub mod usbc_client;
 pub mod usbc_client_ctrl;
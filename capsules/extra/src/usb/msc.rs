@@ -0,0 +1,844 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Mass Storage Class device for USB
+//!
+//! This capsule exposes a region of a `NonvolatileStorage` to the host as a
+//! USB drive, using the Bulk-Only Transport (BOT) and the SCSI transparent
+//! command set. The region is presented as a single logical unit of
+//! 512 byte blocks.
+//!
+//! The host sends each command in a Command Block Wrapper (CBW) on the bulk
+//! OUT endpoint, data moves on the bulk IN or OUT endpoint, and the device
+//! replies with a Command Status Wrapper (CSW) on the bulk IN endpoint:
+//!
+//! ```text
+//!   host                                     device
+//!    |  CBW (31 bytes, OUT)                    |
+//!    | --------------------------------------> |
+//!    |  data (IN or OUT, optional)             |
+//!    | <-------------------------------------> |
+//!    |  CSW (13 bytes, IN)                     |
+//!    | <-------------------------------------- |
+//! ```
+//!
+//! The supported commands are the ones hosts need to mount a drive: TEST
+//! UNIT READY, REQUEST SENSE, INQUIRY, MODE SENSE, START STOP UNIT, PREVENT
+//! ALLOW MEDIUM REMOVAL, READ FORMAT CAPACITIES, READ CAPACITY, READ(10),
+//! WRITE(10), VERIFY and SYNCHRONIZE CACHE. Other commands fail with an
+//! ILLEGAL REQUEST sense.
+//!
+//! The USB HIL cannot stall an endpoint on demand, so when the device has
+//! less data for the host than the host asked for, it pads the transfer with
+//! zeros and reports the difference as residue in the CSW, as allowed by the
+//! BOT specification. Data the host sends for a failed command is discarded.
+//!
+//! Blocks are transferred through one 512 byte buffer, so the host is held
+//! off with NAKs while a block is read from or written to the storage.
+//!
+//! Usage
+//! -----
+//! See `components::usb_msc`. To install apps by copying files to the drive,
+//! the storage can be a `msc_app_drop::AppDrop`.
+
+use core::cell::Cell;
+use core::cmp;
+
+use super::descriptors;
+use super::descriptors::Buffer64;
+use super::descriptors::EndpointAddress;
+use super::descriptors::EndpointDescriptor;
+use super::descriptors::InterfaceDescriptor;
+use super::descriptors::Recipient;
+use super::descriptors::RequestType;
+use super::descriptors::TransferDirection;
+use super::usbc_client_ctrl::ClientCtrl;
+
+use kernel::hil;
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::hil::usb::TransferType;
+use kernel::utilities::cells::{OptionalCell, TakeCell, VolatileCell};
+
+/// Identifying number for the endpoint when transferring data from us to the
+/// host.
+const ENDPOINT_IN_NUM: usize = 1;
+/// Identifying number for the endpoint when transferring data from the host to
+/// us.
+const ENDPOINT_OUT_NUM: usize = 2;
+
+static LANGUAGES: &[u16; 1] = &[
+    0x0409, // English (United States)
+];
+
+/// Size of the blocks of the drive. The buffer passed to
+/// [`MassStorage::new`] must be at least this long.
+pub const BLOCK_SIZE: usize = 512;
+
+const PACKET_SIZE: usize = 64;
+
+/// Class specific control requests.
+const REQUEST_GET_MAX_LUN: u8 = 0xFE;
+const REQUEST_RESET: u8 = 0xFF;
+
+const CBW_SIGNATURE: u32 = 0x43425355;
+const CBW_LEN: usize = 31;
+const CSW_SIGNATURE: u32 = 0x53425355;
+const CSW_LEN: usize = 13;
+
+/// Status codes of the CSW.
+const STATUS_PASSED: u8 = 0;
+const STATUS_FAILED: u8 = 1;
+const STATUS_PHASE_ERROR: u8 = 2;
+
+/// SCSI operation codes.
+mod opcode {
+    pub const TEST_UNIT_READY: u8 = 0x00;
+    pub const REQUEST_SENSE: u8 = 0x03;
+    pub const INQUIRY: u8 = 0x12;
+    pub const MODE_SENSE_6: u8 = 0x1A;
+    pub const START_STOP_UNIT: u8 = 0x1B;
+    pub const PREVENT_ALLOW_MEDIUM_REMOVAL: u8 = 0x1E;
+    pub const READ_FORMAT_CAPACITIES: u8 = 0x23;
+    pub const READ_CAPACITY_10: u8 = 0x25;
+    pub const READ_10: u8 = 0x28;
+    pub const WRITE_10: u8 = 0x2A;
+    pub const VERIFY_10: u8 = 0x2F;
+    pub const SYNCHRONIZE_CACHE_10: u8 = 0x35;
+    pub const MODE_SENSE_10: u8 = 0x5A;
+}
+
+/// SCSI sense data, as (sense key, additional sense code).
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Sense(u8, u8);
+
+impl Sense {
+    const NONE: Sense = Sense(0x00, 0x00);
+    const UNRECOVERED_READ_ERROR: Sense = Sense(0x03, 0x11);
+    const WRITE_ERROR: Sense = Sense(0x03, 0x0C);
+    const INVALID_COMMAND: Sense = Sense(0x05, 0x20);
+    const LBA_OUT_OF_RANGE: Sense = Sense(0x05, 0x21);
+    const INVALID_FIELD_IN_CDB: Sense = Sense(0x05, 0x24);
+}
+
+/// Where the bulk endpoints are in the BOT protocol.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Phase {
+    /// Waiting for a CBW.
+    Command,
+    /// Sending the block buffer, then zeros, to the host.
+    DataIn,
+    /// Receiving data from the host.
+    DataOut,
+    /// Waiting for the storage to read or write a block.
+    Storage,
+    /// The CSW is ready to be sent.
+    Status,
+    /// The CSW was handed to the controller.
+    StatusSent,
+}
+
+/// The storage operation of the current command.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Operation {
+    None,
+    Read { lba: u32, blocks: u32 },
+    Write { lba: u32, blocks: u32 },
+}
+
+/// Implementation of the USB Mass Storage Class (Bulk-Only Transport).
+pub struct MassStorage<'a, U: 'a, S: NonvolatileStorage<'a>> {
+    /// Helper USB client library for handling many USB operations.
+    client_ctrl: ClientCtrl<'a, 'static, U>,
+
+    /// 64 byte buffers for each endpoint.
+    buffers: [Buffer64; 2],
+
+    /// Whether the pending control transfer is a GET MAX LUN request.
+    get_max_lun: Cell<bool>,
+
+    storage: &'a S,
+    /// Address in the storage of the first block of the drive.
+    start_address: usize,
+    /// Number of blocks of the drive.
+    num_blocks: u32,
+
+    /// Vendor and product identification returned by INQUIRY.
+    vendor: &'static str,
+    product: &'static str,
+
+    phase: Cell<Phase>,
+    operation: Cell<Operation>,
+    /// Buffer for blocks and command responses.
+    block: TakeCell<'static, [u8]>,
+    /// Number of valid bytes in `block` in the DataIn phase, or bytes received
+    /// into it in the DataOut phase.
+    block_len: Cell<usize>,
+    /// Bytes of `block` already sent to the host.
+    block_offset: Cell<usize>,
+    /// Whether the last packet sent to the host was a full packet, so the
+    /// host still expects data.
+    last_packet_full: Cell<bool>,
+    /// An OUT packet which arrived while a block was being written. It is
+    /// still in the endpoint buffer.
+    pending_out: OptionalCell<usize>,
+
+    /// Tag of the current command, echoed in its CSW.
+    tag: Cell<u32>,
+    /// Bytes of the data phase the host still expects to transfer.
+    data_remaining: Cell<u32>,
+    /// Status reported in the CSW of the current command.
+    status: Cell<u8>,
+    /// Sense data of the last command, returned by REQUEST SENSE.
+    sense: Cell<Sense>,
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, S: NonvolatileStorage<'a>> MassStorage<'a, U, S> {
+    /// Creates a drive of `num_blocks` blocks, starting at `start_address` in
+    /// `storage`. The manufacturer and product `strings` are also used as the
+    /// vendor and product identification of the drive.
+    pub fn new(
+        controller: &'a U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+        storage: &'a S,
+        start_address: usize,
+        num_blocks: u32,
+        block: &'static mut [u8],
+    ) -> Self {
+        let interfaces: &mut [InterfaceDescriptor] = &mut [InterfaceDescriptor {
+            interface_number: 0,
+            interface_class: 0x08,    // Mass Storage
+            interface_subclass: 0x06, // SCSI transparent command set
+            interface_protocol: 0x50, // Bulk-Only Transport
+            ..InterfaceDescriptor::default()
+        }];
+
+        let endpoints: &[&[EndpointDescriptor]] = &[&[
+            EndpointDescriptor {
+                endpoint_address: EndpointAddress::new_const(
+                    ENDPOINT_IN_NUM,
+                    TransferDirection::DeviceToHost,
+                ),
+                transfer_type: TransferType::Bulk,
+                max_packet_size: PACKET_SIZE as u16,
+                interval: 0,
+            },
+            EndpointDescriptor {
+                endpoint_address: EndpointAddress::new_const(
+                    ENDPOINT_OUT_NUM,
+                    TransferDirection::HostToDevice,
+                ),
+                transfer_type: TransferType::Bulk,
+                max_packet_size: PACKET_SIZE as u16,
+                interval: 0,
+            },
+        ]];
+
+        let (device_descriptor_buffer, other_descriptor_buffer) =
+            descriptors::create_descriptor_buffers(
+                descriptors::DeviceDescriptor {
+                    vendor_id,
+                    product_id,
+                    manufacturer_string: 1,
+                    product_string: 2,
+                    serial_number_string: 3,
+                    max_packet_size_ep0: max_ctrl_packet_size,
+                    ..descriptors::DeviceDescriptor::default()
+                },
+                descriptors::ConfigurationDescriptor::default(),
+                interfaces,
+                endpoints,
+                None, // No HID descriptor
+                None, // No CDC descriptor
+                None, // No DFU descriptor
+            );
+
+        Self {
+            client_ctrl: ClientCtrl::new(
+                controller,
+                device_descriptor_buffer,
+                other_descriptor_buffer,
+                None, // No HID descriptor
+                None, // No report descriptor
+                LANGUAGES,
+                strings,
+            ),
+            buffers: [Buffer64::default(), Buffer64::default()],
+            get_max_lun: Cell::new(false),
+            storage,
+            start_address,
+            num_blocks,
+            vendor: strings[0],
+            product: strings[1],
+            phase: Cell::new(Phase::Command),
+            operation: Cell::new(Operation::None),
+            block: TakeCell::new(block),
+            block_len: Cell::new(0),
+            block_offset: Cell::new(0),
+            last_packet_full: Cell::new(false),
+            pending_out: OptionalCell::empty(),
+            tag: Cell::new(0),
+            data_remaining: Cell::new(0),
+            status: Cell::new(STATUS_PASSED),
+            sense: Cell::new(Sense::NONE),
+        }
+    }
+
+    #[inline]
+    fn controller(&self) -> &'a U {
+        self.client_ctrl.controller()
+    }
+
+    fn buffer(&self, endpoint: usize) -> &[VolatileCell<u8>; 64] {
+        &self.buffers[endpoint - 1].buf
+    }
+
+    /// Returns to waiting for a command, abandoning the current one.
+    fn reset(&self) {
+        self.phase.set(Phase::Command);
+        self.operation.set(Operation::None);
+        self.pending_out.clear();
+        self.data_remaining.set(0);
+    }
+
+    /// Parses the CBW in the OUT endpoint buffer and starts its command.
+    fn receive_command(&self, packet_bytes: usize) {
+        let packet = self.buffer(ENDPOINT_OUT_NUM);
+        let mut cbw = [0; CBW_LEN];
+        for (byte, cell) in cbw.iter_mut().zip(packet.iter()) {
+            *byte = cell.get();
+        }
+
+        // Without a way to stall the endpoints, invalid CBWs are ignored.
+        let signature = u32::from_le_bytes([cbw[0], cbw[1], cbw[2], cbw[3]]);
+        let cb_len = cbw[14] as usize;
+        if packet_bytes != CBW_LEN || signature != CBW_SIGNATURE || cb_len == 0 || cb_len > 16 {
+            return;
+        }
+
+        self.tag
+            .set(u32::from_le_bytes([cbw[4], cbw[5], cbw[6], cbw[7]]));
+        self.data_remaining
+            .set(u32::from_le_bytes([cbw[8], cbw[9], cbw[10], cbw[11]]));
+        let data_in = cbw[12] & 0x80 != 0;
+        let lun = cbw[13] & 0x0F;
+        let cb = &cbw[15..15 + cb_len];
+
+        self.status.set(STATUS_PASSED);
+        self.operation.set(Operation::None);
+        self.block_len.set(0);
+        self.block_offset.set(0);
+        // Until a short packet ends the transfer, the host expects all the
+        // data it asked for.
+        self.last_packet_full.set(true);
+
+        if lun != 0 {
+            self.fail(Sense::INVALID_COMMAND);
+            self.start_data_phase(data_in);
+            return;
+        }
+
+        if cb[0] != opcode::REQUEST_SENSE {
+            self.sense.set(Sense::NONE);
+        }
+        match self.execute(cb) {
+            Ok(Operation::None) => {}
+            Ok(operation @ (Operation::Read { blocks, .. } | Operation::Write { blocks, .. })) => {
+                let read = matches!(operation, Operation::Read { .. });
+                let expected = blocks as usize * BLOCK_SIZE;
+                if read != data_in || (self.data_remaining.get() as usize) < expected {
+                    // The host and the command disagree on the data phase.
+                    self.status.set(STATUS_PHASE_ERROR);
+                } else if blocks > 0 {
+                    self.operation.set(operation);
+                }
+            }
+            Err(sense) => self.fail(sense),
+        }
+        self.start_data_phase(data_in);
+    }
+
+    /// Executes a command. Responses are written to the block buffer, and
+    /// storage operations are returned to be carried out in the data phase.
+    fn execute(&self, cb: &[u8]) -> Result<Operation, Sense> {
+        let byte = |i: usize| cb.get(i).copied().unwrap_or(0);
+        let u16_at = |i: usize| u16::from_be_bytes([byte(i), byte(i + 1)]) as usize;
+        let u32_at =
+            |i: usize| u32::from_be_bytes([byte(i), byte(i + 1), byte(i + 2), byte(i + 3)]);
+
+        match cb[0] {
+            opcode::TEST_UNIT_READY
+            | opcode::START_STOP_UNIT
+            | opcode::PREVENT_ALLOW_MEDIUM_REMOVAL
+            | opcode::VERIFY_10
+            | opcode::SYNCHRONIZE_CACHE_10 => Ok(Operation::None),
+
+            opcode::REQUEST_SENSE => {
+                let sense = self.sense.replace(Sense::NONE);
+                self.respond(byte(4) as usize, |response| {
+                    response[..18].fill(0);
+                    response[0] = 0x70; // Current error, fixed format
+                    response[2] = sense.0;
+                    response[7] = 10; // Additional sense length
+                    response[12] = sense.1;
+                    18
+                });
+                Ok(Operation::None)
+            }
+
+            opcode::INQUIRY => {
+                if byte(1) & 0x01 != 0 {
+                    // Vital product data pages are not supported.
+                    return Err(Sense::INVALID_FIELD_IN_CDB);
+                }
+                self.respond(u16_at(3), |response| {
+                    response[..36].fill(b' ');
+                    response[0] = 0x00; // Direct access block device
+                    response[1] = 0x80; // Removable
+                    response[2] = 0x04; // SPC-2
+                    response[3] = 0x02; // Response data format
+                    response[4] = 36 - 5; // Additional length
+                    response[5..8].fill(0);
+                    copy_padded(&mut response[8..16], self.vendor);
+                    copy_padded(&mut response[16..32], self.product);
+                    copy_padded(&mut response[32..36], "1.0");
+                    36
+                });
+                Ok(Operation::None)
+            }
+
+            opcode::MODE_SENSE_6 => {
+                self.respond(byte(4) as usize, |response| {
+                    // Mode data length, medium type, not write protected, no
+                    // block descriptors.
+                    response[..4].copy_from_slice(&[3, 0, 0, 0]);
+                    4
+                });
+                Ok(Operation::None)
+            }
+
+            opcode::MODE_SENSE_10 => {
+                self.respond(u16_at(7), |response| {
+                    response[..8].copy_from_slice(&[0, 6, 0, 0, 0, 0, 0, 0]);
+                    8
+                });
+                Ok(Operation::None)
+            }
+
+            opcode::READ_FORMAT_CAPACITIES => {
+                self.respond(u16_at(7), |response| {
+                    response[..4].copy_from_slice(&[0, 0, 0, 8]);
+                    response[4..8].copy_from_slice(&self.num_blocks.to_be_bytes());
+                    // Formatted media, and the block length.
+                    response[8..12]
+                        .copy_from_slice(&(0x0200_0000 | BLOCK_SIZE as u32).to_be_bytes());
+                    12
+                });
+                Ok(Operation::None)
+            }
+
+            opcode::READ_CAPACITY_10 => {
+                self.respond(8, |response| {
+                    let last_lba = self.num_blocks.saturating_sub(1);
+                    response[..4].copy_from_slice(&last_lba.to_be_bytes());
+                    response[4..8].copy_from_slice(&(BLOCK_SIZE as u32).to_be_bytes());
+                    8
+                });
+                Ok(Operation::None)
+            }
+
+            opcode::READ_10 | opcode::WRITE_10 => {
+                let lba = u32_at(2);
+                let blocks = u16_at(7) as u32;
+                if lba as u64 + blocks as u64 > self.num_blocks as u64 {
+                    return Err(Sense::LBA_OUT_OF_RANGE);
+                }
+                if cb[0] == opcode::READ_10 {
+                    Ok(Operation::Read { lba, blocks })
+                } else {
+                    Ok(Operation::Write { lba, blocks })
+                }
+            }
+
+            _ => Err(Sense::INVALID_COMMAND),
+        }
+    }
+
+    /// Writes a response of at most `allocation_length` bytes to the block
+    /// buffer.
+    fn respond(&self, allocation_length: usize, f: impl FnOnce(&mut [u8]) -> usize) {
+        self.block.map(|block| {
+            let len = f(block);
+            self.block_len.set(cmp::min(len, allocation_length));
+        });
+    }
+
+    fn fail(&self, sense: Sense) {
+        self.sense.set(sense);
+        self.status.set(STATUS_FAILED);
+        self.operation.set(Operation::None);
+        self.block_len.set(0);
+    }
+
+    /// Moves on to the data phase of the current command, or to its status if
+    /// it has no data.
+    fn start_data_phase(&self, data_in: bool) {
+        if self.data_remaining.get() == 0 {
+            self.send_status();
+        } else if !data_in {
+            self.phase.set(Phase::DataOut);
+        } else if matches!(self.operation.get(), Operation::Read { .. }) {
+            self.start_read();
+        } else {
+            self.phase.set(Phase::DataIn);
+            self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+        }
+    }
+
+    fn send_status(&self) {
+        self.operation.set(Operation::None);
+        self.phase.set(Phase::Status);
+        self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+    }
+
+    fn block_address(&self, lba: u32) -> usize {
+        self.start_address + lba as usize * BLOCK_SIZE
+    }
+
+    /// Reads the next block of a READ command from the storage.
+    fn start_read(&self) {
+        let Operation::Read { lba, .. } = self.operation.get() else {
+            return;
+        };
+        self.phase.set(Phase::Storage);
+        let result = self.block.take().map(|block| {
+            self.storage
+                .read(block, self.block_address(lba), BLOCK_SIZE)
+        });
+        if result != Some(Ok(())) {
+            // `read()` doesn't return the buffer on error, so the drive can't
+            // be used any more.
+            self.fail(Sense::UNRECOVERED_READ_ERROR);
+            self.phase.set(Phase::DataIn);
+            self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+        }
+    }
+
+    /// Writes the received block of a WRITE command to the storage.
+    fn start_write(&self) {
+        let Operation::Write { lba, .. } = self.operation.get() else {
+            return;
+        };
+        self.phase.set(Phase::Storage);
+        let result = self.block.take().map(|block| {
+            self.storage
+                .write(block, self.block_address(lba), BLOCK_SIZE)
+        });
+        if result != Some(Ok(())) {
+            self.fail(Sense::WRITE_ERROR);
+            self.continue_data_out();
+        }
+    }
+
+    /// Stores an OUT packet of the data phase.
+    fn receive_data(&self, packet_bytes: usize) {
+        let len = cmp::min(packet_bytes, self.data_remaining.get() as usize);
+        self.data_remaining
+            .set(self.data_remaining.get() - len as u32);
+
+        if let Operation::Write { .. } = self.operation.get() {
+            let packet = self.buffer(ENDPOINT_OUT_NUM);
+            self.block.map(|block| {
+                let offset = self.block_len.get();
+                let len = cmp::min(len, BLOCK_SIZE - offset);
+                for (byte, cell) in block[offset..offset + len].iter_mut().zip(packet.iter()) {
+                    *byte = cell.get();
+                }
+                self.block_len.set(offset + len);
+            });
+            if self.block_len.get() == BLOCK_SIZE {
+                self.start_write();
+                return;
+            }
+        }
+
+        if self.data_remaining.get() == 0 {
+            self.send_status();
+        }
+    }
+
+    /// Continues the data phase of a WRITE command after the storage is done
+    /// with a block.
+    fn continue_data_out(&self) {
+        self.block_len.set(0);
+        if self.data_remaining.get() == 0 {
+            self.send_status();
+        } else {
+            self.phase.set(Phase::DataOut);
+        }
+        if let Some(packet_bytes) = self.pending_out.take() {
+            self.receive_data(packet_bytes);
+            self.controller().endpoint_resume_out(ENDPOINT_OUT_NUM);
+        }
+    }
+}
+
+/// Copies `s` to `dst`, truncated or padded with spaces.
+fn copy_padded(dst: &mut [u8], s: &str) {
+    dst.fill(b' ');
+    for (d, c) in dst.iter_mut().zip(s.bytes()) {
+        *d = c;
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, S: NonvolatileStorage<'a>> hil::usb::Client<'a>
+    for MassStorage<'a, U, S>
+{
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.client_ctrl.enable();
+
+        // Setup buffers for IN and OUT data transfer.
+        self.controller()
+            .endpoint_set_in_buffer(ENDPOINT_IN_NUM, self.buffer(ENDPOINT_IN_NUM));
+        self.controller()
+            .endpoint_in_enable(TransferType::Bulk, ENDPOINT_IN_NUM);
+
+        self.controller()
+            .endpoint_set_out_buffer(ENDPOINT_OUT_NUM, self.buffer(ENDPOINT_OUT_NUM));
+        self.controller()
+            .endpoint_out_enable(TransferType::Bulk, ENDPOINT_OUT_NUM);
+    }
+
+    fn attach(&'a self) {
+        self.client_ctrl.attach();
+    }
+
+    fn bus_reset(&'a self) {
+        self.reset();
+    }
+
+    /// Handle a Control Setup transaction.
+    ///
+    /// The class requests of the BOT are handled here, everything else by
+    /// `ClientCtrl`.
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        if let Some(setup_data) = descriptors::SetupData::get(&self.client_ctrl.ctrl_buffer.buf) {
+            let class_request =
+                matches!(setup_data.request_type.request_type(), RequestType::Class)
+                    && matches!(setup_data.request_type.recipient(), Recipient::Interface);
+            match setup_data.request_code {
+                REQUEST_GET_MAX_LUN if class_request => {
+                    self.get_max_lun.set(true);
+                    return hil::usb::CtrlSetupResult::Ok;
+                }
+                REQUEST_RESET if class_request => {
+                    let out_delayed = self.pending_out.is_some();
+                    self.reset();
+                    if out_delayed {
+                        self.controller().endpoint_resume_out(ENDPOINT_OUT_NUM);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        self.client_ctrl.ctrl_setup(endpoint)
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
+        if self.get_max_lun.get() {
+            // There is a single logical unit.
+            self.client_ctrl.ctrl_buffer.buf[0].set(0);
+            return hil::usb::CtrlInResult::Packet(1, true);
+        }
+        self.client_ctrl.ctrl_in(endpoint)
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        self.client_ctrl.ctrl_out(endpoint, packet_bytes)
+    }
+
+    fn ctrl_status(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status(endpoint)
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, endpoint: usize) {
+        self.get_max_lun.set(false);
+        self.client_ctrl.ctrl_status_complete(endpoint)
+    }
+
+    /// Handle a Bulk IN transaction.
+    ///
+    /// This sends the data of a command and then its CSW.
+    fn packet_in(&'a self, transfer_type: TransferType, endpoint: usize) -> hil::usb::InResult {
+        if !matches!(transfer_type, TransferType::Bulk) {
+            return hil::usb::InResult::Error;
+        }
+        let packet = self.buffer(endpoint);
+
+        match self.phase.get() {
+            Phase::DataIn => {
+                let remaining = self.data_remaining.get() as usize;
+                let offset = self.block_offset.get();
+                let available = self.block_len.get() - offset;
+                let len = if available > 0 {
+                    // Send from the block buffer.
+                    let len = cmp::min(cmp::min(PACKET_SIZE, available), remaining);
+                    self.block.map(|block| {
+                        for (cell, byte) in packet.iter().zip(&block[offset..offset + len]) {
+                            cell.set(*byte);
+                        }
+                    });
+                    self.block_offset.set(offset + len);
+                    len
+                } else {
+                    // Pad the transfer to the length the host expects.
+                    let len = cmp::min(PACKET_SIZE, remaining);
+                    for cell in packet.iter().take(len) {
+                        cell.set(0);
+                    }
+                    len
+                };
+                if len == 0 {
+                    return hil::usb::InResult::Delay;
+                }
+                self.data_remaining.set((remaining - len) as u32);
+                self.last_packet_full.set(len == PACKET_SIZE);
+                hil::usb::InResult::Packet(len)
+            }
+            Phase::Status => {
+                let mut csw = [0; CSW_LEN];
+                csw[0..4].copy_from_slice(&CSW_SIGNATURE.to_le_bytes());
+                csw[4..8].copy_from_slice(&self.tag.get().to_le_bytes());
+                csw[8..12].copy_from_slice(&self.data_remaining.get().to_le_bytes());
+                csw[12] = self.status.get();
+                for (cell, byte) in packet.iter().zip(csw.iter()) {
+                    cell.set(*byte);
+                }
+                self.phase.set(Phase::StatusSent);
+                hil::usb::InResult::Packet(CSW_LEN)
+            }
+            _ => hil::usb::InResult::Delay,
+        }
+    }
+
+    /// Handle a Bulk OUT transaction.
+    ///
+    /// This receives CBWs and the data of WRITE commands.
+    fn packet_out(
+        &'a self,
+        transfer_type: TransferType,
+        _endpoint: usize,
+        packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        if !matches!(transfer_type, TransferType::Bulk) {
+            return hil::usb::OutResult::Error;
+        }
+
+        match self.phase.get() {
+            // The CBW of the next command can arrive before we are told the
+            // CSW of the previous one was transmitted.
+            Phase::Command | Phase::StatusSent => {
+                self.phase.set(Phase::Command);
+                self.receive_command(packet_bytes as usize);
+                hil::usb::OutResult::Ok
+            }
+            Phase::DataOut => {
+                self.receive_data(packet_bytes as usize);
+                hil::usb::OutResult::Ok
+            }
+            Phase::Storage if matches!(self.operation.get(), Operation::Write { .. }) => {
+                // The packet stays in the endpoint buffer until the storage
+                // is done with the previous block.
+                self.pending_out.set(packet_bytes as usize);
+                hil::usb::OutResult::Delay
+            }
+            // The host doesn't send data in these phases, drop it.
+            Phase::Storage | Phase::DataIn | Phase::Status => hil::usb::OutResult::Ok,
+        }
+    }
+
+    fn packet_transmitted(&'a self, _endpoint: usize) {
+        match self.phase.get() {
+            Phase::DataIn => {
+                let remaining = self.data_remaining.get();
+                if remaining > 0 && self.block_offset.get() < self.block_len.get() {
+                    // More of the current block.
+                    self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+                } else if remaining > 0 && matches!(self.operation.get(), Operation::Read { .. }) {
+                    self.start_read();
+                } else if remaining > 0 && self.last_packet_full.get() {
+                    // The host still expects data, pad it.
+                    self.block_len.set(0);
+                    self.block_offset.set(0);
+                    self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+                } else {
+                    self.send_status();
+                }
+            }
+            Phase::StatusSent => self.phase.set(Phase::Command),
+            _ => {}
+        }
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, S: NonvolatileStorage<'a>> NonvolatileStorageClient
+    for MassStorage<'a, U, S>
+{
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.block.replace(buffer);
+        if self.phase.get() != Phase::Storage {
+            // The command was abandoned by a reset.
+            return;
+        }
+
+        if let Operation::Read { lba, blocks } = self.operation.get() {
+            self.operation.set(if blocks > 1 {
+                Operation::Read {
+                    lba: lba + 1,
+                    blocks: blocks - 1,
+                }
+            } else {
+                Operation::None
+            });
+        }
+        if length != BLOCK_SIZE {
+            self.fail(Sense::UNRECOVERED_READ_ERROR);
+        } else {
+            self.block_len.set(BLOCK_SIZE);
+        }
+        self.block_offset.set(0);
+        self.phase.set(Phase::DataIn);
+        self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.block.replace(buffer);
+        if self.phase.get() != Phase::Storage {
+            // The command was abandoned by a reset.
+            return;
+        }
+
+        if let Operation::Write { lba, blocks } = self.operation.get() {
+            self.operation.set(if blocks > 1 {
+                Operation::Write {
+                    lba: lba + 1,
+                    blocks: blocks - 1,
+                }
+            } else {
+                Operation::None
+            });
+        }
+        if length != BLOCK_SIZE {
+            self.fail(Sense::WRITE_ERROR);
+        }
+        self.continue_data_out();
+    }
+}
@@ -0,0 +1,562 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Drag-and-drop app installation for the USB Mass Storage Class.
+//!
+//! This implements `NonvolatileStorage` as a virtual FAT12 drive to be
+//! exposed with `msc::MassStorage`. The drive contains only a text file, and
+//! apps are installed by copying them to it in the UF2 format: every 512 byte
+//! block of a UF2 file is self-describing, so the blocks can be recognized
+//! among the writes of the host without interpreting the file system. The
+//! payloads are stored with [`DynamicBinaryStore`] and the app is loaded with
+//! [`DynamicProcessLoad`] once its last block was written, so the process
+//! checker verifies its credentials before it runs.
+//!
+//! A TBF is converted to UF2 with
+//! `tools/debugging-and-development/tbf2uf2.py`. The UF2 blocks must carry
+//! the [`UF2_FAMILY_ID`] and the offset of their payload in the TBF as
+//! target address, and are expected in order, as hosts write files.
+//!
+//! The write of the last block of an app completes once the app was loaded,
+//! and fails if the app could not be stored or loaded (for example because
+//! its credentials were rejected), so the host reports the copy as failed.
+//! Other writes are accepted and discarded, and the drive shows its initial
+//! contents again once it is mounted again.
+//!
+//! The same storage driver can only have one client, so a board uses either
+//! this capsule or another app loader with it.
+//!
+//! Usage
+//! -----
+//! See `components::usb_msc`.
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::ErrorCode;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::dynamic_binary_storage::{
+    DynamicBinaryStore, DynamicBinaryStoreClient, DynamicProcessLoad, DynamicProcessLoadClient,
+};
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::process::ProcessLoadError;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+
+use crate::app_installer::{AppInstaller, State};
+
+/// UF2 family of Tock TBFs ("TOCK").
+pub const UF2_FAMILY_ID: u32 = 0x544F434B;
+
+const SECTOR_SIZE: usize = 512;
+
+/// Layout of the drive. One sector per cluster, few enough clusters for
+/// FAT12.
+const RESERVED_SECTORS: u32 = 1;
+const NUM_FATS: u32 = 2;
+const ROOT_ENTRIES: u32 = 64;
+const ROOT_SECTORS: u32 = ROOT_ENTRIES * 32 / SECTOR_SIZE as u32;
+const NUM_CLUSTERS: u32 = 4000;
+const SECTORS_PER_FAT: u32 = ((NUM_CLUSTERS + 2) * 3 / 2).div_ceil(SECTOR_SIZE as u32);
+const FAT_START: u32 = RESERVED_SECTORS;
+const ROOT_START: u32 = FAT_START + NUM_FATS * SECTORS_PER_FAT;
+const DATA_START: u32 = ROOT_START + ROOT_SECTORS;
+
+/// Number of 512 byte blocks of the drive, to be passed to
+/// `msc::MassStorage::new`.
+pub const NUM_BLOCKS: u32 = DATA_START + NUM_CLUSTERS;
+
+const VOLUME_LABEL: &[u8; 11] = b"TOCK APPS  ";
+const INFO_NAME: &[u8; 11] = b"INFO_UF2TXT";
+const INFO: &str = "Tock app drop\r\n\
+    \r\n\
+    Copy an app in the UF2 format to this drive to install it.\r\n\
+    Convert TBFs with tools/debugging-and-development/tbf2uf2.py.\r\n";
+
+/// Length of the buffer payloads are written to flash from.
+pub const BUF_LEN: usize = 512;
+
+const UF2_MAGIC_START0: u32 = 0x0A324655;
+const UF2_MAGIC_START1: u32 = 0x9E5D5157;
+const UF2_MAGIC_END: u32 = 0x0AB16F30;
+const UF2_FLAG_NOT_MAIN_FLASH: u32 = 0x0000_0001;
+const UF2_FLAG_FAMILY_ID: u32 = 0x0000_2000;
+const UF2_PAYLOAD_OFFSET: usize = 32;
+const UF2_MAX_PAYLOAD: usize = 476;
+
+/// The fields of a UF2 block this capsule uses.
+#[derive(Clone, Copy)]
+struct Uf2Block {
+    target_addr: usize,
+    payload_size: usize,
+    block_no: u32,
+    num_blocks: u32,
+}
+
+impl Uf2Block {
+    /// Parses a UF2 block for a TBF.
+    fn parse(sector: &[u8]) -> Option<Self> {
+        let word =
+            |i: usize| u32::from_le_bytes([sector[i], sector[i + 1], sector[i + 2], sector[i + 3]]);
+        if sector.len() < SECTOR_SIZE
+            || word(0) != UF2_MAGIC_START0
+            || word(4) != UF2_MAGIC_START1
+            || word(508) != UF2_MAGIC_END
+        {
+            return None;
+        }
+        let flags = word(8);
+        let block = Uf2Block {
+            target_addr: word(12) as usize,
+            payload_size: word(16) as usize,
+            block_no: word(20),
+            num_blocks: word(24),
+        };
+        if flags & UF2_FLAG_NOT_MAIN_FLASH != 0
+            || flags & UF2_FLAG_FAMILY_ID == 0
+            || word(28) != UF2_FAMILY_ID
+            || block.payload_size > UF2_MAX_PAYLOAD
+            || block.block_no >= block.num_blocks
+        {
+            return None;
+        }
+        Some(block)
+    }
+
+    fn payload<'b>(&self, sector: &'b [u8]) -> &'b [u8] {
+        &sector[UF2_PAYLOAD_OFFSET..UF2_PAYLOAD_OFFSET + self.payload_size]
+    }
+}
+
+/// Writes the contents of sector `lba` of the drive to `sector`.
+fn read_sector(lba: u32, sector: &mut [u8]) {
+    sector.fill(0);
+    let mut put = |offset: usize, bytes: &[u8]| {
+        sector[offset..offset + bytes.len()].copy_from_slice(bytes);
+    };
+
+    match lba {
+        0 => {
+            // Boot sector with the BIOS parameter block.
+            put(0, &[0xEB, 0x3C, 0x90]);
+            put(3, b"TOCK    ");
+            put(11, &(SECTOR_SIZE as u16).to_le_bytes());
+            put(13, &[1]); // Sectors per cluster
+            put(14, &(RESERVED_SECTORS as u16).to_le_bytes());
+            put(16, &[NUM_FATS as u8]);
+            put(17, &(ROOT_ENTRIES as u16).to_le_bytes());
+            put(19, &(NUM_BLOCKS as u16).to_le_bytes());
+            put(21, &[0xF8]); // Fixed media
+            put(22, &(SECTORS_PER_FAT as u16).to_le_bytes());
+            put(24, &1u16.to_le_bytes()); // Sectors per track
+            put(26, &1u16.to_le_bytes()); // Heads
+            put(36, &[0x80]); // Drive number
+            put(38, &[0x29]); // Extended boot signature
+            put(39, &0x544F434Bu32.to_le_bytes()); // Volume serial number
+            put(43, VOLUME_LABEL);
+            put(54, b"FAT12   ");
+            put(510, &[0x55, 0xAA]);
+        }
+        lba if lba >= FAT_START
+            && lba < ROOT_START
+            && (lba - FAT_START).is_multiple_of(SECTORS_PER_FAT) =>
+        {
+            // Media descriptor, end of chain marker, and the single cluster of
+            // the info file.
+            put(0, &[0xF8, 0xFF, 0xFF, 0xFF, 0x0F]);
+        }
+        ROOT_START => {
+            put(0, VOLUME_LABEL);
+            put(11, &[0x08]); // Volume label
+            put(32, INFO_NAME);
+            put(32 + 11, &[0x01]); // Read only
+            put(32 + 26, &2u16.to_le_bytes()); // First cluster
+            put(32 + 28, &(INFO.len() as u32).to_le_bytes());
+        }
+        DATA_START => put(0, INFO.as_bytes()),
+        _ => {}
+    }
+}
+
+pub struct AppDrop<S: DynamicBinaryStore + 'static, L: DynamicProcessLoad + 'static> {
+    installer: AppInstaller<'static, S, L>,
+    client: OptionalCell<&'static dyn NonvolatileStorageClient>,
+    deferred_call: DeferredCall,
+
+    /// Buffer of the pending read or write of the client.
+    client_buffer: TakeCell<'static, [u8]>,
+    /// Whether the pending operation is a read.
+    client_read: Cell<bool>,
+    /// Length of the pending operation, or 0 if it failed. Set when the
+    /// operation is done and the client is about to be notified.
+    client_length: OptionalCell<usize>,
+
+    /// Buffer payloads are copied into to write them to flash.
+    buffer: TakeCell<'static, [u8]>,
+
+    /// The UF2 block being stored, in the client buffer.
+    block: OptionalCell<Uf2Block>,
+    /// Number of the next block of the app.
+    next_block: Cell<u32>,
+    /// Number of blocks of the app.
+    num_blocks: Cell<u32>,
+}
+
+impl<S: DynamicBinaryStore + 'static, L: DynamicProcessLoad + 'static> AppDrop<S, L> {
+    pub fn new(
+        storage_driver: &'static S,
+        load_driver: &'static L,
+        buffer: &'static mut [u8],
+    ) -> Self {
+        Self {
+            installer: AppInstaller::new(storage_driver, load_driver),
+            client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+            client_buffer: TakeCell::empty(),
+            client_read: Cell::new(false),
+            client_length: OptionalCell::empty(),
+            buffer: TakeCell::new(buffer),
+            block: OptionalCell::empty(),
+            next_block: Cell::new(0),
+            num_blocks: Cell::new(0),
+        }
+    }
+
+    /// Completes the pending operation of the client from a deferred call.
+    fn complete(&self, result: Result<(), ErrorCode>) {
+        let length = self.client_buffer.map_or(0, |buffer| buffer.len());
+        self.client_length
+            .set(if result.is_ok() { length } else { 0 });
+        self.deferred_call.set();
+    }
+
+    /// Handles a UF2 block written to the drive. The block is in the client
+    /// buffer.
+    fn receive_block(&self, block: Uf2Block) {
+        match self.installer.state() {
+            State::Idle if block.block_no == 0 => {
+                // The length of the app is in its TBF header.
+                let length = self.client_buffer.map_or(0, |sector| {
+                    let payload = block.payload(sector);
+                    if payload.len() < 8 || block.target_addr != 0 {
+                        return 0;
+                    }
+                    u32::from_le_bytes([payload[4], payload[5], payload[6], payload[7]]) as usize
+                });
+                if length == 0 {
+                    self.complete(Err(ErrorCode::INVAL));
+                    return;
+                }
+                match self.installer.setup(length) {
+                    Ok(()) => {
+                        self.next_block.set(0);
+                        self.num_blocks.set(block.num_blocks);
+                        self.block.set(block);
+                    }
+                    Err(e) => self.complete(Err(e)),
+                }
+            }
+            State::Receiving if block.block_no == self.next_block.get() => {
+                self.write_block(block);
+            }
+            State::Receiving if block.block_no < self.next_block.get() => {
+                // The host wrote a block again.
+                self.complete(Ok(()));
+            }
+            State::Receiving if block.block_no == 0 => {
+                // A new app, after an incomplete one. Start again once the
+                // incomplete one is removed.
+                self.block.set(block);
+                self.abort();
+            }
+            State::Receiving => {
+                // A block is missing.
+                self.abort();
+            }
+            // A block of an app whose first block was rejected.
+            State::Idle => self.complete(Err(ErrorCode::INVAL)),
+            _ => self.complete(Err(ErrorCode::BUSY)),
+        }
+    }
+
+    /// Writes the payload of a UF2 block to flash.
+    fn write_block(&self, block: Uf2Block) {
+        let length = cmp::min(
+            block.payload_size,
+            self.installer.length().saturating_sub(block.target_addr),
+        );
+        if length == 0 {
+            // Padding after the end of the app.
+            self.block_written();
+            return;
+        }
+
+        let result = match (self.buffer.take(), self.client_buffer.take()) {
+            (Some(buffer), Some(sector)) => {
+                buffer[..length].copy_from_slice(&block.payload(sector)[..length]);
+                self.client_buffer.replace(sector);
+                let mut chunk = SubSliceMut::new(buffer);
+                chunk.slice(..length);
+                self.installer
+                    .write(chunk, block.target_addr)
+                    .map_err(|(e, buffer)| {
+                        if let Some(buffer) = buffer {
+                            self.buffer.replace(buffer);
+                        }
+                        e
+                    })
+            }
+            (buffer, sector) => {
+                buffer.map(|buffer| self.buffer.replace(buffer));
+                sector.map(|sector| self.client_buffer.replace(sector));
+                Err(ErrorCode::NOMEM)
+            }
+        };
+        if result.is_err() {
+            self.abort();
+        }
+    }
+
+    /// Moves on to the next block after a block was stored.
+    fn block_written(&self) {
+        self.next_block.set(self.next_block.get() + 1);
+        if self.next_block.get() < self.num_blocks.get() {
+            self.complete(Ok(()));
+            return;
+        }
+
+        if let Err(e) = self.installer.finish() {
+            // Remove the app if it is shorter than its TBF header says.
+            if self.installer.abort().is_err() {
+                self.complete(Err(e));
+            }
+        }
+    }
+
+    /// Removes the app being stored. The pending write of the client fails,
+    /// unless it is the first block of a new app.
+    fn abort(&self) {
+        if let Err(e) = self.installer.abort() {
+            self.block.clear();
+            self.complete(Err(e));
+        }
+    }
+}
+
+impl<S: DynamicBinaryStore + 'static, L: DynamicProcessLoad + 'static> NonvolatileStorage<'static>
+    for AppDrop<S, L>
+{
+    fn set_client(&self, client: &'static dyn NonvolatileStorageClient) {
+        self.client.set(client);
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        if self.client_buffer.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        if !address.is_multiple_of(SECTOR_SIZE)
+            || !length.is_multiple_of(SECTOR_SIZE)
+            || length > buffer.len()
+            || address + length > NUM_BLOCKS as usize * SECTOR_SIZE
+        {
+            return Err(ErrorCode::INVAL);
+        }
+
+        for (i, sector) in buffer[..length].chunks_mut(SECTOR_SIZE).enumerate() {
+            read_sector((address / SECTOR_SIZE + i) as u32, sector);
+        }
+        self.client_buffer.replace(buffer);
+        self.client_read.set(true);
+        self.client_length.set(length);
+        self.deferred_call.set();
+        Ok(())
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        if self.client_buffer.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        if length > buffer.len() || address + length > NUM_BLOCKS as usize * SECTOR_SIZE {
+            return Err(ErrorCode::INVAL);
+        }
+
+        // Blocks of UF2 files are written one sector at a time.
+        let block = if length == SECTOR_SIZE {
+            Uf2Block::parse(&buffer[..SECTOR_SIZE])
+        } else {
+            None
+        };
+        self.client_buffer.replace(buffer);
+        self.client_read.set(false);
+        match block {
+            Some(block) => self.receive_block(block),
+            None => {
+                // Everything else is discarded.
+                self.client_length.set(length);
+                self.deferred_call.set();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S: DynamicBinaryStore + 'static, L: DynamicProcessLoad + 'static> DynamicBinaryStoreClient
+    for AppDrop<S, L>
+{
+    fn setup_done(&self, result: Result<(), ErrorCode>) {
+        match (self.installer.setup_done(result), self.block.take()) {
+            (Some(Ok(())), Some(block)) => self.write_block(block),
+            (Some(result), _) => self.complete(result.and(Err(ErrorCode::FAIL))),
+            (None, _) => {}
+        }
+    }
+
+    fn write_done(&self, result: Result<(), ErrorCode>, buffer: &'static mut [u8], length: usize) {
+        self.buffer.replace(buffer);
+        match self.installer.write_done(result, length) {
+            Some(Ok(())) => self.block_written(),
+            Some(Err(_)) => self.abort(),
+            None => {}
+        }
+    }
+
+    fn finalize_done(&self, result: Result<(), ErrorCode>) {
+        if let Some(result) = self.installer.finalize_done(result) {
+            self.complete(result);
+        }
+    }
+
+    fn abort_done(&self, result: Result<(), ErrorCode>) {
+        if self.installer.abort_done(result).is_none() {
+            return;
+        }
+        match self.block.take() {
+            Some(block) if block.block_no == 0 => self.receive_block(block),
+            _ => self.complete(Err(ErrorCode::CANCEL)),
+        }
+    }
+}
+
+impl<S: DynamicBinaryStore + 'static, L: DynamicProcessLoad + 'static> DynamicProcessLoadClient
+    for AppDrop<S, L>
+{
+    fn load_done(&self, result: Result<(), ProcessLoadError>) {
+        if let Some(result) = self.installer.load_done(result) {
+            self.complete(result);
+        }
+    }
+}
+
+impl<S: DynamicBinaryStore + 'static, L: DynamicProcessLoad + 'static> DeferredCallClient
+    for AppDrop<S, L>
+{
+    fn handle_deferred_call(&self) {
+        let Some(length) = self.client_length.take() else {
+            return;
+        };
+        let Some(buffer) = self.client_buffer.take() else {
+            return;
+        };
+        self.client.map(|client| {
+            if self.client_read.get() {
+                client.read_done(buffer, length);
+            } else {
+                client.write_done(buffer, length);
+            }
+        });
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uf2_block(flags: u32, target_addr: u32, block_no: u32, num_blocks: u32) -> [u8; 512] {
+        let mut sector = [0; 512];
+        let words = [
+            UF2_MAGIC_START0,
+            UF2_MAGIC_START1,
+            flags,
+            target_addr,
+            256,
+            block_no,
+            num_blocks,
+            UF2_FAMILY_ID,
+        ];
+        for (i, word) in words.iter().enumerate() {
+            sector[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        sector[508..].copy_from_slice(&UF2_MAGIC_END.to_le_bytes());
+        sector
+    }
+
+    #[test]
+    fn boot_sector_describes_fat12() {
+        let mut sector = [0xAA; 512];
+        read_sector(0, &mut sector);
+        let word = |i: usize| u16::from_le_bytes([sector[i], sector[i + 1]]) as u32;
+
+        assert_eq!(word(11), SECTOR_SIZE as u32);
+        assert_eq!(word(19), NUM_BLOCKS);
+        assert_eq!(&sector[510..], &[0x55, 0xAA]);
+
+        // The cluster count determines the FAT type, FAT12 has fewer than
+        // 4085 clusters.
+        let root_sectors = word(17) * 32 / word(11);
+        let data_start = word(14) + sector[16] as u32 * word(22) + root_sectors;
+        assert_eq!(data_start, DATA_START);
+        assert!(word(19) - data_start < 4085);
+    }
+
+    #[test]
+    fn info_file_is_in_root_directory() {
+        let mut sector = [0; 512];
+        read_sector(ROOT_START, &mut sector);
+        assert_eq!(&sector[32..43], INFO_NAME);
+        assert_eq!(sector[32 + 26], 2);
+
+        read_sector(DATA_START, &mut sector);
+        assert_eq!(&sector[..INFO.len()], INFO.as_bytes());
+
+        read_sector(DATA_START + 1, &mut sector);
+        assert!(sector.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn parses_tock_uf2_blocks() {
+        let block = Uf2Block::parse(&uf2_block(UF2_FLAG_FAMILY_ID, 512, 2, 3)).unwrap();
+        assert_eq!(block.target_addr, 512);
+        assert_eq!(block.payload_size, 256);
+        assert_eq!(block.block_no, 2);
+        assert_eq!(block.num_blocks, 3);
+
+        // No family ID, not for main flash, or a block past the end.
+        assert!(Uf2Block::parse(&uf2_block(0, 0, 0, 1)).is_none());
+        assert!(
+            Uf2Block::parse(&uf2_block(
+                UF2_FLAG_FAMILY_ID | UF2_FLAG_NOT_MAIN_FLASH,
+                0,
+                0,
+                1
+            ))
+            .is_none()
+        );
+        assert!(Uf2Block::parse(&uf2_block(UF2_FLAG_FAMILY_ID, 0, 1, 1)).is_none());
+        assert!(Uf2Block::parse(&[0; 512]).is_none());
+    }
+}
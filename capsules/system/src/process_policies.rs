@@ -11,6 +11,7 @@
 use kernel::process;
 use kernel::process::Process;
 use kernel::process::ProcessFaultPolicy;
+use kernel::process::ProcessGrantQuotaPolicy;
 
 /// Simply panic the entire board if a process faults.
 pub struct PanicFaultPolicy {}
@@ -116,3 +117,21 @@ impl ProcessFaultPolicy for ThresholdRestartThenPanicFaultPolicy {
         }
     }
 }
+
+/// Implementation of `ProcessGrantQuotaPolicy` that limits every process to
+/// the same number of bytes of grant memory.
+pub struct FixedGrantQuotaPolicy {
+    quota: usize,
+}
+
+impl FixedGrantQuotaPolicy {
+    pub const fn new(quota: usize) -> FixedGrantQuotaPolicy {
+        FixedGrantQuotaPolicy { quota }
+    }
+}
+
+impl ProcessGrantQuotaPolicy for FixedGrantQuotaPolicy {
+    fn quota(&self, _process: &dyn Process) -> Option<usize> {
+        Some(self.quota)
+    }
+}
@@ -226,6 +226,18 @@ impl ProcessPrinter for ProcessPrinterText {
             ));
         }
 
+        if !bww.bytes_remaining() {
+            let high_water_marks = process.debug_high_water_marks();
+            let _ = match high_water_marks.stack {
+                Some(stack) => bww.write_fmt(format_args!(" Stack High-Water Mark: {}", stack)),
+                None => bww.write_str(" Stack High-Water Mark: ?"),
+            };
+            let _ = match high_water_marks.heap {
+                Some(heap) => bww.write_fmt(format_args!("   Heap High-Water Mark: {}\r\n", heap)),
+                None => bww.write_str("   Heap High-Water Mark: ?\r\n"),
+            };
+        }
+
         if bww.bytes_remaining() {
             // The underlying writer is indicating there are still bytes
             // remaining to be sent. That means we want to return a context so
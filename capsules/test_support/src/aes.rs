@@ -0,0 +1,213 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Mock AES-128 engine computing in software.
+
+use core::cell::Cell;
+
+use aes::Aes128;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use kernel::ErrorCode;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::symmetric_encryption::{
+    self, AES, AES_BLOCK_SIZE, AES128, AES128_KEY_SIZE, AESCBC, AESCCM, AESCtr, AESECB,
+};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+use crate::leak;
+
+#[derive(Clone, Copy)]
+enum Mode {
+    Ctr,
+    Cbc { encrypting: bool },
+    Ecb { encrypting: bool },
+}
+
+/// AES-128 in CTR, CBC and ECB mode, like the hardware engines capsules such
+/// as `Aes128Gcm` build on. CCM isn't supported.
+///
+/// The result is computed when `crypt()` is called and reported from a
+/// deferred call.
+pub struct MockAes {
+    key: Cell<[u8; AES128_KEY_SIZE]>,
+    /// The counter in CTR mode, or the chaining value in CBC mode.
+    iv: Cell<[u8; AES_BLOCK_SIZE]>,
+    mode: Cell<Mode>,
+    source: TakeCell<'static, [u8]>,
+    dest: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'static dyn symmetric_encryption::Client<'static>>,
+    deferred_call: DeferredCall,
+}
+
+impl MockAes {
+    pub fn new() -> &'static Self {
+        let aes = leak(Self {
+            key: Cell::new([0; AES128_KEY_SIZE]),
+            iv: Cell::new([0; AES_BLOCK_SIZE]),
+            mode: Cell::new(Mode::Ctr),
+            source: TakeCell::empty(),
+            dest: TakeCell::empty(),
+            client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+        });
+        aes.register();
+        aes
+    }
+
+    /// Process `blocks` in place in the current mode.
+    fn process(&self, blocks: &mut [u8]) {
+        let cipher = Aes128::new(&self.key.get().into());
+        let mut iv = self.iv.get();
+
+        for block in blocks.chunks_exact_mut(AES_BLOCK_SIZE) {
+            let input: [u8; AES_BLOCK_SIZE] = (&*block).try_into().unwrap();
+            let mut output = input.into();
+            match self.mode.get() {
+                Mode::Ctr => {
+                    let mut keystream = iv.into();
+                    cipher.encrypt_block(&mut keystream);
+                    output = keystream;
+                    for (o, i) in output.iter_mut().zip(input.iter()) {
+                        *o ^= i;
+                    }
+                    iv = (u128::from_be_bytes(iv).wrapping_add(1)).to_be_bytes();
+                }
+                Mode::Cbc { encrypting: true } => {
+                    for (o, v) in output.iter_mut().zip(iv.iter()) {
+                        *o ^= v;
+                    }
+                    cipher.encrypt_block(&mut output);
+                    iv = output.into();
+                }
+                Mode::Cbc { encrypting: false } => {
+                    cipher.decrypt_block(&mut output);
+                    for (o, v) in output.iter_mut().zip(iv.iter()) {
+                        *o ^= v;
+                    }
+                    iv = input;
+                }
+                Mode::Ecb { encrypting: true } => cipher.encrypt_block(&mut output),
+                Mode::Ecb { encrypting: false } => cipher.decrypt_block(&mut output),
+            }
+            block.copy_from_slice(&output);
+        }
+
+        self.iv.set(iv);
+    }
+}
+
+impl AES<'static, AES128> for MockAes {
+    fn enable(&self) {}
+
+    fn disable(&self) {}
+
+    fn set_client(&'static self, client: &'static dyn symmetric_encryption::Client<'static>) {
+        self.client.set(client);
+    }
+
+    fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        let key = key.try_into().map_err(|_| ErrorCode::INVAL)?;
+        self.key.set(key);
+        Ok(())
+    }
+
+    fn set_iv(&self, iv: &[u8]) -> Result<(), ErrorCode> {
+        let iv = iv.try_into().map_err(|_| ErrorCode::INVAL)?;
+        self.iv.set(iv);
+        Ok(())
+    }
+
+    fn start_message(&self) {}
+
+    fn crypt(
+        &self,
+        source: Option<&'static mut [u8]>,
+        dest: &'static mut [u8],
+        start_index: usize,
+        stop_index: usize,
+    ) -> Option<(
+        Result<(), ErrorCode>,
+        Option<&'static mut [u8]>,
+        &'static mut [u8],
+    )> {
+        if self.dest.is_some() {
+            return Some((Err(ErrorCode::BUSY), source, dest));
+        }
+        let len = match stop_index.checked_sub(start_index) {
+            Some(len) if len.is_multiple_of(AES_BLOCK_SIZE) && stop_index <= dest.len() => len,
+            _ => return Some((Err(ErrorCode::INVAL), source, dest)),
+        };
+        if source.as_ref().is_some_and(|source| source.len() != len) {
+            return Some((Err(ErrorCode::INVAL), source, dest));
+        }
+
+        if let Some(source) = source {
+            dest[start_index..stop_index].copy_from_slice(source);
+            self.source.replace(source);
+        }
+        self.process(&mut dest[start_index..stop_index]);
+        self.dest.replace(dest);
+        self.deferred_call.set();
+        None
+    }
+}
+
+impl AESCtr for MockAes {
+    fn set_mode_aesctr(&self, _encrypting: bool) -> Result<(), ErrorCode> {
+        self.mode.set(Mode::Ctr);
+        Ok(())
+    }
+}
+
+impl AESCBC for MockAes {
+    fn set_mode_aescbc(&self, encrypting: bool) -> Result<(), ErrorCode> {
+        self.mode.set(Mode::Cbc { encrypting });
+        Ok(())
+    }
+}
+
+impl AESECB for MockAes {
+    fn set_mode_aesecb(&self, encrypting: bool) -> Result<(), ErrorCode> {
+        self.mode.set(Mode::Ecb { encrypting });
+        Ok(())
+    }
+}
+
+impl AESCCM<'static, AES128> for MockAes {
+    fn set_client(&'static self, _client: &'static dyn symmetric_encryption::CCMClient) {}
+
+    fn set_key(&self, _key: &[u8]) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn set_nonce(&self, _nonce: &[u8]) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        _a_off: usize,
+        _m_off: usize,
+        _m_len: usize,
+        _mic_len: usize,
+        _confidential: bool,
+        _encrypting: bool,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        Err((ErrorCode::NOSUPPORT, buf))
+    }
+}
+
+impl DeferredCallClient for MockAes {
+    fn handle_deferred_call(&self) {
+        if let Some(dest) = self.dest.take() {
+            let source = self.source.take();
+            self.client.map(|client| client.crypt_done(source, dest));
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
@@ -0,0 +1,129 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Mock alarm whose time only moves when the test says so.
+
+use core::cell::Cell;
+
+use kernel::ErrorCode;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::time::{Alarm, AlarmClient, Freq1KHz, Ticks, Ticks32, Time};
+use kernel::utilities::cells::OptionalCell;
+
+use crate::leak;
+
+/// Alarm fires in a row after which a client is assumed to re-arm forever.
+const MAX_FIRES: usize = 1000;
+
+/// A 32-bit, 1 kHz alarm.
+///
+/// Time starts at 0 and moves with [`MockAlarm::advance`], which fires the
+/// alarm if it expires. An alarm that is set in the past fires from a
+/// deferred call, like a hardware alarm would fire right after returning.
+pub struct MockAlarm<'a> {
+    now: Cell<Ticks32>,
+    reference: Cell<Ticks32>,
+    dt: Cell<Ticks32>,
+    armed: Cell<bool>,
+    client: OptionalCell<&'a dyn AlarmClient>,
+    deferred_call: DeferredCall,
+}
+
+impl MockAlarm<'static> {
+    pub fn new() -> &'static Self {
+        let alarm = leak(Self {
+            now: Cell::new(0.into()),
+            reference: Cell::new(0.into()),
+            dt: Cell::new(0.into()),
+            armed: Cell::new(false),
+            client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+        });
+        alarm.register();
+        alarm
+    }
+}
+
+impl MockAlarm<'_> {
+    /// Moves time forward by `ticks`, firing the alarm if it expires.
+    pub fn advance(&self, ticks: u32) {
+        self.set_now(self.now.get().into_u32().wrapping_add(ticks));
+    }
+
+    /// Sets the current time, firing the alarm if it expires.
+    pub fn set_now(&self, ticks: u32) {
+        self.now.set(ticks.into());
+        self.fire_expired();
+    }
+
+    fn expired(&self) -> bool {
+        let reference = self.reference.get();
+        !self
+            .now
+            .get()
+            .within_range(reference, reference.wrapping_add(self.dt.get()))
+    }
+
+    fn fire_expired(&self) {
+        for _ in 0..MAX_FIRES {
+            if !self.armed.get() || !self.expired() {
+                return;
+            }
+            self.armed.set(false);
+            self.client.map(|client| client.alarm());
+        }
+        panic!("the alarm expired {MAX_FIRES} times without time passing");
+    }
+}
+
+impl Time for MockAlarm<'_> {
+    type Frequency = Freq1KHz;
+    type Ticks = Ticks32;
+
+    fn now(&self) -> Ticks32 {
+        self.now.get()
+    }
+}
+
+impl<'a> Alarm<'a> for MockAlarm<'a> {
+    fn set_alarm_client(&self, client: &'a dyn AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn set_alarm(&self, reference: Ticks32, dt: Ticks32) {
+        self.reference.set(reference);
+        self.dt.set(dt);
+        self.armed.set(true);
+        if self.expired() {
+            self.deferred_call.set();
+        }
+    }
+
+    fn get_alarm(&self) -> Ticks32 {
+        self.reference.get().wrapping_add(self.dt.get())
+    }
+
+    fn disarm(&self) -> Result<(), ErrorCode> {
+        self.armed.set(false);
+        Ok(())
+    }
+
+    fn is_armed(&self) -> bool {
+        self.armed.get()
+    }
+
+    fn minimum_dt(&self) -> Ticks32 {
+        1.into()
+    }
+}
+
+impl DeferredCallClient for MockAlarm<'_> {
+    fn handle_deferred_call(&self) {
+        self.fire_expired();
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
@@ -0,0 +1,254 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Mock classic CAN controller that records sent frames and receives
+//! scripted ones.
+
+use core::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use kernel::ErrorCode;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::can::{
+    self, Filter, FilterParameters, Id, Receive, ReceiveClient, STANDARD_CAN_PACKET_SIZE, Transmit,
+    TransmitClient,
+};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+use crate::leak;
+
+/// One frame on the bus.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CanFrame {
+    pub id: Id,
+    pub data: Vec<u8>,
+}
+
+impl CanFrame {
+    pub fn new(id: Id, data: &[u8]) -> Self {
+        Self {
+            id,
+            data: data.to_vec(),
+        }
+    }
+}
+
+/// Number of filter banks of a [`MockCan`].
+pub const FILTER_BANKS: usize = 2;
+
+/// A CAN controller on a bus with no other traffic than what the test sends.
+///
+/// Frames passed to [`MockCan::receive`] while the capsule is receiving are
+/// delivered one per deferred call, in order. Frames received at other times
+/// are dropped, as a controller would. Transmissions complete from a
+/// deferred call.
+///
+/// The controller has [`FILTER_BANKS`] filter banks. While none is enabled it
+/// accepts every frame; otherwise it drops frames that pass no enabled bank.
+pub struct MockCan {
+    sent: RefCell<Vec<CanFrame>>,
+    next_error: Cell<Option<can::Error>>,
+    tx_buffer: TakeCell<'static, [u8; STANDARD_CAN_PACKET_SIZE]>,
+    tx_status: Cell<Result<(), can::Error>>,
+    tx_held: Cell<bool>,
+    tx_client: OptionalCell<&'static dyn TransmitClient<STANDARD_CAN_PACKET_SIZE>>,
+    incoming: RefCell<VecDeque<CanFrame>>,
+    rx_buffer: TakeCell<'static, [u8; STANDARD_CAN_PACKET_SIZE]>,
+    receiving: Cell<bool>,
+    stopping: Cell<bool>,
+    rx_client: OptionalCell<&'static dyn ReceiveClient<STANDARD_CAN_PACKET_SIZE>>,
+    filters: [Cell<Option<FilterParameters>>; FILTER_BANKS],
+    /// Banks from this one on can't be enabled.
+    rejected_banks: Cell<usize>,
+    deferred_call: DeferredCall,
+}
+
+impl MockCan {
+    pub fn new() -> &'static Self {
+        let can = leak(Self {
+            sent: RefCell::new(Vec::new()),
+            next_error: Cell::new(None),
+            tx_buffer: TakeCell::empty(),
+            tx_status: Cell::new(Ok(())),
+            tx_held: Cell::new(false),
+            tx_client: OptionalCell::empty(),
+            incoming: RefCell::new(VecDeque::new()),
+            rx_buffer: TakeCell::empty(),
+            receiving: Cell::new(false),
+            stopping: Cell::new(false),
+            rx_client: OptionalCell::empty(),
+            filters: Default::default(),
+            rejected_banks: Cell::new(FILTER_BANKS),
+            deferred_call: DeferredCall::new(),
+        });
+        can.register();
+        can
+    }
+
+    /// Returns and forgets the frames sent so far.
+    pub fn take_sent(&self) -> Vec<CanFrame> {
+        self.sent.take()
+    }
+
+    /// Queues a frame for the capsule to receive, unless the filters drop it.
+    pub fn receive(&self, frame: CanFrame) {
+        let mut filters = self.filters.iter().filter_map(Cell::get).peekable();
+        let accepted = filters.peek().is_none() || filters.any(|f| f.matches(frame.id));
+        if self.receiving.get() && accepted {
+            self.incoming.borrow_mut().push_back(frame);
+            self.deferred_call.set();
+        }
+    }
+
+    /// Whether the capsule is receiving frames.
+    pub fn is_receiving(&self) -> bool {
+        self.receiving.get()
+    }
+
+    /// Returns the identifier and mask of each enabled filter bank.
+    pub fn enabled_filters(&self) -> Vec<(Id, u32)> {
+        self.filters
+            .iter()
+            .filter_map(Cell::get)
+            .map(|filter| (filter.id, filter.mask))
+            .collect()
+    }
+
+    /// While `hold` is true, a transmission does not complete, as if the bus
+    /// were busy.
+    pub fn hold_transmissions(&self, hold: bool) {
+        self.tx_held.set(hold);
+        if !hold && self.tx_buffer.is_some() {
+            self.deferred_call.set();
+        }
+    }
+
+    /// Makes the next transmission fail with `error`.
+    pub fn fail_next(&self, error: can::Error) {
+        self.next_error.set(Some(error));
+    }
+
+    /// Makes enabling the filter banks from `first` on fail.
+    pub fn reject_filter_banks(&self, first: usize) {
+        self.rejected_banks.set(first);
+    }
+}
+
+impl Transmit<STANDARD_CAN_PACKET_SIZE> for MockCan {
+    fn set_client(&self, client: Option<&'static dyn TransmitClient<STANDARD_CAN_PACKET_SIZE>>) {
+        self.tx_client.insert(client);
+    }
+
+    fn send(
+        &self,
+        id: Id,
+        buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8; STANDARD_CAN_PACKET_SIZE])> {
+        if self.tx_buffer.is_some() {
+            return Err((ErrorCode::BUSY, buffer));
+        }
+        if len > STANDARD_CAN_PACKET_SIZE {
+            return Err((ErrorCode::SIZE, buffer));
+        }
+
+        let status = self.next_error.take().map_or(Ok(()), Err);
+        if status.is_ok() {
+            self.sent
+                .borrow_mut()
+                .push(CanFrame::new(id, &buffer[..len]));
+        }
+        self.tx_status.set(status);
+        self.tx_buffer.replace(buffer);
+        self.deferred_call.set();
+        Ok(())
+    }
+}
+
+impl Receive<STANDARD_CAN_PACKET_SIZE> for MockCan {
+    fn set_client(&self, client: Option<&'static dyn ReceiveClient<STANDARD_CAN_PACKET_SIZE>>) {
+        self.rx_client.insert(client);
+    }
+
+    fn start_receive_process(
+        &self,
+        buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; STANDARD_CAN_PACKET_SIZE])> {
+        if self.receiving.get() {
+            return Err((ErrorCode::ALREADY, buffer));
+        }
+        self.rx_buffer.replace(buffer);
+        self.receiving.set(true);
+        Ok(())
+    }
+
+    fn stop_receive(&self) -> Result<(), ErrorCode> {
+        if !self.receiving.get() {
+            return Err(ErrorCode::OFF);
+        }
+        self.receiving.set(false);
+        self.incoming.borrow_mut().clear();
+        self.stopping.set(true);
+        self.deferred_call.set();
+        Ok(())
+    }
+}
+
+impl Filter for MockCan {
+    fn enable_filter(&self, filter: FilterParameters) -> Result<(), ErrorCode> {
+        let bank = self
+            .filters
+            .get(filter.number as usize)
+            .ok_or(ErrorCode::INVAL)?;
+        if filter.number as usize >= self.rejected_banks.get() {
+            return Err(ErrorCode::FAIL);
+        }
+        bank.set(Some(filter));
+        Ok(())
+    }
+
+    fn disable_filter(&self, number: u32) -> Result<(), ErrorCode> {
+        let bank = self.filters.get(number as usize).ok_or(ErrorCode::INVAL)?;
+        bank.set(None);
+        Ok(())
+    }
+
+    fn filter_count(&self) -> usize {
+        FILTER_BANKS
+    }
+}
+
+impl DeferredCallClient for MockCan {
+    fn handle_deferred_call(&self) {
+        if !self.tx_held.get() {
+            if let Some(buffer) = self.tx_buffer.take() {
+                let status = self.tx_status.get();
+                self.tx_client
+                    .map(|client| client.transmit_complete(status, buffer));
+            }
+        }
+
+        let frame = self.incoming.borrow_mut().pop_front();
+        if self.stopping.take() {
+            if let Some(buffer) = self.rx_buffer.take() {
+                self.rx_client.map(|client| client.stopped(buffer));
+            }
+        } else if let Some(frame) = frame {
+            self.rx_buffer.map(|buffer| {
+                buffer[..frame.data.len()].copy_from_slice(&frame.data);
+                self.rx_client.map(|client| {
+                    client.message_received(frame.id, buffer, frame.data.len(), Ok(()))
+                });
+            });
+        }
+
+        if !self.incoming.borrow().is_empty() {
+            self.deferred_call.set();
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
@@ -0,0 +1,367 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Mock CAN FD controller that records sent frames, receives scripted ones
+//! and supports loopback mode.
+
+use core::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use kernel::ErrorCode;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::can::{
+    self, BitTiming, Configure, ConfigureFd, Controller, ControllerClient, FD_CAN_PACKET_SIZE, Id,
+    OperationMode, Receive, ReceiveClient, StandardBitTiming, State, Transmit, TransmitClient,
+};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+use crate::can::CanFrame;
+use crate::leak;
+
+/// Clock of a [`MockCanFd`], used to compute bit timings from bitrates.
+pub const CLOCK_RATE: u32 = 40_000_000;
+
+/// A frame sent by a [`MockCanFd`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SentFrame {
+    pub frame: CanFrame,
+    /// Whether the payload was sent at the payload bitrate.
+    pub bit_rate_switching: bool,
+}
+
+/// A CAN FD controller on a bus with no other traffic than what the test
+/// sends.
+///
+/// The controller must be configured with a bit timing before it can be
+/// enabled, and cannot be configured while it is enabled. Enabling,
+/// disabling and transmissions complete from a deferred call. In
+/// `OperationMode::Loopback` sent frames are also received. Frames passed to
+/// [`MockCanFd::receive`] and errors passed to [`MockCanFd::receive_error`]
+/// while the client is receiving are delivered one per deferred call, in
+/// order.
+pub struct MockCanFd {
+    mode: Cell<OperationMode>,
+    bit_timing: Cell<Option<BitTiming>>,
+    payload_bit_timing: Cell<Option<BitTiming>>,
+    bit_rate_switching: Cell<bool>,
+    enabled: Cell<bool>,
+    state_change: Cell<Option<bool>>,
+    controller_client: OptionalCell<&'static dyn ControllerClient>,
+    sent: RefCell<Vec<SentFrame>>,
+    next_error: Cell<Option<can::Error>>,
+    tx_buffer: TakeCell<'static, [u8; FD_CAN_PACKET_SIZE]>,
+    tx_status: Cell<Result<(), can::Error>>,
+    tx_client: OptionalCell<&'static dyn TransmitClient<FD_CAN_PACKET_SIZE>>,
+    incoming: RefCell<VecDeque<Result<CanFrame, can::Error>>>,
+    rx_buffer: TakeCell<'static, [u8; FD_CAN_PACKET_SIZE]>,
+    receiving: Cell<bool>,
+    stopping: Cell<bool>,
+    rx_client: OptionalCell<&'static dyn ReceiveClient<FD_CAN_PACKET_SIZE>>,
+    deferred_call: DeferredCall,
+}
+
+impl MockCanFd {
+    pub fn new() -> &'static Self {
+        let can = leak(Self {
+            mode: Cell::new(OperationMode::Normal),
+            bit_timing: Cell::new(None),
+            payload_bit_timing: Cell::new(None),
+            bit_rate_switching: Cell::new(false),
+            enabled: Cell::new(false),
+            state_change: Cell::new(None),
+            controller_client: OptionalCell::empty(),
+            sent: RefCell::new(Vec::new()),
+            next_error: Cell::new(None),
+            tx_buffer: TakeCell::empty(),
+            tx_status: Cell::new(Ok(())),
+            tx_client: OptionalCell::empty(),
+            incoming: RefCell::new(VecDeque::new()),
+            rx_buffer: TakeCell::empty(),
+            receiving: Cell::new(false),
+            stopping: Cell::new(false),
+            rx_client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+        });
+        can.register();
+        can
+    }
+
+    /// Returns and forgets the frames sent so far.
+    pub fn take_sent(&self) -> Vec<SentFrame> {
+        self.sent.take()
+    }
+
+    /// Queues a frame for the client to receive.
+    pub fn receive(&self, frame: CanFrame) {
+        self.queue_incoming(Ok(frame));
+    }
+
+    /// Queues a receive error for the client.
+    pub fn receive_error(&self, error: can::Error) {
+        self.queue_incoming(Err(error));
+    }
+
+    fn queue_incoming(&self, incoming: Result<CanFrame, can::Error>) {
+        if self.receiving.get() {
+            self.incoming.borrow_mut().push_back(incoming);
+            self.deferred_call.set();
+        }
+    }
+
+    /// Whether the controller is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    /// Whether the client is receiving frames.
+    pub fn is_receiving(&self) -> bool {
+        self.receiving.get()
+    }
+
+    /// Makes the next transmission fail with `error`.
+    pub fn fail_next(&self, error: can::Error) {
+        self.next_error.set(Some(error));
+    }
+
+    fn configure<T: Copy>(&self, setting: &Cell<T>, value: T) -> Result<(), ErrorCode> {
+        if self.enabled.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        setting.set(value);
+        Ok(())
+    }
+}
+
+impl Configure for MockCanFd {
+    const MIN_BIT_TIMINGS: BitTiming = BitTiming {
+        segment1: 1,
+        segment2: 1,
+        propagation: 0,
+        sync_jump_width: 1,
+        baud_rate_prescaler: 1,
+    };
+    const MAX_BIT_TIMINGS: BitTiming = BitTiming {
+        segment1: 16,
+        segment2: 8,
+        propagation: 0,
+        sync_jump_width: 4,
+        baud_rate_prescaler: 1024,
+    };
+
+    fn set_bitrate(&self, bitrate: u32) -> Result<(), ErrorCode> {
+        let bit_timing = Self::bit_timing_for_bitrate(CLOCK_RATE, bitrate)?;
+        self.configure(&self.bit_timing, Some(bit_timing))
+    }
+
+    fn set_bit_timing(&self, bit_timing: BitTiming) -> Result<(), ErrorCode> {
+        self.configure(&self.bit_timing, Some(bit_timing))
+    }
+
+    fn set_operation_mode(&self, mode: OperationMode) -> Result<(), ErrorCode> {
+        self.configure(&self.mode, mode)
+    }
+
+    fn get_bit_timing(&self) -> Result<BitTiming, ErrorCode> {
+        self.bit_timing.get().ok_or(ErrorCode::INVAL)
+    }
+
+    fn get_operation_mode(&self) -> Result<OperationMode, ErrorCode> {
+        Ok(self.mode.get())
+    }
+
+    fn set_automatic_retransmission(&self, _automatic: bool) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn set_wake_up(&self, _wake_up: bool) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn get_automatic_retransmission(&self) -> Result<bool, ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn get_wake_up(&self) -> Result<bool, ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn receive_fifo_count(&self) -> usize {
+        1
+    }
+}
+
+impl ConfigureFd for MockCanFd {
+    fn set_payload_bit_timing(&self, payload_bit_timing: BitTiming) -> Result<(), ErrorCode> {
+        self.configure(&self.payload_bit_timing, Some(payload_bit_timing))
+    }
+
+    fn get_payload_bit_timing(&self) -> Result<BitTiming, ErrorCode> {
+        self.payload_bit_timing.get().ok_or(ErrorCode::INVAL)
+    }
+
+    fn set_bit_rate_switching(&self, enabled: bool) -> Result<(), ErrorCode> {
+        self.configure(&self.bit_rate_switching, enabled)
+    }
+
+    fn get_bit_rate_switching(&self) -> bool {
+        self.bit_rate_switching.get()
+    }
+
+    fn get_frame_size() -> usize {
+        FD_CAN_PACKET_SIZE
+    }
+}
+
+impl Controller for MockCanFd {
+    fn set_client(&self, client: Option<&'static dyn ControllerClient>) {
+        self.controller_client.insert(client);
+    }
+
+    fn enable(&self) -> Result<(), ErrorCode> {
+        if self.enabled.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        if self.bit_timing.get().is_none() {
+            return Err(ErrorCode::INVAL);
+        }
+        self.state_change.set(Some(true));
+        self.deferred_call.set();
+        Ok(())
+    }
+
+    fn disable(&self) -> Result<(), ErrorCode> {
+        if !self.enabled.get() {
+            return Err(ErrorCode::OFF);
+        }
+        self.state_change.set(Some(false));
+        self.deferred_call.set();
+        Ok(())
+    }
+
+    fn get_state(&self) -> Result<State, ErrorCode> {
+        Ok(if self.enabled.get() {
+            State::Running
+        } else {
+            State::Disabled
+        })
+    }
+}
+
+impl Transmit<FD_CAN_PACKET_SIZE> for MockCanFd {
+    fn set_client(&self, client: Option<&'static dyn TransmitClient<FD_CAN_PACKET_SIZE>>) {
+        self.tx_client.insert(client);
+    }
+
+    fn send(
+        &self,
+        id: Id,
+        buffer: &'static mut [u8; FD_CAN_PACKET_SIZE],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8; FD_CAN_PACKET_SIZE])> {
+        if !self.enabled.get() {
+            return Err((ErrorCode::OFF, buffer));
+        }
+        if self.tx_buffer.is_some() {
+            return Err((ErrorCode::BUSY, buffer));
+        }
+        if len > FD_CAN_PACKET_SIZE {
+            return Err((ErrorCode::SIZE, buffer));
+        }
+
+        let status = self.next_error.take().map_or(Ok(()), Err);
+        if status.is_ok() {
+            let frame = CanFrame::new(id, &buffer[..len]);
+            if matches!(self.mode.get(), OperationMode::Loopback) {
+                self.receive(frame.clone());
+            }
+            self.sent.borrow_mut().push(SentFrame {
+                frame,
+                bit_rate_switching: self.bit_rate_switching.get(),
+            });
+        }
+        self.tx_status.set(status);
+        self.tx_buffer.replace(buffer);
+        self.deferred_call.set();
+        Ok(())
+    }
+}
+
+impl Receive<FD_CAN_PACKET_SIZE> for MockCanFd {
+    fn set_client(&self, client: Option<&'static dyn ReceiveClient<FD_CAN_PACKET_SIZE>>) {
+        self.rx_client.insert(client);
+    }
+
+    fn start_receive_process(
+        &self,
+        buffer: &'static mut [u8; FD_CAN_PACKET_SIZE],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; FD_CAN_PACKET_SIZE])> {
+        if !self.enabled.get() {
+            return Err((ErrorCode::OFF, buffer));
+        }
+        if self.receiving.get() {
+            return Err((ErrorCode::ALREADY, buffer));
+        }
+        self.rx_buffer.replace(buffer);
+        self.receiving.set(true);
+        Ok(())
+    }
+
+    fn stop_receive(&self) -> Result<(), ErrorCode> {
+        if !self.receiving.get() {
+            return Err(ErrorCode::OFF);
+        }
+        self.receiving.set(false);
+        self.incoming.borrow_mut().clear();
+        self.stopping.set(true);
+        self.deferred_call.set();
+        Ok(())
+    }
+}
+
+impl DeferredCallClient for MockCanFd {
+    fn handle_deferred_call(&self) {
+        if let Some(enable) = self.state_change.take() {
+            self.enabled.set(enable);
+            self.controller_client.map(|client| {
+                client.state_changed(self.get_state().unwrap_or(State::Disabled));
+                if enable {
+                    client.enabled(Ok(()));
+                } else {
+                    client.disabled(Ok(()));
+                }
+            });
+        }
+
+        if let Some(buffer) = self.tx_buffer.take() {
+            let status = self.tx_status.get();
+            self.tx_client
+                .map(|client| client.transmit_complete(status, buffer));
+        }
+
+        let incoming = self.incoming.borrow_mut().pop_front();
+        if self.stopping.take() {
+            if let Some(buffer) = self.rx_buffer.take() {
+                self.rx_client.map(|client| client.stopped(buffer));
+            }
+        } else if let Some(incoming) = incoming {
+            self.rx_buffer.map(|buffer| {
+                self.rx_client.map(|client| match incoming {
+                    Ok(frame) => {
+                        buffer[..frame.data.len()].copy_from_slice(&frame.data);
+                        client.message_received(frame.id, buffer, frame.data.len(), Ok(()))
+                    }
+                    Err(error) => client.message_received(Id::Standard(0), buffer, 0, Err(error)),
+                });
+            });
+        }
+
+        if !self.incoming.borrow().is_empty() {
+            self.deferred_call.set();
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
@@ -0,0 +1,348 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! A chip for running the kernel on the host.
+//!
+//! The chip has no interrupts and no memory protection. Processes do not run
+//! any code: each time the kernel switches to a process, [`HostUserspace`]
+//! replays the next system call a test queued for it.
+
+use core::cell::{Cell, RefCell};
+use core::fmt::{self, Display, Write};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use kernel::ErrorCode;
+use kernel::platform::chip::{Chip, ThreadIdProvider};
+use kernel::platform::mpu::{self, Region};
+use kernel::process::FunctionCall;
+use kernel::syscall::{ContextSwitchReason, Syscall, SyscallReturn, UserspaceKernelBoundary};
+
+/// Thread ID of the thread that owns the current [`Environment`].
+///
+/// [`Environment`]: crate::Environment
+const KERNEL_THREAD_ID: usize = 0;
+
+static NEXT_THREAD_ID: AtomicUsize = AtomicUsize::new(KERNEL_THREAD_ID + 1);
+
+std::thread_local! {
+    static THREAD_ID: Cell<usize> = Cell::new(NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed));
+}
+
+/// Thread ID provider for host tests.
+///
+/// The kernel binds its global state to the first thread that initializes it,
+/// but the test harness runs each test on a new thread. So the thread that
+/// currently owns an [`Environment`] always reports the same ID, and
+/// every other thread gets a unique one.
+///
+/// [`Environment`]: crate::Environment
+pub enum TestThreadIdProvider {}
+
+unsafe impl ThreadIdProvider for TestThreadIdProvider {
+    fn running_thread_id() -> usize {
+        THREAD_ID.with(Cell::get)
+    }
+}
+
+impl TestThreadIdProvider {
+    /// Makes the current thread the kernel thread. Only called while creating
+    /// an [`Environment`](crate::Environment).
+    pub(crate) fn claim() {
+        THREAD_ID.with(|id| id.set(KERNEL_THREAD_ID));
+    }
+
+    /// Gives the current thread a unique ID again.
+    pub(crate) fn release() {
+        THREAD_ID.with(|id| id.set(NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed)));
+    }
+}
+
+/// One step of a scripted process.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Step {
+    /// Issue this system call.
+    Syscall(Syscall),
+    /// Return to the kernel as if interrupted, without a system call.
+    Stop,
+}
+
+#[derive(Default)]
+struct ProcessScript {
+    steps: VecDeque<Step>,
+    return_value: Option<SyscallReturn>,
+    functions: VecDeque<FunctionCall>,
+}
+
+/// Userspace/kernel boundary that replays scripted system calls.
+///
+/// Processes are identified by the start of their accessible memory, which
+/// the kernel passes to every method.
+#[derive(Default)]
+pub struct HostUserspace {
+    processes: RefCell<HashMap<usize, ProcessScript>>,
+}
+
+impl HostUserspace {
+    pub(crate) fn push_step(&self, memory_start: usize, step: Step) {
+        self.with_process(memory_start, |p| p.steps.push_back(step));
+    }
+
+    pub(crate) fn has_steps(&self, memory_start: usize) -> bool {
+        self.with_process(memory_start, |p| !p.steps.is_empty())
+    }
+
+    pub(crate) fn clear_steps(&self, memory_start: usize) {
+        self.with_process(memory_start, |p| p.steps.clear());
+    }
+
+    pub(crate) fn take_return_value(&self, memory_start: usize) -> Option<SyscallReturn> {
+        self.with_process(memory_start, |p| p.return_value.take())
+    }
+
+    pub(crate) fn take_function(&self, memory_start: usize) -> Option<FunctionCall> {
+        self.with_process(memory_start, |p| p.functions.pop_front())
+    }
+
+    fn with_process<R>(&self, memory_start: usize, f: impl FnOnce(&mut ProcessScript) -> R) -> R {
+        f(self.processes.borrow_mut().entry(memory_start).or_default())
+    }
+}
+
+impl UserspaceKernelBoundary for HostUserspace {
+    type StoredState = ();
+
+    fn initial_process_app_brk_size(&self) -> usize {
+        crate::environment::APP_MEMORY_SIZE
+    }
+
+    unsafe fn initialize_process(
+        &self,
+        accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        _state: &mut Self::StoredState,
+    ) -> Result<(), ()> {
+        self.processes
+            .borrow_mut()
+            .insert(accessible_memory_start.addr(), ProcessScript::default());
+        Ok(())
+    }
+
+    unsafe fn set_syscall_return_value(
+        &self,
+        accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        _state: &mut Self::StoredState,
+        return_value: SyscallReturn,
+    ) -> Result<(), ()> {
+        self.with_process(accessible_memory_start.addr(), |p| {
+            p.return_value = Some(return_value);
+        });
+        Ok(())
+    }
+
+    unsafe fn set_process_function(
+        &self,
+        accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        _state: &mut Self::StoredState,
+        upcall: FunctionCall,
+    ) -> Result<(), ()> {
+        self.with_process(accessible_memory_start.addr(), |p| {
+            p.functions.push_back(upcall);
+        });
+        Ok(())
+    }
+
+    unsafe fn switch_to_process(
+        &self,
+        accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        _state: &mut Self::StoredState,
+    ) -> (ContextSwitchReason, Option<*const u8>) {
+        let step = self.with_process(accessible_memory_start.addr(), |p| p.steps.pop_front());
+        match step {
+            Some(Step::Syscall(syscall)) => (ContextSwitchReason::SyscallFired { syscall }, None),
+            Some(Step::Stop) | None => (ContextSwitchReason::Interrupted, None),
+        }
+    }
+
+    unsafe fn print_context(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        _state: &Self::StoredState,
+        _writer: &mut dyn Write,
+    ) {
+    }
+
+    fn store_context(
+        &self,
+        _state: &Self::StoredState,
+        _out: &mut [u8],
+    ) -> Result<usize, ErrorCode> {
+        Ok(0)
+    }
+
+    fn load_context(&self, _state: &mut Self::StoredState, _input: &[u8]) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+}
+
+/// MPU that accepts every configuration and protects nothing.
+pub struct NoMpu;
+
+/// The app memory region of a process, which `NoMpu` reports as its only
+/// region.
+#[derive(Default)]
+pub struct NoMpuConfig {
+    app_memory: Option<(*const u8, usize)>,
+}
+
+impl Display for NoMpuConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.app_memory {
+            Some((start, size)) => write!(f, " App memory: {start:p}, {size} bytes"),
+            None => write!(f, " No app memory"),
+        }
+    }
+}
+
+// `MPU` is an unsafe trait. Processes on the host never run in user mode, so
+// there is no memory to keep them from accessing.
+unsafe impl mpu::MPU for NoMpu {
+    type MpuConfig = NoMpuConfig;
+
+    fn enable_app_mpu(&self) {}
+
+    unsafe fn disable_app_mpu(&self) {}
+
+    fn number_total_regions(&self) -> usize {
+        0
+    }
+
+    fn config_region(
+        &self,
+        config: &Self::MpuConfig,
+        region_number: usize,
+    ) -> Option<(Region, mpu::Permissions)> {
+        let (start, size) = config.app_memory.filter(|_| region_number == 0)?;
+        Some((Region::new(start, size), mpu::Permissions::ReadWriteOnly))
+    }
+
+    fn new_config(&self) -> Option<Self::MpuConfig> {
+        Some(NoMpuConfig::default())
+    }
+
+    fn reset_config(&self, config: &mut Self::MpuConfig) {
+        config.app_memory = None;
+    }
+
+    fn allocate_region(
+        &self,
+        unallocated_memory_start: *const u8,
+        _unallocated_memory_size: usize,
+        min_region_size: usize,
+        _permissions: mpu::Permissions,
+        _config: &mut Self::MpuConfig,
+    ) -> Option<Region> {
+        Some(Region::new(unallocated_memory_start, min_region_size))
+    }
+
+    fn remove_memory_region(
+        &self,
+        _region: Region,
+        _config: &mut Self::MpuConfig,
+    ) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn allocate_app_memory_region(
+        &self,
+        unallocated_memory_start: *const u8,
+        unallocated_memory_size: usize,
+        min_memory_size: usize,
+        initial_app_memory_size: usize,
+        _initial_kernel_memory_size: usize,
+        _permissions: mpu::Permissions,
+        config: &mut Self::MpuConfig,
+    ) -> Option<(*const u8, usize)> {
+        // Keep the kernel's data structures in process memory aligned.
+        let padding = unallocated_memory_start.align_offset(core::mem::align_of::<u64>());
+        if padding + min_memory_size > unallocated_memory_size {
+            None
+        } else {
+            let start = unallocated_memory_start.wrapping_add(padding);
+            config.app_memory = Some((start, initial_app_memory_size));
+            Some((start, min_memory_size))
+        }
+    }
+
+    fn update_app_memory_region(
+        &self,
+        app_memory_break: *const u8,
+        kernel_memory_break: *const u8,
+        _permissions: mpu::Permissions,
+        config: &mut Self::MpuConfig,
+    ) -> Result<(), ()> {
+        if app_memory_break > kernel_memory_break {
+            return Err(());
+        }
+        if let Some((start, size)) = config.app_memory.as_mut() {
+            *size = app_memory_break.addr() - start.addr();
+        }
+        Ok(())
+    }
+
+    unsafe fn configure_mpu(&self, _config: &Self::MpuConfig) {}
+}
+
+/// A chip without peripherals or interrupts. Mock peripherals complete
+/// operations with deferred calls instead.
+pub struct HostChip {
+    mpu: NoMpu,
+    userspace: HostUserspace,
+}
+
+impl HostChip {
+    pub(crate) fn new() -> Self {
+        Self {
+            mpu: NoMpu,
+            userspace: HostUserspace::default(),
+        }
+    }
+}
+
+impl Chip for HostChip {
+    type MPU = NoMpu;
+    type ThreadIdProvider = TestThreadIdProvider;
+    type UserspaceKernelBoundary = HostUserspace;
+
+    fn init() {}
+
+    fn service_pending_interrupts(&self) {}
+
+    fn has_pending_interrupts(&self) -> bool {
+        false
+    }
+
+    fn mpu(&self) -> &Self::MPU {
+        &self.mpu
+    }
+
+    fn userspace_kernel_boundary(&self) -> &Self::UserspaceKernelBoundary {
+        &self.userspace
+    }
+
+    fn sleep(&self) {}
+
+    unsafe fn with_interrupts_disabled<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        f()
+    }
+
+    unsafe fn print_state(_this: Option<&Self>, _writer: &mut dyn Write) {}
+}
@@ -0,0 +1,647 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! A kernel with scripted processes for driving `SyscallDriver`s from tests.
+//!
+//! An [`Environment`] owns a [`Kernel`] running on a [`HostChip`]. Tests
+//! register drivers with [`Environment::add_driver`], create grants for them
+//! with [`Environment::create_grant`], and then load apps. Each [`App`] method
+//! queues one system call for its process and runs the kernel loop until the
+//! process has issued it, so system calls go through the same checks as on
+//! hardware: grant allocation, allow bounds, upcall queueing and so on.
+//!
+//! Apps do not run any code. Their flash holds a TBF header and nothing else,
+//! and upcalls are handed back to the test by [`App::yield_wait`] and
+//! [`App::yield_no_wait`] instead of being called.
+
+use core::cell::{Cell, RefCell};
+use core::ptr;
+use std::sync::{Condvar, Mutex, PoisonError};
+
+use kernel::capabilities::{
+    DeferredCallResetCapability, MainLoopCapability, MemoryAllocationCapability,
+    ProcessManagementCapability,
+};
+use kernel::deferred_call::{self, DeferredCall};
+use kernel::grant::{AllowRoSize, AllowRwSize, Grant, UpcallSize};
+use kernel::platform::chip::Chip;
+use kernel::platform::{KernelResources, SyscallDriverLookup};
+use kernel::process::{
+    self, FaultAction, FunctionCallSource, Process, ProcessArray, ProcessFaultPolicy,
+    ProcessGrantQuotaPolicy, StoppedExecutingReason,
+};
+use kernel::scheduler::{Scheduler, SchedulingDecision};
+use kernel::syscall::{Syscall, SyscallDriver, SyscallReturn, YieldVariant};
+use kernel::utilities::capability_ptr::CapabilityPtr;
+use kernel::utilities::machine_register::MachineRegister;
+use kernel::{Kernel, ProcessId};
+
+use crate::chip::{HostChip, HostUserspace, Step, TestThreadIdProvider};
+use crate::leak;
+
+/// Size of the memory each app can access, which holds its [`AppBuffer`]s.
+pub const APP_MEMORY_SIZE: usize = 8 * 1024;
+
+/// Memory each app requests in its TBF header. Everything past
+/// [`APP_MEMORY_SIZE`] is left for grants.
+const APP_RAM_SIZE: usize = 32 * 1024;
+
+/// Memory set aside for each app, which also holds the kernel's per-process
+/// data structures.
+const APP_MEMORY_REGION_SIZE: usize = 64 * 1024;
+
+/// Size of the (empty) code section of each app.
+const APP_CODE_SIZE: usize = 64;
+
+const NUM_PROCS: usize = 4;
+
+/// Kernel loop iterations after which the kernel is assumed to be stuck.
+const MAX_KERNEL_LOOPS: usize = 10_000;
+
+/// Whether an [`Environment`] exists. Serializes the tests that use one.
+static IN_USE: Mutex<bool> = Mutex::new(false);
+static RELEASED: Condvar = Condvar::new();
+
+struct HarnessCapability;
+unsafe impl MainLoopCapability for HarnessCapability {}
+unsafe impl MemoryAllocationCapability for HarnessCapability {}
+unsafe impl ProcessManagementCapability for HarnessCapability {}
+unsafe impl DeferredCallResetCapability for HarnessCapability {}
+
+/// A process fault is always a bug in the test or the capsule under test.
+struct PanicFaultPolicy;
+
+impl ProcessFaultPolicy for PanicFaultPolicy {
+    fn action(&self, _process: &dyn Process) -> FaultAction {
+        FaultAction::Panic
+    }
+}
+
+/// Runs processes that have system calls queued, in the order they were
+/// loaded.
+struct HostScheduler {
+    kernel: &'static Kernel,
+    userspace: &'static HostUserspace,
+}
+
+impl HostScheduler {
+    fn runnable(&self) -> Option<ProcessId> {
+        self.kernel
+            .process_iter_capability(&HarnessCapability)
+            .find(|process| {
+                process.ready() && self.userspace.has_steps(process.get_addresses().sram_start)
+            })
+            .map(|process| process.processid())
+    }
+}
+
+impl Scheduler<HostChip> for HostScheduler {
+    fn next(&self) -> SchedulingDecision {
+        self.runnable()
+            .map_or(SchedulingDecision::TrySleep, |processid| {
+                SchedulingDecision::RunProcess((processid, None))
+            })
+    }
+
+    fn result(&self, _result: StoppedExecutingReason, _execution_time_us: Option<u32>) {}
+
+    fn continue_process(&self, processid: ProcessId, _chip: &HostChip) -> bool {
+        !DeferredCall::has_tasks()
+            && self.kernel.process_map_or_external(
+                false,
+                processid,
+                |process| self.userspace.has_steps(process.get_addresses().sram_start),
+                &HarnessCapability,
+            )
+    }
+}
+
+struct HostResources {
+    drivers: RefCell<Vec<(usize, &'static dyn SyscallDriver)>>,
+    scheduler: HostScheduler,
+}
+
+impl SyscallDriverLookup for HostResources {
+    fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
+    where
+        F: FnOnce(Option<&dyn SyscallDriver>) -> R,
+    {
+        let driver = self
+            .drivers
+            .borrow()
+            .iter()
+            .find(|(num, _)| *num == driver_num)
+            .map(|(_, driver)| *driver);
+        f(driver)
+    }
+}
+
+impl KernelResources<HostChip> for HostResources {
+    type SyscallDriverLookup = Self;
+    type SyscallFilter = ();
+    type ProcessFault = ();
+    type ContextSwitchCallback = ();
+    type Scheduler = HostScheduler;
+    type SchedulerTimer = ();
+    type WatchDog = ();
+
+    fn syscall_driver_lookup(&self) -> &Self::SyscallDriverLookup {
+        self
+    }
+
+    fn syscall_filter(&self) -> &Self::SyscallFilter {
+        &()
+    }
+
+    fn process_fault(&self) -> &Self::ProcessFault {
+        &()
+    }
+
+    fn context_switch_callback(&self) -> &Self::ContextSwitchCallback {
+        &()
+    }
+
+    fn scheduler(&self) -> &Self::Scheduler {
+        &self.scheduler
+    }
+
+    fn scheduler_timer(&self) -> &Self::SchedulerTimer {
+        &()
+    }
+
+    fn watchdog(&self) -> &Self::WatchDog {
+        &()
+    }
+}
+
+/// A kernel for one test.
+///
+/// Only one `Environment` exists at a time: `new()` blocks until any other
+/// test's environment is dropped. Mocks that use deferred calls must be
+/// created after the environment.
+pub struct Environment {
+    kernel: &'static Kernel,
+    chip: &'static HostChip,
+    resources: &'static HostResources,
+    grant_quota_policy: Cell<Option<&'static dyn ProcessGrantQuotaPolicy>>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        // A panicking test still drops its environment, so the flag stays
+        // valid even if the mutex was poisoned.
+        let in_use = IN_USE.lock().unwrap_or_else(PoisonError::into_inner);
+        *RELEASED
+            .wait_while(in_use, |in_use| *in_use)
+            .unwrap_or_else(PoisonError::into_inner) = true;
+
+        TestThreadIdProvider::claim();
+        deferred_call::initialize_deferred_call_state::<TestThreadIdProvider>();
+        DeferredCall::reset(&HarnessCapability);
+
+        let processes: &'static ProcessArray<NUM_PROCS> = leak(ProcessArray::new());
+        let kernel: &'static Kernel = leak(Kernel::new(processes.as_slice()));
+        let chip: &'static HostChip = leak(HostChip::new());
+        let resources = leak(HostResources {
+            drivers: RefCell::new(Vec::new()),
+            scheduler: HostScheduler {
+                kernel,
+                userspace: chip.userspace_kernel_boundary(),
+            },
+        });
+
+        Self {
+            kernel,
+            chip,
+            resources,
+            grant_quota_policy: Cell::new(None),
+        }
+    }
+
+    pub fn kernel(&self) -> &'static Kernel {
+        self.kernel
+    }
+
+    /// The chip processes run on, for loading processes outside of
+    /// [`Environment::load_app`].
+    pub fn chip(&self) -> &'static HostChip {
+        self.chip
+    }
+
+    /// Creates a grant for the driver with number `driver_num`.
+    ///
+    /// All grants must be created before the first app is loaded.
+    pub fn create_grant<
+        T: Default,
+        Upcalls: UpcallSize,
+        AllowROs: AllowRoSize,
+        AllowRWs: AllowRwSize,
+    >(
+        &self,
+        driver_num: usize,
+    ) -> Grant<T, Upcalls, AllowROs, AllowRWs> {
+        self.kernel.create_grant(driver_num, &HarnessCapability)
+    }
+
+    /// Makes `driver` handle system calls for `driver_num`.
+    pub fn add_driver(&self, driver_num: usize, driver: &'static dyn SyscallDriver) {
+        self.resources
+            .drivers
+            .borrow_mut()
+            .push((driver_num, driver));
+    }
+
+    /// Limits the grant memory of apps loaded afterwards with `policy`.
+    pub fn set_grant_quota_policy(&self, policy: &'static dyn ProcessGrantQuotaPolicy) {
+        self.grant_quota_policy.set(Some(policy));
+    }
+
+    /// Loads an app called `name` and runs it up to its first system call.
+    pub fn load_app(&self, name: &str) -> App<'_> {
+        let flash: &'static [u8] = tbf(name).leak();
+        let memory = crate::leak_buffer(APP_MEMORY_REGION_SIZE);
+        let memory_region = memory.as_mut_ptr();
+
+        // Errors for individual apps are only logged, so look for the process
+        // instead of relying on the return value.
+        let _ = process::load_processes_with_grant_quota(
+            self.kernel,
+            self.chip,
+            flash,
+            memory,
+            &PanicFaultPolicy,
+            self.grant_quota_policy.get(),
+            &HarnessCapability,
+        );
+        let process = self
+            .kernel
+            .process_iter_capability(&HarnessCapability)
+            .find(|process| process.get_addresses().flash_start == flash.as_ptr().addr())
+            .unwrap_or_else(|| panic!("app {name} did not load"));
+        self.app(process.processid(), memory_region)
+    }
+
+    /// Takes control of a process that was loaded some other way, for
+    /// example by a process loader the test set up, and runs it up to its
+    /// first system call. `memory_region` is the start of the memory the
+    /// process was loaded into.
+    pub fn app(&self, processid: ProcessId, memory_region: *mut u8) -> App<'_> {
+        let process = self
+            .kernel
+            .process_iter_capability(&HarnessCapability)
+            .find(|process| process.processid() == processid)
+            .expect("the process does not exist");
+        let addresses = process.get_addresses();
+
+        let app = App {
+            env: self,
+            processid,
+            memory_start: addresses.sram_start,
+            memory: memory_region.wrapping_add(addresses.sram_start - memory_region.addr()),
+            memory_len: addresses.sram_app_brk - addresses.sram_start,
+            entry_point: addresses.flash_non_protected_start,
+            next_buffer: Cell::new(0),
+        };
+
+        // Let the process call and return from its init function.
+        self.userspace().push_step(app.memory_start, Step::Stop);
+        assert!(
+            self.run_until(|| !self.userspace().has_steps(app.memory_start)),
+            "{} did not start",
+            process.get_process_name(),
+        );
+        let _ = self.userspace().take_function(app.memory_start);
+
+        app
+    }
+
+    /// Runs the kernel until there are no deferred calls left and no app has
+    /// a system call queued.
+    pub fn run(&self) {
+        self.run_until(|| false);
+    }
+
+    /// Runs the kernel until `done` returns true, or until it has nothing left
+    /// to do. Returns whether `done` returned true.
+    fn run_until(&self, done: impl Fn() -> bool) -> bool {
+        for _ in 0..MAX_KERNEL_LOOPS {
+            if done() {
+                return true;
+            }
+            if !DeferredCall::has_tasks() && self.resources.scheduler.runnable().is_none() {
+                return false;
+            }
+            self.kernel.kernel_loop_operation::<_, _, 0>(
+                self.resources,
+                self.chip,
+                None,
+                true,
+                &HarnessCapability,
+            );
+        }
+        panic!("the kernel is still busy after {MAX_KERNEL_LOOPS} loop iterations");
+    }
+
+    fn userspace(&self) -> &HostUserspace {
+        self.chip.userspace_kernel_boundary()
+    }
+}
+
+impl Drop for Environment {
+    fn drop(&mut self) {
+        TestThreadIdProvider::release();
+        *IN_USE.lock().unwrap_or_else(PoisonError::into_inner) = false;
+        RELEASED.notify_one();
+    }
+}
+
+/// Builds a TBF with Main, Package Name and Kernel Version headers and an
+/// empty code section.
+///
+/// These are the apps [`Environment::load_app`] loads. Tests that load apps
+/// some other way can put them in their flash.
+pub fn tbf(name: &str) -> Vec<u8> {
+    let name_size = name.len().next_multiple_of(4);
+    let header_size = 16 + (4 + 12) + (4 + 4) + (4 + name_size);
+    let total_size = header_size + APP_CODE_SIZE;
+
+    let mut tbf = Vec::with_capacity(total_size);
+    // Base header: version, header size, total size, flags (enabled) and a
+    // checksum filled in below.
+    tbf.extend_from_slice(&2u16.to_le_bytes());
+    tbf.extend_from_slice(&(header_size as u16).to_le_bytes());
+    tbf.extend_from_slice(&(total_size as u32).to_le_bytes());
+    tbf.extend_from_slice(&1u32.to_le_bytes());
+    tbf.extend_from_slice(&0u32.to_le_bytes());
+    // Main: init function offset, protected trailer size and minimum RAM.
+    tbf.extend_from_slice(&1u16.to_le_bytes());
+    tbf.extend_from_slice(&12u16.to_le_bytes());
+    tbf.extend_from_slice(&0u32.to_le_bytes());
+    tbf.extend_from_slice(&0u32.to_le_bytes());
+    tbf.extend_from_slice(&(APP_RAM_SIZE as u32).to_le_bytes());
+    // Kernel version.
+    tbf.extend_from_slice(&8u16.to_le_bytes());
+    tbf.extend_from_slice(&4u16.to_le_bytes());
+    tbf.extend_from_slice(&kernel::KERNEL_MAJOR_VERSION.to_le_bytes());
+    tbf.extend_from_slice(&kernel::KERNEL_MINOR_VERSION.to_le_bytes());
+    // Package name.
+    tbf.extend_from_slice(&3u16.to_le_bytes());
+    tbf.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    tbf.extend_from_slice(name.as_bytes());
+    tbf.resize(header_size, 0);
+
+    let checksum = tbf
+        .chunks_exact(4)
+        .enumerate()
+        .filter(|(i, _)| *i != 3)
+        .fold(0, |checksum, (_, word)| {
+            checksum ^ u32::from_le_bytes([word[0], word[1], word[2], word[3]])
+        });
+    tbf[12..16].copy_from_slice(&checksum.to_le_bytes());
+
+    tbf.resize(total_size, 0);
+    tbf
+}
+
+/// An upcall the kernel delivered to an app.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Upcall {
+    pub driver_number: usize,
+    pub subdriver_number: usize,
+    pub arguments: [usize; 3],
+}
+
+/// A buffer in an app's memory, to share with a capsule through `allow`.
+#[derive(Clone, Copy, Debug)]
+pub struct AppBuffer {
+    address: *mut u8,
+    len: usize,
+}
+
+impl AppBuffer {
+    /// The buffer to allow to revoke a previous allow.
+    pub const NULL: AppBuffer = AppBuffer {
+        address: ptr::null_mut(),
+        len: 0,
+    };
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+
+/// A process that issues the system calls the test asks for.
+pub struct App<'a> {
+    env: &'a Environment,
+    processid: ProcessId,
+    memory_start: usize,
+    memory: *mut u8,
+    memory_len: usize,
+    /// Subscribed upcalls point here, but are never called.
+    entry_point: usize,
+    next_buffer: Cell<usize>,
+}
+
+impl App<'_> {
+    pub fn processid(&self) -> ProcessId {
+        self.processid
+    }
+
+    pub fn command(
+        &self,
+        driver_number: usize,
+        subdriver_number: usize,
+        arg0: usize,
+        arg1: usize,
+    ) -> SyscallReturn {
+        self.syscall(Syscall::Command {
+            driver_number,
+            subdriver_number,
+            arg0,
+            arg1,
+        })
+    }
+
+    /// Calls a command whose driver stops the process, and returns the
+    /// return value the process gets once it is resumed.
+    pub fn command_that_stops(
+        &self,
+        driver_number: usize,
+        subdriver_number: usize,
+        arg0: usize,
+        arg1: usize,
+    ) -> SyscallReturn {
+        assert!(
+            !self.run(Syscall::Command {
+                driver_number,
+                subdriver_number,
+                arg0,
+                arg1,
+            }),
+            "the process was not stopped"
+        );
+        self.env
+            .userspace()
+            .take_return_value(self.memory_start)
+            .expect("the kernel did not return from the system call")
+    }
+
+    /// Runs the kernel until the process, stopped by a command from
+    /// [`App::command_that_stops`], has run again after being resumed.
+    pub fn wait_until_resumed(&self) {
+        let userspace = self.env.userspace();
+        assert!(
+            self.env
+                .run_until(|| !userspace.has_steps(self.memory_start)),
+            "the process was not resumed"
+        );
+    }
+
+    /// Subscribes to upcall `subdriver_number` of `driver_number`.
+    pub fn subscribe(&self, driver_number: usize, subdriver_number: usize) -> SyscallReturn {
+        self.subscribe_to(driver_number, subdriver_number, self.entry_point)
+    }
+
+    /// Replaces the upcall with the null upcall.
+    pub fn unsubscribe(&self, driver_number: usize, subdriver_number: usize) -> SyscallReturn {
+        self.subscribe_to(driver_number, subdriver_number, 0)
+    }
+
+    fn subscribe_to(
+        &self,
+        driver_number: usize,
+        subdriver_number: usize,
+        upcall: usize,
+    ) -> SyscallReturn {
+        self.syscall(Syscall::Subscribe {
+            driver_number,
+            subdriver_number,
+            upcall_ptr: CapabilityPtr::from(upcall),
+            appdata: MachineRegister::from(0),
+        })
+    }
+
+    pub fn allow_readwrite(
+        &self,
+        driver_number: usize,
+        subdriver_number: usize,
+        buffer: AppBuffer,
+    ) -> SyscallReturn {
+        self.syscall(Syscall::ReadWriteAllow {
+            driver_number,
+            subdriver_number,
+            allow_address: buffer.address,
+            allow_size: buffer.len,
+        })
+    }
+
+    pub fn allow_readonly(
+        &self,
+        driver_number: usize,
+        subdriver_number: usize,
+        buffer: AppBuffer,
+    ) -> SyscallReturn {
+        self.syscall(Syscall::ReadOnlyAllow {
+            driver_number,
+            subdriver_number,
+            allow_address: buffer.address,
+            allow_size: buffer.len,
+        })
+    }
+
+    /// Yields until an upcall arrives and returns it.
+    ///
+    /// Panics if the kernel runs out of work before any upcall is scheduled,
+    /// as the app would wait forever. Mocks that complete operations only
+    /// when the test says so (like advancing an alarm) have to be driven
+    /// before calling this.
+    pub fn yield_wait(&self) -> Upcall {
+        if !self.run(Syscall::Yield {
+            yield_type: YieldVariant::Wait,
+        }) {
+            self.env.userspace().clear_steps(self.memory_start);
+            panic!("yield-wait would block forever: no upcall was scheduled");
+        }
+        self.take_upcall()
+            .expect("the process resumed without an upcall")
+    }
+
+    /// Runs the next pending upcall, if there is one.
+    pub fn yield_no_wait(&self) -> Option<Upcall> {
+        self.run(Syscall::Yield {
+            yield_type: YieldVariant::NoWait {
+                ptr: ptr::null_mut(),
+            },
+        });
+        self.take_upcall()
+    }
+
+    /// Allocates a zeroed buffer of `len` bytes in the app's memory.
+    pub fn buffer(&self, len: usize) -> AppBuffer {
+        let offset = self.next_buffer.get();
+        assert!(
+            offset + len <= self.memory_len,
+            "app buffers use more than {APP_MEMORY_SIZE} bytes",
+        );
+        self.next_buffer.set((offset + len).next_multiple_of(4));
+
+        let buffer = AppBuffer {
+            address: self.memory.wrapping_add(offset),
+            len,
+        };
+        self.write(buffer, &vec![0; len]);
+        buffer
+    }
+
+    /// Returns the contents of `buffer`.
+    pub fn read(&self, buffer: AppBuffer) -> Vec<u8> {
+        // # Safety
+        //
+        // The buffer is in app memory, which the kernel does not hold
+        // references to between system calls.
+        unsafe { core::slice::from_raw_parts(buffer.address, buffer.len) }.to_vec()
+    }
+
+    /// Copies `data` to the start of `buffer`.
+    pub fn write(&self, buffer: AppBuffer, data: &[u8]) {
+        assert!(data.len() <= buffer.len, "data does not fit in the buffer");
+        // # Safety
+        //
+        // See `read()`.
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), buffer.address, data.len()) };
+    }
+
+    fn syscall(&self, syscall: Syscall) -> SyscallReturn {
+        assert!(self.run(syscall), "the kernel did not run the process");
+        self.env
+            .userspace()
+            .take_return_value(self.memory_start)
+            .expect("the kernel did not return from the system call")
+    }
+
+    /// Queues `syscall` and runs the kernel until the process has returned
+    /// from it. Returns false if the process is still blocked when the kernel
+    /// runs out of work.
+    fn run(&self, syscall: Syscall) -> bool {
+        let userspace = self.env.userspace();
+        userspace.push_step(self.memory_start, Step::Syscall(syscall));
+        userspace.push_step(self.memory_start, Step::Stop);
+        self.env
+            .run_until(|| !userspace.has_steps(self.memory_start))
+    }
+
+    fn take_upcall(&self) -> Option<Upcall> {
+        self.env
+            .userspace()
+            .take_function(self.memory_start)
+            .map(|function| match function.source {
+                FunctionCallSource::Driver(upcall_id) => Upcall {
+                    driver_number: upcall_id.driver_num,
+                    subdriver_number: upcall_id.subscribe_num,
+                    arguments: [function.argument0, function.argument1, function.argument2],
+                },
+                FunctionCallSource::Kernel => panic!("the kernel restarted the process"),
+            })
+    }
+}
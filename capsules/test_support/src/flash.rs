@@ -0,0 +1,179 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Mock page-based flash backed by host memory.
+
+use core::cell::{Cell, RefCell};
+
+use kernel::ErrorCode;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::flash::{self, Flash, HasClient};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+use crate::leak;
+
+pub const PAGE_SIZE: usize = 512;
+
+/// A page of [`MockFlash`].
+pub struct MockPage(pub [u8; PAGE_SIZE]);
+
+impl Default for MockPage {
+    fn default() -> Self {
+        Self([0; PAGE_SIZE])
+    }
+}
+
+impl AsMut<[u8]> for MockPage {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Operation {
+    Read,
+    Write,
+    Erase,
+}
+
+/// Flash with [`PAGE_SIZE`] byte pages that start out erased (all `0xFF`).
+///
+/// Writing a page replaces its contents, like chip drivers that erase as part
+/// of `write_page()`. Operations complete from a deferred call.
+pub struct MockFlash {
+    pages: RefCell<Vec<[u8; PAGE_SIZE]>>,
+    operation: Cell<Option<Operation>>,
+    buffer: TakeCell<'static, MockPage>,
+    next_error: Cell<Option<flash::Error>>,
+    error: Cell<Option<flash::Error>>,
+    client: OptionalCell<&'static dyn flash::Client<MockFlash>>,
+    deferred_call: DeferredCall,
+}
+
+impl MockFlash {
+    pub fn new(num_pages: usize) -> &'static Self {
+        let flash = leak(Self {
+            pages: RefCell::new(vec![[0xFF; PAGE_SIZE]; num_pages]),
+            operation: Cell::new(None),
+            buffer: TakeCell::empty(),
+            next_error: Cell::new(None),
+            error: Cell::new(None),
+            client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+        });
+        flash.register();
+        flash
+    }
+
+    /// Returns `len` bytes of flash starting at byte `address`.
+    pub fn contents(&self, address: usize, len: usize) -> Vec<u8> {
+        self.pages.borrow().as_flattened()[address..address + len].to_vec()
+    }
+
+    /// Overwrites flash starting at byte `address`, bypassing flash
+    /// semantics.
+    pub fn set_contents(&self, address: usize, data: &[u8]) {
+        self.pages.borrow_mut().as_flattened_mut()[address..address + data.len()]
+            .copy_from_slice(data);
+    }
+
+    /// Makes the next operation complete with `error`. The flash is not
+    /// changed.
+    pub fn fail_next(&self, error: flash::Error) {
+        self.next_error.set(Some(error));
+    }
+
+    fn start(&self, page_number: usize, operation: Operation) -> Result<(), ErrorCode> {
+        if self.operation.get().is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        if page_number >= self.pages.borrow().len() {
+            return Err(ErrorCode::INVAL);
+        }
+        self.operation.set(Some(operation));
+        self.error.set(self.next_error.take());
+        self.deferred_call.set();
+        Ok(())
+    }
+
+    fn succeeds(&self) -> bool {
+        self.error.get().is_none()
+    }
+}
+
+impl<C: flash::Client<Self>> HasClient<'static, C> for MockFlash {
+    fn set_client(&self, client: &'static C) {
+        self.client.set(client);
+    }
+}
+
+impl Flash for MockFlash {
+    type Page = MockPage;
+
+    fn read_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ErrorCode, &'static mut Self::Page)> {
+        if let Err(error) = self.start(page_number, Operation::Read) {
+            return Err((error, buf));
+        }
+        if self.succeeds() {
+            buf.0 = self.pages.borrow()[page_number];
+        }
+        self.buffer.replace(buf);
+        Ok(())
+    }
+
+    fn write_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ErrorCode, &'static mut Self::Page)> {
+        if let Err(error) = self.start(page_number, Operation::Write) {
+            return Err((error, buf));
+        }
+        if self.succeeds() {
+            self.pages.borrow_mut()[page_number] = buf.0;
+        }
+        self.buffer.replace(buf);
+        Ok(())
+    }
+
+    fn erase_page(&self, page_number: usize) -> Result<(), ErrorCode> {
+        self.start(page_number, Operation::Erase)?;
+        if self.succeeds() {
+            self.pages.borrow_mut()[page_number] = [0xFF; PAGE_SIZE];
+        }
+        Ok(())
+    }
+}
+
+impl DeferredCallClient for MockFlash {
+    fn handle_deferred_call(&self) {
+        let result = self.error.take().map_or(Ok(()), Err);
+        match self.operation.take() {
+            Some(Operation::Read) => {
+                if let Some(buffer) = self.buffer.take() {
+                    self.client
+                        .map(|client| client.read_complete(buffer, result));
+                }
+            }
+            Some(Operation::Write) => {
+                if let Some(buffer) = self.buffer.take() {
+                    self.client
+                        .map(|client| client.write_complete(buffer, result));
+                }
+            }
+            Some(Operation::Erase) => {
+                self.client.map(|client| client.erase_complete(result));
+            }
+            None => {}
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
@@ -0,0 +1,180 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Mock GPIO pin whose input level is set by the test.
+
+use core::cell::Cell;
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::gpio::{
+    Client, Configuration, Configure, FloatingState, Input, Interrupt, InterruptEdge, Output,
+};
+use kernel::utilities::cells::OptionalCell;
+
+use crate::leak;
+
+/// A GPIO pin.
+///
+/// The test drives the input level with [`MockPin::set_input`]. An edge that
+/// matches the enabled interrupt is delivered to the client from a deferred
+/// call, as if the chip serviced the interrupt.
+pub struct MockPin<'a> {
+    configuration: Cell<Configuration>,
+    floating_state: Cell<FloatingState>,
+    output: Cell<bool>,
+    input: Cell<bool>,
+    interrupt_edge: Cell<Option<InterruptEdge>>,
+    pending: Cell<bool>,
+    client: OptionalCell<&'a dyn Client>,
+    deferred_call: DeferredCall,
+}
+
+impl MockPin<'static> {
+    pub fn new() -> &'static Self {
+        let pin = leak(Self {
+            configuration: Cell::new(Configuration::LowPower),
+            floating_state: Cell::new(FloatingState::PullNone),
+            output: Cell::new(false),
+            input: Cell::new(false),
+            interrupt_edge: Cell::new(None),
+            pending: Cell::new(false),
+            client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+        });
+        pin.register();
+        pin
+    }
+}
+
+impl MockPin<'_> {
+    /// The level the pin drives when it is an output.
+    pub fn output_level(&self) -> bool {
+        self.output.get()
+    }
+
+    /// Sets the level on the pin, raising an interrupt for a matching edge.
+    pub fn set_input(&self, level: bool) {
+        let rising = !self.input.get() && level;
+        let falling = self.input.get() && !level;
+        self.input.set(level);
+
+        let fire = match self.interrupt_edge.get() {
+            Some(InterruptEdge::RisingEdge) => rising,
+            Some(InterruptEdge::FallingEdge) => falling,
+            Some(InterruptEdge::EitherEdge) => rising || falling,
+            None => false,
+        };
+        if fire {
+            self.pending.set(true);
+            self.deferred_call.set();
+        }
+    }
+}
+
+impl Configure for MockPin<'_> {
+    fn configuration(&self) -> Configuration {
+        self.configuration.get()
+    }
+
+    fn make_output(&self) -> Configuration {
+        self.configuration.set(match self.configuration.get() {
+            Configuration::Input | Configuration::InputOutput => Configuration::InputOutput,
+            _ => Configuration::Output,
+        });
+        self.configuration.get()
+    }
+
+    fn disable_output(&self) -> Configuration {
+        self.configuration.set(match self.configuration.get() {
+            Configuration::InputOutput => Configuration::Input,
+            Configuration::Output => Configuration::LowPower,
+            other => other,
+        });
+        self.configuration.get()
+    }
+
+    fn make_input(&self) -> Configuration {
+        self.configuration.set(match self.configuration.get() {
+            Configuration::Output | Configuration::InputOutput => Configuration::InputOutput,
+            _ => Configuration::Input,
+        });
+        self.configuration.get()
+    }
+
+    fn disable_input(&self) -> Configuration {
+        self.configuration.set(match self.configuration.get() {
+            Configuration::InputOutput => Configuration::Output,
+            Configuration::Input => Configuration::LowPower,
+            other => other,
+        });
+        self.configuration.get()
+    }
+
+    fn deactivate_to_low_power(&self) {
+        self.configuration.set(Configuration::LowPower);
+    }
+
+    fn set_floating_state(&self, state: FloatingState) {
+        self.floating_state.set(state);
+    }
+
+    fn floating_state(&self) -> FloatingState {
+        self.floating_state.get()
+    }
+}
+
+impl Output for MockPin<'_> {
+    fn set(&self) {
+        self.output.set(true);
+    }
+
+    fn clear(&self) {
+        self.output.set(false);
+    }
+
+    fn toggle(&self) -> bool {
+        self.output.set(!self.output.get());
+        self.output.get()
+    }
+}
+
+impl Input for MockPin<'_> {
+    fn read(&self) -> bool {
+        match self.configuration.get() {
+            Configuration::Output => self.output.get(),
+            _ => self.input.get(),
+        }
+    }
+}
+
+impl<'a> Interrupt<'a> for MockPin<'a> {
+    fn set_client(&self, client: &'a dyn Client) {
+        self.client.set(client);
+    }
+
+    fn enable_interrupts(&self, mode: InterruptEdge) {
+        self.interrupt_edge.set(Some(mode));
+    }
+
+    fn disable_interrupts(&self) {
+        self.interrupt_edge.set(None);
+        self.pending.set(false);
+    }
+
+    fn is_pending(&self) -> bool {
+        self.pending.get()
+    }
+}
+
+impl DeferredCallClient for MockPin<'_> {
+    fn handle_deferred_call(&self) {
+        if self.pending.take() {
+            self.client.map(|client| client.fired());
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
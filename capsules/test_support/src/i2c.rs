@@ -0,0 +1,184 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Mock I2C controller with scripted read data.
+
+use core::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::i2c::{Error, I2CHwMasterClient, I2CMaster};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+use crate::leak;
+
+/// One transaction a capsule started on the bus.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct I2CTransaction {
+    /// 7-bit address of the target.
+    pub address: u8,
+    /// Bytes written to the target before any read.
+    pub written: Vec<u8>,
+    /// Number of bytes read from the target.
+    pub read_len: usize,
+}
+
+/// An I2C controller that records transactions.
+///
+/// Transactions that read take their data from the responses queued with
+/// [`MockI2C::respond`], in order. A read without a queued response fails
+/// with `AddressNak`, as if no target answered. All transactions complete
+/// from a deferred call.
+pub struct MockI2C<'a> {
+    enabled: Cell<bool>,
+    transactions: RefCell<Vec<I2CTransaction>>,
+    responses: RefCell<VecDeque<Result<Vec<u8>, Error>>>,
+    next_error: Cell<Option<Error>>,
+    buffer: TakeCell<'static, [u8]>,
+    status: Cell<Result<(), Error>>,
+    client: OptionalCell<&'a dyn I2CHwMasterClient>,
+    deferred_call: DeferredCall,
+}
+
+impl MockI2C<'static> {
+    pub fn new() -> &'static Self {
+        let i2c = leak(Self {
+            enabled: Cell::new(false),
+            transactions: RefCell::new(Vec::new()),
+            responses: RefCell::new(VecDeque::new()),
+            next_error: Cell::new(None),
+            buffer: TakeCell::empty(),
+            status: Cell::new(Ok(())),
+            client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+        });
+        i2c.register();
+        i2c
+    }
+}
+
+impl MockI2C<'_> {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    /// Queues the data for the next transaction that reads.
+    pub fn respond(&self, data: &[u8]) {
+        self.responses.borrow_mut().push_back(Ok(data.to_vec()));
+    }
+
+    /// Makes the next transaction that reads fail with `error`.
+    pub fn respond_error(&self, error: Error) {
+        self.responses.borrow_mut().push_back(Err(error));
+    }
+
+    /// Makes the next transaction fail with `error`, whether it reads or not.
+    pub fn fail_next(&self, error: Error) {
+        self.next_error.set(Some(error));
+    }
+
+    /// Returns and forgets the transactions started so far.
+    pub fn take_transactions(&self) -> Vec<I2CTransaction> {
+        self.transactions.take()
+    }
+
+    fn start(
+        &self,
+        address: u8,
+        buffer: &'static mut [u8],
+        write_len: usize,
+        read_len: usize,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        if self.buffer.is_some() {
+            return Err((Error::Busy, buffer));
+        }
+        if write_len > buffer.len() || read_len > buffer.len() {
+            return Err((Error::NotSupported, buffer));
+        }
+
+        self.transactions.borrow_mut().push(I2CTransaction {
+            address,
+            written: buffer[..write_len].to_vec(),
+            read_len,
+        });
+
+        let status = if let Some(error) = self.next_error.take() {
+            Err(error)
+        } else if read_len > 0 {
+            match self.responses.borrow_mut().pop_front() {
+                Some(Ok(data)) => {
+                    for (byte, response) in buffer[..read_len].iter_mut().zip(data) {
+                        *byte = response;
+                    }
+                    Ok(())
+                }
+                Some(Err(error)) => Err(error),
+                None => Err(Error::AddressNak),
+            }
+        } else {
+            Ok(())
+        };
+
+        self.status.set(status);
+        self.buffer.replace(buffer);
+        self.deferred_call.set();
+        Ok(())
+    }
+}
+
+impl<'a> I2CMaster<'a> for MockI2C<'a> {
+    fn set_master_client(&self, master_client: &'a dyn I2CHwMasterClient) {
+        self.client.set(master_client);
+    }
+
+    fn enable(&self) {
+        self.enabled.set(true);
+    }
+
+    fn disable(&self) {
+        self.enabled.set(false);
+    }
+
+    fn write_read(
+        &self,
+        addr: u8,
+        data: &'static mut [u8],
+        write_len: usize,
+        read_len: usize,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        self.start(addr, data, write_len, read_len)
+    }
+
+    fn write(
+        &self,
+        addr: u8,
+        data: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        self.start(addr, data, len, 0)
+    }
+
+    fn read(
+        &self,
+        addr: u8,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        self.start(addr, buffer, 0, len)
+    }
+}
+
+impl DeferredCallClient for MockI2C<'_> {
+    fn handle_deferred_call(&self) {
+        if let Some(buffer) = self.buffer.take() {
+            let status = self.status.get();
+            self.client
+                .map(|client| client.command_complete(buffer, status));
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
@@ -0,0 +1,180 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Mock I2C slave hardware driven by a master played by the test.
+
+use core::cell::{Cell, RefCell};
+
+use kernel::hil::i2c::{
+    Error, I2CHwSlaveClient, I2CSlave, I2CSlaveMultiAddress, SlaveTransmissionType,
+};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+use crate::leak;
+
+/// I2C slave hardware with a fixed number of addresses.
+///
+/// [`MockI2CTarget::master_write`] and [`MockI2CTarget::master_read`] run a
+/// transfer from the master and call the client as the hardware interrupt
+/// would. If the client has not given the hardware a buffer for the
+/// transfer, the mock asks for one with `write_expected` or `read_expected`,
+/// like hardware that stretches the clock.
+pub struct MockI2CTarget<'a> {
+    addresses: RefCell<Vec<Option<u8>>>,
+    matched: Cell<usize>,
+    enabled: Cell<bool>,
+    listening: Cell<bool>,
+    write_buffer: TakeCell<'static, [u8]>,
+    write_len: Cell<usize>,
+    read_buffer: TakeCell<'static, [u8]>,
+    read_len: Cell<usize>,
+    client: OptionalCell<&'a dyn I2CHwSlaveClient>,
+}
+
+impl MockI2CTarget<'static> {
+    pub fn new(address_count: usize) -> &'static Self {
+        leak(Self {
+            addresses: RefCell::new(vec![None; address_count]),
+            matched: Cell::new(0),
+            enabled: Cell::new(false),
+            listening: Cell::new(false),
+            write_buffer: TakeCell::empty(),
+            write_len: Cell::new(0),
+            read_buffer: TakeCell::empty(),
+            read_len: Cell::new(0),
+            client: OptionalCell::empty(),
+        })
+    }
+}
+
+impl MockI2CTarget<'_> {
+    /// The configured addresses, by index.
+    pub fn addresses(&self) -> Vec<Option<u8>> {
+        self.addresses.borrow().clone()
+    }
+
+    /// Whether the hardware is enabled and listening.
+    pub fn is_listening(&self) -> bool {
+        self.enabled.get() && self.listening.get()
+    }
+
+    /// Selects the address matching `address`, or returns false to NACK it.
+    fn select(&self, address: u8) -> bool {
+        let index = self
+            .addresses
+            .borrow()
+            .iter()
+            .position(|a| *a == Some(address));
+        match index {
+            Some(index) if self.is_listening() => {
+                self.matched.set(index);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The master writes `data` to `address`. Returns false if the address
+    /// was not acknowledged. Bytes past the client's buffer are dropped.
+    pub fn master_write(&self, address: u8, data: &[u8]) -> bool {
+        if !self.select(address) {
+            return false;
+        }
+        if self.write_buffer.is_none() {
+            self.client.map(|client| client.write_expected());
+        }
+        let Some(buffer) = self.write_buffer.take() else {
+            return false;
+        };
+        let len = data.len().min(self.write_len.get());
+        buffer[..len].copy_from_slice(&data[..len]);
+        self.client
+            .map(|client| client.command_complete(buffer, len, SlaveTransmissionType::Write));
+        true
+    }
+
+    /// The master reads `len` bytes from `address`. Returns `None` if the
+    /// address was not acknowledged or the client gave no data. Bytes past
+    /// the client's buffer are not sent.
+    pub fn master_read(&self, address: u8, len: usize) -> Option<Vec<u8>> {
+        if !self.select(address) {
+            return None;
+        }
+        if self.read_buffer.is_none() {
+            self.client.map(|client| client.read_expected());
+        }
+        let buffer = self.read_buffer.take()?;
+        let len = len.min(self.read_len.get());
+        let data = buffer[..len].to_vec();
+        self.client
+            .map(|client| client.command_complete(buffer, len, SlaveTransmissionType::Read));
+        Some(data)
+    }
+}
+
+impl<'a> I2CSlave<'a> for MockI2CTarget<'a> {
+    fn set_slave_client(&self, slave_client: &'a dyn I2CHwSlaveClient) {
+        self.client.set(slave_client);
+    }
+
+    fn enable(&self) {
+        self.enabled.set(true);
+    }
+
+    fn disable(&self) {
+        self.enabled.set(false);
+        self.listening.set(false);
+    }
+
+    fn set_address(&self, addr: u8) -> Result<(), Error> {
+        self.set_address_at(0, addr)
+    }
+
+    fn write_receive(
+        &self,
+        data: &'static mut [u8],
+        max_len: usize,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        if self.write_buffer.is_some() {
+            return Err((Error::Busy, data));
+        }
+        self.write_len.set(max_len.min(data.len()));
+        self.write_buffer.replace(data);
+        Ok(())
+    }
+
+    fn read_send(
+        &self,
+        data: &'static mut [u8],
+        max_len: usize,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        if self.read_buffer.is_some() {
+            return Err((Error::Busy, data));
+        }
+        self.read_len.set(max_len.min(data.len()));
+        self.read_buffer.replace(data);
+        Ok(())
+    }
+
+    fn listen(&self) {
+        self.listening.set(true);
+    }
+}
+
+impl I2CSlaveMultiAddress for MockI2CTarget<'_> {
+    fn address_count(&self) -> usize {
+        self.addresses.borrow().len()
+    }
+
+    fn set_address_at(&self, index: usize, addr: u8) -> Result<(), Error> {
+        let mut addresses = self.addresses.borrow_mut();
+        let slot = addresses.get_mut(index).ok_or(Error::NotSupported)?;
+        *slot = Some(addr);
+        Ok(())
+    }
+
+    fn matched_address(&self) -> usize {
+        self.matched.get()
+    }
+}
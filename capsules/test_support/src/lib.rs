@@ -0,0 +1,65 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Host-side test support for capsules.
+//!
+//! This crate lets `cargo test` exercise capsules on the development machine
+//! instead of on a board. It provides:
+//!
+//! - [`Environment`]: a real [`kernel::Kernel`] running on a fake chip, with
+//!   scripted processes. Tests register [`SyscallDriver`]s, create grants for
+//!   them, and load apps that issue `command`, `subscribe`, `allow` and
+//!   `yield` system calls through the kernel's normal syscall path.
+//! - Mock implementations of the common HILs ([`alarm`], [`uart`], [`i2c`],
+//!   [`spi`], [`flash`], [`gpio`], [`can`] and [`aes`]). Each mock records
+//!   what the capsule asked it to do, and completes operations through a
+//!   deferred call with data the test scripted beforehand.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let env = Environment::new();
+//! let alarm = MockAlarm::new();
+//! let driver = leak(AlarmDriver::new(alarm, env.create_grant(alarm::DRIVER_NUM)));
+//! alarm.set_alarm_client(driver);
+//! env.add_driver(alarm::DRIVER_NUM, driver);
+//!
+//! let app = env.load_app("timer");
+//! app.subscribe(alarm::DRIVER_NUM, 0);
+//! app.command(alarm::DRIVER_NUM, 5, 100, 0);
+//! alarm.advance(100);
+//! assert_eq!(app.yield_wait().driver_number, alarm::DRIVER_NUM);
+//! ```
+//!
+//! Tests that use an [`Environment`] run one at a time, as the kernel's
+//! deferred call state is global. Everything a test creates is leaked to get
+//! the `'static` lifetimes capsules expect.
+//!
+//! [`SyscallDriver`]: kernel::syscall::SyscallDriver
+
+pub mod aes;
+pub mod alarm;
+pub mod can;
+pub mod can_fd;
+pub mod chip;
+pub mod environment;
+pub mod flash;
+pub mod gpio;
+pub mod i2c;
+pub mod i2c_target;
+pub mod spi;
+pub mod uart;
+
+pub use crate::environment::{App, AppBuffer, Environment, Upcall};
+
+/// Moves `value` to the heap and returns a `'static` reference to it.
+pub fn leak<T>(value: T) -> &'static mut T {
+    Box::leak(Box::new(value))
+}
+
+/// Returns a `'static` buffer of `len` zero bytes.
+pub fn leak_buffer(len: usize) -> &'static mut [u8] {
+    vec![0; len].leak()
+}
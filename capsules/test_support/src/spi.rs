@@ -0,0 +1,158 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Mock SPI device with scripted read data.
+
+use core::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use kernel::ErrorCode;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::spi::{ClockPhase, ClockPolarity, SpiMasterClient, SpiMasterDevice};
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+
+use crate::leak;
+
+/// One device on a SPI bus, as a capsule sees it through chip select.
+///
+/// Every transfer records the bytes written. If the capsule passes a read
+/// buffer, it is filled with the bytes queued with [`MockSpiDevice::respond`]
+/// and then with zeros. Transfers complete from a deferred call.
+pub struct MockSpiDevice<'a> {
+    polarity: Cell<ClockPolarity>,
+    phase: Cell<ClockPhase>,
+    rate: Cell<u32>,
+    written: RefCell<Vec<Vec<u8>>>,
+    responses: RefCell<VecDeque<u8>>,
+    next_error: Cell<Option<ErrorCode>>,
+    transfer: MapCell<(SubSliceMut<'static, u8>, Option<SubSliceMut<'static, u8>>)>,
+    status: Cell<Result<usize, ErrorCode>>,
+    client: OptionalCell<&'a dyn SpiMasterClient>,
+    deferred_call: DeferredCall,
+}
+
+impl MockSpiDevice<'static> {
+    pub fn new() -> &'static Self {
+        let spi = leak(Self {
+            polarity: Cell::new(ClockPolarity::IdleLow),
+            phase: Cell::new(ClockPhase::SampleLeading),
+            rate: Cell::new(1_000_000),
+            written: RefCell::new(Vec::new()),
+            responses: RefCell::new(VecDeque::new()),
+            next_error: Cell::new(None),
+            transfer: MapCell::empty(),
+            status: Cell::new(Ok(0)),
+            client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+        });
+        spi.register();
+        spi
+    }
+}
+
+impl MockSpiDevice<'_> {
+    /// Queues bytes for the device to send back in the next transfers.
+    pub fn respond(&self, data: &[u8]) {
+        self.responses.borrow_mut().extend(data);
+    }
+
+    /// Makes the next transfer fail with `error`.
+    pub fn fail_next(&self, error: ErrorCode) {
+        self.next_error.set(Some(error));
+    }
+
+    /// Returns and forgets the bytes written by each transfer so far.
+    pub fn take_written(&self) -> Vec<Vec<u8>> {
+        self.written.take()
+    }
+}
+
+impl<'a> SpiMasterDevice<'a> for MockSpiDevice<'a> {
+    fn set_client(&self, client: &'a dyn SpiMasterClient) {
+        self.client.set(client);
+    }
+
+    fn configure(&self, cpol: ClockPolarity, cpal: ClockPhase, rate: u32) -> Result<(), ErrorCode> {
+        self.polarity.set(cpol);
+        self.phase.set(cpal);
+        self.rate.set(rate);
+        Ok(())
+    }
+
+    fn read_write_bytes(
+        &self,
+        write_buffer: SubSliceMut<'static, u8>,
+        read_buffer: Option<SubSliceMut<'static, u8>>,
+    ) -> Result<
+        (),
+        (
+            ErrorCode,
+            SubSliceMut<'static, u8>,
+            Option<SubSliceMut<'static, u8>>,
+        ),
+    > {
+        if self.transfer.is_some() {
+            return Err((ErrorCode::BUSY, write_buffer, read_buffer));
+        }
+
+        let len = read_buffer.as_ref().map_or(write_buffer.len(), |read| {
+            read.len().min(write_buffer.len())
+        });
+        self.written.borrow_mut().push(write_buffer[..len].to_vec());
+        let mut read_buffer = read_buffer;
+        if let Some(read) = read_buffer.as_mut() {
+            let mut responses = self.responses.borrow_mut();
+            for byte in read[..len].iter_mut() {
+                *byte = responses.pop_front().unwrap_or(0);
+            }
+        }
+
+        self.status.set(self.next_error.take().map_or(Ok(len), Err));
+        self.transfer.replace((write_buffer, read_buffer));
+        self.deferred_call.set();
+        Ok(())
+    }
+
+    fn set_rate(&self, rate: u32) -> Result<(), ErrorCode> {
+        self.rate.set(rate);
+        Ok(())
+    }
+
+    fn get_rate(&self) -> u32 {
+        self.rate.get()
+    }
+
+    fn set_polarity(&self, polarity: ClockPolarity) -> Result<(), ErrorCode> {
+        self.polarity.set(polarity);
+        Ok(())
+    }
+
+    fn get_polarity(&self) -> ClockPolarity {
+        self.polarity.get()
+    }
+
+    fn set_phase(&self, phase: ClockPhase) -> Result<(), ErrorCode> {
+        self.phase.set(phase);
+        Ok(())
+    }
+
+    fn get_phase(&self) -> ClockPhase {
+        self.phase.get()
+    }
+}
+
+impl DeferredCallClient for MockSpiDevice<'_> {
+    fn handle_deferred_call(&self) {
+        if let Some((write_buffer, read_buffer)) = self.transfer.take() {
+            let status = self.status.get();
+            self.client
+                .map(|client| client.read_write_done(write_buffer, read_buffer, status));
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
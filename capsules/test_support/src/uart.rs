@@ -0,0 +1,220 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Mock UART that records transmitted bytes and receives scripted input.
+
+use core::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use kernel::ErrorCode;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::uart::{
+    self, Configure, Parameters, Receive, ReceiveClient, Transmit, TransmitClient,
+};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+use crate::leak;
+
+/// A UART that transmits into a buffer and receives from a queue.
+///
+/// Transmissions complete from a deferred call. A pending receive completes
+/// once the test has queued enough bytes with [`MockUart::receive`].
+pub struct MockUart<'a> {
+    parameters: Cell<Option<Parameters>>,
+    transmitted: RefCell<Vec<u8>>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_word: Cell<bool>,
+    tx_held: Cell<bool>,
+    tx_client: OptionalCell<&'a dyn TransmitClient>,
+    input: RefCell<VecDeque<u8>>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_aborted: Cell<bool>,
+    rx_client: OptionalCell<&'a dyn ReceiveClient>,
+    deferred_call: DeferredCall,
+}
+
+impl MockUart<'static> {
+    pub fn new() -> &'static Self {
+        let uart = leak(Self {
+            parameters: Cell::new(None),
+            transmitted: RefCell::new(Vec::new()),
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_word: Cell::new(false),
+            tx_held: Cell::new(false),
+            tx_client: OptionalCell::empty(),
+            input: RefCell::new(VecDeque::new()),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_aborted: Cell::new(false),
+            rx_client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+        });
+        uart.register();
+        uart
+    }
+}
+
+impl MockUart<'_> {
+    /// Returns the parameters of the last `configure()` call.
+    pub fn parameters(&self) -> Option<Parameters> {
+        self.parameters.get()
+    }
+
+    /// Returns and forgets everything transmitted so far.
+    pub fn take_transmitted(&self) -> Vec<u8> {
+        self.transmitted.take()
+    }
+
+    /// While `hold` is true, a transmission does not complete, as if the
+    /// line were slow.
+    pub fn hold_transmissions(&self, hold: bool) {
+        self.tx_held.set(hold);
+        if !hold && (self.tx_buffer.is_some() || self.tx_word.get()) {
+            self.deferred_call.set();
+        }
+    }
+
+    /// Queues `data` as if it arrived on the line.
+    pub fn receive(&self, data: &[u8]) {
+        self.input.borrow_mut().extend(data);
+        if self.rx_buffer.is_some() {
+            self.deferred_call.set();
+        }
+    }
+
+    fn complete_receive(&self) {
+        let available = self.input.borrow().len();
+        if available < self.rx_len.get() && !self.rx_aborted.get() {
+            return;
+        }
+        if let Some(buffer) = self.rx_buffer.take() {
+            let len = self.rx_len.get().min(available);
+            for (byte, input) in buffer.iter_mut().zip(self.input.borrow_mut().drain(..len)) {
+                *byte = input;
+            }
+            let (rval, error) = if self.rx_aborted.take() {
+                (Err(ErrorCode::CANCEL), uart::Error::Aborted)
+            } else {
+                (Ok(()), uart::Error::None)
+            };
+            self.rx_client
+                .map(|client| client.received_buffer(buffer, len, rval, error));
+        }
+    }
+}
+
+impl Configure for MockUart<'_> {
+    fn configure(&self, params: Parameters) -> Result<(), ErrorCode> {
+        self.parameters.set(Some(params));
+        Ok(())
+    }
+}
+
+impl<'a> Transmit<'a> for MockUart<'a> {
+    fn set_transmit_client(&self, client: &'a dyn TransmitClient) {
+        self.tx_client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.tx_buffer.is_some() || self.tx_word.get() {
+            return Err((ErrorCode::BUSY, tx_buffer));
+        }
+        if tx_len == 0 || tx_len > tx_buffer.len() {
+            return Err((ErrorCode::SIZE, tx_buffer));
+        }
+        self.transmitted
+            .borrow_mut()
+            .extend_from_slice(&tx_buffer[..tx_len]);
+        self.tx_len.set(tx_len);
+        self.tx_buffer.replace(tx_buffer);
+        self.deferred_call.set();
+        Ok(())
+    }
+
+    fn transmit_word(&self, word: u32) -> Result<(), ErrorCode> {
+        if self.tx_buffer.is_some() || self.tx_word.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.transmitted.borrow_mut().push(word as u8);
+        self.tx_word.set(true);
+        self.deferred_call.set();
+        Ok(())
+    }
+
+    fn transmit_abort(&self) -> Result<(), ErrorCode> {
+        // Transmissions finish instantly, so there is nothing to abort, but
+        // the callback for a pending one still comes.
+        if self.tx_buffer.is_some() || self.tx_word.get() {
+            Err(ErrorCode::BUSY)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'a> Receive<'a> for MockUart<'a> {
+    fn set_receive_client(&self, client: &'a dyn ReceiveClient) {
+        self.rx_client.set(client);
+    }
+
+    fn receive_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.rx_buffer.is_some() {
+            return Err((ErrorCode::BUSY, rx_buffer));
+        }
+        if rx_len == 0 || rx_len > rx_buffer.len() {
+            return Err((ErrorCode::SIZE, rx_buffer));
+        }
+        self.rx_len.set(rx_len);
+        self.rx_buffer.replace(rx_buffer);
+        self.deferred_call.set();
+        Ok(())
+    }
+
+    fn receive_word(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn receive_abort(&self) -> Result<(), ErrorCode> {
+        if self.rx_buffer.is_some() {
+            self.rx_aborted.set(true);
+            self.deferred_call.set();
+            Err(ErrorCode::BUSY)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl DeferredCallClient for MockUart<'_> {
+    fn handle_deferred_call(&self) {
+        if self.tx_held.get() {
+            self.complete_receive();
+            return;
+        }
+        if let Some(buffer) = self.tx_buffer.take() {
+            let len = self.tx_len.get();
+            self.tx_client
+                .map(|client| client.transmitted_buffer(buffer, len, Ok(())));
+        }
+        if self.tx_word.take() {
+            self.tx_client.map(|client| client.transmitted_word(Ok(())));
+        }
+        self.complete_receive();
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
@@ -0,0 +1,229 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! AES-GCM test vectors from the GCM specification (McGrew and Viega, test
+//! cases 2 to 4) and NIST CAVP (gcmEncryptExtIV128).
+
+use core::cell::RefCell;
+
+use capsules_aes_gcm::aes_gcm::Aes128Gcm;
+use capsules_test_support::aes::MockAes;
+use capsules_test_support::{Environment, leak, leak_buffer};
+use kernel::ErrorCode;
+use kernel::hil::symmetric_encryption::{AES, AES_BLOCK_SIZE, AESGCM, GCMClient};
+
+type Gcm = Aes128Gcm<'static, MockAes>;
+
+struct Vector {
+    key: &'static str,
+    iv: &'static str,
+    aad: &'static str,
+    pt: &'static str,
+    ct: &'static str,
+    tag: &'static str,
+}
+
+/// Test case 2: one block of plaintext, no AAD.
+const ONE_BLOCK: Vector = Vector {
+    key: "00000000000000000000000000000000",
+    iv: "000000000000000000000000",
+    aad: "",
+    pt: "00000000000000000000000000000000",
+    ct: "0388dace60b6a392f328c2b971b2fe78",
+    tag: "ab6e47d42cec13bdf53a67b21257bddf",
+};
+
+/// Test case 3: four blocks of plaintext, no AAD.
+const FOUR_BLOCKS: Vector = Vector {
+    key: "feffe9928665731c6d6a8f9467308308",
+    iv: "cafebabefacedbaddecaf888",
+    aad: "",
+    pt: "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72\
+         1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b391aafd255",
+    ct: "42831ec2217774244b7221b784d0d49ce3aa212f2c02a4e035c17e2329aca12e\
+         21d514b25466931c7d8f6a5aac84aa051ba30b396a0aac973d58e091473f5985",
+    tag: "4d5c2af327cd64a62cf35abd2ba6fab4",
+};
+
+/// Test case 4: AAD and a plaintext that isn't a multiple of the block size.
+const AAD_AND_PARTIAL_BLOCK: Vector = Vector {
+    key: "feffe9928665731c6d6a8f9467308308",
+    iv: "cafebabefacedbaddecaf888",
+    aad: "feedfacedeadbeeffeedfacedeadbeefabaddad2",
+    pt: "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72\
+         1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b39",
+    ct: "42831ec2217774244b7221b784d0d49ce3aa212f2c02a4e035c17e2329aca12e\
+         21d514b25466931c7d8f6a5aac84aa051ba30b396a0aac973d58e091",
+    tag: "5bc94fbc3221a5db94fae95ae7121a47",
+};
+
+/// CAVP: AAD only, with a truncated tag.
+const AAD_ONLY: Vector = Vector {
+    key: "26730f1ad24b76d66f7ab8459ddcd117",
+    iv: "1ffb3e7571cb70145ea51653",
+    aad: "bfc3a808c060cdfd2ab7691b324ab35929e80f262bf3b94cc2f45c62bb0f32bc\
+          4e4b967369110a7b4c47827e93a9ecd7fcda5e6a9739a0d1786d6dc7a45c9c1e\
+          8ecc8f90dc70bc5a5ae1a0313fd6ef87d7b36e3d48c4448f703e",
+    pt: "",
+    ct: "",
+    tag: "45a9be4c849ecb2585421a1f08e6",
+};
+
+fn hex(s: &str) -> Vec<u8> {
+    let s: String = s.split_whitespace().collect();
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+#[derive(Default)]
+struct Client {
+    done: RefCell<Option<(Vec<u8>, Result<(), ErrorCode>, bool)>>,
+}
+
+impl GCMClient for Client {
+    fn crypt_done(&self, buf: &'static mut [u8], res: Result<(), ErrorCode>, tag_is_valid: bool) {
+        *self.done.borrow_mut() = Some((buf.to_vec(), res, tag_is_valid));
+    }
+}
+
+fn gcm() -> (&'static Gcm, &'static Client) {
+    let aes = MockAes::new();
+    let gcm = leak(Aes128Gcm::new(aes, leak_buffer(7 * AES_BLOCK_SIZE)));
+    AES::set_client(aes, gcm);
+    let client = leak(Client::default());
+    AESGCM::set_client(gcm, client);
+    (gcm, client)
+}
+
+/// Runs `buffer` through the GCM capsule, with the AAD at `aad_offset`
+/// followed by the message and the tag.
+fn crypt(
+    env: &Environment,
+    vector: &Vector,
+    buffer: Vec<u8>,
+    aad_offset: usize,
+    encrypting: bool,
+) -> (Vec<u8>, Result<(), ErrorCode>, bool) {
+    let (gcm, client) = gcm();
+    AESGCM::set_key(gcm, &hex(vector.key)).unwrap();
+    AESGCM::set_iv(gcm, &hex(vector.iv)).unwrap();
+
+    let message_offset = aad_offset + hex(vector.aad).len();
+    let message_len = hex(vector.pt).len();
+    let tag_len = hex(vector.tag).len();
+    assert!(
+        AESGCM::crypt(
+            gcm,
+            buffer.leak(),
+            aad_offset,
+            message_offset,
+            message_len,
+            tag_len,
+            encrypting,
+        )
+        .is_ok()
+    );
+    env.run();
+    client.done.take().expect("crypt_done was not called")
+}
+
+/// Builds a buffer of `aad_offset` padding bytes followed by the AAD, the
+/// message and the tag.
+fn buffer(aad_offset: usize, vector: &Vector, message: &str, tag: &str) -> Vec<u8> {
+    let mut buffer = vec![0xAA; aad_offset];
+    buffer.extend(hex(vector.aad));
+    buffer.extend(hex(message));
+    buffer.extend(hex(tag));
+    buffer
+}
+
+fn check_vector(vector: &Vector) {
+    let env = Environment::new();
+
+    for aad_offset in [0, 3] {
+        let (output, res, tag_is_valid) = crypt(
+            &env,
+            vector,
+            buffer(
+                aad_offset,
+                vector,
+                vector.pt,
+                &"00".repeat(hex(vector.tag).len()),
+            ),
+            aad_offset,
+            true,
+        );
+        assert_eq!(res, Ok(()));
+        assert!(tag_is_valid);
+        assert_eq!(output, buffer(aad_offset, vector, vector.ct, vector.tag));
+
+        let (output, res, tag_is_valid) = crypt(
+            &env,
+            vector,
+            buffer(aad_offset, vector, vector.ct, vector.tag),
+            aad_offset,
+            false,
+        );
+        assert_eq!(res, Ok(()));
+        assert!(tag_is_valid);
+        assert_eq!(output, buffer(aad_offset, vector, vector.pt, vector.tag));
+    }
+}
+
+#[test]
+fn one_block() {
+    check_vector(&ONE_BLOCK);
+}
+
+#[test]
+fn four_blocks() {
+    check_vector(&FOUR_BLOCKS);
+}
+
+#[test]
+fn aad_and_partial_block() {
+    check_vector(&AAD_AND_PARTIAL_BLOCK);
+}
+
+#[test]
+fn aad_only_with_truncated_tag() {
+    check_vector(&AAD_ONLY);
+}
+
+#[test]
+fn tampering_is_detected() {
+    let env = Environment::new();
+    let vector = &AAD_AND_PARTIAL_BLOCK;
+    let aad_len = hex(vector.aad).len();
+    let message_len = hex(vector.pt).len();
+
+    // Flip one bit in the AAD, the ciphertext and the tag in turn.
+    for index in [0, aad_len + 5, aad_len + message_len + 15] {
+        let mut input = buffer(0, vector, vector.ct, vector.tag);
+        input[index] ^= 0x01;
+
+        let (output, res, tag_is_valid) = crypt(&env, vector, input, 0, false);
+        assert_eq!(res, Ok(()));
+        assert!(!tag_is_valid, "modified byte {index} was not detected");
+        // Unauthenticated plaintext is not released.
+        assert!(
+            output[aad_len..aad_len + message_len]
+                .iter()
+                .all(|b| *b == 0)
+        );
+    }
+}
+
+#[test]
+fn message_larger_than_crypt_buffer_is_rejected() {
+    let _env = Environment::new();
+    let (gcm, _client) = gcm();
+
+    // The keystream for the message and the tag mask must fit in the
+    // 7 block crypt buffer.
+    let result = AESGCM::crypt(gcm, leak_buffer(128), 0, 0, 7 * AES_BLOCK_SIZE, 16, true);
+    assert!(matches!(result, Err((ErrorCode::SIZE, _))));
+}
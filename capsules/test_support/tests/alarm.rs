@@ -0,0 +1,84 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use capsules_core::alarm::{self, AlarmDriver};
+use capsules_test_support::alarm::MockAlarm;
+use capsules_test_support::{Environment, leak};
+use kernel::hil::time::Alarm;
+use kernel::syscall::SyscallReturn;
+
+fn alarm_driver(env: &Environment) -> &'static MockAlarm<'static> {
+    let mock = MockAlarm::new();
+    let driver = leak(AlarmDriver::new(mock, env.create_grant(alarm::DRIVER_NUM)));
+    mock.set_alarm_client(driver);
+    env.add_driver(alarm::DRIVER_NUM, driver);
+    mock
+}
+
+#[test]
+fn relative_alarm_fires_after_dt() {
+    let env = Environment::new();
+    let mock = alarm_driver(&env);
+    let app = env.load_app("timer");
+
+    assert!(matches!(
+        app.subscribe(alarm::DRIVER_NUM, 0),
+        SyscallReturn::SubscribeSuccess(..)
+    ));
+    assert!(matches!(
+        app.command(alarm::DRIVER_NUM, 5, 100, 0),
+        SyscallReturn::SuccessU32(100)
+    ));
+
+    mock.advance(99);
+    assert_eq!(app.yield_no_wait(), None);
+
+    mock.advance(1);
+    let upcall = app.yield_wait();
+    assert_eq!(upcall.driver_number, alarm::DRIVER_NUM);
+    assert_eq!(upcall.arguments[..2], [100, 100]);
+    assert!(!mock.is_armed());
+}
+
+#[test]
+fn alarms_of_two_apps_fire_in_order() {
+    let env = Environment::new();
+    let mock = alarm_driver(&env);
+    let first = env.load_app("first");
+    let second = env.load_app("second");
+
+    first.subscribe(alarm::DRIVER_NUM, 0);
+    second.subscribe(alarm::DRIVER_NUM, 0);
+    first.command(alarm::DRIVER_NUM, 5, 300, 0);
+    second.command(alarm::DRIVER_NUM, 5, 200, 0);
+
+    mock.advance(250);
+    assert_eq!(first.yield_no_wait(), None);
+    assert_eq!(second.yield_wait().arguments[1], 200);
+    assert!(mock.is_armed());
+
+    mock.advance(50);
+    assert_eq!(first.yield_wait().arguments[1], 300);
+}
+
+#[test]
+fn stopped_alarm_does_not_fire() {
+    let env = Environment::new();
+    let mock = alarm_driver(&env);
+    let app = env.load_app("timer");
+
+    app.subscribe(alarm::DRIVER_NUM, 0);
+    app.command(alarm::DRIVER_NUM, 5, 10, 0);
+    assert!(matches!(
+        app.command(alarm::DRIVER_NUM, 3, 0, 0),
+        SyscallReturn::Success
+    ));
+    assert!(matches!(
+        app.command(alarm::DRIVER_NUM, 3, 0, 0),
+        SyscallReturn::Failure(kernel::ErrorCode::ALREADY)
+    ));
+
+    mock.advance(1000);
+    assert_eq!(app.yield_no_wait(), None);
+}
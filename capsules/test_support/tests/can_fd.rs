@@ -0,0 +1,304 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use core::cell::Cell;
+
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_extra::can::{self, CanCapsule};
+use capsules_extra::test::can_fd::{TEST_ID, TestCanFd};
+use capsules_test_support::can::CanFrame;
+use capsules_test_support::can_fd::{MockCanFd, SentFrame};
+use capsules_test_support::{App, AppBuffer, Environment, leak};
+use kernel::ErrorCode;
+use kernel::hil::can::{
+    BitTiming, ConfigureFd, Controller, Error as CanError, FD_CAN_PACKET_SIZE, Id, Receive,
+    Transmit,
+};
+use kernel::syscall::SyscallReturn;
+
+const DRIVER_NUM: usize = can::DRIVER_NUM;
+const ENABLE: usize = 0;
+const MESSAGE_SENT: usize = 2;
+const MESSAGE_RECEIVED: usize = 3;
+const TRANSMISSION_ERROR: usize = 5;
+const ERROR_TX: usize = 100;
+const ERROR_RX: usize = 101;
+
+/// Size of the streaming buffer header.
+const HEADER_LEN: usize = 8;
+
+/// Payload timing packed as for command 10: segment 1 of 5, segment 2 of 2,
+/// a synchronization jump width of 2 and a prescaler of 1.
+const PAYLOAD_TIMING: usize = 0x0502_0201;
+
+fn can_fd(env: &Environment) -> &'static MockCanFd {
+    let can = MockCanFd::new();
+    let driver = leak(CanCapsule::<_, FD_CAN_PACKET_SIZE>::new(
+        can,
+        env.create_grant(DRIVER_NUM),
+        leak([0; FD_CAN_PACKET_SIZE]),
+        leak([0; FD_CAN_PACKET_SIZE]),
+    ));
+    Controller::set_client(can, Some(driver));
+    Transmit::set_client(can, Some(driver));
+    Receive::set_client(can, Some(driver));
+    env.add_driver(DRIVER_NUM, driver);
+    can
+}
+
+fn succeeds(result: SyscallReturn) {
+    assert!(matches!(result, SyscallReturn::Success), "{result:?}");
+}
+
+/// Enables the controller in loopback mode with bit rate switching and
+/// starts receiving into a buffer with room for `frames` frames.
+fn enable_loopback(app: &App, frames: usize) -> AppBuffer {
+    for upcall in [ENABLE, MESSAGE_SENT, MESSAGE_RECEIVED, TRANSMISSION_ERROR] {
+        app.subscribe(DRIVER_NUM, upcall);
+    }
+    succeeds(app.command(DRIVER_NUM, 1, 500_000, 0));
+    succeeds(app.command(DRIVER_NUM, 2, 0, 0));
+    succeeds(app.command(DRIVER_NUM, 10, PAYLOAD_TIMING, 0));
+    succeeds(app.command(DRIVER_NUM, 11, 1, 0));
+    succeeds(app.command(DRIVER_NUM, 3, 0, 0));
+    assert_eq!(app.yield_wait().arguments, [0, 0, 0]);
+
+    let rx = app.buffer(HEADER_LEN + frames * FD_CAN_PACKET_SIZE);
+    app.allow_readwrite(DRIVER_NUM, 0, rx);
+    succeeds(app.command(DRIVER_NUM, 7, 0, 0));
+    rx
+}
+
+fn allow_payload(app: &App, payload: &[u8]) {
+    let tx = app.buffer(payload.len());
+    app.write(tx, payload);
+    app.allow_readonly(DRIVER_NUM, 0, tx);
+}
+
+fn payload() -> Vec<u8> {
+    (0..FD_CAN_PACKET_SIZE as u8).collect()
+}
+
+#[test]
+fn loopback_frame_is_sent_with_bit_rate_switching_and_received() {
+    let env = Environment::new();
+    let can = can_fd(&env);
+    let app = env.load_app("app");
+    let rx = enable_loopback(&app, 2);
+
+    assert!(matches!(
+        app.command(DRIVER_NUM, 12, 0, 0),
+        SyscallReturn::SuccessU32(64)
+    ));
+    let timing = can.get_payload_bit_timing().unwrap();
+    assert_eq!(
+        (
+            timing.segment1,
+            timing.segment2,
+            timing.sync_jump_width,
+            timing.baud_rate_prescaler
+        ),
+        (5, 2, 2, 1)
+    );
+
+    allow_payload(&app, &payload());
+    succeeds(app.command(DRIVER_NUM, 6, 0x18DA_F110, FD_CAN_PACKET_SIZE));
+    assert_eq!(app.yield_wait().arguments, [0, 0, 0]);
+    assert_eq!(
+        app.yield_wait().arguments,
+        [0, FD_CAN_PACKET_SIZE, 0x18DA_F110]
+    );
+
+    let frame = CanFrame::new(Id::Extended(0x18DA_F110), &payload());
+    assert_eq!(
+        can.take_sent(),
+        [SentFrame {
+            frame,
+            bit_rate_switching: true,
+        }]
+    );
+    let data = app.read(rx);
+    assert_eq!(
+        data[HEADER_LEN..HEADER_LEN + FD_CAN_PACKET_SIZE],
+        payload()[..]
+    );
+}
+
+#[test]
+fn shorter_frames_are_padded_with_zeros() {
+    let env = Environment::new();
+    let can = can_fd(&env);
+    let app = env.load_app("app");
+    let rx = enable_loopback(&app, 2);
+
+    allow_payload(&app, &payload());
+    succeeds(app.command(DRIVER_NUM, 5, 0x123, FD_CAN_PACKET_SIZE));
+    assert_eq!(app.yield_wait().arguments, [0, 0, 0]);
+    assert_eq!(app.yield_wait().arguments, [0, FD_CAN_PACKET_SIZE, 0x123]);
+
+    can.receive(CanFrame::new(Id::Standard(0x124), &[0xAA; 12]));
+    assert_eq!(
+        app.yield_wait().arguments,
+        [0, 2 * FD_CAN_PACKET_SIZE, 0x124]
+    );
+    let data = app.read(rx);
+    let second = &data[HEADER_LEN + FD_CAN_PACKET_SIZE..HEADER_LEN + 2 * FD_CAN_PACKET_SIZE];
+    assert_eq!(second[..12], [0xAA; 12]);
+    assert!(second[12..].iter().all(|byte| *byte == 0));
+}
+
+#[test]
+fn send_longer_than_a_frame_or_the_buffer_fails() {
+    let env = Environment::new();
+    let can = can_fd(&env);
+    let app = env.load_app("app");
+    enable_loopback(&app, 2);
+    allow_payload(&app, &[0; 16]);
+
+    assert!(matches!(
+        app.command(DRIVER_NUM, 5, 0x123, FD_CAN_PACKET_SIZE + 1),
+        SyscallReturn::Failure(ErrorCode::SIZE)
+    ));
+    assert!(matches!(
+        app.command(DRIVER_NUM, 5, 0x123, 20),
+        SyscallReturn::Failure(ErrorCode::SIZE)
+    ));
+    assert!(can.take_sent().is_empty());
+}
+
+#[test]
+fn payload_timing_cannot_change_while_enabled() {
+    let env = Environment::new();
+    can_fd(&env);
+    let app = env.load_app("app");
+    enable_loopback(&app, 2);
+
+    assert!(matches!(
+        app.command(DRIVER_NUM, 10, PAYLOAD_TIMING, 0),
+        SyscallReturn::Failure(ErrorCode::BUSY)
+    ));
+    assert!(matches!(
+        app.command(DRIVER_NUM, 11, 0, 0),
+        SyscallReturn::Failure(ErrorCode::BUSY)
+    ));
+}
+
+#[test]
+fn data_phase_errors_are_reported() {
+    let env = Environment::new();
+    let can = can_fd(&env);
+    let app = env.load_app("app");
+    enable_loopback(&app, 2);
+    allow_payload(&app, &payload());
+
+    can.fail_next(kernel::hil::can::Error::DataPhaseCrc);
+    succeeds(app.command(DRIVER_NUM, 5, 0x123, FD_CAN_PACKET_SIZE));
+    assert_eq!(
+        app.yield_wait().arguments,
+        [ERROR_TX, kernel::hil::can::Error::DataPhaseCrc as usize, 0]
+    );
+    assert_eq!(app.yield_no_wait(), None);
+}
+
+#[test]
+fn extended_upcalls_report_the_length_and_receive_errors() {
+    let env = Environment::new();
+    let can = can_fd(&env);
+    let app = env.load_app("app");
+    enable_loopback(&app, 2);
+
+    can.receive(CanFrame::new(Id::Standard(0x124), &[0xAA; 12]));
+    can.receive_error(CanError::DataPhaseBit);
+    assert_eq!(app.yield_wait().arguments, [0, FD_CAN_PACKET_SIZE, 0x124]);
+    assert_eq!(
+        app.yield_wait().arguments,
+        [
+            ERROR_RX,
+            usize::from(ErrorCode::from(CanError::DataPhaseBit)),
+            0
+        ]
+    );
+
+    succeeds(app.command(DRIVER_NUM, 13, 1, 0));
+    can.receive(CanFrame::new(Id::Standard(0x125), &[0xBB; 20]));
+    can.receive_error(CanError::DataPhaseBit);
+    assert_eq!(
+        app.yield_wait().arguments,
+        [20, 2 * FD_CAN_PACKET_SIZE, 0x125]
+    );
+    assert_eq!(
+        app.yield_wait().arguments,
+        [
+            ERROR_RX,
+            usize::from(ErrorCode::from(CanError::DataPhaseBit)),
+            CanError::DataPhaseBit as usize
+        ]
+    );
+}
+
+/// Records the result of a capsule test.
+struct TestClient {
+    passed: Cell<Option<bool>>,
+}
+
+impl CapsuleTestClient for TestClient {
+    fn done(&'static self, result: Result<(), CapsuleTestError>) {
+        self.passed.set(Some(result.is_ok()));
+    }
+}
+
+fn loopback_test(can: &'static MockCanFd) -> &'static TestClient {
+    let payload_bit_timing = BitTiming {
+        segment1: 5,
+        segment2: 2,
+        propagation: 0,
+        sync_jump_width: 2,
+        baud_rate_prescaler: 1,
+    };
+    let test = leak(TestCanFd::new(
+        can,
+        500_000,
+        payload_bit_timing,
+        leak([0; FD_CAN_PACKET_SIZE]),
+        leak([0; FD_CAN_PACKET_SIZE]),
+    ));
+    Controller::set_client(can, Some(test));
+    Transmit::set_client(can, Some(test));
+    Receive::set_client(can, Some(test));
+    let client = leak(TestClient {
+        passed: Cell::new(None),
+    });
+    test.set_client(client);
+    test.run();
+    client
+}
+
+#[test]
+fn loopback_test_capsule_passes() {
+    let env = Environment::new();
+    let can = MockCanFd::new();
+    let client = loopback_test(can);
+    env.run();
+
+    assert_eq!(client.passed.get(), Some(true));
+    let sent = can.take_sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].frame.id, TEST_ID);
+    assert_eq!(sent[0].frame.data.len(), FD_CAN_PACKET_SIZE);
+    assert!(sent[0].bit_rate_switching);
+    assert!(!can.is_enabled());
+    assert!(!can.is_receiving());
+}
+
+#[test]
+fn loopback_test_capsule_fails_on_data_phase_error() {
+    let env = Environment::new();
+    let can = MockCanFd::new();
+    can.fail_next(kernel::hil::can::Error::DataPhaseBit);
+    let client = loopback_test(can);
+    env.run();
+
+    assert_eq!(client.passed.get(), Some(false));
+    assert!(!can.is_enabled());
+}
@@ -0,0 +1,250 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use capsules_extra::can_isotp::{self, CanIsoTp};
+use capsules_test_support::alarm::MockAlarm;
+use capsules_test_support::can::{CanFrame, MockCan};
+use capsules_test_support::{App, AppBuffer, Environment, leak};
+use kernel::ErrorCode;
+use kernel::hil::can::{self, Id};
+use kernel::hil::time::Alarm;
+use kernel::syscall::SyscallReturn;
+
+const DRIVER_NUM: usize = can_isotp::DRIVER_NUM;
+const SENT: usize = 0;
+const RECEIVED: usize = 1;
+
+const TESTER: u16 = 0x7E0;
+const ECU: u16 = 0x7E8;
+
+fn isotp(env: &Environment) -> (&'static MockCan, &'static MockAlarm<'static>) {
+    let can = MockCan::new();
+    let alarm = MockAlarm::new();
+    let isotp = leak(CanIsoTp::new(
+        can,
+        alarm,
+        env.create_grant(DRIVER_NUM),
+        leak([0; can::STANDARD_CAN_PACKET_SIZE]),
+        leak([0; can::STANDARD_CAN_PACKET_SIZE]),
+    ));
+    can::Transmit::set_client(can, Some(isotp));
+    can::Receive::set_client(can, Some(isotp));
+    alarm.set_alarm_client(isotp);
+    env.add_driver(DRIVER_NUM, isotp);
+    (can, alarm)
+}
+
+/// Opens the driver as the ECU answering the tester.
+fn open(app: &App) {
+    app.subscribe(DRIVER_NUM, SENT);
+    app.subscribe(DRIVER_NUM, RECEIVED);
+    assert!(matches!(
+        app.command(DRIVER_NUM, 1, ECU as usize, TESTER as usize),
+        SyscallReturn::Success
+    ));
+}
+
+fn send(app: &App, message: &[u8]) -> AppBuffer {
+    let buffer = app.buffer(message.len());
+    app.write(buffer, message);
+    app.allow_readonly(DRIVER_NUM, 0, buffer);
+    assert!(matches!(
+        app.command(DRIVER_NUM, 3, message.len(), 0),
+        SyscallReturn::Success
+    ));
+    buffer
+}
+
+/// A frame from the tester, padded like the capsule pads its frames.
+fn from_tester(data: &[u8]) -> CanFrame {
+    let mut frame = [0xCC; 8];
+    frame[..data.len()].copy_from_slice(data);
+    CanFrame::new(Id::Standard(TESTER), &frame)
+}
+
+fn to_tester(data: &[u8]) -> CanFrame {
+    let mut frame = [0xCC; 8];
+    frame[..data.len()].copy_from_slice(data);
+    CanFrame::new(Id::Standard(ECU), &frame)
+}
+
+fn message(len: u8) -> Vec<u8> {
+    (0..len).collect()
+}
+
+#[test]
+fn short_message_is_a_single_frame() {
+    let env = Environment::new();
+    let (can, _) = isotp(&env);
+    let app = env.load_app("ecu");
+    open(&app);
+
+    send(&app, b"\x62\xF1\x90");
+    assert_eq!(app.yield_wait().arguments, [0, 3, 0]);
+    assert_eq!(can.take_sent(), [to_tester(&[0x03, 0x62, 0xF1, 0x90])]);
+}
+
+#[test]
+fn long_message_waits_for_flow_control_and_separation_time() {
+    let env = Environment::new();
+    let (can, alarm) = isotp(&env);
+    let app = env.load_app("ecu");
+    open(&app);
+
+    let data = message(30);
+    send(&app, &data);
+    assert_eq!(app.yield_no_wait(), None);
+    assert_eq!(
+        can.take_sent(),
+        [to_tester(&[[0x10, 30].as_slice(), &data[..6]].concat())]
+    );
+
+    // Two frames per block, 5 ms apart.
+    can.receive(from_tester(&[0x30, 2, 5]));
+    assert_eq!(app.yield_no_wait(), None);
+    assert_eq!(
+        can.take_sent(),
+        [to_tester(&[[0x21].as_slice(), &data[6..13]].concat())]
+    );
+    alarm.advance(4);
+    assert_eq!(app.yield_no_wait(), None);
+    assert!(can.take_sent().is_empty());
+    alarm.advance(1);
+    assert_eq!(app.yield_no_wait(), None);
+    assert_eq!(
+        can.take_sent(),
+        [to_tester(&[[0x22].as_slice(), &data[13..20]].concat())]
+    );
+
+    // The block is done, so the rest waits for the next flow control frame.
+    alarm.advance(100);
+    assert_eq!(app.yield_no_wait(), None);
+    assert!(can.take_sent().is_empty());
+    can.receive(from_tester(&[0x30, 0, 0]));
+    assert_eq!(app.yield_wait().arguments, [0, 30, 0]);
+    assert_eq!(
+        can.take_sent(),
+        [
+            to_tester(&[[0x23].as_slice(), &data[20..27]].concat()),
+            to_tester(&[[0x24].as_slice(), &data[27..]].concat()),
+        ]
+    );
+}
+
+#[test]
+fn missing_flow_control_times_out() {
+    let env = Environment::new();
+    let (_, alarm) = isotp(&env);
+    let app = env.load_app("ecu");
+    open(&app);
+
+    send(&app, &message(20));
+    alarm.advance(999);
+    assert_eq!(app.yield_no_wait(), None);
+    alarm.advance(1);
+    assert_eq!(
+        app.yield_wait().arguments,
+        [ErrorCode::NOACK as usize, 20, 0]
+    );
+}
+
+#[test]
+fn long_message_is_reassembled_with_flow_control() {
+    let env = Environment::new();
+    let (can, _) = isotp(&env);
+    let app = env.load_app("ecu");
+    open(&app);
+    assert!(matches!(
+        app.command(DRIVER_NUM, 2, 1, 0),
+        SyscallReturn::Success
+    ));
+
+    let buffer = app.buffer(32);
+    app.allow_readwrite(DRIVER_NUM, 0, buffer);
+    let data = message(15);
+
+    can.receive(from_tester(&[[0x10, 15].as_slice(), &data[..6]].concat()));
+    assert_eq!(app.yield_no_wait(), None);
+    assert_eq!(can.take_sent(), [to_tester(&[0x30, 1, 0])]);
+
+    can.receive(from_tester(&[[0x21].as_slice(), &data[6..13]].concat()));
+    assert_eq!(app.yield_no_wait(), None);
+    assert_eq!(can.take_sent(), [to_tester(&[0x30, 1, 0])]);
+
+    can.receive(from_tester(&[[0x22].as_slice(), &data[13..]].concat()));
+    let upcall = app.yield_wait();
+    assert_eq!(upcall.subdriver_number, RECEIVED);
+    assert_eq!(upcall.arguments, [0, 15, 0]);
+    assert_eq!(app.read(buffer)[..15], data);
+    assert!(can.take_sent().is_empty());
+}
+
+#[test]
+fn message_larger_than_buffer_overflows() {
+    let env = Environment::new();
+    let (can, _) = isotp(&env);
+    let app = env.load_app("ecu");
+    open(&app);
+    app.allow_readwrite(DRIVER_NUM, 0, app.buffer(10));
+
+    can.receive(from_tester(&[0x10, 20, 0, 1, 2, 3, 4, 5]));
+    assert_eq!(
+        app.yield_wait().arguments,
+        [ErrorCode::SIZE as usize, 20, 0]
+    );
+    assert_eq!(can.take_sent(), [to_tester(&[0x32, 0, 0])]);
+}
+
+#[test]
+fn lost_consecutive_frame_aborts_reception() {
+    let env = Environment::new();
+    let (can, _) = isotp(&env);
+    let app = env.load_app("ecu");
+    open(&app);
+    app.allow_readwrite(DRIVER_NUM, 0, app.buffer(32));
+
+    can.receive(from_tester(&[0x10, 20, 0, 1, 2, 3, 4, 5]));
+    can.receive(from_tester(&[0x22, 13, 14, 15, 16, 17, 18, 19]));
+    assert_eq!(
+        app.yield_wait().arguments,
+        [ErrorCode::FAIL as usize, 20, 0]
+    );
+}
+
+#[test]
+fn frames_for_other_identifiers_are_ignored() {
+    let env = Environment::new();
+    let (can, _) = isotp(&env);
+    let app = env.load_app("ecu");
+    open(&app);
+    app.allow_readwrite(DRIVER_NUM, 0, app.buffer(8));
+
+    can.receive(CanFrame::new(Id::Standard(0x7DF), &[0x02, 0x10, 0x01]));
+    can.receive(CanFrame::new(
+        Id::Extended(TESTER as u32),
+        &[0x02, 0x10, 0x01],
+    ));
+    assert_eq!(app.yield_no_wait(), None);
+}
+
+#[test]
+fn only_one_process_can_use_the_driver() {
+    let env = Environment::new();
+    let (can, _) = isotp(&env);
+    let first = env.load_app("first");
+    let second = env.load_app("second");
+    open(&first);
+
+    assert!(matches!(
+        second.command(DRIVER_NUM, 1, ECU as usize, TESTER as usize),
+        SyscallReturn::Failure(ErrorCode::RESERVE)
+    ));
+    assert!(matches!(
+        first.command(DRIVER_NUM, 4, 0, 0),
+        SyscallReturn::Success
+    ));
+    env.run();
+    assert!(!can.is_receiving());
+    open(&second);
+}
@@ -0,0 +1,263 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use capsules_core::virtualizers::virtual_can::{MuxCan, VirtualCanDevice};
+use capsules_extra::can_shared::{self, EXTENDED_ID_FLAG, FRAME_RECORD_LEN, SharedCan};
+use capsules_test_support::can::{CanFrame, MockCan};
+use capsules_test_support::{App, AppBuffer, Environment, leak};
+use kernel::ErrorCode;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil::can::{self, Id, STANDARD_CAN_PACKET_SIZE};
+use kernel::syscall::SyscallReturn;
+
+const DRIVER_NUM: usize = can_shared::DRIVER_NUM;
+const SENT: usize = 0;
+const RECEIVED: usize = 1;
+
+/// Size of the streaming buffer header.
+const HEADER_LEN: usize = 8;
+
+fn shared_can(env: &Environment) -> &'static MockCan {
+    let can = MockCan::new();
+    let mux = leak(MuxCan::new(
+        can,
+        Some(can),
+        leak([0; STANDARD_CAN_PACKET_SIZE]),
+    ));
+    mux.register();
+    can::Transmit::set_client(can, Some(mux));
+    can::Receive::set_client(can, Some(mux));
+
+    let device = leak(VirtualCanDevice::new(mux));
+    device.setup();
+    let driver = leak(SharedCan::new(
+        device,
+        env.create_grant(DRIVER_NUM),
+        leak([0; STANDARD_CAN_PACKET_SIZE]),
+        leak([0; STANDARD_CAN_PACKET_SIZE]),
+    ));
+    can::Transmit::set_client(device, Some(driver));
+    can::Receive::set_client(device, Some(driver));
+    env.add_driver(DRIVER_NUM, driver);
+    can
+}
+
+/// Starts receiving into a buffer with room for `frames` frames.
+fn start(app: &App, frames: usize) -> AppBuffer {
+    app.subscribe(DRIVER_NUM, RECEIVED);
+    let buffer = rx_buffer(app, frames);
+    assert!(matches!(
+        app.command(DRIVER_NUM, 3, 0, 0),
+        SyscallReturn::Success
+    ));
+    buffer
+}
+
+fn rx_buffer(app: &App, frames: usize) -> AppBuffer {
+    let buffer = app.buffer(HEADER_LEN + frames * FRAME_RECORD_LEN);
+    app.allow_readwrite(DRIVER_NUM, 0, buffer);
+    buffer
+}
+
+fn add_filter(app: &App, id: usize, mask: usize) -> SyscallReturn {
+    app.command(DRIVER_NUM, 1, id, mask)
+}
+
+/// Decodes the frames in a receive buffer.
+fn received(app: &App, buffer: AppBuffer) -> Vec<CanFrame> {
+    let data = app.read(buffer);
+    let offset = u32::from_ne_bytes(data[4..8].try_into().unwrap()) as usize;
+    data[HEADER_LEN..HEADER_LEN + offset]
+        .chunks(FRAME_RECORD_LEN)
+        .map(|record| {
+            let id = u32::from_le_bytes(record[0..4].try_into().unwrap()) as usize;
+            let id = if id & EXTENDED_ID_FLAG != 0 {
+                Id::Extended((id & !EXTENDED_ID_FLAG) as u32)
+            } else {
+                Id::Standard(id as u16)
+            };
+            CanFrame::new(id, &record[8..8 + record[4] as usize])
+        })
+        .collect()
+}
+
+#[test]
+fn processes_receive_the_frames_their_filters_accept() {
+    let env = Environment::new();
+    let can = shared_can(&env);
+    let engine = env.load_app("engine");
+    let body = env.load_app("body");
+
+    assert!(matches!(
+        add_filter(&engine, 0x100, 0x7FF),
+        SyscallReturn::Success
+    ));
+    assert!(matches!(
+        add_filter(&body, 0x18FE_F100 | EXTENDED_ID_FLAG, 0x1FFF_FF00),
+        SyscallReturn::Success
+    ));
+    let engine_rx = start(&engine, 4);
+    let body_rx = start(&body, 4);
+    assert_eq!(can.enabled_filters().len(), 2);
+
+    can.receive(CanFrame::new(Id::Standard(0x100), &[1, 2]));
+    can.receive(CanFrame::new(Id::Extended(0x18FE_F1AA), &[3]));
+    can.receive(CanFrame::new(Id::Standard(0x300), &[4]));
+
+    assert_eq!(engine.yield_wait().arguments, [0, FRAME_RECORD_LEN, 0]);
+    assert_eq!(body.yield_wait().arguments, [0, FRAME_RECORD_LEN, 0]);
+    assert_eq!(
+        received(&engine, engine_rx),
+        [CanFrame::new(Id::Standard(0x100), &[1, 2])]
+    );
+    assert_eq!(
+        received(&body, body_rx),
+        [CanFrame::new(Id::Extended(0x18FE_F1AA), &[3])]
+    );
+    assert_eq!(engine.yield_no_wait(), None);
+    assert_eq!(body.yield_no_wait(), None);
+}
+
+#[test]
+fn process_without_filters_receives_every_frame() {
+    let env = Environment::new();
+    let can = shared_can(&env);
+    let logger = env.load_app("logger");
+    let engine = env.load_app("engine");
+
+    assert!(matches!(
+        add_filter(&engine, 0x100, 0x7FF),
+        SyscallReturn::Success
+    ));
+    let logger_rx = start(&logger, 4);
+    start(&engine, 4);
+    assert_eq!(can.enabled_filters(), [(Id::Standard(0), 0)]);
+
+    can.receive(CanFrame::new(Id::Standard(0x100), &[1]));
+    can.receive(CanFrame::new(Id::Standard(0x300), &[2]));
+    env.run();
+
+    assert_eq!(received(&logger, logger_rx).len(), 2);
+    assert_eq!(engine.yield_wait().arguments, [0, FRAME_RECORD_LEN, 0]);
+    assert_eq!(engine.yield_no_wait(), None);
+
+    // Once the logger stops, only the engine's filter is left.
+    assert!(matches!(
+        logger.command(DRIVER_NUM, 4, 0, 0),
+        SyscallReturn::Success
+    ));
+    assert_eq!(can.enabled_filters(), [(Id::Standard(0x100), 0x7FF)]);
+}
+
+#[test]
+fn filters_are_limited_per_process() {
+    let env = Environment::new();
+    shared_can(&env);
+    let app = env.load_app("app");
+
+    for id in 0..4 {
+        assert!(matches!(
+            add_filter(&app, id, 0x7FF),
+            SyscallReturn::Success
+        ));
+    }
+    assert!(matches!(
+        add_filter(&app, 4, 0x7FF),
+        SyscallReturn::Failure(ErrorCode::NOMEM)
+    ));
+    assert!(matches!(
+        app.command(DRIVER_NUM, 2, 0, 0),
+        SyscallReturn::Success
+    ));
+    assert!(matches!(add_filter(&app, 4, 0x7FF), SyscallReturn::Success));
+}
+
+#[test]
+fn full_buffer_counts_lost_frames() {
+    let env = Environment::new();
+    let can = shared_can(&env);
+    let app = env.load_app("app");
+    let first = start(&app, 1);
+
+    can.receive(CanFrame::new(Id::Standard(0x100), &[1]));
+    can.receive(CanFrame::new(Id::Standard(0x101), &[2]));
+    assert_eq!(app.yield_wait().arguments, [0, FRAME_RECORD_LEN, 0]);
+    assert_eq!(app.yield_no_wait(), None);
+    assert_eq!(received(&app, first).len(), 1);
+
+    let second = rx_buffer(&app, 1);
+    can.receive(CanFrame::new(Id::Standard(0x102), &[3]));
+    assert_eq!(app.yield_wait().arguments, [0, FRAME_RECORD_LEN, 1]);
+    assert_eq!(
+        received(&app, second),
+        [CanFrame::new(Id::Standard(0x102), &[3])]
+    );
+}
+
+#[test]
+fn sends_from_processes_are_queued() {
+    let env = Environment::new();
+    let can = shared_can(&env);
+    let first = env.load_app("first");
+    let second = env.load_app("second");
+
+    for (app, data) in [(&first, [1, 2]), (&second, [3, 4])] {
+        app.subscribe(DRIVER_NUM, SENT);
+        let buffer = app.buffer(2);
+        app.write(buffer, &data);
+        app.allow_readonly(DRIVER_NUM, 0, buffer);
+    }
+
+    // Keep the first frame on the bus until both processes have queued theirs.
+    can.hold_transmissions(true);
+    assert!(matches!(
+        first.command(DRIVER_NUM, 5, 0x123, 2),
+        SyscallReturn::Success
+    ));
+    assert!(matches!(
+        second.command(DRIVER_NUM, 5, 0x0ABC_DEF0 | EXTENDED_ID_FLAG, 2),
+        SyscallReturn::Success
+    ));
+    assert!(matches!(
+        first.command(DRIVER_NUM, 5, 0x123, 2),
+        SyscallReturn::Failure(ErrorCode::BUSY)
+    ));
+    assert_eq!(can.take_sent().len(), 1);
+
+    can.hold_transmissions(false);
+    assert_eq!(first.yield_wait().arguments, [0, 0, 0]);
+    assert_eq!(second.yield_wait().arguments, [0, 0, 0]);
+    assert_eq!(
+        can.take_sent(),
+        [CanFrame::new(Id::Extended(0x0ABC_DEF0), &[3, 4])]
+    );
+}
+
+#[test]
+fn send_longer_than_the_buffer_fails() {
+    let env = Environment::new();
+    let can = shared_can(&env);
+    let app = env.load_app("app");
+    app.subscribe(DRIVER_NUM, SENT);
+    let buffer = app.buffer(2);
+    app.allow_readonly(DRIVER_NUM, 0, buffer);
+
+    assert!(matches!(
+        app.command(DRIVER_NUM, 5, 0x123, 9),
+        SyscallReturn::Failure(ErrorCode::SIZE)
+    ));
+    assert!(matches!(
+        app.command(DRIVER_NUM, 5, 0x123, 4),
+        SyscallReturn::Success
+    ));
+    assert_eq!(
+        app.yield_wait().arguments,
+        [
+            kernel::errorcode::into_statuscode(Err(ErrorCode::SIZE)),
+            0,
+            0
+        ]
+    );
+    assert!(can.take_sent().is_empty());
+}
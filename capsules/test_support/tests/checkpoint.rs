@@ -0,0 +1,441 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use core::cell::Cell;
+
+use capsules_extra::process_checkpoint::{DRIVER_NUM, ProcessCheckpointDriver};
+use capsules_system::process_checker::basic::{AppCheckerNull, AppIdAssignerSimulated};
+use capsules_system::process_policies::PanicFaultPolicy;
+use capsules_system::storage_permissions::null::NullStoragePermissions;
+use capsules_test_support::chip::HostChip;
+use capsules_test_support::{Environment, leak, leak_buffer};
+use kernel::ErrorCode;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::process::{
+    ProcessBinary, ProcessCheckerMachine, ProcessLoadingAsync, ProcessStandardDebugFull,
+    SequentialProcessLoaderMachine, State,
+};
+use kernel::process_checkpoint::{BUF_LEN, ProcessCheckpoint, SequentialProcessCheckpoint};
+use kernel::syscall::SyscallReturn;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+const NUM_PROCS: usize = 4;
+const APP_SIZE: usize = 1024;
+const RAM_SIZE: usize = 512 * 1024;
+const SLOT_LENGTH: usize = 16 * 1024;
+const NUM_SLOTS: usize = 4;
+const REGION_SIZE: usize = NUM_SLOTS * SLOT_LENGTH;
+
+struct Capability;
+unsafe impl ProcessManagementCapability for Capability {}
+
+/// Memory that outlives a reboot: the flash with the app and the checkpoint
+/// region, and the RAM processes are loaded into, which holds them at the
+/// same addresses on every boot.
+#[derive(Clone, Copy)]
+struct Device {
+    apps: *mut u8,
+    apps_len: usize,
+    region: *mut u8,
+    ram: *mut u8,
+}
+
+impl Device {
+    fn new(apps: &[u8]) -> Self {
+        Self {
+            apps: apps.to_vec().leak().as_mut_ptr(),
+            apps_len: apps.len(),
+            region: vec![0xFF; REGION_SIZE].leak().as_mut_ptr(),
+            ram: leak_buffer(RAM_SIZE).as_mut_ptr(),
+        }
+    }
+
+    fn apps(self) -> &'static [u8] {
+        // SAFETY: the flash is leaked, and only changes between boots.
+        unsafe { core::slice::from_raw_parts(self.apps, self.apps_len) }
+    }
+
+    /// Changes a byte of the apps in flash, as an update in place would.
+    fn update_apps(self, offset: usize, byte: u8) {
+        assert!(offset < self.apps_len);
+        // SAFETY: the offset is within the flash, and the kernel of the
+        // previous boot that read it is no longer used.
+        unsafe { self.apps.add(offset).write(byte) };
+    }
+
+    fn region(self) -> &'static [u8] {
+        // SAFETY: the region is leaked, and only changes through
+        // `MappedFlash`, like flash does on hardware.
+        unsafe { core::slice::from_raw_parts(self.region, REGION_SIZE) }
+    }
+
+    /// The RAM, cleared as by a power loss.
+    fn ram(self) -> &'static mut [u8] {
+        // SAFETY: the RAM is leaked, and the kernel of the previous boot that
+        // held it is no longer used.
+        let ram = unsafe { core::slice::from_raw_parts_mut(self.ram, RAM_SIZE) };
+        ram.fill(0);
+        ram
+    }
+}
+
+/// Nonvolatile storage over the checkpoint region, which the kernel reads
+/// directly like memory-mapped flash. Writes complete from a deferred call.
+///
+/// Power can be cut after a number of writes: the write in progress then only
+/// writes its first half, and never completes.
+struct MappedFlash {
+    region: *mut u8,
+    writes: Cell<usize>,
+    power_cut_after: Cell<Option<usize>>,
+    fail_after: Cell<Option<usize>>,
+    buffer: TakeCell<'static, [u8]>,
+    length: Cell<usize>,
+    client: OptionalCell<&'static dyn NonvolatileStorageClient>,
+    deferred_call: DeferredCall,
+}
+
+impl MappedFlash {
+    fn new(device: Device) -> &'static Self {
+        let flash = leak(Self {
+            region: device.region,
+            writes: Cell::new(0),
+            power_cut_after: Cell::new(None),
+            fail_after: Cell::new(None),
+            buffer: TakeCell::empty(),
+            length: Cell::new(0),
+            client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+        });
+        flash.register();
+        flash
+    }
+}
+
+impl NonvolatileStorage<'static> for MappedFlash {
+    fn set_client(&self, client: &'static dyn NonvolatileStorageClient) {
+        self.client.set(client);
+    }
+
+    fn read(
+        &self,
+        _buffer: &'static mut [u8],
+        _address: usize,
+        _length: usize,
+    ) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        let offset = address.wrapping_sub(self.region.addr());
+        if length > buffer.len() || offset + length > REGION_SIZE {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.fail_after.get() == Some(self.writes.get()) {
+            return Err(ErrorCode::FAIL);
+        }
+        let powered = self.power_cut_after.get() != Some(self.writes.get());
+        let written = if powered { length } else { length / 2 };
+        // SAFETY: the range is within the region.
+        unsafe {
+            core::ptr::copy_nonoverlapping(buffer.as_ptr(), self.region.add(offset), written);
+        }
+        if powered {
+            self.writes.set(self.writes.get() + 1);
+            self.buffer.replace(buffer);
+            self.length.set(length);
+            self.deferred_call.set();
+        }
+        Ok(())
+    }
+}
+
+impl DeferredCallClient for MappedFlash {
+    fn handle_deferred_call(&self) {
+        if let Some(buffer) = self.buffer.take() {
+            self.client
+                .map(|client| client.write_done(buffer, self.length.get()));
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+type Checkpoint = SequentialProcessCheckpoint<'static, MappedFlash>;
+
+struct Board {
+    flash: &'static MappedFlash,
+    checkpoint: &'static Checkpoint,
+    /// The memory the processes were loaded into.
+    ram: *mut u8,
+}
+
+/// Sets up checkpoints and loads the apps, restoring them from their
+/// checkpoints, like a board does at boot.
+fn boot(env: &Environment, device: Device) -> Board {
+    let flash = MappedFlash::new(device);
+    let checkpoint = leak(SequentialProcessCheckpoint::new(
+        env.kernel(),
+        flash,
+        device.region(),
+        SLOT_LENGTH,
+        leak_buffer(BUF_LEN),
+    ));
+    flash.set_client(checkpoint);
+    let driver = leak(ProcessCheckpointDriver::new(
+        checkpoint,
+        env.kernel(),
+        Capability,
+    ));
+    checkpoint.set_checkpoint_client(driver);
+    env.add_driver(DRIVER_NUM, driver);
+
+    let checker = leak(ProcessCheckerMachine::new(leak(AppCheckerNull::new())));
+    let loader = leak(SequentialProcessLoaderMachine::new(
+        checker,
+        leak([const { None::<ProcessBinary> }; NUM_PROCS]),
+        env.kernel(),
+        env.chip(),
+        device.apps(),
+        device.ram(),
+        leak(PanicFaultPolicy {}),
+        leak(NullStoragePermissions::<HostChip, ProcessStandardDebugFull>::new()),
+        leak(AppIdAssignerSimulated {}),
+        &Capability,
+    ));
+    checker.set_client(loader);
+    loader.register();
+    loader.set_restore(checkpoint);
+    loader.start();
+    env.run();
+
+    Board {
+        flash,
+        checkpoint,
+        ram: device.ram,
+    }
+}
+
+/// Builds a TBF of `APP_SIZE` bytes with Main, Package Name and Kernel
+/// Version headers, and a Checkpoint header if `checkpoint` is set.
+fn app(name: &str, checkpoint: bool) -> Vec<u8> {
+    let name_size = name.len().next_multiple_of(4);
+    let checkpoint_size = if checkpoint { 4 } else { 0 };
+    let header_size = 16 + (4 + 12) + (4 + 4) + (4 + name_size) + checkpoint_size;
+
+    let mut tbf = Vec::with_capacity(APP_SIZE);
+    tbf.extend_from_slice(&2u16.to_le_bytes());
+    tbf.extend_from_slice(&(header_size as u16).to_le_bytes());
+    tbf.extend_from_slice(&(APP_SIZE as u32).to_le_bytes());
+    tbf.extend_from_slice(&1u32.to_le_bytes());
+    tbf.extend_from_slice(&0u32.to_le_bytes());
+    tbf.extend_from_slice(&1u16.to_le_bytes());
+    tbf.extend_from_slice(&12u16.to_le_bytes());
+    tbf.extend_from_slice(&0u32.to_le_bytes());
+    tbf.extend_from_slice(&0u32.to_le_bytes());
+    tbf.extend_from_slice(&(32 * 1024u32).to_le_bytes());
+    tbf.extend_from_slice(&8u16.to_le_bytes());
+    tbf.extend_from_slice(&4u16.to_le_bytes());
+    tbf.extend_from_slice(&kernel::KERNEL_MAJOR_VERSION.to_le_bytes());
+    tbf.extend_from_slice(&kernel::KERNEL_MINOR_VERSION.to_le_bytes());
+    tbf.extend_from_slice(&3u16.to_le_bytes());
+    tbf.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    tbf.extend_from_slice(name.as_bytes());
+    tbf.resize(16 + (4 + 12) + (4 + 4) + (4 + name_size), 0);
+    if checkpoint {
+        tbf.extend_from_slice(&11u16.to_le_bytes());
+        tbf.extend_from_slice(&0u16.to_le_bytes());
+    }
+    let header_checksum = tbf
+        .chunks_exact(4)
+        .enumerate()
+        .filter(|(i, _)| *i != 3)
+        .fold(0, |checksum, (_, word)| {
+            checksum ^ u32::from_le_bytes(word.try_into().unwrap())
+        });
+    tbf[12..16].copy_from_slice(&header_checksum.to_le_bytes());
+    tbf.resize(APP_SIZE, 0);
+    tbf
+}
+
+/// The flash with a checkpointing app called `a`.
+fn apps() -> Vec<u8> {
+    let mut apps = app("a", true);
+    apps.resize(4 * APP_SIZE, 0xFF);
+    apps
+}
+
+/// The process of the app at the start of flash.
+fn process(env: &Environment) -> kernel::ProcessId {
+    env.kernel()
+        .process_iter_capability(&Capability)
+        .next()
+        .expect("the app did not load")
+        .processid()
+}
+
+/// The first `len` bytes of the memory of the process, which is where the
+/// first app buffer is.
+fn memory(env: &Environment, board: &Board, len: usize) -> Vec<u8> {
+    let start = env.kernel().process_map_or_external(
+        0,
+        process(env),
+        |p| p.get_addresses().sram_start,
+        &Capability,
+    );
+    // SAFETY: the process memory is within the RAM, and the process does not
+    // run while it is read.
+    unsafe { core::slice::from_raw_parts(board.ram.add(start - board.ram.addr()), len) }.to_vec()
+}
+
+fn state(env: &Environment) -> State {
+    env.kernel()
+        .process_map_or_external(None, process(env), |p| Some(p.get_state()), &Capability)
+        .unwrap()
+}
+
+#[test]
+fn restores_the_process_after_a_reboot() {
+    let device = Device::new(&apps());
+    {
+        let env = Environment::new();
+        let board = boot(&env, device);
+        let app = env.app(process(&env), board.ram);
+        let buffer = app.buffer(16);
+        app.write(buffer, b"checkpointed....");
+
+        // The command returns once the checkpoint is written.
+        assert!(matches!(
+            app.command(DRIVER_NUM, 1, 0, 0),
+            SyscallReturn::Success
+        ));
+        assert!(board.flash.writes.get() > 0);
+        assert_eq!(state(&env), State::Running);
+        app.write(buffer, b"after checkpoint");
+    }
+
+    let env = Environment::new();
+    let board = boot(&env, device);
+    // The restored process continues from its command instead of calling
+    // its init function.
+    assert_eq!(state(&env), State::Running);
+    assert_eq!(memory(&env, &board, 16), b"checkpointed....");
+}
+
+#[test]
+fn restores_the_previous_checkpoint_after_a_power_loss() {
+    let device = Device::new(&apps());
+    {
+        let env = Environment::new();
+        let board = boot(&env, device);
+        let app = env.app(process(&env), board.ram);
+        let buffer = app.buffer(8);
+        for contents in [b"first...", b"second.."] {
+            app.write(buffer, contents);
+            assert!(matches!(
+                app.command(DRIVER_NUM, 1, 0, 0),
+                SyscallReturn::Success
+            ));
+        }
+
+        // Power is lost in the middle of the third checkpoint, which
+        // overwrites the first.
+        app.write(buffer, b"third...");
+        board
+            .flash
+            .power_cut_after
+            .set(Some(board.flash.writes.get() + 2));
+        assert_eq!(board.checkpoint.checkpoint(app.processid()), Ok(()));
+        env.run();
+        assert_eq!(
+            state(&env),
+            State::Stopped(kernel::process::StoppedState::Running)
+        );
+    }
+
+    let env = Environment::new();
+    let board = boot(&env, device);
+    assert_eq!(memory(&env, &board, 8), b"second..");
+}
+
+#[test]
+fn a_failed_checkpoint_fails_the_command() {
+    let device = Device::new(&apps());
+    {
+        let env = Environment::new();
+        let board = boot(&env, device);
+        let app = env.app(process(&env), board.ram);
+        app.write(app.buffer(4), b"lost");
+
+        board.flash.fail_after.set(Some(1));
+        assert!(matches!(
+            app.command(DRIVER_NUM, 1, 0, 0),
+            SyscallReturn::Failure(ErrorCode::FAIL)
+        ));
+        assert_eq!(state(&env), State::Running);
+    }
+
+    // Nothing is restored from the partial checkpoint.
+    let env = Environment::new();
+    let board = boot(&env, device);
+    assert_eq!(memory(&env, &board, 4), [0; 4]);
+}
+
+#[test]
+fn only_running_processes_that_opted_in_are_checkpointed() {
+    let mut apps = app("a", false);
+    apps.extend(app("b", true));
+    apps.resize(4 * APP_SIZE, 0xFF);
+    let device = Device::new(&apps);
+    let env = Environment::new();
+    let board = boot(&env, device);
+    let processes: Vec<_> = env
+        .kernel()
+        .process_iter_capability(&Capability)
+        .map(|process| process.processid())
+        .collect();
+
+    // `b` has not called its init function yet, and is waiting in a yield
+    // that nothing would wake it from after a restore.
+    assert_eq!(
+        board.checkpoint.checkpoint(processes[1]),
+        Err(ErrorCode::INVAL)
+    );
+
+    let a = env.app(processes[0], board.ram);
+    assert!(matches!(
+        a.command(DRIVER_NUM, 1, 0, 0),
+        SyscallReturn::Failure(ErrorCode::NOSUPPORT)
+    ));
+    assert_eq!(board.flash.writes.get(), 0);
+}
+
+#[test]
+fn a_changed_binary_starts_fresh() {
+    let device = Device::new(&apps());
+    {
+        let env = Environment::new();
+        let board = boot(&env, device);
+        let app = env.app(process(&env), board.ram);
+        app.write(app.buffer(4), b"old.");
+        assert!(matches!(
+            app.command(DRIVER_NUM, 1, 0, 0),
+            SyscallReturn::Success
+        ));
+    }
+
+    device.update_apps(APP_SIZE - 1, 1);
+    let env = Environment::new();
+    let board = boot(&env, device);
+    assert_eq!(memory(&env, &board, 4), [0; 4]);
+}
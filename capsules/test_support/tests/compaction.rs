@@ -0,0 +1,470 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use core::cell::Cell;
+
+use capsules_system::process_checker::basic::{AppCheckerNull, AppIdAssignerSimulated};
+use capsules_system::process_policies::PanicFaultPolicy;
+use capsules_system::storage_permissions::null::NullStoragePermissions;
+use capsules_test_support::chip::HostChip;
+use capsules_test_support::{Environment, leak, leak_buffer};
+use kernel::ErrorCode;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::dynamic_binary_storage::{
+    BUF_LEN, DynamicBinaryStore, DynamicBinaryStoreClient, DynamicProcessUpdate,
+    DynamicProcessUpdateClient, SequentialDynamicBinaryStorage,
+};
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::process::{
+    ProcessBinary, ProcessCheckerMachine, ProcessLoadingAsync, ProcessStandardDebugFull,
+    SequentialProcessLoaderMachine,
+};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+const NUM_PROCS: usize = 4;
+const BANK_SIZE: usize = 4096;
+/// Each journal slot is a page of its own.
+const JOURNAL_SLOT_SIZE: usize = 512;
+const APP_SIZE: usize = 1024;
+const APP_MEMORY_SIZE: usize = 512 * 1024;
+const MEMORY_SIZE: usize = BANK_SIZE + 2 * JOURNAL_SLOT_SIZE;
+
+const JOURNAL_MAGIC: u32 = 0x4a43_4254;
+const STEP_PAD_SOURCE: u32 = 5;
+
+struct Capability;
+unsafe impl ProcessManagementCapability for Capability {}
+
+/// The flash bank for apps, followed by the compaction journal. Binaries are
+/// aligned to their length, so the bank is aligned to its own.
+#[repr(C, align(4096))]
+struct Aligned([u8; MEMORY_SIZE]);
+
+/// Memory that stands in for flash.
+///
+/// The memory only changes through [`MappedFlash`], under the slices the
+/// kernel holds, like flash does on hardware.
+#[derive(Clone, Copy)]
+struct Memory(*mut u8);
+
+impl Memory {
+    /// Creates the memory with the binaries in `bank`, and `journal` in the
+    /// journal slots.
+    fn new(bank: &[u8], journal: &[u8]) -> Self {
+        let mut memory = Box::new(Aligned([0xFF; MEMORY_SIZE]));
+        memory.0[..bank.len()].copy_from_slice(bank);
+        memory.0[BANK_SIZE..BANK_SIZE + journal.len()].copy_from_slice(journal);
+        Self(Box::into_raw(memory).cast())
+    }
+
+    fn slice(self, offset: usize, len: usize) -> &'static [u8] {
+        assert!(offset + len <= MEMORY_SIZE);
+        // SAFETY: the memory is leaked, and the range is within it.
+        unsafe { core::slice::from_raw_parts(self.0.add(offset), len) }
+    }
+
+    fn bank(self) -> &'static [u8] {
+        self.slice(0, BANK_SIZE)
+    }
+
+    fn journal(self) -> &'static [u8] {
+        self.slice(BANK_SIZE, 2 * JOURNAL_SLOT_SIZE)
+    }
+
+    fn address(self, offset: usize) -> usize {
+        self.0.addr() + offset
+    }
+}
+
+/// Nonvolatile storage over memory the process loader reads directly, like
+/// memory-mapped flash. Writes complete from a deferred call.
+///
+/// Power can be cut after a number of writes: the write in progress then only
+/// writes its first half, and never completes.
+struct MappedFlash {
+    memory: Memory,
+    writes: Cell<usize>,
+    power_cut_after: Cell<Option<usize>>,
+    buffer: TakeCell<'static, [u8]>,
+    length: Cell<usize>,
+    client: OptionalCell<&'static dyn NonvolatileStorageClient>,
+    deferred_call: DeferredCall,
+}
+
+impl MappedFlash {
+    fn new(memory: Memory) -> &'static Self {
+        let flash = leak(Self {
+            memory,
+            writes: Cell::new(0),
+            power_cut_after: Cell::new(None),
+            buffer: TakeCell::empty(),
+            length: Cell::new(0),
+            client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+        });
+        flash.register();
+        flash
+    }
+}
+
+impl NonvolatileStorage<'static> for MappedFlash {
+    fn set_client(&self, client: &'static dyn NonvolatileStorageClient) {
+        self.client.set(client);
+    }
+
+    fn read(
+        &self,
+        _buffer: &'static mut [u8],
+        _address: usize,
+        _length: usize,
+    ) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        let offset = address.wrapping_sub(self.memory.address(0));
+        if length > buffer.len() || offset + length > MEMORY_SIZE {
+            return Err(ErrorCode::INVAL);
+        }
+        let powered = self.power_cut_after.get() != Some(self.writes.get());
+        let written = if powered { length } else { length / 2 };
+        // SAFETY: the range is within the memory.
+        unsafe {
+            core::ptr::copy_nonoverlapping(buffer.as_ptr(), self.memory.0.add(offset), written);
+        }
+        if powered {
+            self.writes.set(self.writes.get() + 1);
+            self.buffer.replace(buffer);
+            self.length.set(length);
+            self.deferred_call.set();
+        }
+        Ok(())
+    }
+}
+
+impl DeferredCallClient for MappedFlash {
+    fn handle_deferred_call(&self) {
+        if let Some(buffer) = self.buffer.take() {
+            self.client
+                .map(|client| client.write_done(buffer, self.length.get()));
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+#[derive(Default)]
+struct Client {
+    compacted: Cell<Option<Result<usize, ErrorCode>>>,
+    set_up: Cell<Option<Result<(), ErrorCode>>>,
+}
+
+impl DynamicBinaryStoreClient for Client {
+    fn setup_done(&self, result: Result<(), ErrorCode>) {
+        self.set_up.set(Some(result));
+    }
+
+    fn write_done(&self, _result: Result<(), ErrorCode>, _buffer: &'static mut [u8], _len: usize) {}
+
+    fn finalize_done(&self, _result: Result<(), ErrorCode>) {}
+
+    fn abort_done(&self, _result: Result<(), ErrorCode>) {}
+}
+
+impl DynamicProcessUpdateClient for Client {
+    fn uninstall_done(&self, _result: Result<(), ErrorCode>) {}
+
+    fn upgrade_done(&self, _result: Result<(), ErrorCode>) {}
+
+    fn commit_done(&self, _result: Result<(), ErrorCode>) {}
+
+    fn rollback_done(&self, _result: Result<(), ErrorCode>) {}
+
+    fn compact_done(&self, result: Result<usize, ErrorCode>) {
+        self.compacted.set(Some(result));
+    }
+}
+
+type Storage = SequentialDynamicBinaryStorage<
+    'static,
+    'static,
+    HostChip,
+    ProcessStandardDebugFull,
+    MappedFlash,
+>;
+
+struct Board {
+    flash: &'static MappedFlash,
+    storage: &'static Storage,
+    client: &'static Client,
+}
+
+/// Loads the processes in the bank, and sets up the storage with the
+/// journal, like a board does at boot.
+fn boot(env: &Environment, memory: Memory) -> Board {
+    let checker = leak(ProcessCheckerMachine::new(leak(AppCheckerNull::new())));
+    let loader = leak(SequentialProcessLoaderMachine::new(
+        checker,
+        leak([const { None::<ProcessBinary> }; NUM_PROCS]),
+        env.kernel(),
+        env.chip(),
+        memory.bank(),
+        leak_buffer(APP_MEMORY_SIZE),
+        leak(PanicFaultPolicy {}),
+        leak(NullStoragePermissions::<HostChip, ProcessStandardDebugFull>::new()),
+        leak(AppIdAssignerSimulated {}),
+        &Capability,
+    ));
+    checker.set_client(loader);
+    loader.register();
+    loader.start();
+    env.run();
+
+    let flash = MappedFlash::new(memory);
+    let storage = leak(SequentialDynamicBinaryStorage::new(
+        flash,
+        loader,
+        leak_buffer(BUF_LEN),
+    ));
+    flash.set_client(storage);
+    loader.set_runtime_client(storage);
+    storage.register();
+    storage.set_compaction_journal(memory.journal());
+    let client = leak(Client::default());
+    storage.set_storage_client(client);
+    storage.set_update_client(client);
+    Board {
+        flash,
+        storage,
+        client,
+    }
+}
+
+/// Builds a TBF of `APP_SIZE` bytes with Main, Package Name and Kernel
+/// Version headers.
+fn app(name: &str) -> Vec<u8> {
+    let name_size = name.len().next_multiple_of(4);
+    let header_size = 16 + (4 + 12) + (4 + 4) + (4 + name_size);
+
+    let mut tbf = Vec::with_capacity(APP_SIZE);
+    tbf.extend_from_slice(&2u16.to_le_bytes());
+    tbf.extend_from_slice(&(header_size as u16).to_le_bytes());
+    tbf.extend_from_slice(&(APP_SIZE as u32).to_le_bytes());
+    tbf.extend_from_slice(&1u32.to_le_bytes());
+    tbf.extend_from_slice(&0u32.to_le_bytes());
+    tbf.extend_from_slice(&1u16.to_le_bytes());
+    tbf.extend_from_slice(&12u16.to_le_bytes());
+    tbf.extend_from_slice(&0u32.to_le_bytes());
+    tbf.extend_from_slice(&0u32.to_le_bytes());
+    tbf.extend_from_slice(&(32 * 1024u32).to_le_bytes());
+    tbf.extend_from_slice(&8u16.to_le_bytes());
+    tbf.extend_from_slice(&4u16.to_le_bytes());
+    tbf.extend_from_slice(&kernel::KERNEL_MAJOR_VERSION.to_le_bytes());
+    tbf.extend_from_slice(&kernel::KERNEL_MINOR_VERSION.to_le_bytes());
+    tbf.extend_from_slice(&3u16.to_le_bytes());
+    tbf.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    tbf.extend_from_slice(name.as_bytes());
+    tbf.resize(header_size, 0);
+    let header_checksum = checksum(&tbf);
+    tbf[12..16].copy_from_slice(&header_checksum.to_le_bytes());
+
+    // Code that differs between apps, to check that it is copied.
+    tbf.extend((header_size..APP_SIZE).map(|i| (i as u8) ^ name.as_bytes()[0]));
+    tbf
+}
+
+/// Builds the header of a padding app `len` bytes long.
+fn padding(len: usize) -> Vec<u8> {
+    let mut header = Vec::with_capacity(16);
+    header.extend_from_slice(&2u16.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(&(len as u32).to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    let header_checksum = checksum(&header);
+    header[12..16].copy_from_slice(&header_checksum.to_le_bytes());
+    header
+}
+
+/// XOR of the words of a TBF header, except for the checksum word.
+fn checksum(header: &[u8]) -> u32 {
+    header
+        .chunks_exact(4)
+        .enumerate()
+        .filter(|(i, _)| *i != 3)
+        .fold(0, |checksum, (_, word)| {
+            checksum ^ u32::from_le_bytes(word.try_into().unwrap())
+        })
+}
+
+/// Lays out `entries` one after another in a bank.
+fn bank(entries: &[Vec<u8>]) -> Vec<u8> {
+    entries
+        .iter()
+        .flat_map(|entry| {
+            let mut entry = entry.clone();
+            entry.resize(APP_SIZE, 0);
+            entry
+        })
+        .collect()
+}
+
+/// App `a`, a gap, and app `b`, which compaction moves into the gap.
+fn gapped_bank() -> Vec<u8> {
+    bank(&[app("a"), padding(APP_SIZE), app("b")])
+}
+
+/// The bank after `b` was moved into the gap.
+fn compacted_bank() -> Vec<u8> {
+    bank(&[app("a"), app("b"), padding(APP_SIZE)])
+}
+
+/// Names of the processes, by the offset of their binary in the bank.
+fn processes(env: &Environment, memory: Memory) -> Vec<(usize, &'static str)> {
+    let mut processes: Vec<_> = env
+        .kernel()
+        .process_iter_capability(&Capability)
+        .map(|process| {
+            (
+                process.get_addresses().flash_start - memory.address(0),
+                process.get_process_name(),
+            )
+        })
+        .collect();
+    processes.sort_unstable();
+    processes
+}
+
+/// Checks that `b` was moved and runs from its new address.
+fn assert_compacted(env: &Environment, memory: Memory) {
+    let compacted = compacted_bank();
+    assert!(memory.bank()[..APP_SIZE * 2] == compacted[..APP_SIZE * 2]);
+    assert_eq!(
+        memory.bank()[APP_SIZE * 2..APP_SIZE * 2 + 16],
+        compacted[APP_SIZE * 2..APP_SIZE * 2 + 16]
+    );
+    assert_eq!(processes(env, memory), [(0, "a"), (APP_SIZE, "b")]);
+}
+
+#[test]
+fn moves_running_binary_and_restarts_its_process() {
+    let env = Environment::new();
+    let memory = Memory::new(&gapped_bank(), &[]);
+    let board = boot(&env, memory);
+    assert_eq!(processes(&env, memory), [(0, "a"), (2 * APP_SIZE, "b")]);
+
+    assert_eq!(board.storage.compact(), Ok(()));
+    env.run();
+    assert_eq!(board.client.compacted.get(), Some(Ok(1)));
+    assert_compacted(&env, memory);
+
+    // There is nothing left to move.
+    assert_eq!(board.storage.compact(), Ok(()));
+    env.run();
+    assert_eq!(board.client.compacted.get(), Some(Ok(0)));
+}
+
+#[test]
+fn resumes_after_power_loss_during_any_write() {
+    let writes = {
+        let env = Environment::new();
+        let memory = Memory::new(&gapped_bank(), &[]);
+        let board = boot(&env, memory);
+        assert_eq!(board.storage.compact(), Ok(()));
+        env.run();
+        board.flash.writes.get()
+    };
+
+    for power_cut_after in 0..writes {
+        let memory = Memory::new(&gapped_bank(), &[]);
+        {
+            let env = Environment::new();
+            let board = boot(&env, memory);
+            board.flash.power_cut_after.set(Some(power_cut_after));
+            assert_eq!(board.storage.compact(), Ok(()));
+            env.run();
+            assert_eq!(board.client.compacted.get(), None);
+        }
+
+        let env = Environment::new();
+        let board = boot(&env, memory);
+        assert_eq!(board.storage.compact(), Ok(()));
+        env.run();
+        assert!(
+            matches!(board.client.compacted.get(), Some(Ok(_))),
+            "power cut after {power_cut_after} writes",
+        );
+        assert_compacted(&env, memory);
+    }
+}
+
+#[test]
+fn replays_the_newest_valid_journal_entry() {
+    let entry = |sequence: u32, corrupt: bool| {
+        let mut words = [
+            JOURNAL_MAGIC,
+            sequence,
+            STEP_PAD_SOURCE,
+            (2 * APP_SIZE) as u32,
+            APP_SIZE as u32,
+            APP_SIZE as u32,
+            u32::MAX,
+            0,
+        ];
+        words[7] = words[..7].iter().fold(0, |checksum, word| checksum ^ word);
+        if corrupt {
+            words[7] ^= 1;
+        }
+        words
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect::<Vec<u8>>()
+    };
+    // The newer entry in slot 0 was torn, so the entry in slot 1 counts.
+    let mut journal = entry(2, true);
+    journal.resize(JOURNAL_SLOT_SIZE, 0xFF);
+    journal.extend(entry(1, false));
+    // Power was lost after the copy was committed, but before the old binary
+    // was replaced with a padding app.
+    let memory = Memory::new(&bank(&[app("a"), app("b"), app("b")]), &journal);
+
+    let env = Environment::new();
+    let board = boot(&env, memory);
+    // The binary list leads to the copy, so the old binary is a duplicate.
+    assert_eq!(processes(&env, memory), [(0, "a"), (APP_SIZE, "b")]);
+
+    assert_eq!(board.storage.compact(), Ok(()));
+    env.run();
+    assert_eq!(board.client.compacted.get(), Some(Ok(1)));
+    assert_compacted(&env, memory);
+
+    // The journal records that the move is done.
+    assert_eq!(board.storage.compact(), Ok(()));
+    env.run();
+    assert_eq!(board.client.compacted.get(), Some(Ok(0)));
+}
+
+#[test]
+fn setup_without_room_starts_compaction() {
+    let env = Environment::new();
+    let memory = Memory::new(&gapped_bank(), &[]);
+    let board = boot(&env, memory);
+
+    assert_eq!(board.storage.setup(2 * APP_SIZE), Err(ErrorCode::BUSY));
+    env.run();
+    assert_eq!(board.client.compacted.get(), Some(Ok(1)));
+    assert_compacted(&env, memory);
+
+    assert_eq!(board.storage.setup(2 * APP_SIZE), Ok(2 * APP_SIZE));
+    env.run();
+    assert_eq!(board.client.set_up.get(), Some(Ok(())));
+}
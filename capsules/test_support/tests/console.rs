@@ -0,0 +1,93 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use capsules_core::console::{self, Console};
+use capsules_test_support::uart::MockUart;
+use capsules_test_support::{Environment, leak, leak_buffer};
+use kernel::hil::uart::{Receive, Transmit};
+use kernel::syscall::SyscallReturn;
+
+const WRITE_DONE: usize = 1;
+const READ_DONE: usize = 2;
+
+fn console(env: &Environment) -> &'static MockUart<'static> {
+    let uart = MockUart::new();
+    let console = leak(Console::new(
+        uart,
+        leak_buffer(console::DEFAULT_BUF_SIZE),
+        leak_buffer(console::DEFAULT_BUF_SIZE),
+        env.create_grant(console::DRIVER_NUM),
+    ));
+    uart.set_transmit_client(console);
+    uart.set_receive_client(console);
+    env.add_driver(console::DRIVER_NUM, console);
+    uart
+}
+
+#[test]
+fn write_longer_than_kernel_buffer() {
+    let env = Environment::new();
+    let uart = console(&env);
+    let app = env.load_app("console");
+
+    let message: Vec<u8> = (0..150u8).collect();
+    let buffer = app.buffer(message.len());
+    app.write(buffer, &message);
+    app.subscribe(console::DRIVER_NUM, WRITE_DONE);
+    app.allow_readonly(console::DRIVER_NUM, 1, buffer);
+    assert!(matches!(
+        app.command(console::DRIVER_NUM, 1, message.len(), 0),
+        SyscallReturn::Success
+    ));
+
+    let upcall = app.yield_wait();
+    assert_eq!(upcall.subdriver_number, WRITE_DONE);
+    assert_eq!(upcall.arguments[0], message.len());
+    assert_eq!(uart.take_transmitted(), message);
+}
+
+#[test]
+fn read_waits_for_enough_input() {
+    let env = Environment::new();
+    let uart = console(&env);
+    let app = env.load_app("console");
+
+    let buffer = app.buffer(5);
+    app.subscribe(console::DRIVER_NUM, READ_DONE);
+    app.allow_readwrite(console::DRIVER_NUM, 1, buffer);
+    assert!(matches!(
+        app.command(console::DRIVER_NUM, 2, 5, 0),
+        SyscallReturn::Success
+    ));
+
+    uart.receive(b"abc");
+    assert_eq!(app.yield_no_wait(), None);
+
+    uart.receive(b"de");
+    let upcall = app.yield_wait();
+    assert_eq!(upcall.subdriver_number, READ_DONE);
+    assert_eq!(upcall.arguments, [0, 5, 0]);
+    assert_eq!(app.read(buffer), b"abcde");
+}
+
+#[test]
+fn aborted_read_returns_partial_input() {
+    let env = Environment::new();
+    let uart = console(&env);
+    let app = env.load_app("console");
+
+    let buffer = app.buffer(8);
+    app.subscribe(console::DRIVER_NUM, READ_DONE);
+    app.allow_readwrite(console::DRIVER_NUM, 1, buffer);
+    app.command(console::DRIVER_NUM, 2, 8, 0);
+    uart.receive(b"hi");
+    assert!(matches!(
+        app.command(console::DRIVER_NUM, 3, 0, 0),
+        SyscallReturn::Success
+    ));
+
+    let upcall = app.yield_wait();
+    assert_eq!(upcall.arguments[1], 2);
+    assert_eq!(app.read(buffer)[..2], *b"hi");
+}
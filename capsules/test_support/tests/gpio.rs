@@ -0,0 +1,64 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use capsules_core::gpio::{self, GPIO};
+use capsules_test_support::gpio::MockPin;
+use capsules_test_support::{Environment, leak};
+use kernel::hil::gpio::{InterruptValueWrapper, InterruptWithValue};
+use kernel::syscall::SyscallReturn;
+
+fn gpio(env: &Environment) -> [&'static MockPin<'static>; 2] {
+    let pins = [MockPin::new(), MockPin::new()];
+    let wrappers = leak(pins.map(|pin| Some(leak(InterruptValueWrapper::new(pin)).finalize())));
+    let driver = leak(GPIO::new(wrappers, env.create_grant(gpio::DRIVER_NUM)));
+    for wrapper in wrappers.iter().flatten() {
+        wrapper.set_client(driver);
+    }
+    env.add_driver(gpio::DRIVER_NUM, driver);
+    pins
+}
+
+#[test]
+fn output_follows_commands() {
+    let env = Environment::new();
+    let [_, pin] = gpio(&env);
+    let app = env.load_app("blink");
+
+    assert!(matches!(
+        app.command(gpio::DRIVER_NUM, 1, 1, 0),
+        SyscallReturn::Success
+    ));
+    app.command(gpio::DRIVER_NUM, 2, 1, 0);
+    assert!(pin.output_level());
+    app.command(gpio::DRIVER_NUM, 4, 1, 0);
+    assert!(!pin.output_level());
+    assert!(matches!(
+        app.command(gpio::DRIVER_NUM, 2, 2, 0),
+        SyscallReturn::Failure(kernel::ErrorCode::INVAL)
+    ));
+}
+
+#[test]
+fn rising_edge_interrupt_reports_pin_and_level() {
+    let env = Environment::new();
+    let [pin, _] = gpio(&env);
+    let app = env.load_app("button");
+
+    app.subscribe(gpio::DRIVER_NUM, 0);
+    app.command(gpio::DRIVER_NUM, 5, 0, 2);
+    assert!(matches!(
+        app.command(gpio::DRIVER_NUM, 7, 0, 1),
+        SyscallReturn::Success
+    ));
+
+    pin.set_input(true);
+    assert_eq!(app.yield_wait().arguments, [0, 1, 0]);
+    assert!(matches!(
+        app.command(gpio::DRIVER_NUM, 6, 0, 0),
+        SyscallReturn::SuccessU32(1)
+    ));
+
+    pin.set_input(false);
+    assert_eq!(app.yield_no_wait(), None);
+}
@@ -0,0 +1,128 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use capsules_core::console::{self, Console};
+use capsules_system::process_policies::FixedGrantQuotaPolicy;
+use capsules_test_support::uart::MockUart;
+use capsules_test_support::{App, Environment, leak, leak_buffer};
+use kernel::ErrorCode;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::introspection::KernelInfo;
+use kernel::syscall::SyscallReturn;
+
+/// A second console, so that apps can allocate two grants.
+const OTHER_CONSOLE_DRIVER_NUM: usize = 0x9001;
+const WRITE_DONE: usize = 1;
+
+struct Capability;
+unsafe impl ProcessManagementCapability for Capability {}
+
+fn add_console(env: &Environment, driver_num: usize) {
+    let console = leak(Console::new(
+        MockUart::new(),
+        leak_buffer(console::DEFAULT_BUF_SIZE),
+        leak_buffer(console::DEFAULT_BUF_SIZE),
+        env.create_grant(driver_num),
+    ));
+    env.add_driver(driver_num, console);
+}
+
+fn environment() -> Environment {
+    let env = Environment::new();
+    add_console(&env, console::DRIVER_NUM);
+    add_console(&env, OTHER_CONSOLE_DRIVER_NUM);
+    env
+}
+
+fn grant_start(env: &Environment, app: &App) -> usize {
+    env.kernel()
+        .process_map_or_external(
+            None,
+            app.processid(),
+            |process| Some(process.get_addresses().sram_grant_start),
+            &Capability,
+        )
+        .expect("the process does not exist")
+}
+
+fn subscribes(result: SyscallReturn) {
+    assert!(
+        matches!(result, SyscallReturn::SubscribeSuccess(..)),
+        "{result:?}"
+    );
+}
+
+fn usage(info: &KernelInfo, app: &App) -> Vec<(usize, usize)> {
+    let mut usage = Vec::new();
+    info.app_grant_memory_usage(app.processid(), &Capability, |driver_num, size| {
+        usage.push((driver_num, size))
+    });
+    usage
+}
+
+#[test]
+fn grant_memory_usage_is_reported_per_driver() {
+    let env = environment();
+    let info = KernelInfo::new(env.kernel());
+    let first = env.load_app("first");
+    let second = env.load_app("second");
+    assert_eq!(
+        info.app_grant_memory_used(first.processid(), &Capability),
+        0
+    );
+    assert!(usage(&info, &first).is_empty());
+
+    let before = grant_start(&env, &first);
+    subscribes(first.subscribe(console::DRIVER_NUM, WRITE_DONE));
+    let console_size = before - grant_start(&env, &first);
+    assert!(console_size > 0);
+    subscribes(first.subscribe(OTHER_CONSOLE_DRIVER_NUM, WRITE_DONE));
+    let used = before - grant_start(&env, &first);
+
+    assert_eq!(
+        info.app_grant_memory_used(first.processid(), &Capability),
+        used
+    );
+    assert_eq!(
+        usage(&info, &first),
+        [
+            (console::DRIVER_NUM, console_size),
+            (OTHER_CONSOLE_DRIVER_NUM, used - console_size)
+        ]
+    );
+
+    subscribes(second.subscribe(console::DRIVER_NUM, WRITE_DONE));
+    assert_eq!(
+        info.grant_memory_used(&Capability),
+        used + info.app_grant_memory_used(second.processid(), &Capability)
+    );
+}
+
+#[test]
+fn grants_past_the_quota_are_refused() {
+    let env = environment();
+    let info = KernelInfo::new(env.kernel());
+
+    // Measure the grant of one console without a quota.
+    let unlimited = env.load_app("unlimited");
+    subscribes(unlimited.subscribe(console::DRIVER_NUM, WRITE_DONE));
+    let quota = info.app_grant_memory_used(unlimited.processid(), &Capability);
+
+    env.set_grant_quota_policy(leak(FixedGrantQuotaPolicy::new(quota)));
+    let limited = env.load_app("limited");
+    subscribes(limited.subscribe(console::DRIVER_NUM, WRITE_DONE));
+    assert!(matches!(
+        limited.subscribe(OTHER_CONSOLE_DRIVER_NUM, WRITE_DONE),
+        SyscallReturn::SubscribeFailure(ErrorCode::NOMEM, _, _)
+    ));
+    assert!(matches!(
+        limited.command(OTHER_CONSOLE_DRIVER_NUM, 1, 0, 0),
+        SyscallReturn::Failure(ErrorCode::NOMEM)
+    ));
+    assert_eq!(
+        info.app_grant_memory_used(limited.processid(), &Capability),
+        quota
+    );
+    assert_eq!(usage(&info, &limited), [(console::DRIVER_NUM, quota)]);
+}
@@ -0,0 +1,85 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::hs3003::Hs3003;
+use capsules_extra::temperature::{self, TemperatureSensor};
+use capsules_test_support::i2c::{I2CTransaction, MockI2C};
+use capsules_test_support::{Environment, leak, leak_buffer};
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil::i2c::{self, I2CMaster};
+use kernel::hil::sensors::TemperatureDriver;
+use kernel::syscall::SyscallReturn;
+
+const ADDRESS: u8 = 0x44;
+
+fn hs3003(env: &Environment) -> &'static MockI2C<'static> {
+    let i2c = MockI2C::new();
+    let mux = leak(MuxI2C::new(i2c, None));
+    mux.register();
+    i2c.set_master_client(mux);
+
+    let device = leak(I2CDevice::new(mux, ADDRESS));
+    let sensor = leak(Hs3003::new(device, leak_buffer(5)));
+    device.set_client(sensor);
+    let driver = leak(TemperatureSensor::new(
+        sensor,
+        env.create_grant(temperature::DRIVER_NUM),
+    ));
+    TemperatureDriver::set_client(sensor, driver);
+    env.add_driver(temperature::DRIVER_NUM, driver);
+    i2c
+}
+
+#[test]
+fn reading_wakes_sensor_then_fetches_measurement() {
+    let env = Environment::new();
+    let i2c = hs3003(&env);
+    let app = env.load_app("sensor");
+
+    i2c.respond(&[0x20, 0x00, 0x40, 0x00]);
+    app.subscribe(temperature::DRIVER_NUM, 0);
+    assert!(matches!(
+        app.command(temperature::DRIVER_NUM, 1, 0, 0),
+        SyscallReturn::Success
+    ));
+
+    assert_eq!(app.yield_wait().driver_number, temperature::DRIVER_NUM);
+    assert_eq!(
+        i2c.take_transactions(),
+        [
+            I2CTransaction {
+                address: ADDRESS,
+                written: vec![0],
+                read_len: 0,
+            },
+            I2CTransaction {
+                address: ADDRESS,
+                written: vec![],
+                read_len: 4,
+            },
+        ]
+    );
+    assert!(!i2c.is_enabled());
+}
+
+#[test]
+fn sensor_recovers_from_bus_error() {
+    let env = Environment::new();
+    let i2c = hs3003(&env);
+    let app = env.load_app("sensor");
+
+    app.subscribe(temperature::DRIVER_NUM, 0);
+    i2c.fail_next(i2c::Error::AddressNak);
+    app.command(temperature::DRIVER_NUM, 1, 0, 0);
+    assert_eq!(app.yield_no_wait(), None);
+    assert_eq!(i2c.take_transactions().len(), 1);
+
+    i2c.respond(&[0x20, 0x00, 0x40, 0x00]);
+    assert!(matches!(
+        app.command(temperature::DRIVER_NUM, 1, 0, 0),
+        SyscallReturn::Success
+    ));
+    assert_eq!(app.yield_wait().driver_number, temperature::DRIVER_NUM);
+}
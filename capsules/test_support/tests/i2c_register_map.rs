@@ -0,0 +1,167 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use core::cell::RefCell;
+
+use capsules_extra::i2c_register_map::{
+    I2CRegisterMap, MuxI2CRegisterMap, RegisterAccess, RegisterMapClient, UNMAPPED_VALUE,
+};
+use capsules_test_support::i2c_target::MockI2CTarget;
+use capsules_test_support::{leak, leak_buffer};
+use kernel::ErrorCode;
+use kernel::hil::i2c::{I2CSlave, I2CSlaveMultiAddress};
+
+type Mux = MuxI2CRegisterMap<'static, MockI2CTarget<'static>>;
+
+const ACCELEROMETER: u8 = 0x1D;
+const GYROSCOPE: u8 = 0x6B;
+
+/// Records the register writes of a map.
+struct Client {
+    written: RefCell<Vec<(u8, usize)>>,
+}
+
+impl RegisterMapClient for Client {
+    fn registers_written(&self, first: u8, count: usize) {
+        self.written.borrow_mut().push((first, count));
+    }
+}
+
+fn mux(target: &'static MockI2CTarget<'static>, multi_address: bool) -> &'static Mux {
+    let multi_address: Option<&'static dyn I2CSlaveMultiAddress> =
+        if multi_address { Some(target) } else { None };
+    let mux = leak(MuxI2CRegisterMap::new(
+        target,
+        multi_address,
+        leak_buffer(8),
+        leak_buffer(4),
+    ));
+    target.set_slave_client(mux);
+    mux
+}
+
+fn map(
+    mux: &'static Mux,
+    address: u8,
+    registers: &[u8],
+    access: &'static [RegisterAccess],
+) -> (&'static I2CRegisterMap<'static>, &'static Client) {
+    let map = leak(I2CRegisterMap::new(
+        address,
+        registers.to_vec().leak(),
+        access,
+    ));
+    let client = leak(Client {
+        written: RefCell::new(Vec::new()),
+    });
+    map.set_client(client);
+    mux.add_map(map);
+    (map, client)
+}
+
+fn registers(map: &I2CRegisterMap, len: usize) -> Vec<u8> {
+    let mut data = vec![0; len];
+    map.get_registers(0, &mut data).unwrap();
+    data
+}
+
+#[test]
+fn reads_auto_increment_from_the_register_pointer() {
+    let target = MockI2CTarget::new(1);
+    let mux = mux(target, false);
+    map(mux, ACCELEROMETER, &[0x10, 0x11, 0x12, 0x13], &[]);
+    mux.start().unwrap();
+    assert!(target.is_listening());
+
+    assert!(target.master_write(ACCELEROMETER, &[1]));
+    assert_eq!(target.master_read(ACCELEROMETER, 2), Some(vec![0x11, 0x12]));
+    assert_eq!(
+        target.master_read(ACCELEROMETER, 2),
+        Some(vec![0x13, UNMAPPED_VALUE])
+    );
+    // A read is limited by the read buffer.
+    assert!(target.master_write(ACCELEROMETER, &[0]));
+    assert_eq!(
+        target.master_read(ACCELEROMETER, 8),
+        Some(vec![0x10, 0x11, 0x12, 0x13])
+    );
+}
+
+#[test]
+fn writes_skip_read_only_registers_and_notify_the_client() {
+    static ACCESS: [RegisterAccess; 3] = [
+        RegisterAccess::ReadOnly,
+        RegisterAccess::ReadWrite,
+        RegisterAccess::WriteOnly,
+    ];
+    let target = MockI2CTarget::new(1);
+    let mux = mux(target, false);
+    let (map, client) = map(mux, ACCELEROMETER, &[0x33, 0, 0, 0], &ACCESS);
+    mux.start().unwrap();
+
+    assert!(target.master_write(ACCELEROMETER, &[0, 1, 2, 3, 4, 5]));
+    assert_eq!(registers(map, 4), [0x33, 2, 3, 4]);
+    // Only the registers in the map are reported.
+    assert_eq!(*client.written.borrow(), [(0, 4)]);
+
+    // Setting the pointer alone is not a write.
+    assert!(target.master_write(ACCELEROMETER, &[1]));
+    assert_eq!(target.master_read(ACCELEROMETER, 3), Some(vec![2, 0, 4]));
+    assert_eq!(client.written.borrow().len(), 1);
+}
+
+#[test]
+fn kernel_updates_are_seen_by_the_next_read() {
+    let target = MockI2CTarget::new(1);
+    let mux = mux(target, false);
+    let (map, client) = map(mux, ACCELEROMETER, &[0; 4], &[]);
+    mux.start().unwrap();
+
+    assert!(target.master_write(ACCELEROMETER, &[2]));
+    map.set_registers(2, &[0xAB, 0xCD]).unwrap();
+    assert_eq!(target.master_read(ACCELEROMETER, 2), Some(vec![0xAB, 0xCD]));
+    assert!(client.written.borrow().is_empty());
+
+    assert_eq!(map.set_registers(3, &[1, 2]), Err(ErrorCode::INVAL));
+}
+
+#[test]
+fn maps_have_their_own_address_registers_and_pointer() {
+    let target = MockI2CTarget::new(2);
+    let mux = mux(target, true);
+    let (accelerometer, _) = map(mux, ACCELEROMETER, &[1, 2, 3], &[]);
+    let (gyroscope, gyroscope_client) = map(mux, GYROSCOPE, &[4, 5, 6], &[]);
+    mux.start().unwrap();
+    let mut addresses = target.addresses();
+    addresses.sort();
+    assert_eq!(addresses, [Some(ACCELEROMETER), Some(GYROSCOPE)]);
+
+    assert!(target.master_write(ACCELEROMETER, &[1]));
+    assert!(target.master_write(GYROSCOPE, &[2, 9]));
+    assert_eq!(target.master_read(ACCELEROMETER, 2), Some(vec![2, 3]));
+    assert_eq!(target.master_read(GYROSCOPE, 1), Some(vec![UNMAPPED_VALUE]));
+    assert_eq!(*gyroscope_client.written.borrow(), [(2, 1)]);
+    assert_eq!(registers(accelerometer, 3), [1, 2, 3]);
+    assert_eq!(registers(gyroscope, 3), [4, 5, 9]);
+
+    assert!(!target.master_write(0x50, &[0]));
+    assert_eq!(target.master_read(0x50, 1), None);
+}
+
+#[test]
+fn more_maps_than_hardware_addresses_are_not_supported() {
+    let target = MockI2CTarget::new(2);
+    let single = mux(target, false);
+    map(single, ACCELEROMETER, &[0], &[]);
+    map(single, GYROSCOPE, &[0], &[]);
+    assert_eq!(single.start(), Err(ErrorCode::NOSUPPORT));
+    assert!(!target.is_listening());
+
+    let target = MockI2CTarget::new(1);
+    let empty = mux(target, false);
+    assert_eq!(empty.start(), Err(ErrorCode::INVAL));
+    map(empty, ACCELEROMETER, &[0], &[]);
+    empty.start().unwrap();
+    assert_eq!(target.addresses(), [Some(ACCELEROMETER)]);
+}
@@ -0,0 +1,101 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use capsules_extra::littlefs::{self, CACHE_BLOCKS, Config, LittleFsDriver};
+use capsules_test_support::flash::{MockFlash, MockPage, PAGE_SIZE};
+use capsules_test_support::{App, Environment, leak, leak_buffer};
+use kernel::ErrorCode;
+use kernel::errorcode::into_statuscode;
+use kernel::hil::flash::HasClient;
+use kernel::syscall::SyscallReturn;
+
+const PAGES: usize = 16;
+const DONE: usize = 0;
+const STAT: usize = 9;
+
+fn littlefs(
+    env: &Environment,
+    flash: &'static MockFlash,
+) -> &'static LittleFsDriver<'static, MockFlash> {
+    let driver = leak(LittleFsDriver::new(
+        flash,
+        leak(MockPage::default()),
+        0,
+        Config {
+            block_size: PAGE_SIZE,
+            block_count: PAGES as u32,
+            prog_size: 16,
+        },
+        leak_buffer(CACHE_BLOCKS * PAGE_SIZE),
+        leak_buffer(PAGES.div_ceil(8)),
+        leak_buffer(littlefs::BUF_LEN),
+        env.create_grant(littlefs::DRIVER_NUM),
+    ));
+    flash.set_client(driver);
+    env.add_driver(littlefs::DRIVER_NUM, driver);
+    driver
+}
+
+/// Look up the directory of app 0x2a, returning the status of the upcall.
+fn stat(app: &App) -> usize {
+    let path = app.buffer(9);
+    app.write(path, b"/0000002a");
+    app.subscribe(littlefs::DRIVER_NUM, DONE);
+    app.allow_readonly(littlefs::DRIVER_NUM, 0, path);
+    assert!(matches!(
+        app.command(littlefs::DRIVER_NUM, STAT, 0, 0),
+        SyscallReturn::Success
+    ));
+    app.yield_wait().arguments[0]
+}
+
+#[test]
+fn mount_leaves_damaged_image_alone() {
+    let env = Environment::new();
+    let flash = MockFlash::new(PAGES);
+    let image: Vec<u8> = (0..PAGES * PAGE_SIZE).map(|i| (i * 7) as u8).collect();
+    flash.set_contents(0, &image);
+
+    let driver = littlefs(&env, flash);
+    driver.mount();
+    env.run();
+
+    let app = env.load_app("files");
+    assert_eq!(stat(&app), into_statuscode(Err(ErrorCode::OFF)));
+    assert_eq!(flash.contents(0, image.len()), image);
+}
+
+#[test]
+fn format_creates_a_file_system() {
+    let image = {
+        let env = Environment::new();
+        let flash = MockFlash::new(PAGES);
+        let driver = littlefs(&env, flash);
+
+        driver.mount();
+        assert_eq!(driver.format(), Err(ErrorCode::BUSY));
+        env.run();
+        assert!(flash.contents(0, 2 * PAGE_SIZE).iter().all(|b| *b == 0xFF));
+
+        assert_eq!(driver.format(), Ok(()));
+        env.run();
+        assert_eq!(flash.contents(8, 8), b"littlefs");
+
+        // The file system is mounted. The app has no storage permissions, so
+        // it can't look at the directory of another app.
+        let app = env.load_app("files");
+        assert_eq!(stat(&app), into_statuscode(Err(ErrorCode::NOSUPPORT)));
+        flash.contents(0, PAGES * PAGE_SIZE)
+    };
+
+    // It can be mounted again after a reset.
+    let env = Environment::new();
+    let flash = MockFlash::new(PAGES);
+    flash.set_contents(0, &image);
+    let driver = littlefs(&env, flash);
+    driver.mount();
+    env.run();
+    let app = env.load_app("files");
+    assert_eq!(stat(&app), into_statuscode(Err(ErrorCode::NOSUPPORT)));
+}
@@ -0,0 +1,120 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use core::cell::Cell;
+
+use capsules_extra::nonvolatile_counter::{NonvolatileCounter, SLOT_LEN};
+use capsules_extra::nonvolatile_to_pages::NonvolatileToPages;
+use capsules_extra::tickv_encrypted::{MonotonicCounter, MonotonicCounterClient};
+use capsules_test_support::flash::{MockFlash, MockPage, PAGE_SIZE};
+use capsules_test_support::{Environment, leak, leak_buffer};
+use kernel::ErrorCode;
+use kernel::hil;
+use kernel::hil::flash::HasClient;
+
+type Counter = NonvolatileCounter<'static, NonvolatileToPages<'static, MockFlash>>;
+
+const SLOTS: [usize; 2] = [PAGE_SIZE, 2 * PAGE_SIZE];
+
+#[derive(Default)]
+struct Client {
+    result: Cell<Option<Result<u64, ErrorCode>>>,
+}
+
+impl MonotonicCounterClient for Client {
+    fn read_complete(&self, result: Result<u64, ErrorCode>) {
+        self.result.set(Some(result));
+    }
+
+    fn increment_complete(&self, result: Result<u64, ErrorCode>) {
+        self.result.set(Some(result));
+    }
+}
+
+/// Creates a counter on `flash`, as after a reset.
+fn counter(flash: &'static MockFlash) -> (&'static Counter, &'static Client) {
+    let pages = leak(NonvolatileToPages::new(flash, leak(MockPage::default())));
+    flash.set_client(pages);
+    let counter = leak(NonvolatileCounter::new(pages, SLOTS, leak_buffer(SLOT_LEN)));
+    hil::nonvolatile_storage::NonvolatileStorage::set_client(pages, counter);
+    let client = leak(Client::default());
+    counter.set_client(client);
+    (counter, client)
+}
+
+fn read(env: &Environment, counter: &Counter, client: &Client) -> Result<u64, ErrorCode> {
+    counter.read()?;
+    env.run();
+    client.result.take().expect("read did not complete")
+}
+
+fn increment(env: &Environment, counter: &Counter, client: &Client) -> Result<u64, ErrorCode> {
+    counter.increment()?;
+    env.run();
+    client.result.take().expect("increment did not complete")
+}
+
+#[test]
+fn counter_starts_at_zero_and_survives_reset() {
+    let env = Environment::new();
+    let flash = MockFlash::new(4);
+    let (counter, client) = counter(flash);
+
+    assert_eq!(read(&env, counter, client), Ok(0));
+    assert_eq!(increment(&env, counter, client), Ok(1));
+    assert_eq!(increment(&env, counter, client), Ok(2));
+    assert_eq!(increment(&env, counter, client), Ok(3));
+
+    // The slots are written alternately.
+    assert_eq!(flash.contents(SLOTS[1], 8), 3u64.to_be_bytes());
+    assert_eq!(flash.contents(SLOTS[0], 8), 2u64.to_be_bytes());
+
+    let (counter, client) = self::counter(flash);
+    assert_eq!(read(&env, counter, client), Ok(3));
+}
+
+#[test]
+fn increment_without_read_continues_from_flash() {
+    let env = Environment::new();
+    let flash = MockFlash::new(4);
+    let (counter, client) = counter(flash);
+    assert_eq!(increment(&env, counter, client), Ok(1));
+    assert_eq!(increment(&env, counter, client), Ok(2));
+
+    let (counter, client) = self::counter(flash);
+    assert_eq!(increment(&env, counter, client), Ok(3));
+}
+
+#[test]
+fn torn_slot_falls_back_to_the_other_slot() {
+    let env = Environment::new();
+    let flash = MockFlash::new(4);
+    let (counter, client) = counter(flash);
+    for _ in 0..4 {
+        increment(&env, counter, client).unwrap();
+    }
+
+    // A power loss while writing 5 left its slot half written.
+    flash.set_contents(SLOTS[1], &5u64.to_be_bytes());
+    flash.set_contents(SLOTS[1] + 8, &[0xFF; 8]);
+
+    let (counter, client) = self::counter(flash);
+    assert_eq!(read(&env, counter, client), Ok(4));
+    assert_eq!(increment(&env, counter, client), Ok(5));
+
+    let (counter, client) = self::counter(flash);
+    assert_eq!(read(&env, counter, client), Ok(5));
+}
+
+#[test]
+fn busy_while_an_operation_is_in_progress() {
+    let env = Environment::new();
+    let flash = MockFlash::new(4);
+    let (counter, client) = counter(flash);
+
+    assert_eq!(counter.read(), Ok(()));
+    assert_eq!(counter.increment(), Err(ErrorCode::BUSY));
+    env.run();
+    assert_eq!(client.result.take(), Some(Ok(0)));
+}
@@ -0,0 +1,88 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use capsules_extra::nonvolatile_storage_driver::{self, NonvolatileStorage};
+use capsules_extra::nonvolatile_to_pages::NonvolatileToPages;
+use capsules_test_support::flash::{MockFlash, MockPage, PAGE_SIZE};
+use capsules_test_support::{Environment, leak, leak_buffer};
+use kernel::hil;
+use kernel::hil::flash::HasClient;
+use kernel::syscall::SyscallReturn;
+
+const DRIVER_NUM: usize = nonvolatile_storage_driver::DRIVER_NUM;
+const READ_DONE: usize = 0;
+const WRITE_DONE: usize = 1;
+
+/// Start of the region userspace can access, in bytes.
+const USER_START: usize = 2 * PAGE_SIZE;
+const USER_LEN: usize = 4 * PAGE_SIZE;
+
+fn storage(env: &Environment) -> &'static MockFlash {
+    let flash = MockFlash::new(8);
+    let pages = leak(NonvolatileToPages::new(flash, leak(MockPage::default())));
+    flash.set_client(pages);
+    let driver = leak(NonvolatileStorage::new(
+        pages,
+        env.create_grant(DRIVER_NUM),
+        USER_START,
+        USER_LEN,
+        0,
+        0,
+        leak_buffer(nonvolatile_storage_driver::BUF_LEN),
+    ));
+    hil::nonvolatile_storage::NonvolatileStorage::set_client(pages, driver);
+    env.add_driver(DRIVER_NUM, driver);
+    flash
+}
+
+#[test]
+fn unaligned_write_spans_pages_and_reads_back() {
+    let env = Environment::new();
+    let flash = storage(&env);
+    let app = env.load_app("storage");
+
+    let data: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    let source = app.buffer(data.len());
+    app.write(source, &data);
+    app.subscribe(DRIVER_NUM, WRITE_DONE);
+    app.allow_readonly(DRIVER_NUM, 0, source);
+    assert!(matches!(
+        app.command(DRIVER_NUM, 3, PAGE_SIZE - 100, data.len()),
+        SyscallReturn::Success
+    ));
+    assert_eq!(app.yield_wait().arguments[0], data.len());
+
+    let address = USER_START + PAGE_SIZE - 100;
+    assert_eq!(flash.contents(address, data.len()), data);
+    assert_eq!(flash.contents(address - 1, 1), [0xFF]);
+    assert_eq!(flash.contents(address + data.len(), 1), [0xFF]);
+
+    let destination = app.buffer(data.len());
+    app.subscribe(DRIVER_NUM, READ_DONE);
+    app.allow_readwrite(DRIVER_NUM, 0, destination);
+    app.command(DRIVER_NUM, 2, PAGE_SIZE - 100, data.len());
+    assert_eq!(app.yield_wait().arguments[0], data.len());
+    assert_eq!(app.read(destination), data);
+}
+
+#[test]
+fn access_outside_region_is_rejected() {
+    let env = Environment::new();
+    let flash = storage(&env);
+    let app = env.load_app("storage");
+
+    assert!(matches!(
+        app.command(DRIVER_NUM, 1, 0, 0),
+        SyscallReturn::SuccessU32(len) if len as usize == USER_LEN
+    ));
+
+    let source = app.buffer(16);
+    app.allow_readonly(DRIVER_NUM, 0, source);
+    assert!(matches!(
+        app.command(DRIVER_NUM, 3, USER_LEN - 8, 16),
+        SyscallReturn::Failure(_)
+    ));
+    assert_eq!(app.yield_no_wait(), None);
+    assert_eq!(flash.contents(USER_START + USER_LEN - 8, 16), [0xFF; 16]);
+}
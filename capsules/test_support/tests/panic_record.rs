@@ -0,0 +1,242 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use capsules_extra::nonvolatile_to_pages::NonvolatileToPages;
+use capsules_extra::panic_record::{self, PanicRecordDriver};
+use capsules_test_support::flash::{MockFlash, MockPage, PAGE_SIZE};
+use capsules_test_support::{Environment, leak};
+use kernel::ErrorCode;
+use kernel::hil::flash::HasClient;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+use kernel::panic_record::{LOG_LEN, MESSAGE_LEN, PanicRecord, RECORD_LEN};
+use kernel::syscall::{SyscallDriver, SyscallReturn};
+
+const DRIVER_NUM: usize = panic_record::DRIVER_NUM;
+const STORAGE_PAGES: usize = RECORD_LEN.div_ceil(PAGE_SIZE);
+
+const MAGIC_PANICKED: u32 = 0x504e_4943;
+const MESSAGE_OFFSET: usize = 24;
+const LOG_OFFSET: usize = MESSAGE_OFFSET + MESSAGE_LEN;
+
+/// A record as the kernel leaves it when it panics.
+fn panicked(pc: u32, lr: u32, message: &[u8], log: &[u8]) -> &'static mut PanicRecord {
+    let bytes = leak(PanicRecord::new()).into_bytes();
+    for (index, word) in [
+        MAGIC_PANICKED,
+        0,
+        pc,
+        lr,
+        message.len() as u32,
+        log.len() as u32,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        bytes[4 * index..4 * index + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    bytes[MESSAGE_OFFSET..MESSAGE_OFFSET + message.len()].copy_from_slice(message);
+    bytes[LOG_OFFSET..LOG_OFFSET + log.len()].copy_from_slice(log);
+
+    // The checksum is an FNV-1a hash of the record without the checksum.
+    let checksum = bytes[..4]
+        .iter()
+        .chain(&bytes[8..])
+        .fold(0x811c_9dc5_u32, |hash, byte| {
+            (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+        });
+    bytes[4..8].copy_from_slice(&checksum.to_le_bytes());
+    PanicRecord::from_bytes(bytes).unwrap()
+}
+
+/// A record that doesn't hold a panic, as after a power-on reset.
+fn empty() -> &'static mut PanicRecord {
+    let record = leak(PanicRecord::new());
+    record.clear();
+    record
+}
+
+fn driver(
+    env: &Environment,
+    record: &'static mut PanicRecord,
+    flash: Option<&'static MockFlash>,
+) -> &'static PanicRecordDriver<'static> {
+    let pages = flash.map(|flash| {
+        let pages = leak(NonvolatileToPages::new(flash, leak(MockPage::default())));
+        flash.set_client(pages);
+        &*pages
+    });
+    let driver = leak(PanicRecordDriver::new(
+        record,
+        pages.map(|pages| pages as &dyn NonvolatileStorage),
+        0,
+        env.create_grant(DRIVER_NUM),
+    ));
+    if let Some(pages) = pages {
+        pages.set_client(driver);
+    }
+    env.add_driver(DRIVER_NUM, driver);
+    driver.init();
+    env.run();
+    driver
+}
+
+/// A flash with the contents of `flash`, as after a reset.
+fn reset(flash: &MockFlash) -> &'static MockFlash {
+    let len = STORAGE_PAGES * PAGE_SIZE;
+    let new_flash = MockFlash::new(STORAGE_PAGES);
+    new_flash.set_contents(0, &flash.contents(0, len));
+    new_flash
+}
+
+#[test]
+fn reports_the_panic_of_the_previous_boot() {
+    let env = Environment::new();
+    driver(
+        &env,
+        panicked(0x1234, 0x5678, b"panicked at main.rs", b"booting\n"),
+        None,
+    );
+    let app = env.load_app("reporter");
+
+    assert!(matches!(
+        app.command(DRIVER_NUM, 1, 0, 0),
+        SyscallReturn::SuccessU32U32(0x1234, 0x5678)
+    ));
+
+    let buffer = app.buffer(64);
+    app.allow_readwrite(DRIVER_NUM, 0, buffer);
+    assert!(matches!(
+        app.command(DRIVER_NUM, 2, 0, 0),
+        SyscallReturn::SuccessU32(19)
+    ));
+    assert_eq!(&app.read(buffer)[..19], b"panicked at main.rs");
+    assert!(matches!(
+        app.command(DRIVER_NUM, 3, 0, 0),
+        SyscallReturn::SuccessU32(8)
+    ));
+    assert_eq!(&app.read(buffer)[..8], b"booting\n");
+
+    assert!(matches!(
+        app.command(DRIVER_NUM, 4, 0, 0),
+        SyscallReturn::Success
+    ));
+    assert!(matches!(
+        app.command(DRIVER_NUM, 1, 0, 0),
+        SyscallReturn::Failure(ErrorCode::NODEVICE)
+    ));
+    assert!(matches!(
+        app.command(DRIVER_NUM, 2, 0, 0),
+        SyscallReturn::Failure(ErrorCode::NODEVICE)
+    ));
+}
+
+#[test]
+fn copies_are_cut_to_the_allowed_buffer() {
+    let env = Environment::new();
+    let log = [b'x'; LOG_LEN];
+    driver(&env, panicked(0, 0, b"oops", &log), None);
+    let app = env.load_app("reporter");
+
+    // The architecture didn't record the registers.
+    assert!(matches!(
+        app.command(DRIVER_NUM, 1, 0, 0),
+        SyscallReturn::SuccessU32U32(0, 0)
+    ));
+    let buffer = app.buffer(16);
+    app.allow_readwrite(DRIVER_NUM, 0, buffer);
+    assert!(matches!(
+        app.command(DRIVER_NUM, 3, 0, 0),
+        SyscallReturn::SuccessU32(16)
+    ));
+}
+
+#[test]
+fn without_a_panic_there_is_no_record() {
+    let env = Environment::new();
+    driver(&env, empty(), None);
+    let app = env.load_app("reporter");
+    assert!(matches!(
+        app.command(DRIVER_NUM, 1, 0, 0),
+        SyscallReturn::Failure(ErrorCode::NODEVICE)
+    ));
+}
+
+#[test]
+fn the_record_is_kept_in_storage_until_it_is_cleared() {
+    let mut flash;
+    {
+        let env = Environment::new();
+        flash = MockFlash::new(STORAGE_PAGES);
+        driver(&env, panicked(0x1234, 0, b"oops", b""), Some(flash));
+        assert_eq!(flash.contents(0, 4), MAGIC_PANICKED.to_le_bytes().to_vec());
+    }
+    {
+        // After a power loss, the record is read back from the storage.
+        let env = Environment::new();
+        flash = reset(flash);
+        driver(&env, empty(), Some(flash));
+        let app = env.load_app("reporter");
+        assert!(matches!(
+            app.command(DRIVER_NUM, 1, 0, 0),
+            SyscallReturn::SuccessU32U32(0x1234, 0)
+        ));
+        let buffer = app.buffer(16);
+        app.allow_readwrite(DRIVER_NUM, 0, buffer);
+        assert!(matches!(
+            app.command(DRIVER_NUM, 2, 0, 0),
+            SyscallReturn::SuccessU32(4)
+        ));
+        assert_eq!(&app.read(buffer)[..4], b"oops");
+
+        assert!(matches!(
+            app.command(DRIVER_NUM, 4, 0, 0),
+            SyscallReturn::Success
+        ));
+        env.run();
+        assert_eq!(flash.contents(0, 4), vec![0; 4]);
+    }
+    {
+        let env = Environment::new();
+        flash = reset(flash);
+        driver(&env, empty(), Some(flash));
+        let app = env.load_app("reporter");
+        assert!(matches!(
+            app.command(DRIVER_NUM, 1, 0, 0),
+            SyscallReturn::Failure(ErrorCode::NODEVICE)
+        ));
+    }
+}
+
+#[test]
+fn clearing_while_saving_clears_the_storage_afterwards() {
+    let env = Environment::new();
+    let flash = MockFlash::new(STORAGE_PAGES);
+    let pages = leak(NonvolatileToPages::new(flash, leak(MockPage::default())));
+    flash.set_client(pages);
+    let driver = leak(PanicRecordDriver::new(
+        panicked(0, 0, b"oops", b""),
+        Some(pages),
+        0,
+        env.create_grant(DRIVER_NUM),
+    ));
+    pages.set_client(driver);
+    env.add_driver(DRIVER_NUM, driver);
+    let app = env.load_app("reporter");
+    driver.init();
+
+    // Syscalls let the kernel run, so call the driver directly while the
+    // record is still being written to the storage.
+    let processid = app.processid();
+    assert_eq!(
+        driver.command(1, 0, 0, processid).get_failure(),
+        Some(ErrorCode::BUSY)
+    );
+    assert!(driver.command(4, 0, 0, processid).is_success());
+    env.run();
+    assert_eq!(flash.contents(0, 4), vec![0; 4]);
+    assert!(matches!(
+        app.command(DRIVER_NUM, 1, 0, 0),
+        SyscallReturn::Failure(ErrorCode::NODEVICE)
+    ));
+}
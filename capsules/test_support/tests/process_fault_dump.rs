@@ -0,0 +1,237 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use capsules_extra::nonvolatile_to_pages::NonvolatileToPages;
+use capsules_extra::process_fault_dump::{self, ProcessFaultDump, RECORD_LEN};
+use capsules_test_support::flash::{MockFlash, MockPage, PAGE_SIZE};
+use capsules_test_support::{App, Environment, leak, leak_buffer};
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::hil::flash::HasClient;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+use kernel::process::{self, Process, ProcessFaultPolicy};
+use kernel::syscall::SyscallReturn;
+
+const DRIVER_NUM: usize = process_fault_dump::DRIVER_NUM;
+const SLOTS: usize = 3;
+
+struct Capability;
+unsafe impl ProcessManagementCapability for Capability {}
+
+struct Policy(process::FaultAction);
+
+impl ProcessFaultPolicy for Policy {
+    fn action(&self, _process: &dyn Process) -> process::FaultAction {
+        self.0
+    }
+}
+
+/// Memory that keeps its contents across a simulated reset, like the
+/// `.noinit` section the panic buffer is in on a board.
+struct PanicBuffer(*mut u8);
+
+impl PanicBuffer {
+    fn new() -> Self {
+        Self(leak_buffer(RECORD_LEN).as_mut_ptr())
+    }
+
+    fn take(&self) -> &'static mut [u8] {
+        // # Safety
+        //
+        // The buffer is leaked, and the capsule of the previous boot, which
+        // used it before, is no longer used.
+        unsafe { core::slice::from_raw_parts_mut(self.0, RECORD_LEN) }
+    }
+}
+
+/// Sets up the capsule on `flash`, with `action` as the action of the fault
+/// policy it wraps.
+fn fault_dump(
+    env: &Environment,
+    flash: &'static MockFlash,
+    panic_buffer: &PanicBuffer,
+    action: process::FaultAction,
+) -> &'static ProcessFaultDump<'static> {
+    let pages = leak(NonvolatileToPages::new(flash, leak(MockPage::default())));
+    flash.set_client(pages);
+    let fault_dump = leak(ProcessFaultDump::new(
+        pages,
+        0,
+        SLOTS * RECORD_LEN,
+        leak_buffer(RECORD_LEN),
+        panic_buffer.take(),
+        leak(Policy(action)),
+        env.create_grant(DRIVER_NUM),
+    ));
+    pages.set_client(fault_dump);
+    env.add_driver(DRIVER_NUM, fault_dump);
+    fault_dump.init();
+    env.run();
+    fault_dump
+}
+
+/// Lets `fault_dump` handle a fault of `app`, as the kernel would.
+fn fault(
+    env: &Environment,
+    app: &App,
+    fault_dump: &ProcessFaultDump<'static>,
+) -> Option<process::FaultAction> {
+    let action = env.kernel().process_map_or_external(
+        None,
+        app.processid(),
+        |process| Some(fault_dump.action(process)),
+        &Capability,
+    );
+    env.run();
+    action
+}
+
+/// A flash with the contents of `flash`, as after a reset.
+fn reset(flash: &MockFlash) -> &'static MockFlash {
+    let len = SLOTS * RECORD_LEN;
+    let new_flash = MockFlash::new(len / PAGE_SIZE);
+    new_flash.set_contents(0, &flash.contents(0, len));
+    new_flash
+}
+
+fn u32_at(record: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(record[offset..offset + 4].try_into().unwrap())
+}
+
+fn record(flash: &MockFlash, slot: usize) -> Vec<u8> {
+    flash.contents(slot * RECORD_LEN, RECORD_LEN)
+}
+
+#[test]
+fn records_recent_syscalls_and_mpu_regions() {
+    let env = Environment::new();
+    let flash = MockFlash::new(SLOTS * RECORD_LEN / PAGE_SIZE);
+    let fault_dump = fault_dump(&env, flash, &PanicBuffer::new(), process::FaultAction::Stop);
+    let app = env.load_app("faulty");
+
+    for subdriver in 0..10 {
+        app.command(0x9999, subdriver, 0, 0);
+    }
+    assert!(matches!(
+        fault(&env, &app, fault_dump),
+        Some(process::FaultAction::Stop)
+    ));
+    assert_eq!(fault_dump.record_count(), 1);
+
+    let record = record(flash, 0);
+    assert_eq!(u32_at(&record, 4), 2);
+    assert_eq!(u32_at(&record, 28), 10);
+    // The last 8 syscalls, newest first: command class, driver, subdriver.
+    for index in 0..8 {
+        let offset = 88 + 20 * index;
+        assert_eq!(u32_at(&record, offset), 2);
+        assert_eq!(u32_at(&record, offset + 4), 0x9999);
+        assert_eq!(u32_at(&record, offset + 8), 9 - index as u32);
+    }
+
+    // The test MPU reports the memory the process can access as region 0.
+    let (start, app_break) = env
+        .kernel()
+        .process_map_or_external(
+            None,
+            app.processid(),
+            |process| {
+                let addresses = process.get_addresses();
+                Some((addresses.sram_start, addresses.sram_app_brk))
+            },
+            &Capability,
+        )
+        .unwrap();
+    assert_eq!(u32_at(&record, 248), start as u32);
+    assert_eq!(u32_at(&record, 252), (app_break - start) as u32);
+    assert_eq!(u32_at(&record, 256), 2);
+    for region in 1..16 {
+        assert_eq!(u32_at(&record, 248 + 12 * region + 8), 0);
+    }
+}
+
+#[test]
+fn faults_that_panic_are_recorded_after_the_reset() {
+    let panic_buffer = PanicBuffer::new();
+    let mut flash;
+    {
+        let env = Environment::new();
+        flash = MockFlash::new(SLOTS * RECORD_LEN / PAGE_SIZE);
+        let fault_dump = fault_dump(&env, flash, &panic_buffer, process::FaultAction::Stop);
+        let app = env.load_app("faulty");
+        fault(&env, &app, fault_dump);
+        assert_eq!(fault_dump.record_count(), 1);
+    }
+    {
+        let env = Environment::new();
+        flash = reset(flash);
+        let fault_dump = fault_dump(&env, flash, &panic_buffer, process::FaultAction::Panic);
+        let app = env.load_app("faulty");
+        app.command(0x9999, 7, 0, 0);
+        assert!(matches!(
+            fault(&env, &app, fault_dump),
+            Some(process::FaultAction::Panic)
+        ));
+        // The kernel panics before the record could be written.
+        assert_eq!(fault_dump.record_count(), 1);
+        assert_eq!(fault_dump.dropped_count(), 0);
+    }
+    {
+        // The record is written once the records of previous boots are
+        // found.
+        let env = Environment::new();
+        flash = reset(flash);
+        let fault_dump = fault_dump(&env, flash, &panic_buffer, process::FaultAction::Stop);
+        assert_eq!(fault_dump.record_count(), 2);
+        let record = record(flash, 1);
+        assert_eq!(u32_at(&record, 8), 1);
+        assert_eq!(u32_at(&record, 88 + 8), 7);
+    }
+    {
+        // It is only written once.
+        let env = Environment::new();
+        flash = reset(flash);
+        let fault_dump = fault_dump(&env, flash, &panic_buffer, process::FaultAction::Stop);
+        assert_eq!(fault_dump.record_count(), 2);
+        assert_eq!(u32_at(&record(flash, 2), 0), 0xFFFF_FFFF);
+    }
+}
+
+#[test]
+fn reads_the_newest_record_first() {
+    let env = Environment::new();
+    let flash = MockFlash::new(SLOTS * RECORD_LEN / PAGE_SIZE);
+    let fault_dump = fault_dump(
+        &env,
+        flash,
+        &PanicBuffer::new(),
+        process::FaultAction::Restart,
+    );
+    let app = env.load_app("faulty");
+    for _ in 0..SLOTS + 1 {
+        fault(&env, &app, fault_dump);
+    }
+    assert!(matches!(
+        app.command(DRIVER_NUM, 1, 0, 0),
+        SyscallReturn::SuccessU32(3)
+    ));
+
+    let buffer = app.buffer(RECORD_LEN);
+    app.allow_readwrite(DRIVER_NUM, 0, buffer);
+    app.subscribe(DRIVER_NUM, 0);
+    for index in 0..SLOTS {
+        assert!(matches!(
+            app.command(DRIVER_NUM, 2, index, 0),
+            SyscallReturn::Success
+        ));
+        let upcall = app.yield_wait();
+        assert_eq!(upcall.arguments[1], index);
+        let record = app.read(buffer);
+        assert_eq!(upcall.arguments[0], u32_at(&record, 12) as usize);
+        assert_eq!(u32_at(&record, 8), SLOTS as u32 - index as u32);
+    }
+    assert!(matches!(
+        app.command(DRIVER_NUM, 2, SLOTS, 0),
+        SyscallReturn::Failure(kernel::ErrorCode::INVAL)
+    ));
+}
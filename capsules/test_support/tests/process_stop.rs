@@ -0,0 +1,79 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use capsules_test_support::{App, Environment, leak};
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::process::{State, StoppedState};
+use kernel::syscall::{CommandReturn, SyscallDriver, SyscallReturn};
+use kernel::{ErrorCode, Kernel, ProcessId};
+
+const DRIVER_NUM: usize = 0x9002;
+
+struct Capability;
+unsafe impl ProcessManagementCapability for Capability {}
+
+/// Stops the calling process on command 1, and returns 7.
+struct StopDriver {
+    kernel: &'static Kernel,
+}
+
+impl SyscallDriver for StopDriver {
+    fn command(
+        &self,
+        command_num: usize,
+        _: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => {
+                self.kernel
+                    .process_map_or_external((), processid, |p| p.stop(), &Capability);
+                CommandReturn::success_u32(7)
+            }
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, _processid: ProcessId) -> Result<(), kernel::process::Error> {
+        Ok(())
+    }
+}
+
+fn state(env: &Environment, app: &App) -> State {
+    env.kernel()
+        .process_map_or_external(None, app.processid(), |p| Some(p.get_state()), &Capability)
+        .expect("the process does not exist")
+}
+
+#[test]
+fn process_stopped_by_a_syscall_stays_stopped_until_resumed() {
+    let env = Environment::new();
+    env.add_driver(
+        DRIVER_NUM,
+        leak(StopDriver {
+            kernel: env.kernel(),
+        }),
+    );
+    let app = env.load_app("stopped");
+
+    // The return value is set, but the process does not run again.
+    assert!(matches!(
+        app.command_that_stops(DRIVER_NUM, 1, 0, 0),
+        SyscallReturn::SuccessU32(7)
+    ));
+    assert_eq!(state(&env, &app), State::Stopped(StoppedState::Running));
+    env.run();
+    assert_eq!(state(&env, &app), State::Stopped(StoppedState::Running));
+
+    env.kernel()
+        .process_map_or_external((), app.processid(), |p| p.resume(), &Capability);
+    app.wait_until_resumed();
+    assert_eq!(state(&env, &app), State::Running);
+    assert!(matches!(
+        app.command(DRIVER_NUM, 0, 0, 0),
+        SyscallReturn::Success
+    ));
+}
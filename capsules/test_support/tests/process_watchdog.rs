@@ -0,0 +1,300 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use core::cell::Cell;
+use core::num::NonZeroU32;
+
+use capsules_extra::nonvolatile_to_pages::NonvolatileToPages;
+use capsules_extra::process_watchdog::{self, MissAction, ProcessWatchdog, RECORD_LEN};
+use capsules_system::process_checker::basic::AppCheckerNull;
+use capsules_system::process_policies::PanicFaultPolicy;
+use capsules_system::storage_permissions::null::NullStoragePermissions;
+use capsules_test_support::alarm::MockAlarm;
+use capsules_test_support::chip::HostChip;
+use capsules_test_support::environment::tbf;
+use capsules_test_support::flash::{MockFlash, MockPage};
+use capsules_test_support::{App, Environment, leak, leak_buffer};
+use kernel::ErrorCode;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil;
+use kernel::hil::flash::HasClient;
+use kernel::hil::time::Alarm;
+use kernel::platform::watchdog::WatchDog;
+use kernel::process::{
+    Process, ProcessBinary, ProcessCheckerMachine, ProcessLoadingAsync, ProcessStandardDebugFull,
+    SequentialProcessLoaderMachine, ShortId,
+};
+use kernel::process_checker::{AppUniqueness, Compress};
+use kernel::syscall::SyscallReturn;
+
+const DRIVER_NUM: usize = process_watchdog::DRIVER_NUM;
+const REGISTER: usize = 1;
+const CHECK_IN: usize = 2;
+
+const NUM_PROCS: usize = 4;
+const APP_MEMORY_SIZE: usize = 256 * 1024;
+
+/// The only app the board allows to register.
+const CRITICAL: &str = "critical";
+const CRITICAL_ID: ShortId = ShortId::Fixed(NonZeroU32::new(1).unwrap());
+
+struct Capability;
+unsafe impl ProcessManagementCapability for Capability {}
+
+/// Gives the critical app its fixed ShortId, and every other app the same
+/// fixed ShortId that the board doesn't list.
+struct ShortIds;
+
+impl AppUniqueness for ShortIds {
+    fn different_identifier(&self, process_a: &ProcessBinary, process_b: &ProcessBinary) -> bool {
+        process_a.header.get_package_name() != process_b.header.get_package_name()
+    }
+
+    fn different_identifier_process(
+        &self,
+        process_binary: &ProcessBinary,
+        process: &dyn Process,
+    ) -> bool {
+        process_binary.header.get_package_name() != Some(process.get_process_name())
+    }
+
+    fn different_identifier_processes(
+        &self,
+        process_a: &dyn Process,
+        process_b: &dyn Process,
+    ) -> bool {
+        process_a.get_process_name() != process_b.get_process_name()
+    }
+}
+
+impl Compress for ShortIds {
+    fn to_short_id(&self, process: &ProcessBinary) -> ShortId {
+        match process.header.get_package_name() {
+            Some(CRITICAL) => CRITICAL_ID,
+            _ => ShortId::Fixed(NonZeroU32::new(2).unwrap()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct MockWatchdog {
+    tickles: Cell<usize>,
+}
+
+impl WatchDog for MockWatchdog {
+    fn tickle(&self) {
+        self.tickles.set(self.tickles.get() + 1);
+    }
+}
+
+type Watchdog = ProcessWatchdog<'static, MockAlarm<'static>, MockWatchdog, Capability>;
+
+struct Setup {
+    alarm: &'static MockAlarm<'static>,
+    hardware: &'static MockWatchdog,
+    watchdog: &'static Watchdog,
+    flash: &'static MockFlash,
+}
+
+fn watchdog(env: &Environment) -> Setup {
+    let alarm = MockAlarm::new();
+    let hardware = leak(MockWatchdog::default());
+    let flash = MockFlash::new(2);
+    let pages = leak(NonvolatileToPages::new(flash, leak(MockPage::default())));
+    flash.set_client(pages);
+    let watchdog = leak(ProcessWatchdog::new(
+        alarm,
+        hardware,
+        pages,
+        0,
+        leak_buffer(RECORD_LEN),
+        env.kernel(),
+        Capability,
+        MissAction::HardwareReset,
+        leak([CRITICAL_ID]),
+    ));
+    alarm.set_alarm_client(watchdog);
+    hil::nonvolatile_storage::NonvolatileStorage::set_client(pages, watchdog);
+    env.add_driver(DRIVER_NUM, watchdog);
+    Setup {
+        alarm,
+        hardware,
+        watchdog,
+        flash,
+    }
+}
+
+/// Loads the apps called `names` with fixed ShortIds, like a board that
+/// checks credentials does.
+fn load_apps<'a>(env: &'a Environment, names: &[&str]) -> Vec<App<'a>> {
+    let mut flash: Vec<u8> = names.iter().flat_map(|name| tbf(name)).collect();
+    flash.resize(flash.len() + 64, 0xFF);
+    let memory = leak_buffer(APP_MEMORY_SIZE);
+    let memory_region = memory.as_mut_ptr();
+
+    let checker = leak(ProcessCheckerMachine::new(leak(AppCheckerNull::new())));
+    let loader = leak(SequentialProcessLoaderMachine::new(
+        checker,
+        leak([const { None::<ProcessBinary> }; NUM_PROCS]),
+        env.kernel(),
+        env.chip(),
+        flash.leak(),
+        memory,
+        leak(PanicFaultPolicy {}),
+        leak(NullStoragePermissions::<HostChip, ProcessStandardDebugFull>::new()),
+        leak(ShortIds),
+        &Capability,
+    ));
+    checker.set_client(loader);
+    loader.register();
+    loader.start();
+    env.run();
+
+    names
+        .iter()
+        .map(|name| {
+            let process = env
+                .kernel()
+                .process_iter_capability(&Capability)
+                .find(|process| process.get_process_name() == *name)
+                .unwrap_or_else(|| panic!("app {name} did not load"));
+            env.app(process.processid(), memory_region)
+        })
+        .collect()
+}
+
+fn load_critical_app(env: &Environment) -> App<'_> {
+    load_apps(env, &[CRITICAL]).remove(0)
+}
+
+fn command(app: &App, command_num: usize, data: usize) -> SyscallReturn {
+    app.command(DRIVER_NUM, command_num, data, 0)
+}
+
+/// Tickles the watchdog, and returns whether the hardware watchdog was
+/// tickled.
+fn tickle(setup: &Setup) -> bool {
+    let tickles = setup.hardware.tickles.get();
+    setup.watchdog.tickle();
+    setup.hardware.tickles.get() != tickles
+}
+
+fn process(env: &Environment, app: &App, f: impl FnOnce(&dyn Process)) {
+    env.kernel()
+        .process_map_or_external((), app.processid(), f, &Capability);
+}
+
+/// Whether a miss that resets the chip was recorded.
+fn reset_recorded(setup: &Setup) -> bool {
+    setup.flash.contents(0, 4) == (0x5744_5252u32 ^ 2).to_le_bytes()
+}
+
+#[test]
+fn check_in_keeps_hardware_watchdog_alive() {
+    let env = Environment::new();
+    let setup = watchdog(&env);
+    let app = load_critical_app(&env);
+
+    assert!(matches!(
+        command(&app, REGISTER, 100),
+        SyscallReturn::Success
+    ));
+    for _ in 0..5 {
+        setup.alarm.advance(80);
+        assert!(matches!(command(&app, CHECK_IN, 0), SyscallReturn::Success));
+        assert!(tickle(&setup));
+    }
+
+    setup.alarm.advance(120);
+    env.run();
+    assert!(reset_recorded(&setup));
+    assert!(!tickle(&setup));
+}
+
+#[test]
+fn stopped_process_misses_right_away() {
+    let env = Environment::new();
+    let setup = watchdog(&env);
+    let app = load_critical_app(&env);
+    assert!(matches!(
+        command(&app, REGISTER, 1000),
+        SyscallReturn::Success
+    ));
+
+    process(&env, &app, |process| process.stop());
+    // The hardware watchdog is tickled until the record is written.
+    assert!(tickle(&setup));
+    env.run();
+    assert!(reset_recorded(&setup));
+    assert!(!tickle(&setup));
+}
+
+#[test]
+fn terminated_process_misses_although_its_grant_is_gone() {
+    let env = Environment::new();
+    let setup = watchdog(&env);
+    let app = load_critical_app(&env);
+    assert!(matches!(
+        command(&app, REGISTER, 1000),
+        SyscallReturn::Success
+    ));
+
+    process(&env, &app, |process| process.terminate(None));
+    setup.alarm.advance(1000);
+    env.run();
+    assert!(reset_recorded(&setup));
+    assert!(!tickle(&setup));
+}
+
+#[test]
+fn unregistered_process_can_stop() {
+    let env = Environment::new();
+    let setup = watchdog(&env);
+    let app = load_critical_app(&env);
+    assert!(matches!(
+        command(&app, REGISTER, 100),
+        SyscallReturn::Success
+    ));
+    assert!(matches!(command(&app, 3, 0), SyscallReturn::Success));
+    assert!(matches!(
+        command(&app, CHECK_IN, 0),
+        SyscallReturn::Failure(ErrorCode::RESERVE)
+    ));
+
+    process(&env, &app, |process| process.stop());
+    setup.alarm.advance(1000);
+    env.run();
+    assert!(tickle(&setup));
+    assert!(!reset_recorded(&setup));
+}
+
+#[test]
+fn only_listed_processes_can_register() {
+    let env = Environment::new();
+    let setup = watchdog(&env);
+    let apps = load_apps(&env, &[CRITICAL, "listed_nowhere"]);
+    let unlisted = env.load_app("locally_unique");
+
+    for app in [&apps[1], &unlisted] {
+        assert!(matches!(
+            command(app, REGISTER, 1),
+            SyscallReturn::Failure(ErrorCode::NOSUPPORT)
+        ));
+        assert!(matches!(
+            command(app, CHECK_IN, 0),
+            SyscallReturn::Failure(ErrorCode::RESERVE)
+        ));
+        process(&env, app, |process| process.stop());
+    }
+    setup.alarm.advance(1000);
+    env.run();
+    assert!(tickle(&setup));
+    assert!(!reset_recorded(&setup));
+
+    assert!(matches!(
+        command(&apps[0], REGISTER, 100),
+        SyscallReturn::Success
+    ));
+}
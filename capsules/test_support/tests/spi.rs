@@ -0,0 +1,57 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use capsules_core::spi_controller::{self, Spi};
+use capsules_test_support::spi::MockSpiDevice;
+use capsules_test_support::{Environment, leak, leak_buffer};
+use kernel::hil::spi::SpiMasterDevice;
+use kernel::syscall::SyscallReturn;
+
+const KERNEL_BUFFER_LEN: usize = 16;
+
+fn spi(env: &Environment) -> &'static MockSpiDevice<'static> {
+    let device = MockSpiDevice::new();
+    let driver = leak(Spi::new(
+        device,
+        env.create_grant(spi_controller::DRIVER_NUM),
+    ));
+    driver.config_buffers(
+        leak_buffer(KERNEL_BUFFER_LEN),
+        leak_buffer(KERNEL_BUFFER_LEN),
+    );
+    device.set_client(driver);
+    env.add_driver(spi_controller::DRIVER_NUM, driver);
+    device
+}
+
+#[test]
+fn transfer_larger_than_kernel_buffer_is_split() {
+    let env = Environment::new();
+    let device = spi(&env);
+    let app = env.load_app("spi");
+
+    let data: Vec<u8> = (0..40).collect();
+    let response: Vec<u8> = data.iter().map(|byte| !byte).collect();
+    device.respond(&response);
+
+    let write = app.buffer(data.len());
+    let read = app.buffer(data.len());
+    app.write(write, &data);
+    app.subscribe(spi_controller::DRIVER_NUM, 0);
+    app.allow_readonly(spi_controller::DRIVER_NUM, 0, write);
+    app.allow_readwrite(spi_controller::DRIVER_NUM, 0, read);
+    assert!(matches!(
+        app.command(spi_controller::DRIVER_NUM, 2, data.len(), 0),
+        SyscallReturn::Success
+    ));
+
+    assert_eq!(app.yield_wait().arguments[0], data.len());
+    let written = device.take_written();
+    assert_eq!(
+        written.iter().map(Vec::len).collect::<Vec<_>>(),
+        [16, 16, 8]
+    );
+    assert_eq!(written.concat(), data);
+    assert_eq!(app.read(read), response);
+}
@@ -0,0 +1,202 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use core::mem::MaybeUninit;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use capsules_extra::syscall_trace::{EVENT_LEN, SyscallTraceBuffer};
+use capsules_test_support::alarm::MockAlarm;
+use capsules_test_support::uart::MockUart;
+use capsules_test_support::{App, Environment, leak, leak_buffer};
+use kernel::ErrorCode;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil::uart::Transmit;
+use kernel::syscall::SyscallReturn;
+
+/// A driver that doesn't exist, so commands fail with `NODEVICE`. The
+/// decoder knows it as `Temperature`.
+const DRIVER: u32 = 0x60000;
+
+const COMMAND: u8 = 2;
+const RETURN: u8 = 0x80;
+const DROPPED: u8 = 0xFE;
+const INFO: u8 = 0xFF;
+
+/// The TRD104 encoding of `Failure(NODEVICE)`.
+const NODEVICE: [u32; 4] = [0, ErrorCode::NODEVICE as u32, 0, 0];
+
+const DECODER: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../../tools/debugging-and-development/decode_syscall_trace.py"
+);
+
+struct Capability;
+unsafe impl ProcessManagementCapability for Capability {}
+
+/// A decoded event, as `(kind, process, timestamp, words)`.
+type Event = (u8, u16, u32, [u32; 4]);
+
+/// Traces the system calls of `env` into a ring of `slots` events, and sends
+/// up to `tx_events` events at a time.
+fn trace(
+    env: &Environment,
+    slots: usize,
+    tx_events: usize,
+) -> (&'static MockAlarm<'static>, &'static MockUart<'static>) {
+    let alarm = MockAlarm::new();
+    let uart = MockUart::new();
+    let trace = leak(SyscallTraceBuffer::new(
+        alarm,
+        uart,
+        vec![MaybeUninit::uninit(); slots].leak(),
+        leak_buffer(EVENT_LEN * tx_events),
+    ));
+    trace.register();
+    uart.set_transmit_client(trace);
+    env.kernel().set_syscall_trace(trace, &Capability);
+    (alarm, uart)
+}
+
+fn command(app: &App, arg0: usize) {
+    assert!(matches!(
+        app.command(DRIVER as usize, 1, arg0, 0),
+        SyscallReturn::Failure(ErrorCode::NODEVICE)
+    ));
+}
+
+fn parse(stream: &[u8]) -> Vec<Event> {
+    assert_eq!(stream.len() % EVENT_LEN, 0);
+    stream
+        .chunks_exact(EVENT_LEN)
+        .map(|event| {
+            assert_eq!(event[0], 0xA5, "no synchronization byte");
+            let word =
+                |offset: usize| u32::from_le_bytes(event[offset..offset + 4].try_into().unwrap());
+            (
+                event[1],
+                u16::from_le_bytes([event[2], event[3]]),
+                word(4),
+                [word(8), word(12), word(16), word(20)],
+            )
+        })
+        .collect()
+}
+
+/// Runs the host decoder on `stream`, and returns the lines of its timeline.
+fn decode(stream: &[u8]) -> Vec<String> {
+    let mut decoder = Command::new("python3")
+        .arg(DECODER)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("running the decoder requires python3");
+    decoder.stdin.take().unwrap().write_all(stream).unwrap();
+    let output = decoder.wait_with_output().unwrap();
+    assert!(output.status.success(), "the decoder failed");
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| line.trim().to_string())
+        .collect()
+}
+
+#[test]
+fn events_have_the_layout_the_decoder_expects() {
+    let env = Environment::new();
+    let (alarm, uart) = trace(&env, 16, 8);
+    let app = env.load_app("app");
+    let process = app.processid().id() as u16;
+
+    alarm.set_now(1000);
+    command(&app, 2);
+    alarm.advance(500);
+    command(&app, 3);
+    env.run();
+
+    let stream = uart.take_transmitted();
+    assert_eq!(
+        parse(&stream),
+        [
+            (INFO, 0, 0, [1000, 32, 0, 0]),
+            (COMMAND, process, 1000, [DRIVER, 1, 2, 0]),
+            (RETURN, process, 1000, NODEVICE),
+            (COMMAND, process, 1500, [DRIVER, 1, 3, 0]),
+            (RETURN, process, 1500, NODEVICE),
+        ]
+    );
+    assert_eq!(
+        decode(&stream),
+        [
+            format!(
+                "0.000000 s  [{process}] command(Temperature, 1, 0x2, 0x0) -> Failure(NODEVICE) (0.0 us)"
+            ),
+            format!(
+                "0.500000 s  [{process}] command(Temperature, 1, 0x3, 0x0) -> Failure(NODEVICE) (0.0 us)"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn events_lost_while_the_transport_is_busy_are_counted() {
+    let env = Environment::new();
+    // The ring holds 3 events.
+    let (_, uart) = trace(&env, 4, 2);
+    let app = env.load_app("app");
+    let process = app.processid().id() as u16;
+
+    uart.hold_transmissions(true);
+    let mut produced = Vec::new();
+    for arg0 in 0..4 {
+        command(&app, arg0);
+        produced.push((COMMAND, [DRIVER, 1, arg0 as u32, 0]));
+        produced.push((RETURN, NODEVICE));
+    }
+    uart.hold_transmissions(false);
+    env.run();
+
+    let stream = uart.take_transmitted();
+    let events = parse(&stream);
+    assert_eq!(events[0].0, INFO);
+    let lost = events
+        .iter()
+        .position(|event| event.0 == DROPPED)
+        .expect("no events were reported lost");
+    let dropped = events[lost].3[0] as usize;
+    let (before, after) = (&events[1..lost], &events[lost + 1..]);
+    assert!(after.iter().all(|event| event.0 != DROPPED));
+    assert!(
+        events
+            .iter()
+            .all(|event| event.1 == process || event.0 >= DROPPED)
+    );
+
+    // The oldest events that weren't sent yet are the ones lost, and the ring
+    // is full of the newest ones.
+    let kinds =
+        |events: &[Event]| -> Vec<_> { events.iter().map(|event| (event.0, event.3)).collect() };
+    assert_eq!(after.len(), 3);
+    assert_eq!(before.len() + dropped + after.len(), produced.len());
+    assert_eq!(kinds(before), produced[..before.len()]);
+    assert_eq!(kinds(after), produced[produced.len() - 3..]);
+    let lost_line = format!("!!! {dropped} events lost");
+    assert!(
+        decode(&stream)
+            .iter()
+            .any(|line| line.ends_with(&lost_line))
+    );
+
+    // The count starts over once the loss is reported.
+    command(&app, 4);
+    env.run();
+    assert_eq!(
+        parse(&uart.take_transmitted())
+            .iter()
+            .map(|event| event.0)
+            .collect::<Vec<_>>(),
+        [COMMAND, RETURN]
+    );
+}
@@ -0,0 +1,502 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use core::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+
+use capsules_aes_gcm::aes_gcm::Aes128Gcm;
+use capsules_extra::nonvolatile_counter::{NonvolatileCounter, SLOT_LEN};
+use capsules_extra::nonvolatile_to_pages::NonvolatileToPages;
+use capsules_extra::tickv::{KVSystem, KVSystemClient, TicKVKeyType};
+use capsules_extra::tickv_encrypted::{self, EncryptedKVSystem, KeyCounter, MonotonicCounter};
+use capsules_test_support::aes::MockAes;
+use capsules_test_support::flash::{MockFlash, MockPage, PAGE_SIZE};
+use capsules_test_support::{Environment, leak, leak_buffer};
+use kernel::ErrorCode;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil;
+use kernel::hil::flash::HasClient;
+use kernel::hil::symmetric_encryption::{AES, AES_BLOCK_SIZE, AESGCM};
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+
+type Counter = NonvolatileCounter<'static, NonvolatileToPages<'static, MockFlash>>;
+type Gcm = Aes128Gcm<'static, MockAes>;
+type Encrypted = EncryptedKVSystem<'static, MockKv, Gcm, Counter>;
+
+const DEVICE_KEY: [u8; 16] = [0x42; 16];
+const MAX_VALUE_LEN: usize = 32;
+const MAX_KEYS: usize = 3;
+
+enum Pending {
+    Append(&'static mut TicKVKeyType, SubSliceMut<'static, u8>),
+    Get(&'static mut TicKVKeyType, SubSliceMut<'static, u8>),
+    Invalidate(&'static mut TicKVKeyType),
+}
+
+/// A `KVSystem` holding its records in memory, with the same semantics as
+/// `TicKVSystem`. Tests can read and replace the records to simulate an
+/// attacker with access to the flash.
+struct MockKv {
+    records: RefCell<BTreeMap<TicKVKeyType, Vec<u8>>>,
+    pending: MapCell<Pending>,
+    client: OptionalCell<&'static dyn KVSystemClient<TicKVKeyType>>,
+    deferred_call: DeferredCall,
+}
+
+impl MockKv {
+    fn new() -> &'static Self {
+        let kv = leak(Self {
+            records: RefCell::new(BTreeMap::new()),
+            pending: MapCell::empty(),
+            client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+        });
+        kv.register();
+        kv
+    }
+
+    fn snapshot(&self) -> BTreeMap<TicKVKeyType, Vec<u8>> {
+        self.records.borrow().clone()
+    }
+
+    fn restore(&self, records: BTreeMap<TicKVKeyType, Vec<u8>>) {
+        *self.records.borrow_mut() = records;
+    }
+
+    fn record(&self, key: TicKVKeyType) -> Option<Vec<u8>> {
+        self.records.borrow().get(&key).cloned()
+    }
+
+    fn set_record(&self, key: TicKVKeyType, record: Vec<u8>) {
+        self.records.borrow_mut().insert(key, record);
+    }
+
+    fn start(&self, pending: Pending) {
+        self.pending.replace(pending);
+        self.deferred_call.set();
+    }
+}
+
+impl KVSystem<'static> for MockKv {
+    type K = TicKVKeyType;
+
+    fn set_client(&self, client: &'static dyn KVSystemClient<Self::K>) {
+        self.client.set(client);
+    }
+
+    fn generate_key(
+        &self,
+        unhashed_key: SubSliceMut<'static, u8>,
+        key_buf: &'static mut Self::K,
+    ) -> Result<(), (SubSliceMut<'static, u8>, &'static mut Self::K, ErrorCode)> {
+        Err((unhashed_key, key_buf, ErrorCode::NOSUPPORT))
+    }
+
+    fn append_key(
+        &self,
+        key: &'static mut Self::K,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<(), (&'static mut Self::K, SubSliceMut<'static, u8>, ErrorCode)> {
+        if self.pending.is_some() {
+            return Err((key, value, ErrorCode::BUSY));
+        }
+        self.start(Pending::Append(key, value));
+        Ok(())
+    }
+
+    fn get_value(
+        &self,
+        key: &'static mut Self::K,
+        ret_buf: SubSliceMut<'static, u8>,
+    ) -> Result<(), (&'static mut Self::K, SubSliceMut<'static, u8>, ErrorCode)> {
+        if self.pending.is_some() {
+            return Err((key, ret_buf, ErrorCode::BUSY));
+        }
+        self.start(Pending::Get(key, ret_buf));
+        Ok(())
+    }
+
+    fn invalidate_key(
+        &self,
+        key: &'static mut Self::K,
+    ) -> Result<(), (&'static mut Self::K, ErrorCode)> {
+        if self.pending.is_some() {
+            return Err((key, ErrorCode::BUSY));
+        }
+        self.start(Pending::Invalidate(key));
+        Ok(())
+    }
+
+    fn garbage_collect(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+}
+
+impl DeferredCallClient for MockKv {
+    fn handle_deferred_call(&self) {
+        let Some(pending) = self.pending.take() else {
+            return;
+        };
+        let mut records = self.records.borrow_mut();
+        match pending {
+            Pending::Append(key, value) => {
+                let result = if records.contains_key(key) {
+                    Err(ErrorCode::NOSUPPORT)
+                } else {
+                    records.insert(*key, value.as_slice().to_vec());
+                    Ok(())
+                };
+                drop(records);
+                self.client
+                    .map(|client| client.append_key_complete(result, key, value));
+            }
+            Pending::Get(key, mut ret_buf) => {
+                let result = match records.get(key) {
+                    Some(record) => {
+                        let len = record.len().min(ret_buf.len());
+                        ret_buf[..len].copy_from_slice(&record[..len]);
+                        ret_buf.slice(0..len);
+                        if len < record.len() {
+                            Err(ErrorCode::SIZE)
+                        } else {
+                            Ok(())
+                        }
+                    }
+                    None => Err(ErrorCode::NOSUPPORT),
+                };
+                drop(records);
+                self.client
+                    .map(|client| client.get_value_complete(result, key, ret_buf));
+            }
+            Pending::Invalidate(key) => {
+                let result = records.remove(key).map(|_| ()).ok_or(ErrorCode::NOSUPPORT);
+                drop(records);
+                self.client
+                    .map(|client| client.invalidate_key_complete(result, key));
+            }
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+#[derive(Default)]
+struct Client {
+    result: Cell<Option<Result<(), ErrorCode>>>,
+    value: RefCell<Vec<u8>>,
+}
+
+impl KVSystemClient<TicKVKeyType> for Client {
+    fn generate_key_complete(
+        &self,
+        _result: Result<(), ErrorCode>,
+        _unhashed_key: SubSliceMut<'static, u8>,
+        _key_buf: &'static mut TicKVKeyType,
+    ) {
+    }
+
+    fn append_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        _key: &'static mut TicKVKeyType,
+        _value: SubSliceMut<'static, u8>,
+    ) {
+        self.result.set(Some(result));
+    }
+
+    fn get_value_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        _key: &'static mut TicKVKeyType,
+        ret_buf: SubSliceMut<'static, u8>,
+    ) {
+        *self.value.borrow_mut() = ret_buf.as_slice().to_vec();
+        self.result.set(Some(result));
+    }
+
+    fn invalidate_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        _key: &'static mut TicKVKeyType,
+    ) {
+        self.result.set(Some(result));
+    }
+
+    fn garbage_collect_complete(&self, result: Result<(), ErrorCode>) {
+        self.result.set(Some(result));
+    }
+}
+
+/// The flash and K-V store that persist across resets.
+struct Device {
+    env: Environment,
+    kv: &'static MockKv,
+    counter_flash: &'static MockFlash,
+}
+
+impl Device {
+    fn new() -> Self {
+        let env = Environment::new();
+        Self {
+            env,
+            kv: MockKv::new(),
+            counter_flash: MockFlash::new(4),
+        }
+    }
+
+    /// Creates and initialises the encrypted layer, as after a reset.
+    fn boot(&self) -> Store<'_> {
+        let pages = leak(NonvolatileToPages::new(
+            self.counter_flash,
+            leak(MockPage::default()),
+        ));
+        self.counter_flash.set_client(pages);
+        let counter = leak(NonvolatileCounter::new(
+            pages,
+            [0, PAGE_SIZE],
+            leak_buffer(SLOT_LEN),
+        ));
+        hil::nonvolatile_storage::NonvolatileStorage::set_client(pages, counter);
+
+        let aes = MockAes::new();
+        let gcm = leak(Aes128Gcm::new(aes, leak_buffer(7 * AES_BLOCK_SIZE)));
+        AES::set_client(aes, gcm);
+
+        let encrypted = leak(EncryptedKVSystem::new(
+            self.kv,
+            gcm,
+            counter,
+            DEVICE_KEY,
+            leak_buffer(tickv_encrypted::buffer_len(MAX_VALUE_LEN, MAX_KEYS)),
+            leak([0; 8]),
+            leak([KeyCounter::default(); MAX_KEYS]),
+        ));
+        self.kv.set_client(encrypted);
+        AESGCM::set_client(gcm, encrypted);
+        counter.set_client(encrypted);
+
+        let client = leak(Client::default());
+        encrypted.set_client(client);
+
+        encrypted.initialise();
+        self.env.run();
+
+        Store {
+            env: &self.env,
+            encrypted,
+            client,
+        }
+    }
+}
+
+struct Store<'a> {
+    env: &'a Environment,
+    encrypted: &'static Encrypted,
+    client: &'static Client,
+}
+
+impl Store<'_> {
+    fn finish(&self) -> Result<(), ErrorCode> {
+        self.env.run();
+        self.client
+            .result
+            .take()
+            .expect("operation did not complete")
+    }
+
+    fn append(&self, key: TicKVKeyType, value: &[u8]) -> Result<(), ErrorCode> {
+        let buffer = leak_buffer(value.len());
+        buffer.copy_from_slice(value);
+        self.encrypted
+            .append_key(leak(key), SubSliceMut::new(buffer))
+            .map_err(|(_, _, e)| e)?;
+        self.finish()
+    }
+
+    fn get(&self, key: TicKVKeyType) -> Result<Vec<u8>, ErrorCode> {
+        self.encrypted
+            .get_value(leak(key), SubSliceMut::new(leak_buffer(MAX_VALUE_LEN)))
+            .map_err(|(_, _, e)| e)?;
+        self.finish()?;
+        Ok(self.client.value.take())
+    }
+
+    fn invalidate(&self, key: TicKVKeyType) -> Result<(), ErrorCode> {
+        self.encrypted
+            .invalidate_key(leak(key))
+            .map_err(|(_, e)| e)?;
+        self.finish()
+    }
+
+    /// Replace the value of `key`, like `TicKVKVStore` does.
+    fn update(&self, key: TicKVKeyType, value: &[u8]) -> Result<(), ErrorCode> {
+        self.invalidate(key)?;
+        self.append(key, value)
+    }
+}
+
+const KEY_A: TicKVKeyType = *b"key-a\0\0\0";
+const KEY_B: TicKVKeyType = *b"key-b\0\0\0";
+const KEY_C: TicKVKeyType = *b"key-c\0\0\0";
+const KEY_D: TicKVKeyType = *b"key-d\0\0\0";
+
+#[test]
+fn values_are_stored_encrypted() {
+    let device = Device::new();
+    let store = device.boot();
+
+    assert_eq!(store.append(KEY_A, b"secret value"), Ok(()));
+    assert_eq!(store.get(KEY_A).as_deref(), Ok(&b"secret value"[..]));
+
+    let record = device.kv.record(KEY_A).unwrap();
+    assert_eq!(record.len(), 9 + b"secret value".len() + 16);
+    assert!(!record.windows(6).any(|window| window == b"secret"));
+}
+
+#[test]
+fn values_survive_reset() {
+    let device = Device::new();
+    let store = device.boot();
+    store.append(KEY_A, b"one").unwrap();
+    store.append(KEY_B, b"two").unwrap();
+    store.update(KEY_A, b"three").unwrap();
+
+    let store = device.boot();
+    assert_eq!(store.get(KEY_A).as_deref(), Ok(&b"three"[..]));
+    assert_eq!(store.get(KEY_B).as_deref(), Ok(&b"two"[..]));
+    assert_eq!(store.get(KEY_C), Err(ErrorCode::NOSUPPORT));
+}
+
+#[test]
+fn restoring_an_old_value_of_one_key_is_rejected() {
+    let device = Device::new();
+    let store = device.boot();
+    store.append(KEY_A, b"old").unwrap();
+    let old_record = device.kv.record(KEY_A).unwrap();
+    store.update(KEY_A, b"new").unwrap();
+    store.append(KEY_B, b"other").unwrap();
+
+    // Only the record of KEY_A is rolled back, the counter records are left
+    // alone.
+    device.kv.set_record(KEY_A, old_record);
+    assert_eq!(store.get(KEY_A), Err(ErrorCode::FAIL));
+    assert_eq!(store.get(KEY_B).as_deref(), Ok(&b"other"[..]));
+
+    let store = device.boot();
+    assert_eq!(store.get(KEY_A), Err(ErrorCode::FAIL));
+
+    // Writing the key again recovers it.
+    store.update(KEY_A, b"newer").unwrap();
+    assert_eq!(store.get(KEY_A).as_deref(), Ok(&b"newer"[..]));
+}
+
+#[test]
+fn restoring_a_deleted_key_is_rejected() {
+    let device = Device::new();
+    let store = device.boot();
+    store.append(KEY_A, b"deleted").unwrap();
+    let record = device.kv.record(KEY_A).unwrap();
+    store.invalidate(KEY_A).unwrap();
+
+    device.kv.set_record(KEY_A, record);
+    assert_eq!(store.get(KEY_A), Err(ErrorCode::FAIL));
+    let store = device.boot();
+    assert_eq!(store.get(KEY_A), Err(ErrorCode::FAIL));
+}
+
+#[test]
+fn record_moved_to_another_key_is_rejected() {
+    let device = Device::new();
+    let store = device.boot();
+    store.append(KEY_A, b"for a").unwrap();
+    store.append(KEY_B, b"for b").unwrap();
+
+    device
+        .kv
+        .set_record(KEY_B, device.kv.record(KEY_A).unwrap());
+    assert_eq!(store.get(KEY_B), Err(ErrorCode::FAIL));
+}
+
+#[test]
+fn modified_record_is_rejected() {
+    let device = Device::new();
+    let store = device.boot();
+    store.append(KEY_A, b"value").unwrap();
+
+    let mut record = device.kv.record(KEY_A).unwrap();
+    record[10] ^= 0x01;
+    device.kv.set_record(KEY_A, record);
+    assert_eq!(store.get(KEY_A), Err(ErrorCode::FAIL));
+}
+
+#[test]
+fn rolling_back_the_whole_store_is_detected() {
+    let device = Device::new();
+    let store = device.boot();
+    store.append(KEY_A, b"a").unwrap();
+    let snapshot = device.kv.snapshot();
+    store.append(KEY_B, b"b").unwrap();
+    store.append(KEY_C, b"c").unwrap();
+
+    device.kv.restore(snapshot);
+    let store = device.boot();
+    assert_eq!(store.get(KEY_A), Err(ErrorCode::FAIL));
+
+    // The store can still be written, and remembers the rollback.
+    store.append(KEY_D, b"d").unwrap();
+    let store = device.boot();
+    assert_eq!(store.get(KEY_D).as_deref(), Ok(&b"d"[..]));
+    assert_eq!(store.get(KEY_A), Err(ErrorCode::FAIL));
+}
+
+#[test]
+fn store_one_write_behind_is_accepted() {
+    let device = Device::new();
+    let store = device.boot();
+    store.append(KEY_A, b"a").unwrap();
+    let snapshot = device.kv.snapshot();
+    store.append(KEY_B, b"b").unwrap();
+
+    // A power loss before the counter record was written looks the same as
+    // rolling back the last write, which can't be told apart.
+    device.kv.restore(snapshot);
+    let store = device.boot();
+    assert_eq!(store.get(KEY_A).as_deref(), Ok(&b"a"[..]));
+    assert_eq!(store.get(KEY_B), Err(ErrorCode::NOSUPPORT));
+}
+
+#[test]
+fn key_table_limits_the_number_of_keys() {
+    let device = Device::new();
+    let store = device.boot();
+    store.append(KEY_A, b"a").unwrap();
+    store.append(KEY_B, b"b").unwrap();
+    store.append(KEY_C, b"c").unwrap();
+    assert_eq!(store.append(KEY_D, b"d"), Err(ErrorCode::NOMEM));
+
+    // Existing keys can still be updated, and removing a key makes room.
+    store.update(KEY_A, b"a2").unwrap();
+    store.invalidate(KEY_B).unwrap();
+    store.append(KEY_D, b"d").unwrap();
+
+    let store = device.boot();
+    assert_eq!(store.get(KEY_A).as_deref(), Ok(&b"a2"[..]));
+    assert_eq!(store.get(KEY_D).as_deref(), Ok(&b"d"[..]));
+}
+
+#[test]
+fn counter_behind_the_store_fails() {
+    let device = Device::new();
+    let store = device.boot();
+    store.append(KEY_A, b"a").unwrap();
+
+    // Reusing counter values would reuse nonces, so refuse to run.
+    device.counter_flash.set_contents(0, &[0xFF; 2 * PAGE_SIZE]);
+    let store = device.boot();
+    assert_eq!(store.get(KEY_A), Err(ErrorCode::FAIL));
+    assert_eq!(store.append(KEY_B, b"b"), Err(ErrorCode::FAIL));
+}
@@ -0,0 +1,477 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use core::cell::{Cell, RefCell};
+
+use capsules_extra::net::ipv6::ip_utils::IPAddr;
+use capsules_extra::net::network_capabilities::{AddrRange, NetworkCapability, PortRange};
+use capsules_extra::net::udp::UDPHeader;
+use capsules_extra::net::udp::udp_port_table::UdpPortBindingTx;
+use capsules_extra::net::udp::udp_recv::UDPRecvClient;
+use capsules_extra::net::udp::udp_send::{UDPSendClient, UDPSender};
+use capsules_extra::udp_app_loader::{ACK_LEN, BUF_LEN, UdpAppLoader};
+use capsules_test_support::{Environment, leak, leak_buffer};
+use kernel::ErrorCode;
+use kernel::capabilities::{NetworkCapabilityCreationCapability, UdpDriverCapability};
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::dynamic_binary_storage::{
+    DynamicBinaryStore, DynamicBinaryStoreClient, DynamicProcessLoad, DynamicProcessLoadClient,
+};
+use kernel::errorcode::into_statuscode;
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+
+const START: u8 = 0x01;
+const DATA: u8 = 0x02;
+const FINISH: u8 = 0x03;
+const ABORT: u8 = 0x04;
+const STATUS: u8 = 0x05;
+const ACK: u8 = 0x80;
+
+const SESSION: u16 = 0x1234;
+const IMAGE_LEN: usize = 8;
+
+const HOST: IPAddr = IPAddr([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+const OTHER_HOST: IPAddr = IPAddr([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+const NODE: IPAddr = IPAddr([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3]);
+const HOST_PORT: u16 = 4000;
+const LOADER_PORT: u16 = 4001;
+
+struct CreationCapability;
+unsafe impl NetworkCapabilityCreationCapability for CreationCapability {}
+
+/// Operations of the mock storage, completed from a deferred call.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Operation {
+    Setup,
+    Write(usize),
+    Finalize,
+    Abort,
+    Load,
+}
+
+/// Stores the image in memory.
+struct MockStore {
+    image: RefCell<Vec<u8>>,
+    pending: Cell<Option<Operation>>,
+    buffer: TakeCell<'static, [u8]>,
+    storage_client: OptionalCell<&'static dyn DynamicBinaryStoreClient>,
+    load_client: OptionalCell<&'static dyn DynamicProcessLoadClient>,
+    deferred_call: DeferredCall,
+}
+
+impl MockStore {
+    fn new() -> &'static Self {
+        let store = leak(Self {
+            image: RefCell::new(Vec::new()),
+            pending: Cell::new(None),
+            buffer: TakeCell::empty(),
+            storage_client: OptionalCell::empty(),
+            load_client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+        });
+        store.register();
+        store
+    }
+
+    fn start(&self, operation: Operation) -> Result<(), ErrorCode> {
+        if self.pending.get().is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.pending.set(Some(operation));
+        self.deferred_call.set();
+        Ok(())
+    }
+}
+
+impl DynamicBinaryStore for MockStore {
+    fn setup(&self, app_length: usize) -> Result<usize, ErrorCode> {
+        self.start(Operation::Setup)?;
+        *self.image.borrow_mut() = vec![0xFF; app_length];
+        Ok(app_length)
+    }
+
+    fn write(
+        &self,
+        buffer: SubSliceMut<'static, u8>,
+        offset: usize,
+    ) -> Result<(), (ErrorCode, Option<&'static mut [u8]>)> {
+        let length = buffer.len();
+        if let Err(e) = self.start(Operation::Write(length)) {
+            return Err((e, Some(buffer.take())));
+        }
+        self.image.borrow_mut()[offset..offset + length].copy_from_slice(buffer.as_slice());
+        self.buffer.replace(buffer.take());
+        Ok(())
+    }
+
+    fn finalize(&self) -> Result<(), ErrorCode> {
+        self.start(Operation::Finalize)
+    }
+
+    fn abort(&self) -> Result<(), ErrorCode> {
+        self.start(Operation::Abort)
+    }
+
+    fn set_storage_client(&self, client: &'static dyn DynamicBinaryStoreClient) {
+        self.storage_client.set(client);
+    }
+}
+
+impl DynamicProcessLoad for MockStore {
+    fn load(&self) -> Result<(), ErrorCode> {
+        self.start(Operation::Load)
+    }
+
+    fn set_load_client(&self, client: &'static dyn DynamicProcessLoadClient) {
+        self.load_client.set(client);
+    }
+}
+
+impl DeferredCallClient for MockStore {
+    fn handle_deferred_call(&self) {
+        let Some(operation) = self.pending.take() else {
+            return;
+        };
+        match operation {
+            Operation::Setup => {
+                self.storage_client.map(|client| client.setup_done(Ok(())));
+            }
+            Operation::Write(length) => {
+                let buffer = self.buffer.take().unwrap();
+                self.storage_client
+                    .map(|client| client.write_done(Ok(()), buffer, length));
+            }
+            Operation::Finalize => {
+                self.storage_client
+                    .map(|client| client.finalize_done(Ok(())));
+            }
+            Operation::Abort => {
+                self.storage_client.map(|client| client.abort_done(Ok(())));
+            }
+            Operation::Load => {
+                self.load_client.map(|client| client.load_done(Ok(())));
+            }
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+/// Records the datagrams sent, and completes sends from a deferred call.
+struct MockUdpSender {
+    sent: RefCell<Vec<(IPAddr, u16, Vec<u8>)>>,
+    buffer: MapCell<SubSliceMut<'static, u8>>,
+    client: OptionalCell<&'static dyn UDPSendClient>,
+    deferred_call: DeferredCall,
+}
+
+impl MockUdpSender {
+    fn new() -> &'static Self {
+        let sender = leak(Self {
+            sent: RefCell::new(Vec::new()),
+            buffer: MapCell::empty(),
+            client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+        });
+        sender.register();
+        sender
+    }
+}
+
+impl UDPSender<'static> for MockUdpSender {
+    fn set_client(&self, client: &'static dyn UDPSendClient) {
+        self.client.set(client);
+    }
+
+    fn send_to(
+        &'static self,
+        dest: IPAddr,
+        dst_port: u16,
+        buf: SubSliceMut<'static, u8>,
+        _net_cap: &'static NetworkCapability,
+    ) -> Result<(), SubSliceMut<'static, u8>> {
+        self.sent
+            .borrow_mut()
+            .push((dest, dst_port, buf.as_slice().to_vec()));
+        self.buffer.replace(buf);
+        self.deferred_call.set();
+        Ok(())
+    }
+
+    fn driver_send_to(
+        &'static self,
+        _dest: IPAddr,
+        _dst_port: u16,
+        _src_port: u16,
+        buf: SubSliceMut<'static, u8>,
+        _driver_send_cap: &dyn UdpDriverCapability,
+        _net_cap: &'static NetworkCapability,
+    ) -> Result<(), SubSliceMut<'static, u8>> {
+        Err(buf)
+    }
+
+    fn send(
+        &'static self,
+        _dest: IPAddr,
+        _udp_header: UDPHeader,
+        buf: SubSliceMut<'static, u8>,
+        _net_cap: &'static NetworkCapability,
+    ) -> Result<(), SubSliceMut<'static, u8>> {
+        Err(buf)
+    }
+
+    fn get_binding(&self) -> Option<UdpPortBindingTx> {
+        None
+    }
+
+    fn is_bound(&self) -> bool {
+        true
+    }
+
+    fn set_binding(&self, binding: UdpPortBindingTx) -> Option<UdpPortBindingTx> {
+        Some(binding)
+    }
+}
+
+impl DeferredCallClient for MockUdpSender {
+    fn handle_deferred_call(&self) {
+        if let Some(dgram) = self.buffer.take() {
+            self.client.map(|client| client.send_done(Ok(()), dgram));
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+type Loader = UdpAppLoader<'static, MockStore, MockStore>;
+
+struct Node {
+    env: Environment,
+    loader: &'static Loader,
+    store: &'static MockStore,
+    sender: &'static MockUdpSender,
+}
+
+/// An acknowledgment, as `(type, session, offset, status)`.
+type Ack = (u8, u16, u32, u8);
+
+impl Node {
+    fn new() -> Self {
+        let env = Environment::new();
+        let store = MockStore::new();
+        let sender = MockUdpSender::new();
+        let net_cap = leak(NetworkCapability::new(
+            AddrRange::Any,
+            PortRange::Any,
+            PortRange::Any,
+            &CreationCapability,
+        ));
+        let loader = leak(UdpAppLoader::new(
+            store,
+            store,
+            sender,
+            net_cap,
+            leak_buffer(BUF_LEN),
+            SubSliceMut::new(leak_buffer(ACK_LEN)),
+        ));
+        store.set_storage_client(loader);
+        store.set_load_client(loader);
+        sender.set_client(loader);
+        Self {
+            env,
+            loader,
+            store,
+            sender,
+        }
+    }
+
+    /// Delivers `payload` from `host` at `port`, and returns the
+    /// acknowledgments sent to each host until the node is idle again.
+    fn request(&self, host: IPAddr, port: u16, payload: &[u8]) -> Vec<(IPAddr, u16, Ack)> {
+        self.loader.receive(host, NODE, port, LOADER_PORT, payload);
+        self.env.run();
+        self.sender
+            .sent
+            .take()
+            .into_iter()
+            .map(|(dest, dst_port, ack)| {
+                assert_eq!(ack.len(), ACK_LEN);
+                let session = u16::from_be_bytes([ack[1], ack[2]]);
+                let offset = u32::from_be_bytes(ack[3..7].try_into().unwrap());
+                (dest, dst_port, (ack[0], session, offset, ack[7]))
+            })
+            .collect()
+    }
+
+    fn image(&self) -> Vec<u8> {
+        self.store.image.borrow().clone()
+    }
+}
+
+fn status(result: Result<(), ErrorCode>) -> u8 {
+    into_statuscode(result) as u8
+}
+
+fn start(session: u16, length: usize) -> Vec<u8> {
+    let mut request = vec![START];
+    request.extend_from_slice(&session.to_be_bytes());
+    request.extend_from_slice(&(length as u32).to_be_bytes());
+    request
+}
+
+fn data(session: u16, offset: usize, data: &[u8]) -> Vec<u8> {
+    let mut request = vec![DATA];
+    request.extend_from_slice(&session.to_be_bytes());
+    request.extend_from_slice(&(offset as u32).to_be_bytes());
+    request.extend_from_slice(data);
+    request
+}
+
+fn request(kind: u8, session: u16) -> Vec<u8> {
+    let mut request = vec![kind];
+    request.extend_from_slice(&session.to_be_bytes());
+    request
+}
+
+/// An acknowledgment of `offset` bytes with `result` sent to `host` at
+/// `port`.
+fn ack(
+    host: IPAddr,
+    port: u16,
+    offset: usize,
+    result: Result<(), ErrorCode>,
+) -> (IPAddr, u16, Ack) {
+    (host, port, (ACK, SESSION, offset as u32, status(result)))
+}
+
+#[test]
+fn installs_and_resumes_after_the_host_restarts() {
+    let node = Node::new();
+    assert_eq!(
+        node.request(HOST, HOST_PORT, &start(SESSION, IMAGE_LEN)),
+        [ack(HOST, HOST_PORT, 0, Ok(()))]
+    );
+    assert_eq!(
+        node.request(HOST, HOST_PORT, &data(SESSION, 0, &[1, 2, 3, 4])),
+        [ack(HOST, HOST_PORT, 4, Ok(()))]
+    );
+
+    // The restarted host sends from another port, and continues where the
+    // node is.
+    let port = HOST_PORT + 1;
+    assert_eq!(
+        node.request(HOST, port, &start(SESSION, IMAGE_LEN)),
+        [ack(HOST, port, 4, Ok(()))]
+    );
+    assert_eq!(
+        node.request(HOST, port, &request(STATUS, SESSION)),
+        [ack(HOST, port, 4, Ok(()))]
+    );
+    assert_eq!(
+        node.request(HOST, port, &data(SESSION, 4, &[5, 6, 7, 8])),
+        [ack(HOST, port, 8, Ok(()))]
+    );
+    assert_eq!(
+        node.request(HOST, port, &request(FINISH, SESSION)),
+        [ack(HOST, port, 8, Ok(()))]
+    );
+    assert_eq!(node.image(), [1, 2, 3, 4, 5, 6, 7, 8]);
+}
+
+#[test]
+fn chunks_out_of_order_are_not_stored() {
+    let node = Node::new();
+    node.request(HOST, HOST_PORT, &start(SESSION, IMAGE_LEN));
+
+    // The acknowledgment tells the host where to continue.
+    assert_eq!(
+        node.request(HOST, HOST_PORT, &data(SESSION, 4, &[5, 6, 7, 8])),
+        [ack(HOST, HOST_PORT, 0, Ok(()))]
+    );
+    assert_eq!(node.image(), [0xFF; IMAGE_LEN]);
+
+    node.request(HOST, HOST_PORT, &data(SESSION, 0, &[1, 2, 3, 4]));
+    // A repeated chunk is not stored again.
+    assert_eq!(
+        node.request(HOST, HOST_PORT, &data(SESSION, 0, &[9, 9, 9, 9])),
+        [ack(HOST, HOST_PORT, 4, Ok(()))]
+    );
+    assert_eq!(node.image(), [1, 2, 3, 4, 0xFF, 0xFF, 0xFF, 0xFF]);
+
+    // The image is not complete yet.
+    assert_eq!(
+        node.request(HOST, HOST_PORT, &request(FINISH, SESSION)),
+        [ack(HOST, HOST_PORT, 4, Err(ErrorCode::BUSY))]
+    );
+}
+
+#[test]
+fn oversize_chunks_are_rejected() {
+    let node = Node::new();
+    node.request(HOST, HOST_PORT, &start(SESSION, BUF_LEN + 4));
+
+    assert_eq!(
+        node.request(HOST, HOST_PORT, &data(SESSION, 0, &[1; BUF_LEN + 1])),
+        [ack(HOST, HOST_PORT, 0, Err(ErrorCode::INVAL))]
+    );
+    assert_eq!(
+        node.request(HOST, HOST_PORT, &data(SESSION, 0, &[1; BUF_LEN])),
+        [ack(HOST, HOST_PORT, BUF_LEN, Ok(()))]
+    );
+    // So are chunks past the end of the image.
+    assert_eq!(
+        node.request(HOST, HOST_PORT, &data(SESSION, BUF_LEN, &[2; 8])),
+        [ack(HOST, HOST_PORT, BUF_LEN, Err(ErrorCode::INVAL))]
+    );
+    assert_eq!(
+        node.request(HOST, HOST_PORT, &data(SESSION, BUF_LEN, &[2; 4])),
+        [ack(HOST, HOST_PORT, BUF_LEN + 4, Ok(()))]
+    );
+    assert_eq!(node.image()[BUF_LEN - 1..], [1, 2, 2, 2, 2]);
+}
+
+#[test]
+fn other_hosts_cannot_take_over_the_session() {
+    let node = Node::new();
+    node.request(HOST, HOST_PORT, &start(SESSION, IMAGE_LEN));
+    node.request(HOST, HOST_PORT, &data(SESSION, 0, &[1, 2, 3, 4]));
+
+    // Another host that guessed the session is turned away.
+    let busy = [ack(OTHER_HOST, HOST_PORT, 0, Err(ErrorCode::BUSY))];
+    for other in [
+        start(SESSION, IMAGE_LEN),
+        data(SESSION, 4, &[9, 9, 9, 9]),
+        request(FINISH, SESSION),
+        request(ABORT, SESSION),
+        request(STATUS, SESSION),
+    ] {
+        assert_eq!(node.request(OTHER_HOST, HOST_PORT, &other), busy);
+    }
+    // Nor can it cancel the transfer with a session of its own.
+    assert_eq!(
+        node.request(OTHER_HOST, HOST_PORT, &start(SESSION + 1, IMAGE_LEN)),
+        [(
+            OTHER_HOST,
+            HOST_PORT,
+            (ACK, SESSION + 1, 0, status(Err(ErrorCode::BUSY)))
+        )]
+    );
+    assert_eq!(node.image(), [1, 2, 3, 4, 0xFF, 0xFF, 0xFF, 0xFF]);
+
+    // The transfer continues with the host that started it.
+    assert_eq!(
+        node.request(HOST, HOST_PORT, &data(SESSION, 4, &[5, 6, 7, 8])),
+        [ack(HOST, HOST_PORT, 8, Ok(()))]
+    );
+    assert_eq!(
+        node.request(HOST, HOST_PORT, &request(FINISH, SESSION)),
+        [ack(HOST, HOST_PORT, 8, Ok(()))]
+    );
+    assert_eq!(node.image(), [1, 2, 3, 4, 5, 6, 7, 8]);
+}
@@ -0,0 +1,432 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use core::cell::Cell;
+
+use capsules_system::process_checker::basic::{AppCheckerNull, AppIdAssignerSimulated};
+use capsules_system::process_policies::{PanicFaultPolicy, StopFaultPolicy};
+use capsules_system::storage_permissions::null::NullStoragePermissions;
+use capsules_test_support::chip::HostChip;
+use capsules_test_support::{Environment, leak, leak_buffer};
+use kernel::ErrorCode;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::dynamic_binary_storage::{
+    BUF_LEN, DynamicBinaryStore, DynamicBinaryStoreClient, DynamicProcessUpdate,
+    DynamicProcessUpdateClient, SequentialDynamicBinaryStorage,
+};
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::process::{
+    Process, ProcessBinary, ProcessCheckerMachine, ProcessLoadingAsync, ProcessStandardDebugFull,
+    SequentialProcessLoaderMachine, State,
+};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+
+const NUM_PROCS: usize = 4;
+const BANK_SIZE: usize = 4096;
+/// Each trial record slot is a page of its own.
+const RECORD_SLOT_SIZE: usize = 512;
+const APP_SIZE: usize = 1024;
+const APP_MEMORY_SIZE: usize = 512 * 1024;
+const MEMORY_SIZE: usize = BANK_SIZE + 2 * RECORD_SLOT_SIZE;
+
+struct Capability;
+unsafe impl ProcessManagementCapability for Capability {}
+
+/// The flash bank for apps, followed by the trial record. Binaries are
+/// aligned to their length, so the bank is aligned to its own.
+#[repr(C, align(4096))]
+struct Aligned([u8; MEMORY_SIZE]);
+
+/// Memory that stands in for flash, and keeps its contents across the boots
+/// of a test.
+#[derive(Clone, Copy)]
+struct Memory(*mut u8);
+
+impl Memory {
+    fn new(bank: &[u8]) -> Self {
+        let mut memory = Box::new(Aligned([0xFF; MEMORY_SIZE]));
+        memory.0[..bank.len()].copy_from_slice(bank);
+        Self(Box::into_raw(memory).cast())
+    }
+
+    fn slice(self, offset: usize, len: usize) -> &'static [u8] {
+        assert!(offset + len <= MEMORY_SIZE);
+        // SAFETY: the memory is leaked, and the range is within it.
+        unsafe { core::slice::from_raw_parts(self.0.add(offset), len) }
+    }
+
+    fn bank(self) -> &'static [u8] {
+        self.slice(0, BANK_SIZE)
+    }
+
+    fn record(self) -> &'static [u8] {
+        self.slice(BANK_SIZE, 2 * RECORD_SLOT_SIZE)
+    }
+
+    fn address(self, offset: usize) -> usize {
+        self.0.addr() + offset
+    }
+}
+
+/// Nonvolatile storage over memory the process loader reads directly, like
+/// memory-mapped flash. Writes complete from a deferred call.
+struct MappedFlash {
+    memory: Memory,
+    buffer: TakeCell<'static, [u8]>,
+    length: Cell<usize>,
+    client: OptionalCell<&'static dyn NonvolatileStorageClient>,
+    deferred_call: DeferredCall,
+}
+
+impl MappedFlash {
+    fn new(memory: Memory) -> &'static Self {
+        let flash = leak(Self {
+            memory,
+            buffer: TakeCell::empty(),
+            length: Cell::new(0),
+            client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+        });
+        flash.register();
+        flash
+    }
+}
+
+impl NonvolatileStorage<'static> for MappedFlash {
+    fn set_client(&self, client: &'static dyn NonvolatileStorageClient) {
+        self.client.set(client);
+    }
+
+    fn read(
+        &self,
+        _buffer: &'static mut [u8],
+        _address: usize,
+        _length: usize,
+    ) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        let offset = address.wrapping_sub(self.memory.address(0));
+        if length > buffer.len() || offset + length > MEMORY_SIZE {
+            return Err(ErrorCode::INVAL);
+        }
+        // SAFETY: the range is within the memory.
+        unsafe {
+            core::ptr::copy_nonoverlapping(buffer.as_ptr(), self.memory.0.add(offset), length);
+        }
+        self.buffer.replace(buffer);
+        self.length.set(length);
+        self.deferred_call.set();
+        Ok(())
+    }
+}
+
+impl DeferredCallClient for MappedFlash {
+    fn handle_deferred_call(&self) {
+        if let Some(buffer) = self.buffer.take() {
+            self.client
+                .map(|client| client.write_done(buffer, self.length.get()));
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+#[derive(Default)]
+struct Client {
+    set_up: Cell<Option<Result<(), ErrorCode>>>,
+    written: Cell<Option<Result<(), ErrorCode>>>,
+    finalized: Cell<Option<Result<(), ErrorCode>>>,
+    upgraded: Cell<Option<Result<(), ErrorCode>>>,
+    committed: Cell<Option<Result<(), ErrorCode>>>,
+    rolled_back: Cell<Option<Result<(), ErrorCode>>>,
+}
+
+impl DynamicBinaryStoreClient for Client {
+    fn setup_done(&self, result: Result<(), ErrorCode>) {
+        self.set_up.set(Some(result));
+    }
+
+    fn write_done(&self, result: Result<(), ErrorCode>, _buffer: &'static mut [u8], _len: usize) {
+        self.written.set(Some(result));
+    }
+
+    fn finalize_done(&self, result: Result<(), ErrorCode>) {
+        self.finalized.set(Some(result));
+    }
+
+    fn abort_done(&self, _result: Result<(), ErrorCode>) {}
+}
+
+impl DynamicProcessUpdateClient for Client {
+    fn uninstall_done(&self, _result: Result<(), ErrorCode>) {}
+
+    fn upgrade_done(&self, result: Result<(), ErrorCode>) {
+        self.upgraded.set(Some(result));
+    }
+
+    fn commit_done(&self, result: Result<(), ErrorCode>) {
+        self.committed.set(Some(result));
+    }
+
+    fn rollback_done(&self, result: Result<(), ErrorCode>) {
+        self.rolled_back.set(Some(result));
+    }
+
+    fn compact_done(&self, _result: Result<usize, ErrorCode>) {}
+}
+
+type Storage = SequentialDynamicBinaryStorage<
+    'static,
+    'static,
+    HostChip,
+    ProcessStandardDebugFull,
+    MappedFlash,
+>;
+
+struct Board {
+    storage: &'static Storage,
+    client: &'static Client,
+}
+
+/// Sets up the storage as the fault policy of the loader, with the trial
+/// record, then loads the processes in the bank, like a board does at boot.
+fn boot(env: &Environment, memory: Memory) -> Board {
+    let checker = leak(ProcessCheckerMachine::new(leak(AppCheckerNull::new())));
+    let loader = leak(SequentialProcessLoaderMachine::new(
+        checker,
+        leak([const { None::<ProcessBinary> }; NUM_PROCS]),
+        env.kernel(),
+        env.chip(),
+        memory.bank(),
+        leak_buffer(APP_MEMORY_SIZE),
+        leak(PanicFaultPolicy {}),
+        leak(NullStoragePermissions::<HostChip, ProcessStandardDebugFull>::new()),
+        leak(AppIdAssignerSimulated {}),
+        &Capability,
+    ));
+    checker.set_client(loader);
+    loader.register();
+
+    let flash = MappedFlash::new(memory);
+    let storage = leak(SequentialDynamicBinaryStorage::new(
+        flash,
+        loader,
+        leak_buffer(BUF_LEN),
+    ));
+    flash.set_client(storage);
+    loader.set_runtime_client(storage);
+    storage.register();
+    storage.set_fault_policy(leak(StopFaultPolicy {}));
+    loader.set_fault_policy(storage);
+    storage.set_trial_record(memory.record());
+    let client = leak(Client::default());
+    storage.set_storage_client(client);
+    storage.set_update_client(client);
+
+    loader.start();
+    env.run();
+    Board { storage, client }
+}
+
+/// Builds version `version` of app `name`, a TBF of `APP_SIZE` bytes with
+/// Program, Package Name and Kernel Version headers.
+fn app(name: &str, version: u32) -> Vec<u8> {
+    let name_size = name.len().next_multiple_of(4);
+    let header_size = 16 + (4 + 20) + (4 + 4) + (4 + name_size);
+
+    let mut tbf = Vec::with_capacity(APP_SIZE);
+    tbf.extend_from_slice(&2u16.to_le_bytes());
+    tbf.extend_from_slice(&(header_size as u16).to_le_bytes());
+    tbf.extend_from_slice(&(APP_SIZE as u32).to_le_bytes());
+    tbf.extend_from_slice(&1u32.to_le_bytes());
+    tbf.extend_from_slice(&0u32.to_le_bytes());
+    tbf.extend_from_slice(&9u16.to_le_bytes());
+    tbf.extend_from_slice(&20u16.to_le_bytes());
+    tbf.extend_from_slice(&0u32.to_le_bytes());
+    tbf.extend_from_slice(&0u32.to_le_bytes());
+    tbf.extend_from_slice(&(32 * 1024u32).to_le_bytes());
+    tbf.extend_from_slice(&(APP_SIZE as u32).to_le_bytes());
+    tbf.extend_from_slice(&version.to_le_bytes());
+    tbf.extend_from_slice(&8u16.to_le_bytes());
+    tbf.extend_from_slice(&4u16.to_le_bytes());
+    tbf.extend_from_slice(&kernel::KERNEL_MAJOR_VERSION.to_le_bytes());
+    tbf.extend_from_slice(&kernel::KERNEL_MINOR_VERSION.to_le_bytes());
+    tbf.extend_from_slice(&3u16.to_le_bytes());
+    tbf.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    tbf.extend_from_slice(name.as_bytes());
+    tbf.resize(header_size, 0);
+    let header_checksum = checksum(&tbf);
+    tbf[12..16].copy_from_slice(&header_checksum.to_le_bytes());
+    tbf.resize(APP_SIZE, 0);
+    tbf
+}
+
+/// XOR of the words of a TBF header, except for the checksum word.
+fn checksum(header: &[u8]) -> u32 {
+    header
+        .chunks_exact(4)
+        .enumerate()
+        .filter(|(i, _)| *i != 3)
+        .fold(0, |checksum, (_, word)| {
+            checksum ^ u32::from_le_bytes(word.try_into().unwrap())
+        })
+}
+
+/// Whether a padding app starts at `offset` in the bank.
+fn is_padding(memory: Memory, offset: usize) -> bool {
+    memory.bank()[offset + 2..offset + 4] == 16u16.to_le_bytes()
+}
+
+/// Names of the processes, by the offset of their binary in the bank.
+fn processes(env: &Environment, memory: Memory) -> Vec<(usize, &'static str)> {
+    let mut processes: Vec<_> = env
+        .kernel()
+        .process_iter_capability(&Capability)
+        .map(|process| {
+            (
+                process.get_addresses().flash_start - memory.address(0),
+                process.get_process_name(),
+            )
+        })
+        .collect();
+    processes.sort_unstable();
+    processes
+}
+
+fn process_at(env: &Environment, memory: Memory, offset: usize) -> &'static dyn Process {
+    env.kernel()
+        .process_iter_capability(&Capability)
+        .find(|process| process.get_addresses().flash_start == memory.address(offset))
+        .expect("no process runs from the offset")
+}
+
+/// Faults the process running from `offset`, and lets the kernel handle it.
+fn fault(env: &Environment, memory: Memory, offset: usize) {
+    process_at(env, memory, offset).set_fault_state();
+    env.run();
+}
+
+/// Writes version 2 of app `a` to `offset`, the free space after the other
+/// binaries, and upgrades version 1 at the start of the bank to it.
+fn upgrade(env: &Environment, memory: Memory, board: &Board, offset: usize) {
+    let storage = board.storage;
+    assert_eq!(storage.setup(APP_SIZE), Ok(APP_SIZE));
+    env.run();
+    assert_eq!(board.client.set_up.get(), Some(Ok(())));
+
+    let buffer = leak_buffer(APP_SIZE);
+    buffer.copy_from_slice(&app("a", 2));
+    assert!(storage.write(SubSliceMut::new(buffer), 0).is_ok());
+    env.run();
+    assert_eq!(board.client.written.get(), Some(Ok(())));
+    assert_eq!(storage.finalize(), Ok(()));
+    env.run();
+    assert_eq!(board.client.finalized.get(), Some(Ok(())));
+
+    let old = process_at(env, memory, 0).processid().id();
+    assert_eq!(storage.upgrade(old), Ok(()));
+    env.run();
+    assert_eq!(board.client.upgraded.get(), Some(Ok(())));
+    let new = process_at(env, memory, offset);
+    assert_eq!(new.get_process_name(), "a");
+    assert!(!is_padding(memory, 0));
+}
+
+#[test]
+fn rolls_back_after_repeated_faults_across_resets() {
+    let memory = Memory::new(&app("a", 1));
+    {
+        let env = Environment::new();
+        let board = boot(&env, memory);
+        upgrade(&env, memory, &board, APP_SIZE);
+        assert_eq!(board.storage.trial_faults(), Ok(0));
+
+        // The kernel restarts the version on trial below the threshold.
+        fault(&env, memory, APP_SIZE);
+        assert_eq!(board.storage.trial_faults(), Ok(1));
+        assert_ne!(
+            process_at(&env, memory, APP_SIZE).get_state(),
+            State::Faulted
+        );
+    }
+
+    // The trial and its faults survive a reset.
+    {
+        let env = Environment::new();
+        let board = boot(&env, memory);
+        assert_eq!(processes(&env, memory), [(APP_SIZE, "a")]);
+        assert_eq!(board.storage.trial_faults(), Ok(1));
+
+        fault(&env, memory, APP_SIZE);
+        assert_eq!(board.client.rolled_back.get(), None);
+        fault(&env, memory, APP_SIZE);
+        assert_eq!(board.client.rolled_back.get(), Some(Ok(())));
+        assert_eq!(processes(&env, memory), [(0, "a")]);
+        assert!(is_padding(memory, APP_SIZE));
+        assert_eq!(board.storage.trial_faults(), Err(ErrorCode::INVAL));
+    }
+
+    // The rollback ended the trial.
+    let env = Environment::new();
+    let board = boot(&env, memory);
+    assert_eq!(processes(&env, memory), [(0, "a")]);
+    assert_eq!(board.storage.trial_faults(), Err(ErrorCode::INVAL));
+}
+
+#[test]
+fn commit_ends_the_trial() {
+    let memory = Memory::new(&app("a", 1));
+    {
+        let env = Environment::new();
+        let board = boot(&env, memory);
+        upgrade(&env, memory, &board, APP_SIZE);
+        fault(&env, memory, APP_SIZE);
+
+        assert_eq!(board.storage.commit(), Ok(()));
+        env.run();
+        assert_eq!(board.client.committed.get(), Some(Ok(())));
+        assert!(is_padding(memory, 0));
+    }
+
+    let env = Environment::new();
+    let board = boot(&env, memory);
+    assert_eq!(processes(&env, memory), [(APP_SIZE, "a")]);
+    assert_eq!(board.storage.trial_faults(), Err(ErrorCode::INVAL));
+
+    // Faults of the committed version are up to the board's policy.
+    fault(&env, memory, APP_SIZE);
+    assert_eq!(
+        process_at(&env, memory, APP_SIZE).get_state(),
+        State::Faulted
+    );
+}
+
+#[test]
+fn faults_of_other_processes_are_not_counted() {
+    let mut bank = app("a", 1);
+    bank.extend(app("b", 1));
+    let memory = Memory::new(&bank);
+    let env = Environment::new();
+    let board = boot(&env, memory);
+    upgrade(&env, memory, &board, 2 * APP_SIZE);
+
+    // The board's policy stops `b`.
+    fault(&env, memory, APP_SIZE);
+    assert_eq!(
+        process_at(&env, memory, APP_SIZE).get_state(),
+        State::Faulted
+    );
+    assert_eq!(board.storage.trial_faults(), Ok(0));
+}
@@ -0,0 +1,332 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+use core::cell::{Cell, RefCell};
+
+use capsules_core::virtualizers::virtual_can::{MuxCan, VirtualCanDevice};
+use capsules_test_support::can::{CanFrame, MockCan};
+use capsules_test_support::{Environment, leak};
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil::can::{
+    self, Filter, FilterParameters, Id, IdentifierMode, Receive, STANDARD_CAN_PACKET_SIZE,
+    ScaleBits, Transmit,
+};
+use kernel::utilities::cells::TakeCell;
+
+type Device = VirtualCanDevice<'static, MockCan>;
+
+/// A kernel capsule using one virtual device.
+struct Client {
+    received: RefCell<Vec<CanFrame>>,
+    sent: RefCell<Vec<Result<(), can::Error>>>,
+    stopped: Cell<bool>,
+    tx_buffer: TakeCell<'static, [u8; STANDARD_CAN_PACKET_SIZE]>,
+}
+
+impl Client {
+    fn new() -> Self {
+        Self {
+            received: RefCell::new(Vec::new()),
+            sent: RefCell::new(Vec::new()),
+            stopped: Cell::new(false),
+            tx_buffer: TakeCell::new(leak([0; STANDARD_CAN_PACKET_SIZE])),
+        }
+    }
+}
+
+impl can::TransmitClient<STANDARD_CAN_PACKET_SIZE> for Client {
+    fn transmit_complete(
+        &self,
+        status: Result<(), can::Error>,
+        buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE],
+    ) {
+        self.sent.borrow_mut().push(status);
+        self.tx_buffer.replace(buffer);
+    }
+}
+
+impl can::ReceiveClient<STANDARD_CAN_PACKET_SIZE> for Client {
+    fn message_received(
+        &self,
+        id: Id,
+        buffer: &mut [u8; STANDARD_CAN_PACKET_SIZE],
+        len: usize,
+        _status: Result<(), can::Error>,
+    ) {
+        self.received
+            .borrow_mut()
+            .push(CanFrame::new(id, &buffer[..len]));
+    }
+
+    fn stopped(&self, _buffer: &'static mut [u8; STANDARD_CAN_PACKET_SIZE]) {
+        self.stopped.set(true);
+    }
+}
+
+fn mux(hardware_filters: bool) -> (&'static MockCan, &'static MuxCan<'static, MockCan>) {
+    let can = MockCan::new();
+    let filter: Option<&'static dyn Filter> = if hardware_filters { Some(can) } else { None };
+    let mux = leak(MuxCan::new(
+        can,
+        filter,
+        leak([0; STANDARD_CAN_PACKET_SIZE]),
+    ));
+    mux.register();
+    Transmit::set_client(can, Some(mux));
+    Receive::set_client(can, Some(mux));
+    (can, mux)
+}
+
+fn device(mux: &'static MuxCan<'static, MockCan>) -> (&'static Device, &'static Client) {
+    let device = leak(VirtualCanDevice::new(mux));
+    device.setup();
+    let client = leak(Client::new());
+    Transmit::set_client(device, Some(client));
+    Receive::set_client(device, Some(client));
+    (device, client)
+}
+
+fn filter(number: u32, id: Id, mask: u32) -> FilterParameters {
+    FilterParameters {
+        number,
+        scale_bits: ScaleBits::Bits32,
+        identifier_mode: IdentifierMode::Mask,
+        fifo_number: 0,
+        id,
+        mask,
+    }
+}
+
+fn start(device: &Device) {
+    assert!(
+        device
+            .start_receive_process(leak([0; STANDARD_CAN_PACKET_SIZE]))
+            .is_ok()
+    );
+}
+
+fn send(device: &Device, client: &Client, id: Id, data: &[u8]) {
+    let buffer = client.tx_buffer.take().unwrap();
+    buffer[..data.len()].copy_from_slice(data);
+    assert!(device.send(id, buffer, data.len()).is_ok());
+}
+
+#[test]
+fn frames_reach_the_devices_whose_filters_accept_them() {
+    let env = Environment::new();
+    let (can, mux) = mux(false);
+    let (range, range_client) = device(mux);
+    let (exact, exact_client) = device(mux);
+
+    // 0x100 to 0x10F, and one extended identifier.
+    range
+        .enable_filter(filter(0, Id::Standard(0x100), 0x7F0))
+        .unwrap();
+    exact
+        .enable_filter(FilterParameters {
+            identifier_mode: IdentifierMode::List,
+            ..filter(0, Id::Extended(0x18DA_F110), 0)
+        })
+        .unwrap();
+    start(range);
+    start(exact);
+
+    can.receive(CanFrame::new(Id::Standard(0x105), &[1]));
+    can.receive(CanFrame::new(Id::Standard(0x200), &[2]));
+    can.receive(CanFrame::new(Id::Extended(0x18DA_F110), &[3]));
+    can.receive(CanFrame::new(Id::Extended(0x105), &[4]));
+    env.run();
+
+    assert_eq!(
+        *range_client.received.borrow(),
+        [CanFrame::new(Id::Standard(0x105), &[1])]
+    );
+    assert_eq!(
+        *exact_client.received.borrow(),
+        [CanFrame::new(Id::Extended(0x18DA_F110), &[3])]
+    );
+}
+
+#[test]
+fn device_without_filters_receives_every_frame() {
+    let env = Environment::new();
+    let (can, mux) = mux(true);
+    let (filtered, filtered_client) = device(mux);
+    let (all, all_client) = device(mux);
+
+    filtered
+        .enable_filter(filter(0, Id::Standard(0x100), 0x7FF))
+        .unwrap();
+    start(filtered);
+    start(all);
+    assert_eq!(can.enabled_filters(), [(Id::Standard(0), 0)]);
+
+    can.receive(CanFrame::new(Id::Standard(0x100), &[1]));
+    can.receive(CanFrame::new(Id::Standard(0x300), &[2]));
+    env.run();
+
+    assert_eq!(filtered_client.received.borrow().len(), 1);
+    assert_eq!(all_client.received.borrow().len(), 2);
+}
+
+#[test]
+fn filters_go_to_hardware_banks_while_they_fit() {
+    let env = Environment::new();
+    let (can, mux) = mux(true);
+    let (first, first_client) = device(mux);
+    let (second, second_client) = device(mux);
+
+    first
+        .enable_filter(filter(0, Id::Standard(0x100), 0x7FF))
+        .unwrap();
+    second
+        .enable_filter(filter(0, Id::Standard(0x200), 0x7FF))
+        .unwrap();
+    start(first);
+    start(second);
+    let mut banks = can.enabled_filters();
+    banks.sort_by_key(|(id, _)| matches!(id, Id::Standard(0x200)));
+    assert_eq!(
+        banks,
+        [(Id::Standard(0x100), 0x7FF), (Id::Standard(0x200), 0x7FF)]
+    );
+
+    // A third filter does not fit in the two banks, so the controller accepts
+    // everything and the mux filters in software.
+    first
+        .enable_filter(filter(1, Id::Standard(0x101), 0x7FF))
+        .unwrap();
+    assert_eq!(can.enabled_filters(), [(Id::Standard(0), 0)]);
+
+    can.receive(CanFrame::new(Id::Standard(0x101), &[1]));
+    can.receive(CanFrame::new(Id::Standard(0x300), &[2]));
+    env.run();
+    assert_eq!(first_client.received.borrow().len(), 1);
+    assert!(second_client.received.borrow().is_empty());
+
+    // Removing it moves the filters back to hardware.
+    first.disable_filter(1).unwrap();
+    assert_eq!(can.enabled_filters().len(), 2);
+}
+
+#[test]
+fn rejected_filters_fall_back_to_accepting_every_frame() {
+    let env = Environment::new();
+    let (can, mux) = mux(true);
+    let (device, client) = device(mux);
+    can.reject_filter_banks(1);
+
+    device
+        .enable_filter(filter(0, Id::Standard(0x100), 0x7FF))
+        .unwrap();
+    device
+        .enable_filter(filter(1, Id::Standard(0x200), 0x7FF))
+        .unwrap();
+    start(device);
+    assert_eq!(can.enabled_filters(), [(Id::Standard(0), 0)]);
+
+    can.receive(CanFrame::new(Id::Standard(0x200), &[1]));
+    can.receive(CanFrame::new(Id::Standard(0x300), &[2]));
+    env.run();
+    assert_eq!(
+        *client.received.borrow(),
+        [CanFrame::new(Id::Standard(0x200), &[1])]
+    );
+}
+
+#[test]
+fn failed_filter_leaves_no_bank_enabled() {
+    let _env = Environment::new();
+    let (can, mux) = mux(true);
+    let (device, _) = device(mux);
+
+    device
+        .enable_filter(filter(0, Id::Standard(0x100), 0x7FF))
+        .unwrap();
+    start(device);
+    assert_eq!(can.enabled_filters(), [(Id::Standard(0x100), 0x7FF)]);
+
+    // Not even the filter accepting every frame can be enabled.
+    can.reject_filter_banks(0);
+    assert_eq!(
+        device.enable_filter(filter(1, Id::Standard(0x200), 0x7FF)),
+        Err(kernel::ErrorCode::FAIL)
+    );
+    assert!(can.enabled_filters().is_empty());
+}
+
+#[test]
+fn sends_from_devices_are_queued() {
+    let env = Environment::new();
+    let (can, mux) = mux(false);
+    let (first, first_client) = device(mux);
+    let (second, second_client) = device(mux);
+
+    send(first, first_client, Id::Standard(0x100), &[1]);
+    send(second, second_client, Id::Standard(0x200), &[2]);
+
+    // A device can only have one frame queued.
+    let buffer = leak([0; STANDARD_CAN_PACKET_SIZE]);
+    assert!(matches!(
+        first.send(Id::Standard(0x100), buffer, 1),
+        Err((kernel::ErrorCode::BUSY, _))
+    ));
+
+    env.run();
+    assert_eq!(*first_client.sent.borrow(), [Ok(())]);
+    assert_eq!(*second_client.sent.borrow(), [Ok(())]);
+    assert_eq!(
+        can.take_sent(),
+        [
+            CanFrame::new(Id::Standard(0x100), &[1]),
+            CanFrame::new(Id::Standard(0x200), &[2]),
+        ]
+    );
+}
+
+#[test]
+fn failed_send_is_reported_to_its_device() {
+    let env = Environment::new();
+    let (can, mux) = mux(false);
+    let (first, first_client) = device(mux);
+    let (second, second_client) = device(mux);
+
+    can.fail_next(can::Error::Ack);
+    send(first, first_client, Id::Standard(0x100), &[1]);
+    send(second, second_client, Id::Standard(0x200), &[2]);
+    env.run();
+
+    assert_eq!(*first_client.sent.borrow(), [Err(can::Error::Ack)]);
+    assert_eq!(*second_client.sent.borrow(), [Ok(())]);
+}
+
+#[test]
+fn controller_keeps_receiving_until_the_last_device_stops() {
+    let env = Environment::new();
+    let (can, mux) = mux(true);
+    let (first, first_client) = device(mux);
+    let (second, second_client) = device(mux);
+    start(first);
+    start(second);
+
+    first.stop_receive().unwrap();
+    env.run();
+    assert!(first_client.stopped.get());
+    assert!(can.is_receiving());
+
+    can.receive(CanFrame::new(Id::Standard(0x100), &[1]));
+    env.run();
+    assert!(first_client.received.borrow().is_empty());
+    assert_eq!(second_client.received.borrow().len(), 1);
+
+    second.stop_receive().unwrap();
+    env.run();
+    assert!(second_client.stopped.get());
+    assert!(!can.is_receiving());
+    assert!(can.enabled_filters().is_empty());
+
+    // Receiving can start again once the controller has stopped.
+    start(first);
+    assert!(can.is_receiving());
+}
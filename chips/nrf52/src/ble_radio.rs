@@ -37,17 +37,28 @@
 //! * Payload - 2 to 255 bytes
 //!
 //! * CRC - 3 bytes
+//!
+//! ### Link Layer
+//!
+//! The radio also implements `BleLinkRadio` for a connection-oriented link
+//! layer. The turnaround between a packet and its answer uses the
+//! `DISABLED_RXEN` and `DISABLED_TXEN` shortcuts, so that the radio keeps the
+//! inter frame space of 150 µs by itself, and TIMER1 ends a reception when no
+//! packet starts within the listening window.
 
+use crate::timer::TimerAlarm;
 use core::cell::Cell;
 use core::ptr::addr_of;
 use core::ptr::addr_of_mut;
 use kernel::ErrorCode;
 use kernel::hil::ble_advertising;
 use kernel::hil::ble_advertising::RadioChannel;
+use kernel::hil::ble_link::{self, BleLinkRadio, BleLinkRadioClient};
+use kernel::hil::time::{Alarm, AlarmClient, Ticks32, Time};
 use kernel::utilities::StaticRef;
 use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::cells::TakeCell;
-use kernel::utilities::registers::interfaces::{Readable, Writeable};
+use kernel::utilities::registers::interfaces::{ReadWriteable, Readable, Writeable};
 use kernel::utilities::registers::{ReadOnly, ReadWrite, WriteOnly, register_bitfields};
 use nrf5x::constants::TxPower;
 
@@ -536,12 +547,54 @@ register_bitfields! [u32,
 static mut PAYLOAD: [u8; nrf5x::constants::RADIO_PAYLOAD_LENGTH] =
     [0x00; nrf5x::constants::RADIO_PAYLOAD_LENGTH];
 
+/// The answer of the link layer to a received packet.
+static mut LINK_RESPONSE: [u8; ble_link::MAX_PACKET_LEN] = [0x00; ble_link::MAX_PACKET_LEN];
+
+/// Inter frame space (T_IFS) in microseconds.
+///
+/// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.1
+const T_IFS_US: u32 = 150;
+
+/// Time the radio takes to ramp up before it starts listening, in
+/// microseconds.
+const RAMP_UP_US: u32 = 140;
+
+/// Time from the start of a packet until its access address is received, in
+/// microseconds: the preamble and access address take 40 µs at 1 Mbit/s, plus
+/// a margin for the clock accuracy of the sender.
+const ADDRESS_US: u32 = 50;
+
+/// Operation of a `BleLinkRadio` client in progress.
+#[derive(Clone, Copy, PartialEq)]
+enum LinkOperation {
+    TransmitThenReceive,
+    ReceiveThenTransmit,
+}
+
+/// Step of the current `LinkOperation`.
+#[derive(Clone, Copy, PartialEq)]
+enum LinkPhase {
+    /// Transmitting the packet of `transmit_then_receive`.
+    Transmitting,
+    /// Listening for a packet to answer.
+    Listening,
+    /// Transmitting the answer to the received packet.
+    Answering,
+}
+
 pub struct Radio<'a> {
     registers: StaticRef<RadioRegisters>,
     tx_power: Cell<TxPower>,
     rx_client: OptionalCell<&'a dyn ble_advertising::RxClient>,
     tx_client: OptionalCell<&'a dyn ble_advertising::TxClient>,
     buffer: TakeCell<'static, [u8]>,
+    link_client: OptionalCell<&'a dyn BleLinkRadioClient>,
+    /// Ends the listening window of link layer operations.
+    timer: OptionalCell<&'a TimerAlarm<'a>>,
+    access_address: Cell<u32>,
+    crc_init: Cell<u32>,
+    link_operation: OptionalCell<LinkOperation>,
+    link_phase: Cell<LinkPhase>,
 }
 
 impl<'a> Radio<'a> {
@@ -552,9 +605,21 @@ impl<'a> Radio<'a> {
             rx_client: OptionalCell::empty(),
             tx_client: OptionalCell::empty(),
             buffer: TakeCell::empty(),
+            link_client: OptionalCell::empty(),
+            timer: OptionalCell::empty(),
+            access_address: Cell::new(ble_link::ADVERTISING_ACCESS_ADDRESS),
+            crc_init: Cell::new(ble_link::ADVERTISING_CRC_INIT),
+            link_operation: OptionalCell::empty(),
+            link_phase: Cell::new(LinkPhase::Transmitting),
         }
     }
 
+    /// Set the timer used to end the listening window of link layer
+    /// operations.
+    pub fn set_timer_ref(&self, timer: &'a TimerAlarm<'a>) {
+        self.timer.set(timer);
+    }
+
     pub fn is_enabled(&self) -> bool {
         self.registers.mode.matches_all(Mode::MODE::BLE_1MBIT)
     }
@@ -601,6 +666,11 @@ impl<'a> Radio<'a> {
 
     #[inline(never)]
     pub fn handle_interrupt(&self) {
+        if self.link_operation.is_some() {
+            self.handle_link_interrupt();
+            return;
+        }
+
         self.disable_all_interrupts();
 
         if self.registers.event_ready.is_set(Event::READY) {
@@ -660,6 +730,124 @@ impl<'a> Radio<'a> {
         self.enable_interrupts();
     }
 
+    fn handle_link_interrupt(&self) {
+        if self.registers.event_address.is_set(Event::READY) {
+            self.registers.event_address.write(Event::READY::CLEAR);
+            // A packet is being received, and is not cut at the end of the
+            // window.
+            if self.link_phase.get() == LinkPhase::Listening {
+                self.timer.map(|timer| timer.disarm());
+            }
+        }
+
+        if !self.registers.event_disabled.is_set(Event::READY) {
+            return;
+        }
+        self.registers.event_disabled.write(Event::READY::CLEAR);
+        match self.link_phase.get() {
+            LinkPhase::Transmitting => {
+                // The `DISABLED_RXEN` shortcut already started ramping up the
+                // receiver.
+                self.link_listen(T_IFS_US + ADDRESS_US);
+            }
+            LinkPhase::Listening => {
+                // The `DISABLED_TXEN` shortcut already started ramping up the
+                // transmitter, which starts T_IFS after the received packet.
+                // The packet pointer can be changed until then, but the radio
+                // must stop after the answer.
+                self.registers
+                    .shorts
+                    .write(Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET);
+                if !self.registers.crcstatus.is_set(Event::READY) {
+                    self.link_done(Err(ErrorCode::FAIL));
+                    return;
+                }
+                let answer_len = self.link_client.map_or(0, |client| {
+                    // # Safety
+                    //
+                    // The radio is not using either buffer: the reception is
+                    // over, and the transmission of the answer has not
+                    // started.
+                    unsafe {
+                        let packet = &*addr_of!(PAYLOAD);
+                        let len = (2 + packet[1] as usize).min(ble_link::MAX_PACKET_LEN);
+                        client.packet_received(&packet[..len], &mut *addr_of_mut!(LINK_RESPONSE))
+                    }
+                });
+                if answer_len == 0 {
+                    self.link_done(Ok(()));
+                } else {
+                    self.registers.packetptr.set(addr_of!(LINK_RESPONSE) as u32);
+                    self.link_phase.set(LinkPhase::Answering);
+                }
+            }
+            LinkPhase::Answering => self.link_done(Ok(())),
+        }
+    }
+
+    /// Configure the radio for a link layer operation on `channel`.
+    fn link_initialize(&self, channel: RadioChannel) {
+        self.ble_initialize(channel);
+
+        // The base address holds the 3 least significant bytes of the
+        // access address, and the prefix its most significant byte.
+        let access_address = self.access_address.get();
+        self.registers.prefix0.set(access_address >> 24);
+        self.registers.base0.set(access_address << 8);
+        self.registers.crcinit.set(self.crc_init.get());
+        self.registers
+            .pcnf1
+            .modify(PacketConfiguration1::MAXLEN.val(ble_link::MAX_PACKET_LEN as u32 - 2));
+        self.registers
+            .tifs
+            .write(InterFrameSpacing::TIFS.val(T_IFS_US));
+
+        self.registers.event_address.write(Event::READY::CLEAR);
+        self.registers.event_disabled.write(Event::READY::CLEAR);
+        self.registers
+            .intenset
+            .write(Interrupt::ADDRESS::SET + Interrupt::DISABLED::SET);
+    }
+
+    /// Receive into `PAYLOAD`, and end the operation if no packet starts
+    /// within `window_us`.
+    fn link_listen(&self, window_us: u32) {
+        self.link_phase.set(LinkPhase::Listening);
+        self.registers.shorts.write(
+            Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET + Shortcut::DISABLED_TXEN::SET,
+        );
+        self.set_dma_ptr();
+        // Like the 802.15.4 radio, this relies on the timer counting
+        // microseconds.
+        self.timer
+            .map(|timer| timer.set_alarm(timer.now(), Ticks32::from(window_us)));
+    }
+
+    /// End the link layer operation in progress.
+    fn link_done(&self, result: Result<(), ErrorCode>) {
+        self.timer.map(|timer| timer.disarm());
+        self.registers.shorts.set(0);
+        self.disable_all_interrupts();
+        self.radio_off();
+        match self.link_operation.take() {
+            Some(LinkOperation::TransmitThenReceive) => {
+                // The transmission itself succeeded unless it was stopped.
+                let result = match result {
+                    Err(ErrorCode::CANCEL) => result,
+                    _ => Ok(()),
+                };
+                if let Some(buffer) = self.buffer.take() {
+                    self.link_client
+                        .map(|client| client.transmit_done(buffer, result));
+                }
+            }
+            Some(LinkOperation::ReceiveThenTransmit) => {
+                self.link_client.map(|client| client.receive_done(result));
+            }
+            None => {}
+        }
+    }
+
     pub fn enable_interrupts(&self) {
         self.registers.intenset.write(
             Interrupt::READY::SET
@@ -819,6 +1007,82 @@ impl<'a> ble_advertising::BleAdvertisementDriver<'a> for Radio<'a> {
     }
 }
 
+impl<'a> BleLinkRadio<'a> for Radio<'a> {
+    fn set_link_client(&self, client: &'a dyn BleLinkRadioClient) {
+        self.link_client.set(client);
+    }
+
+    fn set_access_address(&self, access_address: u32, crc_init: u32) {
+        self.access_address.set(access_address);
+        self.crc_init.set(crc_init);
+    }
+
+    fn transmit_then_receive(
+        &self,
+        channel: RadioChannel,
+        packet: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.link_operation.is_some() {
+            return Err((ErrorCode::BUSY, packet));
+        }
+        if len > packet.len() || len > ble_link::MAX_PACKET_LEN {
+            return Err((ErrorCode::SIZE, packet));
+        }
+        // # Safety
+        //
+        // The radio is off, so it is not using the buffer.
+        unsafe {
+            (&mut *addr_of_mut!(PAYLOAD))[..len].copy_from_slice(&packet[..len]);
+        }
+        self.buffer.replace(packet);
+        self.link_operation.set(LinkOperation::TransmitThenReceive);
+        self.link_phase.set(LinkPhase::Transmitting);
+
+        self.link_initialize(channel);
+        self.registers.shorts.write(
+            Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET + Shortcut::DISABLED_RXEN::SET,
+        );
+        self.registers.task_txen.write(Task::ENABLE::SET);
+        Ok(())
+    }
+
+    fn receive_then_transmit(
+        &self,
+        channel: RadioChannel,
+        window_us: u32,
+    ) -> Result<(), ErrorCode> {
+        if self.link_operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.link_operation.set(LinkOperation::ReceiveThenTransmit);
+
+        self.link_initialize(channel);
+        self.link_listen(RAMP_UP_US + window_us + ADDRESS_US);
+        self.registers.task_rxen.write(Task::ENABLE::SET);
+        Ok(())
+    }
+
+    fn stop(&self) {
+        if self.link_operation.is_some() {
+            self.link_done(Err(ErrorCode::CANCEL));
+        }
+    }
+}
+
+impl AlarmClient for Radio<'_> {
+    fn alarm(&self) {
+        // Unless a packet started in the window, which the radio interrupt
+        // will handle.
+        if self.link_operation.is_some()
+            && self.link_phase.get() == LinkPhase::Listening
+            && !self.registers.event_address.is_set(Event::READY)
+        {
+            self.link_done(Err(ErrorCode::NOACK));
+        }
+    }
+}
+
 impl ble_advertising::BleConfig for Radio<'_> {
     // The BLE Advertising Driver validates that the `tx_power` is between -20 to 10 dBm but then
     // underlying chip must validate if the current `tx_power` is supported as well
@@ -6,6 +6,7 @@
 
 use core::fmt::Write;
 use cortexm4f::{CortexM4F, CortexMVariant, nvic};
+use kernel::hil::time::Alarm;
 use kernel::platform::chip::InterruptService;
 use kernel::utilities::StaticRef;
 
@@ -113,6 +114,8 @@ impl Nrf52DefaultPeripherals<'_> {
     }
     // Necessary for setting up circular dependencies
     pub fn init(&'static self) {
+        self.ble_radio.set_timer_ref(&self.timer1);
+        self.timer1.set_alarm_client(&self.ble_radio);
         kernel::deferred_call::DeferredCallClient::register(&self.nvmc);
     }
 }
@@ -403,6 +403,30 @@ impl<'a> hil::i2c::I2CSlave<'a> for TWI<'a> {
     }
 }
 
+impl hil::i2c::I2CSlaveMultiAddress for TWI<'_> {
+    fn address_count(&self) -> usize {
+        2
+    }
+
+    fn set_address_at(&self, index: usize, addr: u8) -> Result<(), hil::i2c::Error> {
+        match index {
+            0 => hil::i2c::I2CSlave::set_address(self, addr),
+            1 => {
+                self.registers
+                    .address_1
+                    .write(ADDRESS::ADDRESS.val(addr as u32));
+                self.registers.config.modify(CONFIG::ADDRESS1::Enable);
+                Ok(())
+            }
+            _ => Err(hil::i2c::Error::NotSupported),
+        }
+    }
+
+    fn matched_address(&self) -> usize {
+        self.registers.match_reg.get() as usize & 1
+    }
+}
+
 impl<'a> hil::i2c::I2CMasterSlave<'a> for TWI<'a> {}
 
 // The SPI0_TWI0 and SPI1_TWI1 interrupts are dispatched to the
@@ -616,8 +616,25 @@ impl<'a> Can<'a> {
             }
         }
 
-        self.registers.can_firx[(filter_info.number as usize) * 2].modify(CAN_FiRx::FB.val(0));
-        self.registers.can_firx[(filter_info.number as usize) * 2 + 1].modify(CAN_FiRx::FB.val(0));
+        // request the identifier and mask (or second identifier) bits; only
+        // the 32 bit layout is supported
+        let (id, mask) = match filter_info.scale_bits {
+            can::ScaleBits::Bits16 => (0, 0),
+            can::ScaleBits::Bits32 => {
+                let id = Self::filter_register_id(filter_info.id);
+                match filter_info.identifier_mode {
+                    can::IdentifierMode::List => (id, id),
+                    can::IdentifierMode::Mask if filter_info.mask == 0 => (0, 0),
+                    can::IdentifierMode::Mask => (
+                        id,
+                        Self::filter_register_mask(filter_info.id, filter_info.mask),
+                    ),
+                }
+            }
+        };
+        self.registers.can_firx[(filter_info.number as usize) * 2].modify(CAN_FiRx::FB.val(id));
+        self.registers.can_firx[(filter_info.number as usize) * 2 + 1]
+            .modify(CAN_FiRx::FB.val(mask));
 
         // request filter mode to be mask or list
         match filter_info.identifier_mode {
@@ -655,6 +672,25 @@ impl<'a> Can<'a> {
         }
     }
 
+    /// Lays out an identifier as a 32 bit filter register expects it: the
+    /// standard or extended identifier followed by the IDE and RTR bits.
+    fn filter_register_id(id: can::Id) -> u32 {
+        match id {
+            can::Id::Standard(id) => (id as u32) << 21,
+            can::Id::Extended(id) => (id << 3) | (1 << 2),
+        }
+    }
+
+    /// Lays out a mask as a 32 bit filter register expects it. The IDE bit is
+    /// always compared, so that a filter for a standard identifier does not
+    /// accept extended ones and the other way around.
+    fn filter_register_mask(id: can::Id, mask: u32) -> u32 {
+        match id {
+            can::Id::Standard(_) => ((mask & 0x7FF) << 21) | (1 << 2),
+            can::Id::Extended(_) => ((mask & 0x1FFF_FFFF) << 3) | (1 << 2),
+        }
+    }
+
     pub fn enable_filter_config(&self) {
         // activate the filter configuration
         self.registers.can_fmr.modify(CAN_FMR::FINIT::CLEAR);
@@ -1226,6 +1262,38 @@ impl can::Configure for Can<'_> {
     }
 }
 
+impl can::Filter for Can<'_> {
+    fn enable_filter(&self, filter: can::FilterParameters) -> Result<(), kernel::ErrorCode> {
+        if filter.number as usize >= self.filter_count() {
+            return Err(kernel::ErrorCode::INVAL);
+        }
+        if let can::ScaleBits::Bits16 = filter.scale_bits {
+            return Err(kernel::ErrorCode::NOSUPPORT);
+        }
+        self.config_filter(filter, true);
+        self.enable_filter_config();
+        Ok(())
+    }
+
+    fn disable_filter(&self, number: u32) -> Result<(), kernel::ErrorCode> {
+        if number as usize >= self.filter_count() {
+            return Err(kernel::ErrorCode::INVAL);
+        }
+        let filter_number = 1 << number;
+        self.registers.can_fmr.modify(CAN_FMR::FINIT::SET);
+        self.registers.can_fa1r.modify(
+            CAN_FA1R::FACT.val(self.registers.can_fa1r.read(CAN_FA1R::FACT) & !filter_number),
+        );
+        self.enable_filter_config();
+        Ok(())
+    }
+
+    fn filter_count(&self) -> usize {
+        // each filter bank uses two registers
+        FILTER_COUNT / 2
+    }
+}
+
 impl can::Controller for Can<'_> {
     fn set_client(&self, client: Option<&'static dyn can::ControllerClient>) {
         if let Some(client) = client {
@@ -1358,6 +1426,8 @@ impl can::Receive<{ can::STANDARD_CAN_PACKET_SIZE }> for Can<'_> {
                         scale_bits: can::ScaleBits::Bits32,
                         identifier_mode: can::IdentifierMode::Mask,
                         fifo_number: 0,
+                        id: can::Id::Standard(0),
+                        mask: 0,
                     },
                     true,
                 );
@@ -1367,6 +1437,8 @@ impl can::Receive<{ can::STANDARD_CAN_PACKET_SIZE }> for Can<'_> {
                         scale_bits: can::ScaleBits::Bits32,
                         identifier_mode: can::IdentifierMode::Mask,
                         fifo_number: 1,
+                        id: can::Id::Standard(0),
+                        mask: 0,
                     },
                     true,
                 );
@@ -1390,6 +1462,8 @@ impl can::Receive<{ can::STANDARD_CAN_PACKET_SIZE }> for Can<'_> {
                         scale_bits: can::ScaleBits::Bits32,
                         identifier_mode: can::IdentifierMode::Mask,
                         fifo_number: 0,
+                        id: can::Id::Standard(0),
+                        mask: 0,
                     },
                     false,
                 );
@@ -1399,6 +1473,8 @@ impl can::Receive<{ can::STANDARD_CAN_PACKET_SIZE }> for Can<'_> {
                         scale_bits: can::ScaleBits::Bits32,
                         identifier_mode: can::IdentifierMode::Mask,
                         fifo_number: 1,
+                        id: can::Id::Standard(0),
+                        mask: 0,
                     },
                     false,
                 );
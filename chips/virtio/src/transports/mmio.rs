@@ -86,9 +86,8 @@ pub struct VirtIOMMIODeviceRegisters {
     /// 0x100 - 0x19C device configuration space
     ///
     /// This is individually defined per device, with a variable
-    /// size. TODO: How to address this properly? Just hand around
-    /// addresses to this?
-    config: [u32; 40],
+    /// size. Read through [`VirtIOMMIODevice::read_device_config`].
+    config: [ReadOnly<u32>; 40],
 }
 
 register_bitfields![u32,
@@ -191,6 +190,26 @@ impl VirtIOMMIODevice {
         // return the raw device ID number in the `Err` variant.
         VirtIODeviceType::from_device_id(device_id).ok_or(device_id)
     }
+
+    /// Read the device-specific configuration space from `offset` into `buf`.
+    ///
+    /// The layout of this space depends on the device type, for example the
+    /// MAC address of a network card is at offset 0. As per 4.2.2.2, the read
+    /// is retried until the configuration generation stays the same, so the
+    /// returned bytes are consistent.
+    pub fn read_device_config(&self, offset: usize, buf: &mut [u8]) {
+        loop {
+            let generation = self.regs.config_generation.get();
+            for (i, byte) in buf.iter_mut().enumerate() {
+                let position = offset + i;
+                let word = self.regs.config[position / 4].get();
+                *byte = word.to_le_bytes()[position % 4];
+            }
+            if self.regs.config_generation.get() == generation {
+                break;
+            }
+        }
+    }
 }
 
 impl VirtIOTransport for VirtIOMMIODevice {
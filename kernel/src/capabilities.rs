@@ -125,3 +125,18 @@ pub unsafe trait NetworkCapabilityCreationCapability {}
 /// debug writer mechanism has access to debugging print messages which may
 /// contain information about the operation of the kernel.
 pub unsafe trait SetDebugWriterCapability {}
+
+/// The `KernelUpdateCapability` allows the holder to write a new kernel image.
+///
+/// Whoever holds this capability can replace the code that runs with full
+/// privilege on the next boot, so it should only be given to the capsule the
+/// board trusts to receive kernel updates.
+pub unsafe trait KernelUpdateCapability {}
+
+/// The `DeferredCallResetCapability` allows the holder to forget all deferred
+/// calls with `DeferredCall::reset()`.
+///
+/// Deferred calls created before a reset deliver their callbacks to the
+/// clients of new ones, so this is only meant for host test environments
+/// which set up capsules many times. Boards should never create it.
+pub unsafe trait DeferredCallResetCapability {}
@@ -80,6 +80,12 @@ pub(crate) struct Config {
     // credentials checking, e.g., whether elf2tab and tockloader are generating
     // properly formatted footers.
     pub(crate) debug_process_credentials: bool,
+
+    /// Whether the kernel should paint the unused part of a process stack
+    /// with a pattern when the process tells the kernel where its stack
+    /// starts, so it can later find the deepest point the stack reached,
+    /// including between context switches.
+    pub(crate) paint_process_stacks: bool,
 }
 
 /// A unique instance of `Config` where compile-time configuration options are
@@ -92,4 +98,5 @@ pub(crate) const CONFIG: Config = Config {
     debug_load_processes: cfg!(feature = "debug_load_processes"),
     debug_panics: !cfg!(feature = "no_debug_panics"),
     debug_process_credentials: cfg!(feature = "debug_process_credentials"),
+    paint_process_stacks: cfg!(feature = "paint_process_stacks"),
 };
@@ -164,6 +164,9 @@ pub unsafe fn panic_print<PW: PanicWriter, C: Chip, PP: ProcessPrinter>(
     panic_resources: Option<&PanicResources<C, PP>>,
 ) {
     unsafe {
+        // Record the panic first, in case printing it fails.
+        crate::panic_record::record_panic(panic_info);
+
         // Create the synchronous writer we can use to output the panic message.
         let mut writer = PW::create_panic_writer(writer_config);
 
@@ -235,6 +238,9 @@ pub unsafe fn panic_print_old<W: Write + IoWrite, C: Chip, PP: ProcessPrinter>(
     panic_resources: Option<&PanicResources<C, PP>>,
 ) {
     unsafe {
+        // Record the panic first, in case printing it fails.
+        crate::panic_record::record_panic(panic_info);
+
         panic_begin(nop);
         // Flush debug buffer if needed
         flush(writer);
@@ -587,6 +593,7 @@ impl Write for &dyn DebugWriter {
 
 /// Write a debug message without a trailing newline.
 pub fn debug_print(args: Arguments) {
+    crate::panic_record::log(args);
     try_get_debug_writer(|mut writer| {
         let _ = write(&mut writer, args);
         writer.publish();
@@ -595,6 +602,7 @@ pub fn debug_print(args: Arguments) {
 
 /// Write a debug message with a trailing newline.
 pub fn debug_println(args: Arguments) {
+    crate::panic_record::log(format_args!("{}\r\n", args));
     try_get_debug_writer(|mut writer| {
         let _ = write(&mut writer, args);
         let _ = writer.write_str("\r\n");
@@ -645,6 +653,7 @@ fn write_header(
 /// Write a debug message with file and line information without a trailing
 /// newline.
 pub fn debug_verbose_print(args: Arguments, file_line: &(&'static str, u32)) {
+    crate::panic_record::log(args);
     try_get_debug_writer(|mut writer| {
         let _ = write_header(&mut writer, file_line);
         let _ = write(&mut writer, args);
@@ -655,6 +664,7 @@ pub fn debug_verbose_print(args: Arguments, file_line: &(&'static str, u32)) {
 /// Write a debug message with file and line information with a trailing
 /// newline.
 pub fn debug_verbose_println(args: Arguments, file_line: &(&'static str, u32)) {
+    crate::panic_record::log(format_args!("{}\r\n", args));
     try_get_debug_writer(|mut writer| {
         let _ = write_header(&mut writer, file_line);
         let _ = write(&mut writer, args);
@@ -81,6 +81,7 @@
 //! some_capsule.register();
 //! ```
 
+use crate::capabilities::DeferredCallResetCapability;
 use crate::platform::chip::ThreadIdProvider;
 use crate::utilities::cells::OptionalCell;
 use crate::utilities::single_thread_value::SingleThreadValue;
@@ -279,6 +280,23 @@ impl DeferredCall {
         }
     }
 
+    /// Forgets all deferred calls, so that up to 32 new ones can be created.
+    ///
+    /// This is meant for host tests, which set up capsules many times in one
+    /// process. Deferred calls created before this must not be used anymore,
+    /// as their callbacks would go to the clients of new deferred calls.
+    pub fn reset(_capability: &dyn DeferredCallResetCapability) {
+        if let (Some(ctr), Some(bitmask), Some(defcalls)) =
+            (CTR.get(), BITMASK.get(), DEFCALLS.get())
+        {
+            ctr.set(0);
+            bitmask.set(0);
+            for defcall in defcalls {
+                defcall.clear();
+            }
+        }
+    }
+
     /// Returns true if any deferred calls are waiting to be serviced, false
     /// otherwise.
     pub fn has_tasks() -> bool {
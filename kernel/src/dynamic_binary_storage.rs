@@ -6,8 +6,50 @@
 //!
 //! These functions facilitate dynamic application flashing and process creation
 //! during runtime without requiring the user to restart the device.
+//!
+//! Apps can also be uninstalled and upgraded at runtime. An upgrade keeps the
+//! old version of the app in flash while the new version runs on trial. The
+//! upgrade is then either committed, which replaces the old binary with a
+//! padding app, or rolled back, which replaces the new binary with a padding
+//! app and loads the old version again. Until then, both versions are in
+//! flash and after a reset the kernel loads the one with the higher version
+//! number.
+//!
+//! Processes removed this way free their process slot and their RAM. The
+//! process loader assigns freed RAM to processes loaded later, such as the
+//! new version of an upgraded app, or the old version again on rollback.
+//!
+//! The kernel counts the faults of the version on trial, as the fault policy
+//! of the processes it loads (see
+//! [`SequentialDynamicBinaryStorage::set_fault_policy()`]). The version on
+//! trial is restarted after a fault, until it faulted as many times as the
+//! rollback threshold: it is then stopped, and the upgrade is rolled back.
+//! The trial and its count of faults are kept in a trial record in a
+//! board-provided flash region, so that after a reset the kernel loads the
+//! new version and continues the trial, including the faults counted before.
+//! Without a trial record, a reset ends the trial and keeps the new version.
+//!
+//! Installing and removing apps leaves gaps of padding apps in flash that
+//! may be too small for the next app. Compaction moves app binaries down
+//! into these gaps so that the free space ends up after the last app. Only
+//! binaries that are position independent (they have no fixed flash address
+//! in their TBF header) are moved. A process running from a binary keeps
+//! running while the binary is copied, is stopped and removed right before
+//! the binary list is switched to the copy, and is loaded again from the
+//! copy once the move is done.
+//!
+//! Compaction writes flash one chunk at a time, so it runs in the background
+//! while the kernel is otherwise idle. It is started by the client, and
+//! automatically when there is no room for a new app. Each step of a move is
+//! recorded in a journal in a board-provided flash region first, and every
+//! step leaves the list of binaries intact. The journal has two slots that
+//! are written in turn, and a slot is erased before the next entry is
+//! written to it, so the previous entry survives a power loss while the slot
+//! is written. If power is lost during a move, the next compaction finishes
+//! it from the recorded step.
 
 use core::cell::Cell;
+use core::cmp;
 
 use crate::ErrorCode;
 use crate::config;
@@ -15,10 +57,11 @@ use crate::debug;
 use crate::deferred_call::{DeferredCall, DeferredCallClient};
 use crate::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
 use crate::platform::chip::Chip;
-use crate::process::ProcessLoadingAsyncClient;
+use crate::process::{FaultAction, Process, ProcessLoadingAsyncClient};
 use crate::process_loading::{
     PaddingRequirement, ProcessLoadError, SequentialProcessLoaderMachine,
 };
+use crate::process_policies::ProcessFaultPolicy;
 use crate::process_standard::ProcessStandardDebug;
 use crate::utilities::cells::{OptionalCell, TakeCell};
 use crate::utilities::leasable_buffer::SubSliceMut;
@@ -29,6 +72,26 @@ pub const BUF_LEN: usize = 512;
 /// The number of bytes in the TBF header for a padding app.
 const PADDING_TBF_HEADER_LENGTH: usize = 16;
 
+/// The number of bytes one entry of the compaction journal or the trial
+/// record needs: seven words, followed by their checksum.
+const ENTRY_LENGTH: usize = 32;
+
+/// The number of bytes the compaction journal needs, for its two slots.
+pub const COMPACTION_JOURNAL_LENGTH: usize = 2 * ENTRY_LENGTH;
+
+/// Marks a valid compaction journal entry.
+const COMPACTION_JOURNAL_MAGIC: u32 = 0x4a43_4254;
+
+/// The number of bytes the trial record needs, for its two slots.
+pub const TRIAL_RECORD_LENGTH: usize = 2 * ENTRY_LENGTH;
+
+/// Marks a valid trial record entry.
+const TRIAL_RECORD_MAGIC: u32 = 0x5452_4254;
+
+/// The number of faults of the version on trial after which the upgrade is
+/// rolled back, unless the board sets another threshold.
+pub const DEFAULT_ROLLBACK_THRESHOLD: usize = 3;
+
 #[derive(Clone, Copy, PartialEq)]
 pub enum State {
     Idle,
@@ -38,6 +101,13 @@ pub enum State {
     Abort,
     PaddingWrite,
     Fail,
+    Uninstall,
+    Upgrade,
+    Trial,
+    Commit,
+    Rollback,
+    Restore,
+    Compact,
 }
 
 /// Addresses of where the new process will be stored.
@@ -51,6 +121,114 @@ struct ProcessLoadMetadata {
     setup_padding: bool,
 }
 
+/// Steps of moving one app binary during compaction, in order.
+///
+/// Before the commit step, the binary list still leads to the app at its
+/// old address, after it, to the copy.
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum CompactionStep {
+    /// Make the gap starting at the new address one padding app.
+    MergeGap,
+    /// End the padding app that covers the new address there.
+    SplitGap,
+    /// Copy the binary, except for its first chunk.
+    Copy,
+    /// Write the padding app that follows the copy.
+    PadTail,
+    /// Copy the first chunk, which holds the TBF header.
+    Commit,
+    /// Replace the old binary with a padding app, if the copy ends where it
+    /// starts.
+    PadSource,
+    Done,
+}
+
+impl CompactionStep {
+    fn from_u32(step: u32) -> Option<Self> {
+        match step {
+            0 => Some(Self::MergeGap),
+            1 => Some(Self::SplitGap),
+            2 => Some(Self::Copy),
+            3 => Some(Self::PadTail),
+            4 => Some(Self::Commit),
+            5 => Some(Self::PadSource),
+            6 => Some(Self::Done),
+            _ => None,
+        }
+    }
+}
+
+/// An app binary being moved by compaction.
+#[derive(Clone, Copy)]
+struct CompactionMetadata {
+    step: CompactionStep,
+    src_addr: usize,
+    dst_addr: usize,
+    length: usize,
+    /// The padding app that covers `dst_addr`, if one does not start there.
+    split_addr: Option<usize>,
+    /// The next chunk to copy in the `Copy` step.
+    copy_offset: usize,
+    /// How far recording the current step in the journal got.
+    journal: JournalProgress,
+    /// Whether a process running from the binary was removed, and has to be
+    /// loaded from the copy.
+    reload: bool,
+}
+
+/// How far recording a step of a move in the compaction journal, or the
+/// trial of an upgrade in the trial record, got.
+#[derive(Clone, Copy, PartialEq)]
+enum JournalProgress {
+    /// The slot for the entry is being erased.
+    Erase,
+    /// The entry is being written to the erased slot.
+    Write,
+    /// The entry is recorded.
+    Recorded,
+}
+
+impl CompactionMetadata {
+    /// The step after the current one, skipping steps this move doesn't
+    /// need.
+    fn next_step(&self) -> CompactionStep {
+        let distance = self.src_addr - self.dst_addr;
+        match self.step {
+            CompactionStep::MergeGap if self.split_addr.is_some() => CompactionStep::SplitGap,
+            CompactionStep::MergeGap | CompactionStep::SplitGap if self.length > BUF_LEN => {
+                CompactionStep::Copy
+            }
+            CompactionStep::MergeGap | CompactionStep::SplitGap | CompactionStep::Copy
+                if distance > self.length =>
+            {
+                CompactionStep::PadTail
+            }
+            CompactionStep::MergeGap
+            | CompactionStep::SplitGap
+            | CompactionStep::Copy
+            | CompactionStep::PadTail => CompactionStep::Commit,
+            CompactionStep::Commit if distance == self.length => CompactionStep::PadSource,
+            CompactionStep::Commit | CompactionStep::PadSource | CompactionStep::Done => {
+                CompactionStep::Done
+            }
+        }
+    }
+}
+
+/// Addresses of the two versions of an app during an upgrade.
+#[derive(Clone, Copy, Default)]
+struct ProcessUpdateMetadata {
+    old_app_start_addr: usize,
+    old_app_length: usize,
+    new_app_start_addr: usize,
+    new_app_length: usize,
+    /// Whether the new version was loaded and is running on trial.
+    on_trial: bool,
+    /// How many times the new version faulted since it was loaded, including
+    /// before resets.
+    faults: usize,
+}
+
 /// This interface supports flashing binaries at runtime.
 pub trait DynamicBinaryStore {
     /// Call to request flashing a new binary.
@@ -63,6 +241,9 @@ pub trait DynamicBinaryStore {
     /// - `Ok(length)`: If there is a place to load the
     ///   process, the function will return `Ok()` with the size of the region
     ///   to store the process.
+    /// - `Err(ErrorCode::BUSY)`: If there is no room for the process, but
+    ///   the kernel started compacting the flash for apps. The update client
+    ///   is told when compaction is done, and setup can be tried again then.
     /// - `Err(ErrorCode)`: If there is nowhere to store the process a suitable
     ///   `ErrorCode` will be returned.
     fn setup(&self, app_length: usize) -> Result<usize, ErrorCode>;
@@ -77,8 +258,13 @@ pub trait DynamicBinaryStore {
     /// entire first 8 bytes.
     ///
     /// Returns an error if the write is outside of the permitted region or is
-    /// writing an invalid header.
-    fn write(&self, buffer: SubSliceMut<'static, u8>, offset: usize) -> Result<(), ErrorCode>;
+    /// writing an invalid header. The buffer is returned with the error,
+    /// unless the flash driver failed to start the write and kept it.
+    fn write(
+        &self,
+        buffer: SubSliceMut<'static, u8>,
+        offset: usize,
+    ) -> Result<(), (ErrorCode, Option<&'static mut [u8]>)>;
 
     /// Signal to the kernel that the requesting process is done writing the new
     /// binary.
@@ -128,6 +314,79 @@ pub trait DynamicProcessLoadClient {
     fn load_done(&self, result: Result<(), ProcessLoadError>);
 }
 
+/// This interface supports removing and replacing processes at runtime.
+///
+/// Processes are selected with their process identifier, i.e.
+/// `ProcessId::id()`.
+pub trait DynamicProcessUpdate {
+    /// Terminate the process, replace its binary in flash with a padding app
+    /// and free its process slot.
+    fn uninstall(&self, identifier: usize) -> Result<(), ErrorCode>;
+
+    /// Replace the process with the binary written with
+    /// [`DynamicBinaryStore`].
+    ///
+    /// This is called instead of [`DynamicProcessLoad::load()`] once
+    /// `finalize_done()` was received. The old process is terminated and the
+    /// new binary is loaded. If the new binary fails its credential checks or
+    /// cannot be loaded, the old version is loaded again.
+    ///
+    /// Once `upgrade_done()` reports success, the new version runs on trial
+    /// until [`DynamicProcessUpdate::commit()`] or
+    /// [`DynamicProcessUpdate::rollback()`] is called.
+    fn upgrade(&self, identifier: usize) -> Result<(), ErrorCode>;
+
+    /// Returns how many times the version on trial faulted since it was
+    /// loaded, including before resets.
+    fn trial_faults(&self) -> Result<usize, ErrorCode>;
+
+    /// Keep the version on trial and remove the old version from flash.
+    fn commit(&self) -> Result<(), ErrorCode>;
+
+    /// Remove the version on trial and load the old version again.
+    fn rollback(&self) -> Result<(), ErrorCode>;
+
+    /// Move app binaries towards the start of flash to merge the free space
+    /// between them.
+    ///
+    /// Binaries with a fixed flash address stay where they are. A process
+    /// running from a binary that is moved is restarted from the new copy.
+    fn compact(&self) -> Result<(), ErrorCode>;
+
+    /// Sets a client for the DynamicProcessUpdate Object
+    ///
+    /// When the client operation is done, it calls the `uninstall_done()`,
+    /// `upgrade_done()`, `commit_done()`, `rollback_done()` and
+    /// `compact_done()` functions.
+    fn set_update_client(&self, client: &'static dyn DynamicProcessUpdateClient);
+}
+
+/// The callback for removing and replacing processes.
+pub trait DynamicProcessUpdateClient {
+    /// The process was removed and its flash region is free.
+    fn uninstall_done(&self, result: Result<(), ErrorCode>);
+
+    /// The new version of the app was loaded and is running on trial.
+    ///
+    /// `Err(ErrorCode::CANCEL)` means the new version could not be loaded and
+    /// the old version is running again. `Err(ErrorCode::FAIL)` means neither
+    /// version could be loaded.
+    fn upgrade_done(&self, result: Result<(), ErrorCode>);
+
+    /// The old version of the app was removed from flash.
+    fn commit_done(&self, result: Result<(), ErrorCode>);
+
+    /// The version on trial was removed and the old version is running again.
+    ///
+    /// This is also called when the kernel rolled the upgrade back because
+    /// the version on trial faulted too often. An error means the old version
+    /// could not be loaded again.
+    fn rollback_done(&self, result: Result<(), ErrorCode>);
+
+    /// Compaction finished, returning how many binaries were moved.
+    fn compact_done(&self, result: Result<usize, ErrorCode>);
+}
+
 /// Dynamic process loading machine.
 pub struct SequentialDynamicBinaryStorage<
     'a,
@@ -141,7 +400,27 @@ pub struct SequentialDynamicBinaryStorage<
     buffer: TakeCell<'static, [u8]>,
     storage_client: OptionalCell<&'static dyn DynamicBinaryStoreClient>,
     load_client: OptionalCell<&'static dyn DynamicProcessLoadClient>,
+    update_client: OptionalCell<&'static dyn DynamicProcessUpdateClient>,
     process_metadata: OptionalCell<ProcessLoadMetadata>,
+    update_metadata: OptionalCell<ProcessUpdateMetadata>,
+    compaction_journal: OptionalCell<&'static [u8]>,
+    compaction_metadata: OptionalCell<CompactionMetadata>,
+    /// Sequence number of the newest entry in the compaction journal.
+    compaction_sequence: Cell<u32>,
+    compaction_moved: Cell<usize>,
+    /// The result of a compaction that stopped, reported once the process
+    /// removed for the interrupted move is loaded again.
+    compaction_result: OptionalCell<Result<usize, ErrorCode>>,
+    /// The policy for faults of processes other than the version on trial.
+    fault_policy: OptionalCell<&'static dyn ProcessFaultPolicy>,
+    rollback_threshold: Cell<usize>,
+    trial_record: OptionalCell<&'static [u8]>,
+    /// Sequence number of the newest entry in the trial record.
+    trial_record_sequence: Cell<u32>,
+    /// How far writing the trial record got, while it is written.
+    trial_record_progress: OptionalCell<JournalProgress>,
+    /// The version on trial faulted again while the trial record was written.
+    trial_record_stale: Cell<bool>,
     state: Cell<State>,
     deferred_call: DeferredCall,
 }
@@ -160,12 +439,76 @@ impl<'a, 'b, C: Chip + 'static, D: ProcessStandardDebug + 'static, F: Nonvolatil
             buffer: TakeCell::new(buffer),
             storage_client: OptionalCell::empty(),
             load_client: OptionalCell::empty(),
+            update_client: OptionalCell::empty(),
             process_metadata: OptionalCell::empty(),
+            update_metadata: OptionalCell::empty(),
+            compaction_journal: OptionalCell::empty(),
+            compaction_metadata: OptionalCell::empty(),
+            compaction_sequence: Cell::new(0),
+            compaction_moved: Cell::new(0),
+            compaction_result: OptionalCell::empty(),
+            fault_policy: OptionalCell::empty(),
+            rollback_threshold: Cell::new(DEFAULT_ROLLBACK_THRESHOLD),
+            trial_record: OptionalCell::empty(),
+            trial_record_sequence: Cell::new(0),
+            trial_record_progress: OptionalCell::empty(),
+            trial_record_stale: Cell::new(false),
             state: Cell::new(State::Idle),
             deferred_call: DeferredCall::new(),
         }
     }
 
+    /// Set the flash region compaction keeps its journal in.
+    ///
+    /// The region must be at least `COMPACTION_JOURNAL_LENGTH` bytes long
+    /// and must not be part of the flash region for apps. Its two halves are
+    /// the two journal slots, and each should be a flash page of its own, so
+    /// erasing one slot never touches the other. Compaction is not supported
+    /// without a journal.
+    pub fn set_compaction_journal(&self, journal: &'static [u8]) {
+        self.compaction_journal.set(journal);
+    }
+
+    /// Set the policy for faults of processes other than the version of an
+    /// app on trial.
+    ///
+    /// The board makes the storage the fault policy of the process loader,
+    /// and passes the policy it would have used here. Without a policy, the
+    /// kernel panics when such a process faults.
+    pub fn set_fault_policy(&self, policy: &'static dyn ProcessFaultPolicy) {
+        self.fault_policy.set(policy);
+    }
+
+    /// Set how many times the version of an app on trial may fault before
+    /// the upgrade is rolled back.
+    pub fn set_rollback_threshold(&self, threshold: usize) {
+        self.rollback_threshold.set(threshold);
+    }
+
+    /// Set the flash region the trial of an upgrade is recorded in, and
+    /// continue the trial recorded there.
+    ///
+    /// The region must be at least `TRIAL_RECORD_LENGTH` bytes long, must not
+    /// be part of the flash region for apps, and its two halves should each
+    /// be a flash page of its own, like the compaction journal. The board
+    /// sets the region at boot, before processes are loaded, so the new
+    /// version of an app on trial is loaded as such.
+    pub fn set_trial_record(&self, record: &'static [u8]) {
+        self.trial_record.set(record);
+        let flash = self.loader_driver.flash_bank();
+        let (sequence, trial) = (0..2)
+            .filter_map(|sequence| read_trial_record_entry(slot(record, sequence)?, flash))
+            .max_by_key(|(sequence, _)| *sequence)
+            .unwrap_or((0, None));
+        self.trial_record_sequence.set(sequence);
+        if let Some(update) = trial {
+            if self.state.get() == State::Idle {
+                self.update_metadata.set(update);
+                self.state.set(State::Trial);
+            }
+        }
+    }
+
     /// Function to reset variables and states.
     fn reset_process_loading_metadata(&self) {
         self.state.set(State::Idle);
@@ -210,7 +553,14 @@ impl<'a, 'b, C: Chip + 'static, D: ProcessStandardDebug + 'static, F: Nonvolatil
             // If we are going to write the padding header, we already know
             // where to write in flash, so we don't have to add the start
             // address
-            State::Setup | State::Load | State::PaddingWrite | State::Abort => Ok(offset),
+            State::Setup
+            | State::Load
+            | State::PaddingWrite
+            | State::Abort
+            | State::Uninstall
+            | State::Commit
+            | State::Rollback
+            | State::Compact => Ok(offset),
             // We aren't supposed to be able to write unless we are in one of
             // the first two write states
             _ => Err(ErrorCode::FAIL),
@@ -219,15 +569,30 @@ impl<'a, 'b, C: Chip + 'static, D: ProcessStandardDebug + 'static, F: Nonvolatil
 
     /// Compute the physical address where we should write the data and then
     /// write it.
+    ///
+    /// The buffer is returned with the error, unless the flash driver failed
+    /// to start the write and kept it.
     fn write_buffer(
         &self,
         user_buffer: SubSliceMut<'static, u8>,
         offset: usize,
-    ) -> Result<(), ErrorCode> {
+    ) -> Result<(), (ErrorCode, Option<&'static mut [u8]>)> {
         let length = user_buffer.len();
         // Take the buffer to perform tbf header validation and write with.
         let buffer = user_buffer.take();
 
+        match self.check_write(buffer, offset, length) {
+            Ok(physical_address) => self
+                .flash_driver
+                .write(buffer, physical_address, length)
+                .map_err(|e| (e, None)),
+            Err(e) => Err((e, Some(buffer))),
+        }
+    }
+
+    /// Check that `length` bytes of `buffer` can be written at `offset`, and
+    /// return the physical address to write them to.
+    fn check_write(&self, buffer: &[u8], offset: usize, length: usize) -> Result<usize, ErrorCode> {
         let physical_address = self.compute_address(offset, length)?;
 
         // The kernel needs to check if the app is trying to write/overwrite the
@@ -281,7 +646,7 @@ impl<'a, 'b, C: Chip + 'static, D: ProcessStandardDebug + 'static, F: Nonvolatil
                 return Err(ErrorCode::INVAL);
             }
         }
-        self.flash_driver.write(buffer, physical_address, length)
+        Ok(physical_address)
     }
 
     /// Function to generate the padding header to append after the new app.
@@ -317,33 +682,668 @@ impl<'a, 'b, C: Chip + 'static, D: ProcessStandardDebug + 'static, F: Nonvolatil
             buffer[15] = buffer[3] ^ buffer[7] ^ buffer[11];
         });
 
+        if !self
+            .loader_driver
+            .check_if_within_flash_bounds(offset, PADDING_TBF_HEADER_LENGTH)
+        {
+            return Err(ErrorCode::NOMEM);
+        }
         self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
-            match self
+            // Write the header only if there are more than 16 bytes available
+            // in the flash.
+            let mut padding_slice = SubSliceMut::new(buffer);
+            padding_slice.slice(..PADDING_TBF_HEADER_LENGTH);
+            // We are only writing the header, so 16 bytes is enough.
+            self.write_buffer(padding_slice, offset)
+                .map_err(|(e, buffer)| {
+                    if let Some(buffer) = buffer {
+                        self.buffer.replace(buffer);
+                    }
+                    e
+                })
+        })
+    }
+
+    /// Replace the new version of the app being upgraded with a padding app,
+    /// then load the old version again.
+    fn start_rollback(&self) {
+        self.state.set(State::Rollback);
+        if let Some(update) = self.update_metadata.get() {
+            if self
+                .write_padding_app(update.new_app_length, update.new_app_start_addr)
+                .is_err()
+            {
+                // Failing to remove the new binary must not keep the old
+                // version from running.
+                self.restore_old_version();
+            }
+        }
+    }
+
+    /// Load the new version of the app being upgraded, once the upgrade is
+    /// recorded.
+    fn load_upgrade(&self) {
+        if let Some(update) = self.update_metadata.get() {
+            if self
                 .loader_driver
-                .check_if_within_flash_bounds(offset, PADDING_TBF_HEADER_LENGTH)
+                .load_new_process_binary(update.new_app_start_addr, update.new_app_length)
+                .is_err()
             {
-                true => {
-                    // Write the header only if there are more than 16 bytes.
-                    // available in the flash.
-                    let mut padding_slice = SubSliceMut::new(buffer);
-                    padding_slice.slice(..PADDING_TBF_HEADER_LENGTH);
-                    // We are only writing the header, so 16 bytes is enough.
-                    self.write_buffer(padding_slice, offset)
+                self.start_rollback();
+            }
+        }
+    }
+
+    /// Remove the version on trial, then replace it with a padding app and
+    /// load the old version again.
+    fn roll_back_trial(&self) {
+        if let Some(update) = self.update_metadata.get() {
+            if let Some(process) = self
+                .loader_driver
+                .find_process_at(update.new_app_start_addr)
+            {
+                self.loader_driver.remove_process(process);
+            }
+        }
+        self.start_rollback();
+    }
+
+    /// Load the old version of the app being upgraded again.
+    fn restore_old_version(&self) {
+        self.state.set(State::Restore);
+        if let Some(update) = self.update_metadata.get() {
+            if self
+                .loader_driver
+                .load_new_process_binary(update.old_app_start_addr, update.old_app_length)
+                .is_err()
+            {
+                // Signal the failure from a deferred call.
+                self.deferred_call.set();
+            }
+        }
+    }
+
+    /// Record that the upgrade ended, then let the client know which version
+    /// of the app is running after a rollback.
+    fn restore_done(&self) {
+        self.update_trial_record();
+    }
+
+    /// Let the client know which version of the app is running after a
+    /// rollback.
+    fn report_restore(&self) {
+        let update = self.update_metadata.take();
+        self.state.set(State::Idle);
+
+        let restored = update
+            .and_then(|update| {
+                self.loader_driver
+                    .find_process_at(update.old_app_start_addr)
+            })
+            .is_some();
+        let on_trial = update.is_some_and(|update| update.on_trial);
+
+        self.update_client.map(|client| match (on_trial, restored) {
+            (true, true) => client.rollback_done(Ok(())),
+            (true, false) => client.rollback_done(Err(ErrorCode::FAIL)),
+            (false, true) => client.upgrade_done(Err(ErrorCode::CANCEL)),
+            (false, false) => client.upgrade_done(Err(ErrorCode::FAIL)),
+        });
+    }
+
+    /// Record the upgrade being set up or on trial in the trial record, or
+    /// that there is none, then continue with the operation in progress.
+    ///
+    /// Failing to write the record does not stop the operation, the trial is
+    /// just not kept across a reset.
+    fn update_trial_record(&self) {
+        if self.trial_record_progress.is_some() {
+            // The record is written again once the entry being written is
+            // done.
+            self.trial_record_stale.set(true);
+        } else if self.write_trial_record(JournalProgress::Erase).is_err() {
+            self.trial_record_updated();
+        }
+    }
+
+    /// Erase the slot of the next trial record entry, or write the entry to
+    /// the erased slot, depending on `progress`.
+    fn write_trial_record(&self, progress: JournalProgress) -> Result<(), ErrorCode> {
+        let sequence = self.trial_record_sequence.get().wrapping_add(1);
+        let slot = self
+            .trial_record
+            .get()
+            .and_then(|record| slot(record, sequence))
+            .ok_or(ErrorCode::NOSUPPORT)?;
+        let mut entry = [0xff; ENTRY_LENGTH];
+        if progress == JournalProgress::Write {
+            let update = match self.state.get() {
+                State::Upgrade | State::Trial | State::Rollback => self.update_metadata.get(),
+                _ => None,
+            };
+            // Addresses are recorded as offsets into the flash for apps, a
+            // zero length records that there is no trial.
+            let flash_start = self.loader_driver.flash_bank().as_ptr() as usize;
+            entry = encode_entry(match update {
+                Some(update) => [
+                    TRIAL_RECORD_MAGIC,
+                    sequence,
+                    (update.old_app_start_addr - flash_start) as u32,
+                    update.old_app_length as u32,
+                    (update.new_app_start_addr - flash_start) as u32,
+                    update.new_app_length as u32,
+                    update.faults as u32,
+                ],
+                None => [TRIAL_RECORD_MAGIC, sequence, 0, 0, 0, 0, 0],
+            });
+        }
+        self.write_entry(slot, &entry)?;
+        self.trial_record_progress.set(progress);
+        Ok(())
+    }
+
+    /// Continue writing the trial record after a flash write finished.
+    fn trial_record_write_done(&self) {
+        let result = match self.trial_record_progress.take() {
+            Some(JournalProgress::Erase) => self.write_trial_record(JournalProgress::Write),
+            _ => {
+                self.trial_record_sequence
+                    .set(self.trial_record_sequence.get().wrapping_add(1));
+                if self.trial_record_stale.take() {
+                    self.write_trial_record(JournalProgress::Erase)
+                } else {
+                    self.trial_record_updated();
+                    Ok(())
+                }
+            }
+        };
+        if result.is_err() {
+            self.trial_record_stale.set(false);
+            self.trial_record_updated();
+        }
+    }
+
+    /// Continue the operation that updated the trial record.
+    fn trial_record_updated(&self) {
+        match self.state.get() {
+            State::Upgrade => self.load_upgrade(),
+            // The version on trial reached the rollback threshold while its
+            // faults were recorded.
+            State::Rollback => self.roll_back_trial(),
+            State::Commit => {
+                self.update_metadata.take();
+                self.state.set(State::Idle);
+                self.update_client.map(|client| {
+                    client.commit_done(Ok(()));
+                });
+            }
+            State::Restore => self.report_restore(),
+            _ => {}
+        }
+    }
+
+    /// Find the next app binary compaction can move, and where to.
+    ///
+    /// A binary is moved to the first address after the previous binary that
+    /// follows the alignment rules for new apps. Its current address follows
+    /// the same rules, so the old and new copies never overlap.
+    fn find_compaction_move(&self) -> Option<CompactionMetadata> {
+        let flash = self.loader_driver.flash_bank();
+        let flash_start = flash.as_ptr() as usize;
+        // End of the last binary that stays where it is.
+        let mut gap_start = flash_start;
+        let mut offset = 0;
+
+        while let Some((version, header_length, length)) = entry_lengths(flash, offset) {
+            let address = flash_start + offset;
+            // Padding apps are part of the gap.
+            if header_length != PADDING_TBF_HEADER_LENGTH {
+                if address > gap_start && is_movable(flash, offset, version, header_length) {
+                    let dst_addr = self
+                        .loader_driver
+                        .find_next_cortex_m_aligned_address(gap_start, length);
+                    if dst_addr + length <= address {
+                        return Some(CompactionMetadata {
+                            step: CompactionStep::MergeGap,
+                            src_addr: address,
+                            dst_addr,
+                            length,
+                            split_addr: padding_covering(flash, gap_start, dst_addr),
+                            copy_offset: BUF_LEN,
+                            journal: JournalProgress::Erase,
+                            reload: false,
+                        });
+                    }
+                }
+                gap_start = address + length;
+            }
+            offset += length;
+        }
+        None
+    }
+
+    /// Check whether there is a move to finish or a binary to move.
+    fn compaction_needed(&self) -> bool {
+        self.compaction_journal_slot(0).is_some()
+            && (self.read_compaction_journal().1.is_some() || self.find_compaction_move().is_some())
+    }
+
+    /// Start compacting the flash used for apps, finishing the move recorded
+    /// in the journal first.
+    fn start_compaction(&self) -> Result<(), ErrorCode> {
+        self.compaction_moved.set(0);
+        self.state.set(State::Compact);
+        let (sequence, interrupted) = self.read_compaction_journal();
+        self.compaction_sequence.set(sequence);
+        let result = match interrupted {
+            // Continue with the step that was interrupted.
+            Some(mut metadata) => {
+                self.stop_moved_process(&mut metadata);
+                self.compaction_metadata.set(metadata);
+                self.run_compaction_step(metadata)
+            }
+            None => self.start_next_compaction_move(),
+        };
+        if result.is_err() {
+            self.compaction_metadata.take();
+            self.state.set(State::Idle);
+        }
+        result
+    }
+
+    /// Start moving the next binary, or finish compaction if there is none.
+    fn start_next_compaction_move(&self) -> Result<(), ErrorCode> {
+        match self.find_compaction_move() {
+            Some(metadata) => {
+                self.compaction_metadata.set(metadata);
+                self.write_compaction_journal(metadata)
+            }
+            None => {
+                // Signal the completion from a deferred call.
+                self.deferred_call.set();
+                Ok(())
+            }
+        }
+    }
+
+    /// Remove the process running from the binary being moved, once the
+    /// binary list is about to lead to the copy.
+    ///
+    /// The process is loaded from the copy again when the move is done.
+    fn stop_moved_process(&self, metadata: &mut CompactionMetadata) {
+        if metadata.step < CompactionStep::Commit {
+            return;
+        }
+        if let Some(process) = self.loader_driver.find_process_at(metadata.src_addr) {
+            self.loader_driver.remove_process(process);
+            metadata.reload = true;
+        }
+    }
+
+    /// Get the compaction journal slot for the entry with `sequence`.
+    fn compaction_journal_slot(&self, sequence: u32) -> Option<&'static [u8]> {
+        slot(self.compaction_journal.get()?, sequence)
+    }
+
+    /// Erase the slot of the next compaction journal entry, or write the
+    /// entry to the erased slot, depending on `metadata.journal`.
+    ///
+    /// The entry records the step of the move, or that there is no move in
+    /// progress once the move is done.
+    fn write_compaction_journal(&self, metadata: CompactionMetadata) -> Result<(), ErrorCode> {
+        let sequence = self.compaction_sequence.get().wrapping_add(1);
+        let slot = self
+            .compaction_journal_slot(sequence)
+            .ok_or(ErrorCode::NOSUPPORT)?;
+        let mut entry = [0xff; ENTRY_LENGTH];
+        if metadata.journal == JournalProgress::Write {
+            // Addresses are recorded as offsets into the flash for apps.
+            let flash_start = self.loader_driver.flash_bank().as_ptr() as usize;
+            entry = encode_entry([
+                COMPACTION_JOURNAL_MAGIC,
+                sequence,
+                metadata.step as u32,
+                (metadata.src_addr - flash_start) as u32,
+                (metadata.dst_addr - flash_start) as u32,
+                metadata.length as u32,
+                metadata
+                    .split_addr
+                    .map_or(u32::MAX, |split_addr| (split_addr - flash_start) as u32),
+            ]);
+        }
+        self.write_entry(slot, &entry)
+    }
+
+    /// Write `entry` to the start of `slot` of the compaction journal or the
+    /// trial record.
+    fn write_entry(&self, slot: &[u8], entry: &[u8]) -> Result<(), ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            if buffer.len() < entry.len() {
+                self.buffer.replace(buffer);
+                return Err(ErrorCode::SIZE);
+            }
+            buffer[..entry.len()].copy_from_slice(entry);
+            self.flash_driver
+                .write(buffer, slot.as_ptr() as usize, entry.len())
+        })
+    }
+
+    /// Get the sequence number of the newest compaction journal entry, and
+    /// the move it records, if there is one.
+    fn read_compaction_journal(&self) -> (u32, Option<CompactionMetadata>) {
+        let flash = self.loader_driver.flash_bank();
+        (0..2)
+            .filter_map(|sequence| {
+                read_compaction_journal_entry(self.compaction_journal_slot(sequence)?, flash)
+            })
+            .max_by_key(|(sequence, _)| *sequence)
+            .unwrap_or((0, None))
+    }
+
+    /// Write the flash for the current step of the move.
+    fn run_compaction_step(&self, metadata: CompactionMetadata) -> Result<(), ErrorCode> {
+        let distance = metadata.src_addr - metadata.dst_addr;
+        match metadata.step {
+            CompactionStep::MergeGap => self.write_padding_app(distance, metadata.dst_addr),
+            CompactionStep::SplitGap => {
+                let split_addr = metadata.split_addr.ok_or(ErrorCode::FAIL)?;
+                self.write_padding_app(metadata.dst_addr - split_addr, split_addr)
+            }
+            CompactionStep::Copy => self.copy_compaction_chunk(metadata, metadata.copy_offset),
+            CompactionStep::PadTail => {
+                self.write_padding_app(distance, metadata.dst_addr + metadata.length)
+            }
+            CompactionStep::Commit => self.copy_compaction_chunk(metadata, 0),
+            CompactionStep::PadSource => self.write_padding_app(metadata.length, metadata.src_addr),
+            CompactionStep::Done => Err(ErrorCode::FAIL),
+        }
+    }
+
+    /// Copy the chunk at `offset` of the binary being moved to its new
+    /// address.
+    fn copy_compaction_chunk(
+        &self,
+        metadata: CompactionMetadata,
+        offset: usize,
+    ) -> Result<(), ErrorCode> {
+        let flash = self.loader_driver.flash_bank();
+        let src_offset = metadata.src_addr - flash.as_ptr() as usize + offset;
+        let length = cmp::min(BUF_LEN, metadata.length - offset);
+        let chunk = flash
+            .get(src_offset..src_offset + length)
+            .ok_or(ErrorCode::FAIL)?;
+
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            if buffer.len() < length {
+                self.buffer.replace(buffer);
+                return Err(ErrorCode::SIZE);
+            }
+            buffer[..length].copy_from_slice(chunk);
+            self.flash_driver
+                .write(buffer, metadata.dst_addr + offset, length)
+        })
+    }
+
+    /// Continue compaction after a flash write finished.
+    fn compaction_write_done(&self) {
+        let Some(mut metadata) = self.compaction_metadata.get() else {
+            self.compaction_done(Err(ErrorCode::FAIL));
+            return;
+        };
+        let result = match metadata.journal {
+            JournalProgress::Erase => {
+                metadata.journal = JournalProgress::Write;
+                self.compaction_metadata.set(metadata);
+                self.write_compaction_journal(metadata)
+            }
+            JournalProgress::Write => {
+                self.compaction_sequence
+                    .set(self.compaction_sequence.get().wrapping_add(1));
+                metadata.journal = JournalProgress::Recorded;
+                if metadata.step == CompactionStep::Done {
+                    self.compaction_metadata.take();
+                    self.compaction_moved.set(self.compaction_moved.get() + 1);
+                    if metadata.reload {
+                        // Loading continues compaction once it is finished.
+                        self.loader_driver
+                            .load_new_process_binary(metadata.dst_addr, metadata.length)
+                            .map_err(|_| ErrorCode::FAIL)
+                    } else {
+                        self.start_next_compaction_move()
+                    }
+                } else {
+                    self.compaction_metadata.set(metadata);
+                    self.run_compaction_step(metadata)
                 }
-                false => Err(ErrorCode::NOMEM),
             }
+            JournalProgress::Recorded
+                if metadata.step == CompactionStep::Copy
+                    && metadata.copy_offset + BUF_LEN < metadata.length =>
+            {
+                // Chunks are not journaled, a resumed move copies all of
+                // them again.
+                metadata.copy_offset += BUF_LEN;
+                self.compaction_metadata.set(metadata);
+                self.run_compaction_step(metadata)
+            }
+            JournalProgress::Recorded => {
+                metadata.step = metadata.next_step();
+                metadata.journal = JournalProgress::Erase;
+                self.stop_moved_process(&mut metadata);
+                self.compaction_metadata.set(metadata);
+                self.write_compaction_journal(metadata)
+            }
+        };
+        if let Err(e) = result {
+            self.compaction_done(Err(e));
+        }
+    }
+
+    /// Let the client know compaction stopped.
+    ///
+    /// If it stopped in the middle of a move, the journal still records the
+    /// move, so the next compaction finishes it. A process removed for the
+    /// move is loaded again first, from the copy the binary list leads to.
+    fn compaction_done(&self, result: Result<usize, ErrorCode>) {
+        if let Some(metadata) = self
+            .compaction_metadata
+            .take()
+            .filter(|metadata| metadata.reload)
+        {
+            let flash = self.loader_driver.flash_bank();
+            // Until the commit step, a padding app starts at the new address.
+            let committed = entry_lengths(flash, metadata.dst_addr - flash.as_ptr() as usize)
+                .is_some_and(|(_, header_length, length)| {
+                    header_length != PADDING_TBF_HEADER_LENGTH && length == metadata.length
+                });
+            let address = if committed {
+                metadata.dst_addr
+            } else {
+                metadata.src_addr
+            };
+            if self
+                .loader_driver
+                .load_new_process_binary(address, metadata.length)
+                .is_ok()
+            {
+                self.compaction_result.set(result);
+                return;
+            }
+        }
+
+        self.state.set(State::Idle);
+        self.update_client.map(|client| {
+            client.compact_done(result);
+        });
+    }
+}
+
+/// Check whether compaction may move the binary at `offset` in `flash`.
+fn is_movable(flash: &[u8], offset: usize, version: u16, header_length: usize) -> bool {
+    flash
+        .get(offset..offset + header_length)
+        .and_then(|header| tock_tbf::parse::parse_tbf_header(header, version).ok())
+        .is_some_and(|header| header.get_fixed_address_flash().is_none())
+}
+
+/// Parse the compaction journal entry in `slot`, returning its sequence
+/// number and the move it records in `flash`.
+///
+/// Returns `None` if the slot holds no valid entry.
+fn read_compaction_journal_entry(
+    slot: &[u8],
+    flash: &[u8],
+) -> Option<(u32, Option<CompactionMetadata>)> {
+    let words = decode_entry(slot, COMPACTION_JOURNAL_MAGIC)?;
+    let step = CompactionStep::from_u32(words[2])?;
+    if step == CompactionStep::Done {
+        return Some((words[1], None));
+    }
+    let (src_offset, dst_offset, length) =
+        (words[3] as usize, words[4] as usize, words[5] as usize);
+    if dst_offset.checked_add(length)? > src_offset || src_offset + length > flash.len() {
+        return None;
+    }
+    let flash_start = flash.as_ptr() as usize;
+    let metadata = CompactionMetadata {
+        step,
+        src_addr: flash_start + src_offset,
+        dst_addr: flash_start + dst_offset,
+        length,
+        split_addr: (words[6] != u32::MAX).then_some(flash_start + words[6] as usize),
+        copy_offset: BUF_LEN,
+        journal: JournalProgress::Recorded,
+        reload: false,
+    };
+    Some((words[1], Some(metadata)))
+}
+
+/// Parse the trial record entry in `slot`, returning its sequence number and
+/// the upgrade on trial it records.
+///
+/// The trial ends with the upgrade when one of the versions is replaced with
+/// a padding app, so an entry records no trial unless both versions are still
+/// in `flash`. Returns `None` if the slot holds no valid entry.
+fn read_trial_record_entry(
+    slot: &[u8],
+    flash: &[u8],
+) -> Option<(u32, Option<ProcessUpdateMetadata>)> {
+    let words = decode_entry(slot, TRIAL_RECORD_MAGIC)?;
+    let (old_offset, old_length, new_offset, new_length) = (
+        words[2] as usize,
+        words[3] as usize,
+        words[4] as usize,
+        words[5] as usize,
+    );
+    let is_app = |offset: usize, length: usize| {
+        entry_lengths(flash, offset).is_some_and(|(_, header_length, entry_length)| {
+            header_length != PADDING_TBF_HEADER_LENGTH && entry_length == length
         })
+    };
+    if old_length == 0 || !is_app(old_offset, old_length) || !is_app(new_offset, new_length) {
+        return Some((words[1], None));
     }
+    let flash_start = flash.as_ptr() as usize;
+    let update = ProcessUpdateMetadata {
+        old_app_start_addr: flash_start + old_offset,
+        old_app_length: old_length,
+        new_app_start_addr: flash_start + new_offset,
+        new_app_length: new_length,
+        on_trial: true,
+        faults: words[6] as usize,
+    };
+    Some((words[1], Some(update)))
+}
+
+/// Get the slot of `region`, the compaction journal or the trial record, for
+/// the entry with `sequence`.
+fn slot(region: &'static [u8], sequence: u32) -> Option<&'static [u8]> {
+    let slot_length = region.len() / 2;
+    let start = (sequence % 2) as usize * slot_length;
+    region
+        .get(start..start + slot_length)
+        .filter(|slot| slot.len() >= ENTRY_LENGTH)
+}
+
+/// Encode the words of an entry of the compaction journal or the trial
+/// record, followed by their checksum.
+fn encode_entry(words: [u32; ENTRY_LENGTH / 4 - 1]) -> [u8; ENTRY_LENGTH] {
+    let checksum = words.iter().fold(0, |checksum, word| checksum ^ word);
+    let mut entry = [0; ENTRY_LENGTH];
+    for (bytes, word) in entry
+        .chunks_exact_mut(4)
+        .zip(words.iter().chain(core::iter::once(&checksum)))
+    {
+        bytes.copy_from_slice(&word.to_le_bytes());
+    }
+    entry
+}
+
+/// Decode the words of the entry in `slot`, if it is marked with `magic`
+/// and its checksum matches.
+fn decode_entry(slot: &[u8], magic: u32) -> Option<[u32; ENTRY_LENGTH / 4]> {
+    let mut words = [0u32; ENTRY_LENGTH / 4];
+    for (word, bytes) in words
+        .iter_mut()
+        .zip(slot.get(..ENTRY_LENGTH)?.chunks_exact(4))
+    {
+        *word = u32::from_le_bytes(bytes.try_into().ok()?);
+    }
+    let checksum = words[..7].iter().fold(0, |checksum, word| checksum ^ word);
+    (words[0] == magic && words[7] == checksum).then_some(words)
+}
+
+/// Parse the lengths in the TBF header at `offset` in `flash`.
+///
+/// Returns `None` at the end of the list of binaries.
+fn entry_lengths(flash: &[u8], offset: usize) -> Option<(u16, usize, usize)> {
+    let header = flash.get(offset..offset + 8)?.try_into().ok()?;
+    let (version, header_length, entry_length) =
+        match tock_tbf::parse::parse_tbf_header_lengths(header) {
+            Ok((v, hl, el)) => (v, hl as usize, el as usize),
+            // Keep the entry so it is skipped, but it can't be parsed further.
+            Err(tock_tbf::types::InitialTbfParseError::InvalidHeader(el)) => (0, 0, el as usize),
+            Err(tock_tbf::types::InitialTbfParseError::UnableToParse) => return None,
+        };
+    if entry_length == 0 || offset + entry_length > flash.len() {
+        return None;
+    }
+    Some((version, header_length, entry_length))
+}
+
+/// Find the padding app from `start` on that covers `address`, unless an entry
+/// starts at `address`.
+fn padding_covering(flash: &[u8], start: usize, address: usize) -> Option<usize> {
+    let flash_start = flash.as_ptr() as usize;
+    let mut entry = start;
+    while entry < address {
+        let (_, _, length) = entry_lengths(flash, entry - flash_start)?;
+        if entry + length > address {
+            return Some(entry);
+        }
+        entry += length;
+    }
+    None
 }
 
 impl<'b, C: Chip, D: ProcessStandardDebug, F: NonvolatileStorage<'b>> DeferredCallClient
     for SequentialDynamicBinaryStorage<'_, 'b, C, D, F>
 {
     fn handle_deferred_call(&self) {
-        // We use deferred call to signal the completion of finalize
-        self.storage_client.map(|client| {
-            client.finalize_done(Ok(()));
-        });
+        match self.state.get() {
+            // The old version of an upgraded app could not be loaded again.
+            State::Restore => self.restore_done(),
+            // The version on trial reached the rollback threshold.
+            State::Rollback => self.roll_back_trial(),
+            // There is nothing left to move.
+            State::Compact => self.compaction_done(Ok(self.compaction_moved.get())),
+            // We use deferred call to signal the completion of finalize
+            _ => {
+                self.storage_client.map(|client| {
+                    client.finalize_done(Ok(()));
+                });
+            }
+        }
     }
 
     fn register(&'static self) {
@@ -361,6 +1361,11 @@ impl<'b, C: Chip + 'static, D: ProcessStandardDebug + 'static, F: NonvolatileSto
     }
 
     fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        if self.trial_record_progress.is_some() {
+            self.buffer.replace(buffer);
+            self.trial_record_write_done();
+            return;
+        }
         match self.state.get() {
             State::AppWrite => {
                 self.state.set(State::AppWrite);
@@ -426,7 +1431,29 @@ impl<'b, C: Chip + 'static, D: ProcessStandardDebug + 'static, F: NonvolatileSto
                     client.abort_done(Ok(()));
                 });
             }
-            State::Idle => {
+            State::Uninstall => {
+                // The binary of the removed process is now a padding app.
+                self.buffer.replace(buffer);
+                self.state.set(State::Idle);
+                self.update_client.map(|client| {
+                    client.uninstall_done(Ok(()));
+                });
+            }
+            State::Commit => {
+                // The old version of the upgraded app is now a padding app.
+                self.buffer.replace(buffer);
+                self.update_trial_record();
+            }
+            State::Rollback => {
+                // The new version of the upgraded app is now a padding app.
+                self.buffer.replace(buffer);
+                self.restore_old_version();
+            }
+            State::Compact => {
+                self.buffer.replace(buffer);
+                self.compaction_write_done();
+            }
+            State::Idle | State::Upgrade | State::Trial | State::Restore => {
                 self.buffer.replace(buffer);
             }
         }
@@ -438,15 +1465,56 @@ impl<'b, C: Chip + 'static, D: ProcessStandardDebug + 'static, F: NonvolatileSto
     ProcessLoadingAsyncClient for SequentialDynamicBinaryStorage<'_, 'b, C, D, F>
 {
     fn process_loaded(&self, result: Result<(), ProcessLoadError>) {
-        self.load_client.map(|client| {
-            client.load_done(result);
-        });
+        match self.state.get() {
+            // Upgrades check which version is running once loading finishes,
+            // and compaction continues.
+            State::Upgrade | State::Restore | State::Compact => {}
+            _ => {
+                self.load_client.map(|client| {
+                    client.load_done(result);
+                });
+            }
+        }
     }
 
     fn process_loading_finished(&self) {
-        self.load_client.map(|client| {
-            client.load_done(Ok(()));
-        });
+        match self.state.get() {
+            State::Upgrade => {
+                if let Some(mut update) = self.update_metadata.get() {
+                    if self
+                        .loader_driver
+                        .find_process_at(update.new_app_start_addr)
+                        .is_some()
+                    {
+                        update.on_trial = true;
+                        self.update_metadata.set(update);
+                        self.state.set(State::Trial);
+                        self.update_client.map(|client| {
+                            client.upgrade_done(Ok(()));
+                        });
+                    } else {
+                        // The new version failed its credential checks or
+                        // could not be loaded.
+                        self.start_rollback();
+                    }
+                }
+            }
+            State::Restore => self.restore_done(),
+            // The process removed for a move runs from the copy again.
+            State::Compact => match self.compaction_result.take() {
+                Some(result) => self.compaction_done(result),
+                None => {
+                    if let Err(e) = self.start_next_compaction_move() {
+                        self.compaction_done(Err(e));
+                    }
+                }
+            },
+            _ => {
+                self.load_client.map(|client| {
+                    client.load_done(Ok(()));
+                });
+            }
+        }
     }
 }
 
@@ -525,7 +1593,12 @@ impl<'b, C: Chip + 'static, D: ProcessStandardDebug + 'static, F: NonvolatileSto
                     // Reset the state to None because we did not find any
                     // available address for this app.
                     self.reset_process_loading_metadata();
-                    Err(ErrorCode::FAIL)
+                    // Compacting the flash for apps may make room for it.
+                    if self.compaction_needed() && self.start_compaction().is_ok() {
+                        Err(ErrorCode::BUSY)
+                    } else {
+                        Err(ErrorCode::FAIL)
+                    }
                 }
             }
         } else {
@@ -536,24 +1609,32 @@ impl<'b, C: Chip + 'static, D: ProcessStandardDebug + 'static, F: NonvolatileSto
         }
     }
 
-    fn write(&self, buffer: SubSliceMut<'static, u8>, offset: usize) -> Result<(), ErrorCode> {
+    fn write(
+        &self,
+        buffer: SubSliceMut<'static, u8>,
+        offset: usize,
+    ) -> Result<(), (ErrorCode, Option<&'static mut [u8]>)> {
         match self.state.get() {
             State::AppWrite => {
                 let res = self.write_buffer(buffer, offset);
                 match res {
-                    Ok(()) => Ok(()),
-                    Err(e) => {
-                        // If we fail here, let us erase the app we just wrote.
+                    // The write was rejected, the client can write again or
+                    // abort.
+                    Err((e, Some(buffer))) => Err((e, Some(buffer))),
+                    Err((e, None)) => {
+                        // The flash driver failed, the client has to abort to
+                        // erase the app we just wrote.
                         self.state.set(State::Fail);
-                        Err(e)
+                        Err((e, None))
                     }
+                    Ok(()) => Ok(()),
                 }
             }
             _ => {
                 // We are in the wrong mode of operation. Ideally we should never reach
                 // here, but this error exists as a failsafe. The capsule should send
                 // a busy error out to the userland app.
-                Err(ErrorCode::INVAL)
+                Err((ErrorCode::INVAL, Some(buffer.take())))
             }
         }
     }
@@ -610,7 +1691,7 @@ impl<'b, C: Chip + 'static, D: ProcessStandardDebug + 'static, F: NonvolatileSto
 
     fn abort(&self) -> Result<(), ErrorCode> {
         match self.state.get() {
-            State::Setup | State::AppWrite => {
+            State::Setup | State::AppWrite | State::Fail => {
                 self.state.set(State::Abort);
                 if let Some(metadata) = self.process_metadata.get() {
                     // Write padding header to the beginning of the new app address.
@@ -672,3 +1753,153 @@ impl<'b, C: Chip + 'static, D: ProcessStandardDebug + 'static, F: NonvolatileSto
         }
     }
 }
+
+/// Update interface exposed to the app_loader capsule
+impl<'b, C: Chip + 'static, D: ProcessStandardDebug + 'static, F: NonvolatileStorage<'b>>
+    DynamicProcessUpdate for SequentialDynamicBinaryStorage<'_, 'b, C, D, F>
+{
+    fn set_update_client(&self, client: &'static dyn DynamicProcessUpdateClient) {
+        self.update_client.set(client);
+    }
+
+    fn uninstall(&self, identifier: usize) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::INVAL);
+        }
+        let process = self
+            .loader_driver
+            .find_process(identifier)
+            .ok_or(ErrorCode::INVAL)?;
+        let addresses = process.get_addresses();
+
+        self.loader_driver.remove_process(process);
+        self.state.set(State::Uninstall);
+        match self.write_padding_app(
+            addresses.flash_end - addresses.flash_start,
+            addresses.flash_start,
+        ) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                // The binary is still in flash, so the app will be loaded
+                // again after a reset.
+                self.state.set(State::Idle);
+                Err(e)
+            }
+        }
+    }
+
+    fn upgrade(&self, identifier: usize) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Load {
+            return Err(ErrorCode::INVAL);
+        }
+        let metadata = self.process_metadata.get().ok_or(ErrorCode::INVAL)?;
+        let process = self
+            .loader_driver
+            .find_process(identifier)
+            .ok_or(ErrorCode::INVAL)?;
+        let addresses = process.get_addresses();
+
+        self.update_metadata.set(ProcessUpdateMetadata {
+            old_app_start_addr: addresses.flash_start,
+            old_app_length: addresses.flash_end - addresses.flash_start,
+            new_app_start_addr: metadata.new_app_start_addr,
+            new_app_length: metadata.new_app_length,
+            on_trial: false,
+            faults: 0,
+        });
+        self.process_metadata.take();
+
+        // Both versions have the same AppID, so the old version has to be
+        // removed before the new one can be loaded.
+        self.loader_driver.remove_process(process);
+        self.state.set(State::Upgrade);
+        // The new version is loaded once the upgrade is recorded, so that it
+        // is on trial after a reset.
+        self.update_trial_record();
+        Ok(())
+    }
+
+    fn trial_faults(&self) -> Result<usize, ErrorCode> {
+        if self.state.get() != State::Trial {
+            return Err(ErrorCode::INVAL);
+        }
+        let update = self.update_metadata.get().ok_or(ErrorCode::FAIL)?;
+        Ok(update.faults)
+    }
+
+    fn commit(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Trial {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.trial_record_progress.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        let update = self.update_metadata.get().ok_or(ErrorCode::FAIL)?;
+
+        self.state.set(State::Commit);
+        match self.write_padding_app(update.old_app_length, update.old_app_start_addr) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                self.state.set(State::Trial);
+                Err(e)
+            }
+        }
+    }
+
+    fn rollback(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Trial {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.trial_record_progress.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        if self.update_metadata.is_none() {
+            return Err(ErrorCode::FAIL);
+        }
+        self.roll_back_trial();
+        Ok(())
+    }
+
+    fn compact(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.compaction_journal_slot(0).is_none() {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        self.start_compaction()
+    }
+}
+
+/// The kernel handles faults of the version on trial, and the board's policy
+/// faults of other processes.
+impl<'b, C: Chip + 'static, D: ProcessStandardDebug + 'static, F: NonvolatileStorage<'b>>
+    ProcessFaultPolicy for SequentialDynamicBinaryStorage<'_, 'b, C, D, F>
+{
+    fn action(&self, process: &dyn Process) -> FaultAction {
+        let trial = self.update_metadata.get().filter(|update| {
+            self.state.get() == State::Trial
+                && process.get_addresses().flash_start == update.new_app_start_addr
+        });
+        let Some(mut update) = trial else {
+            return self
+                .fault_policy
+                .map_or(FaultAction::Panic, |policy| policy.action(process));
+        };
+
+        update.faults += 1;
+        self.update_metadata.set(update);
+        if update.faults >= self.rollback_threshold.get() {
+            self.state.set(State::Rollback);
+            // The process is removed once it is done faulting, or once the
+            // faults recorded before are written.
+            if self.trial_record_progress.is_none() {
+                self.deferred_call.set();
+            }
+            FaultAction::Stop
+        } else {
+            self.update_trial_record();
+            FaultAction::Restart
+        }
+    }
+}
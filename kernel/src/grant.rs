@@ -1139,12 +1139,12 @@ impl<'a, T: Default, Upcalls: UpcallSize, AllowROs: AllowRoSize, AllowRWs: Allow
                     );
 
                     // Allocate grant, the memory is still uninitialized though.
-                    if process
+                    process
                         .allocate_grant(grant_num, driver_num, alloc_size, alloc_align)
-                        .is_err()
-                    {
-                        return Err(Error::OutOfMemory);
-                    }
+                        .map_err(|err| match err {
+                            Error::GrantQuotaExceeded => Error::GrantQuotaExceeded,
+                            _ => Error::OutOfMemory,
+                        })?;
 
                     let grant_ptr = process.enter_grant(grant_num)?;
 
@@ -1732,10 +1732,10 @@ impl GrantRegionAllocator {
             .process_map_or(Err(Error::NoSuchApp), self.processid, |process| {
                 process
                     .allocate_custom_grant(alloc_size, alloc_align)
-                    .map_or(
-                        Err(Error::OutOfMemory),
-                        |(custom_grant_identifier, raw_ptr)| Ok((custom_grant_identifier, raw_ptr)),
-                    )
+                    .map_err(|err| match err {
+                        Error::GrantQuotaExceeded => Error::GrantQuotaExceeded,
+                        _ => Error::OutOfMemory,
+                    })
             })
     }
 }
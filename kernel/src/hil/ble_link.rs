@@ -0,0 +1,104 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Interface for a Bluetooth Low Energy radio used by a connection-oriented
+//! link layer.
+//!
+//! [`BleAdvertisementDriver`](crate::hil::ble_advertising::BleAdvertisementDriver)
+//! only sends or receives single advertising packets, which is enough for
+//! non-connectable advertising and passive scanning. A link layer accepting
+//! connections additionally needs the radio to:
+//!
+//! - answer a received packet one inter frame space (T_IFS, 150 µs) after it
+//!   ends, which is too short to go through a deferred call or the alarm
+//!   stack, so the response is built in the reception callback, and
+//! - send and receive data channel packets with the access address and CRC
+//!   initialization value of the connection.
+//!
+//! Timing of connection events is left to the link layer, which starts each
+//! radio operation from an alarm. A radio only has to implement the two
+//! operations below; the link layer above it is radio agnostic.
+//!
+//! Packets are passed starting with the 2 byte PDU header, without the
+//! preamble, access address or CRC, which the radio adds and checks.
+
+use crate::ErrorCode;
+use crate::hil::ble_advertising::RadioChannel;
+
+/// Access address of all advertising channel packets.
+///
+/// Bluetooth Core Specification Vol. 6, Part B, section 2.1.2.
+pub const ADVERTISING_ACCESS_ADDRESS: u32 = 0x8E89BED6;
+
+/// CRC initialization value of all advertising channel packets.
+pub const ADVERTISING_CRC_INIT: u32 = 0x555555;
+
+/// Length of the longest packet the radio has to handle, in bytes.
+///
+/// This is the longest advertising channel packet (a 2 byte header and a 37
+/// byte payload), which is also longer than data channel packets without the
+/// data length extension.
+pub const MAX_PACKET_LEN: usize = 39;
+
+pub trait BleLinkRadio<'a> {
+    fn set_link_client(&self, client: &'a dyn BleLinkRadioClient);
+
+    /// Use `access_address` and `crc_init` for the following operations.
+    ///
+    /// Use [`ADVERTISING_ACCESS_ADDRESS`] and [`ADVERTISING_CRC_INIT`] on the
+    /// advertising channels.
+    fn set_access_address(&self, access_address: u32, crc_init: u32);
+
+    /// Transmit the first `len` bytes of `packet` on `channel`, then listen
+    /// for a response starting within T_IFS of the end of the transmission.
+    ///
+    /// A received response is passed to
+    /// [`BleLinkRadioClient::packet_received`], and the radio transmits the
+    /// answer it returns, if any. `transmit_done` is called once all of this
+    /// is over.
+    fn transmit_then_receive(
+        &self,
+        channel: RadioChannel,
+        packet: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+
+    /// Listen on `channel` for a packet starting within `window_us`, then
+    /// transmit the answer returned by
+    /// [`BleLinkRadioClient::packet_received`] T_IFS after the end of the
+    /// received packet.
+    ///
+    /// `receive_done` is called once the answer is transmitted, or when no
+    /// packet was received in the window.
+    fn receive_then_transmit(&self, channel: RadioChannel, window_us: u32)
+    -> Result<(), ErrorCode>;
+
+    /// Abort the current operation.
+    ///
+    /// The operation completes with `CANCEL`, possibly before this returns.
+    fn stop(&self);
+}
+
+pub trait BleLinkRadioClient {
+    /// A packet with a valid CRC was received.
+    ///
+    /// This is called from the radio interrupt, and the answer must be ready
+    /// before T_IFS elapses, so implementations must only do the minimum
+    /// required here and defer everything else to `transmit_done` or
+    /// `receive_done`. The client writes the answer into `response`, which is
+    /// at least [`MAX_PACKET_LEN`] bytes long, and returns its length, or 0
+    /// to not answer.
+    fn packet_received(&self, packet: &[u8], response: &mut [u8]) -> usize;
+
+    /// A `transmit_then_receive` operation finished, and the buffer passed
+    /// to it is returned.
+    fn transmit_done(&self, packet: &'static mut [u8], result: Result<(), ErrorCode>);
+
+    /// A `receive_then_transmit` operation finished.
+    ///
+    /// The result is `Ok(())` if a packet was received and answered, `NOACK`
+    /// if nothing was received in the window, `FAIL` if a packet was received
+    /// with a bad CRC and `CANCEL` if the operation was stopped.
+    fn receive_done(&self, result: Result<(), ErrorCode>);
+}
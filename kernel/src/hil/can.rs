@@ -109,6 +109,24 @@ pub enum Error {
     /// Set by software to force the hardware to indicate the
     /// current communication status.
     SetBySoftware,
+
+    /// While transmitting the payload of a CAN FD frame at the
+    /// payload bitrate, the sensed bit was different from the
+    /// transmitted one.
+    DataPhaseBit,
+
+    /// The payload of a CAN FD frame, sent at the payload bitrate,
+    /// has been corrupted on the CAN bus.
+    DataPhaseCrc,
+
+    /// The stuff count of a CAN FD frame does not match the number
+    /// of stuff bits that were received.
+    StuffCount,
+
+    /// A CAN FD frame was received by a peripheral that is not
+    /// configured for CAN FD, or a reserved bit of a CAN FD frame
+    /// was set.
+    ProtocolException,
 }
 
 impl From<Error> for ErrorCode {
@@ -116,12 +134,17 @@ impl From<Error> for ErrorCode {
         match val {
             Error::ArbitrationLost => ErrorCode::RESERVE,
             Error::BusOff => ErrorCode::OFF,
-            Error::Form => ErrorCode::INVAL,
+            Error::Form | Error::StuffCount => ErrorCode::INVAL,
             Error::BitRecessive | Error::BitDominant => ErrorCode::BUSY,
             Error::Ack | Error::Transmission => ErrorCode::NOACK,
-            Error::Crc | Error::SetBySoftware | Error::Warning | Error::Passive | Error::Stuff => {
-                ErrorCode::FAIL
-            }
+            Error::Crc
+            | Error::SetBySoftware
+            | Error::Warning
+            | Error::Passive
+            | Error::Stuff
+            | Error::DataPhaseBit
+            | Error::DataPhaseCrc => ErrorCode::FAIL,
+            Error::ProtocolException => ErrorCode::NOSUPPORT,
         }
     }
 }
@@ -145,7 +168,7 @@ pub enum IdentifierMode {
 }
 
 /// The identifier can be standard (11 bits) or extended (29 bits)
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Id {
     Standard(u16),
     Extended(u32),
@@ -168,6 +191,31 @@ pub struct FilterParameters {
 
     /// The receive FIFO Id that the filter will be applied to
     pub fifo_number: usize,
+
+    /// The identifier that received messages are compared against
+    pub id: Id,
+
+    /// The identifier bits that must match `id` when the filter is in
+    /// `Mask` mode. A zero mask accepts every message, standard or
+    /// extended. In `List` mode the mask is ignored and the identifier
+    /// must be equal to `id`.
+    pub mask: u32,
+}
+
+impl FilterParameters {
+    /// Returns whether a message with the identifier `id` passes the filter.
+    pub fn matches(&self, id: Id) -> bool {
+        let mask = match self.identifier_mode {
+            IdentifierMode::List => u32::MAX,
+            IdentifierMode::Mask if self.mask == 0 => return true,
+            IdentifierMode::Mask => self.mask,
+        };
+        match (self.id, id) {
+            (Id::Standard(filter), Id::Standard(id)) => (filter ^ id) as u32 & mask == 0,
+            (Id::Extended(filter), Id::Extended(id)) => (filter ^ id) & mask == 0,
+            _ => false,
+        }
+    }
 }
 
 /// This structure defines the parameters for the timing mode
@@ -538,6 +586,27 @@ pub trait ConfigureFd: Configure {
     ///     supported
     fn get_payload_bit_timing(&self) -> Result<BitTiming, ErrorCode>;
 
+    /// Configures whether CAN FD frames are sent with bit rate switching
+    /// (BRS), meaning that their payload is sent using the payload bit
+    /// timing. This function is supposed to be called before the `enable`
+    /// function and should only store the setting.
+    ///
+    /// # Arguments:
+    ///
+    /// * `enabled` - Whether frames are sent with bit rate switching
+    ///
+    /// # Return values:
+    ///
+    /// * `Ok()` - The setting was stored.
+    /// * `Err(ErrorCode)` - Indicates the error because of which the request
+    ///   cannot be completed
+    ///   - `ErrorCode::NOSUPPORT` indicates that bit rate switching is not
+    ///     supported
+    fn set_bit_rate_switching(&self, enabled: bool) -> Result<(), ErrorCode>;
+
+    /// Returns whether CAN FD frames are sent with bit rate switching.
+    fn get_bit_rate_switching(&self) -> bool;
+
     /// Returns the maximum accepted frame size in bytes.
     ///
     /// - for CanFD BRS this should be 8 bytes
@@ -808,8 +877,10 @@ pub trait Can:
 {
 }
 
+/// Convenience type for capsules that configure, send
+/// and receive data using a CAN FD peripheral
 pub trait CanFd:
-    Transmit<FD_CAN_PACKET_SIZE> + Configure + ConfigureFd + Receive<FD_CAN_PACKET_SIZE>
+    Transmit<FD_CAN_PACKET_SIZE> + Configure + ConfigureFd + Controller + Receive<FD_CAN_PACKET_SIZE>
 {
 }
 
@@ -821,7 +892,12 @@ impl<
 }
 
 /// Provide blanket implementation for CanFd trait group
-impl<T: Transmit<FD_CAN_PACKET_SIZE> + Configure + ConfigureFd + Receive<FD_CAN_PACKET_SIZE>> CanFd
-    for T
+impl<
+    T: Transmit<FD_CAN_PACKET_SIZE>
+        + Configure
+        + ConfigureFd
+        + Controller
+        + Receive<FD_CAN_PACKET_SIZE>,
+> CanFd for T
 {
 }
@@ -176,6 +176,22 @@ pub trait I2CSlave<'a> {
     fn listen(&self);
 }
 
+/// Interface for I2C Slave hardware that responds to more than one address.
+///
+/// Address 0 is the one set with `I2CSlave::set_address`.
+pub trait I2CSlaveMultiAddress {
+    /// Returns the number of addresses the hardware can respond to.
+    fn address_count(&self) -> usize;
+
+    /// Sets the address at `index`, which must be lower than
+    /// `address_count()`.
+    fn set_address_at(&self, index: usize, addr: u8) -> Result<(), Error>;
+
+    /// Returns the index of the address the master used for the current
+    /// or last transfer. It is valid in the `I2CHwSlaveClient` callbacks.
+    fn matched_address(&self) -> usize;
+}
+
 /// Convenience type for capsules that need hardware that supports both
 /// Master and Slave modes.
 pub trait I2CMasterSlave<'a>: I2CMaster<'a> + I2CSlave<'a> {}
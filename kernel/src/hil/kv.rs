@@ -517,3 +517,52 @@ pub trait KV<'a> {
     ///     completed.
     fn garbage_collect(&self) -> Result<(), ErrorCode>;
 }
+
+/// Usage and wear statistics of a KV store.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Statistics {
+    /// Bytes that can still be used to store new objects.
+    pub free_bytes: usize,
+    /// Bytes used by deleted objects that garbage collection can reclaim.
+    pub invalidated_bytes: usize,
+    /// Lowest erase count of all flash regions.
+    pub min_erase_count: u32,
+    /// Highest erase count of all flash regions.
+    pub max_erase_count: u32,
+}
+
+/// Callback trait for KV store statistics.
+///
+/// Implement this trait and use `set_statistics_client()` to receive
+/// callbacks.
+pub trait StatisticsClient {
+    /// This callback is called when the statistics operation completes.
+    ///
+    /// ### Return Values
+    ///
+    /// - `result`: The collected `Statistics` on success, `Err(ErrorCode)` on
+    ///   error. Valid `ErrorCode`s:
+    ///   - `FAIL`: An internal error occurred and the operation cannot be
+    ///     completed.
+    fn statistics_complete(&self, result: Result<Statistics, ErrorCode>);
+}
+
+/// Interface for KV stores that can report usage and wear statistics.
+///
+/// Collecting statistics generally requires reading all of the underlying
+/// flash, so this is an asynchronous operation.
+pub trait KVStatistics<'a> {
+    /// Configure the client for statistics callbacks.
+    fn set_statistics_client(&self, client: &'a dyn StatisticsClient);
+
+    /// Collect the statistics of the KV store.
+    ///
+    /// ### Return
+    ///
+    /// - On success returns `Ok(())`. A callback will be issued.
+    /// - On error returns:
+    ///   - `BUSY`: An operation is already in progress.
+    ///   - `FAIL`: An internal error occurred and the operation cannot be
+    ///     completed.
+    fn get_statistics(&self) -> Result<(), ErrorCode>;
+}
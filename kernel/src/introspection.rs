@@ -147,6 +147,49 @@ impl KernelInfo {
         (used, number_of_grants)
     }
 
+    /// Returns the number of bytes of grant memory the app uses for grants
+    /// and custom grants.
+    pub fn app_grant_memory_used(
+        &self,
+        app: ProcessId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> usize {
+        self.kernel
+            .process_map_or(0, app, |process| process.grant_memory_used())
+    }
+
+    /// Calls `f` with the driver number and the number of bytes of grant
+    /// memory for each grant the app has allocated.
+    ///
+    /// Custom grants do not belong to a driver, so they are not included. The
+    /// memory they use is the difference between
+    /// [`KernelInfo::app_grant_memory_used`] and the sum reported here.
+    pub fn app_grant_memory_usage(
+        &self,
+        app: ProcessId,
+        _capability: &dyn ProcessManagementCapability,
+        mut f: impl FnMut(usize, usize),
+    ) {
+        let number_of_grants = self.kernel.get_grant_count_and_finalize();
+        self.kernel.process_map_or((), app, |process| {
+            for grant_num in 0..number_of_grants {
+                if let Some((driver_num, size)) = process.grant_memory_usage(grant_num) {
+                    f(driver_num, size);
+                }
+            }
+        });
+    }
+
+    /// Returns the total number of bytes of grant memory used by all
+    /// processes.
+    pub fn grant_memory_used(&self, _capability: &dyn ProcessManagementCapability) -> usize {
+        let count: Cell<usize> = Cell::new(0);
+        self.kernel.process_each(|proc| {
+            count.add(proc.grant_memory_used());
+        });
+        count.get()
+    }
+
     /// Returns the total number of times all processes have exceeded
     /// their timeslices.
     pub fn timeslice_expirations(&self, _capability: &dyn ProcessManagementCapability) -> usize {
@@ -31,10 +31,10 @@ use crate::process::{self, ProcessId, Task};
 use crate::scheduler::{Scheduler, SchedulingDecision};
 use crate::syscall::SyscallDriver;
 use crate::syscall::{ContextSwitchReason, SyscallReturn};
-use crate::syscall::{Syscall, YieldVariant};
+use crate::syscall::{Syscall, SyscallTrace, YieldVariant};
 use crate::syscall_driver::CommandReturn;
 use crate::upcall::{Upcall, UpcallId};
-use crate::utilities::cells::NumericCellExt;
+use crate::utilities::cells::{NumericCellExt, OptionalCell};
 
 /// Threshold in microseconds to consider a process's timeslice to be exhausted.
 /// That is, Tock will skip re-scheduling a process if its remaining timeslice
@@ -60,6 +60,9 @@ pub struct Kernel {
     /// created and the data structures for grants have already been
     /// established.
     grants_finalized: Cell<bool>,
+
+    /// Observer of the system calls processes make.
+    syscall_trace: OptionalCell<&'static dyn SyscallTrace>,
 }
 
 /// Represents the different outcomes when trying to allocate a grant region
@@ -67,18 +70,31 @@ enum AllocResult {
     NoAllocation,
     NewAllocation,
     SameAllocation,
+    QuotaExceeded,
+}
+
+impl AllocResult {
+    /// The error to return to userspace if allocating the grant failed,
+    /// instead of `err`.
+    fn failure_code(&self, err: ErrorCode) -> ErrorCode {
+        match self {
+            AllocResult::QuotaExceeded => process::Error::GrantQuotaExceeded.into(),
+            _ => err,
+        }
+    }
 }
 
 /// Tries to allocate the grant region for specified driver and process.
 /// Returns if a new grant was allocated or not
 fn try_allocate_grant(driver: &dyn SyscallDriver, process: &dyn process::Process) -> AllocResult {
     let before_count = process.grant_allocated_count().unwrap_or(0);
-    match driver.allocate_grant(process.processid()).is_ok() {
-        true if before_count == process.grant_allocated_count().unwrap_or(0) => {
+    match driver.allocate_grant(process.processid()) {
+        Ok(()) if before_count == process.grant_allocated_count().unwrap_or(0) => {
             AllocResult::SameAllocation
         }
-        true => AllocResult::NewAllocation,
-        false => AllocResult::NoAllocation,
+        Ok(()) => AllocResult::NewAllocation,
+        Err(process::Error::GrantQuotaExceeded) => AllocResult::QuotaExceeded,
+        Err(_) => AllocResult::NoAllocation,
     }
 }
 
@@ -94,9 +110,25 @@ impl Kernel {
             process_identifier_max: Cell::new(0),
             grant_counter: Cell::new(0),
             grants_finalized: Cell::new(false),
+            syscall_trace: OptionalCell::empty(),
         }
     }
 
+    /// Set the observer of the system calls that processes make.
+    pub fn set_syscall_trace(
+        &self,
+        trace: &'static dyn SyscallTrace,
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) {
+        self.syscall_trace.set(trace);
+    }
+
+    /// Report the return value of a system call to the syscall tracer.
+    pub(crate) fn trace_syscall_return(&self, processid: ProcessId, return_value: &SyscallReturn) {
+        self.syscall_trace
+            .map(|trace| trace.syscall_return(processid, return_value));
+    }
+
     /// Helper function that moves all non-generic portions of process_map_or
     /// into a non-generic function to reduce code bloat from monomorphization.
     pub(crate) fn get_process(&self, processid: ProcessId) -> Option<&dyn process::Process> {
@@ -314,6 +346,18 @@ impl Kernel {
         Err(())
     }
 
+    /// Remove the process with `processid` from the processes array so the
+    /// slot can be used for a new process.
+    ///
+    /// The process should be terminated first. Its memory is not reclaimed.
+    pub(crate) fn remove_process(&self, processid: ProcessId) {
+        if let Some(slot) = self.processes.get(processid.index) {
+            if slot.contains_process_with_id(processid.id()) {
+                slot.clear();
+            }
+        }
+    }
+
     /// Cause all apps to fault.
     ///
     /// This will call `set_fault_state()` on each app, causing the app to enter
@@ -752,6 +796,8 @@ impl Kernel {
     ) {
         // Hook for process debugging.
         process.debug_syscall_called(syscall);
+        self.syscall_trace
+            .map(|trace| trace.syscall(process.processid(), &syscall));
 
         // Enforce platform-specific syscall filtering here.
         //
@@ -979,6 +1025,7 @@ impl Kernel {
                                                         // We didn't actually
                                                         // create a new alloc,
                                                         // so just error.
+                                                        let err = alloc_failure.failure_code(err);
                                                         match (
                                                             config::CONFIG.trace_syscalls,
                                                             alloc_failure,
@@ -991,6 +1038,10 @@ impl Kernel {
                                                                 debug!("[{:?}] ERROR driver #{:x} allocated wrong grant counts",
                                                                            process.processid(), driver_number);
                                                             }
+                                                            (true, AllocResult::QuotaExceeded) => {
+                                                                debug!("[{:?}] WARN driver #{:x} grant exceeds grant quota",
+                                                                           process.processid(), driver_number);
+                                                            }
                                                             _ => {}
                                                         }
                                                         upcall.into_subscribe_failure(err)
@@ -1123,6 +1174,7 @@ impl Kernel {
                                                         // We didn't actually
                                                         // create a new alloc,
                                                         // so just error.
+                                                        let err = alloc_failure.failure_code(err);
                                                         match (
                                                             config::CONFIG.trace_syscalls,
                                                             alloc_failure,
@@ -1135,6 +1187,10 @@ impl Kernel {
                                                                 debug!("[{:?}] ERROR driver #{:x} allocated wrong grant counts",
                                                                            process.processid(), driver_number);
                                                             }
+                                                            (true, AllocResult::QuotaExceeded) => {
+                                                                debug!("[{:?}] WARN driver #{:x} grant exceeds grant quota",
+                                                                           process.processid(), driver_number);
+                                                            }
                                                             _ => {}
                                                         }
                                                         let (ptr, len) = rw_pbuf.consume();
@@ -1328,6 +1384,7 @@ impl Kernel {
                                                         // We didn't actually
                                                         // create a new alloc,
                                                         // so just error.
+                                                        let err = alloc_failure.failure_code(err);
                                                         match (
                                                             config::CONFIG.trace_syscalls,
                                                             alloc_failure,
@@ -1340,6 +1397,10 @@ impl Kernel {
                                                                 debug!("[{:?}] ERROR driver #{:x} allocated wrong grant counts",
                                                                            process.processid(), driver_number);
                                                             }
+                                                            (true, AllocResult::QuotaExceeded) => {
+                                                                debug!("[{:?}] WARN driver #{:x} grant exceeds grant quota",
+                                                                           process.processid(), driver_number);
+                                                            }
                                                             _ => {}
                                                         }
                                                         let (ptr, len) = ro_pbuf.consume();
@@ -170,8 +170,10 @@ pub mod hil;
 pub mod introspection;
 pub mod ipc;
 pub mod platform;
+pub mod panic_record;
 pub mod process;
 pub mod process_checker;
+pub mod process_checkpoint;
 pub mod processbuffer;
 pub mod scheduler;
 pub mod storage_permissions;
@@ -0,0 +1,417 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Panic records that survive a reset.
+//!
+//! The panic handler prints the panic to the debug writer, which is lost if
+//! nobody is watching the console and the board resets after the panic. A
+//! panic record keeps the panic message, the PC and LR of the fault if the
+//! architecture reported them, and the last `LOG_LEN` bytes of debug output
+//! (the last few `debug!()` lines) in RAM that is not initialized at boot, so
+//! it survives a warm reset. The next boot finds the record, and can print it
+//! and make it available to userspace, for example through the
+//! `panic_record` capsule, which can also copy it to flash.
+//!
+//! The record must be placed in the `.noinit` section, which the Tock linker
+//! script neither loads nor zeroes:
+//!
+//! ```ignore
+//! #[unsafe(link_section = ".noinit")]
+//! static mut PANIC_RECORD: core::mem::MaybeUninit<kernel::panic_record::PanicRecord> =
+//!     core::mem::MaybeUninit::uninit();
+//!
+//! let previous = kernel::panic_record::initialize_panic_record::<
+//!     <ChipHw as kernel::platform::chip::Chip>::ThreadIdProvider,
+//! >(
+//!     &mut *core::ptr::addr_of_mut!(PANIC_RECORD),
+//!     kernel::static_buf!(kernel::panic_record::PanicRecord),
+//! );
+//! if previous.is_valid() {
+//!     kernel::debug!("{}", previous);
+//! }
+//! ```
+//!
+//! Boards should initialize the record as early as possible, as panics and
+//! debug output before that are not recorded. A cold reset (power loss)
+//! clears the record.
+
+use core::fmt::{self, Write};
+use core::mem::MaybeUninit;
+use core::panic::PanicInfo;
+
+use crate::platform::chip::ThreadIdProvider;
+use crate::utilities::cells::MapCell;
+use crate::utilities::single_thread_value::SingleThreadValue;
+
+/// Maximum length of the recorded panic message, including its location.
+pub const MESSAGE_LEN: usize = 256;
+
+/// Length of the recorded debug output.
+pub const LOG_LEN: usize = 512;
+
+const MAGIC_LOGGING: u32 = 0x504e_4c47;
+const MAGIC_PANICKED: u32 = 0x504e_4943;
+
+/// A panic record.
+///
+/// The record only holds `u32`s and bytes, so that any memory contents are a
+/// valid (if meaningless) record, and it can be stored as bytes.
+#[repr(C)]
+pub struct PanicRecord {
+    magic: u32,
+    checksum: u32,
+    /// PC of the fault, or 0.
+    pc: u32,
+    /// LR of the fault, or 0.
+    lr: u32,
+    message_len: u32,
+    /// Number of bytes written to `log` since the record was initialized.
+    log_written: u32,
+    message: [u8; MESSAGE_LEN],
+    log: [u8; LOG_LEN],
+}
+
+/// Length of a `PanicRecord` in bytes.
+pub const RECORD_LEN: usize = size_of::<PanicRecord>();
+
+impl PanicRecord {
+    /// Create an empty record.
+    pub const fn new() -> Self {
+        Self {
+            magic: MAGIC_LOGGING,
+            checksum: 0,
+            pc: 0,
+            lr: 0,
+            message_len: 0,
+            log_written: 0,
+            message: [0; MESSAGE_LEN],
+            log: [0; LOG_LEN],
+        }
+    }
+
+    fn compute_checksum(&self) -> u32 {
+        let words = [
+            self.magic,
+            self.pc,
+            self.lr,
+            self.message_len,
+            self.log_written,
+        ];
+        words
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .chain(self.message.iter().copied())
+            .chain(self.log.iter().copied())
+            .fold(0x811c_9dc5, |hash: u32, byte| {
+                (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+            })
+    }
+
+    /// Returns true if the record holds a panic.
+    pub fn is_valid(&self) -> bool {
+        self.magic == MAGIC_PANICKED && self.checksum == self.compute_checksum()
+    }
+
+    /// Mark the record as not holding a panic.
+    pub fn clear(&mut self) {
+        self.magic = 0;
+    }
+
+    /// The PC and LR of the fault that caused the panic, if the architecture
+    /// recorded them.
+    pub fn registers(&self) -> Option<(u32, u32)> {
+        (self.pc != 0 || self.lr != 0).then_some((self.pc, self.lr))
+    }
+
+    /// The panic message, including its location.
+    pub fn message(&self) -> &[u8] {
+        &self.message[..(self.message_len as usize).min(MESSAGE_LEN)]
+    }
+
+    /// The recorded debug output, oldest first, as two slices. If older
+    /// output was overwritten, the first partial line is skipped.
+    pub fn log(&self) -> (&[u8], &[u8]) {
+        let written = self.log_written as usize;
+        if written <= LOG_LEN {
+            return (&self.log[..written], &[]);
+        }
+        let head = written % LOG_LEN;
+        let (newer, older) = self.log.split_at(head);
+        match older.iter().position(|c| *c == b'\n') {
+            Some(i) => (&older[i + 1..], newer),
+            None => match newer.iter().position(|c| *c == b'\n') {
+                Some(i) => (&newer[i + 1..], &[]),
+                None => (newer, &[]),
+            },
+        }
+    }
+
+    /// View the record as bytes, for example to write it to flash.
+    pub fn as_bytes(&self) -> &[u8] {
+        // # Safety
+        //
+        // `PanicRecord` is `repr(C)` and only holds `u32`s and byte arrays
+        // whose lengths are multiples of 4, so it has no padding bytes.
+        unsafe { core::slice::from_raw_parts(core::ptr::from_ref(self).cast::<u8>(), RECORD_LEN) }
+    }
+
+    /// Reinterpret a buffer holding a record, for example one read from flash.
+    ///
+    /// Returns the buffer if it is too short or not aligned for a record.
+    pub fn from_bytes(buffer: &'static mut [u8]) -> Result<&'static mut Self, &'static mut [u8]> {
+        if buffer.len() < RECORD_LEN || buffer.as_ptr().align_offset(align_of::<Self>()) != 0 {
+            return Err(buffer);
+        }
+        // # Safety
+        //
+        // The buffer is long enough and aligned, and any bytes are a valid
+        // `PanicRecord`.
+        Ok(unsafe { &mut *buffer.as_mut_ptr().cast::<Self>() })
+    }
+
+    /// Return the memory of the record as a byte buffer.
+    pub fn into_bytes(&'static mut self) -> &'static mut [u8] {
+        self.as_mut_bytes()
+    }
+
+    fn as_mut_bytes(&mut self) -> &mut [u8] {
+        // # Safety
+        //
+        // As for `as_bytes()`, and any bytes written through the buffer are a
+        // valid `PanicRecord`.
+        unsafe {
+            core::slice::from_raw_parts_mut(core::ptr::from_mut(self).cast::<u8>(), RECORD_LEN)
+        }
+    }
+
+    fn append_log(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.log[self.log_written as usize % LOG_LEN] = *byte;
+            self.log_written = self.log_written.wrapping_add(1);
+            // Keep the index continuous when the counter wraps.
+            if self.log_written == 0 {
+                self.log_written = LOG_LEN as u32;
+            }
+        }
+    }
+}
+
+impl Default for PanicRecord {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for PanicRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("---| Previous boot panicked |---\r\n")?;
+        f.write_str(utf8_prefix(self.message()))?;
+        f.write_str("\r\n")?;
+        if let Some((pc, lr)) = self.registers() {
+            write!(f, "\tpc  {:#010x}\r\n\tlr  {:#010x}\r\n", pc, lr)?;
+        }
+        f.write_str("---| Last debug output |---\r\n")?;
+        let (older, newer) = self.log();
+        f.write_str(utf8_prefix(older))?;
+        f.write_str(utf8_prefix(newer))
+    }
+}
+
+/// The longest UTF-8 prefix of `bytes`, which may have been cut in the middle
+/// of a character.
+fn utf8_prefix(bytes: &[u8]) -> &str {
+    match core::str::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or(""),
+    }
+}
+
+/// Writes into the message of a record, truncating what does not fit.
+struct MessageWriter<'a> {
+    record: &'a mut PanicRecord,
+}
+
+impl Write for MessageWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let start = self.record.message_len as usize;
+        let len = s.len().min(MESSAGE_LEN - start);
+        self.record.message[start..start + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.record.message_len += len as u32;
+        Ok(())
+    }
+}
+
+/// The record that panics and debug output are written to.
+static PANIC_RECORD: SingleThreadValue<MapCell<&'static mut PanicRecord>> =
+    SingleThreadValue::new();
+
+/// Move a panic left in `record` before the reset to `previous`, and reset
+/// `record`.
+fn take_previous(
+    record: &'static mut MaybeUninit<PanicRecord>,
+    previous: &'static mut MaybeUninit<PanicRecord>,
+) -> (&'static mut PanicRecord, &'static mut PanicRecord) {
+    let previous = previous.write(PanicRecord::new());
+    previous.clear();
+
+    // # Safety
+    //
+    // `record` is RAM that has not been initialized since the reset, and so
+    // holds whatever it held before, which is a valid `PanicRecord` as any
+    // bytes are.
+    let record = unsafe { record.assume_init_mut() };
+    if record.is_valid() {
+        previous.as_mut_bytes().copy_from_slice(record.as_bytes());
+    }
+    *record = PanicRecord::new();
+    (record, previous)
+}
+
+/// Set up the panic record in `record`, which must be memory that is not
+/// initialized at boot.
+///
+/// If the record holds a panic from before the reset, it is copied to
+/// `previous`; otherwise `previous` is left empty. The returned previous
+/// record can be printed, and passed to the `panic_record` capsule.
+#[cfg(target_has_atomic = "ptr")]
+pub fn initialize_panic_record<P: ThreadIdProvider>(
+    record: &'static mut MaybeUninit<PanicRecord>,
+    previous: &'static mut MaybeUninit<PanicRecord>,
+) -> &'static mut PanicRecord {
+    let (record, previous) = take_previous(record, previous);
+    PANIC_RECORD
+        .bind_to_thread::<P>(MapCell::new(record))
+        .map_err(|_| ())
+        .unwrap();
+    previous
+}
+
+/// Set up the panic record in `record`, as [`initialize_panic_record`].
+///
+/// # Safety
+///
+/// Callers of this function must ensure that this function is never called
+/// concurrently with other calls to [`initialize_panic_record_unsafe`].
+pub unsafe fn initialize_panic_record_unsafe<P: ThreadIdProvider>(
+    record: &'static mut MaybeUninit<PanicRecord>,
+    previous: &'static mut MaybeUninit<PanicRecord>,
+) -> &'static mut PanicRecord {
+    let (record, previous) = take_previous(record, previous);
+    unsafe {
+        PANIC_RECORD
+            .bind_to_thread_unsafe::<P>(MapCell::new(record))
+            .map_err(|_| ())
+            .unwrap();
+    }
+    previous
+}
+
+/// Append debug output to the record.
+pub(crate) fn log(args: fmt::Arguments) {
+    struct LogWriter<'a>(&'a mut PanicRecord);
+
+    impl Write for LogWriter<'_> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.0.append_log(s.as_bytes());
+            Ok(())
+        }
+    }
+
+    PANIC_RECORD.get().map(|record| {
+        record.map(|record| {
+            let _ = LogWriter(record).write_fmt(args);
+        })
+    });
+}
+
+/// Record the PC and LR of a fault that is about to cause a panic.
+///
+/// Architectures call this from their fault handlers before panicking.
+pub fn record_registers(pc: usize, lr: usize) {
+    PANIC_RECORD.get().map(|record| {
+        record.map(|record| {
+            record.pc = pc as u32;
+            record.lr = lr as u32;
+        })
+    });
+}
+
+/// Record a panic. Called by the panic routines in `debug`.
+pub(crate) fn record_panic(panic_info: &PanicInfo) {
+    PANIC_RECORD.get().map(|record| {
+        record.map(|record| {
+            record.message_len = 0;
+            let mut writer = MessageWriter { record };
+            let _ = match panic_info.location() {
+                Some(location) => writer.write_fmt(format_args!(
+                    "panicked at {}:{}:{}:\r\n{}",
+                    location.file(),
+                    location.line(),
+                    location.column(),
+                    panic_info.message(),
+                )),
+                None => writer.write_fmt(format_args!("{}", panic_info.message())),
+            };
+            record.magic = MAGIC_PANICKED;
+            record.checksum = record.compute_checksum();
+        })
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn panicked(message: &[u8]) -> PanicRecord {
+        let mut record = PanicRecord::new();
+        record.message[..message.len()].copy_from_slice(message);
+        record.message_len = message.len() as u32;
+        record.magic = MAGIC_PANICKED;
+        record.checksum = record.compute_checksum();
+        record
+    }
+
+    #[test]
+    fn only_sealed_panics_are_valid() {
+        assert!(!PanicRecord::new().is_valid());
+
+        let mut record = panicked(b"oops");
+        assert!(record.is_valid());
+        assert_eq!(record.message(), b"oops");
+
+        record.log[0] = b'x';
+        assert!(!record.is_valid());
+
+        let mut record = panicked(b"oops");
+        record.clear();
+        assert!(!record.is_valid());
+    }
+
+    #[test]
+    fn log_keeps_the_whole_output_until_it_wraps() {
+        let mut record = PanicRecord::new();
+        record.append_log(b"one\ntwo\n");
+        assert_eq!(record.log(), (&b"one\ntwo\n"[..], &[][..]));
+    }
+
+    #[test]
+    fn log_skips_the_partial_oldest_line_after_it_wraps() {
+        let mut record = PanicRecord::new();
+        record.append_log(&[b'a'; LOG_LEN - 4]);
+        record.append_log(b"\nline\nend");
+        // The line of `a`s lost its start, so the output starts after it.
+        // The rest wraps around the end of the buffer.
+        assert_eq!(record.log(), (&b"lin"[..], &b"e\nend"[..]));
+    }
+
+    #[test]
+    fn log_index_stays_continuous_when_the_counter_wraps() {
+        let mut record = PanicRecord::new();
+        record.log_written = u32::MAX;
+        record.append_log(b"ab");
+        assert_eq!(record.log_written, LOG_LEN as u32 + 1);
+        assert_eq!(record.log[(u32::MAX as usize) % LOG_LEN], b'a');
+        assert_eq!(record.log[0], b'b');
+    }
+}
@@ -135,6 +135,21 @@ pub unsafe trait MPU {
     /// Returns the maximum number of regions supported by the MPU.
     fn number_total_regions(&self) -> usize;
 
+    /// Returns the memory that region `region_number` of `config` covers and
+    /// the permissions userspace has for it, or `None` if the region is
+    /// unused.
+    ///
+    /// This is for debugging, for example to record the memory a process
+    /// could access when it faulted. The default implementation reports no
+    /// regions.
+    fn config_region(
+        &self,
+        _config: &Self::MpuConfig,
+        _region_number: usize,
+    ) -> Option<(Region, Permissions)> {
+        None
+    }
+
     /// Creates a new empty MPU configuration.
     ///
     /// The returned configuration must not have any userspace-accessible
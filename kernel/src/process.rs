@@ -30,9 +30,11 @@ pub use crate::process_checker::AcceptedCredential;
 pub use crate::process_checker::{ProcessCheckerMachine, ProcessCheckerMachineClient};
 pub use crate::process_loading::ProcessLoadError;
 pub use crate::process_loading::SequentialProcessLoaderMachine;
-pub use crate::process_loading::load_processes;
 pub use crate::process_loading::{ProcessLoadingAsync, ProcessLoadingAsyncClient};
-pub use crate::process_policies::{ProcessFaultPolicy, ProcessStandardStoragePermissionsPolicy};
+pub use crate::process_loading::{load_processes, load_processes_with_grant_quota};
+pub use crate::process_policies::{
+    ProcessFaultPolicy, ProcessGrantQuotaPolicy, ProcessStandardStoragePermissionsPolicy,
+};
 pub use crate::process_printer::{ProcessPrinter, ProcessPrinterContext};
 pub use crate::process_standard::ProcessStandard;
 pub use crate::process_standard::{ProcessStandardDebug, ProcessStandardDebugFull};
@@ -706,9 +708,11 @@ pub trait Process {
     /// app_brk, as MPU alignment and size constraints may result in the MPU
     /// enforced region differing from the app_brk.
     ///
-    /// This will return `Err(())` and fail if:
+    /// This will return an `Err` and fail if:
     /// - The process is inactive, or
     /// - There is not enough available memory to do the allocation, or
+    /// - The allocation would exceed the grant quota of the process
+    ///   (`Error::GrantQuotaExceeded`), or
     /// - The grant_num is invalid, or
     /// - The grant_num already has an allocated grant.
     fn allocate_grant(
@@ -717,7 +721,7 @@ pub trait Process {
         driver_num: usize,
         size: usize,
         align: usize,
-    ) -> Result<(), ()>;
+    ) -> Result<(), Error>;
 
     /// Check if a given grant for this process has been allocated.
     ///
@@ -732,12 +736,14 @@ pub trait Process {
     ///
     /// If successful, return a Ok() with an identifier that can be used with
     /// `enter_custom_grant()` to get access to the memory and the pointer to
-    /// the memory which must be used to initialize the memory.
+    /// the memory which must be used to initialize the memory. Like
+    /// `allocate_grant()`, this fails with `Error::GrantQuotaExceeded` if the
+    /// allocation would exceed the grant quota of the process.
     fn allocate_custom_grant(
         &self,
         size: usize,
         align: usize,
-    ) -> Result<(ProcessCustomGrantIdentifier, NonNull<u8>), ()>;
+    ) -> Result<(ProcessCustomGrantIdentifier, NonNull<u8>), Error>;
 
     /// Enter the grant based on `grant_num` for this process.
     ///
@@ -787,6 +793,19 @@ pub trait Process {
     /// Useful for debugging/inspecting the system.
     fn grant_allocated_count(&self) -> Option<usize>;
 
+    /// Return the driver number and the number of bytes of the grant region
+    /// used by the grant `grant_num`, if it is allocated.
+    ///
+    /// Useful for debugging/inspecting the system.
+    fn grant_memory_usage(&self, grant_num: usize) -> Option<(usize, usize)>;
+
+    /// Return the number of bytes of the grant region used by all grants and
+    /// custom grants of this process. This does not include the memory the
+    /// kernel uses for every process, such as the grant pointers.
+    ///
+    /// Useful for debugging/inspecting the system.
+    fn grant_memory_used(&self) -> usize;
+
     /// Get the grant number (grant_num) associated with a given driver number
     /// if there is a grant associated with that driver_num.
     fn lookup_grant_from_driver_num(&self, driver_num: usize) -> Result<usize, Error>;
@@ -808,7 +827,8 @@ pub trait Process {
     /// again after the syscall.
     ///
     /// It is not valid to call this function when the process is inactive (i.e.
-    /// the process will not run again).
+    /// the process will not run again). A stopped process stays stopped, and
+    /// sees the return value once it is resumed.
     ///
     /// This can fail, if the UKB implementation cannot correctly set the return
     /// value. An example of how this might occur:
@@ -850,6 +870,29 @@ pub trait Process {
     /// binary representation. Returns `ErrorCode::FAIL` on an internal error.
     fn get_stored_state(&self, out: &mut [u8]) -> Result<usize, ErrorCode>;
 
+    /// Copy process-accessible memory, starting `offset` bytes after the start
+    /// of the process's memory, into `out`. Returns the number of bytes
+    /// copied, which is less than `out.len()` if the copy reaches the app
+    /// break.
+    ///
+    /// This is used to checkpoint the process, so it returns
+    /// `ErrorCode::NOSUPPORT` if the process did not opt in to checkpoints in
+    /// its TBF header.
+    fn read_memory(&self, offset: usize, out: &mut [u8]) -> Result<usize, ErrorCode>;
+
+    /// Restore the process from a checkpoint taken before a reboot.
+    ///
+    /// `memory` replaces the process-accessible memory, which then ends where
+    /// `memory` ends, and `stored_state` is the output of
+    /// `get_stored_state()`. The process then
+    /// continues running where the checkpoint was taken, and does not call its
+    /// init function. Its grants are not part of the checkpoint.
+    ///
+    /// Returns `ErrorCode::NOSUPPORT` if the process did not opt in to
+    /// checkpoints in its TBF header, and `ErrorCode::INVAL` if the process
+    /// has already started running.
+    fn restore(&self, memory: &[u8], stored_state: &[u8]) -> Result<(), ErrorCode>;
+
     /// Print out the full state of the process: its memory map, its context,
     /// and the state of the memory protection unit (MPU).
     fn print_full_process(&self, writer: &mut dyn Write);
@@ -875,6 +918,27 @@ pub trait Process {
     /// Return the last syscall the process called. Returns `None` if the
     /// process has not called any syscalls or the information is unknown.
     fn debug_syscall_last(&self) -> Option<Syscall>;
+
+    /// Return one of the most recent syscalls the process called, where
+    /// `index` 0 is the last one. Returns `None` if the process has not
+    /// called that many syscalls or the information is unknown.
+    fn debug_syscall_history(&self, index: usize) -> Option<Syscall>;
+
+    /// Return the memory that MPU region `region_number` of the process
+    /// covers and the permissions the process has for it. Returns `None` if
+    /// the region is unused or the MPU does not report its regions.
+    fn debug_mpu_region(&self, region_number: usize) -> Option<(mpu::Region, mpu::Permissions)>;
+
+    /// Return the most memory the process has used for its stack and heap
+    /// since it last started.
+    fn debug_high_water_marks(&self) -> ProcessHighWaterMarks;
+
+    /// Copy the top of the process stack into `out`, starting at the stack
+    /// pointer the process had when it last stopped running, for example
+    /// because it faulted. Returns the address of the stack pointer and the
+    /// number of bytes copied, or `None` if the stack pointer is unknown or
+    /// not in process-accessible memory.
+    fn debug_stack_slice(&self, out: &mut [u8]) -> Option<(usize, usize)>;
 }
 
 /// Opaque identifier for custom grants allocated dynamically from a process's
@@ -915,6 +979,8 @@ pub enum Error {
     KernelError,
     /// Indicates some process data, such as a Grant, is already borrowed.
     AlreadyInUse,
+    /// The process has used up its quota of grant memory.
+    GrantQuotaExceeded,
 }
 
 impl<T> From<Error> for Result<T, ErrorCode> {
@@ -926,6 +992,7 @@ impl<T> From<Error> for Result<T, ErrorCode> {
             Error::InactiveApp => Err(ErrorCode::FAIL),
             Error::KernelError => Err(ErrorCode::FAIL),
             Error::AlreadyInUse => Err(ErrorCode::FAIL),
+            Error::GrantQuotaExceeded => Err(ErrorCode::NOMEM),
         }
     }
 }
@@ -939,6 +1006,7 @@ impl From<Error> for ErrorCode {
             Error::InactiveApp => ErrorCode::FAIL,
             Error::KernelError => ErrorCode::FAIL,
             Error::AlreadyInUse => ErrorCode::FAIL,
+            Error::GrantQuotaExceeded => ErrorCode::NOMEM,
         }
     }
 }
@@ -1164,6 +1232,21 @@ pub struct ProcessAddresses {
     pub sram_stack_bottom: Option<usize>,
 }
 
+/// The most memory a process has used for its stack and heap.
+#[derive(Clone, Copy, Debug)]
+pub struct ProcessHighWaterMarks {
+    /// The largest number of bytes of stack the process has used, if known.
+    /// This requires the process to tell the kernel where its stack starts.
+    /// Without stack painting, the kernel only sees the stack pointer at
+    /// context switches, so the stack may have been deeper than this.
+    pub stack: Option<usize>,
+    /// The largest number of bytes of heap the process has used, if known.
+    /// This is how far the process has ever moved its app break past the
+    /// start of its heap, and requires the process to tell the kernel where
+    /// its heap starts.
+    pub heap: Option<usize>,
+}
+
 /// Collection of process state related to the size in memory of various process
 /// structures.
 pub struct ProcessSizes {
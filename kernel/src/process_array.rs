@@ -36,6 +36,10 @@ impl ProcessSlot {
         self.proc.set(Some(process));
     }
 
+    pub(crate) fn clear(&self) {
+        self.proc.set(None);
+    }
+
     /// Install a [`Process`](process::Process) into this process slot from outside the
     /// kernel crate.
     ///
@@ -133,6 +133,19 @@ pub struct ProcessBinary {
 }
 
 impl ProcessBinary {
+    /// Create a second `ProcessBinary` for the same binary, including the
+    /// credential it was accepted with.
+    pub(crate) fn duplicate(&self) -> Self {
+        let credential = OptionalCell::empty();
+        credential.insert(self.credential.get());
+        Self {
+            flash: self.flash,
+            footers: self.footers,
+            header: self.header,
+            credential,
+        }
+    }
+
     pub(crate) fn create(
         app_flash: &'static [u8],
         header_length: usize,
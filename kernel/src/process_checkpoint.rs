@@ -0,0 +1,541 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2025.
+
+//! Checkpointing processes to flash and restoring them after a reboot.
+//!
+//! Processes opt in with the Checkpoint TLV in their TBF header. A checkpoint
+//! holds the process-accessible memory of a process and its architecture
+//! specific stored state, i.e. its registers. The process is stopped while
+//! the checkpoint is written. After a reboot, the process loader restores the
+//! process from its checkpoint before it runs for the first time, so it
+//! continues where the checkpoint was taken instead of calling its init
+//! function.
+//!
+//! Grants are not part of a checkpoint: a restored process has no allowed
+//! buffers, subscribed upcalls or pending upcalls. A process waiting in a
+//! yield could therefore never be woken up after a restore, so only a process
+//! that is running, or was stopped while running, can be checkpointed. A
+//! process usually checkpoints itself through a system call (see
+//! `capsules_extra::process_checkpoint`), and continues from the return of
+//! that system call once restored.
+//!
+//! A checkpoint is only restored if the process binary is unchanged, and if
+//! the binary and the RAM of the process are at the same addresses as when it
+//! was taken, since the memory of the process contains absolute addresses.
+//!
+//! The checkpoint region is divided into slots of the same length. A slot
+//! holds a header, the stored state and the memory of the process. Each
+//! process uses two slots in turn: a new checkpoint overwrites the older
+//! checkpoint of the process, and the header, which is written last, has a
+//! sequence number that is one more than that of the other checkpoint. The
+//! header includes a checksum of the whole checkpoint, so a checkpoint that
+//! was interrupted by a power loss is ignored and the process is restored
+//! from its previous checkpoint.
+
+use core::cell::Cell;
+
+use crate::ErrorCode;
+use crate::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use crate::kernel::Kernel;
+use crate::process::{self, Process, ProcessId, ShortId, StoppedState};
+use crate::utilities::cells::{OptionalCell, TakeCell};
+
+/// Expected buffer length for writing checkpoints.
+pub const BUF_LEN: usize = 512;
+
+/// The number of bytes in the header of a checkpoint.
+const HEADER_LENGTH: usize = 44;
+
+/// The number of bytes reserved for the stored state of the process.
+const STORED_STATE_LENGTH: usize = 160;
+
+/// Offset of the process memory in a slot.
+pub const MEMORY_OFFSET: usize = HEADER_LENGTH + STORED_STATE_LENGTH;
+
+/// Marks a checkpoint header.
+const MAGIC: u32 = 0x5443_4b50;
+
+/// Version of the checkpoint format.
+const VERSION: u32 = 2;
+
+/// Initial value of the FNV-1a checksum.
+const CHECKSUM_INIT: u32 = 0x811c_9dc5;
+
+/// Interface to checkpoint processes.
+pub trait ProcessCheckpoint<'a> {
+    /// Write a checkpoint of the process to flash.
+    ///
+    /// The process is stopped while the checkpoint is written, and resumed
+    /// afterwards unless it was stopped already. Only a running process, or a
+    /// process that was stopped while running, can be checkpointed; any other
+    /// process fails with `ErrorCode::INVAL`.
+    fn checkpoint(&self, processid: ProcessId) -> Result<(), ErrorCode>;
+
+    /// Sets a client for the ProcessCheckpoint Object
+    ///
+    /// When the client operation is done, it calls the `checkpoint_done()`
+    /// function.
+    fn set_checkpoint_client(&self, client: &'a dyn ProcessCheckpointClient);
+}
+
+/// The callback for checkpointing processes.
+pub trait ProcessCheckpointClient {
+    /// The checkpoint of the process was written.
+    fn checkpoint_done(&self, processid: ProcessId, result: Result<(), ErrorCode>);
+}
+
+/// Interface to restore processes when they are loaded.
+pub trait ProcessRestore {
+    /// Restore `process` from its checkpoint. Returns `Ok(false)` if there is
+    /// no checkpoint of the process.
+    ///
+    /// This is called after the process was created and before it runs for
+    /// the first time.
+    fn restore(&self, process: &dyn Process) -> Result<bool, ErrorCode>;
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Memory,
+    StoredState,
+    Header,
+}
+
+/// Header of a checkpoint. Addresses are stored as 32-bit words.
+#[derive(Clone, Copy)]
+struct CheckpointHeader {
+    /// Orders the checkpoints of a process, the newest has the highest.
+    sequence: u32,
+    short_id: u32,
+    flash_start: u32,
+    flash_end: u32,
+    /// Checksum of the process binary.
+    binary_checksum: u32,
+    memory_start: u32,
+    /// The length of the process-accessible memory, up to the app break.
+    memory_length: usize,
+    stored_state_length: usize,
+    /// Checksum of the checkpoint. While the checkpoint is being written,
+    /// this is the checksum of the parts written so far.
+    checksum: u32,
+}
+
+impl CheckpointHeader {
+    fn new(process: &dyn Process, sequence: u32, binary_checksum: u32) -> Self {
+        let addresses = process.get_addresses();
+        Self {
+            sequence,
+            short_id: short_id_value(process.short_app_id()),
+            flash_start: addresses.flash_start as u32,
+            flash_end: addresses.flash_end as u32,
+            binary_checksum,
+            memory_start: addresses.sram_start as u32,
+            memory_length: addresses.sram_app_brk - addresses.sram_start,
+            stored_state_length: 0,
+            checksum: CHECKSUM_INIT,
+        }
+    }
+
+    /// The header words before the checksum.
+    fn to_words(self) -> [u32; 10] {
+        [
+            MAGIC,
+            VERSION,
+            self.sequence,
+            self.short_id,
+            self.flash_start,
+            self.flash_end,
+            self.binary_checksum,
+            self.memory_start,
+            self.memory_length as u32,
+            self.stored_state_length as u32,
+        ]
+    }
+
+    fn from_words(words: &[u32; 11]) -> Option<Self> {
+        if words[0] != MAGIC || words[1] != VERSION {
+            return None;
+        }
+        Some(Self {
+            sequence: words[2],
+            short_id: words[3],
+            flash_start: words[4],
+            flash_end: words[5],
+            binary_checksum: words[6],
+            memory_start: words[7],
+            memory_length: words[8] as usize,
+            stored_state_length: words[9] as usize,
+            checksum: words[10],
+        })
+    }
+
+    /// Whether this checkpoint was taken of a process at the same addresses
+    /// as `process`, possibly with a different binary.
+    fn belongs_to(&self, process: &dyn Process) -> bool {
+        let addresses = process.get_addresses();
+        self.short_id == short_id_value(process.short_app_id())
+            && self.flash_start == addresses.flash_start as u32
+            && self.flash_end == addresses.flash_end as u32
+            && self.memory_start == addresses.sram_start as u32
+    }
+
+    /// Whether `process`, whose binary has the checksum `binary_checksum`, can
+    /// be restored from this checkpoint.
+    fn matches(&self, process: &dyn Process, binary_checksum: u32) -> bool {
+        self.belongs_to(process) && self.binary_checksum == binary_checksum
+    }
+}
+
+/// The checksum of the binary of `process`.
+fn binary_checksum(process: &dyn Process) -> u32 {
+    let addresses = process.get_addresses();
+    // # Safety
+    //
+    // `[flash_start, flash_end)` is the binary of the process in memory mapped
+    // flash, which the kernel only reads while the process exists.
+    let binary = unsafe {
+        core::slice::from_raw_parts(
+            addresses.flash_start as *const u8,
+            addresses.flash_end - addresses.flash_start,
+        )
+    };
+    checksum(CHECKSUM_INIT, binary)
+}
+
+fn short_id_value(short_id: ShortId) -> u32 {
+    match short_id {
+        ShortId::LocallyUnique => 0,
+        ShortId::Fixed(id) => id.get(),
+    }
+}
+
+/// Continue the FNV-1a checksum `checksum` over `data`.
+fn checksum(checksum: u32, data: &[u8]) -> u32 {
+    data.iter().fold(checksum, |checksum, byte| {
+        (checksum ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// A checkpoint read from a slot.
+struct Checkpoint {
+    header: CheckpointHeader,
+    stored_state: &'static [u8],
+    memory: &'static [u8],
+}
+
+/// Read the checkpoint in `slot`, if it holds a valid one.
+fn read_checkpoint(slot: &'static [u8]) -> Option<Checkpoint> {
+    let mut words = [0u32; 11];
+    for (word, bytes) in words
+        .iter_mut()
+        .zip(slot.get(..HEADER_LENGTH)?.chunks_exact(4))
+    {
+        *word = u32::from_le_bytes(bytes.try_into().ok()?);
+    }
+    let header = CheckpointHeader::from_words(&words)?;
+    if header.stored_state_length > STORED_STATE_LENGTH {
+        return None;
+    }
+    let stored_state = slot.get(HEADER_LENGTH..HEADER_LENGTH + header.stored_state_length)?;
+    let memory = slot.get(MEMORY_OFFSET..MEMORY_OFFSET + header.memory_length)?;
+
+    let expected = checksum(
+        checksum(checksum(CHECKSUM_INIT, memory), stored_state),
+        slot.get(..HEADER_LENGTH - 4)?,
+    );
+    (expected == header.checksum).then_some(Checkpoint {
+        header,
+        stored_state,
+        memory,
+    })
+}
+
+/// Checkpoints processes into a flash region one at a time.
+pub struct SequentialProcessCheckpoint<'a, F: NonvolatileStorage<'a>> {
+    kernel: &'static Kernel,
+    flash_driver: &'a F,
+    /// The checkpoint region, mapped into memory.
+    region: &'static [u8],
+    slot_length: usize,
+    buffer: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'a dyn ProcessCheckpointClient>,
+    state: Cell<State>,
+    processid: OptionalCell<ProcessId>,
+    header: OptionalCell<CheckpointHeader>,
+    /// Offset of the slot in the region.
+    slot: Cell<usize>,
+    /// Number of bytes of process memory written so far.
+    offset: Cell<usize>,
+    /// Whether to resume the process once the checkpoint is written.
+    resume: Cell<bool>,
+}
+
+impl<'a, F: NonvolatileStorage<'a>> SequentialProcessCheckpoint<'a, F> {
+    /// Create the checkpoint storage for the flash `region`, which is divided
+    /// into slots of `slot_length` bytes each. A slot must be larger than
+    /// `MEMORY_OFFSET` bytes plus the RAM of the process checkpointed into
+    /// it.
+    ///
+    /// `region` must be memory mapped, and `flash_driver` must address it by
+    /// its address in memory.
+    pub fn new(
+        kernel: &'static Kernel,
+        flash_driver: &'a F,
+        region: &'static [u8],
+        slot_length: usize,
+        buffer: &'static mut [u8],
+    ) -> Self {
+        Self {
+            kernel,
+            flash_driver,
+            region,
+            slot_length,
+            buffer: TakeCell::new(buffer),
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            processid: OptionalCell::empty(),
+            header: OptionalCell::empty(),
+            slot: Cell::new(0),
+            offset: Cell::new(0),
+            resume: Cell::new(false),
+        }
+    }
+
+    fn slots(&self) -> impl Iterator<Item = (usize, &'static [u8])> {
+        let region = self.region;
+        let slot_length = self.slot_length;
+        (0..region.len().checked_div(slot_length).unwrap_or(0)).filter_map(move |index| {
+            let offset = index * slot_length;
+            region
+                .get(offset..offset + slot_length)
+                .map(|slot| (offset, slot))
+        })
+    }
+
+    /// Find the slot for the next checkpoint of `process`, and its sequence
+    /// number.
+    ///
+    /// This is the slot with the older of the two checkpoints of the process,
+    /// else an empty slot, else a slot with a checkpoint of a previous binary
+    /// of the process or of a process that is not loaded. The newest
+    /// checkpoint of the process is never overwritten.
+    fn find_slot(&self, process: &dyn Process, binary_checksum: u32) -> Option<(usize, u32)> {
+        let mut empty = None;
+        let mut stale = None;
+        // The number of checkpoints of the process, the slot with the oldest
+        // one, and the sequence number of the newest one.
+        let mut count = 0;
+        let mut oldest: Option<(usize, u32)> = None;
+        let mut newest = None;
+        for (offset, slot) in self.slots() {
+            match read_checkpoint(slot) {
+                Some(checkpoint) if checkpoint.header.matches(process, binary_checksum) => {
+                    let sequence = checkpoint.header.sequence;
+                    count += 1;
+                    if oldest.is_none_or(|(_, oldest)| sequence < oldest) {
+                        oldest = Some((offset, sequence));
+                    }
+                    newest = newest.max(Some(sequence));
+                }
+                Some(checkpoint) => {
+                    if stale.is_none()
+                        && (checkpoint.header.belongs_to(process)
+                            || !self
+                                .kernel
+                                .get_process_iter()
+                                .any(|other| checkpoint.header.belongs_to(other)))
+                    {
+                        stale = Some(offset);
+                    }
+                }
+                None => {
+                    empty = empty.or(Some(offset));
+                }
+            }
+        }
+        let slot = if count >= 2 {
+            oldest.map(|(offset, _)| offset)
+        } else {
+            empty.or(stale)
+        };
+        slot.map(|slot| (slot, newest.map_or(0, |newest: u32| newest.wrapping_add(1))))
+    }
+
+    /// Write the next part of the checkpoint.
+    fn write_next(&self) -> Result<(), ErrorCode> {
+        let process = self
+            .processid
+            .get()
+            .and_then(|processid| self.kernel.get_process(processid))
+            .ok_or(ErrorCode::FAIL)?;
+
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            match self.fill_buffer(process, buffer) {
+                Ok((address, length)) => self.flash_driver.write(buffer, address, length),
+                Err(e) => {
+                    self.buffer.replace(buffer);
+                    Err(e)
+                }
+            }
+        })
+    }
+
+    /// Copy the next part of the checkpoint into `buffer`, and return where
+    /// to write how much of it.
+    fn fill_buffer(
+        &self,
+        process: &dyn Process,
+        buffer: &mut [u8],
+    ) -> Result<(usize, usize), ErrorCode> {
+        let mut header = self.header.get().ok_or(ErrorCode::FAIL)?;
+        let slot_address = self.region.as_ptr() as usize + self.slot.get();
+
+        if self.state.get() == State::Memory && self.offset.get() >= header.memory_length {
+            self.state.set(State::StoredState);
+        }
+        let (address, length) = match self.state.get() {
+            State::Memory => {
+                let offset = self.offset.get();
+                let out = buffer.get_mut(..BUF_LEN).ok_or(ErrorCode::SIZE)?;
+                let length = process.read_memory(offset, out)?;
+                if length == 0 {
+                    return Err(ErrorCode::FAIL);
+                }
+                self.offset.set(offset + length);
+                header.checksum = checksum(header.checksum, &out[..length]);
+                (slot_address + MEMORY_OFFSET + offset, length)
+            }
+            State::StoredState => {
+                // This is read after the memory is written, so if the process
+                // requested the checkpoint with a system call, the stored
+                // state includes the return value of that system call.
+                let out = buffer
+                    .get_mut(..STORED_STATE_LENGTH)
+                    .ok_or(ErrorCode::SIZE)?;
+                let length = process.get_stored_state(out)?;
+                header.stored_state_length = length;
+                header.checksum = checksum(header.checksum, &out[..length]);
+                (slot_address + HEADER_LENGTH, length)
+            }
+            State::Header => {
+                let out = buffer.get_mut(..HEADER_LENGTH).ok_or(ErrorCode::SIZE)?;
+                for (bytes, word) in out.chunks_exact_mut(4).zip(header.to_words()) {
+                    bytes.copy_from_slice(&word.to_le_bytes());
+                }
+                // The checksum covers the header as well.
+                header.checksum = checksum(header.checksum, &out[..HEADER_LENGTH - 4]);
+                out[HEADER_LENGTH - 4..].copy_from_slice(&header.checksum.to_le_bytes());
+                (slot_address, HEADER_LENGTH)
+            }
+            State::Idle => return Err(ErrorCode::FAIL),
+        };
+        self.header.set(header);
+        Ok((address, length))
+    }
+
+    /// Stop checkpointing and resume the process if it was stopped for the
+    /// checkpoint.
+    fn reset(&self) -> Option<ProcessId> {
+        self.state.set(State::Idle);
+        self.header.take();
+        let processid = self.processid.take();
+        if self.resume.get() {
+            processid
+                .and_then(|processid| self.kernel.get_process(processid))
+                .map(|process| process.resume());
+        }
+        processid
+    }
+
+    fn checkpoint_done(&self, result: Result<(), ErrorCode>) {
+        if let Some(processid) = self.reset() {
+            self.client.map(|client| {
+                client.checkpoint_done(processid, result);
+            });
+        }
+    }
+}
+
+impl<'a, F: NonvolatileStorage<'a>> ProcessCheckpoint<'a> for SequentialProcessCheckpoint<'a, F> {
+    fn checkpoint(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        let process = self.kernel.get_process(processid).ok_or(ErrorCode::INVAL)?;
+        let resume = match process.get_state() {
+            process::State::Running => true,
+            process::State::Stopped(StoppedState::Running) => false,
+            _ => return Err(ErrorCode::INVAL),
+        };
+
+        let binary_checksum = binary_checksum(process);
+        let (slot, sequence) = self
+            .find_slot(process, binary_checksum)
+            .ok_or(ErrorCode::NOMEM)?;
+        let header = CheckpointHeader::new(process, sequence, binary_checksum);
+        if MEMORY_OFFSET + header.memory_length > self.slot_length {
+            return Err(ErrorCode::SIZE);
+        }
+
+        self.processid.set(processid);
+        self.header.set(header);
+        self.slot.set(slot);
+        self.offset.set(0);
+        self.resume.set(resume);
+        self.state.set(State::Memory);
+        process.stop();
+
+        // This fails before anything is written if the process did not opt
+        // in to checkpoints.
+        self.write_next().inspect_err(|_| {
+            self.reset();
+        })
+    }
+
+    fn set_checkpoint_client(&self, client: &'a dyn ProcessCheckpointClient) {
+        self.client.set(client);
+    }
+}
+
+impl<'a, F: NonvolatileStorage<'a>> ProcessRestore for SequentialProcessCheckpoint<'a, F> {
+    fn restore(&self, process: &dyn Process) -> Result<bool, ErrorCode> {
+        let binary_checksum = binary_checksum(process);
+        let Some(checkpoint) = self
+            .slots()
+            .filter_map(|(_, slot)| read_checkpoint(slot))
+            .filter(|checkpoint| checkpoint.header.matches(process, binary_checksum))
+            .max_by_key(|checkpoint| checkpoint.header.sequence)
+        else {
+            return Ok(false);
+        };
+
+        process.restore(checkpoint.memory, checkpoint.stored_state)?;
+        Ok(true)
+    }
+}
+
+impl<'a, F: NonvolatileStorage<'a>> NonvolatileStorageClient
+    for SequentialProcessCheckpoint<'a, F>
+{
+    fn read_done(&self, _buffer: &'static mut [u8], _length: usize) {
+        // Checkpoints are read from memory, so this is never used.
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], _length: usize) {
+        self.buffer.replace(buffer);
+        match self.state.get() {
+            State::Idle => {}
+            State::Header => self.checkpoint_done(Ok(())),
+            State::Memory | State::StoredState => {
+                if self.state.get() == State::StoredState {
+                    self.state.set(State::Header);
+                }
+                if let Err(e) = self.write_next() {
+                    self.checkpoint_done(Err(e));
+                }
+            }
+        }
+    }
+}
@@ -14,6 +14,7 @@
 
 use core::cell::Cell;
 use core::fmt;
+use core::ptr;
 
 use crate::capabilities::ProcessManagementCapability;
 use crate::config;
@@ -25,7 +26,9 @@ use crate::process::{Process, ShortId};
 use crate::process_binary::{ProcessBinary, ProcessBinaryError};
 use crate::process_checker::AcceptedCredential;
 use crate::process_checker::{AppIdPolicy, ProcessCheckError, ProcessCheckerMachine};
+use crate::process_checkpoint::ProcessRestore;
 use crate::process_policies::ProcessFaultPolicy;
+use crate::process_policies::ProcessGrantQuotaPolicy;
 use crate::process_policies::ProcessStandardStoragePermissionsPolicy;
 use crate::process_standard::ProcessStandard;
 use crate::process_standard::{ProcessStandardDebug, ProcessStandardDebugFull};
@@ -142,6 +145,32 @@ pub fn load_processes<C: Chip>(
     app_flash: &'static [u8],
     app_memory: &'static mut [u8],
     fault_policy: &'static dyn ProcessFaultPolicy,
+    capability_management: &dyn ProcessManagementCapability,
+) -> Result<(), ProcessLoadError> {
+    load_processes_with_grant_quota(
+        kernel,
+        chip,
+        app_flash,
+        app_memory,
+        fault_policy,
+        None,
+        capability_management,
+    )
+}
+
+/// Load processes into runnable process structures, limiting the grant
+/// memory of each process with `grant_quota_policy`.
+///
+/// This is [`load_processes`] with a [`ProcessGrantQuotaPolicy`], which is
+/// `None` for no quota.
+#[inline(always)]
+pub fn load_processes_with_grant_quota<C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: &'static mut [u8],
+    fault_policy: &'static dyn ProcessFaultPolicy,
+    grant_quota_policy: Option<&'static dyn ProcessGrantQuotaPolicy>,
     _capability_management: &dyn ProcessManagementCapability,
 ) -> Result<(), ProcessLoadError> {
     load_processes_from_flash::<C, ProcessStandardDebugFull>(
@@ -150,6 +179,7 @@ pub fn load_processes<C: Chip>(
         app_flash,
         app_memory,
         fault_policy,
+        grant_quota_policy,
     )?;
 
     if config::CONFIG.debug_process_credentials {
@@ -186,6 +216,7 @@ fn load_processes_from_flash<C: Chip, D: ProcessStandardDebug + 'static>(
     app_flash: &'static [u8],
     app_memory: *mut [u8],
     fault_policy: &'static dyn ProcessFaultPolicy,
+    grant_quota_policy: Option<&'static dyn ProcessGrantQuotaPolicy>,
 ) -> Result<(), ProcessLoadError> {
     if config::CONFIG.debug_load_processes {
         debug!(
@@ -218,6 +249,7 @@ fn load_processes_from_flash<C: Chip, D: ProcessStandardDebug + 'static>(
                             index,
                             fault_policy,
                             &(),
+                            grant_quota_policy,
                         );
                         match load_result {
                             Ok((new_mem, proc)) => {
@@ -365,6 +397,7 @@ fn load_process<C: Chip, D: ProcessStandardDebug>(
     index: usize,
     fault_policy: &'static dyn ProcessFaultPolicy,
     storage_policy: &'static dyn ProcessStandardStoragePermissionsPolicy<C, D>,
+    grant_quota_policy: Option<&'static dyn ProcessGrantQuotaPolicy>,
 ) -> Result<(*mut [u8], Option<&'static dyn Process>), (*mut [u8], ProcessLoadError)> {
     if config::CONFIG.debug_load_processes {
         debug!(
@@ -394,6 +427,7 @@ fn load_process<C: Chip, D: ProcessStandardDebug>(
             app_memory,
             fault_policy,
             storage_policy,
+            grant_quota_policy,
             app_id,
             index,
         )
@@ -454,6 +488,10 @@ pub trait ProcessLoadingAsync<'a> {
     fn start(&self);
 }
 
+/// Number of separate regions of freed process memory the loader keeps
+/// track of.
+const FREED_MEMORY_REGIONS: usize = 4;
+
 /// Operating mode of the loader.
 #[derive(Clone, Copy)]
 enum SequentialProcessLoaderMachineState {
@@ -509,6 +547,8 @@ pub struct SequentialProcessLoaderMachine<'a, C: Chip + 'static, D: ProcessStand
     flash: Cell<&'static [u8]>,
     /// Memory available to assign to applications.
     app_memory: MapCell<*mut [u8]>,
+    /// Memory of removed processes that is not adjacent to `app_memory`.
+    freed_memory: MapCell<[Option<*mut [u8]>; FREED_MEMORY_REGIONS]>,
     /// Mechanism for generating async callbacks.
     deferred_call: DeferredCall,
     /// Reference to the kernel object for creating Processes.
@@ -518,13 +558,17 @@ pub struct SequentialProcessLoaderMachine<'a, C: Chip + 'static, D: ProcessStand
     /// The policy to use when determining ShortIds and process uniqueness.
     policy: OptionalCell<&'a dyn AppIdPolicy>,
     /// The fault policy to assign to each created Process.
-    fault_policy: &'static dyn ProcessFaultPolicy,
+    fault_policy: Cell<&'static dyn ProcessFaultPolicy>,
     /// The storage permissions policy to assign to each created Process.
     storage_policy: &'static dyn ProcessStandardStoragePermissionsPolicy<C, D>,
     /// Current mode of the loading machine.
     state: OptionalCell<SequentialProcessLoaderMachineState>,
     /// Current operating mode of the loading machine.
     run_mode: OptionalCell<SequentialProcessLoaderMachineRunMode>,
+    /// Restores loaded processes from their checkpoints.
+    restore: OptionalCell<&'a dyn ProcessRestore>,
+    /// The grant quota policy to assign to each created Process.
+    grant_quota_policy: OptionalCell<&'static dyn ProcessGrantQuotaPolicy>,
 }
 
 impl<'a, C: Chip, D: ProcessStandardDebug> SequentialProcessLoaderMachine<'a, C, D> {
@@ -556,13 +600,33 @@ impl<'a, C: Chip, D: ProcessStandardDebug> SequentialProcessLoaderMachine<'a, C,
             flash_bank: Cell::new(flash),
             flash: Cell::new(flash),
             app_memory: MapCell::new(app_memory),
+            freed_memory: MapCell::new([None; FREED_MEMORY_REGIONS]),
             policy: OptionalCell::new(policy),
-            fault_policy,
+            fault_policy: Cell::new(fault_policy),
             storage_policy,
             state: OptionalCell::empty(),
+            restore: OptionalCell::empty(),
+            grant_quota_policy: OptionalCell::empty(),
         }
     }
 
+    /// Set the fault policy of processes loaded afterwards.
+    pub fn set_fault_policy(&self, policy: &'static dyn ProcessFaultPolicy) {
+        self.fault_policy.set(policy);
+    }
+
+    /// Set the policy limiting how much grant memory each process loaded
+    /// afterwards may use.
+    pub fn set_grant_quota_policy(&self, policy: &'static dyn ProcessGrantQuotaPolicy) {
+        self.grant_quota_policy.set(policy);
+    }
+
+    /// Set the mechanism to restore processes from their checkpoints after
+    /// they are loaded.
+    pub fn set_restore(&self, restore: &'a dyn ProcessRestore) {
+        self.restore.set(restore);
+    }
+
     /// Set the runtime client to receive callbacks about process loading and when
     /// process loading has finished.
     pub fn set_runtime_client(&self, client: &'a dyn ProcessLoadingAsyncClient) {
@@ -706,22 +770,8 @@ impl<'a, C: Chip, D: ProcessStandardDebug> SequentialProcessLoaderMachine<'a, C,
                         });
 
                         // Try to create a `Process` object.
-                        let load_result = load_process(
-                            self.kernel,
-                            self.chip,
-                            process_binary,
-                            // If this fails, this indicates a bug in the code
-                            // here: we must've failed to place the `new_mem`
-                            // pointer back into the `MapCell` below:
-                            self.app_memory.take().unwrap(),
-                            short_app_id,
-                            index,
-                            self.fault_policy,
-                            self.storage_policy,
-                        );
-                        match load_result {
-                            Ok((new_mem, proc)) => {
-                                self.app_memory.replace(new_mem);
+                        match self.load_process_binary(process_binary, short_app_id, index) {
+                            Ok(proc) => {
                                 match proc {
                                     Some(p) => {
                                         if config::CONFIG.debug_load_processes {
@@ -731,6 +781,20 @@ impl<'a, C: Chip, D: ProcessStandardDebug> SequentialProcessLoaderMachine<'a, C,
                                             )
                                         }
 
+                                        // Restore the process from its checkpoint
+                                        // before it runs for the first time. If
+                                        // that fails, the process starts fresh.
+                                        self.restore.map(|restore| {
+                                            let result = restore.restore(p);
+                                            if config::CONFIG.debug_load_processes {
+                                                debug!(
+                                                    "Loading: Restoring process {}: {:?}",
+                                                    p.get_process_name(),
+                                                    result
+                                                );
+                                            }
+                                        });
+
                                         // Store the `ProcessStandard` object in the `PROCESSES`
                                         // array.
                                         slot.set(p);
@@ -747,8 +811,7 @@ impl<'a, C: Chip, D: ProcessStandardDebug> SequentialProcessLoaderMachine<'a, C,
                                     }
                                 }
                             }
-                            Err((new_mem, err)) => {
-                                self.app_memory.replace(new_mem);
+                            Err(err) => {
                                 if config::CONFIG.debug_load_processes {
                                     debug!("Could not load process: {:?}.", err);
                                 }
@@ -948,7 +1011,11 @@ impl<'a, C: Chip, D: ProcessStandardDebug> SequentialProcessLoaderMachine<'a, C,
 
     /// Helper function to find the next potential aligned address for the
     /// new app with size `app_length` assuming Cortex-M alignment rules.
-    fn find_next_cortex_m_aligned_address(&self, address: usize, app_length: usize) -> usize {
+    pub(crate) fn find_next_cortex_m_aligned_address(
+        &self,
+        address: usize,
+        app_length: usize,
+    ) -> usize {
         let remaining = address % app_length;
         if remaining == 0 {
             address
@@ -1172,6 +1239,159 @@ impl<'a, C: Chip, D: ProcessStandardDebug> SequentialProcessLoaderMachine<'a, C,
         }
     }
 
+    /// Get the flash region that holds all process binaries.
+    pub(crate) fn flash_bank(&self) -> &'static [u8] {
+        self.flash_bank.get()
+    }
+
+    /// Find the loaded process with the process identifier `identifier`.
+    pub(crate) fn find_process(&self, identifier: usize) -> Option<&'static dyn Process> {
+        self.kernel
+            .get_process_iter()
+            .find(|process| process.processid().id() == identifier)
+    }
+
+    /// Find the loaded process whose binary starts at address `app_address`.
+    pub(crate) fn find_process_at(&self, app_address: usize) -> Option<&'static dyn Process> {
+        self.kernel
+            .get_process_iter()
+            .find(|process| process.get_addresses().flash_start == app_address)
+    }
+
+    /// Terminate `process` and remove it from the processes array.
+    ///
+    /// This frees the process slot and the RAM assigned to the process, which
+    /// processes loaded later can use.
+    pub(crate) fn remove_process(&self, process: &dyn Process) {
+        let addresses = process.get_addresses();
+        process.terminate(None);
+        self.kernel.remove_process(process.processid());
+        self.free_memory(addresses.sram_start, addresses.sram_end);
+    }
+
+    /// Make the RAM from `start` to `end` available to processes again.
+    ///
+    /// Memory next to other free memory is merged with it. If there are
+    /// already `FREED_MEMORY_REGIONS` separate free regions, the memory is not
+    /// reused until the next reset.
+    fn free_memory(&self, mut start: usize, mut end: usize) {
+        // Pointers to freed memory are derived from `app_memory`, which has
+        // the provenance of all process memory.
+        let Some(base) = self.app_memory.map(|memory| memory.cast::<u8>()) else {
+            return;
+        };
+        self.freed_memory.map(|freed_memory| {
+            // Free regions are never next to each other, so there is at most
+            // one region before and one after the new one.
+            for entry in freed_memory.iter_mut() {
+                if let Some(region) = *entry {
+                    if region.addr() + region.len() == start {
+                        start = region.addr();
+                        *entry = None;
+                    } else if region.addr() == end {
+                        end = region.addr() + region.len();
+                        *entry = None;
+                    }
+                }
+            }
+
+            let merged = self.app_memory.map_or(false, |memory| {
+                if memory.addr() == end {
+                    *memory = ptr::slice_from_raw_parts_mut(
+                        base.with_addr(start),
+                        memory.len() + (end - start),
+                    );
+                    true
+                } else {
+                    false
+                }
+            });
+            if !merged {
+                if let Some(entry) = freed_memory.iter_mut().find(|entry| entry.is_none()) {
+                    *entry = Some(ptr::slice_from_raw_parts_mut(
+                        base.with_addr(start),
+                        end - start,
+                    ));
+                }
+            }
+        });
+    }
+
+    /// Create a process from `process_binary`, in memory freed by a removed
+    /// process if it fits there, and in the unused process memory otherwise.
+    fn load_process_binary(
+        &self,
+        process_binary: ProcessBinary,
+        short_app_id: ShortId,
+        index: usize,
+    ) -> Result<Option<&'static dyn Process>, ProcessLoadError> {
+        let regions = self
+            .freed_memory
+            .map_or([None; FREED_MEMORY_REGIONS], |freed_memory| *freed_memory);
+        for (i, region) in regions.iter().enumerate() {
+            let Some(region) = *region else {
+                continue;
+            };
+            self.freed_memory.map(|freed_memory| freed_memory[i] = None);
+            match self.load_process_into(process_binary.duplicate(), region, short_app_id, index) {
+                Ok((unused, proc)) => {
+                    self.return_freed_memory(unused);
+                    return Ok(proc);
+                }
+                // Try the next region.
+                Err((
+                    unused,
+                    ProcessLoadError::NotEnoughMemory
+                    | ProcessLoadError::MemoryAddressMismatch { .. },
+                )) => self.return_freed_memory(unused),
+                Err((unused, err)) => {
+                    self.return_freed_memory(unused);
+                    return Err(err);
+                }
+            }
+        }
+
+        // If this fails, this indicates a bug in the code here: we must've
+        // failed to place the `new_mem` pointer back into the `MapCell`
+        // below.
+        let app_memory = self.app_memory.take().unwrap();
+        let (new_mem, result) =
+            match self.load_process_into(process_binary, app_memory, short_app_id, index) {
+                Ok((new_mem, proc)) => (new_mem, Ok(proc)),
+                Err((new_mem, err)) => (new_mem, Err(err)),
+            };
+        self.app_memory.replace(new_mem);
+        result
+    }
+
+    /// Put what is left of a freed memory region back.
+    fn return_freed_memory(&self, region: *mut [u8]) {
+        if region.len() > 0 {
+            self.free_memory(region.addr(), region.addr() + region.len());
+        }
+    }
+
+    /// Create a process from `process_binary` with `memory` as its RAM pool.
+    fn load_process_into(
+        &self,
+        process_binary: ProcessBinary,
+        memory: *mut [u8],
+        short_app_id: ShortId,
+        index: usize,
+    ) -> Result<(*mut [u8], Option<&'static dyn Process>), (*mut [u8], ProcessLoadError)> {
+        load_process(
+            self.kernel,
+            self.chip,
+            process_binary,
+            memory,
+            short_app_id,
+            index,
+            self.fault_policy.get(),
+            self.storage_policy,
+            self.grant_quota_policy.get(),
+        )
+    }
+
     /// Function to check if the app binary at address `app_address` is valid.
     fn check_new_binary_validity(&self, app_address: usize) -> bool {
         let flash = self.flash_bank.get();
@@ -25,6 +25,18 @@ pub trait ProcessFaultPolicy {
     fn action(&self, process: &dyn Process) -> process::FaultAction;
 }
 
+/// Generic trait for implementing a policy on how much grant memory a process
+/// may use.
+///
+/// The kernel denies grant and custom grant allocations that would make a
+/// process exceed its quota with `Error::GrantQuotaExceeded`, which userspace
+/// sees as `ErrorCode::NOMEM`, as when the process runs out of memory.
+pub trait ProcessGrantQuotaPolicy {
+    /// Return the number of bytes of grant memory `process` may use, or
+    /// `None` if its grant memory is only limited by its memory allocation.
+    fn quota(&self, process: &dyn Process) -> Option<usize>;
+}
+
 /// Generic trait for implementing a policy on how applications should be
 /// assigned storage permissions.
 pub trait ProcessStandardStoragePermissionsPolicy<C: Chip, D: ProcessStandardDebug> {
@@ -28,11 +28,12 @@ use crate::process::ProcessBinary;
 use crate::process::{BinaryVersion, ReturnArguments};
 use crate::process::{Error, FunctionCall, FunctionCallSource, Process, Task};
 use crate::process::{FaultAction, ProcessCustomGrantIdentifier, ProcessId};
-use crate::process::{ProcessAddresses, ProcessSizes, ShortId};
+use crate::process::{ProcessAddresses, ProcessHighWaterMarks, ProcessSizes, ShortId};
 use crate::process::{State, StoppedState};
 use crate::process_checker::AcceptedCredential;
 use crate::process_loading::ProcessLoadError;
 use crate::process_policies::ProcessFaultPolicy;
+use crate::process_policies::ProcessGrantQuotaPolicy;
 use crate::process_policies::ProcessStandardStoragePermissionsPolicy;
 use crate::processbuffer::{ReadOnlyProcessBuffer, ReadWriteProcessBuffer};
 use crate::storage_permissions::StoragePermissions;
@@ -65,6 +66,33 @@ const unsafe fn maybe_uninit_slice_assume_init_mut<T>(src: &mut [MaybeUninit<T>]
     }
 }
 
+/// The byte the kernel paints the unused part of a process stack with when
+/// stack painting is enabled.
+const STACK_PAINT_BYTE: u8 = 0xA5;
+
+/// Computes how deep the stack of a process, which starts at `stack_top` and
+/// grows down, has reached. `stack_min` is the lowest stack pointer the
+/// kernel saw.
+///
+/// If the stack was painted, `painted` is the process memory from
+/// `memory_start` up to `stack_top`. The stack then also reached the lowest
+/// byte that no longer holds the paint, even if the kernel did not see it.
+fn stack_high_water_mark(
+    stack_top: usize,
+    stack_min: usize,
+    memory_start: usize,
+    painted: Option<&[u8]>,
+) -> usize {
+    let stack_bottom = painted.map_or(stack_min, |memory| {
+        let unused = memory
+            .iter()
+            .take_while(|byte| **byte == STACK_PAINT_BYTE)
+            .count();
+        cmp::min(stack_min, memory_start + unused)
+    });
+    stack_top.saturating_sub(stack_bottom)
+}
+
 /// Divides one mutable raw slice into two at an index.
 ///
 /// This method implementation is copied from the standard library, where it is
@@ -148,12 +176,28 @@ pub trait ProcessStandardDebug: Default {
     /// address if it is the lowest address that the process's stack has
     /// reached.
     fn set_new_app_stack_min_pointer(&self, ptr: *const u8);
+    /// Record the stack pointer the process had when it last stopped running.
+    fn set_app_stack_pointer(&self, ptr: *const u8);
+    /// Get the stack pointer the process had when it last stopped running, if
+    /// it was recorded.
+    fn get_app_stack_pointer(&self) -> Option<*const u8>;
+    /// Get the highest address the process's app break has reached, if it was
+    /// recorded.
+    fn get_app_break_max_pointer(&self) -> Option<*const u8>;
+    /// Provide the new app break and record the address if it is the highest
+    /// address that the process's app break has reached.
+    fn set_new_app_break_max_pointer(&self, ptr: *const u8);
+    /// Clear any record of the highest app break.
+    fn reset_app_break_max_pointer(&self);
 
     /// Record the most recent system call the process called.
     fn set_last_syscall(&self, syscall: Syscall);
     /// Get the most recent system call the process called, if it was recorded.
     fn get_last_syscall(&self) -> Option<Syscall>;
-    /// Clear any record of the most recent system call the process called.
+    /// Get a recent system call the process called, if it was recorded, where
+    /// `index` 0 is the most recent one.
+    fn get_syscall_history(&self, index: usize) -> Option<Syscall>;
+    /// Clear any record of the system calls the process called.
     fn reset_last_syscall(&self);
 
     /// Increase the recorded count of the number of system calls the process
@@ -206,6 +250,9 @@ pub struct ProcessStandardDebugFull {
     debug: MapCell<ProcessStandardDebugFullInner>,
 }
 
+/// The number of recent syscalls `ProcessStandardDebugFull` records.
+const SYSCALL_HISTORY_LEN: usize = 8;
+
 /// Struct for debugging [`ProcessStandard`] processes that records the full set
 /// of debugging information.
 ///
@@ -234,11 +281,19 @@ struct ProcessStandardDebugFullInner {
     /// How low have we ever seen the stack pointer.
     app_stack_min_pointer: Option<*const u8>,
 
+    /// The stack pointer when the process last stopped running.
+    app_stack_pointer: Option<*const u8>,
+
+    /// How high has the process ever moved its app break.
+    app_break_max_pointer: Option<*const u8>,
+
     /// How many syscalls have occurred since the process started.
     syscall_count: usize,
 
-    /// What was the most recent syscall.
-    last_syscall: Option<Syscall>,
+    /// The most recent syscalls, as a ring where `syscall_history_next` is
+    /// the index the next syscall is recorded at.
+    syscall_history: [Option<Syscall>; SYSCALL_HISTORY_LEN],
+    syscall_history_next: usize,
 
     /// How many upcalls were dropped because the queue was insufficiently
     /// long.
@@ -293,15 +348,49 @@ impl ProcessStandardDebug for ProcessStandardDebugFull {
             }
         });
     }
+    fn set_app_stack_pointer(&self, ptr: *const u8) {
+        self.debug.map(|d| d.app_stack_pointer = Some(ptr));
+    }
+    fn get_app_stack_pointer(&self) -> Option<*const u8> {
+        self.debug.map_or(None, |d| d.app_stack_pointer)
+    }
+    fn get_app_break_max_pointer(&self) -> Option<*const u8> {
+        self.debug.map_or(None, |d| d.app_break_max_pointer)
+    }
+    fn set_new_app_break_max_pointer(&self, ptr: *const u8) {
+        self.debug.map(|d| {
+            if d.app_break_max_pointer.is_none_or(|abmp| ptr > abmp) {
+                d.app_break_max_pointer = Some(ptr);
+            }
+        });
+    }
+    fn reset_app_break_max_pointer(&self) {
+        self.debug.map(|d| d.app_break_max_pointer = None);
+    }
 
     fn set_last_syscall(&self, syscall: Syscall) {
-        self.debug.map(|d| d.last_syscall = Some(syscall));
+        self.debug.map(|d| {
+            d.syscall_history[d.syscall_history_next] = Some(syscall);
+            d.syscall_history_next = (d.syscall_history_next + 1) % SYSCALL_HISTORY_LEN;
+        });
     }
     fn get_last_syscall(&self) -> Option<Syscall> {
-        self.debug.map_or(None, |d| d.last_syscall)
+        self.get_syscall_history(0)
+    }
+    fn get_syscall_history(&self, index: usize) -> Option<Syscall> {
+        if index >= SYSCALL_HISTORY_LEN {
+            return None;
+        }
+        self.debug.map_or(None, |d| {
+            d.syscall_history
+                [(d.syscall_history_next + SYSCALL_HISTORY_LEN - 1 - index) % SYSCALL_HISTORY_LEN]
+        })
     }
     fn reset_last_syscall(&self) {
-        self.debug.map(|d| d.last_syscall = None);
+        self.debug.map(|d| {
+            d.syscall_history = [None; SYSCALL_HISTORY_LEN];
+            d.syscall_history_next = 0;
+        });
     }
 
     fn increment_syscall_count(&self) {
@@ -365,11 +454,23 @@ impl ProcessStandardDebug for () {
         None
     }
     fn set_new_app_stack_min_pointer(&self, _ptr: *const u8) {}
+    fn set_app_stack_pointer(&self, _ptr: *const u8) {}
+    fn get_app_stack_pointer(&self) -> Option<*const u8> {
+        None
+    }
+    fn get_app_break_max_pointer(&self) -> Option<*const u8> {
+        None
+    }
+    fn set_new_app_break_max_pointer(&self, _ptr: *const u8) {}
+    fn reset_app_break_max_pointer(&self) {}
 
     fn set_last_syscall(&self, _syscall: Syscall) {}
     fn get_last_syscall(&self) -> Option<Syscall> {
         None
     }
+    fn get_syscall_history(&self, _index: usize) -> Option<Syscall> {
+        None
+    }
     fn reset_last_syscall(&self) {}
 
     fn increment_syscall_count(&self) {}
@@ -408,6 +509,10 @@ struct GrantPointerEntry {
     /// The start of the memory location where the grant has been allocated, or
     /// null if the grant has not been allocated.
     grant_ptr: *mut u8,
+
+    /// The number of bytes of the grant region used by the allocation,
+    /// including any padding for alignment.
+    size: usize,
 }
 
 /// A type for userspace processes in Tock.
@@ -534,6 +639,13 @@ pub struct ProcessStandard<'a, C: 'static + Chip, D: 'static + ProcessStandardDe
     /// How to respond if this process faults.
     fault_policy: &'a dyn ProcessFaultPolicy,
 
+    /// How much grant memory this process may use, if limited.
+    grant_quota_policy: Option<&'a dyn ProcessGrantQuotaPolicy>,
+
+    /// The number of bytes of the grant region used by grants and custom
+    /// grants.
+    grant_memory_used: Cell<usize>,
+
     /// Storage permissions for this process.
     ///
     /// This is stored in a `Cell` because we need to create the
@@ -877,6 +989,31 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
             // We also reset the minimum stack pointer because whatever
             // value we had could be entirely wrong by now.
             self.debug.set_app_stack_min_pointer(stack_pointer);
+
+            // Paint the stack below the current stack pointer, so the stack
+            // high-water mark includes stack use between context switches.
+            // The stack grows down towards the start of process memory. The
+            // rest of process memory, including the heap, stays as it is.
+            if config::CONFIG.paint_process_stacks {
+                if let Some(current) = self.debug.get_app_stack_pointer() {
+                    let paint_end = cmp::min(current, stack_pointer);
+                    if paint_end > self.mem_start() {
+                        // # Safety
+                        //
+                        // `[mem_start, paint_end)` is process-accessible
+                        // memory below the stack pointer of the process,
+                        // which the process does not use, and the process is
+                        // not running while the kernel writes it.
+                        unsafe {
+                            core::ptr::write_bytes(
+                                self.mem_start().cast_mut(),
+                                STACK_PAINT_BYTE,
+                                paint_end.addr() - self.mem_start().addr(),
+                            );
+                        }
+                    }
+                }
+            }
         }
     }
 
@@ -1022,6 +1159,7 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
                             new_break.addr() - old_break.addr(),
                         );
                     }
+                    self.debug.set_new_app_break_max_pointer(new_break);
                 }
 
                 let base = self.mem_start() as usize;
@@ -1219,22 +1357,22 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
         driver_num: usize,
         size: usize,
         align: usize,
-    ) -> Result<(), ()> {
+    ) -> Result<(), Error> {
         // Do not modify an inactive process.
         if !self.is_running() {
-            return Err(());
+            return Err(Error::InactiveApp);
         }
 
         // Verify the grant_num is valid.
         if grant_num >= self.kernel.get_grant_count_and_finalize() {
-            return Err(());
+            return Err(Error::KernelError);
         }
 
         // Verify that the grant is not already allocated. If the pointer is not
         // null then the grant is already allocated.
         if let Some(is_allocated) = self.grant_is_allocated(grant_num) {
             if is_allocated {
-                return Err(());
+                return Err(Error::AlreadyInUse);
             }
         }
 
@@ -1251,55 +1389,51 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
         // If we find a match, then the `driver_num` must already be used and
         // the grant allocation fails.
         if exists {
-            return Err(());
+            return Err(Error::AlreadyInUse);
         }
 
         // Use the shared grant allocator function to actually allocate memory.
-        // Returns `None` if the allocation cannot be created.
-        if let Some(grant_ptr) = self.allocate_in_grant_region_internal(size, align) {
-            // Update the grant pointer to the address of the new allocation.
-            self.grant_pointers.map_or(Err(()), |grant_pointers| {
+        // Returns an error if the allocation cannot be created.
+        let (grant_ptr, used) = self.allocate_in_grant_region_internal(size, align)?;
+
+        // Update the grant pointer to the address of the new allocation.
+        self.grant_pointers
+            .map_or(Err(Error::KernelError), |grant_pointers| {
                 // Implement `grant_pointers[grant_num] = grant_ptr` without a
                 // chance of a panic.
                 grant_pointers
                     .get_mut(grant_num)
-                    .map_or(Err(()), |grant_entry| {
+                    .map_or(Err(Error::KernelError), |grant_entry| {
                         // Actually set the driver num and grant pointer.
                         grant_entry.driver_num = driver_num;
                         grant_entry.grant_ptr = grant_ptr.as_ptr();
+                        grant_entry.size = used;
 
                         // If all of this worked, return true.
                         Ok(())
                     })
             })
-        } else {
-            // Could not allocate the memory for the grant region.
-            Err(())
-        }
     }
 
     fn allocate_custom_grant(
         &self,
         size: usize,
         align: usize,
-    ) -> Result<(ProcessCustomGrantIdentifier, NonNull<u8>), ()> {
+    ) -> Result<(ProcessCustomGrantIdentifier, NonNull<u8>), Error> {
         // Do not modify an inactive process.
         if !self.is_running() {
-            return Err(());
+            return Err(Error::InactiveApp);
         }
 
         // Use the shared grant allocator function to actually allocate memory.
-        // Returns `None` if the allocation cannot be created.
-        if let Some(ptr) = self.allocate_in_grant_region_internal(size, align) {
-            // Create the identifier that the caller will use to get access to
-            // this custom grant in the future.
-            let identifier = self.create_custom_grant_identifier(ptr);
+        // Returns an error if the allocation cannot be created.
+        let (ptr, _) = self.allocate_in_grant_region_internal(size, align)?;
 
-            Ok((identifier, ptr))
-        } else {
-            // Could not allocate memory for the custom grant.
-            Err(())
-        }
+        // Create the identifier that the caller will use to get access to
+        // this custom grant in the future.
+        let identifier = self.create_custom_grant_identifier(ptr);
+
+        Ok((identifier, ptr))
     }
 
     fn enter_grant(&self, grant_num: usize) -> Result<NonNull<u8>, Error> {
@@ -1397,6 +1531,19 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
         })
     }
 
+    fn grant_memory_usage(&self, grant_num: usize) -> Option<(usize, usize)> {
+        self.grant_pointers.map_or(None, |grant_pointers| {
+            grant_pointers
+                .get(grant_num)
+                .filter(|grant_entry| !grant_entry.grant_ptr.is_null())
+                .map(|grant_entry| (grant_entry.driver_num, grant_entry.size))
+        })
+    }
+
+    fn grant_memory_used(&self) -> usize {
+        self.grant_memory_used.get()
+    }
+
     fn lookup_grant_from_driver_num(&self, driver_num: usize) -> Result<usize, Error> {
         self.grant_pointers
             .map_or(Err(Error::KernelError), |grant_pointers| {
@@ -1430,6 +1577,8 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
     }
 
     fn set_syscall_return_value(&self, return_value: SyscallReturn) {
+        self.kernel
+            .trace_syscall_return(self.processid(), &return_value);
         match self.stored_state.map(|stored_state| unsafe {
             // Actually set the return value for a particular process.
             //
@@ -1453,8 +1602,14 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
                 // just called a nonblocking syscall like command) or needs to
                 // be moved to the running state having called Yield-WaitFor and
                 // now needing to be resumed. Either way we can set the state to
-                // running.
-                self.state.set(State::Running);
+                // running. A process that was stopped, for example by the
+                // syscall itself or while it waited in Yield-WaitFor, stays
+                // stopped, and runs once it is resumed. Otherwise returning
+                // from the syscall would undo the stop.
+                match self.state.get() {
+                    State::Stopped(_) => self.state.set(State::Stopped(StoppedState::Running)),
+                    _ => self.state.set(State::Running),
+                }
                 // The task is running, if it was yielded-for an upcall,
                 // the upcall must have been scheduled, unset
                 // the ready flag.
@@ -1547,6 +1702,7 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
         // debugging state. This is completely optional.
         if let Some(sp) = stack_pointer {
             self.debug.set_new_app_stack_min_pointer(sp);
+            self.debug.set_app_stack_pointer(sp);
         }
 
         switch_reason
@@ -1577,6 +1733,84 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
         self.debug.get_last_syscall()
     }
 
+    fn debug_syscall_history(&self, index: usize) -> Option<Syscall> {
+        self.debug.get_syscall_history(index)
+    }
+
+    fn debug_mpu_region(&self, region_number: usize) -> Option<(mpu::Region, mpu::Permissions)> {
+        self.mpu_config.map_or(None, |config| {
+            self.chip.mpu().config_region(config, region_number)
+        })
+    }
+
+    fn debug_high_water_marks(&self) -> ProcessHighWaterMarks {
+        let stack = self.debug.get_app_stack_start_pointer().map(|stack_top| {
+            let stack_min = self.debug.get_app_stack_min_pointer().unwrap_or(stack_top);
+
+            // With stack painting, the stack reached the lowest byte below
+            // the top of the stack that no longer holds the paint pattern.
+            // This includes stack use the kernel did not see at a context
+            // switch.
+            let painted = config::CONFIG.paint_process_stacks.then(|| {
+                let end = cmp::min(stack_top, self.app_break.get());
+                // # Safety
+                //
+                // `[mem_start, end)` is process-accessible memory, which the
+                // kernel initialized, and the process is not running while
+                // the kernel reads it.
+                unsafe {
+                    slice::from_raw_parts(
+                        self.mem_start(),
+                        end.addr().saturating_sub(self.mem_start().addr()),
+                    )
+                }
+            });
+            stack_high_water_mark(
+                stack_top.addr(),
+                stack_min.addr(),
+                self.mem_start().addr(),
+                painted,
+            )
+        });
+
+        let heap = self.debug.get_app_heap_start_pointer().map(|heap_start| {
+            let heap_end = self
+                .debug
+                .get_app_break_max_pointer()
+                .map_or(self.app_break.get(), |max| {
+                    cmp::max(max, self.app_break.get())
+                });
+            heap_end.addr().saturating_sub(heap_start.addr())
+        });
+
+        ProcessHighWaterMarks { stack, heap }
+    }
+
+    fn debug_stack_slice(&self, out: &mut [u8]) -> Option<(usize, usize)> {
+        let sp = self.debug.get_app_stack_pointer()?;
+        let end = self
+            .debug
+            .get_app_stack_start_pointer()
+            .filter(|stack_top| *stack_top > sp)
+            .map_or(self.app_break.get(), |stack_top| {
+                cmp::min(stack_top, self.app_break.get())
+            });
+        if sp < self.mem_start() || sp >= end {
+            return None;
+        }
+        let length = cmp::min(end.addr() - sp.addr(), out.len());
+
+        // # Safety
+        //
+        // `[sp, sp + length)` is within process-accessible memory, which the
+        // kernel initialized, and the process is not running while the kernel
+        // reads it. `out` is kernel memory, so they do not overlap.
+        unsafe {
+            core::ptr::copy_nonoverlapping(sp, out.as_mut_ptr(), length);
+        }
+        Some((sp.addr(), length))
+    }
+
     fn get_addresses(&self) -> ProcessAddresses {
         ProcessAddresses {
             flash_start: self.flash_start() as usize,
@@ -1704,6 +1938,69 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
             })
             .unwrap_or(Err(ErrorCode::FAIL))
     }
+
+    fn read_memory(&self, offset: usize, out: &mut [u8]) -> Result<usize, ErrorCode> {
+        if !self.header.checkpoint_enabled() {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        let length = (self.app_break.get().addr() - self.mem_start().addr())
+            .checked_sub(offset)
+            .ok_or(ErrorCode::INVAL)?
+            .min(out.len());
+
+        // # Safety
+        //
+        // `[mem_start + offset, mem_start + offset + length)` is within the
+        // process-accessible memory, which is initialized, and it does not
+        // overlap `out`, which the process cannot access.
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                self.mem_start().wrapping_add(offset),
+                out.as_mut_ptr(),
+                length,
+            );
+        }
+        Ok(length)
+    }
+
+    fn restore(&self, memory: &[u8], stored_state: &[u8]) -> Result<(), ErrorCode> {
+        if !self.header.checkpoint_enabled() {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        // Only a process that was just created, and is waiting to call its
+        // init function, can be restored.
+        if self.state.get() != State::Yielded || self.restart_count.get() != 0 {
+            return Err(ErrorCode::INVAL);
+        }
+
+        self.brk(self.mem_start().wrapping_add(memory.len()))?;
+        self.stored_state
+            .map(|state| {
+                self.chip
+                    .userspace_kernel_boundary()
+                    .load_context(state, stored_state)
+            })
+            .unwrap_or(Err(ErrorCode::FAIL))?;
+
+        // # Safety
+        //
+        // `brk()` made `[mem_start, app_break)` process-accessible memory,
+        // which is exactly `memory.len()` bytes long. `memory` holds the
+        // checkpoint, so it does not overlap process memory.
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                memory.as_ptr(),
+                self.mem_start().cast_mut(),
+                memory.len(),
+            );
+        }
+
+        self.tasks.map(|tasks| {
+            tasks.empty();
+        });
+        self.state.set(State::Running);
+        Ok(())
+    }
 }
 
 impl<C: 'static + Chip, D: 'static + ProcessStandardDebug> ProcessStandard<'_, C, D> {
@@ -1748,6 +2045,7 @@ impl<C: 'static + Chip, D: 'static + ProcessStandardDebug> ProcessStandard<'_, C
         remaining_memory: *mut [u8],
         fault_policy: &'static dyn ProcessFaultPolicy,
         storage_permissions_policy: &'static dyn ProcessStandardStoragePermissionsPolicy<C, D>,
+        grant_quota_policy: Option<&'static dyn ProcessGrantQuotaPolicy>,
         app_id: ShortId,
         index: usize,
     ) -> Result<(Option<&'static dyn Process>, *mut [u8]), (ProcessLoadError, *mut [u8])> {
@@ -2125,6 +2423,7 @@ impl<C: 'static + Chip, D: 'static + ProcessStandardDebug> ProcessStandard<'_, C
             grant_entry.write(GrantPointerEntry {
                 driver_num: 0,
                 grant_ptr: core::ptr::null_mut(),
+                size: 0,
             });
         }
         // # Safety
@@ -2228,6 +2527,8 @@ impl<C: 'static + Chip, D: 'static + ProcessStandardDebug> ProcessStandard<'_, C
                 stored_state: MapCell::new(Default::default()),
                 state: Cell::new(State::Yielded),
                 fault_policy: fault_policy,
+                grant_quota_policy: grant_quota_policy,
+                grant_memory_used: Cell::new(0),
                 restart_count: Cell::new(0),
                 completion_code: OptionalCell::empty(),
 
@@ -2372,6 +2673,7 @@ impl<C: 'static + Chip, D: 'static + ProcessStandardDebug> ProcessStandard<'_, C
         self.debug.reset_syscall_count();
         self.debug.reset_dropped_upcall_count();
         self.debug.reset_timeslice_expiration_count();
+        self.debug.reset_app_break_max_pointer();
 
         // Reset MPU region configuration.
         //
@@ -2561,8 +2863,10 @@ impl<C: 'static + Chip, D: 'static + ProcessStandardDebug> ProcessStandard<'_, C
             for grant_entry in grant_pointers.iter_mut() {
                 grant_entry.driver_num = 0;
                 grant_entry.grant_ptr = ptr::null_mut();
+                grant_entry.size = 0;
             }
         });
+        self.grant_memory_used.set(0);
     }
 
     /// Allocate memory in a process's grant region.
@@ -2570,11 +2874,20 @@ impl<C: 'static + Chip, D: 'static + ProcessStandardDebug> ProcessStandard<'_, C
     /// Ensures that the allocation is of `size` bytes and aligned to `align`
     /// bytes.
     ///
+    /// On success, returns the allocation and the number of bytes of the grant
+    /// region it uses, including padding for alignment.
+    ///
     /// If there is not enough memory, or the MPU cannot isolate the process
     /// accessible region from the new kernel memory break after doing the
-    /// allocation, then this will return `None`.
-    fn allocate_in_grant_region_internal(&self, size: usize, align: usize) -> Option<NonNull<u8>> {
-        self.mpu_config.and_then(|config| {
+    /// allocation, then this will return `Error::OutOfMemory`. If the
+    /// allocation would exceed the grant quota of the process, this will
+    /// return `Error::GrantQuotaExceeded`.
+    fn allocate_in_grant_region_internal(
+        &self,
+        size: usize,
+        align: usize,
+    ) -> Result<(NonNull<u8>, usize), Error> {
+        self.mpu_config.map_or(Err(Error::KernelError), |config| {
             // First, compute the candidate new pointer. Note that at this point
             // we have not yet checked whether there is space for this
             // allocation or that it meets alignment requirements.
@@ -2593,12 +2906,26 @@ impl<C: 'static + Chip, D: 'static + ProcessStandardDebug> ProcessStandard<'_, C
             let alignment_mask = !(align - 1);
             let new_break = (new_break_unaligned as usize & alignment_mask) as *const u8;
 
+            // The number of bytes this allocation uses, including padding.
+            let used = self
+                .kernel_memory_break
+                .get()
+                .addr()
+                .wrapping_sub(new_break.addr());
+
             // Verify there is space for this allocation
             if new_break < self.app_break.get() {
-                None
+                Err(Error::OutOfMemory)
                 // Verify it didn't wrap around
             } else if new_break > self.kernel_memory_break.get() {
-                None
+                Err(Error::OutOfMemory)
+                // Verify the process stays within its grant quota.
+            } else if self.grant_quota_policy.is_some_and(|policy| {
+                policy
+                    .quota(self)
+                    .is_some_and(|quota| self.grant_memory_used.get() + used > quota)
+            }) {
+                Err(Error::GrantQuotaExceeded)
                 // Verify this is compatible with the MPU.
             } else if let Err(()) = self.chip.mpu().update_app_memory_region(
                 self.app_break.get(),
@@ -2606,9 +2933,11 @@ impl<C: 'static + Chip, D: 'static + ProcessStandardDebug> ProcessStandard<'_, C
                 mpu::Permissions::ReadWriteOnly,
                 config,
             ) {
-                None
+                Err(Error::OutOfMemory)
             } else {
                 // Allocation is valid.
+                self.grant_memory_used
+                    .set(self.grant_memory_used.get() + used);
 
                 // We always allocate down, so we must lower the
                 // kernel_memory_break.
@@ -2622,7 +2951,7 @@ impl<C: 'static + Chip, D: 'static + ProcessStandardDebug> ProcessStandard<'_, C
                 // Here we are guaranteeing that `grant_ptr` is not null. We can
                 // ensure this because we just created `grant_ptr` based on the
                 // process's allocated memory, and we know it cannot be null.
-                unsafe { Some(NonNull::new_unchecked(grant_ptr)) }
+                unsafe { Ok((NonNull::new_unchecked(grant_ptr), used)) }
             }
         })
     }
@@ -2720,3 +3049,53 @@ impl<C: 'static + Chip, D: 'static + ProcessStandardDebug> ProcessStandard<'_, C
         self.app_break.get()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{STACK_PAINT_BYTE, stack_high_water_mark};
+
+    const MEMORY_START: usize = 0x2000_0000;
+    const STACK_TOP: usize = MEMORY_START + 64;
+
+    #[test]
+    fn high_water_mark_without_paint_is_the_lowest_stack_pointer() {
+        assert_eq!(
+            stack_high_water_mark(STACK_TOP, STACK_TOP - 16, MEMORY_START, None),
+            16
+        );
+    }
+
+    #[test]
+    fn high_water_mark_reaches_the_lowest_overwritten_paint() {
+        let mut memory = [STACK_PAINT_BYTE; 64];
+        // The process used 40 bytes of stack between context switches, but
+        // left one painted byte inside that region.
+        memory[24..].fill(0);
+        memory[30] = STACK_PAINT_BYTE;
+        assert_eq!(
+            stack_high_water_mark(STACK_TOP, STACK_TOP - 16, MEMORY_START, Some(&memory)),
+            40
+        );
+    }
+
+    #[test]
+    fn high_water_mark_keeps_a_lower_stack_pointer() {
+        // The stack pointer went below the paint the process overwrote, for
+        // example into memory the kernel did not paint.
+        let mut memory = [STACK_PAINT_BYTE; 64];
+        memory[48..].fill(0);
+        assert_eq!(
+            stack_high_water_mark(STACK_TOP, STACK_TOP - 32, MEMORY_START, Some(&memory)),
+            32
+        );
+    }
+
+    #[test]
+    fn high_water_mark_of_a_fully_used_stack() {
+        let memory = [0; 64];
+        assert_eq!(
+            stack_high_water_mark(STACK_TOP, STACK_TOP, MEMORY_START, Some(&memory)),
+            64
+        );
+    }
+}
@@ -310,6 +310,103 @@ impl Syscall {
             _ => None,
         }
     }
+
+    /// Get the class of the system call and its arguments as four words, for
+    /// compact records of system calls such as traces and fault dumps.
+    ///
+    /// The words are the driver number, the subdriver number, and two
+    /// arguments, for the classes that have them. Otherwise:
+    ///
+    /// - Yield: the driver and subdriver number for `WaitFor`, and the yield
+    ///   variant (0 for `NoWait`, 1 for `Wait`, 2 for `WaitFor`) as the first
+    ///   argument.
+    /// - Subscribe: the upcall pointer and application data as the arguments.
+    /// - Allow: the buffer address and size as the arguments.
+    /// - Memop: the operand as the subdriver number, and the argument.
+    /// - Exit: the exit identifier as the subdriver number, and the completion
+    ///   code as the first argument.
+    pub fn class_and_arguments(&self) -> (SyscallClass, [usize; 4]) {
+        match *self {
+            Syscall::Yield { yield_type } => match yield_type {
+                YieldVariant::NoWait { ptr: _ } => (SyscallClass::Yield, [0, 0, 0, 0]),
+                YieldVariant::Wait => (SyscallClass::Yield, [0, 0, 1, 0]),
+                YieldVariant::WaitFor {
+                    driver_number,
+                    subdriver_number,
+                } => (SyscallClass::Yield, [driver_number, subdriver_number, 2, 0]),
+            },
+            Syscall::Subscribe {
+                driver_number,
+                subdriver_number,
+                upcall_ptr,
+                appdata,
+            } => (
+                SyscallClass::Subscribe,
+                [
+                    driver_number,
+                    subdriver_number,
+                    upcall_ptr.addr(),
+                    appdata.as_usize(),
+                ],
+            ),
+            Syscall::Command {
+                driver_number,
+                subdriver_number,
+                arg0,
+                arg1,
+            } => (
+                SyscallClass::Command,
+                [driver_number, subdriver_number, arg0, arg1],
+            ),
+            Syscall::ReadWriteAllow {
+                driver_number,
+                subdriver_number,
+                allow_address,
+                allow_size,
+            } => (
+                SyscallClass::ReadWriteAllow,
+                [
+                    driver_number,
+                    subdriver_number,
+                    allow_address as usize,
+                    allow_size,
+                ],
+            ),
+            Syscall::UserspaceReadableAllow {
+                driver_number,
+                subdriver_number,
+                allow_address,
+                allow_size,
+            } => (
+                SyscallClass::UserspaceReadableAllow,
+                [
+                    driver_number,
+                    subdriver_number,
+                    allow_address as usize,
+                    allow_size,
+                ],
+            ),
+            Syscall::ReadOnlyAllow {
+                driver_number,
+                subdriver_number,
+                allow_address,
+                allow_size,
+            } => (
+                SyscallClass::ReadOnlyAllow,
+                [
+                    driver_number,
+                    subdriver_number,
+                    allow_address as usize,
+                    allow_size,
+                ],
+            ),
+            Syscall::Memop { operand, arg0 } => (SyscallClass::Memop, [0, operand, arg0, 0]),
+            Syscall::Exit {
+                which,
+                completion_code,
+            } => (SyscallClass::Exit, [0, which, completion_code, 0]),
+        }
+    }
 }
 
 // ---------- SYSCALL RETURN VALUES ----------
@@ -453,6 +550,25 @@ impl SyscallReturn {
     }
 }
 
+// ---------- SYSCALL TRACING ----------
+
+/// Observer of the system calls processes make, for example to record them
+/// in a trace buffer.
+///
+/// Unlike the `trace_syscalls` kernel configuration option, which prints each
+/// system call with `debug!()`, implementations should be cheap and not
+/// produce output while they are called. The tracer is set with
+/// [`Kernel::set_syscall_trace()`](crate::Kernel::set_syscall_trace).
+pub trait SyscallTrace {
+    /// Called when `processid` makes a system call, before it is handled.
+    fn syscall(&self, processid: process::ProcessId, syscall: &Syscall);
+
+    /// Called when the kernel sets the return value of a system call for
+    /// `processid`. For a blocking `yield`, this is when the process is
+    /// resumed.
+    fn syscall_return(&self, processid: process::ProcessId, return_value: &SyscallReturn);
+}
+
 // ---------- USERSPACE KERNEL BOUNDARY ----------
 
 /// [`ContextSwitchReason`] specifies why the process stopped executing and
@@ -654,4 +770,9 @@ pub trait UserspaceKernelBoundary {
     /// Store architecture specific (e.g. CPU registers or status flags) data
     /// for a process. On success returns the number of elements written to out.
     fn store_context(&self, state: &Self::StoredState, out: &mut [u8]) -> Result<usize, ErrorCode>;
+
+    /// Load architecture specific data for a process that was stored with
+    /// [`store_context()`](UserspaceKernelBoundary::store_context()) into
+    /// `state`.
+    fn load_context(&self, state: &mut Self::StoredState, input: &[u8]) -> Result<(), ErrorCode>;
 }
@@ -0,0 +1,262 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! The block cache.
+//!
+//! All block accesses go through a small cache of whole blocks. Operations
+//! are split into steps, and a step is retried from the start whenever it
+//! touches a block that isn't cached (see `Flow::Miss`). Blocks used by the
+//! current attempt are evicted last, so a step can use up to `CACHE_BLOCKS`
+//! blocks at once.
+//!
+//! Modified blocks are written back once a step completes, in the order they
+//! were first modified. If a step fails all of its modifications are
+//! dropped, so a step either reaches the flash completely or not at all.
+
+use crate::format::BLOCK_NULL;
+use crate::{Error, Request};
+
+/// The number of blocks held in the cache.
+pub const CACHE_BLOCKS: usize = 4;
+
+/// Why a step stopped before completing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Flow {
+    /// The block needs to be read into the cache before retrying.
+    Miss(u32),
+    /// The allocator ran out of blocks; it needs to be rebuilt before
+    /// retrying.
+    Rebuild,
+    /// The operation failed.
+    Fail(Error),
+}
+
+impl From<Error> for Flow {
+    fn from(e: Error) -> Flow {
+        Flow::Fail(e)
+    }
+}
+
+pub(crate) type StepResult<T> = Result<T, Flow>;
+
+#[derive(Clone, Copy)]
+struct Slot {
+    block: u32,
+    /// Used by the current attempt.
+    touched: bool,
+    last_use: u32,
+    dirty: bool,
+    /// The block must be erased before being written.
+    erase: bool,
+    /// Order in which the dirty blocks must be written.
+    seq: u32,
+}
+
+const EMPTY_SLOT: Slot = Slot {
+    block: BLOCK_NULL,
+    touched: false,
+    last_use: 0,
+    dirty: false,
+    erase: false,
+    seq: 0,
+};
+
+/// The block IO the cache is waiting on.
+#[derive(Clone, Copy, PartialEq)]
+enum Io {
+    Load(usize, u32),
+    Erase(usize),
+    Write(usize),
+}
+
+pub(crate) struct Cache<'a> {
+    buffer: &'a mut [u8],
+    block_size: usize,
+    slots: [Slot; CACHE_BLOCKS],
+    clock: u32,
+    io: Option<Io>,
+}
+
+impl<'a> Cache<'a> {
+    pub(crate) fn new(buffer: &'a mut [u8], block_size: usize) -> Self {
+        Self {
+            buffer,
+            block_size,
+            slots: [EMPTY_SLOT; CACHE_BLOCKS],
+            clock: 0,
+            io: None,
+        }
+    }
+
+    fn range(&self, slot: usize) -> core::ops::Range<usize> {
+        slot * self.block_size..(slot + 1) * self.block_size
+    }
+
+    fn lookup(&self, block: u32) -> Option<usize> {
+        self.slots.iter().position(|s| s.block == block)
+    }
+
+    fn touch(&mut self, slot: usize) {
+        self.clock = self.clock.wrapping_add(1);
+        self.slots[slot].touched = true;
+        self.slots[slot].last_use = self.clock;
+    }
+
+    fn mark_dirty(&mut self, slot: usize) {
+        if !self.slots[slot].dirty {
+            self.slots[slot].dirty = true;
+            self.slots[slot].seq = self.clock;
+        }
+    }
+
+    /// Pick a clean slot to reuse. Prefer slots not used by the current
+    /// attempt, empty ones first and then the least recently used. A step can
+    /// read more blocks than the cache holds (for example while walking a
+    /// path), so otherwise fall back to the least recently used clean block;
+    /// if the step needs it again it will be reloaded on the next attempt.
+    fn victim(&self) -> Option<usize> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| !s.dirty)
+            .min_by_key(|(_, s)| (s.touched, s.block != BLOCK_NULL, s.last_use))
+            .map(|(i, _)| i)
+    }
+
+    /// Read access to a block.
+    pub(crate) fn get(&mut self, block: u32) -> StepResult<&[u8]> {
+        let slot = self.lookup(block).ok_or(Flow::Miss(block))?;
+        self.touch(slot);
+        let range = self.range(slot);
+        Ok(&self.buffer[range])
+    }
+
+    /// Write access to a block that already contains data. New data must
+    /// only be written to erased parts of the block.
+    pub(crate) fn get_mut(&mut self, block: u32) -> StepResult<&mut [u8]> {
+        let slot = self.lookup(block).ok_or(Flow::Miss(block))?;
+        self.touch(slot);
+        self.mark_dirty(slot);
+        let range = self.range(slot);
+        Ok(&mut self.buffer[range])
+    }
+
+    /// Write access to a block that will be erased before being written.
+    /// The returned data is all ones.
+    pub(crate) fn fresh(&mut self, block: u32) -> StepResult<&mut [u8]> {
+        let slot = match self.lookup(block) {
+            Some(slot) => slot,
+            None => self.victim().ok_or(Error::NoMemory)?,
+        };
+        self.slots[slot].block = block;
+        self.touch(slot);
+        self.mark_dirty(slot);
+        self.slots[slot].erase = true;
+        let range = self.range(slot);
+        self.buffer[range.clone()].fill(0xff);
+        Ok(&mut self.buffer[range])
+    }
+
+    /// Read access to `src` and write access to `dst` at the same time. Both
+    /// blocks must have been accessed in the current attempt.
+    pub(crate) fn get_pair(&mut self, src: u32, dst: u32) -> StepResult<(&[u8], &mut [u8])> {
+        let s = self.lookup(src).ok_or(Flow::Miss(src))?;
+        let d = self.lookup(dst).ok_or(Flow::Miss(dst))?;
+        self.mark_dirty(d);
+        let bs = self.block_size;
+        if s < d {
+            let (a, b) = self.buffer.split_at_mut(d * bs);
+            Ok((&a[s * bs..(s + 1) * bs], &mut b[..bs]))
+        } else {
+            let (a, b) = self.buffer.split_at_mut(s * bs);
+            Ok((&b[..bs], &mut a[d * bs..(d + 1) * bs]))
+        }
+    }
+
+    /// Start a new attempt of a step.
+    pub(crate) fn begin_attempt(&mut self) {
+        for slot in self.slots.iter_mut() {
+            slot.touched = false;
+        }
+    }
+
+    /// Drop all modifications made by the current attempt.
+    pub(crate) fn discard(&mut self) {
+        for slot in self.slots.iter_mut().filter(|s| s.dirty) {
+            *slot = EMPTY_SLOT;
+        }
+    }
+
+    /// Forget all cached blocks, for example after an IO error.
+    pub(crate) fn clear(&mut self) {
+        self.slots = [EMPTY_SLOT; CACHE_BLOCKS];
+        self.io = None;
+    }
+
+    /// Start loading `block`.
+    pub(crate) fn load(&mut self, block: u32) -> Result<(), Error> {
+        let slot = self.victim().ok_or(Error::NoMemory)?;
+        self.slots[slot] = EMPTY_SLOT;
+        self.io = Some(Io::Load(slot, block));
+        Ok(())
+    }
+
+    /// Start writing back the oldest dirty block. Returns false if there is
+    /// nothing to write.
+    pub(crate) fn flush(&mut self) -> bool {
+        let oldest = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.dirty)
+            .max_by_key(|(_, s)| self.clock.wrapping_sub(s.seq))
+            .map(|(i, s)| (i, s.erase));
+
+        self.io = oldest.map(|(slot, erase)| {
+            if erase {
+                Io::Erase(slot)
+            } else {
+                Io::Write(slot)
+            }
+        });
+        self.io.is_some()
+    }
+
+    /// The IO request the cache is waiting on.
+    pub(crate) fn request(&self) -> Option<Request> {
+        self.io.map(|io| match io {
+            Io::Load(_, block) => Request::Read(block),
+            Io::Erase(slot) => Request::Erase(self.slots[slot].block),
+            Io::Write(slot) => Request::Write(self.slots[slot].block),
+        })
+    }
+
+    /// The buffer to fill for a read request, or to write out for a write
+    /// request.
+    pub(crate) fn io_buffer(&mut self) -> &mut [u8] {
+        let slot = match self.io {
+            Some(Io::Load(slot, _)) | Some(Io::Erase(slot)) | Some(Io::Write(slot)) => slot,
+            None => 0,
+        };
+        let range = self.range(slot);
+        &mut self.buffer[range]
+    }
+
+    /// The requested IO has completed.
+    pub(crate) fn io_done(&mut self) {
+        match self.io.take() {
+            Some(Io::Load(slot, block)) => {
+                self.slots[slot].block = block;
+            }
+            Some(Io::Erase(slot)) => {
+                self.slots[slot].erase = false;
+                self.io = Some(Io::Write(slot));
+            }
+            Some(Io::Write(slot)) => {
+                self.slots[slot].dirty = false;
+            }
+            None => {}
+        }
+    }
+}
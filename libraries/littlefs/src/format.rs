@@ -0,0 +1,236 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! The littlefs v2 on-disk format: tags, checksums and CTZ skip-lists.
+//!
+//! This follows `SPEC.md` of the upstream littlefs project. Multi-byte
+//! values are little endian, except for tags which are big endian and stored
+//! XORed with the previous tag of the commit.
+
+/// The version written to the superblock, littlefs v2.0.
+pub(crate) const DISK_VERSION: u32 = 0x0002_0000;
+/// The highest minor version of the v2 format we can read.
+pub(crate) const DISK_VERSION_MINOR_MAX: u32 = 1;
+/// The name of the superblock entry.
+pub(crate) const MAGIC: &[u8; 8] = b"littlefs";
+/// The longest file name we create, stored in the superblock.
+pub(crate) const NAME_MAX: u32 = 255;
+/// The largest file size, stored in the superblock.
+pub(crate) const FILE_MAX: u32 = 0x7fff_ffff;
+/// The largest custom attribute, stored in the superblock.
+pub(crate) const ATTR_MAX: u32 = 1022;
+/// Size of the superblock inline struct.
+pub(crate) const SUPERBLOCK_LEN: usize = 24;
+
+/// An unused block pointer.
+pub(crate) const BLOCK_NULL: u32 = 0xffff_ffff;
+/// The metadata pair holding the superblock and the root directory.
+pub(crate) const ROOT_PAIR: [u32; 2] = [0, 1];
+
+pub(crate) const TYPE_NAME: u16 = 0x000;
+pub(crate) const TYPE_REG: u16 = 0x001;
+pub(crate) const TYPE_DIR: u16 = 0x002;
+pub(crate) const TYPE_SUPERBLOCK: u16 = 0x0ff;
+pub(crate) const TYPE_STRUCT: u16 = 0x200;
+pub(crate) const TYPE_DIRSTRUCT: u16 = 0x200;
+pub(crate) const TYPE_INLINESTRUCT: u16 = 0x201;
+pub(crate) const TYPE_CTZSTRUCT: u16 = 0x202;
+pub(crate) const TYPE_USERATTR: u16 = 0x300;
+pub(crate) const TYPE_SPLICE: u16 = 0x400;
+pub(crate) const TYPE_CREATE: u16 = 0x401;
+pub(crate) const TYPE_DELETE: u16 = 0x4ff;
+pub(crate) const TYPE_CRC: u16 = 0x500;
+pub(crate) const TYPE_TAIL: u16 = 0x600;
+pub(crate) const TYPE_SOFTTAIL: u16 = 0x600;
+pub(crate) const TYPE_MOVESTATE: u16 = 0x7ff;
+
+/// The id used by tags that don't belong to an entry.
+pub(crate) const ID_NONE: u16 = 0x3ff;
+/// Size of the global state carried by `TYPE_MOVESTATE` tags.
+pub(crate) const GSTATE_LEN: usize = 12;
+
+/// A metadata tag.
+///
+/// ```text
+/// [1 bit valid] [11 bits type] [10 bits id] [10 bits size]
+/// ```
+///
+/// The valid bit is stored inverted, a tag with the top bit set marks the
+/// end of the committed data.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct Tag(pub u32);
+
+impl Tag {
+    pub(crate) const fn new(type3: u16, id: u16, size: u32) -> Tag {
+        Tag(((type3 as u32) << 20) | ((id as u32) << 10) | size)
+    }
+
+    pub(crate) fn is_valid(self) -> bool {
+        self.0 & 0x8000_0000 == 0
+    }
+
+    pub(crate) fn type1(self) -> u16 {
+        ((self.0 & 0x7000_0000) >> 20) as u16
+    }
+
+    pub(crate) fn type2(self) -> u16 {
+        ((self.0 & 0x7800_0000) >> 20) as u16
+    }
+
+    pub(crate) fn type3(self) -> u16 {
+        ((self.0 & 0x7ff0_0000) >> 20) as u16
+    }
+
+    pub(crate) fn chunk(self) -> u8 {
+        ((self.0 & 0x0ff0_0000) >> 20) as u8
+    }
+
+    /// The change in entry count caused by a `TYPE_SPLICE` tag.
+    pub(crate) fn splice(self) -> i8 {
+        self.chunk() as i8
+    }
+
+    pub(crate) fn id(self) -> u16 {
+        ((self.0 & 0x000f_fc00) >> 10) as u16
+    }
+
+    pub(crate) fn size(self) -> u32 {
+        self.0 & 0x3ff
+    }
+
+    /// A size of all ones marks a deleted tag without any data.
+    pub(crate) fn is_delete(self) -> bool {
+        self.size() == 0x3ff
+    }
+
+    /// The size of the tag and its data on disk.
+    pub(crate) fn dsize(self) -> usize {
+        4 + if self.is_delete() {
+            0
+        } else {
+            self.size() as usize
+        }
+    }
+
+    /// Returns true if this is a commit CRC tag. Other tags in the CRC range
+    /// (such as the v2.1 erased-state CRC) are regular tags.
+    pub(crate) fn is_commit_crc(self) -> bool {
+        self.type2() == TYPE_CRC
+    }
+}
+
+/// Nibble table for the reflected CRC-32 polynomial.
+const CRC_TABLE: [u32; 16] = crc_table();
+
+const fn crc_table() -> [u32; 16] {
+    let mut table = [0; 16];
+    let mut i = 0;
+    while i < 16 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 4 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// The CRC-32 used by littlefs: the standard polynomial without the final
+/// inversion. Commits start from `0xffffffff`.
+pub(crate) fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc = (crc >> 4) ^ CRC_TABLE[((crc ^ *byte as u32) & 0xf) as usize];
+        crc = (crc >> 4) ^ CRC_TABLE[((crc ^ (*byte as u32 >> 4)) & 0xf) as usize];
+    }
+    crc
+}
+
+pub(crate) fn read_le32(data: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes([data[off], data[off + 1], data[off + 2], data[off + 3]])
+}
+
+pub(crate) fn read_be32(data: &[u8], off: usize) -> u32 {
+    u32::from_be_bytes([data[off], data[off + 1], data[off + 2], data[off + 3]])
+}
+
+pub(crate) fn write_le32(data: &mut [u8], off: usize, value: u32) {
+    data[off..off + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Compare two revision counts, allowing for overflow.
+pub(crate) fn rev_newer(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+/// Returns true if two metadata pairs refer to the same blocks, in any order.
+pub(crate) fn pair_eq(a: [u32; 2], b: [u32; 2]) -> bool {
+    (a[0] == b[0] && a[1] == b[1]) || (a[0] == b[1] && a[1] == b[0])
+}
+
+pub(crate) fn pair_is_null(pair: [u32; 2]) -> bool {
+    pair[0] == BLOCK_NULL || pair[1] == BLOCK_NULL
+}
+
+pub(crate) fn encode_pair(pair: [u32; 2]) -> [u8; 8] {
+    let mut data = [0; 8];
+    write_le32(&mut data, 0, pair[0]);
+    write_le32(&mut data, 4, pair[1]);
+    data
+}
+
+pub(crate) fn decode_pair(data: &[u8]) -> [u32; 2] {
+    [read_le32(data, 0), read_le32(data, 4)]
+}
+
+/// Encode the superblock inline struct.
+pub(crate) fn encode_superblock(block_size: u32, block_count: u32) -> [u8; SUPERBLOCK_LEN] {
+    let mut data = [0; SUPERBLOCK_LEN];
+    for (i, value) in [
+        DISK_VERSION,
+        block_size,
+        block_count,
+        NAME_MAX,
+        FILE_MAX,
+        ATTR_MAX,
+    ]
+    .iter()
+    .enumerate()
+    {
+        write_le32(&mut data, i * 4, *value);
+    }
+    data
+}
+
+/// Locate byte `pos` of a CTZ skip-list file.
+///
+/// Block `i` of the list starts with `ctz(i) + 1` pointers to blocks
+/// `i - 1`, `i - 2`, `i - 4`, ... and the rest of the block holds data.
+/// Returns the block index and the offset of the byte in that block.
+pub(crate) fn ctz_index(block_size: usize, pos: u32) -> (u32, usize) {
+    let b = (block_size - 8) as u32;
+    let mut i = pos / b;
+    if i == 0 {
+        return (0, pos as usize);
+    }
+
+    i = (pos - 4 * ((i - 1).count_ones() + 2)) / b;
+    let off = pos - b * i - 4 * i.count_ones();
+    (i, off as usize)
+}
+
+/// The number of pointers stored at the start of CTZ block `index`.
+pub(crate) fn ctz_pointers(index: u32) -> usize {
+    if index == 0 {
+        0
+    } else {
+        index.trailing_zeros() as usize + 1
+    }
+}
@@ -0,0 +1,1721 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! The file system operations.
+//!
+//! Each operation is a small state machine stored in `Op`. `LittleFs::poll()`
+//! runs steps of the current operation until one of them needs block IO.
+//! A step may record read-only progress (such as how far it walked a
+//! skip-list) before returning `Flow::Miss`, but it must only change state
+//! that depends on modified blocks once nothing else in the step can fail.
+
+use crate::cache::{Cache, Flow, StepResult};
+use crate::format::{
+    BLOCK_NULL, DISK_VERSION, DISK_VERSION_MINOR_MAX, GSTATE_LEN, ID_NONE, MAGIC, NAME_MAX,
+    ROOT_PAIR, SUPERBLOCK_LEN, TYPE_CREATE, TYPE_CTZSTRUCT, TYPE_DELETE, TYPE_DIR, TYPE_DIRSTRUCT,
+    TYPE_INLINESTRUCT, TYPE_NAME, TYPE_REG, TYPE_SOFTTAIL, TYPE_STRUCT, TYPE_SUPERBLOCK, TYPE_TAIL,
+    Tag, ctz_index, ctz_pointers, decode_pair, encode_pair, encode_superblock, pair_eq,
+    pair_is_null, read_le32, write_le32,
+};
+use crate::mdir::{
+    self, Attr, MASK_NAME_ID, MASK_TYPE1_ID, MASK_TYPE3_ID, MDir, Split, fetch, find_tag,
+};
+use crate::{Config, Entry, EntryKind, Error, FileHandle, OpenFlags, Output, Request};
+
+/// The number of files that can be open at once.
+pub const MAX_OPEN_FILES: usize = 8;
+
+/// The longest CTZ skip-list has a pointer per bit of the block index.
+const MAX_CTZ_POINTERS: usize = 32;
+
+/// How file data is stored.
+enum Struct {
+    Dir([u32; 2]),
+    /// Inline data: size and offset of the data in the metadata block.
+    Inline(u32, usize),
+    /// CTZ skip-list: head block and size.
+    Ctz(u32, u32),
+}
+
+/// Read the struct of entry `id`.
+fn get_struct(data: &[u8], mdir: &MDir, id: u16) -> Result<Struct, Error> {
+    let (tag, off) =
+        find_tag(data, mdir, MASK_TYPE1_ID, Tag::new(TYPE_STRUCT, id, 0)).ok_or(Error::Corrupt)?;
+    match (tag.type3(), tag.size()) {
+        (TYPE_DIRSTRUCT, 8) => Ok(Struct::Dir(decode_pair(&data[off..]))),
+        (TYPE_INLINESTRUCT, size) => Ok(Struct::Inline(size, off)),
+        (TYPE_CTZSTRUCT, 8) => Ok(Struct::Ctz(read_le32(data, off), read_le32(data, off + 4))),
+        _ => Err(Error::Corrupt),
+    }
+}
+
+/// The state of an open file.
+#[derive(Clone, Copy)]
+struct File {
+    open: bool,
+    flags: OpenFlags,
+    /// The metadata pair holding the entry.
+    pair: [u32; 2],
+    id: u16,
+    /// The entry was removed while the file was open.
+    removed: bool,
+    /// The data is a CTZ skip-list, otherwise it is inline.
+    ctz: bool,
+    head: u32,
+    size: u32,
+    pos: u32,
+    /// `head` was written since the last commit, so data can be appended to
+    /// it in place.
+    writing: bool,
+    /// `head` and `size` need to be committed.
+    dirty: bool,
+    /// The head and size of the skip-list being rewritten from `size` on.
+    /// Its data past `size` is still part of the file, and is copied to the
+    /// new skip-list before the file is committed.
+    orig: Option<(u32, u32)>,
+}
+
+impl File {
+    /// The size of the file, including data being rewritten.
+    fn total_size(&self) -> u32 {
+        self.orig.map_or(self.size, |(_, size)| size.max(self.size))
+    }
+
+    /// Record that `n` bytes were written to `block` at the end of the
+    /// skip-list.
+    fn extended(&mut self, block: u32, n: usize) {
+        self.head = block;
+        self.size += n as u32;
+        self.writing = true;
+        self.dirty = true;
+        if self.orig.is_some_and(|(_, size)| self.size >= size) {
+            self.orig = None;
+        }
+    }
+}
+
+const CLOSED_FILE: File = File {
+    open: false,
+    flags: OpenFlags(0),
+    pair: [BLOCK_NULL; 2],
+    id: 0,
+    removed: false,
+    ctz: false,
+    head: BLOCK_NULL,
+    size: 0,
+    pos: 0,
+    writing: false,
+    dirty: false,
+    orig: None,
+};
+
+/// Update the ids of open files after an entry was created or deleted.
+fn shift_files(files: &mut [File], pair: [u32; 2], id: u16, created: bool) {
+    for file in files.iter_mut().filter(|f| f.open && pair_eq(f.pair, pair)) {
+        if created && file.id >= id {
+            file.id += 1;
+        } else if !created && file.id == id {
+            file.removed = true;
+        } else if !created && file.id > id {
+            file.id -= 1;
+        }
+    }
+}
+
+/// Update the location of open files after a commit split their metadata
+/// pair.
+fn relocate_files(files: &mut [File], pair: [u32; 2], split: Option<Split>) {
+    if split.is_none() {
+        return;
+    }
+    for file in files.iter_mut().filter(|f| f.open && pair_eq(f.pair, pair)) {
+        (file.pair, file.id) = Split::locate(split, file.pair, file.id);
+    }
+}
+
+/// Commit `attrs` to `mdir`, splitting it into a pair from `alloc` if it is
+/// full, and move open files along with their entries. Returns where entry
+/// `id` is afterwards.
+fn commit(
+    cache: &mut Cache,
+    alloc: &mut Alloc,
+    files: &mut [File],
+    mdir: &mut MDir,
+    id: u16,
+    attrs: &[Attr],
+    prog_size: usize,
+) -> StepResult<([u32; 2], u16)> {
+    let pair = mdir.pair;
+    let split = mdir::commit(cache, mdir, attrs, prog_size, || alloc.alloc())?;
+    relocate_files(files, pair, split);
+    Ok(Split::locate(split, pair, id))
+}
+
+/// A block of a skip-list and its index, to continue walking from.
+#[derive(Clone, Copy)]
+struct Cursor {
+    head: u32,
+    block: u32,
+    index: u32,
+}
+
+/// Find block `index` of the skip-list `head` holding `size` bytes. The walk
+/// starts from `cursor` when it is on the way, and records its progress
+/// there.
+fn find_block(
+    cache: &mut Cache,
+    block_size: usize,
+    (head, size): (u32, u32),
+    index: u32,
+    cursor: &mut Option<Cursor>,
+) -> StepResult<u32> {
+    let (mut block, mut current) = match *cursor {
+        Some(c) if c.head == head && c.index >= index => (c.block, c.index),
+        _ => (head, ctz_index(block_size, size - 1).0),
+    };
+    while current > index {
+        let skip = (current - index).ilog2().min(current.trailing_zeros()) as usize;
+        block = read_le32(cache.get(block)?, 4 * skip);
+        current -= 1 << skip;
+        *cursor = Some(Cursor {
+            head,
+            block,
+            index: current,
+        });
+    }
+    Ok(block)
+}
+
+/// Progress of adding a block of data to a skip-list.
+#[derive(Clone, Copy)]
+struct Extend {
+    /// Pointers of the next block, collected so far.
+    pointers: [u32; MAX_CTZ_POINTERS],
+    pointers_len: usize,
+    /// Where the data being rewritten was last found.
+    cursor: Option<Cursor>,
+}
+
+const NEW_EXTEND: Extend = Extend {
+    pointers: [BLOCK_NULL; MAX_CTZ_POINTERS],
+    pointers_len: 0,
+    cursor: None,
+};
+
+/// Tracks which blocks are in use.
+///
+/// Blocks are allocated from a bitmap built by traversing the file system.
+/// Blocks freed by later commits are only found by the next traversal,
+/// which happens when the bitmap runs out of free blocks.
+struct Alloc<'a> {
+    bitmap: &'a mut [u8],
+    count: u32,
+    /// Where to start looking, so allocations rotate through the flash.
+    next: u32,
+    /// Blocks allocated by the current attempt.
+    attempt: [u32; 4],
+    attempt_len: usize,
+    /// The bitmap was rebuilt since the last allocation.
+    rebuilt: bool,
+}
+
+impl Alloc<'_> {
+    fn is_used(&self, block: u32) -> bool {
+        self.bitmap[block as usize / 8] & (1 << (block % 8)) != 0
+    }
+
+    fn set(&mut self, block: u32, used: bool) {
+        if used {
+            self.bitmap[block as usize / 8] |= 1 << (block % 8);
+        } else {
+            self.bitmap[block as usize / 8] &= !(1 << (block % 8));
+        }
+    }
+
+    /// Mark a block found by a traversal.
+    fn mark(&mut self, block: u32) -> Result<(), Error> {
+        if block >= self.count {
+            return Err(Error::Corrupt);
+        }
+        self.set(block, true);
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        self.bitmap.fill(0);
+    }
+
+    fn alloc(&mut self) -> StepResult<u32> {
+        for i in 0..self.count {
+            let block = (self.next + i) % self.count;
+            if !self.is_used(block) {
+                if self.attempt_len == self.attempt.len() {
+                    return Err(Flow::Fail(Error::NoMemory));
+                }
+                self.set(block, true);
+                self.attempt[self.attempt_len] = block;
+                self.attempt_len += 1;
+                self.next = (block + 1) % self.count;
+                return Ok(block);
+            }
+        }
+
+        if self.rebuilt {
+            Err(Flow::Fail(Error::NoSpace))
+        } else {
+            Err(Flow::Rebuild)
+        }
+    }
+
+    /// The current attempt completed, keep its allocations.
+    fn keep(&mut self) {
+        if self.attempt_len > 0 {
+            self.rebuilt = false;
+        }
+        self.attempt_len = 0;
+    }
+
+    /// The current attempt failed, free its allocations.
+    fn rewind(&mut self) {
+        for i in 0..self.attempt_len {
+            let block = self.attempt[i];
+            self.set(block, false);
+        }
+        self.attempt_len = 0;
+    }
+}
+
+/// A walk over every block in use.
+#[derive(Clone, Copy)]
+struct Traverse {
+    /// The next metadata pair, null once all directories are visited.
+    pair: [u32; 2],
+    /// The metadata pair being visited.
+    mdir: Option<MDir>,
+    id: u16,
+    /// The skip-list being walked: a block and its index.
+    ctz: Option<(u32, u32)>,
+    /// The next skip-list of an open file to visit, once all directories are
+    /// visited. Each file has two: the new one and the one being rewritten.
+    file: usize,
+    /// Number of metadata pairs visited, to detect loops.
+    visited: u32,
+    /// Collect the global state, when mounting.
+    gstate: bool,
+}
+
+impl Traverse {
+    fn new(gstate: bool) -> Self {
+        Self {
+            pair: ROOT_PAIR,
+            mdir: None,
+            id: 0,
+            ctz: None,
+            file: 0,
+            visited: 0,
+            gstate,
+        }
+    }
+}
+
+/// The result of looking up a path.
+#[derive(Clone, Copy)]
+struct Resolved {
+    /// The path names the root directory.
+    root: bool,
+    /// The metadata pair holding the entry, or where it would be created.
+    pair: [u32; 2],
+    /// The id of the entry, or where it would be created.
+    id: u16,
+    /// `TYPE_REG` or `TYPE_DIR`, `None` if the entry doesn't exist.
+    kind: Option<u16>,
+    /// The last path component, in the buffer.
+    name: (usize, usize),
+    /// `pair` is not the first metadata pair of the directory.
+    continued: bool,
+}
+
+/// A path lookup in progress.
+#[derive(Clone, Copy)]
+struct Lookup {
+    path_len: usize,
+    /// Start of the remaining path.
+    pos: usize,
+    /// The metadata pair being searched.
+    pair: [u32; 2],
+    /// `pair` is not the first metadata pair of the directory.
+    split: bool,
+    /// Where a missing entry would be created: before the first entry with
+    /// a greater name, so the entries of a directory stay sorted.
+    insert: Option<([u32; 2], u16, bool)>,
+    visited: u32,
+    resolved: Option<Resolved>,
+}
+
+impl Lookup {
+    fn new(path_len: usize) -> Self {
+        Self {
+            path_len,
+            pos: 0,
+            pair: ROOT_PAIR,
+            split: false,
+            insert: None,
+            visited: 0,
+            resolved: None,
+        }
+    }
+}
+
+/// Find the next component of `path` at or after `pos`.
+fn next_component(path: &[u8], pos: usize) -> Result<Option<(usize, usize)>, Error> {
+    let mut start = pos;
+    loop {
+        while start < path.len() && path[start] == b'/' {
+            start += 1;
+        }
+        if start == path.len() {
+            return Ok(None);
+        }
+        let end = path[start..]
+            .iter()
+            .position(|c| *c == b'/')
+            .map_or(path.len(), |i| start + i);
+
+        match &path[start..end] {
+            b"." => start = end,
+            b".." => return Err(Error::Unsupported),
+            name if name.len() > NAME_MAX as usize => return Err(Error::NameTooLong),
+            _ => return Ok(Some((start, end))),
+        }
+    }
+}
+
+/// Run a path lookup. Progress through the directories is kept in `lookup`.
+fn lookup(
+    cache: &mut Cache,
+    path: &[u8],
+    lookup: &mut Lookup,
+    config: &Config,
+) -> StepResult<Resolved> {
+    if let Some(resolved) = lookup.resolved {
+        return Ok(resolved);
+    }
+    let path = &path[..lookup.path_len];
+
+    loop {
+        let Some((start, end)) = next_component(path, lookup.pos)? else {
+            let resolved = Resolved {
+                root: true,
+                pair: ROOT_PAIR,
+                id: 0,
+                kind: Some(TYPE_DIR),
+                name: (0, 0),
+                continued: false,
+            };
+            lookup.resolved = Some(resolved);
+            return Ok(resolved);
+        };
+        let last = next_component(path, end)?.is_none();
+        let name = &path[start..end];
+
+        lookup.visited += 1;
+        if lookup.visited > config.block_count {
+            return Err(Flow::Fail(Error::Corrupt));
+        }
+
+        let mdir = fetch(cache, lookup.pair, config.prog_size)?;
+        let data = cache.get(mdir.pair[0])?;
+
+        let mut found = None;
+        let mut insert = None;
+        for id in 0..mdir.count {
+            let Some((tag, off)) = find_tag(data, &mdir, MASK_NAME_ID, Tag::new(TYPE_NAME, id, 0))
+            else {
+                continue;
+            };
+            if tag.type3() != TYPE_REG && tag.type3() != TYPE_DIR {
+                continue;
+            }
+            match data[off..off + tag.size() as usize].cmp(name) {
+                core::cmp::Ordering::Equal => {
+                    found = Some((id, tag.type3()));
+                    break;
+                }
+                core::cmp::Ordering::Greater if insert.is_none() => insert = Some(id),
+                _ => {}
+            }
+        }
+        if let (None, Some(id)) = (lookup.insert, insert) {
+            lookup.insert = Some((mdir.pair, id, lookup.split));
+        }
+
+        match found {
+            Some((id, kind)) if last => {
+                let resolved = Resolved {
+                    root: false,
+                    pair: mdir.pair,
+                    id,
+                    kind: Some(kind),
+                    name: (start, end),
+                    continued: lookup.split,
+                };
+                lookup.resolved = Some(resolved);
+                return Ok(resolved);
+            }
+            Some((id, TYPE_DIR)) => match get_struct(data, &mdir, id)? {
+                Struct::Dir(pair) => {
+                    lookup.pair = pair;
+                    lookup.split = false;
+                    lookup.insert = None;
+                    lookup.pos = end;
+                }
+                _ => return Err(Flow::Fail(Error::Corrupt)),
+            },
+            Some(_) => return Err(Flow::Fail(Error::NotDir)),
+            None if mdir.split => {
+                lookup.pair = mdir.tail;
+                lookup.split = true;
+            }
+            None if last => {
+                let (pair, id, continued) =
+                    lookup
+                        .insert
+                        .unwrap_or((mdir.pair, mdir.count, lookup.split));
+                let resolved = Resolved {
+                    root: false,
+                    pair,
+                    id,
+                    kind: None,
+                    name: (start, end),
+                    continued,
+                };
+                lookup.resolved = Some(resolved);
+                return Ok(resolved);
+            }
+            None => return Err(Flow::Fail(Error::NotFound)),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum RemoveStage {
+    Lookup,
+    /// Remove `child`, a deleted directory or an emptied metadata pair of a
+    /// directory, from the list of metadata pairs: find the pair pointing at
+    /// it, starting from `pair`, and point it to the tail of `child`. Then
+    /// do the same for `next`.
+    Unlink {
+        child: [u32; 2],
+        next: Option<[u32; 2]>,
+        pair: [u32; 2],
+    },
+}
+
+#[derive(Clone, Copy)]
+enum Op {
+    Format,
+    Mount {
+        checked: bool,
+    },
+    Open {
+        flags: OpenFlags,
+        lookup: Lookup,
+    },
+    Read {
+        handle: usize,
+        len: usize,
+        done: usize,
+        cursor: Option<Cursor>,
+    },
+    Write {
+        handle: usize,
+        len: usize,
+        done: usize,
+        extend: Extend,
+    },
+    Sync {
+        handle: usize,
+        close: bool,
+        extend: Extend,
+    },
+    Remove {
+        lookup: Lookup,
+        stage: RemoveStage,
+    },
+    Mkdir {
+        lookup: Lookup,
+        /// The last metadata pair of the parent directory, once found.
+        pred: Option<[u32; 2]>,
+        /// The new metadata pair, once written.
+        pair: Option<[u32; 2]>,
+        /// `pred` points to the new pair, when it isn't where the entry goes.
+        linked: bool,
+    },
+    ReadDir {
+        lookup: Lookup,
+        index: u32,
+        /// The metadata pair being listed and the number of entries in
+        /// earlier pairs of the directory.
+        scan: Option<([u32; 2], u32)>,
+    },
+    Stat {
+        lookup: Lookup,
+    },
+}
+
+/// A littlefs file system.
+pub struct LittleFs<'a> {
+    config: Config,
+    cache: Cache<'a>,
+    alloc: Alloc<'a>,
+    buffer: &'a mut [u8],
+    files: [File; MAX_OPEN_FILES],
+    op: Option<Op>,
+    /// A traversal rebuilding the allocator, which pauses `op`.
+    traverse: Option<Traverse>,
+    result: Option<Result<Output, Error>>,
+    mounted: bool,
+    /// The global state is not empty, so the image has an unfinished
+    /// operation we can't complete.
+    read_only: bool,
+    gstate: [u8; GSTATE_LEN],
+}
+
+impl<'a> LittleFs<'a> {
+    /// Create a file system. `cache` must hold `CACHE_BLOCKS` blocks and
+    /// `bitmap` a bit per block. `buffer` holds paths and file data.
+    ///
+    /// Panics if the configuration is invalid.
+    pub fn new(
+        config: Config,
+        cache: &'a mut [u8],
+        bitmap: &'a mut [u8],
+        buffer: &'a mut [u8],
+    ) -> Self {
+        assert!(config.prog_size > 0 && config.prog_size <= 512);
+        assert!(config.block_size >= 128 && config.block_size.is_multiple_of(config.prog_size));
+        assert!(config.block_count >= 2);
+        assert!(cache.len() >= crate::CACHE_BLOCKS * config.block_size);
+        assert!(bitmap.len() * 8 >= config.block_count as usize);
+
+        Self {
+            config,
+            cache: Cache::new(cache, config.block_size),
+            alloc: Alloc {
+                bitmap,
+                count: config.block_count,
+                next: 0,
+                attempt: [BLOCK_NULL; 4],
+                attempt_len: 0,
+                rebuilt: false,
+            },
+            buffer,
+            files: [CLOSED_FILE; MAX_OPEN_FILES],
+            op: None,
+            traverse: None,
+            result: None,
+            mounted: false,
+            read_only: false,
+            gstate: [0; GSTATE_LEN],
+        }
+    }
+
+    /// The buffer used to pass paths and file data.
+    pub fn buffer(&self) -> &[u8] {
+        self.buffer
+    }
+
+    pub fn buffer_mut(&mut self) -> &mut [u8] {
+        self.buffer
+    }
+
+    pub fn is_mounted(&self) -> bool {
+        self.mounted
+    }
+
+    /// Returns true if an operation is in progress.
+    pub fn is_busy(&self) -> bool {
+        self.op.is_some() || self.result.is_some()
+    }
+
+    fn start(&mut self, op: Op) -> Result<(), Error> {
+        if self.is_busy() {
+            return Err(Error::Busy);
+        }
+        if !self.mounted && !matches!(op, Op::Format | Op::Mount { .. }) {
+            return Err(Error::NotMounted);
+        }
+        self.op = Some(op);
+        Ok(())
+    }
+
+    fn check_path(&self, path_len: usize) -> Result<Lookup, Error> {
+        if path_len > self.buffer.len() {
+            return Err(Error::Invalid);
+        }
+        Ok(Lookup::new(path_len))
+    }
+
+    fn file(&self, handle: FileHandle) -> Result<&File, Error> {
+        match self.files.get(handle.0 as usize) {
+            Some(file) if file.open => Ok(file),
+            _ => Err(Error::BadHandle),
+        }
+    }
+
+    /// Write an empty file system, replacing the contents of the flash.
+    /// All files must be closed.
+    pub fn format(&mut self) -> Result<(), Error> {
+        if self.files.iter().any(|f| f.open) {
+            return Err(Error::Busy);
+        }
+        self.start(Op::Format)?;
+        self.mounted = false;
+        Ok(())
+    }
+
+    /// Mount the file system.
+    pub fn mount(&mut self) -> Result<(), Error> {
+        self.start(Op::Mount { checked: false })
+    }
+
+    /// Open the file at the path in `buffer()[..path_len]`. Returns
+    /// `Output::File`.
+    pub fn open(&mut self, path_len: usize, flags: OpenFlags) -> Result<(), Error> {
+        if !flags.is_valid() || flags.0 & 0x3 == 0 {
+            return Err(Error::Invalid);
+        }
+        if self.files.iter().all(|f| f.open) {
+            return Err(Error::NoMemory);
+        }
+        let lookup = self.check_path(path_len)?;
+        self.start(Op::Open { flags, lookup })
+    }
+
+    /// Read up to `len` bytes into `buffer()` from the current position.
+    /// Returns `Output::Length`.
+    pub fn read(&mut self, handle: FileHandle, len: usize) -> Result<(), Error> {
+        if !self.file(handle)?.flags.contains(OpenFlags::READ) {
+            return Err(Error::Invalid);
+        }
+        let len = len.min(self.buffer.len());
+        self.start(Op::Read {
+            handle: handle.0 as usize,
+            len,
+            done: 0,
+            cursor: None,
+        })
+    }
+
+    /// Write `buffer()[..len]` at the current position, or at the end of
+    /// the file if it was opened with `APPEND`. Returns `Output::Length`.
+    pub fn write(&mut self, handle: FileHandle, len: usize) -> Result<(), Error> {
+        let file = self.file(handle)?;
+        if !file.flags.contains(OpenFlags::WRITE) || len > self.buffer.len() {
+            return Err(Error::Invalid);
+        }
+        self.start(Op::Write {
+            handle: handle.0 as usize,
+            len,
+            done: 0,
+            extend: NEW_EXTEND,
+        })
+    }
+
+    /// Commit the data written to the file.
+    pub fn sync(&mut self, handle: FileHandle) -> Result<(), Error> {
+        self.file(handle)?;
+        self.start(Op::Sync {
+            handle: handle.0 as usize,
+            close: false,
+            extend: NEW_EXTEND,
+        })
+    }
+
+    /// Commit the data written to the file and close it.
+    pub fn close(&mut self, handle: FileHandle) -> Result<(), Error> {
+        self.file(handle)?;
+        self.start(Op::Sync {
+            handle: handle.0 as usize,
+            close: true,
+            extend: NEW_EXTEND,
+        })
+    }
+
+    /// Close a file without committing data written since the last sync.
+    pub fn release(&mut self, handle: FileHandle) -> Result<(), Error> {
+        self.file(handle)?;
+        let busy = match self.op {
+            Some(Op::Read { handle: h, .. })
+            | Some(Op::Write { handle: h, .. })
+            | Some(Op::Sync { handle: h, .. }) => h == handle.0 as usize,
+            _ => false,
+        };
+        if busy {
+            return Err(Error::Busy);
+        }
+        self.files[handle.0 as usize] = CLOSED_FILE;
+        Ok(())
+    }
+
+    /// Move the position of the file, which can't be past the end.
+    pub fn seek(&mut self, handle: FileHandle, pos: u32) -> Result<(), Error> {
+        let file = self.file(handle)?;
+        if pos > file.total_size() {
+            return Err(Error::Invalid);
+        }
+        self.files[handle.0 as usize].pos = pos;
+        Ok(())
+    }
+
+    /// The current position in the file.
+    pub fn tell(&self, handle: FileHandle) -> Result<u32, Error> {
+        Ok(self.file(handle)?.pos)
+    }
+
+    /// The size of the file, including data not yet committed.
+    pub fn size(&self, handle: FileHandle) -> Result<u32, Error> {
+        Ok(self.file(handle)?.total_size())
+    }
+
+    /// Remove the file or empty directory at the path in
+    /// `buffer()[..path_len]`.
+    pub fn remove(&mut self, path_len: usize) -> Result<(), Error> {
+        let lookup = self.check_path(path_len)?;
+        self.start(Op::Remove {
+            lookup,
+            stage: RemoveStage::Lookup,
+        })
+    }
+
+    /// Create a directory at the path in `buffer()[..path_len]`.
+    pub fn mkdir(&mut self, path_len: usize) -> Result<(), Error> {
+        let lookup = self.check_path(path_len)?;
+        self.start(Op::Mkdir {
+            lookup,
+            pred: None,
+            pair: None,
+            linked: false,
+        })
+    }
+
+    /// Find entry `index` of the directory at the path in
+    /// `buffer()[..path_len]`. Returns `Output::Entry`, with the name placed
+    /// at the start of `buffer()`, or `Error::NotFound` past the last entry.
+    pub fn read_dir(&mut self, path_len: usize, index: u32) -> Result<(), Error> {
+        let lookup = self.check_path(path_len)?;
+        self.start(Op::ReadDir {
+            lookup,
+            index,
+            scan: None,
+        })
+    }
+
+    /// Look up the path in `buffer()[..path_len]`. Returns `Output::Entry`.
+    pub fn stat(&mut self, path_len: usize) -> Result<(), Error> {
+        let lookup = self.check_path(path_len)?;
+        self.start(Op::Stat { lookup })
+    }
+
+    /// The buffer for the current `Request::Read` or `Request::Write`.
+    pub fn io_buffer(&mut self) -> &mut [u8] {
+        self.cache.io_buffer()
+    }
+
+    /// The requested IO completed.
+    pub fn io_complete(&mut self) {
+        self.cache.io_done();
+    }
+
+    /// The requested IO failed. The current operation fails with
+    /// `Error::Io`, and changes not yet written are dropped.
+    pub fn io_failed(&mut self) {
+        self.cache.clear();
+        self.traverse = None;
+        if self.op.take().is_some() {
+            self.result = Some(Err(Error::Io));
+        }
+    }
+
+    /// Run the current operation until it needs block IO or completes.
+    pub fn poll(&mut self) -> Request {
+        loop {
+            if let Some(request) = self.cache.request() {
+                return request;
+            }
+            if self.cache.flush() {
+                continue;
+            }
+            if let Some(result) = self.result.take() {
+                return Request::Done(result);
+            }
+            let Some(mut op) = self.op else {
+                return Request::Idle;
+            };
+
+            self.cache.begin_attempt();
+            let step = match self.traverse {
+                Some(mut traverse) => {
+                    let step = self.traverse_step(&mut traverse);
+                    self.traverse = Some(traverse);
+                    step.map(|()| {
+                        self.traverse = None;
+                        self.alloc.rebuilt = true;
+                        None
+                    })
+                }
+                None => {
+                    let step = self.step(&mut op);
+                    self.op = Some(op);
+                    step
+                }
+            };
+
+            match step {
+                Ok(None) => self.alloc.keep(),
+                Ok(Some(output)) => {
+                    self.alloc.keep();
+                    self.op = None;
+                    self.result = Some(Ok(output));
+                }
+                Err(flow) => {
+                    self.cache.discard();
+                    self.alloc.rewind();
+                    let error = match flow {
+                        Flow::Miss(block) if block < self.config.block_count => {
+                            match self.cache.load(block) {
+                                Ok(()) => continue,
+                                Err(e) => e,
+                            }
+                        }
+                        Flow::Miss(_) => Error::Corrupt,
+                        Flow::Rebuild if self.traverse.is_none() => {
+                            self.alloc.clear();
+                            self.traverse = Some(Traverse::new(false));
+                            continue;
+                        }
+                        Flow::Rebuild => Error::NoSpace,
+                        Flow::Fail(e) => e,
+                    };
+                    self.traverse = None;
+                    self.op = None;
+                    self.result = Some(Err(error));
+                }
+            }
+        }
+    }
+
+    fn step(&mut self, op: &mut Op) -> StepResult<Option<Output>> {
+        match op {
+            Op::Format => self.format_step(),
+            Op::Mount { checked } => self.mount_step(checked),
+            Op::Open { flags, lookup } => self.open_step(*flags, lookup),
+            Op::Read {
+                handle,
+                len,
+                done,
+                cursor,
+            } => self.read_step(*handle, *len, done, cursor),
+            Op::Write {
+                handle,
+                len,
+                done,
+                extend,
+            } => self.write_step(*handle, *len, done, extend),
+            Op::Sync {
+                handle,
+                close,
+                extend,
+            } => self.sync_step(*handle, *close, extend),
+            Op::Remove { lookup, stage } => self.remove_step(lookup, stage),
+            Op::Mkdir {
+                lookup,
+                pred,
+                pair,
+                linked,
+            } => self.mkdir_step(lookup, pred, pair, linked),
+            Op::ReadDir {
+                lookup,
+                index,
+                scan,
+            } => self.read_dir_step(lookup, *index, scan),
+            Op::Stat { lookup } => self.stat_step(lookup),
+        }
+    }
+
+    fn check_writable(&self) -> StepResult<()> {
+        if self.read_only {
+            Err(Flow::Fail(Error::ReadOnly))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn format_step(&mut self) -> StepResult<Option<Output>> {
+        let superblock = encode_superblock(self.config.block_size as u32, self.config.block_count);
+        mdir::create(
+            &mut self.cache,
+            ROOT_PAIR,
+            &[
+                Attr::new(TYPE_SUPERBLOCK, 0, MAGIC),
+                Attr::new(TYPE_INLINESTRUCT, 0, &superblock),
+            ],
+            self.config.prog_size,
+        )?;
+        Ok(Some(Output::None))
+    }
+
+    fn mount_step(&mut self, checked: &mut bool) -> StepResult<Option<Output>> {
+        if *checked {
+            // The traversal collecting the used blocks has finished.
+            self.read_only = self.gstate.iter().any(|b| *b != 0);
+            self.mounted = true;
+            return Ok(Some(Output::None));
+        }
+
+        let root = fetch(&mut self.cache, ROOT_PAIR, self.config.prog_size)?;
+        let data = self.cache.get(root.pair[0])?;
+
+        let (tag, off) = find_tag(data, &root, MASK_TYPE3_ID, Tag::new(TYPE_SUPERBLOCK, 0, 0))
+            .ok_or(Error::Corrupt)?;
+        if data[off..off + tag.size() as usize] != MAGIC[..] {
+            return Err(Flow::Fail(Error::Corrupt));
+        }
+
+        let (tag, off) = find_tag(
+            data,
+            &root,
+            MASK_TYPE3_ID,
+            Tag::new(TYPE_INLINESTRUCT, 0, 0),
+        )
+        .ok_or(Error::Corrupt)?;
+        if (tag.size() as usize) < SUPERBLOCK_LEN {
+            return Err(Flow::Fail(Error::Corrupt));
+        }
+        let version = read_le32(data, off);
+        if version >> 16 != DISK_VERSION >> 16 || version & 0xffff > DISK_VERSION_MINOR_MAX {
+            return Err(Flow::Fail(Error::UnsupportedVersion));
+        }
+        if read_le32(data, off + 4) != self.config.block_size as u32
+            || read_le32(data, off + 8) != self.config.block_count
+        {
+            return Err(Flow::Fail(Error::Corrupt));
+        }
+
+        *checked = true;
+        self.gstate = [0; GSTATE_LEN];
+        self.alloc.clear();
+        self.traverse = Some(Traverse::new(true));
+        Ok(None)
+    }
+
+    /// Visit every block in use, marking it in the allocator.
+    fn traverse_step(&mut self, t: &mut Traverse) -> StepResult<()> {
+        let bs = self.config.block_size;
+        loop {
+            if let Some((block, index)) = t.ctz {
+                self.alloc.mark(block)?;
+                if index == 0 {
+                    t.ctz = None;
+                    continue;
+                }
+                // Odd blocks only point to the previous block, even blocks
+                // can skip two at once.
+                let data = self.cache.get(block)?;
+                let count = 2 - (index & 1);
+                if count == 2 {
+                    self.alloc.mark(read_le32(data, 0))?;
+                }
+                let next = read_le32(data, 4 * (count as usize - 1));
+                t.ctz = Some((next, index - count));
+                continue;
+            }
+
+            if let Some(mdir) = t.mdir {
+                if t.id < mdir.count {
+                    let data = self.cache.get(mdir.pair[0])?;
+                    if let Ok(Struct::Ctz(head, size)) = get_struct(data, &mdir, t.id) {
+                        if size > 0 {
+                            t.ctz = Some((head, ctz_index(bs, size - 1).0));
+                        }
+                    }
+                    t.id += 1;
+                } else {
+                    t.pair = if mdir.tail[0] == BLOCK_NULL {
+                        [BLOCK_NULL; 2]
+                    } else {
+                        mdir.tail
+                    };
+                    t.mdir = None;
+                }
+                continue;
+            }
+
+            if !pair_is_null(t.pair) {
+                t.visited += 1;
+                if t.visited > self.config.block_count {
+                    return Err(Flow::Fail(Error::Corrupt));
+                }
+                let mdir = fetch(&mut self.cache, t.pair, self.config.prog_size)?;
+                self.alloc.mark(mdir.pair[0])?;
+                self.alloc.mark(mdir.pair[1])?;
+                if t.gstate {
+                    let delta = mdir::gdelta(self.cache.get(mdir.pair[0])?, &mdir);
+                    for (g, d) in self.gstate.iter_mut().zip(delta) {
+                        *g ^= d;
+                    }
+                }
+                t.mdir = Some(mdir);
+                t.id = 0;
+                continue;
+            }
+
+            // Data written to open files but not yet committed, and the data
+            // they are rewriting.
+            let lists = self.files.iter().flat_map(|f| {
+                let new = f.open && f.ctz && f.writing && f.size > 0;
+                [new.then_some((f.head, f.size)), f.orig]
+            });
+            match lists
+                .enumerate()
+                .skip(t.file)
+                .find_map(|(i, list)| Some((i, list?)))
+            {
+                Some((i, (head, size))) => {
+                    t.ctz = Some((head, ctz_index(bs, size - 1).0));
+                    t.file = i + 1;
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn open_step(&mut self, flags: OpenFlags, l: &mut Lookup) -> StepResult<Option<Output>> {
+        let r = lookup(&mut self.cache, self.buffer, l, &self.config)?;
+        let writing = flags.contains(OpenFlags::WRITE);
+        if r.root || r.kind == Some(TYPE_DIR) {
+            return Err(Flow::Fail(Error::IsDir));
+        }
+
+        let mut file = File {
+            open: true,
+            flags,
+            pair: r.pair,
+            id: r.id,
+            ..CLOSED_FILE
+        };
+        let mut mdir = fetch(&mut self.cache, r.pair, self.config.prog_size)?;
+
+        match r.kind {
+            Some(_) => {
+                if flags.contains(OpenFlags::CREATE | OpenFlags::EXCL) {
+                    return Err(Flow::Fail(Error::Exists));
+                }
+                let data = self.cache.get(mdir.pair[0])?;
+                match get_struct(data, &mdir, r.id)? {
+                    Struct::Inline(size, _) => file.size = size,
+                    Struct::Ctz(head, size) => {
+                        file.ctz = true;
+                        file.head = head;
+                        file.size = size;
+                    }
+                    Struct::Dir(_) => return Err(Flow::Fail(Error::Corrupt)),
+                }
+
+                if writing && flags.contains(OpenFlags::TRUNC) && (file.ctz || file.size > 0) {
+                    self.check_writable()?;
+                    (file.pair, file.id) = commit(
+                        &mut self.cache,
+                        &mut self.alloc,
+                        &mut self.files,
+                        &mut mdir,
+                        r.id,
+                        &[Attr::new(TYPE_INLINESTRUCT, r.id, &[])],
+                        self.config.prog_size,
+                    )?;
+                    file.ctz = false;
+                    file.head = BLOCK_NULL;
+                    file.size = 0;
+                }
+            }
+            None => {
+                if !writing || !flags.contains(OpenFlags::CREATE) {
+                    return Err(Flow::Fail(Error::NotFound));
+                }
+                self.check_writable()?;
+                let name = &self.buffer[r.name.0..r.name.1];
+                (file.pair, file.id) = commit(
+                    &mut self.cache,
+                    &mut self.alloc,
+                    &mut self.files,
+                    &mut mdir,
+                    r.id,
+                    &[
+                        Attr::new(TYPE_CREATE, r.id, &[]),
+                        Attr::new(TYPE_REG, r.id, name),
+                        Attr::new(TYPE_INLINESTRUCT, r.id, &[]),
+                    ],
+                    self.config.prog_size,
+                )?;
+                shift_files(&mut self.files, file.pair, file.id, true);
+            }
+        }
+
+        let handle = self
+            .files
+            .iter()
+            .position(|f| !f.open)
+            .ok_or(Error::NoMemory)?;
+        self.files[handle] = file;
+        Ok(Some(Output::File(FileHandle(handle as u8))))
+    }
+
+    fn read_step(
+        &mut self,
+        handle: usize,
+        len: usize,
+        done: &mut usize,
+        cursor: &mut Option<Cursor>,
+    ) -> StepResult<Option<Output>> {
+        let bs = self.config.block_size;
+        let file = &mut self.files[handle];
+        if file.removed {
+            return Err(Flow::Fail(Error::NotFound));
+        }
+
+        while *done < len && file.pos < file.total_size() {
+            let n = (len - *done).min((file.total_size() - file.pos) as usize);
+
+            if !file.ctz {
+                let mdir = fetch(&mut self.cache, file.pair, self.config.prog_size)?;
+                let data = self.cache.get(mdir.pair[0])?;
+                let Struct::Inline(_, off) = get_struct(data, &mdir, file.id)? else {
+                    return Err(Flow::Fail(Error::Corrupt));
+                };
+                let start = off + file.pos as usize;
+                self.buffer[*done..*done + n].copy_from_slice(&data[start..start + n]);
+                *done += n;
+                file.pos += n as u32;
+                continue;
+            }
+
+            // Data past the end of a skip-list being rewritten is still in
+            // the original one.
+            let (list, n) = match file.orig {
+                Some(orig) if file.pos >= file.size => (orig, n),
+                _ => (
+                    (file.head, file.size),
+                    n.min((file.size - file.pos) as usize),
+                ),
+            };
+            let (index, off) = ctz_index(bs, file.pos);
+            let block = find_block(&mut self.cache, bs, list, index, cursor)?;
+            let data = self.cache.get(block)?;
+            let n = n.min(bs - off);
+            self.buffer[*done..*done + n].copy_from_slice(&data[off..off + n]);
+            *done += n;
+            file.pos += n as u32;
+        }
+
+        Ok(Some(Output::Length(*done)))
+    }
+
+    /// Get the block to write to at the end of the skip-list of a file, and
+    /// the offset of the end in it. This is the head if it has room and was
+    /// written since the last commit, or a new block.
+    fn extend(&mut self, handle: usize, extend: &mut Extend) -> StepResult<(u32, usize)> {
+        let bs = self.config.block_size;
+        let file = &self.files[handle];
+        let (index, off) = ctz_index(bs, file.size);
+        if file.size == 0 {
+            let block = self.alloc.alloc()?;
+            self.cache.fresh(block)?;
+            return Ok((block, off));
+        }
+        if index == ctz_index(bs, file.size - 1).0 {
+            self.cache.get(file.head)?;
+            if file.writing {
+                return Ok((file.head, off));
+            }
+            // The last block was committed, copy it rather than appending to
+            // it.
+            let block = self.alloc.alloc()?;
+            self.cache.fresh(block)?;
+            let (old, new) = self.cache.get_pair(file.head, block)?;
+            new[..off].copy_from_slice(&old[..off]);
+            return Ok((block, off));
+        }
+
+        // Start a new block, pointing back to block `index - 2^i` for each of
+        // its pointers. Each pointer is found in the block the previous
+        // pointer refers to.
+        let count = ctz_pointers(index);
+        let pointers = &mut extend.pointers;
+        if extend.pointers_len == 0 {
+            pointers[0] = file.head;
+            extend.pointers_len = 1;
+        }
+        while extend.pointers_len < count {
+            let i = extend.pointers_len - 1;
+            pointers[i + 1] = read_le32(self.cache.get(pointers[i])?, 4 * i);
+            extend.pointers_len += 1;
+        }
+
+        let block = self.alloc.alloc()?;
+        let data = self.cache.fresh(block)?;
+        for (i, pointer) in pointers[..count].iter().enumerate() {
+            write_le32(data, 4 * i, *pointer);
+        }
+        Ok((block, off))
+    }
+
+    /// Copy the data being rewritten from the end of the new skip-list of a
+    /// file up to `end`, at most a block at a time.
+    fn copy_step(&mut self, handle: usize, extend: &mut Extend, end: u32) -> StepResult<()> {
+        let bs = self.config.block_size;
+        let file = self.files[handle];
+        let Some(orig) = file.orig else {
+            return Ok(());
+        };
+        let (index, off) = ctz_index(bs, file.size);
+        let src = find_block(&mut self.cache, bs, orig, index, &mut extend.cursor)?;
+        // Use the source block now, so the blocks used to extend the file
+        // don't evict it.
+        self.cache.get(src)?;
+        let (block, _) = self.extend(handle, extend)?;
+
+        let n = (bs - off).min((end - file.size) as usize);
+        let (old, new) = self.cache.get_pair(src, block)?;
+        new[off..off + n].copy_from_slice(&old[off..off + n]);
+
+        extend.pointers_len = 0;
+        self.files[handle].extended(block, n);
+        Ok(())
+    }
+
+    fn write_step(
+        &mut self,
+        handle: usize,
+        len: usize,
+        done: &mut usize,
+        extend: &mut Extend,
+    ) -> StepResult<Option<Output>> {
+        let bs = self.config.block_size;
+        let prog_size = self.config.prog_size;
+        if self.files[handle].removed {
+            return Err(Flow::Fail(Error::NotFound));
+        }
+        self.check_writable()?;
+        let file = &mut self.files[handle];
+        if file.flags.contains(OpenFlags::APPEND) {
+            file.pos = file.total_size();
+        }
+
+        if *done == len {
+            return Ok(Some(Output::Length(len)));
+        }
+        if file
+            .pos
+            .checked_add((len - *done) as u32)
+            .is_none_or(|s| s > 0x7fff_ffff)
+        {
+            return Err(Flow::Fail(Error::NoSpace));
+        }
+
+        if !file.ctz && file.size == 0 {
+            file.ctz = true;
+            file.head = BLOCK_NULL;
+            file.writing = false;
+        }
+
+        if !file.ctz {
+            // Move inline data written by another implementation into the
+            // first block of a skip-list.
+            let mdir = fetch(&mut self.cache, file.pair, prog_size)?;
+            let Struct::Inline(size, off) =
+                get_struct(self.cache.get(mdir.pair[0])?, &mdir, file.id)?
+            else {
+                return Err(Flow::Fail(Error::Corrupt));
+            };
+            let block = self.alloc.alloc()?;
+            self.cache.fresh(block)?;
+            let (old, new) = self.cache.get_pair(mdir.pair[0], block)?;
+            new[..size as usize].copy_from_slice(&old[off..off + size as usize]);
+
+            file.ctz = true;
+            file.head = block;
+            file.writing = true;
+            file.dirty = true;
+            return Ok(None);
+        }
+
+        if file.pos != file.size {
+            match file.orig {
+                // Copy the data up to the position, or all of it before
+                // rewriting from an earlier position.
+                Some((_, size)) => {
+                    let end = if file.pos > file.size { file.pos } else { size };
+                    self.copy_step(handle, extend, end)?;
+                }
+                // Rewrite from the position: keep the blocks before it, and
+                // copy the rest of the data after the new data.
+                None => {
+                    let head = match file.pos {
+                        0 => BLOCK_NULL,
+                        pos => {
+                            let index = ctz_index(bs, pos - 1).0;
+                            let list = (file.head, file.size);
+                            find_block(&mut self.cache, bs, list, index, &mut extend.cursor)?
+                        }
+                    };
+                    let file = &mut self.files[handle];
+                    file.orig = Some((file.head, file.size));
+                    file.head = head;
+                    file.size = file.pos;
+                    file.writing = false;
+                    file.dirty = true;
+                }
+            }
+            return Ok(None);
+        }
+
+        let (block, off) = self.extend(handle, extend)?;
+        let n = (len - *done).min(bs - off);
+        let data = self.cache.get_mut(block)?;
+        data[off..off + n].copy_from_slice(&self.buffer[*done..*done + n]);
+
+        extend.pointers_len = 0;
+        *done += n;
+        let file = &mut self.files[handle];
+        file.extended(block, n);
+        file.pos = file.size;
+        Ok(None)
+    }
+
+    fn sync_step(
+        &mut self,
+        handle: usize,
+        close: bool,
+        extend: &mut Extend,
+    ) -> StepResult<Option<Output>> {
+        let file = self.files[handle];
+        if file.dirty && !file.removed {
+            self.check_writable()?;
+            if let Some((_, size)) = file.orig {
+                self.copy_step(handle, extend, size)?;
+                return Ok(None);
+            }
+
+            let mut mdir = fetch(&mut self.cache, file.pair, self.config.prog_size)?;
+            let ctz = encode_pair([file.head, file.size]);
+            let attr = if file.ctz && file.size > 0 {
+                Attr::new(TYPE_CTZSTRUCT, file.id, &ctz)
+            } else {
+                Attr::new(TYPE_INLINESTRUCT, file.id, &[])
+            };
+            commit(
+                &mut self.cache,
+                &mut self.alloc,
+                &mut self.files,
+                &mut mdir,
+                file.id,
+                &[attr],
+                self.config.prog_size,
+            )?;
+
+            let file = &mut self.files[handle];
+            file.dirty = false;
+            file.writing = false;
+        }
+
+        if close {
+            self.files[handle] = CLOSED_FILE;
+        } else if file.removed {
+            return Err(Flow::Fail(Error::NotFound));
+        }
+        Ok(Some(Output::None))
+    }
+
+    fn remove_step(
+        &mut self,
+        l: &mut Lookup,
+        stage: &mut RemoveStage,
+    ) -> StepResult<Option<Output>> {
+        let prog_size = self.config.prog_size;
+        self.check_writable()?;
+
+        if let RemoveStage::Unlink { child, next, pair } = stage {
+            while !pair_is_null(*pair) {
+                let mut mdir = fetch(&mut self.cache, *pair, prog_size)?;
+                if pair_eq(mdir.tail, *child) {
+                    let child = fetch(&mut self.cache, *child, prog_size)?;
+                    let tail_data = encode_pair(child.tail);
+                    commit(
+                        &mut self.cache,
+                        &mut self.alloc,
+                        &mut self.files,
+                        &mut mdir,
+                        ID_NONE,
+                        &[Attr::new(
+                            TYPE_TAIL + child.split as u16,
+                            ID_NONE,
+                            &tail_data,
+                        )],
+                        prog_size,
+                    )?;
+                    break;
+                }
+                *pair = mdir.tail;
+            }
+            let Some(next) = *next else {
+                return Ok(Some(Output::None));
+            };
+            *stage = RemoveStage::Unlink {
+                child: next,
+                next: None,
+                pair: ROOT_PAIR,
+            };
+            return Ok(None);
+        }
+
+        let r = lookup(&mut self.cache, self.buffer, l, &self.config)?;
+        if r.root {
+            return Err(Flow::Fail(Error::Invalid));
+        }
+        let mut mdir = fetch(&mut self.cache, r.pair, prog_size)?;
+
+        let child = match r.kind {
+            None => return Err(Flow::Fail(Error::NotFound)),
+            Some(TYPE_DIR) => {
+                let Struct::Dir(child) = get_struct(self.cache.get(mdir.pair[0])?, &mdir, r.id)?
+                else {
+                    return Err(Flow::Fail(Error::Corrupt));
+                };
+                let dir = fetch(&mut self.cache, child, prog_size)?;
+                if dir.count > 0 || dir.split {
+                    return Err(Flow::Fail(Error::NotEmpty));
+                }
+
+                // The directory is usually right after its parent in the list
+                // of metadata pairs, then both changes fit in one commit.
+                let tail_data = encode_pair(dir.tail);
+                let parent_is_pred = pair_eq(mdir.tail, child);
+                let attrs = [
+                    Attr::new(TYPE_DELETE, r.id, &[]),
+                    Attr::new(TYPE_TAIL + dir.split as u16, ID_NONE, &tail_data),
+                ];
+                let len = if parent_is_pred { 2 } else { 1 };
+                let (pair, id) = commit(
+                    &mut self.cache,
+                    &mut self.alloc,
+                    &mut self.files,
+                    &mut mdir,
+                    r.id,
+                    &attrs[..len],
+                    prog_size,
+                )?;
+                shift_files(&mut self.files, pair, id, false);
+                (!parent_is_pred).then_some(child)
+            }
+            Some(_) => {
+                let (pair, id) = commit(
+                    &mut self.cache,
+                    &mut self.alloc,
+                    &mut self.files,
+                    &mut mdir,
+                    r.id,
+                    &[Attr::new(TYPE_DELETE, r.id, &[])],
+                    prog_size,
+                )?;
+                shift_files(&mut self.files, pair, id, false);
+                None
+            }
+        };
+
+        // A metadata pair continuing a directory is dropped once empty.
+        let emptied = (mdir.count == 0 && r.continued).then_some(mdir.pair);
+        let (child, next) = match (child, emptied) {
+            (None, None) => return Ok(Some(Output::None)),
+            (Some(child), next) => (child, next),
+            (None, Some(emptied)) => (emptied, None),
+        };
+        *stage = RemoveStage::Unlink {
+            child,
+            next,
+            pair: ROOT_PAIR,
+        };
+        Ok(None)
+    }
+
+    fn mkdir_step(
+        &mut self,
+        l: &mut Lookup,
+        pred: &mut Option<[u32; 2]>,
+        new_pair: &mut Option<[u32; 2]>,
+        linked: &mut bool,
+    ) -> StepResult<Option<Output>> {
+        let prog_size = self.config.prog_size;
+        self.check_writable()?;
+        let r = lookup(&mut self.cache, self.buffer, l, &self.config)?;
+        if r.root || r.kind.is_some() {
+            return Err(Flow::Fail(Error::Exists));
+        }
+
+        let Some(pair) = *new_pair else {
+            // Write the new directory first. It goes after the last pair of
+            // its parent in the list of metadata pairs.
+            let mut last = fetch(&mut self.cache, pred.unwrap_or(r.pair), prog_size)?;
+            let mut visited = 0;
+            while last.split {
+                visited += 1;
+                if visited > self.config.block_count {
+                    return Err(Flow::Fail(Error::Corrupt));
+                }
+                *pred = Some(last.tail);
+                last = fetch(&mut self.cache, last.tail, prog_size)?;
+            }
+            *pred = Some(last.pair);
+
+            let pair = [self.alloc.alloc()?, self.alloc.alloc()?];
+            let tail = encode_pair(last.tail);
+            let attrs = [Attr::new(TYPE_SOFTTAIL, ID_NONE, &tail)];
+            let len = usize::from(last.tail[0] != BLOCK_NULL);
+            mdir::create(&mut self.cache, pair, &attrs[..len], prog_size)?;
+            *new_pair = Some(pair);
+            return Ok(None);
+        };
+        let pair_data = encode_pair(pair);
+
+        match *pred {
+            Some(last) if !*linked && !pair_eq(last, r.pair) => {
+                let mut mdir = fetch(&mut self.cache, last, prog_size)?;
+                commit(
+                    &mut self.cache,
+                    &mut self.alloc,
+                    &mut self.files,
+                    &mut mdir,
+                    ID_NONE,
+                    &[Attr::new(TYPE_SOFTTAIL, ID_NONE, &pair_data)],
+                    prog_size,
+                )?;
+                *linked = true;
+                return Ok(None);
+            }
+            _ => {}
+        }
+
+        // The last pair of the parent points to the new directory, unless it
+        // was linked above.
+        let mut mdir = fetch(&mut self.cache, r.pair, prog_size)?;
+        let name = &self.buffer[r.name.0..r.name.1];
+        let attrs = [
+            Attr::new(TYPE_CREATE, r.id, &[]),
+            Attr::new(TYPE_DIR, r.id, name),
+            Attr::new(TYPE_DIRSTRUCT, r.id, &pair_data),
+            Attr::new(TYPE_SOFTTAIL, ID_NONE, &pair_data),
+        ];
+        let len = if mdir.split { 3 } else { 4 };
+        let (pair, id) = commit(
+            &mut self.cache,
+            &mut self.alloc,
+            &mut self.files,
+            &mut mdir,
+            r.id,
+            &attrs[..len],
+            prog_size,
+        )?;
+        shift_files(&mut self.files, pair, id, true);
+        Ok(Some(Output::None))
+    }
+
+    fn read_dir_step(
+        &mut self,
+        l: &mut Lookup,
+        index: u32,
+        scan: &mut Option<([u32; 2], u32)>,
+    ) -> StepResult<Option<Output>> {
+        let prog_size = self.config.prog_size;
+        if scan.is_none() {
+            let r = lookup(&mut self.cache, self.buffer, l, &self.config)?;
+            let pair = match r.kind {
+                _ if r.root => ROOT_PAIR,
+                Some(TYPE_DIR) => {
+                    let mdir = fetch(&mut self.cache, r.pair, prog_size)?;
+                    match get_struct(self.cache.get(mdir.pair[0])?, &mdir, r.id)? {
+                        Struct::Dir(pair) => pair,
+                        _ => return Err(Flow::Fail(Error::Corrupt)),
+                    }
+                }
+                Some(_) => return Err(Flow::Fail(Error::NotDir)),
+                None => return Err(Flow::Fail(Error::NotFound)),
+            };
+            *scan = Some((pair, 0));
+        }
+
+        while let Some((pair, seen)) = *scan {
+            let mdir = fetch(&mut self.cache, pair, prog_size)?;
+            let data = self.cache.get(mdir.pair[0])?;
+            let mut seen = seen;
+
+            for id in 0..mdir.count {
+                let Some((tag, off)) =
+                    find_tag(data, &mdir, MASK_NAME_ID, Tag::new(TYPE_NAME, id, 0))
+                else {
+                    continue;
+                };
+                let kind = match tag.type3() {
+                    TYPE_REG => EntryKind::File,
+                    TYPE_DIR => EntryKind::Dir,
+                    _ => continue,
+                };
+                if seen < index {
+                    seen += 1;
+                    continue;
+                }
+
+                let name_len = tag.size() as usize;
+                if name_len > self.buffer.len() {
+                    return Err(Flow::Fail(Error::NameTooLong));
+                }
+                self.buffer[..name_len].copy_from_slice(&data[off..off + name_len]);
+                let size = match get_struct(data, &mdir, id)? {
+                    Struct::Inline(size, _) | Struct::Ctz(_, size) => size,
+                    Struct::Dir(_) => 0,
+                };
+                return Ok(Some(Output::Entry(Entry {
+                    kind,
+                    size,
+                    name_len,
+                })));
+            }
+
+            if !mdir.split {
+                return Err(Flow::Fail(Error::NotFound));
+            }
+            *scan = Some((mdir.tail, seen));
+        }
+        Err(Flow::Fail(Error::NotFound))
+    }
+
+    fn stat_step(&mut self, l: &mut Lookup) -> StepResult<Option<Output>> {
+        let r = lookup(&mut self.cache, self.buffer, l, &self.config)?;
+        let (kind, size) = match r.kind {
+            _ if r.root => (EntryKind::Dir, 0),
+            Some(TYPE_DIR) => (EntryKind::Dir, 0),
+            Some(_) => {
+                let mdir = fetch(&mut self.cache, r.pair, self.config.prog_size)?;
+                match get_struct(self.cache.get(mdir.pair[0])?, &mdir, r.id)? {
+                    Struct::Inline(size, _) | Struct::Ctz(_, size) => (EntryKind::File, size),
+                    Struct::Dir(_) => return Err(Flow::Fail(Error::Corrupt)),
+                }
+            }
+            None => return Err(Flow::Fail(Error::NotFound)),
+        };
+        Ok(Some(Output::Entry(Entry {
+            kind,
+            size,
+            name_len: 0,
+        })))
+    }
+}
@@ -0,0 +1,230 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! # littlefs
+//!
+//! A no_std implementation of the [littlefs](https://github.com/littlefs-project/littlefs)
+//! v2 on-disk format, written so that the Tock kernel can share a file
+//! system with images created or read by the upstream littlefs tools.
+//!
+//! ## Driving the file system
+//!
+//! Unlike the C implementation this crate never calls into the flash
+//! driver. Flash operations in Tock complete asynchronously, so instead an
+//! operation is started (for example `LittleFs::open()`) and the caller
+//! repeatedly calls `LittleFs::poll()`, which returns the next block IO the
+//! file system needs:
+//!
+//! ```rust,ignore
+//! fs.buffer_mut()[..4].copy_from_slice(b"/log");
+//! fs.open(4, OpenFlags::READ)?;
+//! loop {
+//!     match fs.poll() {
+//!         Request::Read(block) => {
+//!             flash.read(block, fs.io_buffer());
+//!             fs.io_complete();
+//!         }
+//!         Request::Erase(block) => {
+//!             flash.erase(block);
+//!             fs.io_complete();
+//!         }
+//!         Request::Write(block) => {
+//!             flash.write(block, fs.io_buffer());
+//!             fs.io_complete();
+//!         }
+//!         Request::Done(result) => break result,
+//!         Request::Idle => unreachable!(),
+//!     }
+//! }
+//! ```
+//!
+//! IO is always done on whole blocks. Paths and file data are passed
+//! through the buffer returned by `LittleFs::buffer_mut()`.
+//!
+//! ## Power loss resilience
+//!
+//! Every operation is split into steps. Blocks modified by a step are kept in
+//! RAM until the step completes, and then written back in an order that
+//! keeps the file system valid if power is lost at any point: new data is
+//! written to free blocks first and only then referenced by a metadata
+//! commit. Metadata commits are protected by a CRC, a partially written
+//! commit is ignored by the next mount.
+//!
+//! ## Supported subset
+//!
+//! Images written by this crate are valid littlefs v2.0 images. Reading
+//! supports all of v2.0 and v2.1. Files can be written at any position, and
+//! directories are split over several metadata pairs when they outgrow a
+//! block. Some write paths of the C implementation are left out on purpose:
+//!
+//!  * File data is never stored inline in the metadata. Inline files
+//!    created by other littlefs implementations are moved out on the first
+//!    write.
+//!  * Rename is not supported. Images with an unfinished rename or orphans
+//!    left by an interrupted C operation are mounted read-only.
+//!  * Custom attributes are preserved but can't be read or written.
+//!
+//! ## Memory use
+//!
+//! The caller provides a cache of `CACHE_BLOCKS` blocks, a bitmap with a
+//! bit per block used to allocate free blocks, and a buffer for paths and
+//! file data. The bitmap is built by scanning the file system at mount, and
+//! rebuilt when it runs out of free blocks.
+
+#![no_std]
+
+mod cache;
+mod format;
+mod fs;
+mod mdir;
+
+#[cfg(test)]
+extern crate std;
+#[cfg(test)]
+mod tests;
+
+pub use crate::cache::CACHE_BLOCKS;
+pub use crate::fs::{LittleFs, MAX_OPEN_FILES};
+
+/// Errors returned by file system operations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The flash driver reported an error.
+    Io,
+    /// The image isn't a littlefs file system, or is damaged.
+    Corrupt,
+    /// The image uses a littlefs version we can't read.
+    UnsupportedVersion,
+    /// The file or directory doesn't exist.
+    NotFound,
+    /// The file or directory already exists.
+    Exists,
+    /// A path component isn't a directory.
+    NotDir,
+    /// The path names a directory.
+    IsDir,
+    /// The directory isn't empty.
+    NotEmpty,
+    /// The file system or a directory is full.
+    NoSpace,
+    /// The cache or the file table is too small.
+    NoMemory,
+    /// A name is longer than the file system allows.
+    NameTooLong,
+    /// An argument is invalid, for example the file wasn't opened for
+    /// reading or writing.
+    Invalid,
+    /// The operation is outside the subset of littlefs we implement.
+    Unsupported,
+    /// The file system can only be read.
+    ReadOnly,
+    /// Another operation is in progress.
+    Busy,
+    /// The file system isn't mounted.
+    NotMounted,
+    /// The file handle isn't open.
+    BadHandle,
+}
+
+/// The geometry of the flash.
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    /// The erase unit, a multiple of `prog_size`.
+    pub block_size: usize,
+    /// The number of blocks used by the file system.
+    pub block_count: u32,
+    /// The write unit. Commits are padded to this size, which must be at
+    /// most 512 bytes.
+    pub prog_size: usize,
+}
+
+/// What the file system needs from the caller.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Request {
+    /// No operation is in progress.
+    Idle,
+    /// Read the block into `LittleFs::io_buffer()`, then call
+    /// `LittleFs::io_complete()`.
+    Read(u32),
+    /// Erase the block, then call `LittleFs::io_complete()`.
+    Erase(u32),
+    /// Write `LittleFs::io_buffer()` to the block, which has been erased
+    /// or only differs from its content in bytes that were erased. Then call
+    /// `LittleFs::io_complete()`.
+    Write(u32),
+    /// The operation finished.
+    Done(Result<Output, Error>),
+}
+
+/// The result of a successful operation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Output {
+    /// The operation has no result.
+    None,
+    /// A file was opened.
+    File(FileHandle),
+    /// The number of bytes read or written.
+    Length(usize),
+    /// The directory entry or path that was looked up.
+    Entry(Entry),
+}
+
+/// A directory entry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Entry {
+    pub kind: EntryKind,
+    /// The file size, zero for directories.
+    pub size: u32,
+    /// Length of the name, placed at the start of the buffer. Zero if the
+    /// entry was looked up by its path.
+    pub name_len: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Dir,
+}
+
+/// An open file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileHandle(pub(crate) u8);
+
+/// Flags used to open a file. The values match the C implementation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpenFlags(pub u32);
+
+impl OpenFlags {
+    /// Open for reading.
+    pub const READ: OpenFlags = OpenFlags(0x1);
+    /// Open for writing.
+    pub const WRITE: OpenFlags = OpenFlags(0x2);
+    /// Create the file if it doesn't exist.
+    pub const CREATE: OpenFlags = OpenFlags(0x100);
+    /// Fail if the file already exists.
+    pub const EXCL: OpenFlags = OpenFlags(0x200);
+    /// Discard the current contents of the file.
+    pub const TRUNC: OpenFlags = OpenFlags(0x400);
+    /// Move to the end of the file before every write.
+    pub const APPEND: OpenFlags = OpenFlags(0x800);
+
+    const ALL: u32 = 0xf03;
+
+    pub fn contains(self, other: OpenFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns true if only known flags are set.
+    pub fn is_valid(self) -> bool {
+        self.0 & !Self::ALL == 0
+    }
+}
+
+impl core::ops::BitOr for OpenFlags {
+    type Output = OpenFlags;
+
+    fn bitor(self, rhs: OpenFlags) -> OpenFlags {
+        OpenFlags(self.0 | rhs.0)
+    }
+}
@@ -0,0 +1,676 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Metadata pairs.
+//!
+//! A metadata pair is two blocks holding a log of commits, each commit being
+//! a list of tags followed by a CRC tag. The block with the newer revision
+//! count is the active one. Commits are appended to the active block until
+//! it is full, then the live tags are compacted into the other block with an
+//! incremented revision count.
+//!
+//! A directory with more entries than fit in a block is split over several
+//! metadata pairs, each one pointing to the next with a hard tail. When a
+//! compaction would leave the block more than half full, the upper half of
+//! the entries is moved to a new pair, so that a full block isn't compacted
+//! over and over.
+
+use crate::Error;
+use crate::cache::{Cache, Flow, StepResult};
+use crate::format::{
+    BLOCK_NULL, GSTATE_LEN, ID_NONE, TYPE_CRC, TYPE_CREATE, TYPE_DELETE, TYPE_MOVESTATE, TYPE_NAME,
+    TYPE_SPLICE, TYPE_STRUCT, TYPE_TAIL, TYPE_USERATTR, Tag, crc32, decode_pair, encode_pair,
+    read_be32, read_le32, rev_newer, write_le32,
+};
+
+/// Mask matching the type1 and id of a tag.
+pub(crate) const MASK_TYPE1_ID: u32 = 0x700f_fc00;
+/// Mask matching the type1, the top bit of the chunk and the id of a tag.
+/// This matches regular files and directories but not the superblock.
+pub(crate) const MASK_NAME_ID: u32 = 0x780f_fc00;
+/// Mask matching the full type and id of a tag.
+pub(crate) const MASK_TYPE3_ID: u32 = 0x7fff_fc00;
+
+/// Space a compacted block needs besides the entries: the revision count, a
+/// tail, the global state and the CRC.
+const COMPACT_OVERHEAD: usize = 4 + 12 + (4 + GSTATE_LEN) + 8;
+
+/// The state of a fetched metadata pair.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct MDir {
+    /// `pair[0]` is the active block.
+    pub(crate) pair: [u32; 2],
+    pub(crate) rev: u32,
+    /// End of the last valid commit.
+    pub(crate) off: usize,
+    /// The last tag before `off`, used to continue the tag XOR chain.
+    pub(crate) etag: u32,
+    /// Number of entry ids.
+    pub(crate) count: u16,
+    pub(crate) tail: [u32; 2],
+    /// The tail continues this directory (hard tail), rather than pointing
+    /// to the next directory.
+    pub(crate) split: bool,
+    /// The rest of the active block is erased, so commits can be appended.
+    pub(crate) erased: bool,
+}
+
+/// A tag to commit, along with its data.
+pub(crate) struct Attr<'d> {
+    pub(crate) tag: Tag,
+    pub(crate) data: &'d [u8],
+}
+
+impl<'d> Attr<'d> {
+    pub(crate) fn new(type3: u16, id: u16, data: &'d [u8]) -> Self {
+        Self {
+            tag: Tag::new(type3, id, data.len() as u32),
+            data,
+        }
+    }
+
+    /// Returns true if the attr goes to the new pair when a pair is split
+    /// at `split`. Tails go to the new pair, which is last in the list.
+    fn after(&self, split: u16) -> bool {
+        self.tag.id() == ID_NONE || self.tag.id() >= split
+    }
+
+    /// The change in the number of entries caused by the attr.
+    fn count_change(&self) -> i32 {
+        match self.tag.type3() {
+            TYPE_CREATE => 1,
+            TYPE_DELETE => -1,
+            _ => 0,
+        }
+    }
+}
+
+/// Where a commit moved entries when it split a metadata pair: entries
+/// from `id` on are now in `pair`, numbered from 0.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Split {
+    pub(crate) id: u16,
+    pub(crate) pair: [u32; 2],
+}
+
+impl Split {
+    /// The new location of entry `id` of the split pair.
+    pub(crate) fn locate(split: Option<Split>, pair: [u32; 2], id: u16) -> ([u32; 2], u16) {
+        match split {
+            Some(split) if id != ID_NONE && id >= split.id => (split.pair, id - split.id),
+            _ => (pair, id),
+        }
+    }
+}
+
+/// Parse the commits of one block. Returns `None` if the block doesn't
+/// contain a valid commit.
+fn parse(data: &[u8], block: u32, other: u32, prog_size: usize) -> Option<MDir> {
+    let block_size = data.len();
+    let rev = read_le32(data, 0);
+    let mut crc = crc32(0xffff_ffff, &data[0..4]);
+    let mut off = 0;
+    let mut ptag = Tag(0xffff_ffff);
+
+    let mut count: u16 = 0;
+    let mut tail = [BLOCK_NULL; 2];
+    let mut split = false;
+    let mut found: Option<MDir> = None;
+
+    loop {
+        off += ptag.dsize();
+        if off + 4 > block_size {
+            break;
+        }
+
+        let raw = read_be32(data, off);
+        let tag = Tag(raw ^ ptag.0);
+        if !tag.is_valid() {
+            if let Some(mdir) = found.as_mut() {
+                mdir.erased = ptag.type1() == TYPE_CRC && mdir.off % prog_size == 0;
+            }
+            break;
+        }
+        if off + tag.dsize() > block_size {
+            break;
+        }
+        crc = crc32(crc, &data[off..off + 4]);
+        ptag = tag;
+
+        if tag.is_commit_crc() {
+            if tag.size() < 4 || read_le32(data, off + 4) != crc {
+                break;
+            }
+            ptag = Tag(tag.0 ^ ((tag.chunk() as u32 & 1) << 31));
+            found = Some(MDir {
+                pair: [block, other],
+                rev,
+                off: off + tag.dsize(),
+                etag: ptag.0,
+                count,
+                tail,
+                split,
+                erased: false,
+            });
+            crc = 0xffff_ffff;
+            continue;
+        }
+
+        crc = crc32(crc, &data[off + 4..off + tag.dsize()]);
+        match tag.type1() {
+            TYPE_NAME => {
+                if tag.id() != ID_NONE && tag.id() >= count {
+                    count = tag.id() + 1;
+                }
+            }
+            TYPE_SPLICE => {
+                count = (count as i32 + tag.splice() as i32) as u16;
+            }
+            TYPE_TAIL => {
+                split = tag.chunk() & 1 != 0;
+                tail = decode_pair(&data[off + 4..]);
+            }
+            _ => {}
+        }
+    }
+
+    found
+}
+
+/// Fetch the active state of the metadata pair.
+pub(crate) fn fetch(cache: &mut Cache, pair: [u32; 2], prog_size: usize) -> StepResult<MDir> {
+    let rev0 = read_le32(cache.get(pair[0])?, 0);
+    let rev1 = read_le32(cache.get(pair[1])?, 0);
+
+    let order = if rev_newer(rev1, rev0) {
+        [pair[1], pair[0]]
+    } else {
+        pair
+    };
+
+    for (i, block) in order.iter().enumerate() {
+        let other = order[1 - i];
+        if let Some(mdir) = parse(cache.get(*block)?, *block, other, prog_size) {
+            return Ok(mdir);
+        }
+    }
+
+    Err(Flow::Fail(Error::Corrupt))
+}
+
+/// Find the newest tag matching `gtag` under `gmask`, following the id
+/// changes made by later creates and deletes.
+///
+/// Returns the tag, with its id as currently numbered, and the offset of its
+/// data in `data`, the active block of `mdir`.
+pub(crate) fn find_tag(data: &[u8], mdir: &MDir, gmask: u32, gtag: Tag) -> Option<(Tag, usize)> {
+    let mut off = mdir.off;
+    let mut ntag = Tag(mdir.etag);
+    let mut gdiff: u32 = 0;
+    let check_splices = Tag(gmask).id() != 0;
+
+    while off >= 4 + ntag.dsize() {
+        off -= ntag.dsize();
+        let tag = ntag;
+        ntag = Tag((read_be32(data, off) ^ tag.0) & 0x7fff_ffff);
+
+        let want = Tag(gtag.0.wrapping_sub(gdiff));
+        if check_splices && tag.type1() == TYPE_SPLICE && tag.id() <= want.id() {
+            if tag == Tag::new(TYPE_CREATE, want.id(), 0) {
+                // This is where the entry was created.
+                return None;
+            }
+            gdiff = gdiff.wrapping_add(((tag.splice() as i32) << 10) as u32);
+        }
+
+        if gmask & tag.0 == gmask & gtag.0.wrapping_sub(gdiff) {
+            if tag.is_delete() {
+                return None;
+            }
+            return Some((Tag(tag.0.wrapping_add(gdiff)), off + 4));
+        }
+    }
+
+    None
+}
+
+/// Call `f` with every tag of the valid commits in the active block of
+/// `mdir`, in the order they were written.
+fn for_each_tag(data: &[u8], mdir: &MDir, mut f: impl FnMut(Tag, usize)) {
+    let mut off = 4;
+    let mut ptag = Tag(0xffff_ffff);
+
+    while off < mdir.off {
+        let tag = Tag((read_be32(data, off) ^ ptag.0) & 0x7fff_ffff);
+        if tag.is_commit_crc() {
+            ptag = Tag(tag.0 ^ ((tag.chunk() as u32 & 1) << 31));
+        } else {
+            f(tag, off + 4);
+            ptag = tag;
+        }
+        off += tag.dsize();
+    }
+}
+
+/// The XOR of all global state deltas stored in the metadata pair.
+pub(crate) fn gdelta(data: &[u8], mdir: &MDir) -> [u8; GSTATE_LEN] {
+    let mut delta = [0; GSTATE_LEN];
+    for_each_tag(data, mdir, |tag, off| {
+        if tag.type3() == TYPE_MOVESTATE && tag.size() as usize >= GSTATE_LEN {
+            for (d, s) in delta.iter_mut().zip(&data[off..off + GSTATE_LEN]) {
+                *d ^= s;
+            }
+        }
+    });
+    delta
+}
+
+/// Appends tags to a block, keeping the tag XOR chain and the CRC.
+struct CommitWriter {
+    off: usize,
+    ptag: u32,
+    crc: u32,
+    /// Tags must end before this offset, leaving space for the CRC.
+    end: usize,
+}
+
+impl CommitWriter {
+    fn prog(&mut self, block: &mut [u8], data: &[u8]) {
+        block[self.off..self.off + data.len()].copy_from_slice(data);
+        self.crc = crc32(self.crc, data);
+        self.off += data.len();
+    }
+
+    fn attr(&mut self, block: &mut [u8], tag: Tag, data: &[u8]) -> Result<(), Error> {
+        if self.off + tag.dsize() > self.end {
+            return Err(Error::NoSpace);
+        }
+        let tag = tag.0 & 0x7fff_ffff;
+        self.prog(block, &(tag ^ self.ptag).to_be_bytes());
+        self.prog(block, data);
+        self.ptag = tag;
+        Ok(())
+    }
+
+    /// Finish the commit with a CRC tag padded to `prog_size`.
+    fn finish(&mut self, block: &mut [u8], prog_size: usize) {
+        let noff = (self.off + 8).next_multiple_of(prog_size);
+
+        // The valid bit of the next tag tells littlefs whether the padding
+        // was programmed, make it differ from whatever follows.
+        let next = if noff + 4 <= block.len() {
+            read_be32(block, noff)
+        } else {
+            0xffff_ffff
+        };
+        let reset = !next >> 31;
+
+        let tag = Tag::new(
+            TYPE_CRC + reset as u16,
+            ID_NONE,
+            (noff - self.off - 4) as u32,
+        );
+        let off = self.off;
+        self.prog(block, &(tag.0 ^ self.ptag).to_be_bytes());
+        write_le32(block, off + 4, self.crc);
+
+        self.off = noff;
+        self.ptag = tag.0 ^ (reset << 31);
+        self.crc = 0xffff_ffff;
+    }
+}
+
+/// Returns true if a commit of `attrs` fits after the last commit of the
+/// active block.
+fn fits(mdir: &MDir, attrs: &[Attr], block_size: usize) -> bool {
+    let len: usize = attrs.iter().map(|a| a.tag.dsize()).sum();
+    mdir.off + len + 8 <= block_size
+}
+
+/// The space a commit of `attrs` takes, without padding.
+fn commit_len(attrs: &[Attr]) -> usize {
+    match attrs.iter().map(|a| a.tag.dsize()).sum() {
+        0 => 0,
+        len => len + 8,
+    }
+}
+
+/// Append a commit of the attrs for which `keep` returns true to the active
+/// block. The block must be cached.
+fn append(
+    cache: &mut Cache,
+    mdir: &mut MDir,
+    attrs: &[Attr],
+    keep: impl Fn(&Attr) -> bool,
+    prog_size: usize,
+) -> StepResult<()> {
+    let block = cache.get_mut(mdir.pair[0])?;
+    let mut writer = CommitWriter {
+        off: mdir.off,
+        ptag: mdir.etag,
+        crc: 0xffff_ffff,
+        end: block.len() - 8,
+    };
+
+    for attr in attrs.iter().filter(|a| keep(a)) {
+        writer.attr(block, attr.tag, attr.data)?;
+    }
+    writer.finish(block, prog_size);
+
+    mdir.off = writer.off;
+    mdir.etag = writer.ptag;
+    apply(mdir, attrs.iter().filter(|a| keep(a)));
+    Ok(())
+}
+
+/// Update the entry count and the tail of `mdir` for committed `attrs`.
+fn apply<'a, 'd: 'a>(mdir: &mut MDir, attrs: impl Iterator<Item = &'a Attr<'d>>) {
+    for attr in attrs {
+        match attr.tag.type1() {
+            TYPE_NAME if attr.tag.id() >= mdir.count => mdir.count = attr.tag.id() + 1,
+            TYPE_SPLICE => {
+                mdir.count = (mdir.count as i32 + attr.tag.splice() as i32) as u16;
+            }
+            TYPE_TAIL => {
+                mdir.split = attr.tag.chunk() & 1 != 0;
+                mdir.tail = decode_pair(attr.data);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Call `f` with the live tags of entry `id` and the offset of their data in
+/// `data`, the active block of `mdir`. These are the tags a compaction keeps.
+fn for_each_live(
+    data: &[u8],
+    mdir: &MDir,
+    id: u16,
+    mut f: impl FnMut(Tag, usize) -> Result<(), Error>,
+) -> Result<(), Error> {
+    for gtag in [Tag::new(TYPE_NAME, id, 0), Tag::new(TYPE_STRUCT, id, 0)] {
+        if let Some((tag, off)) = find_tag(data, mdir, MASK_TYPE1_ID, gtag) {
+            f(tag, off)?;
+        }
+    }
+
+    // Custom attributes, the newest tag of each type is the live one.
+    let mut seen = [0u32; 8];
+    loop {
+        let mut next = None;
+        let mut off = mdir.off;
+        let mut ntag = Tag(mdir.etag);
+        let mut gdiff: u32 = 0;
+        while off >= 4 + ntag.dsize() {
+            off -= ntag.dsize();
+            let tag = ntag;
+            ntag = Tag((read_be32(data, off) ^ tag.0) & 0x7fff_ffff);
+            let want = Tag(Tag::new(0, id, 0).0.wrapping_sub(gdiff)).id();
+            if tag.type1() == TYPE_SPLICE && tag.id() <= want {
+                if tag == Tag::new(TYPE_CREATE, want, 0) {
+                    break;
+                }
+                gdiff = gdiff.wrapping_add(((tag.splice() as i32) << 10) as u32);
+            } else if tag.type1() == TYPE_USERATTR && tag.id() == want {
+                let chunk = tag.chunk() as usize;
+                if seen[chunk / 32] & (1 << (chunk % 32)) == 0 {
+                    seen[chunk / 32] |= 1 << (chunk % 32);
+                    if !tag.is_delete() {
+                        next = Some((Tag::new(tag.type3(), id, tag.size()), off + 4));
+                        break;
+                    }
+                }
+            }
+        }
+        match next {
+            Some((tag, off)) => f(tag, off)?,
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+/// The space the live tags of entry `id` take.
+fn live_size(data: &[u8], mdir: &MDir, id: u16) -> usize {
+    let mut size = 0;
+    let _ = for_each_live(data, mdir, id, |tag, _| {
+        size += tag.dsize();
+        Ok(())
+    });
+    size
+}
+
+/// Copy the live tags of the active block into the other block of the pair,
+/// which becomes the active block.
+fn compact(cache: &mut Cache, mdir: &mut MDir, prog_size: usize) -> StepResult<()> {
+    let src = mdir.pair[0];
+    let dst = mdir.pair[1];
+    cache.get(src)?;
+    cache.fresh(dst)?;
+    let (old, new) = cache.get_pair(src, dst)?;
+
+    let rev = mdir.rev.wrapping_add(1);
+    let mut writer = CommitWriter {
+        off: 0,
+        ptag: 0xffff_ffff,
+        crc: 0xffff_ffff,
+        end: new.len() - 8,
+    };
+    writer.prog(new, &rev.to_le_bytes());
+
+    for id in 0..mdir.count {
+        for_each_live(old, mdir, id, |tag, off| {
+            writer.attr(new, tag, &old[off..off + tag.size() as usize])
+        })?;
+    }
+
+    if mdir.tail[0] != BLOCK_NULL {
+        let tail = encode_pair(mdir.tail);
+        writer.attr(
+            new,
+            Tag::new(TYPE_TAIL + mdir.split as u16, ID_NONE, 8),
+            &tail,
+        )?;
+    }
+
+    let delta = gdelta(old, mdir);
+    if delta.iter().any(|b| *b != 0) {
+        writer.attr(
+            new,
+            Tag::new(TYPE_MOVESTATE, ID_NONE, GSTATE_LEN as u32),
+            &delta,
+        )?;
+    }
+
+    writer.finish(new, prog_size);
+
+    mdir.pair = [dst, src];
+    mdir.rev = rev;
+    mdir.off = writer.off;
+    mdir.etag = writer.ptag;
+    mdir.erased = true;
+    Ok(())
+}
+
+/// Choose where to split `mdir`, so that the larger of the two pairs is as
+/// small as possible and both keep at least one entry. Returns `None` if
+/// there is no such split that fits.
+fn split_point(
+    data: &[u8],
+    mdir: &MDir,
+    attrs: &[Attr],
+    live: usize,
+    block_size: usize,
+    prog_size: usize,
+) -> Option<u16> {
+    let mut best: Option<(usize, u16)> = None;
+    let mut before = 0;
+    for split in 1..=mdir.count {
+        before += live_size(data, mdir, split - 1);
+        // The size of the attrs on each side, and the entries they keep.
+        let (mut first_len, mut first_count) = (0, split as i32);
+        let (mut after_len, mut after_count) = (0, (mdir.count - split) as i32);
+        for attr in attrs {
+            if attr.after(split) {
+                after_len += attr.tag.dsize();
+                after_count += attr.count_change();
+            } else {
+                first_len += attr.tag.dsize();
+                first_count += attr.count_change();
+            }
+        }
+        if first_count < 1 || after_count < 1 {
+            continue;
+        }
+
+        // The first pair is compacted and the attrs appended, the new pair
+        // is written with a single commit.
+        let first_len = (before + COMPACT_OVERHEAD).next_multiple_of(prog_size)
+            + if first_len > 0 { first_len + 8 } else { 0 };
+        let after_len = 4 + (live - before) + 12 + after_len + 8;
+        let len = first_len.max(after_len);
+        if len <= block_size && best.is_none_or(|(best, _)| len < best) {
+            best = Some((len, split));
+        }
+    }
+    best.map(|(_, split)| split)
+}
+
+/// Move the entries of `mdir` from `split` on to the new pair `new`, which
+/// takes over the tail of `mdir`, and commit the attrs for these entries and
+/// for the tail with them. `mdir` is then compacted with the other entries
+/// and a hard tail to `new`, and the other attrs are appended to it.
+///
+/// The new pair is written first, so that power loss leaves at most an
+/// unused pair.
+fn split(
+    cache: &mut Cache,
+    mdir: &mut MDir,
+    attrs: &[Attr],
+    split: u16,
+    new: [u32; 2],
+    prog_size: usize,
+) -> StepResult<()> {
+    let renumber = |tag: Tag| {
+        if tag.id() == ID_NONE {
+            tag
+        } else {
+            Tag(tag.0 - ((split as u32) << 10))
+        }
+    };
+
+    cache.get(mdir.pair[0])?;
+    cache.fresh(new[1])?;
+    cache.fresh(new[0])?;
+    let (old, block) = cache.get_pair(mdir.pair[0], new[0])?;
+    let mut writer = CommitWriter {
+        off: 0,
+        ptag: 0xffff_ffff,
+        crc: 0xffff_ffff,
+        end: block.len() - 8,
+    };
+    writer.prog(block, &1u32.to_le_bytes());
+    for id in split..mdir.count {
+        for_each_live(old, mdir, id, |tag, off| {
+            writer.attr(block, renumber(tag), &old[off..off + tag.size() as usize])
+        })?;
+    }
+    if mdir.tail[0] != BLOCK_NULL {
+        writer.attr(
+            block,
+            Tag::new(TYPE_TAIL + mdir.split as u16, ID_NONE, 8),
+            &encode_pair(mdir.tail),
+        )?;
+    }
+    for attr in attrs.iter().filter(|a| a.after(split)) {
+        writer.attr(block, renumber(attr.tag), attr.data)?;
+    }
+    writer.finish(block, prog_size);
+
+    mdir.count = split;
+    mdir.tail = new;
+    mdir.split = true;
+    compact(cache, mdir, prog_size)?;
+    if attrs.iter().any(|a| !a.after(split)) {
+        append(cache, mdir, attrs, |a| !a.after(split), prog_size)?;
+    }
+    Ok(())
+}
+
+/// Commit `attrs` to the metadata pair, compacting it if the active block is
+/// full. If the compacted block would be more than half full, the pair is
+/// split, with a new pair allocated by `alloc`.
+pub(crate) fn commit(
+    cache: &mut Cache,
+    mdir: &mut MDir,
+    attrs: &[Attr],
+    prog_size: usize,
+    mut alloc: impl FnMut() -> StepResult<u32>,
+) -> StepResult<Option<Split>> {
+    let data = cache.get(mdir.pair[0])?;
+    let block_size = data.len();
+
+    if mdir.erased && fits(mdir, attrs, block_size) {
+        append(cache, mdir, attrs, |_| true, prog_size)?;
+        return Ok(None);
+    }
+
+    let live: usize = (0..mdir.count).map(|id| live_size(data, mdir, id)).sum();
+    let compacted = (live + COMPACT_OVERHEAD).next_multiple_of(prog_size) + commit_len(attrs);
+    let split_at = if compacted > (block_size / 2).next_multiple_of(prog_size) {
+        split_point(data, mdir, attrs, live, block_size, prog_size)
+    } else {
+        None
+    };
+
+    match split_at {
+        Some(id) => {
+            let pair = [alloc()?, alloc()?];
+            split(cache, mdir, attrs, id, pair, prog_size)?;
+            Ok(Some(Split { id, pair }))
+        }
+        None if compacted <= block_size => {
+            compact(cache, mdir, prog_size)?;
+            append(cache, mdir, attrs, |_| true, prog_size)?;
+            Ok(None)
+        }
+        None => Err(Flow::Fail(Error::NoSpace)),
+    }
+}
+
+/// Write a new metadata pair with `attrs` as its first commit. Both blocks
+/// are erased, so the new pair can't be confused with older data.
+pub(crate) fn create(
+    cache: &mut Cache,
+    pair: [u32; 2],
+    attrs: &[Attr],
+    prog_size: usize,
+) -> StepResult<MDir> {
+    cache.fresh(pair[1])?;
+    let block = cache.fresh(pair[0])?;
+    let mut writer = CommitWriter {
+        off: 0,
+        ptag: 0xffff_ffff,
+        crc: 0xffff_ffff,
+        end: block.len() - 8,
+    };
+    writer.prog(block, &1u32.to_le_bytes());
+    for attr in attrs {
+        writer.attr(block, attr.tag, attr.data)?;
+    }
+    writer.finish(block, prog_size);
+
+    let mut mdir = MDir {
+        pair,
+        rev: 1,
+        off: writer.off,
+        etag: writer.ptag,
+        count: 0,
+        tail: [BLOCK_NULL; 2],
+        split: false,
+        erased: true,
+    };
+    apply(&mut mdir, attrs.iter());
+    Ok(mdir)
+}
@@ -0,0 +1,1029 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+use crate::format::{crc32, ctz_index, ctz_pointers};
+use crate::*;
+use std::string::String;
+use std::vec;
+use std::vec::Vec;
+
+const BLOCK_SIZE: usize = 512;
+
+/// A NOR flash: writes can only clear bits.
+struct Flash {
+    data: Vec<u8>,
+    /// The number of erases and writes left before power is lost.
+    budget: Option<usize>,
+}
+
+impl Flash {
+    fn new(blocks: u32) -> Self {
+        Self {
+            data: vec![0xff; blocks as usize * BLOCK_SIZE],
+            budget: None,
+        }
+    }
+
+    fn block(&mut self, block: u32) -> &mut [u8] {
+        let start = block as usize * BLOCK_SIZE;
+        &mut self.data[start..start + BLOCK_SIZE]
+    }
+
+    /// Returns false if power was lost.
+    fn spend(&mut self) -> bool {
+        match self.budget.as_mut() {
+            Some(0) => false,
+            Some(budget) => {
+                *budget -= 1;
+                true
+            }
+            None => true,
+        }
+    }
+}
+
+struct Buffers {
+    cache: Vec<u8>,
+    bitmap: Vec<u8>,
+    buffer: Vec<u8>,
+    config: Config,
+}
+
+impl Buffers {
+    fn new(blocks: u32) -> Self {
+        Self {
+            cache: vec![0; CACHE_BLOCKS * BLOCK_SIZE],
+            bitmap: vec![0; (blocks as usize).div_ceil(8)],
+            buffer: vec![0; 256],
+            config: Config {
+                block_size: BLOCK_SIZE,
+                block_count: blocks,
+                prog_size: 16,
+            },
+        }
+    }
+
+    fn fs(&mut self) -> LittleFs<'_> {
+        LittleFs::new(
+            self.config,
+            &mut self.cache,
+            &mut self.bitmap,
+            &mut self.buffer,
+        )
+    }
+}
+
+/// Run the current operation. Returns `None` if power was lost.
+fn try_run(fs: &mut LittleFs, flash: &mut Flash) -> Option<Result<Output, Error>> {
+    loop {
+        match fs.poll() {
+            Request::Read(block) => {
+                fs.io_buffer().copy_from_slice(flash.block(block));
+                fs.io_complete();
+            }
+            Request::Erase(block) => {
+                if !flash.spend() {
+                    return None;
+                }
+                flash.block(block).fill(0xff);
+                fs.io_complete();
+            }
+            Request::Write(block) => {
+                if !flash.spend() {
+                    return None;
+                }
+                let data = fs.io_buffer();
+                for (old, new) in flash.block(block).iter_mut().zip(data.iter()) {
+                    assert_eq!(
+                        *old & *new,
+                        *new,
+                        "programmed a 0 bit to 1 in block {}",
+                        block
+                    );
+                    *old = *new;
+                }
+                fs.io_complete();
+            }
+            Request::Done(result) => return Some(result),
+            Request::Idle => panic!("no operation in progress"),
+        }
+    }
+}
+
+fn run(fs: &mut LittleFs, flash: &mut Flash) -> Result<Output, Error> {
+    try_run(fs, flash).expect("power lost")
+}
+
+fn set_path(fs: &mut LittleFs, path: &str) -> usize {
+    fs.buffer_mut()[..path.len()].copy_from_slice(path.as_bytes());
+    path.len()
+}
+
+fn format_and_mount(fs: &mut LittleFs, flash: &mut Flash) {
+    fs.format().unwrap();
+    assert_eq!(run(fs, flash), Ok(Output::None));
+    fs.mount().unwrap();
+    assert_eq!(run(fs, flash), Ok(Output::None));
+}
+
+fn open(
+    fs: &mut LittleFs,
+    flash: &mut Flash,
+    path: &str,
+    flags: OpenFlags,
+) -> Result<FileHandle, Error> {
+    let len = set_path(fs, path);
+    fs.open(len, flags).unwrap();
+    match run(fs, flash)? {
+        Output::File(handle) => Ok(handle),
+        output => panic!("unexpected output {:?}", output),
+    }
+}
+
+fn write(fs: &mut LittleFs, flash: &mut Flash, handle: FileHandle, data: &[u8]) {
+    for chunk in data.chunks(100) {
+        fs.buffer_mut()[..chunk.len()].copy_from_slice(chunk);
+        fs.write(handle, chunk.len()).unwrap();
+        assert_eq!(run(fs, flash), Ok(Output::Length(chunk.len())));
+    }
+}
+
+fn read_to_end(fs: &mut LittleFs, flash: &mut Flash, handle: FileHandle) -> Vec<u8> {
+    let mut data = Vec::new();
+    loop {
+        fs.read(handle, 200).unwrap();
+        match run(fs, flash) {
+            Ok(Output::Length(0)) => return data,
+            Ok(Output::Length(n)) => data.extend_from_slice(&fs.buffer()[..n]),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+}
+
+fn close(fs: &mut LittleFs, flash: &mut Flash, handle: FileHandle) {
+    fs.close(handle).unwrap();
+    assert_eq!(run(fs, flash), Ok(Output::None));
+}
+
+fn write_file(fs: &mut LittleFs, flash: &mut Flash, path: &str, data: &[u8]) {
+    let flags = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNC;
+    let handle = open(fs, flash, path, flags).unwrap();
+    write(fs, flash, handle, data);
+    close(fs, flash, handle);
+}
+
+fn read_file(fs: &mut LittleFs, flash: &mut Flash, path: &str) -> Result<Vec<u8>, Error> {
+    let handle = open(fs, flash, path, OpenFlags::READ)?;
+    let data = read_to_end(fs, flash, handle);
+    close(fs, flash, handle);
+    Ok(data)
+}
+
+fn list(fs: &mut LittleFs, flash: &mut Flash, path: &str) -> Vec<(String, EntryKind, u32)> {
+    let mut entries = Vec::new();
+    for index in 0.. {
+        let len = set_path(fs, path);
+        fs.read_dir(len, index).unwrap();
+        match run(fs, flash) {
+            Ok(Output::Entry(entry)) => {
+                let name = String::from_utf8(fs.buffer()[..entry.name_len].to_vec()).unwrap();
+                entries.push((name, entry.kind, entry.size));
+            }
+            Err(Error::NotFound) => return entries,
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+    unreachable!()
+}
+
+fn simple_op(fs: &mut LittleFs, flash: &mut Flash, path: &str, mkdir: bool) -> Result<(), Error> {
+    let len = set_path(fs, path);
+    if mkdir {
+        fs.mkdir(len).unwrap();
+    } else {
+        fs.remove(len).unwrap();
+    }
+    run(fs, flash).map(|_| ())
+}
+
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed))
+        .collect()
+}
+
+#[test]
+fn crc_matches_crc32() {
+    assert_eq!(!crc32(0xffff_ffff, b"123456789"), 0xcbf4_3926);
+}
+
+#[test]
+fn ctz_index_matches_layout() {
+    // Lay out a skip-list block by block and check every byte position.
+    let mut pos = 0;
+    for index in 0..200u32 {
+        let pointers = 4 * ctz_pointers(index);
+        for off in pointers..BLOCK_SIZE {
+            assert_eq!(ctz_index(BLOCK_SIZE, pos), (index, off));
+            pos += 1;
+        }
+    }
+}
+
+#[test]
+fn format_writes_littlefs_superblock() {
+    let mut flash = Flash::new(32);
+    let mut buffers = Buffers::new(32);
+    let mut fs = buffers.fs();
+    format_and_mount(&mut fs, &mut flash);
+
+    // The revision count, the superblock name tag and the start of the
+    // superblock struct, as found at the start of every littlefs image.
+    assert_eq!(
+        flash.data[..24],
+        [
+            0x01, 0x00, 0x00, 0x00, 0xf0, 0x0f, 0xff, 0xf7, b'l', b'i', b't', b't', b'l', b'e',
+            b'f', b's', 0x2f, 0xe0, 0x00, 0x10, 0x00, 0x00, 0x02, 0x00,
+        ]
+    );
+    assert_eq!(flash.data[24..28], (BLOCK_SIZE as u32).to_le_bytes());
+    assert_eq!(flash.data[28..32], 32u32.to_le_bytes());
+    assert!(
+        flash.data[BLOCK_SIZE..2 * BLOCK_SIZE]
+            .iter()
+            .all(|b| *b == 0xff)
+    );
+}
+
+#[test]
+fn mount_rejects_blank_flash() {
+    let mut flash = Flash::new(32);
+    let mut buffers = Buffers::new(32);
+    let mut fs = buffers.fs();
+    fs.mount().unwrap();
+    assert_eq!(run(&mut fs, &mut flash), Err(Error::Corrupt));
+    assert!(!fs.is_mounted());
+    assert_eq!(fs.stat(0), Err(Error::NotMounted));
+}
+
+#[test]
+fn write_and_read_back() {
+    let mut flash = Flash::new(64);
+    let mut buffers = Buffers::new(64);
+    let mut fs = buffers.fs();
+    format_and_mount(&mut fs, &mut flash);
+
+    let data = pattern(10_000, 7);
+    write_file(&mut fs, &mut flash, "/data.bin", &data);
+    assert_eq!(read_file(&mut fs, &mut flash, "data.bin"), Ok(data.clone()));
+
+    // The data survives a remount.
+    let mut fs = buffers.fs();
+    fs.mount().unwrap();
+    assert_eq!(run(&mut fs, &mut flash), Ok(Output::None));
+    assert_eq!(read_file(&mut fs, &mut flash, "/data.bin"), Ok(data));
+}
+
+#[test]
+fn append_and_seek() {
+    let mut flash = Flash::new(64);
+    let mut buffers = Buffers::new(64);
+    let mut fs = buffers.fs();
+    format_and_mount(&mut fs, &mut flash);
+
+    let mut expected = Vec::new();
+    for i in 0..6 {
+        let part = pattern(300 + i * 70, i as u8);
+        let flags = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::APPEND;
+        let handle = open(&mut fs, &mut flash, "log", flags).unwrap();
+        write(&mut fs, &mut flash, handle, &part);
+        close(&mut fs, &mut flash, handle);
+        expected.extend_from_slice(&part);
+    }
+    assert_eq!(read_file(&mut fs, &mut flash, "log"), Ok(expected.clone()));
+
+    let handle = open(
+        &mut fs,
+        &mut flash,
+        "log",
+        OpenFlags::READ | OpenFlags::WRITE,
+    )
+    .unwrap();
+    assert_eq!(fs.size(handle), Ok(expected.len() as u32));
+    fs.seek(handle, 1234).unwrap();
+    assert_eq!(read_to_end(&mut fs, &mut flash, handle), expected[1234..]);
+
+    assert_eq!(
+        fs.seek(handle, expected.len() as u32 + 1),
+        Err(Error::Invalid)
+    );
+    close(&mut fs, &mut flash, handle);
+}
+
+#[test]
+fn writes_in_place() {
+    let mut flash = Flash::new(64);
+    let mut buffers = Buffers::new(64);
+    let mut fs = buffers.fs();
+    format_and_mount(&mut fs, &mut flash);
+
+    let mut expected = pattern(3000, 1);
+    write_file(&mut fs, &mut flash, "data", &expected);
+    let flags = OpenFlags::READ | OpenFlags::WRITE;
+    let handle = open(&mut fs, &mut flash, "data", flags).unwrap();
+
+    // Each write lands in the middle of the previous rewrite, before it,
+    // after it or past the end of the file.
+    for (i, (pos, len)) in [(1000, 700), (2000, 150), (500, 30), (2900, 400), (0, 10)]
+        .into_iter()
+        .enumerate()
+    {
+        let part = pattern(len, i as u8 + 2);
+        fs.seek(handle, pos as u32).unwrap();
+        write(&mut fs, &mut flash, handle, &part);
+        if pos + len > expected.len() {
+            expected.resize(pos + len, 0);
+        }
+        expected[pos..pos + len].copy_from_slice(&part);
+
+        assert_eq!(fs.tell(handle), Ok((pos + len) as u32));
+        assert_eq!(fs.size(handle), Ok(expected.len() as u32));
+        fs.seek(handle, 0).unwrap();
+        assert_eq!(read_to_end(&mut fs, &mut flash, handle), expected);
+    }
+    close(&mut fs, &mut flash, handle);
+    assert_eq!(read_file(&mut fs, &mut flash, "data"), Ok(expected.clone()));
+
+    let mut fs = buffers.fs();
+    fs.mount().unwrap();
+    assert_eq!(run(&mut fs, &mut flash), Ok(Output::None));
+    assert_eq!(read_file(&mut fs, &mut flash, "data"), Ok(expected));
+}
+
+#[test]
+fn open_flags() {
+    let mut flash = Flash::new(32);
+    let mut buffers = Buffers::new(32);
+    let mut fs = buffers.fs();
+    format_and_mount(&mut fs, &mut flash);
+
+    assert_eq!(
+        open(&mut fs, &mut flash, "missing", OpenFlags::READ),
+        Err(Error::NotFound)
+    );
+    write_file(&mut fs, &mut flash, "a", b"hello");
+    let exclusive = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::EXCL;
+    assert_eq!(
+        open(&mut fs, &mut flash, "a", exclusive),
+        Err(Error::Exists)
+    );
+    assert_eq!(
+        open(&mut fs, &mut flash, "a/b", OpenFlags::READ),
+        Err(Error::NotDir)
+    );
+    assert_eq!(
+        open(&mut fs, &mut flash, "../a", OpenFlags::READ),
+        Err(Error::Unsupported)
+    );
+    assert_eq!(
+        open(&mut fs, &mut flash, "/", OpenFlags::READ),
+        Err(Error::IsDir)
+    );
+
+    let handle = open(&mut fs, &mut flash, "./a", OpenFlags::READ).unwrap();
+    assert_eq!(fs.write(handle, 1), Err(Error::Invalid));
+    close(&mut fs, &mut flash, handle);
+
+    write_file(&mut fs, &mut flash, "a", b"bye");
+    assert_eq!(read_file(&mut fs, &mut flash, "a"), Ok(b"bye".to_vec()));
+}
+
+#[test]
+fn directories() {
+    let mut flash = Flash::new(64);
+    let mut buffers = Buffers::new(64);
+    let mut fs = buffers.fs();
+    format_and_mount(&mut fs, &mut flash);
+
+    simple_op(&mut fs, &mut flash, "etc", true).unwrap();
+    simple_op(&mut fs, &mut flash, "apps", true).unwrap();
+    simple_op(&mut fs, &mut flash, "apps/sensor", true).unwrap();
+    assert_eq!(
+        simple_op(&mut fs, &mut flash, "apps", true),
+        Err(Error::Exists)
+    );
+    write_file(&mut fs, &mut flash, "zeta", &[1; 600]);
+    write_file(&mut fs, &mut flash, "apps/sensor/cal", b"12345");
+    write_file(&mut fs, &mut flash, "apps/b", b"");
+
+    assert_eq!(
+        list(&mut fs, &mut flash, "/"),
+        [
+            (String::from("apps"), EntryKind::Dir, 0),
+            (String::from("etc"), EntryKind::Dir, 0),
+            (String::from("zeta"), EntryKind::File, 600),
+        ]
+    );
+    assert_eq!(
+        list(&mut fs, &mut flash, "apps"),
+        [
+            (String::from("b"), EntryKind::File, 0),
+            (String::from("sensor"), EntryKind::Dir, 0),
+        ]
+    );
+
+    let len = set_path(&mut fs, "apps/sensor/cal");
+    fs.stat(len).unwrap();
+    assert_eq!(
+        run(&mut fs, &mut flash),
+        Ok(Output::Entry(Entry {
+            kind: EntryKind::File,
+            size: 5,
+            name_len: 0
+        }))
+    );
+
+    assert_eq!(
+        simple_op(&mut fs, &mut flash, "apps/sensor", false),
+        Err(Error::NotEmpty)
+    );
+    simple_op(&mut fs, &mut flash, "apps/sensor/cal", false).unwrap();
+    simple_op(&mut fs, &mut flash, "apps/sensor", false).unwrap();
+    // `etc` was created before `apps`, so it isn't next to the root in the
+    // list of metadata pairs.
+    simple_op(&mut fs, &mut flash, "etc", false).unwrap();
+    assert_eq!(
+        simple_op(&mut fs, &mut flash, "etc", false),
+        Err(Error::NotFound)
+    );
+
+    let mut fs = buffers.fs();
+    fs.mount().unwrap();
+    assert_eq!(run(&mut fs, &mut flash), Ok(Output::None));
+    assert_eq!(
+        list(&mut fs, &mut flash, ""),
+        [
+            (String::from("apps"), EntryKind::Dir, 0),
+            (String::from("zeta"), EntryKind::File, 600),
+        ]
+    );
+    assert_eq!(
+        list(&mut fs, &mut flash, "apps"),
+        [(String::from("b"), EntryKind::File, 0)]
+    );
+    assert_eq!(read_file(&mut fs, &mut flash, "zeta"), Ok(vec![1; 600]));
+}
+
+#[test]
+fn metadata_compaction() {
+    let mut flash = Flash::new(32);
+    let mut buffers = Buffers::new(32);
+    let mut fs = buffers.fs();
+    format_and_mount(&mut fs, &mut flash);
+
+    write_file(&mut fs, &mut flash, "keep", b"kept");
+    // Every create and remove appends a commit, so the root fills up and
+    // is compacted many times.
+    for i in 0..100 {
+        let name = std::format!("tmp{}", i % 3);
+        write_file(&mut fs, &mut flash, &name, &pattern(i, i as u8));
+        if i % 2 == 0 {
+            simple_op(&mut fs, &mut flash, &name, false).unwrap();
+        }
+    }
+
+    let revision = |block: usize| {
+        u32::from_le_bytes(flash.data[block * BLOCK_SIZE..][..4].try_into().unwrap())
+    };
+    assert!(revision(0).max(revision(1)) > 2);
+
+    let mut fs = buffers.fs();
+    fs.mount().unwrap();
+    assert_eq!(run(&mut fs, &mut flash), Ok(Output::None));
+    assert_eq!(read_file(&mut fs, &mut flash, "keep"), Ok(b"kept".to_vec()));
+    // The last writes were i = 99, 97 and 98, and even ones were removed.
+    assert_eq!(read_file(&mut fs, &mut flash, "tmp0"), Ok(pattern(99, 99)));
+    assert_eq!(read_file(&mut fs, &mut flash, "tmp1"), Ok(pattern(97, 97)));
+    assert_eq!(read_file(&mut fs, &mut flash, "tmp2"), Err(Error::NotFound));
+}
+
+#[test]
+fn reclaims_freed_blocks() {
+    let mut flash = Flash::new(24);
+    let mut buffers = Buffers::new(24);
+    let mut fs = buffers.fs();
+    format_and_mount(&mut fs, &mut flash);
+
+    // Each round uses most of the flash, so blocks freed by the previous
+    // round must be found again.
+    for round in 0..5u8 {
+        let data = pattern(8 * BLOCK_SIZE, round);
+        write_file(&mut fs, &mut flash, "big", &data);
+        assert_eq!(read_file(&mut fs, &mut flash, "big"), Ok(data));
+    }
+
+    let flags = OpenFlags::WRITE | OpenFlags::APPEND;
+    let handle = open(&mut fs, &mut flash, "big", flags).unwrap();
+    fs.buffer_mut()[..200].fill(0);
+    let mut result = Ok(Output::None);
+    for _ in 0..200 {
+        fs.write(handle, 200).unwrap();
+        result = run(&mut fs, &mut flash);
+        if result.is_err() {
+            break;
+        }
+    }
+    assert_eq!(result, Err(Error::NoSpace));
+    fs.release(handle).unwrap();
+    assert_eq!(
+        read_file(&mut fs, &mut flash, "big"),
+        Ok(pattern(8 * BLOCK_SIZE, 4))
+    );
+}
+
+#[test]
+fn open_files_follow_id_changes() {
+    let mut flash = Flash::new(32);
+    let mut buffers = Buffers::new(32);
+    let mut fs = buffers.fs();
+    format_and_mount(&mut fs, &mut flash);
+
+    let flags = OpenFlags::WRITE | OpenFlags::CREATE;
+    let m = open(&mut fs, &mut flash, "m", flags).unwrap();
+    write(&mut fs, &mut flash, m, b"middle");
+    // Created before and after "m" in the directory order.
+    write_file(&mut fs, &mut flash, "a", b"first");
+    write_file(&mut fs, &mut flash, "z", b"last");
+    simple_op(&mut fs, &mut flash, "a", false).unwrap();
+    close(&mut fs, &mut flash, m);
+
+    assert_eq!(read_file(&mut fs, &mut flash, "m"), Ok(b"middle".to_vec()));
+    assert_eq!(read_file(&mut fs, &mut flash, "z"), Ok(b"last".to_vec()));
+
+    // A removed file can't be used.
+    let m = open(&mut fs, &mut flash, "m", OpenFlags::READ).unwrap();
+    simple_op(&mut fs, &mut flash, "m", false).unwrap();
+    fs.read(m, 10).unwrap();
+    assert_eq!(run(&mut fs, &mut flash), Err(Error::NotFound));
+    close(&mut fs, &mut flash, m);
+}
+
+#[test]
+fn survives_power_loss() {
+    let old = pattern(1500, 1);
+    let new = pattern(2500, 2);
+
+    for budget in 0.. {
+        let mut flash = Flash::new(32);
+        let mut buffers = Buffers::new(32);
+        let mut fs = buffers.fs();
+        format_and_mount(&mut fs, &mut flash);
+        write_file(&mut fs, &mut flash, "old", &old);
+        write_file(&mut fs, &mut flash, "new", &old);
+
+        // Replace "new" and create a directory, losing power after `budget`
+        // erases and writes.
+        flash.budget = Some(budget);
+        let completed = (|| {
+            let flags = OpenFlags::WRITE | OpenFlags::TRUNC;
+            let len = set_path(&mut fs, "new");
+            fs.open(len, flags).unwrap();
+            let Output::File(handle) = try_run(&mut fs, &mut flash)?.unwrap() else {
+                unreachable!()
+            };
+            for chunk in new.chunks(250) {
+                fs.buffer_mut()[..chunk.len()].copy_from_slice(chunk);
+                fs.write(handle, chunk.len()).unwrap();
+                try_run(&mut fs, &mut flash)?.unwrap();
+            }
+            fs.close(handle).unwrap();
+            try_run(&mut fs, &mut flash)?.unwrap();
+            let len = set_path(&mut fs, "dir");
+            fs.mkdir(len).unwrap();
+            try_run(&mut fs, &mut flash)?.unwrap();
+            Some(())
+        })()
+        .is_some();
+        flash.budget = None;
+
+        let mut fs = buffers.fs();
+        fs.mount().unwrap();
+        assert_eq!(run(&mut fs, &mut flash), Ok(Output::None));
+        assert_eq!(read_file(&mut fs, &mut flash, "old"), Ok(old.clone()));
+        let contents = read_file(&mut fs, &mut flash, "new").unwrap();
+        assert!(
+            contents.is_empty() || contents == old || contents == new,
+            "bad contents after {} IOs",
+            budget
+        );
+
+        // The file system is still usable.
+        write_file(&mut fs, &mut flash, "after", b"ok");
+        assert_eq!(read_file(&mut fs, &mut flash, "after"), Ok(b"ok".to_vec()));
+
+        if completed {
+            assert_eq!(contents, new);
+            assert_eq!(list(&mut fs, &mut flash, "dir"), []);
+            break;
+        }
+    }
+}
+
+#[test]
+fn in_place_writes_survive_power_loss() {
+    let old = pattern(2000, 1);
+    let mut new = old.clone();
+    new[700..1200].copy_from_slice(&pattern(500, 2));
+
+    for budget in 0.. {
+        let mut flash = Flash::new(32);
+        let mut buffers = Buffers::new(32);
+        let mut fs = buffers.fs();
+        format_and_mount(&mut fs, &mut flash);
+        write_file(&mut fs, &mut flash, "data", &old);
+
+        flash.budget = Some(budget);
+        let completed = (|| {
+            let len = set_path(&mut fs, "data");
+            fs.open(len, OpenFlags::WRITE).unwrap();
+            let Output::File(handle) = try_run(&mut fs, &mut flash)?.unwrap() else {
+                unreachable!()
+            };
+            fs.seek(handle, 700).unwrap();
+            for chunk in new[700..1200].chunks(250) {
+                fs.buffer_mut()[..chunk.len()].copy_from_slice(chunk);
+                fs.write(handle, chunk.len()).unwrap();
+                try_run(&mut fs, &mut flash)?.unwrap();
+            }
+            fs.close(handle).unwrap();
+            try_run(&mut fs, &mut flash)?.unwrap();
+            Some(())
+        })()
+        .is_some();
+        flash.budget = None;
+
+        let mut fs = buffers.fs();
+        fs.mount().unwrap();
+        assert_eq!(run(&mut fs, &mut flash), Ok(Output::None));
+        let contents = read_file(&mut fs, &mut flash, "data").unwrap();
+        assert!(
+            contents == old || contents == new,
+            "bad contents after {} IOs",
+            budget
+        );
+        if completed {
+            assert_eq!(contents, new);
+            break;
+        }
+    }
+}
+
+#[test]
+fn splits_full_directories() {
+    let mut flash = Flash::new(64);
+    let mut buffers = Buffers::new(64);
+    let mut fs = buffers.fs();
+    format_and_mount(&mut fs, &mut flash);
+
+    // Kept open while the root is split under it.
+    let flags = OpenFlags::WRITE | OpenFlags::CREATE;
+    let kept = open(&mut fs, &mut flash, "file050", flags).unwrap();
+
+    // Empty files only take space in the metadata. Created out of order so
+    // entries are also added to pairs other than the last one.
+    let names: Vec<String> = (0..100)
+        .map(|i| std::format!("file{:03}", i * 37 % 100))
+        .collect();
+    for name in &names {
+        write_file(&mut fs, &mut flash, name, b"");
+    }
+    simple_op(&mut fs, &mut flash, "dir", true).unwrap();
+    write_file(&mut fs, &mut flash, "dir/inner", b"inner");
+    write(&mut fs, &mut flash, kept, b"kept");
+    close(&mut fs, &mut flash, kept);
+
+    let mut sorted = names.clone();
+    sorted.sort();
+    let mut expected: Vec<_> = sorted
+        .into_iter()
+        .map(|name| (name, EntryKind::File, 0))
+        .collect();
+    expected[50].2 = 4;
+    expected.push((String::from("dir"), EntryKind::Dir, 0));
+    expected.rotate_right(1);
+    assert_eq!(list(&mut fs, &mut flash, "/"), expected);
+
+    let mut fs = buffers.fs();
+    fs.mount().unwrap();
+    assert_eq!(run(&mut fs, &mut flash), Ok(Output::None));
+    assert_eq!(list(&mut fs, &mut flash, ""), expected);
+    assert_eq!(
+        read_file(&mut fs, &mut flash, "file050"),
+        Ok(b"kept".to_vec())
+    );
+    assert_eq!(
+        read_file(&mut fs, &mut flash, "dir/inner"),
+        Ok(b"inner".to_vec())
+    );
+
+    // Pairs emptied by removing their entries are dropped.
+    for name in &names {
+        simple_op(&mut fs, &mut flash, name, false).unwrap();
+    }
+    simple_op(&mut fs, &mut flash, "dir/inner", false).unwrap();
+    simple_op(&mut fs, &mut flash, "dir", false).unwrap();
+    assert_eq!(list(&mut fs, &mut flash, "/"), []);
+
+    let mut fs = buffers.fs();
+    fs.mount().unwrap();
+    assert_eq!(run(&mut fs, &mut flash), Ok(Output::None));
+    assert_eq!(list(&mut fs, &mut flash, "/"), []);
+    write_file(&mut fs, &mut flash, "again", b"again");
+    assert_eq!(
+        list(&mut fs, &mut flash, "/"),
+        [(String::from("again"), EntryKind::File, 5)]
+    );
+}
+
+/// Writes metadata blocks the way the C implementation commits them
+/// (`lfs_dir_commitattr()` and `lfs_dir_commitcrc()` of littlefs v2.9), so
+/// the tests can check images laid out by other implementations, including
+/// the v2.1 erased-state CRCs and the padding of commits.
+struct CMetadataBlock {
+    data: Vec<u8>,
+    off: usize,
+    ptag: u32,
+    crc: u32,
+}
+
+impl CMetadataBlock {
+    const PROG_SIZE: usize = 16;
+
+    fn new(rev: u32) -> Self {
+        let mut data = vec![0xff; BLOCK_SIZE];
+        data[..4].copy_from_slice(&rev.to_le_bytes());
+        Self {
+            crc: crc32(0xffff_ffff, &data[..4]),
+            data,
+            off: 4,
+            ptag: 0xffff_ffff,
+        }
+    }
+
+    fn attr(&mut self, type3: u16, id: u16, data: &[u8]) -> &mut Self {
+        let tag = ((type3 as u32) << 20) | ((id as u32) << 10) | data.len() as u32;
+        let raw = (tag ^ self.ptag).to_be_bytes();
+        self.crc = crc32(self.crc, &raw);
+        self.crc = crc32(self.crc, data);
+        self.data[self.off..self.off + 4].copy_from_slice(&raw);
+        self.data[self.off + 4..self.off + 4 + data.len()].copy_from_slice(data);
+        self.off += 4 + data.len();
+        self.ptag = tag & 0x7fff_ffff;
+        self
+    }
+
+    /// End the commit with an erased-state CRC of the next program unit and
+    /// the commit CRC, padded to the program size.
+    fn commit(&mut self) -> &mut Self {
+        let end = (self.off + 20)
+            .min(BLOCK_SIZE)
+            .next_multiple_of(Self::PROG_SIZE);
+        while self.off < end {
+            let mut noff = (end - (self.off + 4)).min(0x3fe) + self.off + 4;
+            if noff < end {
+                noff = noff.min(end - 20);
+            }
+
+            let mut eperturb = 0xff;
+            if noff >= end && noff <= BLOCK_SIZE - Self::PROG_SIZE {
+                eperturb = self.data[noff];
+                let erased = &self.data[noff..noff + Self::PROG_SIZE];
+                let mut fcrc = [0; 8];
+                fcrc[..4].copy_from_slice(&(Self::PROG_SIZE as u32).to_le_bytes());
+                fcrc[4..].copy_from_slice(&crc32(0xffff_ffff, erased).to_le_bytes());
+                self.attr(0x5ff, 0x3ff, &fcrc);
+            }
+
+            let ntag = ((0x500 + (!eperturb >> 7) as u32) << 20)
+                | (0x3ff << 10)
+                | (noff - (self.off + 4)) as u32;
+            let raw = (ntag ^ self.ptag).to_be_bytes();
+            self.crc = crc32(self.crc, &raw);
+            self.data[self.off..self.off + 4].copy_from_slice(&raw);
+            self.data[self.off + 4..self.off + 8].copy_from_slice(&self.crc.to_le_bytes());
+
+            self.off = noff;
+            self.ptag = ntag ^ ((0x80 & !eperturb as u32) << 24);
+            self.crc = 0xffff_ffff;
+        }
+        self
+    }
+}
+
+/// Build a v2.1 image with the layout the C implementation leaves after
+/// formatting and then:
+///
+///  * creating `hello.txt`, `stale.txt` and the directory `logs`, and
+///    compacting the root,
+///  * writing the 1500 byte `logs/boot.log`,
+///  * creating the directory `many` with ten files, which splits it over two
+///    metadata pairs,
+///  * rewriting `hello.txt` and removing `stale.txt`.
+fn c_image() -> Flash {
+    const ROOT: [u32; 2] = [0, 1];
+    const LOGS: [u32; 2] = [2, 3];
+    const MANY: [u32; 2] = [4, 5];
+    const MANY_SPLIT: [u32; 2] = [6, 7];
+    const BOOT_LOG: [u32; 3] = [8, 9, 10];
+
+    let pair = |pair: [u32; 2]| {
+        let mut data = [0; 8];
+        data[..4].copy_from_slice(&pair[0].to_le_bytes());
+        data[4..].copy_from_slice(&pair[1].to_le_bytes());
+        data
+    };
+    let mut superblock = [0; 24];
+    for (i, value) in [0x0002_0001, BLOCK_SIZE as u32, 32, 255, 0x7fff_ffff, 1022]
+        .iter()
+        .enumerate()
+    {
+        superblock[i * 4..i * 4 + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    let mut flash = Flash::new(32);
+    let mut write = |block: u32, data: &[u8]| flash.block(block).copy_from_slice(data);
+
+    // The root before it was compacted into its other block.
+    let mut old_root = CMetadataBlock::new(1);
+    old_root
+        .attr(0x0ff, 0, b"littlefs")
+        .attr(0x201, 0, &superblock)
+        .commit();
+    write(ROOT[0], &old_root.data);
+
+    let mut root = CMetadataBlock::new(2);
+    root.attr(0x0ff, 0, b"littlefs")
+        .attr(0x201, 0, &superblock)
+        .attr(0x001, 1, b"hello.txt")
+        .attr(0x201, 1, b"Hello, world!\n")
+        .attr(0x374, 1, b"custom attribute")
+        .attr(0x002, 2, b"logs")
+        .attr(0x200, 2, &pair(LOGS))
+        .attr(0x001, 3, b"stale.txt")
+        .attr(0x201, 3, b"remove me")
+        .attr(0x600, 0x3ff, &pair(LOGS))
+        .commit();
+    // mkdir("many") sorts it before "stale.txt".
+    root.attr(0x401, 3, &[])
+        .attr(0x002, 3, b"many")
+        .attr(0x200, 3, &pair(MANY))
+        .attr(0x600, 0x3ff, &pair(MANY))
+        .commit();
+    root.attr(0x201, 1, b"Hello from the C implementation!\n")
+        .attr(0x374, 1, b"custom attribute")
+        .commit();
+    root.attr(0x4ff, 4, &[]).commit();
+    write(ROOT[1], &root.data);
+
+    let mut logs = CMetadataBlock::new(1);
+    logs.attr(0x600, 0x3ff, &pair([0xffff_ffff; 2])).commit();
+    logs.attr(0x401, 0, &[])
+        .attr(0x001, 0, b"boot.log")
+        .attr(0x202, 0, &{
+            let mut ctz = [0; 8];
+            ctz[..4].copy_from_slice(&BOOT_LOG[2].to_le_bytes());
+            ctz[4..].copy_from_slice(&1500u32.to_le_bytes());
+            ctz
+        })
+        .commit();
+    write(LOGS[0], &logs.data);
+
+    // Each block of the skip-list starts with pointers to the previous
+    // blocks.
+    let boot_log = pattern(1500, 3);
+    let mut block = vec![0xff; BLOCK_SIZE];
+    block.copy_from_slice(&boot_log[..512]);
+    write(BOOT_LOG[0], &block);
+    block[..4].copy_from_slice(&BOOT_LOG[0].to_le_bytes());
+    block[4..].copy_from_slice(&boot_log[512..1020]);
+    write(BOOT_LOG[1], &block);
+    block.fill(0xff);
+    block[..4].copy_from_slice(&BOOT_LOG[1].to_le_bytes());
+    block[4..8].copy_from_slice(&BOOT_LOG[0].to_le_bytes());
+    block[8..488].copy_from_slice(&boot_log[1020..]);
+    write(BOOT_LOG[2], &block);
+
+    // The first half of `many` ends with a hard tail to the second half,
+    // which continues the list of metadata pairs to `logs`.
+    let mut many = CMetadataBlock::new(1);
+    for i in 0..5u8 {
+        many.attr(0x001, i as u16, std::format!("file{:02}", i).as_bytes())
+            .attr(0x201, i as u16, &[b'0' + i; 3]);
+    }
+    many.attr(0x601, 0x3ff, &pair(MANY_SPLIT)).commit();
+    write(MANY[0], &many.data);
+
+    let mut many_split = CMetadataBlock::new(1);
+    for i in 0..5u8 {
+        many_split
+            .attr(0x001, i as u16, std::format!("file{:02}", i + 5).as_bytes())
+            .attr(0x201, i as u16, &[b'5' + i; 3]);
+    }
+    many_split.attr(0x600, 0x3ff, &pair(LOGS)).commit();
+    write(MANY_SPLIT[0], &many_split.data);
+
+    flash
+}
+
+#[test]
+fn reads_image_laid_out_like_c_implementation() {
+    let mut flash = c_image();
+    let mut buffers = Buffers::new(32);
+    let mut fs = buffers.fs();
+    fs.mount().unwrap();
+    assert_eq!(run(&mut fs, &mut flash), Ok(Output::None));
+
+    assert_eq!(
+        list(&mut fs, &mut flash, "/"),
+        [
+            (String::from("hello.txt"), EntryKind::File, 33),
+            (String::from("logs"), EntryKind::Dir, 0),
+            (String::from("many"), EntryKind::Dir, 0),
+        ]
+    );
+    assert_eq!(
+        read_file(&mut fs, &mut flash, "hello.txt"),
+        Ok(b"Hello from the C implementation!\n".to_vec())
+    );
+    assert_eq!(
+        read_file(&mut fs, &mut flash, "stale.txt"),
+        Err(Error::NotFound)
+    );
+    assert_eq!(
+        read_file(&mut fs, &mut flash, "logs/boot.log"),
+        Ok(pattern(1500, 3))
+    );
+
+    let many = list(&mut fs, &mut flash, "many");
+    assert_eq!(many.len(), 10);
+    for (i, (name, kind, size)) in many.iter().enumerate() {
+        assert_eq!(*name, std::format!("file{:02}", i));
+        assert_eq!((*kind, *size), (EntryKind::File, 3));
+    }
+    assert_eq!(
+        read_file(&mut fs, &mut flash, "many/file07"),
+        Ok(b"777".to_vec())
+    );
+}
+
+#[test]
+fn writes_to_image_laid_out_like_c_implementation() {
+    let mut flash = c_image();
+    let mut buffers = Buffers::new(32);
+    let mut fs = buffers.fs();
+    fs.mount().unwrap();
+    assert_eq!(run(&mut fs, &mut flash), Ok(Output::None));
+
+    // Appending moves the inline file out of the metadata.
+    let flags = OpenFlags::WRITE | OpenFlags::APPEND;
+    let handle = open(&mut fs, &mut flash, "hello.txt", flags).unwrap();
+    write(&mut fs, &mut flash, handle, b"And from Tock.\n");
+    close(&mut fs, &mut flash, handle);
+    let flags = OpenFlags::WRITE | OpenFlags::APPEND;
+    let handle = open(&mut fs, &mut flash, "logs/boot.log", flags).unwrap();
+    write(&mut fs, &mut flash, handle, &pattern(700, 4));
+    close(&mut fs, &mut flash, handle);
+    write_file(&mut fs, &mut flash, "logs/new.log", &pattern(900, 5));
+    simple_op(&mut fs, &mut flash, "stale.txt", true).unwrap();
+    // The new directory goes in the last pair of the split directory.
+    simple_op(&mut fs, &mut flash, "many/file10", true).unwrap();
+    simple_op(&mut fs, &mut flash, "many/file03", false).unwrap();
+
+    let mut fs = buffers.fs();
+    fs.mount().unwrap();
+    assert_eq!(run(&mut fs, &mut flash), Ok(Output::None));
+    assert_eq!(
+        read_file(&mut fs, &mut flash, "hello.txt"),
+        Ok(b"Hello from the C implementation!\nAnd from Tock.\n".to_vec())
+    );
+    let mut boot_log = pattern(1500, 3);
+    boot_log.extend(pattern(700, 4));
+    assert_eq!(
+        read_file(&mut fs, &mut flash, "logs/boot.log"),
+        Ok(boot_log)
+    );
+    assert_eq!(
+        read_file(&mut fs, &mut flash, "logs/new.log"),
+        Ok(pattern(900, 5))
+    );
+    assert_eq!(list(&mut fs, &mut flash, "stale.txt"), []);
+    let many = list(&mut fs, &mut flash, "many");
+    assert_eq!(many.len(), 10);
+    assert!(!many.iter().any(|(name, _, _)| name == "file03"));
+    assert_eq!(many[9], (String::from("file10"), EntryKind::Dir, 0));
+    assert_eq!(
+        read_file(&mut fs, &mut flash, "many/file09"),
+        Ok(b"999".to_vec())
+    );
+}
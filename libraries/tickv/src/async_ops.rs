@@ -129,7 +129,7 @@
 use crate::error_codes::ErrorCode;
 use crate::flash_controller::FlashController;
 use crate::success_codes::SuccessCode;
-use crate::tickv::{State, TicKV};
+use crate::tickv::{State, Stats, TicKV};
 use core::cell::Cell;
 
 /// The return type from the continue operation
@@ -149,6 +149,7 @@ pub struct AsyncTicKV<'a, C: FlashController<S>, const S: usize> {
     key: Cell<Option<u64>>,
     value: Cell<Option<&'static mut [u8]>>,
     value_length: Cell<usize>,
+    stats: Cell<Option<Stats>>,
 }
 
 impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
@@ -164,6 +165,7 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
             key: Cell::new(None),
             value: Cell::new(None),
             value_length: Cell::new(0),
+            stats: Cell::new(None),
         }
     }
 
@@ -308,6 +310,40 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
         }
     }
 
+    /// Collect usage and wear statistics for all regions.
+    ///
+    /// Once the operation has completed the statistics can be retrieved with
+    /// `take_stats()`.
+    ///
+    /// On success a `SuccessCode` will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn stats(&self) -> Result<SuccessCode, ErrorCode> {
+        match self.tickv.stats() {
+            Ok(_stats) => {
+                // Ok is a problem, since that means no asynchronous operations
+                // were called, which means our client will never get a
+                // callback. We need to error.
+                Err(ErrorCode::ReadFail)
+            }
+            Err(e) => match e {
+                ErrorCode::ReadNotReady(_) => Ok(SuccessCode::Queued),
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Retrieve the statistics collected by the last completed `stats()`
+    /// operation.
+    pub fn take_stats(&self) -> Option<Stats> {
+        self.stats.take()
+    }
+
+    /// Returns true if an operation is waiting on a flash operation and
+    /// needs to be continued with `continue_operation()`.
+    pub fn operation_pending(&self) -> bool {
+        self.tickv.state.get() != State::None
+    }
+
     /// Copy data from `read_buffer` argument to the internal read_buffer.
     /// This should be used to copy the data that the implementation wanted
     /// to read when calling `read_region` after the async operation has
@@ -362,6 +398,13 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
                 Ok(bytes_freed) => (Ok(SuccessCode::Complete), bytes_freed),
                 Err(e) => (Err(e), 0),
             },
+            State::Stats(_) => match self.tickv.stats() {
+                Ok(stats) => {
+                    self.stats.set(Some(stats));
+                    (Ok(SuccessCode::Complete), 0)
+                }
+                Err(e) => (Err(e), 0),
+            },
             _ => unreachable!(),
         };
 
@@ -371,11 +414,12 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
                 (ret, self.value.take(), length)
             }
             Err(e) => match e {
-                ErrorCode::ReadNotReady(_) | ErrorCode::EraseNotReady(_) => (ret, None, 0),
-                ErrorCode::WriteNotReady(_) => {
-                    self.tickv.state.set(State::None);
-                    (ret, None, 0)
-                }
+                // A completed write of an object is reported as `Queued`, so
+                // `WriteNotReady` means that a region header is being written
+                // in the middle of an operation.
+                ErrorCode::ReadNotReady(_)
+                | ErrorCode::EraseNotReady(_)
+                | ErrorCode::WriteNotReady(_) => (ret, None, 0),
                 _ => {
                     self.tickv.state.set(State::None);
                     (ret, self.value.take(), length)
@@ -395,7 +439,9 @@ mod tests {
         use crate::error_codes::ErrorCode;
         use crate::flash_controller::FlashController;
         use crate::success_codes::SuccessCode;
-        use crate::tickv::{HASH_OFFSET, LEN_OFFSET, MAIN_KEY, VERSION, VERSION_OFFSET};
+        use crate::tickv::{
+            HASH_OFFSET, LEN_OFFSET, MAIN_KEY, REGION_HEADER_LENGTH, VERSION, VERSION_OFFSET,
+        };
         use core::hash::{Hash, Hasher};
         use core::ptr::addr_of_mut;
         use std::cell::Cell;
@@ -421,10 +467,10 @@ mod tests {
             assert_eq!(buf[HASH_OFFSET + 7], 0x44);
 
             // Check the check hash
-            assert_eq!(buf[HASH_OFFSET + 8], 0xbb);
-            assert_eq!(buf[HASH_OFFSET + 9], 0x32);
-            assert_eq!(buf[HASH_OFFSET + 10], 0x74);
-            assert_eq!(buf[HASH_OFFSET + 11], 0x1d);
+            assert_eq!(buf[HASH_OFFSET + 8], 0x3e);
+            assert_eq!(buf[HASH_OFFSET + 9], 0xa7);
+            assert_eq!(buf[HASH_OFFSET + 10], 0x40);
+            assert_eq!(buf[HASH_OFFSET + 11], 0x13);
         }
 
         fn check_region_one(buf: &[u8]) {
@@ -451,10 +497,10 @@ mod tests {
             assert_eq!(buf[42], 0x23);
 
             // Check the check hash
-            assert_eq!(buf[43], 0xfd);
-            assert_eq!(buf[44], 0x24);
-            assert_eq!(buf[45], 0xf0);
-            assert_eq!(buf[46], 0x07);
+            assert_eq!(buf[43], 0x54);
+            assert_eq!(buf[44], 0x72);
+            assert_eq!(buf[45], 0xf4);
+            assert_eq!(buf[46], 0x31);
         }
 
         fn check_region_two(buf: &[u8]) {
@@ -481,10 +527,10 @@ mod tests {
             assert_eq!(buf[42], 0x23);
 
             // Check the check hash
-            assert_eq!(buf[43], 0x1b);
-            assert_eq!(buf[44], 0x53);
-            assert_eq!(buf[45], 0xf9);
-            assert_eq!(buf[46], 0x54);
+            assert_eq!(buf[43], 0xb2);
+            assert_eq!(buf[44], 0x05);
+            assert_eq!(buf[45], 0xfd);
+            assert_eq!(buf[46], 0x62);
         }
 
         fn get_hashed_key(unhashed_key: &[u8]) -> u64 {
@@ -556,6 +602,12 @@ mod tests {
                     self.buf.borrow_mut()[address / S][(address % S) + i] = *d;
                 }
 
+                // Region headers are written after every erase, don't count them
+                if buf.len() == REGION_HEADER_LENGTH {
+                    self.waiting_on.set(FlashCtrlAction::Write);
+                    return Err(ErrorCode::WriteNotReady(address));
+                }
+
                 // Check to see if we are adding a key
                 if buf.len() > 1 && self.check_write_contents {
                     if self.run.get() == 0 {
@@ -0,0 +1,232 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Build and inspect TicKV flash images on a host.
+//!
+//! ```text
+//! tickv-image build --region-size <bytes> --size <bytes> [--write-id <id>] <manifest> <image>
+//! tickv-image dump --region-size <bytes> <image>
+//! tickv-image verify --region-size <bytes> <image>
+//! tickv-image gc --region-size <bytes> <image>
+//! ```
+//!
+//! See `tickv::image::parse_manifest()` for the manifest format.
+
+use std::path::Path;
+use std::process::ExitCode;
+use tickv::image::{self, ImageError, RegionHeaderInfo};
+
+const USAGE: &str = "\
+Usage:
+  tickv-image build --region-size <bytes> --size <bytes> [--write-id <id>] <manifest> <image>
+  tickv-image dump --region-size <bytes> <image>
+  tickv-image verify --region-size <bytes> <image>
+  tickv-image gc --region-size <bytes> <image>
+
+  build   Create a new image containing the keys listed in the manifest
+  dump    Print the regions and objects stored in an image
+  verify  Check the region headers and object checksums of an image
+  gc      Run garbage collection on an image, modifying it in place
+
+  --region-size  The size of a flash region (the erase unit)
+  --size         The total size of the image
+  --write-id     Add a Tock KV permissions header with this write ID to
+                 every value, as required by the Tock KV driver
+
+Numbers can be decimal or hex (0x...).
+";
+
+/// Region sizes the tool can be used with.
+const REGION_SIZES: &[usize] = &[256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536];
+
+/// Call `$f::<S>($args)` where `S` is the `usize` region size.
+macro_rules! with_region_size {
+    ($size:expr, $f:ident($($args:expr),*)) => {
+        match $size {
+            256 => $f::<256>($($args),*),
+            512 => $f::<512>($($args),*),
+            1024 => $f::<1024>($($args),*),
+            2048 => $f::<2048>($($args),*),
+            4096 => $f::<4096>($($args),*),
+            8192 => $f::<8192>($($args),*),
+            16384 => $f::<16384>($($args),*),
+            32768 => $f::<32768>($($args),*),
+            65536 => $f::<65536>($($args),*),
+            _ => unreachable!(),
+        }
+    };
+}
+
+struct Args {
+    command: String,
+    region_size: Option<usize>,
+    size: Option<usize>,
+    write_id: Option<u32>,
+    files: Vec<String>,
+}
+
+fn parse_number(arg: &str) -> Result<usize, String> {
+    let parsed = match arg.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => arg.parse(),
+    };
+
+    parsed.map_err(|_| format!("invalid number {:?}", arg))
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = std::env::args().skip(1);
+    let command = args.next().ok_or("missing command")?;
+    let mut parsed = Args {
+        command,
+        region_size: None,
+        size: None,
+        write_id: None,
+        files: Vec::new(),
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+
+        match arg.as_str() {
+            "--region-size" => parsed.region_size = Some(parse_number(&value()?)?),
+            "--size" => parsed.size = Some(parse_number(&value()?)?),
+            "--write-id" => {
+                let id = parse_number(&value()?)?;
+                parsed.write_id = Some(u32::try_from(id).map_err(|_| "write ID is too large")?);
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => parsed.files.push(arg),
+        }
+    }
+
+    Ok(parsed)
+}
+
+fn build<const S: usize>(args: &Args) -> Result<(), ImageError> {
+    let manifest_path = Path::new(&args.files[0]);
+    let manifest = std::fs::read_to_string(manifest_path)?;
+    let base = manifest_path.parent().unwrap_or(Path::new("."));
+
+    let entries = image::parse_manifest(&manifest, |path| std::fs::read(base.join(path)))?;
+    let image = image::build_image::<S>(args.size.unwrap(), &entries, args.write_id)?;
+    std::fs::write(&args.files[1], image)?;
+
+    println!("Wrote {} keys to {}", entries.len(), args.files[1]);
+    Ok(())
+}
+
+fn garbage_collect<const S: usize>(args: &Args) -> Result<(), ImageError> {
+    let image = std::fs::read(&args.files[0])?;
+    let (image, freed) = image::garbage_collect_image::<S>(image)?;
+    std::fs::write(&args.files[0], image)?;
+
+    println!("Freed {} bytes", freed);
+    Ok(())
+}
+
+/// Print the content of the image. Returns false if it contains errors.
+fn inspect(args: &Args, print_objects: bool) -> Result<bool, ImageError> {
+    let image = std::fs::read(&args.files[0])?;
+    let regions = image::inspect_image(&image, args.region_size.unwrap())?;
+    let mut ok = true;
+
+    for region in &regions {
+        if print_objects || !region.is_ok() {
+            match region.header {
+                RegionHeaderInfo::Erased => println!("Region {}: no header", region.region),
+                RegionHeaderInfo::Legacy => {
+                    println!("Region {}: version 1, no header", region.region)
+                }
+                RegionHeaderInfo::Valid {
+                    erase_count,
+                    reclaimed,
+                } => println!(
+                    "Region {}: erase count {}{}, {} bytes free",
+                    region.region,
+                    erase_count,
+                    if reclaimed { " (reclaimed)" } else { "" },
+                    region.free_bytes
+                ),
+                RegionHeaderInfo::Unknown(magic) => println!(
+                    "Region {}: not a TicKV region (magic {:#04x})",
+                    region.region, magic
+                ),
+            }
+        }
+
+        for object in &region.objects {
+            let check_sum = match object.check_sum_ok {
+                Some(true) => "ok",
+                Some(false) => "BAD CHECKSUM",
+                None => "invalid",
+            };
+
+            if print_objects || object.check_sum_ok == Some(false) {
+                println!(
+                    "  {:#010x}: key {:#018x}, {} bytes, {}",
+                    object.address, object.hashed_key, object.value_length, check_sum
+                );
+            }
+        }
+
+        if let Some(e) = region.error {
+            println!("  Unable to parse the remaining objects: {:?}", e);
+        }
+
+        ok &= region.is_ok();
+    }
+
+    Ok(ok)
+}
+
+fn run(args: &Args) -> Result<bool, String> {
+    let files = match args.command.as_str() {
+        "build" => 2,
+        "dump" | "verify" | "gc" => 1,
+        _ => return Err(format!("unknown command {:?}", args.command)),
+    };
+    if args.files.len() != files {
+        return Err(format!("expected {} file arguments", files));
+    }
+
+    let region_size = args.region_size.ok_or("missing --region-size")?;
+    if !REGION_SIZES.contains(&region_size) {
+        return Err(format!(
+            "unsupported region size {}, supported sizes are {:?}",
+            region_size, REGION_SIZES
+        ));
+    }
+
+    let result = match args.command.as_str() {
+        "build" => {
+            if args.size.is_none() {
+                return Err(String::from("missing --size"));
+            }
+            with_region_size!(region_size, build(args)).map(|()| true)
+        }
+        "gc" => with_region_size!(region_size, garbage_collect(args)).map(|()| true),
+        "dump" => inspect(args, true),
+        _ => inspect(args, false).inspect(|ok| {
+            if *ok {
+                println!("Image is valid");
+            }
+        }),
+    };
+
+    result.map_err(|e| e.to_string())
+}
+
+fn main() -> ExitCode {
+    let result = parse_args().and_then(|args| run(&args));
+
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            ExitCode::FAILURE
+        }
+    }
+}
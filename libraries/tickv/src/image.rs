@@ -0,0 +1,672 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Host side support for building and inspecting TicKV flash images.
+//!
+//! This is only available with the `std` feature and is used by the
+//! `tickv-image` tool. It allows creating a TicKV store on a host (for
+//! example to pre-populate the store when provisioning boards), as well as
+//! dumping, verifying and garbage collecting an image read back from a
+//! device.
+//!
+//! Keys are hashed the same way as the Tock TicKV capsule does it, so images
+//! built here can be used directly by a Tock board.
+
+use crate::crc32;
+use crate::error_codes::ErrorCode;
+use crate::flash_controller::FlashController;
+use crate::tickv::{
+    CHECK_SUM_LEN, FLAGS_VALID, HASH_OFFSET, HEADER_LENGTH, LEGACY_VERSION, LEN_OFFSET,
+    REGION_ERASE_COUNT_OFFSET, REGION_FLAGS_FRESH, REGION_FLAGS_OFFSET, REGION_HEADER_LENGTH,
+    REGION_MAGIC, REGION_MAGIC_OFFSET, TicKV, VERSION, VERSION_OFFSET,
+};
+use core::cell::RefCell;
+use core::fmt;
+use std::boxed::Box;
+use std::format;
+use std::string::String;
+use std::vec;
+use std::vec::Vec;
+
+/// The hashed `MAIN_KEY` used by the Tock TicKV capsule.
+pub const TOCK_MAIN_KEY_HASH: u64 = 0x7bc9f7ff4f76f244;
+
+/// Length of the header the Tock KV permissions layer adds to every value.
+pub const TOCK_KV_HEADER_LENGTH: usize = 9;
+
+/// Errors from building or inspecting an image.
+#[derive(Debug)]
+pub enum ImageError {
+    /// Reading or writing a file failed.
+    Io(std::io::Error),
+    /// A line of the manifest couldn't be parsed.
+    Manifest {
+        /// The line number, starting from 1.
+        line: usize,
+        /// What is wrong with the line.
+        reason: String,
+    },
+    /// The image size doesn't fit the region size.
+    InvalidSize,
+    /// A TicKV operation failed.
+    TicKV(ErrorCode),
+    /// Adding a key from the manifest failed.
+    Key {
+        /// The unhashed key.
+        key: String,
+        /// The TicKV error.
+        error: ErrorCode,
+    },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::Io(e) => write!(f, "{}", e),
+            ImageError::Manifest { line, reason } => {
+                write!(f, "manifest line {}: {}", line, reason)
+            }
+            ImageError::InvalidSize => {
+                write!(f, "the image size must be a multiple of the region size")
+            }
+            ImageError::TicKV(e) => write!(f, "TicKV error: {:?}", e),
+            ImageError::Key { key, error } => write!(f, "unable to add key {:?}: {:?}", key, error),
+        }
+    }
+}
+
+impl From<std::io::Error> for ImageError {
+    fn from(e: std::io::Error) -> Self {
+        ImageError::Io(e)
+    }
+}
+
+impl From<ErrorCode> for ImageError {
+    fn from(e: ErrorCode) -> Self {
+        ImageError::TicKV(e)
+    }
+}
+
+/// Hash `key` the same way the Tock TicKV capsule does.
+///
+/// Tock hashes keys with SipHash-2-4 (with zero keys), stores the digest as
+/// little endian bytes and then converts those bytes to the TicKV key as a
+/// big endian value.
+pub fn tock_key_hash(key: &[u8]) -> u64 {
+    siphash24(key).swap_bytes()
+}
+
+/// SipHash-2-4 with zero keys.
+fn siphash24(data: &[u8]) -> u64 {
+    let mut v = [
+        0x736f6d6570736575u64,
+        0x646f72616e646f6d,
+        0x6c7967656e657261,
+        0x7465646279746573,
+    ];
+
+    fn sip_round(v: &mut [u64; 4]) {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    }
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let m = u64::from_le_bytes(chunk.try_into().unwrap());
+        v[3] ^= m;
+        sip_round(&mut v);
+        sip_round(&mut v);
+        v[0] ^= m;
+    }
+
+    let mut last = (data.len() as u64) << 56;
+    for (i, b) in chunks.remainder().iter().enumerate() {
+        last |= (*b as u64) << (8 * i);
+    }
+    v[3] ^= last;
+    sip_round(&mut v);
+    sip_round(&mut v);
+    v[0] ^= last;
+
+    v[2] ^= 0xFF;
+    for _ in 0..4 {
+        sip_round(&mut v);
+    }
+
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+/// Prefix `value` with the header the Tock KV permissions layer expects.
+///
+/// `write_id` is the storage write ID of the app (or kernel) that is allowed
+/// to modify the value.
+pub fn tock_kv_value(value: &[u8], write_id: u32) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(TOCK_KV_HEADER_LENGTH + value.len());
+
+    // Header version
+    buf.push(0);
+    buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&write_id.to_le_bytes());
+    buf.extend_from_slice(value);
+
+    buf
+}
+
+/// A single key/value pair from a manifest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ManifestEntry {
+    /// The unhashed key.
+    pub key: String,
+    /// The value to store.
+    pub value: Vec<u8>,
+}
+
+/// Parse a key/value manifest.
+///
+/// Every line of the manifest has the format `key = value`. Empty lines and
+/// lines starting with `#` are ignored. Values can be:
+///
+/// - `hex:0123abcd`: The bytes encoded as hex.
+/// - `file:path`: The content of a file. Relative paths are resolved with
+///   `read_file`.
+/// - Anything else is stored as the UTF-8 text, with surrounding whitespace
+///   removed.
+pub fn parse_manifest(
+    manifest: &str,
+    read_file: impl Fn(&str) -> Result<Vec<u8>, std::io::Error>,
+) -> Result<Vec<ManifestEntry>, ImageError> {
+    let mut entries = Vec::new();
+
+    for (i, line) in manifest.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let error = |reason: String| ImageError::Manifest {
+            line: i + 1,
+            reason,
+        };
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error(String::from("expected `key = value`")))?;
+        let key = key.trim();
+        let value = value.trim();
+
+        if key.is_empty() {
+            return Err(error(String::from("empty key")));
+        }
+
+        let value = if let Some(hex) = value.strip_prefix("hex:") {
+            parse_hex(hex).ok_or_else(|| error(format!("invalid hex value {:?}", hex)))?
+        } else if let Some(path) = value.strip_prefix("file:") {
+            read_file(path).map_err(|e| error(format!("unable to read {:?}: {}", path, e)))?
+        } else {
+            value.as_bytes().to_vec()
+        };
+
+        entries.push(ManifestEntry {
+            key: String::from(key),
+            value,
+        });
+    }
+
+    Ok(entries)
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// A `FlashController` backed by an in-memory flash image.
+///
+/// Like NOR flash, writes can only clear bits and erasing a region sets all
+/// of its bits.
+pub struct ImageFlash<const S: usize> {
+    image: RefCell<Vec<u8>>,
+}
+
+impl<const S: usize> ImageFlash<S> {
+    /// Create a new erased image of `flash_size` bytes.
+    pub fn new(flash_size: usize) -> Result<Self, ImageError> {
+        Self::from_bytes(vec![0xFF; flash_size])
+    }
+
+    /// Use an existing flash image.
+    pub fn from_bytes(image: Vec<u8>) -> Result<Self, ImageError> {
+        if image.is_empty() || !image.len().is_multiple_of(S) {
+            return Err(ImageError::InvalidSize);
+        }
+
+        Ok(Self {
+            image: RefCell::new(image),
+        })
+    }
+
+    /// The size of the image in bytes.
+    pub fn len(&self) -> usize {
+        self.image.borrow().len()
+    }
+
+    /// Return the flash image.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.image.into_inner()
+    }
+}
+
+impl<const S: usize> FlashController<S> for ImageFlash<S> {
+    fn read_region(&self, region_number: usize, buf: &mut [u8; S]) -> Result<(), ErrorCode> {
+        let image = self.image.borrow();
+        let region = image
+            .get(region_number * S..(region_number + 1) * S)
+            .ok_or(ErrorCode::ReadFail)?;
+
+        buf.copy_from_slice(region);
+
+        Ok(())
+    }
+
+    fn write(&self, address: usize, buf: &[u8]) -> Result<(), ErrorCode> {
+        let mut image = self.image.borrow_mut();
+        let dest = image
+            .get_mut(address..address + buf.len())
+            .ok_or(ErrorCode::WriteFail)?;
+
+        for (d, b) in dest.iter_mut().zip(buf) {
+            *d &= *b;
+        }
+
+        Ok(())
+    }
+
+    fn erase_region(&self, region_number: usize) -> Result<(), ErrorCode> {
+        let mut image = self.image.borrow_mut();
+        image
+            .get_mut(region_number * S..(region_number + 1) * S)
+            .ok_or(ErrorCode::EraseFail)?
+            .fill(0xFF);
+
+        Ok(())
+    }
+}
+
+/// Create a TicKV instance using `flash`.
+fn open<const S: usize>(flash: ImageFlash<S>) -> TicKV<'static, ImageFlash<S>, S> {
+    let flash_size = flash.len();
+    // The read buffer is only used for the lifetime of the tool.
+    let read_buffer: &'static mut [u8; S] = Box::leak(Box::new([0; S]));
+
+    TicKV::new(flash, read_buffer, flash_size)
+}
+
+/// Build a new image of `flash_size` bytes containing `entries`.
+///
+/// If `write_id` is set every value is prefixed with a Tock KV permissions
+/// header for that write ID.
+pub fn build_image<const S: usize>(
+    flash_size: usize,
+    entries: &[ManifestEntry],
+    write_id: Option<u32>,
+) -> Result<Vec<u8>, ImageError> {
+    let tickv = open(ImageFlash::<S>::new(flash_size)?);
+    tickv.initialise(TOCK_MAIN_KEY_HASH)?;
+
+    for entry in entries {
+        let value = match write_id {
+            Some(id) => tock_kv_value(&entry.value, id),
+            None => entry.value.clone(),
+        };
+
+        tickv
+            .append_key(tock_key_hash(entry.key.as_bytes()), &value)
+            .map_err(|error| ImageError::Key {
+                key: entry.key.clone(),
+                error,
+            })?;
+    }
+
+    Ok(tickv.controller.into_bytes())
+}
+
+/// Garbage collect an existing image.
+///
+/// Returns the new image and the number of bytes that were freed.
+pub fn garbage_collect_image<const S: usize>(
+    image: Vec<u8>,
+) -> Result<(Vec<u8>, usize), ImageError> {
+    let tickv = open(ImageFlash::<S>::from_bytes(image)?);
+    let freed = tickv.garbage_collect()?;
+
+    Ok((tickv.controller.into_bytes(), freed))
+}
+
+/// The state of a region header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RegionHeaderInfo {
+    /// The region header hasn't been written.
+    Erased,
+    /// The region header is valid.
+    Valid {
+        /// The number of times the region has been erased.
+        erase_count: u32,
+        /// The region has been erased by garbage collection.
+        reclaimed: bool,
+    },
+    /// The region was written by TicKV version 1, which had no region
+    /// headers.
+    Legacy,
+    /// The region doesn't start with a TicKV region header.
+    Unknown(u8),
+}
+
+/// Information about a single object in an image.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectInfo {
+    /// Offset of the object in the image.
+    pub address: usize,
+    /// The hashed key.
+    pub hashed_key: u64,
+    /// The object is valid, i.e. it hasn't been invalidated or zeroised.
+    pub valid: bool,
+    /// Length of the stored value.
+    pub value_length: usize,
+    /// Whether the checksum matches. Only valid objects are checked, as
+    /// invalidating an object changes its header.
+    pub check_sum_ok: Option<bool>,
+}
+
+/// Information about a single region in an image.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegionInfo {
+    /// The region number.
+    pub region: usize,
+    /// The region header.
+    pub header: RegionHeaderInfo,
+    /// All objects found in the region.
+    pub objects: Vec<ObjectInfo>,
+    /// Unused bytes at the end of the region.
+    pub free_bytes: usize,
+    /// Set if the objects couldn't be parsed. Objects after the error are
+    /// missing from `objects`.
+    pub error: Option<ErrorCode>,
+}
+
+impl RegionInfo {
+    /// Returns true if the region header and every valid object are intact.
+    pub fn is_ok(&self) -> bool {
+        !matches!(self.header, RegionHeaderInfo::Unknown(_))
+            && self.error.is_none()
+            && self.objects.iter().all(|o| o.check_sum_ok != Some(false))
+    }
+}
+
+/// Walk over all regions of `image` and describe their content.
+pub fn inspect_image(image: &[u8], region_size: usize) -> Result<Vec<RegionInfo>, ImageError> {
+    if region_size <= REGION_HEADER_LENGTH
+        || image.is_empty()
+        || !image.len().is_multiple_of(region_size)
+    {
+        return Err(ImageError::InvalidSize);
+    }
+
+    Ok(image
+        .chunks_exact(region_size)
+        .enumerate()
+        .map(|(region, data)| inspect_region(region, region * region_size, data))
+        .collect())
+}
+
+fn inspect_region(region: usize, base: usize, data: &[u8]) -> RegionInfo {
+    let header = match data[REGION_MAGIC_OFFSET] {
+        0xFF => RegionHeaderInfo::Erased,
+        REGION_MAGIC => RegionHeaderInfo::Valid {
+            erase_count: u32::from_be_bytes(
+                data[REGION_ERASE_COUNT_OFFSET..REGION_HEADER_LENGTH]
+                    .try_into()
+                    .unwrap(),
+            ),
+            reclaimed: data[REGION_FLAGS_OFFSET] & REGION_FLAGS_FRESH != REGION_FLAGS_FRESH,
+        },
+        LEGACY_VERSION => RegionHeaderInfo::Legacy,
+        magic => RegionHeaderInfo::Unknown(magic),
+    };
+
+    let mut info = RegionInfo {
+        region,
+        header,
+        objects: Vec::new(),
+        free_bytes: 0,
+        error: None,
+    };
+
+    if let RegionHeaderInfo::Unknown(_) = header {
+        return info;
+    }
+
+    let mut offset = match header {
+        RegionHeaderInfo::Legacy => 0,
+        _ => REGION_HEADER_LENGTH,
+    };
+    while offset + HEADER_LENGTH < data.len() {
+        let version = data[offset + VERSION_OFFSET];
+
+        if version == 0xFF {
+            // We hit the end of valid data
+            break;
+        }
+
+        if version != VERSION && version != LEGACY_VERSION {
+            info.error = Some(ErrorCode::UnsupportedVersion);
+            return info;
+        }
+
+        let len_flags = data[offset + LEN_OFFSET];
+        let total_length =
+            ((len_flags as usize) & 0x0F) << 8 | data[offset + LEN_OFFSET + 1] as usize;
+
+        if total_length < HEADER_LENGTH + CHECK_SUM_LEN || offset + total_length > data.len() {
+            info.error = Some(ErrorCode::CorruptData);
+            return info;
+        }
+
+        let object = &data[offset..offset + total_length];
+        let valid = (len_flags >> 4) & FLAGS_VALID == FLAGS_VALID;
+
+        let check_sum_ok = valid.then(|| {
+            let check_sum = crc32::Crc32::new();
+            check_sum.update(&object[..total_length - CHECK_SUM_LEN]);
+            check_sum.finalise().to_ne_bytes() == object[total_length - CHECK_SUM_LEN..]
+        });
+
+        info.objects.push(ObjectInfo {
+            address: base + offset,
+            hashed_key: u64::from_be_bytes(
+                object[HASH_OFFSET..HASH_OFFSET + 8].try_into().unwrap(),
+            ),
+            valid,
+            value_length: total_length - HEADER_LENGTH - CHECK_SUM_LEN,
+            check_sum_ok,
+        });
+
+        offset += total_length;
+    }
+
+    info.free_bytes = data.len().saturating_sub(offset);
+
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tickv::MAIN_KEY;
+    use core::hash::{Hash, Hasher};
+    use std::collections::hash_map::DefaultHasher;
+
+    #[test]
+    fn test_main_key_hash() {
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+
+        assert_eq!(hash_function.finish(), TOCK_MAIN_KEY_HASH);
+    }
+
+    #[test]
+    fn test_siphash() {
+        // Test vector from the SipHash paper, with a zero key.
+        assert_eq!(siphash24(b""), 0x1e924b9d737700d7);
+        assert_eq!(tock_key_hash(b"tickv-super-key"), 0x1d4d0b463a7b2916_u64);
+    }
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = "# Provisioning data\n\
+                        name = board 1\n\
+                        \n\
+                        key=hex:00ff10\n\
+                        cert = file:cert.der\n";
+
+        let entries = parse_manifest(manifest, |path| {
+            assert_eq!(path, "cert.der");
+            Ok(vec![1, 2, 3])
+        })
+        .unwrap();
+
+        assert_eq!(
+            entries,
+            [
+                ManifestEntry {
+                    key: String::from("name"),
+                    value: b"board 1".to_vec(),
+                },
+                ManifestEntry {
+                    key: String::from("key"),
+                    value: vec![0x00, 0xff, 0x10],
+                },
+                ManifestEntry {
+                    key: String::from("cert"),
+                    value: vec![1, 2, 3],
+                },
+            ]
+        );
+
+        assert!(matches!(
+            parse_manifest("name", |_| Ok(Vec::new())),
+            Err(ImageError::Manifest { line: 1, .. })
+        ));
+        assert!(matches!(
+            parse_manifest("\nkey = hex:0g", |_| Ok(Vec::new())),
+            Err(ImageError::Manifest { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_build_and_inspect() {
+        let entries = [
+            ManifestEntry {
+                key: String::from("ONE"),
+                value: vec![0x23; 32],
+            },
+            ManifestEntry {
+                key: String::from("TWO"),
+                value: vec![0x42; 16],
+            },
+        ];
+
+        let image = build_image::<256>(0x400, &entries, Some(7)).unwrap();
+        let regions = inspect_image(&image, 256).unwrap();
+
+        assert_eq!(regions.len(), 4);
+        assert!(regions.iter().all(|r| r.is_ok()));
+        assert!(regions.iter().all(|r| r.header
+            == RegionHeaderInfo::Valid {
+                erase_count: 1,
+                reclaimed: false,
+            }));
+
+        let objects: Vec<&ObjectInfo> = regions.iter().flat_map(|r| &r.objects).collect();
+        assert_eq!(objects.len(), 3);
+        assert!(objects.iter().any(|o| o.hashed_key == TOCK_MAIN_KEY_HASH));
+        assert!(objects.iter().any(|o| o.hashed_key == tock_key_hash(b"ONE")
+            && o.value_length == TOCK_KV_HEADER_LENGTH + 32));
+
+        // Read a value back using the library
+        let tickv = open(ImageFlash::<256>::from_bytes(image.clone()).unwrap());
+        let mut buf = [0; TOCK_KV_HEADER_LENGTH + 16];
+        tickv.get_key(tock_key_hash(b"TWO"), &mut buf).unwrap();
+        assert_eq!(buf[..TOCK_KV_HEADER_LENGTH], [0, 16, 0, 0, 0, 7, 0, 0, 0]);
+        assert_eq!(buf[TOCK_KV_HEADER_LENGTH..], [0x42; 16]);
+
+        // Corrupt the value of ONE
+        let one = objects
+            .iter()
+            .find(|o| o.hashed_key == tock_key_hash(b"ONE"))
+            .unwrap();
+        let mut corrupt = image;
+        corrupt[one.address + HEADER_LENGTH] ^= 0xFF;
+        assert!(
+            !inspect_image(&corrupt, 256)
+                .unwrap()
+                .iter()
+                .all(|r| r.is_ok())
+        );
+    }
+
+    #[test]
+    fn test_garbage_collect_image() {
+        let entries = [ManifestEntry {
+            key: String::from("ONE"),
+            value: vec![0x23; 32],
+        }];
+
+        let image = build_image::<256>(0x400, &entries, None).unwrap();
+
+        // Nothing to collect
+        let (image, freed) = garbage_collect_image::<256>(image).unwrap();
+        assert_eq!(freed, 0);
+
+        let tickv = open(ImageFlash::<256>::from_bytes(image).unwrap());
+        tickv.invalidate_key(tock_key_hash(b"ONE")).unwrap();
+        let image = tickv.controller.into_bytes();
+
+        let (image, freed) = garbage_collect_image::<256>(image).unwrap();
+        assert_eq!(freed, 256);
+
+        let regions = inspect_image(&image, 256).unwrap();
+        assert_eq!(regions.iter().flat_map(|r| &r.objects).count(), 1);
+        assert!(regions.iter().any(|r| r.header
+            == RegionHeaderInfo::Valid {
+                erase_count: 2,
+                reclaimed: true,
+            }));
+    }
+
+    #[test]
+    fn test_invalid_size() {
+        assert!(matches!(
+            ImageFlash::<256>::new(0x180),
+            Err(ImageError::InvalidSize)
+        ));
+        assert!(matches!(
+            inspect_image(&[0xFF; 0x180], 256),
+            Err(ImageError::InvalidSize)
+        ));
+    }
+}
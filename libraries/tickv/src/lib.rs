@@ -79,6 +79,10 @@
 //! A TicKV region is the smallest region of the flash memory that can be erased
 //! in a single command.
 //!
+//! Every region starts with a small header that counts how often the region has
+//! been erased. TicKV uses this to avoid storing new objects in heavily worn
+//! regions, and the counts can be inspected with `stats()`.
+//!
 //! TicKV saves and restores objects from flash. TicKV objects contain the value
 //! the user wanted to store as well as extra header data. Objects are internal to
 //! TicKV and users don't need to understand them in detail to use it.
@@ -215,6 +219,8 @@ pub mod async_ops;
 pub mod crc32;
 pub mod error_codes;
 pub mod flash_controller;
+#[cfg(feature = "std")]
+pub mod image;
 pub mod success_codes;
 pub mod tickv;
 
@@ -229,8 +235,8 @@ pub use crate::tickv::MAIN_KEY;
 #[doc(inline)]
 pub use crate::tickv::TicKV;
 
-// This is used to run the tests on a host
-#[cfg(test)]
+// This is used to run the tests and the image tool on a host
+#[cfg(any(test, feature = "std"))]
 #[macro_use]
 extern crate std;
 
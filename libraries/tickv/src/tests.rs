@@ -4,7 +4,9 @@
 
 use crate::error_codes::ErrorCode;
 use crate::flash_controller::FlashController;
-use crate::tickv::{HASH_OFFSET, LEN_OFFSET, MAIN_KEY, TicKV, VERSION, VERSION_OFFSET};
+use crate::tickv::{
+    HASH_OFFSET, LEN_OFFSET, MAIN_KEY, REGION_HEADER_LENGTH, TicKV, VERSION, VERSION_OFFSET,
+};
 use core::hash::{Hash, Hasher};
 use std::cell::Cell;
 use std::cell::RefCell;
@@ -29,10 +31,10 @@ fn check_region_main(buf: &[u8]) {
     assert_eq!(buf[HASH_OFFSET + 7], 0x44);
 
     // Check the check hash
-    assert_eq!(buf[HASH_OFFSET + 8], 0xbb);
-    assert_eq!(buf[HASH_OFFSET + 9], 0x32);
-    assert_eq!(buf[HASH_OFFSET + 10], 0x74);
-    assert_eq!(buf[HASH_OFFSET + 11], 0x1d);
+    assert_eq!(buf[HASH_OFFSET + 8], 0x3e);
+    assert_eq!(buf[HASH_OFFSET + 9], 0xa7);
+    assert_eq!(buf[HASH_OFFSET + 10], 0x40);
+    assert_eq!(buf[HASH_OFFSET + 11], 0x13);
 }
 
 fn check_region_one(buf: &[u8]) {
@@ -59,10 +61,10 @@ fn check_region_one(buf: &[u8]) {
     assert_eq!(buf[42], 0x23);
 
     // Check the check hash
-    assert_eq!(buf[43], 0xfd);
-    assert_eq!(buf[44], 0x24);
-    assert_eq!(buf[45], 0xf0);
-    assert_eq!(buf[46], 0x07);
+    assert_eq!(buf[43], 0x54);
+    assert_eq!(buf[44], 0x72);
+    assert_eq!(buf[45], 0xf4);
+    assert_eq!(buf[46], 0x31);
 }
 
 fn check_region_one_zeroed(buf: &[u8]) {
@@ -123,10 +125,10 @@ fn check_region_two(buf: &[u8]) {
     assert_eq!(buf[42], 0x23);
 
     // Check the check hash
-    assert_eq!(buf[43], 0x1b);
-    assert_eq!(buf[44], 0x53);
-    assert_eq!(buf[45], 0xf9);
-    assert_eq!(buf[46], 0x54);
+    assert_eq!(buf[43], 0xb2);
+    assert_eq!(buf[44], 0x05);
+    assert_eq!(buf[45], 0xfd);
+    assert_eq!(buf[46], 0x62);
 }
 
 fn get_hashed_key(unhashed_key: &[u8]) -> u64 {
@@ -161,7 +163,10 @@ mod simple_flash_ctrl {
         }
 
         fn write(&self, _address: usize, buf: &[u8]) -> Result<(), ErrorCode> {
-            check_region_main(buf);
+            // Region headers are written after every erase
+            if buf.len() != REGION_HEADER_LENGTH {
+                check_region_main(buf);
+            }
 
             Ok(())
         }
@@ -211,7 +216,10 @@ mod single_erase_flash_ctrl {
         }
 
         fn write(&self, _address: usize, buf: &[u8]) -> Result<(), ErrorCode> {
-            check_region_main(buf);
+            // Region headers are written after every erase
+            if buf.len() != REGION_HEADER_LENGTH {
+                check_region_main(buf);
+            }
 
             Ok(())
         }
@@ -281,6 +289,11 @@ mod store_flast_ctrl {
                 self.buf.borrow_mut()[address / 1024][(address % 1024) + i] = *d;
             }
 
+            // Region headers are written after every erase, don't count them
+            if buf.len() == REGION_HEADER_LENGTH {
+                return Ok(());
+            }
+
             // Check to see if we are adding a key
             if buf.len() > 1 {
                 if self.run.get() == 0 {
@@ -590,8 +603,8 @@ mod no_check_store_flast_ctrl {
         let tickv = TicKV::<FlashCtrl, 256>::new(FlashCtrl::new(), &mut read_buf, 0x200);
         tickv.initialise(hash).unwrap();
 
-        let value: [u8; 64] = [0x23; 64];
-        let mut buf: [u8; 64] = [0; 64];
+        let value: [u8; 56] = [0x23; 56];
+        let mut buf: [u8; 56] = [0; 56];
 
         println!("Add Key ONE");
         tickv.append_key(get_hashed_key(b"ONE"), &value).unwrap();
@@ -666,3 +679,307 @@ mod no_check_store_flast_ctrl {
         );
     }
 }
+
+/// Tests for region erase counts and wear leveling
+mod wear_flash_ctrl {
+    use super::*;
+    use crate::crc32;
+    use crate::success_codes::SuccessCode;
+    use crate::tickv::{REGION_ERASE_COUNT_OFFSET, REGION_MAGIC, Stats};
+    use std::format;
+    use std::vec;
+    use std::vec::Vec;
+
+    // A FlashCtrl implementation that really erases regions
+    struct FlashCtrl {
+        buf: RefCell<[[u8; 256]; 4]>,
+    }
+
+    impl FlashCtrl {
+        fn new() -> Self {
+            Self {
+                buf: RefCell::new([[0xFF; 256]; 4]),
+            }
+        }
+
+        fn erase_count(&self, region_number: usize) -> u32 {
+            let buf = self.buf.borrow();
+            let count = &buf[region_number][REGION_ERASE_COUNT_OFFSET..REGION_HEADER_LENGTH];
+            u32::from_be_bytes(count.try_into().unwrap())
+        }
+
+        fn region_is_empty(&self, region_number: usize) -> bool {
+            self.buf.borrow()[region_number][REGION_HEADER_LENGTH..]
+                .iter()
+                .all(|b| *b == 0xFF)
+        }
+    }
+
+    impl FlashController<256> for FlashCtrl {
+        fn read_region(&self, region_number: usize, buf: &mut [u8; 256]) -> Result<(), ErrorCode> {
+            buf.copy_from_slice(&self.buf.borrow()[region_number]);
+
+            Ok(())
+        }
+
+        fn write(&self, address: usize, buf: &[u8]) -> Result<(), ErrorCode> {
+            for (i, d) in buf.iter().enumerate() {
+                self.buf.borrow_mut()[address / 256][(address % 256) + i] = *d;
+            }
+
+            Ok(())
+        }
+
+        fn erase_region(&self, region_number: usize) -> Result<(), ErrorCode> {
+            self.buf.borrow_mut()[region_number] = [0xFF; 256];
+
+            Ok(())
+        }
+    }
+
+    /// Find a key that isn't stored in the same region as the main key
+    fn key_outside_main_region() -> (u64, usize) {
+        let main_region = (get_hashed_key(MAIN_KEY) as usize & 0xFFFF) % 4;
+
+        [&b"ONE"[..], b"TWO", b"THREE", b"FOUR", b"FIVE"]
+            .iter()
+            .map(|k| {
+                let hash = get_hashed_key(k);
+                (hash, (hash as usize & 0xFFFF) % 4)
+            })
+            .find(|(_, region)| *region != main_region)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_erase_counts() {
+        let mut read_buf: [u8; 256] = [0; 256];
+        let tickv = TicKV::<FlashCtrl, 256>::new(FlashCtrl::new(), &mut read_buf, 0x400);
+        tickv.initialise(get_hashed_key(MAIN_KEY)).unwrap();
+
+        let (key, region) = key_outside_main_region();
+        let value: [u8; 32] = [0x23; 32];
+
+        for r in 0..4 {
+            assert_eq!(tickv.controller.erase_count(r), 1);
+        }
+
+        let fresh = tickv.stats().unwrap();
+        assert_eq!(fresh.invalidated_bytes, 0);
+        assert_eq!(fresh.min_erase_count, 1);
+        assert_eq!(fresh.max_erase_count, 1);
+
+        tickv.append_key(key, &value).unwrap();
+        tickv.invalidate_key(key).unwrap();
+
+        let used = tickv.stats().unwrap();
+        assert_eq!(used.free_bytes, fresh.free_bytes - (11 + 32 + 4));
+        assert_eq!(used.invalidated_bytes, 11 + 32 + 4);
+
+        assert_eq!(tickv.garbage_collect(), Ok(256));
+        assert_eq!(tickv.controller.erase_count(region), 2);
+
+        assert_eq!(
+            tickv.stats(),
+            Ok(Stats {
+                free_bytes: fresh.free_bytes,
+                invalidated_bytes: 0,
+                min_erase_count: 1,
+                max_erase_count: 2,
+            })
+        );
+
+        // A second initialisation must keep the existing erase counts
+        let mut read_buf: [u8; 256] = [0; 256];
+        let controller = FlashCtrl::new();
+        *controller.buf.borrow_mut() = *tickv.controller.buf.borrow();
+        let tickv = TicKV::<FlashCtrl, 256>::new(controller, &mut read_buf, 0x400);
+        tickv.initialise(get_hashed_key(MAIN_KEY)).unwrap();
+        assert_eq!(tickv.controller.erase_count(region), 2);
+    }
+
+    #[test]
+    fn test_wear_aware_append() {
+        let mut read_buf: [u8; 256] = [0; 256];
+        let tickv = TicKV::<FlashCtrl, 256>::new(FlashCtrl::new(), &mut read_buf, 0x400);
+        tickv.initialise(get_hashed_key(MAIN_KEY)).unwrap();
+        tickv.set_wear_leveling_threshold(Some(1));
+
+        let (key, region) = key_outside_main_region();
+        let value: [u8; 32] = [0x23; 32];
+        let mut buf: [u8; 32] = [0; 32];
+
+        tickv.append_key(key, &value).unwrap();
+        tickv.invalidate_key(key).unwrap();
+        tickv.garbage_collect().unwrap();
+        assert!(tickv.controller.region_is_empty(region));
+
+        println!("Re-add the key, it should avoid the worn region");
+        tickv.append_key(key, &value).unwrap();
+        assert!(tickv.controller.region_is_empty(region));
+
+        // The lookup has to continue past the reclaimed region
+        assert_eq!(
+            tickv.get_key(key, &mut buf),
+            Ok((SuccessCode::Complete, 32))
+        );
+        assert_eq!(buf, value);
+
+        println!("Without wear leveling the key is stored in its own region");
+        tickv.invalidate_key(key).unwrap();
+        tickv.garbage_collect().unwrap();
+        tickv.set_wear_leveling_threshold(None);
+        tickv.append_key(key, &value).unwrap();
+        assert!(!tickv.controller.region_is_empty(region));
+    }
+
+    /// Find two keys that are stored in the same region, which isn't the
+    /// region of the main key
+    fn keys_sharing_a_region() -> (u64, u64, usize) {
+        let main_region = (get_hashed_key(MAIN_KEY) as usize & 0xFFFF) % 4;
+        let keys: Vec<(u64, usize)> = (0..32)
+            .map(|i| {
+                let hash = get_hashed_key(format!("key{}", i).as_bytes());
+                (hash, (hash as usize & 0xFFFF) % 4)
+            })
+            .collect();
+
+        keys.iter()
+            .filter(|(_, region)| *region != main_region)
+            .find_map(|(first, region)| {
+                keys.iter()
+                    .find(|(other, r)| r == region && other != first)
+                    .map(|(second, _)| (*first, *second, *region))
+            })
+            .unwrap()
+    }
+
+    /// Build an object the way TicKV version 1 stored it
+    fn legacy_object(hash: u64, value: &[u8]) -> Vec<u8> {
+        let len = 11 + value.len() + 4;
+        let mut object = vec![1, 0x80 | (len >> 8) as u8, len as u8];
+        object.extend_from_slice(&hash.to_be_bytes());
+        object.extend_from_slice(value);
+
+        let check_sum = crc32::Crc32::new();
+        check_sum.update(&object);
+        object.extend_from_slice(&check_sum.finalise().to_ne_bytes());
+        object
+    }
+
+    #[test]
+    fn test_legacy_store() {
+        let main_key = get_hashed_key(MAIN_KEY);
+        let main_region = (main_key as usize & 0xFFFF) % 4;
+        let (key, region) = key_outside_main_region();
+        let value: [u8; 32] = [0x23; 32];
+        let mut buf: [u8; 32] = [0; 32];
+
+        // A store written by version 1 has no region headers
+        let controller = FlashCtrl::new();
+        {
+            let mut flash = controller.buf.borrow_mut();
+            let main = legacy_object(main_key, &[]);
+            flash[main_region][..main.len()].copy_from_slice(&main);
+            let object = legacy_object(key, &value);
+            flash[region][..object.len()].copy_from_slice(&object);
+        }
+
+        let mut read_buf: [u8; 256] = [0; 256];
+        let tickv = TicKV::<FlashCtrl, 256>::new(controller, &mut read_buf, 0x400);
+        assert_eq!(tickv.initialise(main_key), Ok(SuccessCode::Complete));
+
+        // Nothing was erased and the old objects can still be read
+        assert_eq!(tickv.controller.buf.borrow()[region][0], 1);
+        assert_eq!(
+            tickv.get_key(key, &mut buf),
+            Ok((SuccessCode::Complete, 32))
+        );
+        assert_eq!(buf, value);
+        assert_eq!(tickv.stats().unwrap().min_erase_count, 0);
+
+        // Once the region is garbage collected it gets a region header
+        tickv.invalidate_key(key).unwrap();
+        assert_eq!(tickv.garbage_collect(), Ok(256));
+        assert_eq!(tickv.controller.buf.borrow()[region][0], REGION_MAGIC);
+        assert_eq!(tickv.controller.erase_count(region), 1);
+        assert!(tickv.controller.region_is_empty(region));
+
+        tickv.append_key(key, &value).unwrap();
+        assert_eq!(
+            tickv.get_key(key, &mut buf),
+            Ok((SuccessCode::Complete, 32))
+        );
+    }
+
+    #[test]
+    fn test_wear_leveling_after_reboot() {
+        let mut read_buf: [u8; 256] = [0; 256];
+        let tickv = TicKV::<FlashCtrl, 256>::new(FlashCtrl::new(), &mut read_buf, 0x400);
+        tickv.initialise(get_hashed_key(MAIN_KEY)).unwrap();
+
+        let (key, region) = key_outside_main_region();
+        let value: [u8; 32] = [0x23; 32];
+
+        tickv.append_key(key, &value).unwrap();
+        tickv.invalidate_key(key).unwrap();
+        tickv.garbage_collect().unwrap();
+
+        // After a reboot the least worn region is known straight away
+        let mut read_buf: [u8; 256] = [0; 256];
+        let controller = FlashCtrl::new();
+        *controller.buf.borrow_mut() = *tickv.controller.buf.borrow();
+        let tickv = TicKV::<FlashCtrl, 256>::new(controller, &mut read_buf, 0x400);
+        tickv.initialise(get_hashed_key(MAIN_KEY)).unwrap();
+        tickv.set_wear_leveling_threshold(Some(1));
+
+        tickv.append_key(key, &value).unwrap();
+        assert!(tickv.controller.region_is_empty(region));
+    }
+
+    #[test]
+    fn test_relocate_from_worn_region() {
+        let mut read_buf: [u8; 256] = [0; 256];
+        let mut relocation_buf: [u8; 64] = [0; 64];
+        let tickv = TicKV::<FlashCtrl, 256>::new(FlashCtrl::new(), &mut read_buf, 0x400);
+        tickv.initialise(get_hashed_key(MAIN_KEY)).unwrap();
+
+        let (hot, cold, region) = keys_sharing_a_region();
+        let value: [u8; 32] = [0x23; 32];
+        let mut buf: [u8; 32] = [0; 32];
+
+        // Wear the region out
+        tickv.append_key(hot, &value).unwrap();
+        tickv.invalidate_key(hot).unwrap();
+        tickv.garbage_collect().unwrap();
+        assert_eq!(tickv.controller.erase_count(region), 2);
+
+        // The region ends up with a valid and an invalidated object
+        tickv.append_key(cold, &value).unwrap();
+        tickv.append_key(hot, &value).unwrap();
+        tickv.invalidate_key(hot).unwrap();
+        assert!(!tickv.controller.region_is_empty(region));
+
+        println!("Without a relocation buffer the region is kept");
+        tickv.set_wear_leveling_threshold(Some(1));
+        assert_eq!(tickv.garbage_collect(), Ok(0));
+        assert!(!tickv.controller.region_is_empty(region));
+
+        println!("The valid object is moved and the region reclaimed");
+        tickv.set_relocation_buffer(&mut relocation_buf);
+        assert_eq!(tickv.garbage_collect(), Ok(256));
+        assert!(tickv.controller.region_is_empty(region));
+        assert_eq!(tickv.controller.erase_count(region), 3);
+
+        assert_eq!(
+            tickv.get_key(cold, &mut buf),
+            Ok((SuccessCode::Complete, 32))
+        );
+        assert_eq!(buf, value);
+        assert_eq!(tickv.get_key(hot, &mut buf), Err(ErrorCode::KeyNotFound));
+
+        // The moved key can be updated as usual
+        tickv.invalidate_key(cold).unwrap();
+        assert_eq!(tickv.get_key(cold, &mut buf), Err(ErrorCode::KeyNotFound));
+    }
+}
@@ -11,7 +11,12 @@ use crate::success_codes::SuccessCode;
 use core::cell::Cell;
 
 /// The current version of TicKV
-pub const VERSION: u8 = 1;
+pub const VERSION: u8 = 2;
+
+/// Version 1 used the same object format, but regions didn't start with a
+/// region header. Stores written by version 1 are still read and are moved
+/// to the current format region by region as they are garbage collected.
+pub(crate) const LEGACY_VERSION: u8 = 1;
 
 #[derive(Clone, Copy, PartialEq)]
 pub(crate) enum InitState {
@@ -19,10 +24,14 @@ pub(crate) enum InitState {
     GetKeyReadRegion(usize),
     /// Trying to erase a region
     EraseRegion(usize),
+    /// Trying to write the header of an erased region
+    WriteHeader(usize),
     /// Finished erasing regions
     EraseComplete,
     /// Trying to read a region while appending a key
     AppendKeyReadRegion(usize),
+    /// Reading the region headers to find the least worn region
+    ScanRegion(usize),
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -34,7 +43,18 @@ pub(crate) enum KeyState {
 #[derive(Clone, Copy, PartialEq)]
 pub(crate) enum RubbishState {
     ReadRegion(usize, usize),
-    EraseRegion(usize, usize),
+    /// Erasing a region, the last value is the new erase count
+    EraseRegion(usize, usize, u32),
+    WriteHeader(usize, usize),
+    /// Reading a region to find space for an object that is moved out of a
+    /// worn region: (worn region, flash freed, object offset, region)
+    RelocateFind(usize, usize, usize, usize),
+    /// The copy of a moved object has been written
+    RelocateWrite(usize, usize, usize),
+    /// Reading the worn region to invalidate a moved object
+    RelocateRead(usize, usize, usize),
+    /// A moved object has been invalidated in the worn region
+    RelocateInvalidate(usize, usize),
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -55,6 +75,35 @@ pub(crate) enum State {
     ZeroiseKey(KeyState),
     /// Running garbage collection
     GarbageCollect(RubbishState),
+    /// Collecting statistics
+    Stats(KeyState),
+}
+
+/// Tracks if `append_key()` has skipped a worn region that could have
+/// stored the object.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum AppendFallback {
+    /// No worn region with free space has been found
+    None,
+    /// The first worn region with free space that was skipped
+    Candidate(usize),
+    /// All other regions are full, the worn region is being used
+    Active,
+}
+
+/// Usage and wear statistics of a TicKV store, as returned by `stats()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// The number of bytes at the end of regions that are free for new
+    /// objects.
+    pub free_bytes: usize,
+    /// The number of bytes used by invalidated objects. These are only
+    /// reclaimed once all objects in a region have been invalidated.
+    pub invalidated_bytes: usize,
+    /// The lowest erase count of all regions.
+    pub min_erase_count: u32,
+    /// The highest erase count of all regions.
+    pub max_erase_count: u32,
 }
 
 /// The struct storing all of the TicKV information.
@@ -64,6 +113,34 @@ pub struct TicKV<'a, C: FlashController<S>, const S: usize> {
     flash_size: usize,
     pub(crate) read_buffer: Cell<Option<&'a mut [u8; S]>>,
     pub(crate) state: Cell<State>,
+    /// The lowest erase count seen during the last full pass over the flash
+    /// (initialisation, garbage collection or statistics collection).
+    erase_count_floor: Cell<Option<u32>>,
+    /// How many more erases than `erase_count_floor` a region can have before
+    /// it is avoided for new objects.
+    wear_leveling_threshold: Cell<Option<u32>>,
+    append_fallback: Cell<AppendFallback>,
+    /// Statistics accumulated while walking over all regions.
+    scan_stats: Cell<Stats>,
+    /// Holds an object while it is moved out of a worn region.
+    relocation_buffer: Cell<Option<&'a mut [u8]>>,
+}
+
+/// This is the header stored at the start of every region
+struct RegionHeader {
+    erase_count: u32,
+    // Set once the region has been erased by garbage collection.
+    reclaimed: bool,
+    // Offset of the first object, 0 for regions written by version 1.
+    data_offset: usize,
+}
+
+/// The information gathered by walking over all objects in a region
+struct RegionInfo {
+    header: RegionHeader,
+    valid_objects: usize,
+    invalidated_bytes: usize,
+    free_bytes: usize,
 }
 
 /// This is the current object header used for TicKV objects
@@ -97,6 +174,21 @@ pub(crate) const HASH_OFFSET: usize = 3;
 pub(crate) const HEADER_LENGTH: usize = HASH_OFFSET + 8;
 pub(crate) const CHECK_SUM_LEN: usize = 4;
 
+// A list of offsets into the RegionHeader
+pub(crate) const REGION_MAGIC_OFFSET: usize = 0;
+pub(crate) const REGION_FLAGS_OFFSET: usize = 1;
+pub(crate) const REGION_ERASE_COUNT_OFFSET: usize = 4;
+pub(crate) const REGION_HEADER_LENGTH: usize = REGION_ERASE_COUNT_OFFSET + 4;
+
+/// The first byte of every formatted region
+pub(crate) const REGION_MAGIC: u8 = 0x54;
+/// This flag is cleared once a region has been erased by garbage collection
+pub(crate) const REGION_FLAGS_FRESH: u8 = 0x80;
+
+/// The default number of erases a region can be ahead of the least worn
+/// region before new objects are placed in other regions.
+pub const WEAR_LEVELING_THRESHOLD: u32 = 16;
+
 /// The main key. A hashed version of this should be passed to
 /// `initialise()`.
 pub const MAIN_KEY: &[u8; 15] = b"tickv-super-key";
@@ -115,9 +207,36 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
             flash_size,
             read_buffer: Cell::new(Some(read_buffer)),
             state: Cell::new(State::None),
+            erase_count_floor: Cell::new(None),
+            wear_leveling_threshold: Cell::new(Some(WEAR_LEVELING_THRESHOLD)),
+            append_fallback: Cell::new(AppendFallback::None),
+            scan_stats: Cell::new(Stats::default()),
+            relocation_buffer: Cell::new(None),
         }
     }
 
+    /// Set the wear leveling threshold.
+    ///
+    /// When appending a key, regions that have been erased `threshold` or
+    /// more times than the least worn region are skipped, unless there is
+    /// no other space left. `None` disables wear leveling.
+    ///
+    /// The erase count of the least worn region is found by `initialise()`
+    /// and updated by `garbage_collect()` and `stats()`.
+    pub fn set_wear_leveling_threshold(&self, threshold: Option<u32>) {
+        self.wear_leveling_threshold.set(threshold);
+    }
+
+    /// Set the buffer used by `garbage_collect()` to move objects out of
+    /// worn regions.
+    ///
+    /// Without a relocation buffer objects are never moved. Objects larger
+    /// than the buffer are never moved, so a region sized buffer is needed
+    /// to move every object.
+    pub fn set_relocation_buffer(&self, buffer: &'a mut [u8]) {
+        self.relocation_buffer.set(Some(buffer));
+    }
+
     /// This function setups the flash region to be used as a key-value store.
     /// If the region is already initialised this won't make any changes.
     ///
@@ -131,6 +250,10 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
     pub fn initialise(&self, hashed_main_key: u64) -> Result<SuccessCode, ErrorCode> {
         let mut buf: [u8; 0] = [0; 0];
 
+        if let State::Init(InitState::ScanRegion(reg)) = self.state.get() {
+            return self.find_erase_count_floor(reg);
+        }
+
         let key_ret = match self.state.get() {
             State::None => self.get_key(hashed_main_key, &mut buf),
             State::Init(state) => match state {
@@ -141,7 +264,13 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
         };
 
         match key_ret {
-            Ok((ret, _len)) => Ok(ret),
+            Ok(_) => {
+                // The flash is already set up, find the least worn region
+                // for wear leveling.
+                self.state.set(State::None);
+                self.erase_count_floor.set(None);
+                self.find_erase_count_floor(0)
+            }
             Err(e) => {
                 match e {
                     ErrorCode::ReadNotReady(reg) => {
@@ -153,12 +282,23 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                         match self.state.get() {
                             State::None
                             | State::Init(InitState::GetKeyReadRegion(_))
-                            | State::Init(InitState::EraseRegion(_)) => {
+                            | State::Init(InitState::EraseRegion(_))
+                            | State::Init(InitState::WriteHeader(_)) => {
                                 // Erase all regions
                                 let mut start = 0;
-                                if let State::Init(InitState::EraseRegion(reg)) = self.state.get() {
-                                    // We already erased region reg, so move to the next one
-                                    start = reg + 1;
+                                match self.state.get() {
+                                    State::Init(InitState::EraseRegion(reg)) => {
+                                        // We already erased region reg, so write
+                                        // the header and move to the next one
+                                        self.format_region(reg)?;
+                                        start = reg + 1;
+                                    }
+                                    State::Init(InitState::WriteHeader(reg)) => {
+                                        // We already wrote the header of region reg,
+                                        // so move to the next one
+                                        start = reg + 1;
+                                    }
+                                    _ => {}
                                 }
 
                                 if start < (self.flash_size / S) {
@@ -167,9 +307,12 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                                             self.state.set(State::Init(InitState::EraseRegion(r)));
                                             return Err(e);
                                         }
+                                        self.format_region(r)?;
                                     }
                                 }
 
+                                // Every region has just been erased once
+                                self.erase_count_floor.set(Some(1));
                                 self.state.set(State::Init(InitState::EraseComplete));
                             }
                             _ => {}
@@ -200,6 +343,201 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
         }
     }
 
+    /// Read the header of every region, starting at `start`, to find the
+    /// erase count of the least worn region.
+    fn find_erase_count_floor(&self, start: usize) -> Result<SuccessCode, ErrorCode> {
+        for region in start..(self.flash_size / S) {
+            let region_data = self.read_buffer.take().unwrap();
+            if self.state.get() != State::Init(InitState::ScanRegion(region)) {
+                if let Err(e) = self.controller.read_region(region, region_data) {
+                    self.read_buffer.replace(Some(region_data));
+                    if let ErrorCode::ReadNotReady(reg) = e {
+                        self.state.set(State::Init(InitState::ScanRegion(reg)));
+                    }
+                    return Err(e);
+                }
+            }
+
+            // Regions with a damaged header are left to the operations that
+            // use them to report.
+            if let Ok(header) = self.read_region_header(region_data) {
+                let floor = self
+                    .erase_count_floor
+                    .get()
+                    .map_or(header.erase_count, |floor| floor.min(header.erase_count));
+                self.erase_count_floor.set(Some(floor));
+            }
+            self.read_buffer.replace(Some(region_data));
+        }
+
+        self.state.set(State::None);
+        Ok(SuccessCode::Complete)
+    }
+
+    /// Write the header of a region that was erased while formatting the
+    /// flash.
+    fn format_region(&self, region: usize) -> Result<(), ErrorCode> {
+        self.write_region_header(region, 1, false).inspect_err(|e| {
+            if let ErrorCode::WriteNotReady(_) = e {
+                self.state.set(State::Init(InitState::WriteHeader(region)));
+            }
+        })
+    }
+
+    /// Write a new header to the start of the just erased `region`.
+    fn write_region_header(
+        &self,
+        region: usize,
+        erase_count: u32,
+        reclaimed: bool,
+    ) -> Result<(), ErrorCode> {
+        let mut header = [0xFF; REGION_HEADER_LENGTH];
+
+        header[REGION_MAGIC_OFFSET] = REGION_MAGIC;
+        if reclaimed {
+            header[REGION_FLAGS_OFFSET] &= !REGION_FLAGS_FRESH;
+        }
+        header[REGION_ERASE_COUNT_OFFSET..REGION_HEADER_LENGTH]
+            .copy_from_slice(&erase_count.to_be_bytes());
+
+        self.controller.write(S * region, &header)
+    }
+
+    /// Parse the header at the start of the loaded region data.
+    fn read_region_header(&self, region_data: &[u8]) -> Result<RegionHeader, ErrorCode> {
+        let magic = *region_data
+            .get(REGION_MAGIC_OFFSET)
+            .ok_or(ErrorCode::CorruptData)?;
+
+        if magic == 0xFF {
+            // The region has been erased, but the header was never written.
+            // This can happen if power was lost straight after an erase.
+            return Ok(RegionHeader {
+                erase_count: 0,
+                reclaimed: false,
+                data_offset: REGION_HEADER_LENGTH,
+            });
+        }
+
+        if magic == LEGACY_VERSION {
+            // A region written by version 1 starts with an object. Its erase
+            // count is unknown until the region is garbage collected.
+            return Ok(RegionHeader {
+                erase_count: 0,
+                reclaimed: false,
+                data_offset: 0,
+            });
+        }
+
+        if magic != REGION_MAGIC {
+            return Err(ErrorCode::UnsupportedVersion);
+        }
+
+        let flags = *region_data
+            .get(REGION_FLAGS_OFFSET)
+            .ok_or(ErrorCode::CorruptData)?;
+        let erase_count = region_data
+            .get(REGION_ERASE_COUNT_OFFSET..REGION_HEADER_LENGTH)
+            .and_then(|count| count.try_into().ok())
+            .map(u32::from_be_bytes)
+            .ok_or(ErrorCode::CorruptData)?;
+
+        Ok(RegionHeader {
+            erase_count,
+            reclaimed: flags & REGION_FLAGS_FRESH != REGION_FLAGS_FRESH,
+            data_offset: REGION_HEADER_LENGTH,
+        })
+    }
+
+    /// Returns true if objects of this version can be read.
+    fn supported_version(version: u8) -> bool {
+        version == VERSION || version == LEGACY_VERSION
+    }
+
+    /// Walk over all of the objects in some loaded region data.
+    fn scan_region(&self, region_data: &[u8]) -> Result<RegionInfo, ErrorCode> {
+        let mut info = RegionInfo {
+            header: self.read_region_header(region_data)?,
+            valid_objects: 0,
+            invalidated_bytes: 0,
+            free_bytes: 0,
+        };
+        let mut offset = info.header.data_offset;
+
+        while offset + HEADER_LENGTH < S {
+            let version = *region_data
+                .get(offset + VERSION_OFFSET)
+                .ok_or(ErrorCode::CorruptData)?;
+
+            if version == 0xFF {
+                // We hit the end of valid data
+                break;
+            }
+
+            if !Self::supported_version(version) {
+                return Err(ErrorCode::UnsupportedVersion);
+            }
+
+            let len_flags = *region_data
+                .get(offset + LEN_OFFSET)
+                .ok_or(ErrorCode::CorruptData)?;
+
+            // Find this entries length
+            let total_length = ((len_flags as u16) & !0xF0) << 8
+                | *region_data
+                    .get(offset + LEN_OFFSET + 1)
+                    .ok_or(ErrorCode::CorruptData)? as u16;
+
+            if total_length == 0 {
+                return Err(ErrorCode::CorruptData);
+            }
+
+            if len_flags & 0x80 == 0x80 {
+                info.valid_objects += 1;
+            } else {
+                info.invalidated_bytes += total_length as usize;
+            }
+
+            offset += total_length as usize;
+        }
+
+        info.free_bytes = S.saturating_sub(offset);
+
+        Ok(info)
+    }
+
+    /// Add the information of a single region to `scan_stats`.
+    fn accumulate_stats(&self, erase_count: u32, free_bytes: usize, invalidated_bytes: usize) {
+        let mut stats = self.scan_stats.get();
+
+        stats.free_bytes += free_bytes;
+        stats.invalidated_bytes += invalidated_bytes;
+        stats.min_erase_count = stats.min_erase_count.min(erase_count);
+        stats.max_erase_count = stats.max_erase_count.max(erase_count);
+
+        self.scan_stats.set(stats);
+    }
+
+    /// Reset `scan_stats` before walking over all regions.
+    fn reset_stats(&self) {
+        self.scan_stats.set(Stats {
+            min_erase_count: u32::MAX,
+            ..Stats::default()
+        });
+    }
+
+    /// Returns true if the region has been erased a lot more often than the
+    /// least worn region.
+    fn region_is_worn(&self, erase_count: u32) -> bool {
+        match (
+            self.erase_count_floor.get(),
+            self.wear_leveling_threshold.get(),
+        ) {
+            (Some(floor), Some(threshold)) => erase_count >= floor.saturating_add(threshold),
+            _ => false,
+        }
+    }
+
     /// Get region number from a hashed key
     fn get_region(&self, hash: u64) -> usize {
         assert_ne!(hash, 0xFFFF_FFFF_FFFF_FFFF);
@@ -258,6 +596,28 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
         None
     }
 
+    // Determine the next region offset to try when appending an object
+    // that didn't fit in `current_region`.
+    //
+    // If all regions have been tried, the first worn region that was
+    // skipped is used. Otherwise the flash is full.
+    fn next_append_region(&self, region: usize, current_region: usize) -> Result<isize, ErrorCode> {
+        let region_offset = current_region as isize - region as isize;
+
+        self.state.set(State::None);
+
+        match self.increment_region_offset(region, region_offset) {
+            Some(o) => Ok(o),
+            None => match self.append_fallback.get() {
+                AppendFallback::Candidate(fallback) => {
+                    self.append_fallback.set(AppendFallback::Active);
+                    Ok(fallback as isize - region as isize)
+                }
+                _ => Err(ErrorCode::FlashFull),
+            },
+        }
+    }
+
     /// Find a key in some loaded region data.
     ///
     /// On success return the offset in the region_data where the key is and the
@@ -274,13 +634,22 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
         // Split the hash
         let hash = hash.to_ne_bytes();
 
-        let mut offset: usize = 0;
+        // Objects that belong in this region might have been placed in a
+        // neighbouring region if this region was full or worn. Once this
+        // region has been reclaimed by garbage collection it might be empty,
+        // but we still need to keep looking.
+        let header = self
+            .read_region_header(region_data)
+            .map_err(|e| (false, e))?;
+        let reclaimed = header.reclaimed;
+
+        let mut offset: usize = header.data_offset;
         let mut empty: bool = true;
 
         loop {
             if offset + HEADER_LENGTH >= S {
                 // We have reached the end of the region
-                return Err((false, ErrorCode::KeyNotFound));
+                return Err((!empty || reclaimed, ErrorCode::KeyNotFound));
             }
 
             // Check to see if we have data
@@ -293,11 +662,11 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                 empty = false;
 
                 // We found a version, check that we support it
-                if *region_data
-                    .get(offset + VERSION_OFFSET)
-                    .ok_or((false, ErrorCode::KeyNotFound))?
-                    != VERSION
-                {
+                if !Self::supported_version(
+                    *region_data
+                        .get(offset + VERSION_OFFSET)
+                        .ok_or((false, ErrorCode::KeyNotFound))?,
+                ) {
                     return Err((false, ErrorCode::UnsupportedVersion));
                 }
 
@@ -373,7 +742,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                 return Ok((offset, total_length));
             } else {
                 // We hit the end.
-                return Err((!empty, ErrorCode::KeyNotFound));
+                return Err((!empty || reclaimed, ErrorCode::KeyNotFound));
             }
         }
     }
@@ -403,6 +772,14 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
 
         let mut region_offset: isize = 0;
 
+        match self.state.get() {
+            State::AppendKey(_) | State::Init(InitState::AppendKeyReadRegion(_)) => {}
+            _ => {
+                // This is a new operation
+                self.append_fallback.set(AppendFallback::None);
+            }
+        }
+
         loop {
             let new_region = match self.state.get() {
                 State::None => (region as isize + region_offset) as usize,
@@ -440,7 +817,13 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                 return Err(ErrorCode::KeyAlreadyExists);
             }
 
-            let mut offset: usize = 0;
+            let (erase_count, mut offset) = match self.read_region_header(region_data) {
+                Ok(header) => (header.erase_count, header.data_offset),
+                Err(e) => {
+                    self.read_buffer.replace(Some(region_data));
+                    return Err(e);
+                }
+            };
 
             loop {
                 if offset + package_length >= S {
@@ -450,16 +833,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                     // Replace the buffer
                     self.read_buffer.replace(Some(region_data));
 
-                    region_offset = new_region as isize - region as isize;
-                    match self.increment_region_offset(region, region_offset) {
-                        Some(o) => {
-                            region_offset = o;
-                            self.state.set(State::None);
-                        }
-                        None => {
-                            return Err(ErrorCode::FlashFull);
-                        }
-                    }
+                    region_offset = self.next_append_region(region, new_region)?;
                     break;
                 }
 
@@ -470,11 +844,11 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                     != 0xFF
                 {
                     // We found a version, check that we support it
-                    if *region_data
-                        .get(offset + VERSION_OFFSET)
-                        .ok_or(ErrorCode::KeyNotFound)?
-                        != VERSION
-                    {
+                    if !Self::supported_version(
+                        *region_data
+                            .get(offset + VERSION_OFFSET)
+                            .ok_or(ErrorCode::KeyNotFound)?,
+                    ) {
                         self.read_buffer.replace(Some(region_data));
                         return Err(ErrorCode::UnsupportedVersion);
                     }
@@ -567,6 +941,23 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
 
                 // If we get here we have found an empty spot
 
+                // Avoid adding more wear to this region if it has been
+                // erased a lot more than others. We will only come back to
+                // it if all of the other regions are full.
+                if self.append_fallback.get() != AppendFallback::Active
+                    && self.region_is_worn(erase_count)
+                {
+                    self.read_buffer.replace(Some(region_data));
+
+                    if self.append_fallback.get() == AppendFallback::None {
+                        self.append_fallback
+                            .set(AppendFallback::Candidate(new_region));
+                    }
+
+                    region_offset = self.next_append_region(region, new_region)?;
+                    break;
+                }
+
                 // Copy in new header
                 // This is a little painful, but avoids any unsafe Rust
                 *region_data
@@ -972,131 +1363,373 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
         region: usize,
         flash_freed: usize,
     ) -> Result<usize, ErrorCode> {
-        // Get the data from that region
-        let region_data = self.read_buffer.take().unwrap();
-        if self.state.get() != State::GarbageCollect(RubbishState::ReadRegion(region, flash_freed))
-        {
-            if let Err(e) = self.controller.read_region(region, region_data) {
-                self.read_buffer.replace(Some(region_data));
-                if let ErrorCode::ReadNotReady(reg) = e {
+        loop {
+            // Get the data from that region
+            let region_data = self.read_buffer.take().unwrap();
+            if self.state.get()
+                != State::GarbageCollect(RubbishState::ReadRegion(region, flash_freed))
+            {
+                if let Err(e) = self.controller.read_region(region, region_data) {
+                    self.read_buffer.replace(Some(region_data));
+                    if let ErrorCode::ReadNotReady(reg) = e {
+                        self.state
+                            .set(State::GarbageCollect(RubbishState::ReadRegion(
+                                reg,
+                                flash_freed,
+                            )));
+                    }
+                    return Err(e);
+                }
+            }
+
+            let info = self.scan_region(region_data);
+            let relocate = match info {
+                Ok(ref info) => self.hold_object_to_relocate(region_data, info),
+                Err(_) => None,
+            };
+            self.read_buffer.replace(Some(region_data));
+            let info = info?;
+
+            // Move the valid objects out of a worn region, so that the
+            // region can be reclaimed and the objects end up in less worn
+            // regions.
+            if let Some(offset) = relocate {
+                self.accumulate_stats(
+                    info.header.erase_count,
+                    info.free_bytes,
+                    info.invalidated_bytes,
+                );
+                self.state.set(State::None);
+                let moved = self.relocate_object(region, flash_freed, offset, None)?;
+                self.state.set(State::None);
+                if moved {
+                    continue;
+                }
+                return Ok(0);
+            }
+
+            // The possible outcomes:
+            //    * The region is empty, we don't need to do anything
+            //    * The region has a valid entry, don't perform an erase
+            //    * The region has entries, all of which are marked for
+            //      deletion
+            if info.valid_objects > 0 || info.invalidated_bytes == 0 {
+                self.accumulate_stats(
+                    info.header.erase_count,
+                    info.free_bytes,
+                    info.invalidated_bytes,
+                );
+                return Ok(0);
+            }
+
+            // If we got down here, the region is ready to be erased.
+            let erase_count = info.header.erase_count.saturating_add(1);
+
+            if let Err(e) = self.controller.erase_region(region) {
+                if let ErrorCode::EraseNotReady(reg) = e {
                     self.state
-                        .set(State::GarbageCollect(RubbishState::ReadRegion(
+                        .set(State::GarbageCollect(RubbishState::EraseRegion(
                             reg,
-                            flash_freed,
+                            flash_freed + S,
+                            erase_count,
                         )));
                 }
                 return Err(e);
             }
+
+            self.reclaim_region(region, flash_freed + S, erase_count)?;
+
+            return Ok(S);
         }
+    }
 
-        let mut entry_found = false;
-        let mut offset: usize = 0;
+    /// If the region should have its objects moved out, copy the first
+    /// valid object to the relocation buffer and return its offset.
+    ///
+    /// Objects are only moved out of worn regions that can't be reclaimed
+    /// because some of their objects are still valid, and only if every
+    /// valid object fits in the relocation buffer.
+    fn hold_object_to_relocate(&self, region_data: &[u8], info: &RegionInfo) -> Option<usize> {
+        if info.valid_objects == 0
+            || info.invalidated_bytes == 0
+            || !self.region_is_worn(info.header.erase_count)
+        {
+            return None;
+        }
 
-        loop {
-            if offset >= S {
-                // We have reached the end of the region without finding a
-                // valid object. All entries must be marked for deletion then.
-                break;
+        let buffer = self.relocation_buffer.take()?;
+        let mut first_valid = None;
+        let mut offset = info.header.data_offset;
+
+        while offset + HEADER_LENGTH < S && region_data[offset + VERSION_OFFSET] != 0xFF {
+            let len_flags = region_data[offset + LEN_OFFSET];
+            let total_length =
+                ((len_flags as usize) & 0x0F) << 8 | region_data[offset + LEN_OFFSET + 1] as usize;
+
+            if len_flags & 0x80 == 0x80 {
+                if total_length > buffer.len() || offset + total_length > S {
+                    first_valid = None;
+                    break;
+                }
+                first_valid.get_or_insert(offset);
             }
+            offset += total_length;
+        }
 
-            // Check to see if we have data
-            if *region_data
-                .get(offset + VERSION_OFFSET)
-                .ok_or(ErrorCode::KeyNotFound)?
-                != 0xFF
-            {
-                // We found a version, check that we support it
-                if *region_data
-                    .get(offset + VERSION_OFFSET)
-                    .ok_or(ErrorCode::KeyNotFound)?
-                    != VERSION
+        if let Some(offset) = first_valid {
+            let total_length = ((region_data[offset + LEN_OFFSET] as usize) & 0x0F) << 8
+                | region_data[offset + LEN_OFFSET + 1] as usize;
+            buffer[..total_length].copy_from_slice(&region_data[offset..offset + total_length]);
+        }
+        self.relocation_buffer.set(Some(buffer));
+
+        first_valid
+    }
+
+    /// Write the object in the relocation buffer, which was read from
+    /// `offset` in the worn `region`, to the first region that isn't worn
+    /// and has space for it, then invalidate the original.
+    ///
+    /// Regions are tried in the same order as `append_key()` uses, starting
+    /// at `next` or at the region of the key. Returns false if the object
+    /// can't be moved.
+    ///
+    /// If power is lost after the copy is written, but before the original
+    /// is invalidated, the key is stored twice. The next garbage collection
+    /// finds the copy and only invalidates the original.
+    fn relocate_object(
+        &self,
+        region: usize,
+        flash_freed: usize,
+        offset: usize,
+        next: Option<usize>,
+    ) -> Result<bool, ErrorCode> {
+        let object = self.relocation_buffer.take().unwrap();
+        let total_length =
+            ((object[LEN_OFFSET] as usize) & 0x0F) << 8 | object[LEN_OFFSET + 1] as usize;
+        let hash = u64::from_be_bytes(object[HASH_OFFSET..HASH_OFFSET + 8].try_into().unwrap());
+        let home = self.get_region(hash);
+        let mut candidate = next.unwrap_or(home);
+
+        loop {
+            if candidate != region {
+                let region_data = self.read_buffer.take().unwrap();
+                if self.state.get()
+                    != State::GarbageCollect(RubbishState::RelocateFind(
+                        region,
+                        flash_freed,
+                        offset,
+                        candidate,
+                    ))
                 {
+                    if let Err(e) = self.controller.read_region(candidate, region_data) {
+                        self.read_buffer.replace(Some(region_data));
+                        self.relocation_buffer.set(Some(object));
+                        if let ErrorCode::ReadNotReady(reg) = e {
+                            self.state
+                                .set(State::GarbageCollect(RubbishState::RelocateFind(
+                                    region,
+                                    flash_freed,
+                                    offset,
+                                    reg,
+                                )));
+                        }
+                        return Err(e);
+                    }
+                }
+
+                if self.find_key_offset(hash, region_data).is_ok() {
+                    // An earlier move was interrupted after writing the copy
                     self.read_buffer.replace(Some(region_data));
-                    return Err(ErrorCode::UnsupportedVersion);
+                    break;
                 }
 
-                entry_found = true;
+                let info = self.scan_region(region_data);
+                self.read_buffer.replace(Some(region_data));
+                let info = match info {
+                    Ok(info) => info,
+                    Err(e) => {
+                        self.relocation_buffer.set(Some(object));
+                        return Err(e);
+                    }
+                };
 
-                // Find this entries length
-                let total_length = ((*region_data
-                    .get(offset + LEN_OFFSET)
-                    .ok_or(ErrorCode::CorruptData)? as u16)
-                    & !0xF0)
-                    << 8
-                    | *region_data
-                        .get(offset + LEN_OFFSET + 1)
-                        .ok_or(ErrorCode::CorruptData)? as u16;
+                // A region that is empty and was never reclaimed ends the
+                // search for keys, so it has to be used even if it is worn.
+                let unused = info.valid_objects == 0
+                    && info.invalidated_bytes == 0
+                    && !info.header.reclaimed;
+                let free = S - info.free_bytes;
 
-                // Check to see if the entry has been deleted
-                if *region_data
-                    .get(offset + LEN_OFFSET)
-                    .ok_or(ErrorCode::CorruptData)?
-                    & 0x80
-                    != 0x80
+                if (unused || !self.region_is_worn(info.header.erase_count))
+                    && free + total_length - CHECK_SUM_LEN < S
                 {
-                    // The entry has been deleted, this region might be ready
-                    // for erasure.
-                    // Increment our offset by the length and repeat the loop
-                    offset += total_length as usize;
-                    continue;
+                    let ret = self
+                        .controller
+                        .write(S * candidate + free, &object[..total_length]);
+                    self.relocation_buffer.set(Some(object));
+
+                    if let Err(e) = ret {
+                        if let ErrorCode::WriteNotReady(_) = e {
+                            self.state
+                                .set(State::GarbageCollect(RubbishState::RelocateWrite(
+                                    region,
+                                    flash_freed,
+                                    offset,
+                                )));
+                        }
+                        return Err(e);
+                    }
+
+                    self.invalidate_relocated(region, flash_freed, offset)?;
+                    return Ok(true);
+                }
+            }
+
+            match self.increment_region_offset(home, candidate as isize - home as isize) {
+                Some(o) => {
+                    candidate = (home as isize + o) as usize;
+                    self.state.set(State::None);
                 }
+                None => {
+                    self.relocation_buffer.set(Some(object));
+                    return Ok(false);
+                }
+            }
+        }
+
+        self.relocation_buffer.set(Some(object));
+        self.invalidate_relocated(region, flash_freed, offset)?;
+        Ok(true)
+    }
 
-                // We have found a valid entry!
-                // Don't perform an erase!
+    /// Invalidate the object at `offset` in `region` once it has been moved.
+    fn invalidate_relocated(
+        &self,
+        region: usize,
+        flash_freed: usize,
+        offset: usize,
+    ) -> Result<(), ErrorCode> {
+        // Read the region again, as some flash controllers update the region
+        // that was read last.
+        let region_data = self.read_buffer.take().unwrap();
+        if self.state.get()
+            != State::GarbageCollect(RubbishState::RelocateRead(region, flash_freed, offset))
+        {
+            if let Err(e) = self.controller.read_region(region, region_data) {
                 self.read_buffer.replace(Some(region_data));
-                return Ok(0);
-            } else {
-                // We hit the end of valid data.
-                // The possible outcomes:
-                //    * The region is empty, we don't need to do anything
-                //    * The region has entries, all of which are marked for
-                //      deletion
-                if !entry_found {
-                    // We didn't find anything, don't bother erasing an empty region.
-                    self.read_buffer.replace(Some(region_data));
-                    return Ok(0);
+                if let ErrorCode::ReadNotReady(reg) = e {
+                    self.state
+                        .set(State::GarbageCollect(RubbishState::RelocateRead(
+                            reg,
+                            flash_freed,
+                            offset,
+                        )));
                 }
-                break;
+                return Err(e);
             }
         }
 
+        region_data[offset + LEN_OFFSET] &= !0x80;
+        let ret = self.controller.write(
+            S * region + offset + LEN_OFFSET,
+            &region_data[offset + LEN_OFFSET..offset + LEN_OFFSET + 1],
+        );
         self.read_buffer.replace(Some(region_data));
 
-        // If we got down here, the region is ready to be erased.
-
-        if let Err(e) = self.controller.erase_region(region) {
-            if let ErrorCode::EraseNotReady(reg) = e {
+        ret.inspect_err(|e| {
+            if let ErrorCode::WriteNotReady(_) = e {
                 self.state
-                    .set(State::GarbageCollect(RubbishState::EraseRegion(
-                        reg,
-                        flash_freed + S,
+                    .set(State::GarbageCollect(RubbishState::RelocateInvalidate(
+                        region,
+                        flash_freed,
                     )));
             }
-            return Err(e);
-        }
+        })
+    }
+
+    /// Write the header of a region that was erased by garbage collection.
+    ///
+    /// The region is marked as reclaimed, so that searches for keys that were
+    /// placed in neighbouring regions continue past it.
+    fn reclaim_region(
+        &self,
+        region: usize,
+        flash_freed: usize,
+        erase_count: u32,
+    ) -> Result<(), ErrorCode> {
+        self.accumulate_stats(erase_count, S - REGION_HEADER_LENGTH, 0);
 
-        Ok(S)
+        self.write_region_header(region, erase_count, true)
+            .inspect_err(|e| {
+                if let ErrorCode::WriteNotReady(_) = e {
+                    self.state
+                        .set(State::GarbageCollect(RubbishState::WriteHeader(
+                            region,
+                            flash_freed,
+                        )));
+                }
+            })
     }
 
     /// Perform a garbage collection on TicKV
     ///
+    /// Every region erased is marked with an increased erase count. The lowest
+    /// erase count found is then used for wear leveling when appending keys.
+    ///
+    /// If a relocation buffer has been set, the valid objects of worn regions
+    /// that also contain invalidated objects are moved to less worn regions,
+    /// so that the worn region can be reclaimed and left alone until the
+    /// other regions catch up.
+    ///
     /// On success the number of bytes freed will be returned.
     /// On error a `ErrorCode` will be returned.
     pub fn garbage_collect(&self) -> Result<usize, ErrorCode> {
         let num_region = self.flash_size / S;
         let mut flash_freed = 0;
         let start = match self.state.get() {
-            State::None => 0,
+            State::None => {
+                self.reset_stats();
+                0
+            }
             State::GarbageCollect(state) => match state {
                 RubbishState::ReadRegion(reg, ff) => {
                     flash_freed += ff;
                     reg
                 }
-                // We already erased region reg, so move to the next one
-                RubbishState::EraseRegion(reg, ff) => {
+                // We already erased region reg, so write the header and move
+                // to the next one
+                RubbishState::EraseRegion(reg, ff, erase_count) => {
                     flash_freed += ff;
+                    self.reclaim_region(reg, ff, erase_count)?;
                     reg + 1
                 }
+                // We already wrote the header of region reg, so move to the
+                // next one
+                RubbishState::WriteHeader(reg, ff) => {
+                    flash_freed += ff;
+                    reg + 1
+                }
+                // We are moving an object out of region reg, finish that and
+                // then look at the region again
+                RubbishState::RelocateFind(reg, ff, offset, candidate) => {
+                    flash_freed += ff;
+                    let moved = self.relocate_object(reg, ff, offset, Some(candidate))?;
+                    self.state.set(State::None);
+                    if moved { reg } else { reg + 1 }
+                }
+                RubbishState::RelocateWrite(reg, ff, offset)
+                | RubbishState::RelocateRead(reg, ff, offset) => {
+                    flash_freed += ff;
+                    self.invalidate_relocated(reg, ff, offset)?;
+                    self.state.set(State::None);
+                    reg
+                }
+                RubbishState::RelocateInvalidate(reg, ff) => {
+                    flash_freed += ff;
+                    self.state.set(State::None);
+                    reg
+                }
             },
             _ => unreachable!(),
         };
@@ -1106,6 +1739,58 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
             flash_freed += freed
         }
 
+        self.erase_count_floor
+            .set(Some(self.scan_stats.get().min_erase_count));
+
         Ok(flash_freed)
     }
+
+    /// Collect usage and wear statistics by reading every region.
+    ///
+    /// This also updates the lowest erase count used for wear leveling.
+    ///
+    /// On success the `Stats` will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn stats(&self) -> Result<Stats, ErrorCode> {
+        let num_region = self.flash_size / S;
+        let start = match self.state.get() {
+            State::None => {
+                self.reset_stats();
+                0
+            }
+            State::Stats(key_state) => match key_state {
+                KeyState::ReadRegion(reg) => reg,
+            },
+            _ => unreachable!(),
+        };
+
+        for region in start..num_region {
+            // Get the data from that region
+            let region_data = self.read_buffer.take().unwrap();
+            if self.state.get() != State::Stats(KeyState::ReadRegion(region)) {
+                if let Err(e) = self.controller.read_region(region, region_data) {
+                    self.read_buffer.replace(Some(region_data));
+                    if let ErrorCode::ReadNotReady(reg) = e {
+                        self.state.set(State::Stats(KeyState::ReadRegion(reg)));
+                    }
+                    return Err(e);
+                }
+            }
+
+            let info = self.scan_region(region_data);
+            self.read_buffer.replace(Some(region_data));
+            let info = info?;
+
+            self.accumulate_stats(
+                info.header.erase_count,
+                info.free_bytes,
+                info.invalidated_bytes,
+            );
+        }
+
+        let stats = self.scan_stats.get();
+        self.erase_count_floor.set(Some(stats.min_erase_count));
+
+        Ok(stats)
+    }
 }
@@ -131,6 +131,7 @@ pub enum TbfHeaderTypes {
     TbfHeaderKernelVersion = 8,
     TbfHeaderProgram = 9,
     TbfHeaderShortId = 10,
+    TbfHeaderCheckpoint = 11,
     TbfFooterCredentials = 128,
 
     /// Some field in the header that we do not understand. Since the TLV format
@@ -330,6 +331,7 @@ impl core::convert::TryFrom<u16> for TbfHeaderTypes {
             8 => Ok(TbfHeaderTypes::TbfHeaderKernelVersion),
             9 => Ok(TbfHeaderTypes::TbfHeaderProgram),
             10 => Ok(TbfHeaderTypes::TbfHeaderShortId),
+            11 => Ok(TbfHeaderTypes::TbfHeaderCheckpoint),
             128 => Ok(TbfHeaderTypes::TbfFooterCredentials),
             _ => Ok(TbfHeaderTypes::Unknown),
         }
@@ -661,6 +663,7 @@ pub struct TbfHeaderV2<'a> {
     pub(crate) storage_permissions: Option<&'a [u8]>,
     pub(crate) kernel_version: Option<TbfHeaderV2KernelVersion>,
     pub(crate) short_id: Option<TbfHeaderV2ShortId>,
+    pub(crate) checkpoint: bool,
 }
 
 /// Type that represents the fields of the Tock Binary Format header.
@@ -669,7 +672,7 @@ pub struct TbfHeaderV2<'a> {
 /// in the tock binary, as well as other information about the application.
 /// The kernel can also use this header to keep persistent state about
 /// the application.
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub enum TbfHeader<'a> {
     TbfHeaderV2(TbfHeaderV2<'a>),
     Padding(TbfHeaderV2Base),
@@ -1013,6 +1016,16 @@ impl<'a> TbfHeader<'a> {
         }
     }
 
+    /// Return whether the kernel may checkpoint the process and restore it
+    /// after a reboot. The Checkpoint TLV has no value, including it in the
+    /// header opts in.
+    pub fn checkpoint_enabled(&self) -> bool {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd.checkpoint,
+            _ => false,
+        }
+    }
+
     /// Return the fixed ShortId of the application if it was specified in the
     /// TBF header.
     pub fn get_fixed_short_id(&self) -> Option<core::num::NonZeroU32> {
@@ -50,7 +50,7 @@ def find_implemented_capsules(root_path, implemented_list):
                 document_within_this_folder = False
                 for doc_capsule in documented_capsules:
                     fp = os.fsdecode(subdir)
-                    if doc_capsule.startswith(fp) and doc_capsule != fp:
+                    if doc_capsule.startswith(fp + os.sep):
                         document_within_this_folder = True
                         break
                 if document_within_this_folder:
@@ -68,7 +68,7 @@ def find_implemented_capsules(root_path, implemented_list):
                 # Skip files where the directory (e.g. extra/src/net) is
                 # documented.
                 for doc_capsule in documented_capsules:
-                    if filepath.startswith(doc_capsule) and filepath != doc_capsule:
+                    if filepath.startswith(doc_capsule + os.sep):
                         break
                 else:
                     implemented_list.append(filepath)
@@ -0,0 +1,272 @@
+#!/usr/bin/env python3
+
+# Licensed under the Apache License, Version 2.0 or the MIT License.
+# SPDX-License-Identifier: Apache-2.0 OR MIT
+# Copyright Tock Contributors 2025.
+
+# Decodes process fault records written by the `process_fault_dump` capsule.
+#
+# The input is either a single record, as read by an app through the capsule's
+# syscall interface, or an image of the whole record area read out of flash
+# (for example with `tockloader read` or a debugger). Records are printed
+# oldest first. The record format is documented in
+# `capsules/extra/src/process_fault_dump.rs`.
+#
+# Usage: decode_fault_dump.py [--stack-words N] <file>
+
+import argparse
+import struct
+import sys
+
+RECORD_LEN = 1024
+RECORD_MAGIC = 0x46544450
+RECORD_VERSION = 2
+
+ADDRESSES_OFFSET = 32
+NAME_OFFSET = 56
+NAME_LEN = 32
+SYSCALLS_OFFSET = 88
+SYSCALLS = 8
+SYSCALL_LEN = 20
+MPU_REGIONS_OFFSET = 248
+MPU_REGIONS = 16
+MPU_REGION_LEN = 12
+STORED_STATE_OFFSET = 444
+STACK_OFFSET = 612
+CHECKSUM_LEN = 4
+
+SYSCALL_CLASSES = [
+    "Yield",
+    "Subscribe",
+    "Command",
+    "ReadWriteAllow",
+    "ReadOnlyAllow",
+    "Memop",
+    "Exit",
+    "UserspaceReadableAllow",
+]
+
+YIELD_VARIANTS = ["NoWait", "Wait", "WaitFor"]
+
+MPU_PERMISSIONS = [
+    None,
+    "ReadWriteExecute",
+    "ReadWriteOnly",
+    "ReadExecuteOnly",
+    "ReadOnly",
+    "ExecuteOnly",
+]
+
+RISCV_REGS = [
+    "ra",
+    "sp",
+    "gp",
+    "tp",
+    "t0",
+    "t1",
+    "t2",
+    "s0",
+    "s1",
+    "a0",
+    "a1",
+    "a2",
+    "a3",
+    "a4",
+    "a5",
+    "a6",
+    "a7",
+    "s2",
+    "s3",
+    "s4",
+    "s5",
+    "s6",
+    "s7",
+    "s8",
+    "s9",
+    "s10",
+    "s11",
+    "t3",
+    "t4",
+    "t5",
+    "t6",
+]
+
+
+def fnv1a(data):
+    h = 0x811C9DC5
+    for b in data:
+        h = ((h ^ b) * 0x01000193) & 0xFFFFFFFF
+    return h
+
+
+def u32(data, offset):
+    return struct.unpack_from("<I", data, offset)[0]
+
+
+def parse_record(data):
+    if len(data) < STACK_OFFSET + CHECKSUM_LEN or u32(data, 0) != RECORD_MAGIC:
+        return None
+    length = u32(data, 12)
+    if length < STACK_OFFSET + CHECKSUM_LEN or length > min(len(data), RECORD_LEN):
+        return None
+    end = length - CHECKSUM_LEN
+    if u32(data, end) != fnv1a(data[:end]):
+        return None
+    return data[:length]
+
+
+def format_syscall(words):
+    (cls, driver, subdriver, arg0, arg1) = words
+    if cls == 0xFFFFFFFF:
+        return "none"
+    if cls >= len(SYSCALL_CLASSES):
+        return "unknown class {}".format(cls)
+    name = SYSCALL_CLASSES[cls]
+    if name == "Yield":
+        variant = YIELD_VARIANTS[arg0] if arg0 < len(YIELD_VARIANTS) else arg0
+        if variant == "WaitFor":
+            return "Yield WaitFor driver {:#x} subdriver {}".format(driver, subdriver)
+        return "Yield {}".format(variant)
+    if name == "Memop":
+        return "Memop operand {} arg0 {:#010x}".format(subdriver, arg0)
+    if name == "Exit":
+        return "Exit which {} completion code {:#x}".format(subdriver, arg0)
+    return "{} driver {:#x} subdriver {} args {:#010x} {:#010x}".format(
+        name, driver, subdriver, arg0, arg1
+    )
+
+
+def print_stored_state(state):
+    if len(state) < 12:
+        print("  Stored state:       unavailable")
+        return
+    tag = state[8:12]
+    words = [u32(state, i) for i in range(0, len(state) - 3, 4)]
+    if tag == b"ctxm" and len(words) >= 14:
+        print("  PC (yield):         {:#010x}".format(words[3]))
+        print("  xPSR:               {:#010x}".format(words[4]))
+        print("  PSP:                {:#010x}".format(words[5]))
+        for i, value in enumerate(words[6:14]):
+            print("  R{:<2}                 {:#010x}".format(i + 4, value))
+    elif tag == b"rv5i" and len(words) >= 37:
+        print("  PC:                 {:#010x}".format(words[3]))
+        print("  mcause:             {:#010x}".format(words[4]))
+        print("  mtval:              {:#010x}".format(words[5]))
+        for name, value in zip(RISCV_REGS, words[6:37]):
+            print("  {:<4}                {:#010x}".format(name, value))
+    else:
+        print("  Stored state ({} bytes): {}".format(len(state), state.hex()))
+
+
+def print_stack(record, stack_pointer, stack_len, tag, max_words):
+    stack = record[STACK_OFFSET : STACK_OFFSET + stack_len]
+    if tag == b"ctxm" and len(stack) >= 32:
+        # The hardware stacks an exception frame on the process stack.
+        frame = [u32(stack, i) for i in range(0, 32, 4)]
+        names = ["R0", "R1", "R2", "R3", "R12", "LR", "PC", "xPSR"]
+        print("  Exception frame:")
+        for name, value in zip(names, frame):
+            print("    {:<4}               {:#010x}".format(name, value))
+    print("  Stack ({} bytes from {:#010x}):".format(stack_len, stack_pointer))
+    for i in range(0, min(len(stack) // 4, max_words)):
+        address = stack_pointer + 4 * i
+        print("    {:#010x}: {:#010x}".format(address, u32(stack, 4 * i)))
+
+
+def print_record(record, max_words):
+    version = u32(record, 4)
+    print("Record {}".format(u32(record, 8)))
+    if version != RECORD_VERSION:
+        print("  Unsupported record version {}".format(version))
+        return
+    kernel_version = u32(record, 16)
+    name = record[NAME_OFFSET : NAME_OFFSET + NAME_LEN].split(b"\0")[0]
+    print("  Process:            {}".format(name.decode("utf-8", "replace")))
+    short_id = u32(record, 20)
+    short_id = hex(short_id) if short_id else "LocallyUnique"
+    print("  ShortId:            {}".format(short_id))
+    major = kernel_version & 0xFFFF
+    minor = kernel_version >> 16
+    print("  Kernel version:     {}.{}".format(major, minor))
+    print("  Restart count:      {}".format(u32(record, 24)))
+    print("  Syscall count:      {}".format(u32(record, 28)))
+    (flash_start, flash_end, sram_start, app_brk, grant_start, sram_end) = [
+        u32(record, ADDRESSES_OFFSET + 4 * i) for i in range(6)
+    ]
+    print("  Flash:              {:#010x}-{:#010x}".format(flash_start, flash_end))
+    print("  RAM:                {:#010x}-{:#010x}".format(sram_start, sram_end))
+    print("  App break:          {:#010x}".format(app_brk))
+    print("  Grant start:        {:#010x}".format(grant_start))
+
+    print("  Syscalls, newest first:")
+    for index in range(SYSCALLS):
+        offset = SYSCALLS_OFFSET + SYSCALL_LEN * index
+        words = [u32(record, offset + 4 * i) for i in range(5)]
+        if words[0] == 0xFFFFFFFF:
+            break
+        print("    {}".format(format_syscall(words)))
+
+    print("  MPU regions:")
+    for index in range(MPU_REGIONS):
+        offset = MPU_REGIONS_OFFSET + MPU_REGION_LEN * index
+        (start, size, permissions) = [u32(record, offset + 4 * i) for i in range(3)]
+        if permissions == 0:
+            continue
+        name = (
+            MPU_PERMISSIONS[permissions]
+            if permissions < len(MPU_PERMISSIONS)
+            else "unknown permissions {}".format(permissions)
+        )
+        print(
+            "    Region {:<2}          {:#010x}-{:#010x} {}".format(
+                index, start, start + size, name
+            )
+        )
+
+    state_len = min(
+        u32(record, STORED_STATE_OFFSET - 4), STACK_OFFSET - 8 - STORED_STATE_OFFSET
+    )
+    state = record[STORED_STATE_OFFSET : STORED_STATE_OFFSET + state_len]
+    print_stored_state(state)
+
+    stack_pointer = u32(record, STACK_OFFSET - 8)
+    stack_len = min(
+        u32(record, STACK_OFFSET - 4), len(record) - CHECKSUM_LEN - STACK_OFFSET
+    )
+    if stack_len:
+        print_stack(record, stack_pointer, stack_len, state[8:12], max_words)
+    else:
+        print("  Stack:              unavailable")
+
+
+def main():
+    parser = argparse.ArgumentParser(description="Decode Tock process fault records.")
+    parser.add_argument("file", help="record or record area image")
+    parser.add_argument(
+        "--stack-words",
+        type=int,
+        default=16,
+        help="number of stack words to print (default 16)",
+    )
+    args = parser.parse_args()
+
+    with open(args.file, "rb") as f:
+        data = f.read()
+
+    records = []
+    for offset in range(0, max(len(data), 1), RECORD_LEN):
+        record = parse_record(data[offset : offset + RECORD_LEN])
+        if record is not None:
+            records.append(record)
+    if not records:
+        print("No valid fault records found.", file=sys.stderr)
+        sys.exit(1)
+
+    records.sort(key=lambda r: u32(r, 8))
+    for record in records:
+        print_record(record, args.stack_words)
+        print()
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,277 @@
+#!/usr/bin/env python3
+
+# Licensed under the Apache License, Version 2.0 or the MIT License.
+# SPDX-License-Identifier: Apache-2.0 OR MIT
+# Copyright Tock Contributors 2025.
+
+# Decodes the binary syscall trace sent by the `syscall_trace` capsule into a
+# timeline.
+#
+# The input is either a file holding a capture of the stream, or a serial port
+# (which requires pyserial). Each system call is printed with its return value
+# and how long the kernel took to return it. The stream format is documented
+# in `capsules/extra/src/syscall_trace.rs`.
+#
+# A trace sent over Segger RTT can be captured into a file with
+# `JLinkRTTLogger -Device <device> -If SWD -RTTChannel 0 trace.bin`.
+#
+# Usage: decode_syscall_trace.py <file>
+#        decode_syscall_trace.py --serial /dev/ttyACM1 --baud 115200
+
+import argparse
+import os
+import re
+import struct
+import sys
+
+EVENT_LEN = 24
+SYNC = 0xA5
+
+KIND_RETURN = 0x80
+KIND_DROPPED = 0xFE
+KIND_INFO = 0xFF
+
+SYSCALL_CLASSES = [
+    "yield",
+    "subscribe",
+    "command",
+    "allow-rw",
+    "allow-ro",
+    "memop",
+    "exit",
+    "allow-userspace-r",
+]
+
+YIELD_VARIANTS = ["no-wait", "wait", "wait-for"]
+
+ERROR_CODES = {
+    1: "FAIL",
+    2: "BUSY",
+    3: "ALREADY",
+    4: "OFF",
+    5: "RESERVE",
+    6: "INVAL",
+    7: "SIZE",
+    8: "CANCEL",
+    9: "NOMEM",
+    10: "NOSUPPORT",
+    11: "NODEVICE",
+    12: "UNINSTALLED",
+    13: "NOACK",
+}
+
+# TRD104 return variants and how many of the following registers they use.
+RETURN_VARIANTS = {
+    0: ("Failure", 0),
+    1: ("FailureU32", 1),
+    2: ("FailureU32U32", 2),
+    3: ("FailureU64", 2),
+    128: ("Success", 0),
+    129: ("SuccessU32", 1),
+    130: ("SuccessU32U32", 2),
+    131: ("SuccessU64", 2),
+    132: ("SuccessU32U32U32", 3),
+    133: ("SuccessU32U64", 3),
+}
+
+DEFAULT_DRIVERS = os.path.join(
+    os.path.dirname(os.path.abspath(__file__)),
+    "..",
+    "..",
+    "capsules",
+    "core",
+    "src",
+    "driver.rs",
+)
+
+
+def load_driver_names(path):
+    names = {}
+    try:
+        with open(path) as f:
+            for line in f:
+                m = re.match(r"\s*(\w+)\s*=\s*(0x[0-9a-fA-F]+),", line)
+                if m:
+                    names[int(m.group(2), 16)] = m.group(1)
+    except OSError:
+        pass
+    return names
+
+
+def read_events(stream):
+    """Yield the events in the stream, resynchronizing on corrupt data."""
+    data = b""
+    while True:
+        chunk = stream.read(EVENT_LEN)
+        if not chunk:
+            return
+        data += chunk
+        while len(data) >= EVENT_LEN:
+            kind = data[1]
+            known = kind < len(SYSCALL_CLASSES) or kind in (
+                KIND_RETURN,
+                KIND_DROPPED,
+                KIND_INFO,
+            )
+            if data[0] != SYNC or not known:
+                data = data[1:]
+                continue
+            (kind, process, timestamp, w0, w1, w2, w3) = struct.unpack_from(
+                "<xBHIIIII", data
+            )
+            data = data[EVENT_LEN:]
+            yield (kind, process, timestamp, (w0, w1, w2, w3))
+
+
+def format_syscall(kind, words, drivers):
+    (driver, subdriver, arg0, arg1) = words
+    name = SYSCALL_CLASSES[kind]
+    driver_name = drivers.get(driver, "{:#x}".format(driver))
+    if name == "yield":
+        variant = YIELD_VARIANTS[arg0] if arg0 < len(YIELD_VARIANTS) else arg0
+        if variant == "wait-for":
+            return "yield-wait-for({}, {})".format(driver_name, subdriver)
+        return "yield-{}".format(variant)
+    if name == "memop":
+        return "memop({}, {:#x})".format(subdriver, arg0)
+    if name == "exit":
+        return "exit({}, {})".format(subdriver, arg0)
+    if name == "command":
+        return "command({}, {}, {:#x}, {:#x})".format(
+            driver_name, subdriver, arg0, arg1
+        )
+    if name == "subscribe":
+        return "subscribe({}, {}, upcall {:#x}, data {:#x})".format(
+            driver_name, subdriver, arg0, arg1
+        )
+    return "{}({}, {}, {:#010x}, {})".format(
+        name, driver_name, subdriver, arg0, arg1
+    )
+
+
+def format_return(words):
+    (variant, r1, r2, r3) = words
+    if variant not in RETURN_VARIANTS:
+        return "variant {:#x} ({:#x}, {:#x}, {:#x})".format(variant, r1, r2, r3)
+    (name, count) = RETURN_VARIANTS[variant]
+    values = [r1, r2, r3]
+    if variant < 128:
+        error = ERROR_CODES.get(r1, str(r1))
+        values = values[1 : 1 + count]
+        return "{}({})".format(name, ", ".join([error] + [hex(v) for v in values]))
+    return "{}({})".format(name, ", ".join(hex(v) for v in values[:count]))
+
+
+class Timeline:
+    def __init__(self, drivers):
+        self.drivers = drivers
+        self.frequency = None
+        self.mask = 0xFFFFFFFF
+        self.start = None
+        self.last = None
+        self.elapsed = 0
+        # The last system call of each process, waiting for its return value.
+        self.pending = {}
+
+    def time(self, timestamp):
+        """Ticks since the first event, accounting for the timer wrapping."""
+        if self.start is None:
+            self.start = timestamp
+            self.last = timestamp
+        self.elapsed += (timestamp - self.last) & self.mask
+        self.last = timestamp
+        return self.elapsed
+
+    def format_ticks(self, ticks):
+        if self.frequency:
+            return "{:12.6f} s".format(ticks / self.frequency)
+        return "{:12d} ticks".format(ticks)
+
+    def format_duration(self, ticks):
+        if self.frequency:
+            return "{:.1f} us".format(ticks * 1e6 / self.frequency)
+        return "{} ticks".format(ticks)
+
+    def event(self, kind, process, timestamp, words):
+        if kind == KIND_INFO:
+            (self.frequency, width) = (words[0], words[1])
+            if 0 < width < 32:
+                self.mask = (1 << width) - 1
+            return
+        now = self.time(timestamp)
+        if kind == KIND_DROPPED:
+            print("{}  !!! {} events lost".format(self.format_ticks(now), words[0]))
+            self.pending.clear()
+            return
+        if kind == KIND_RETURN:
+            call = self.pending.pop(process, None)
+            if call is None:
+                print(
+                    "{}  [{}] -> {}".format(
+                        self.format_ticks(now), process, format_return(words)
+                    )
+                )
+                return
+            (start, text) = call
+            print(
+                "{}  [{}] {} -> {} ({})".format(
+                    self.format_ticks(start),
+                    process,
+                    text,
+                    format_return(words),
+                    self.format_duration(now - start),
+                )
+            )
+            return
+        # A new system call. Calls that never return (like exit) are printed
+        # when the next call of the process is seen.
+        previous = self.pending.pop(process, None)
+        if previous is not None:
+            (start, text) = previous
+            print("{}  [{}] {}".format(self.format_ticks(start), process, text))
+        self.pending[process] = (now, format_syscall(kind, words, self.drivers))
+
+    def finish(self):
+        for process, (start, text) in sorted(self.pending.items()):
+            print(
+                "{}  [{}] {} -> (pending)".format(
+                    self.format_ticks(start), process, text
+                )
+            )
+
+
+def main():
+    parser = argparse.ArgumentParser(description="Decode a Tock syscall trace.")
+    parser.add_argument("file", nargs="?", help="capture of the trace stream")
+    parser.add_argument("--serial", help="read the stream from this serial port")
+    parser.add_argument("--baud", type=int, default=115200, help="serial baud rate")
+    parser.add_argument(
+        "--drivers",
+        default=DEFAULT_DRIVERS,
+        help="driver number list (default: capsules/core/src/driver.rs)",
+    )
+    args = parser.parse_args()
+
+    if args.serial:
+        try:
+            import serial
+        except ImportError:
+            print("Reading from a serial port requires pyserial.", file=sys.stderr)
+            sys.exit(1)
+        stream = serial.Serial(args.serial, args.baud)
+    elif args.file:
+        stream = open(args.file, "rb")
+    else:
+        stream = sys.stdin.buffer
+
+    timeline = Timeline(load_driver_names(args.drivers))
+    try:
+        for event in read_events(stream):
+            timeline.event(*event)
+    except KeyboardInterrupt:
+        pass
+    timeline.finish()
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,165 @@
+#!/usr/bin/env python3
+
+# Licensed under the Apache License, Version 2.0 or the MIT License.
+# SPDX-License-Identifier: Apache-2.0 OR MIT
+# Copyright Tock Contributors 2025.
+
+# Simulates the radio medium between emulated IEEE 802.15.4 nodes.
+#
+# Each node is a QEMU instance running the `qemu_rv32_virt-test-ieee802154`
+# configuration board, whose VirtIO NetworkCard is connected to this script
+# with the QEMU UDP socket backend:
+#
+#   -netdev socket,id=n0,udp=127.0.0.1:<port>,localaddr=127.0.0.1:<node port>
+#
+# Node N sends from port `--node-base-port` + N. Every 802.15.4 frame is
+# tunnelled in one Ethernet frame (the format is documented in
+# `capsules/extra/src/ieee802154_medium.rs`). The medium forwards it to the
+# neighbours of the sender, dropping it with the configured probability and
+# setting the link quality indicator of the link.
+#
+# Usage: ieee802154_medium.py --nodes 3
+#        ieee802154_medium.py --nodes 3 --links 0-1,1-2:120 --loss 0.1
+
+import argparse
+import random
+import socket
+import struct
+import sys
+
+ETHERTYPE = 0x88B5
+VERSION = 1
+
+ETHERNET_HEADER_LEN = 14
+# Version, channel, LQI and PSDU length.
+MEDIUM_HEADER_LEN = 4
+LQI_OFFSET = ETHERNET_HEADER_LEN + 2
+
+DEFAULT_LQI = 255
+
+
+def parse_links(spec, nodes):
+    """Parses a list of links `a-b[:lqi]`, returning {(src, dst): lqi}."""
+    links = {}
+    if spec is None:
+        for a in range(nodes):
+            for b in range(nodes):
+                if a != b:
+                    links[(a, b)] = DEFAULT_LQI
+        return links
+
+    for link in spec.split(","):
+        link = link.strip()
+        if not link:
+            continue
+        ends, _, lqi = link.partition(":")
+        a, _, b = ends.partition("-")
+        a, b = int(a), int(b)
+        lqi = int(lqi) if lqi else DEFAULT_LQI
+        if not (0 <= a < nodes and 0 <= b < nodes) or a == b:
+            raise ValueError("invalid link {}".format(link))
+        if not 0 <= lqi <= 255:
+            raise ValueError("invalid LQI in link {}".format(link))
+        # Links are symmetric.
+        links[(a, b)] = lqi
+        links[(b, a)] = lqi
+    return links
+
+
+def describe(frame):
+    """Returns (channel, psdu) of a tunnelled frame, or None if invalid."""
+    if len(frame) < ETHERNET_HEADER_LEN + MEDIUM_HEADER_LEN:
+        return None
+    (ethertype,) = struct.unpack_from(">H", frame, 12)
+    version, channel, _lqi, length = frame[ETHERNET_HEADER_LEN:LQI_OFFSET + 2]
+    if ethertype != ETHERTYPE or version != VERSION:
+        return None
+    start = ETHERNET_HEADER_LEN + MEDIUM_HEADER_LEN
+    if len(frame) < start + length:
+        return None
+    return channel, frame[start : start + length]
+
+
+def main():
+    parser = argparse.ArgumentParser(
+        description="Radio medium simulator for emulated 802.15.4 nodes."
+    )
+    parser.add_argument("--nodes", type=int, required=True, help="number of nodes")
+    parser.add_argument(
+        "--links",
+        help="comma separated links between nodes, as a-b or a-b:lqi "
+        "(default: every node hears every other node)",
+    )
+    parser.add_argument(
+        "--loss",
+        type=float,
+        default=0.0,
+        help="probability of dropping a frame on a link (default: 0)",
+    )
+    parser.add_argument(
+        "--port", type=int, default=9000, help="port of the medium (default: 9000)"
+    )
+    parser.add_argument(
+        "--node-base-port",
+        type=int,
+        default=9100,
+        help="port of node 0, node N uses this port + N (default: 9100)",
+    )
+    parser.add_argument("--seed", type=int, help="seed of the loss generator")
+    parser.add_argument(
+        "-v", "--verbose", action="store_true", help="print every frame"
+    )
+    args = parser.parse_args()
+
+    if not 0.0 <= args.loss <= 1.0:
+        parser.error("--loss must be between 0 and 1")
+    try:
+        links = parse_links(args.links, args.nodes)
+    except ValueError as e:
+        parser.error(str(e))
+    rng = random.Random(args.seed)
+
+    sock = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+    sock.bind(("127.0.0.1", args.port))
+    print(
+        "Medium listening on port {}, nodes on ports {}-{}".format(
+            args.port, args.node_base_port, args.node_base_port + args.nodes - 1
+        ),
+        file=sys.stderr,
+    )
+
+    while True:
+        frame, (_, port) = sock.recvfrom(65536)
+        src = port - args.node_base_port
+        if not 0 <= src < args.nodes:
+            continue
+        decoded = describe(frame)
+        if decoded is None:
+            # Not a tunnelled 802.15.4 frame, e.g. an IPv6 router solicitation
+            # from a misconfigured node.
+            continue
+        channel, psdu = decoded
+
+        delivered = []
+        for dst in range(args.nodes):
+            lqi = links.get((src, dst))
+            if lqi is None or rng.random() < args.loss:
+                continue
+            out = bytearray(frame)
+            out[LQI_OFFSET] = lqi
+            sock.sendto(out, ("127.0.0.1", args.node_base_port + dst))
+            delivered.append(dst)
+
+        if args.verbose:
+            print(
+                "node {} ch {} {} -> {}".format(
+                    src, channel, psdu.hex(), delivered or "nobody"
+                )
+            )
+
+
+if __name__ == "__main__":
+    try:
+        main()
+    except KeyboardInterrupt:
+        pass
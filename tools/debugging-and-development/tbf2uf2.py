@@ -0,0 +1,109 @@
+#!/usr/bin/env python3
+
+# Licensed under the Apache License, Version 2.0 or the MIT License.
+# SPDX-License-Identifier: Apache-2.0 OR MIT
+# Copyright Tock Contributors 2025.
+
+# Converts a Tock app (TBF) to the UF2 format.
+#
+# The result is installed by copying it to the drive of a board exposing the
+# USB mass storage app drop (`capsules/extra/src/usb/msc_app_drop.rs`). The
+# target address of every block is the offset of its payload in the TBF, and
+# the blocks carry the Tock family ID.
+#
+# Usage: tbf2uf2.py app.tbf app.uf2
+#        tbf2uf2.py app.tab app.uf2 --arch cortex-m4
+
+import argparse
+import struct
+import sys
+import tarfile
+
+UF2_MAGIC_START0 = 0x0A324655
+UF2_MAGIC_START1 = 0x9E5D5157
+UF2_MAGIC_END = 0x0AB16F30
+UF2_FLAG_FAMILY_ID = 0x00002000
+
+# "TOCK", `UF2_FAMILY_ID` in the capsule.
+TOCK_FAMILY_ID = 0x544F434B
+
+BLOCK_SIZE = 512
+PAYLOAD_SIZE = 256
+
+
+def read_tbf(path, arch):
+    """Returns the TBF in `path`, extracting it from a TAB if needed."""
+    if not tarfile.is_tarfile(path):
+        with open(path, "rb") as f:
+            return f.read()
+
+    with tarfile.open(path) as tab:
+        names = [n for n in tab.getnames() if n.endswith(".tbf")]
+        if arch is not None:
+            names = [n for n in names if n.split("/")[-1].startswith(arch)]
+        if len(names) != 1:
+            raise ValueError(
+                "expected one TBF in the TAB, found {}, select one with --arch".format(
+                    ", ".join(names) or "none"
+                )
+            )
+        return tab.extractfile(names[0]).read()
+
+
+def convert(tbf):
+    """Returns the UF2 blocks of `tbf`."""
+    if len(tbf) < 8:
+        raise ValueError("too short for a TBF")
+    (total_size,) = struct.unpack_from("<I", tbf, 4)
+    if total_size > len(tbf):
+        raise ValueError(
+            "TBF header claims {} bytes, the file has {}".format(total_size, len(tbf))
+        )
+    tbf = tbf[:total_size]
+
+    num_blocks = (len(tbf) + PAYLOAD_SIZE - 1) // PAYLOAD_SIZE
+    blocks = bytearray()
+    for block_no in range(num_blocks):
+        offset = block_no * PAYLOAD_SIZE
+        payload = tbf[offset : offset + PAYLOAD_SIZE]
+        header = struct.pack(
+            "<IIIIIIII",
+            UF2_MAGIC_START0,
+            UF2_MAGIC_START1,
+            UF2_FLAG_FAMILY_ID,
+            offset,
+            len(payload),
+            block_no,
+            num_blocks,
+            TOCK_FAMILY_ID,
+        )
+        data = payload.ljust(BLOCK_SIZE - len(header) - 4, b"\x00")
+        blocks += header + data + struct.pack("<I", UF2_MAGIC_END)
+    return blocks
+
+
+def main():
+    parser = argparse.ArgumentParser(
+        description="Convert a Tock app to UF2 for the USB mass storage app drop."
+    )
+    parser.add_argument("input", help="TBF or TAB of the app")
+    parser.add_argument("output", help="UF2 file to write")
+    parser.add_argument("--arch", help="architecture of the TBF to take from a TAB")
+    args = parser.parse_args()
+
+    try:
+        uf2 = convert(read_tbf(args.input, args.arch))
+    except ValueError as e:
+        print("{}: {}".format(args.input, e), file=sys.stderr)
+        sys.exit(1)
+
+    with open(args.output, "wb") as f:
+        f.write(uf2)
+    print(
+        "Wrote {} blocks to {}".format(len(uf2) // BLOCK_SIZE, args.output),
+        file=sys.stderr,
+    )
+
+
+if __name__ == "__main__":
+    main()